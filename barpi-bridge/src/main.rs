@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use barrier_client::{Backoff, Client, RemoteActuator, DEFAULT_MTU};
+use clap::Parser;
+use env_logger::Env;
+use log::{error, info};
+
+/// Connects to the Barrier server as a screen and forwards the events to a remote
+/// `barpi --listen` or any other `serve_actuator` endpoint
+#[derive(Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Barrier server address in "server:port" format
+    #[arg(short = 's', long, env = "BARRIER_SERVER")]
+    server: String,
+    /// Screen name, must be accepted by the Barrier server
+    #[arg(short = 'n', long, env = "SCREEN_NAME")]
+    screen_name: String,
+    /// Screen width of the target
+    #[arg(short = 'w', long, default_value = "1920", env = "SCREEN_WIDTH")]
+    screen_width: u16,
+    /// Screen height of the target
+    #[arg(short = 'e', long, default_value = "1080", env = "SCREEN_HEIGHT")]
+    screen_height: u16,
+    /// Address of the device applying the events, in "host:port" format
+    #[arg(short = 'r', long, env = "REMOTE")]
+    remote: String,
+    /// Maximum size of a frame sent to the remote device
+    #[arg(long, default_value_t = DEFAULT_MTU, env = "MTU")]
+    mtu: usize,
+    /// Maximum delay between reconnection attempts in seconds
+    #[arg(long, default_value = "60", env = "MAX_RECONNECT_DELAY")]
+    max_reconnect_delay: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+
+    let mut actuator =
+        RemoteActuator::new(&args.remote, args.screen_width, args.screen_height).mtu(args.mtu);
+    let barrier = Client::builder()
+        .server(&args.server)
+        .screen_name(&args.screen_name)
+        .reconnect(true)
+        .backoff(Backoff {
+            max: Duration::from_secs(args.max_reconnect_delay),
+            ..Default::default()
+        })
+        .on_status(|status| info!("Connection status: {:?}", status))
+        .build()?;
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Recieve SIGINT, shutting down..."),
+        result = barrier.run(&mut actuator) => {
+            if let Err(e) = result {
+                error!("Cannot connect to the server, error: {:?}", e);
+            }
+        }
+    }
+    Ok(())
+}
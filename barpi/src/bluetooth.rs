@@ -0,0 +1,376 @@
+use std::{
+    future, io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bluer::{
+    agent::{Agent, AgentHandle, ReqError},
+    l2cap::{SeqPacket, SeqPacketListener, SocketAddr},
+    rfcomm::{Profile, ProfileHandle, Role},
+    Adapter, Address, AddressType, Session, Uuid,
+};
+use log::{debug, info, warn};
+use synergy_hid::{DescriptorConfig, ReportType, SynergyHid};
+use tokio::{runtime::Handle, select, time};
+
+use crate::{
+    client::LedReports,
+    hidp::{
+        control_reply, input_frame, parse_message, sdp_record, HostMessage, PSM_CONTROL,
+        PSM_INTERRUPT,
+    },
+    sink::{ReportSink, Sink},
+};
+
+/// UUID of the HID service class
+const HID_UUID: Uuid = Uuid::from_u128(0x00001124_0000_1000_8000_00805f9b34fb);
+
+/// A report the interrupt channel doesn't take within this time is busy, like a full hidg
+const SEND_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Delay between attempts to connect back to the host once it disconnected
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Interrupt channel of the host connected, `None` while there is none
+type Connection = Arc<Mutex<Option<Arc<SeqPacket>>>>;
+
+/// The HID service registered with BlueZ, the devices share its interrupt channel and tell
+/// their reports apart with a report ID.
+///
+/// bluetoothd must run without its input plugin, `-P input`, which would take the HID PSMs.
+pub struct BluetoothHid {
+    // Unregistered when dropped
+    _session: Session,
+    _agent: AgentHandle,
+    _profile: ProfileHandle,
+    control: SeqPacketListener,
+    interrupt: SeqPacketListener,
+    connection: Connection,
+    pairing: Arc<Pairing>,
+}
+
+impl BluetoothHid {
+    /// Advertises the devices of `descriptors` as `name` on the default adapter, which is made
+    /// discoverable and pairable for `pairing_window`, until a host connects. Only `hosts` can
+    /// pair and connect, or any host while the window is open if there are none.
+    pub async fn register(
+        name: &str,
+        descriptors: &DescriptorConfig,
+        hosts: Vec<Address>,
+        pairing_window: Duration,
+    ) -> anyhow::Result<Self> {
+        let session = Session::new()
+            .await
+            .context("cannot connect to bluetoothd")?;
+        let adapter = session
+            .default_adapter()
+            .await
+            .context("cannot get the Bluetooth adapter")?;
+        adapter.set_powered(true).await?;
+        // A timeout of 0 would keep the adapter discoverable forever, BlueZ also ends the
+        // window if barpi doesn't
+        let window = pairing_window.as_secs().min(u32::MAX.into()) as u32;
+        if window > 0 {
+            adapter.set_pairable_timeout(window).await?;
+            adapter.set_discoverable_timeout(window).await?;
+        }
+        adapter.set_pairable(window > 0).await?;
+        adapter.set_discoverable(window > 0).await?;
+        let pairing = Arc::new(Pairing {
+            closes: Mutex::new((window > 0).then(|| Instant::now() + pairing_window)),
+            adapter: adapter.clone(),
+            hosts,
+        });
+
+        let confirming = pairing.clone();
+        let authorizing = pairing.clone();
+        let agent = session
+            .register_agent(Agent {
+                request_default: true,
+                request_confirmation: Some(Box::new(move |request| {
+                    let pairing = confirming.clone();
+                    Box::pin(async move {
+                        if !pairing.may_pair(request.device) {
+                            warn!("Rejected pairing with Bluetooth host {}", request.device);
+                            return Err(ReqError::Rejected);
+                        }
+                        info!("Pairing with Bluetooth host {}", request.device);
+                        Ok(())
+                    })
+                })),
+                authorize_service: Some(Box::new(move |request| {
+                    let pairing = authorizing.clone();
+                    Box::pin(async move {
+                        if !pairing.may_pair(request.device) && !pairing.knows(request.device).await
+                        {
+                            warn!(
+                                "Rejected {} for unknown Bluetooth host {}",
+                                request.service, request.device
+                            );
+                            return Err(ReqError::Rejected);
+                        }
+                        debug!("Authorizing {} for {}", request.device, request.service);
+                        Ok(())
+                    })
+                })),
+                ..Default::default()
+            })
+            .await
+            .context("cannot register the pairing agent")?;
+
+        // There is a single interface
+        let descriptor = DescriptorConfig {
+            merge_into_single_interface: true,
+            ..*descriptors
+        }
+        .build()
+        .remove(0)
+        .descriptor;
+        let profile = session
+            .register_profile(Profile {
+                uuid: HID_UUID,
+                name: Some(name.to_string()),
+                role: Some(Role::Server),
+                require_authentication: Some(true),
+                require_authorization: Some(false),
+                service_record: Some(sdp_record(name, &descriptor)),
+                ..Default::default()
+            })
+            .await
+            .context("cannot register the HID profile")?;
+
+        let listen =
+            |psm| SeqPacketListener::bind(SocketAddr::new(Address::any(), AddressType::BrEdr, psm));
+        let control = listen(PSM_CONTROL).await.context(
+            "cannot listen on the HID control PSM, is the input plugin of bluetoothd disabled?",
+        )?;
+        let interrupt = listen(PSM_INTERRUPT)
+            .await
+            .context("cannot listen on the HID interrupt PSM")?;
+        info!(
+            "Bluetooth HID device {:?} registered on {}, waiting for a host",
+            name,
+            adapter.address().await?
+        );
+        Ok(Self {
+            _session: session,
+            _agent: agent,
+            _profile: profile,
+            control,
+            interrupt,
+            connection: Default::default(),
+            pairing,
+        })
+    }
+
+    /// The sink of the reports of `report_type`, it must be one of the devices registered
+    pub fn sink(&self, report_type: ReportType) -> Sink {
+        Sink::Bluetooth(Channel {
+            id: report_type.report_id(),
+            connection: self.connection.clone(),
+            runtime: Handle::current(),
+        })
+    }
+
+    /// Takes the connections of the known hosts until barpi exits, one at a time, the pairing
+    /// window ends with the first one. The last host connected is also connected back to,
+    /// unless it removed the pairing. The sinks are detached while there is no connection,
+    /// the keyboard LEDs are stored in `leds`.
+    pub fn serve(self, leds: LedReports) {
+        tokio::spawn(async move {
+            let mut paired = None;
+            loop {
+                let connected = select! {
+                    accepted = accept(&self.control, &self.interrupt, &self.pairing) => accepted,
+                    connected = connect_back(paired) => Ok(connected),
+                };
+                let (control, interrupt, host) = match connected {
+                    Ok(connected) => connected,
+                    Err(e) => {
+                        warn!("Cannot accept the Bluetooth HID connection: {:?}", e);
+                        time::sleep(RECONNECT_INTERVAL).await;
+                        continue;
+                    }
+                };
+                info!("Bluetooth host {} connected", host);
+                self.pairing.close().await;
+                let interrupt = Arc::new(interrupt);
+                *self.connection.lock().unwrap() = Some(interrupt.clone());
+                let unplugged = serve_host(&control, &interrupt, &leds).await;
+                *self.connection.lock().unwrap() = None;
+                if unplugged {
+                    info!("Bluetooth host {} removed the pairing", host);
+                    paired = None;
+                } else {
+                    info!("Bluetooth host {} disconnected, waiting for it", host);
+                    paired = Some(host);
+                }
+            }
+        });
+    }
+}
+
+/// Who can pair and connect, see [`BluetoothHid::register`]
+struct Pairing {
+    adapter: Adapter,
+    hosts: Vec<Address>,
+    // End of the pairing window, `None` once it's closed
+    closes: Mutex<Option<Instant>>,
+}
+
+impl Pairing {
+    // Whether `host` can pair now
+    fn may_pair(&self, host: Address) -> bool {
+        let open = matches!(*self.closes.lock().unwrap(), Some(closes) if Instant::now() < closes);
+        open && (self.hosts.is_empty() || self.hosts.contains(&host))
+    }
+
+    // Whether `host` is allowed, or paired when no host is configured
+    async fn knows(&self, host: Address) -> bool {
+        if !self.hosts.is_empty() {
+            return self.hosts.contains(&host);
+        }
+        match self.adapter.device(host) {
+            Ok(device) => device.is_paired().await.unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    // Ends the pairing window early, once a host connected
+    async fn close(&self) {
+        if self.closes.lock().unwrap().take().is_none() {
+            return;
+        }
+        info!("Bluetooth host paired, the adapter is no longer discoverable");
+        if let Err(e) = self.adapter.set_discoverable(false).await {
+            warn!("Cannot make the Bluetooth adapter undiscoverable: {:?}", e);
+        }
+        if let Err(e) = self.adapter.set_pairable(false).await {
+            warn!("Cannot make the Bluetooth adapter unpairable: {:?}", e);
+        }
+    }
+}
+
+/// A device of the Bluetooth HID connection, see [`BluetoothHid::sink`]
+pub struct Channel {
+    id: u8,
+    connection: Connection,
+    // The writer thread isn't a runtime thread
+    runtime: Handle,
+}
+
+impl ReportSink for Channel {
+    fn send_report(&mut self, report: &[u8]) -> io::Result<()> {
+        let interrupt = self.connection.lock().unwrap().clone();
+        let Some(interrupt) = interrupt else {
+            return Err(detached());
+        };
+        let frame = input_frame(self.id, report);
+        match self
+            .runtime
+            .block_on(time::timeout(SEND_TIMEOUT, interrupt.send(&frame)))
+        {
+            Ok(Ok(_)) => Ok(()),
+            Err(_) => Err(io::ErrorKind::WouldBlock.into()),
+            // The connection is lost, the writer waits until the host is back
+            Ok(Err(e)) => {
+                debug!("Error sending Bluetooth HID report: {:?}", e);
+                Err(detached())
+            }
+        }
+    }
+}
+
+// The error of a hidg device while the host is detached
+fn detached() -> io::Error {
+    io::Error::from_raw_os_error(libc::ESHUTDOWN)
+}
+
+// The host opens the control channel, then the interrupt one. Unknown hosts are turned down.
+async fn accept(
+    control: &SeqPacketListener,
+    interrupt: &SeqPacketListener,
+    pairing: &Pairing,
+) -> io::Result<(SeqPacket, SeqPacket, Address)> {
+    let (control, host) = loop {
+        let (control, host) = control.accept().await?;
+        if pairing.knows(host.addr).await {
+            break (control, host);
+        }
+        warn!(
+            "Rejected the connection of unknown Bluetooth host {}",
+            host.addr
+        );
+    };
+    loop {
+        let (interrupt, from) = interrupt.accept().await?;
+        if from.addr == host.addr {
+            return Ok((control, interrupt, host.addr));
+        }
+        debug!("Interrupt channel from {} without a control one", from.addr);
+    }
+}
+
+// Never returns without a host to connect back to
+async fn connect_back(host: Option<Address>) -> (SeqPacket, SeqPacket, Address) {
+    let Some(host) = host else {
+        return future::pending().await;
+    };
+    loop {
+        time::sleep(RECONNECT_INTERVAL).await;
+        let connect = |psm| SeqPacket::connect(SocketAddr::new(host, AddressType::BrEdr, psm));
+        match connect(PSM_CONTROL).await {
+            Ok(control) => match connect(PSM_INTERRUPT).await {
+                Ok(interrupt) => return (control, interrupt, host),
+                Err(e) => debug!("Cannot open the interrupt channel to {}: {:?}", host, e),
+            },
+            Err(e) => debug!("Cannot connect back to {}: {:?}", host, e),
+        }
+    }
+}
+
+// Answers the host until it disconnects, returns whether it removed the pairing
+async fn serve_host(control: &SeqPacket, interrupt: &SeqPacket, leds: &LedReports) -> bool {
+    let mut control_buf = [0; 64];
+    let mut interrupt_buf = [0; 64];
+    loop {
+        let (read, on_control) = select! {
+            read = control.recv(&mut control_buf) => (read, true),
+            read = interrupt.recv(&mut interrupt_buf) => (read, false),
+        };
+        let len = match read {
+            Ok(0) => return false,
+            Ok(len) => len,
+            Err(e) => {
+                debug!("Error reading from the Bluetooth host: {:?}", e);
+                return false;
+            }
+        };
+        let message = if on_control {
+            &control_buf[..len]
+        } else {
+            &interrupt_buf[..len]
+        };
+        debug!("Bluetooth HID message: {:02x?}", message);
+        match parse_message(message) {
+            HostMessage::Output(id, data) if id == ReportType::Keyboard.report_id() => {
+                if let Some(state) = SynergyHid::parse_output_report(ReportType::Keyboard, data) {
+                    leds.store(state);
+                }
+            }
+            HostMessage::Unplug => return true,
+            _ => {}
+        }
+        if !on_control {
+            continue;
+        }
+        if let Some(reply) = control_reply(message) {
+            if let Err(e) = control.send(&reply).await {
+                debug!("Error answering the Bluetooth host: {:?}", e);
+                return false;
+            }
+        }
+    }
+}
@@ -1,162 +1,557 @@
-use std::{fs::File, io::Write};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use barrier_client::{Actuator, ClipboardData};
-use log::{debug, error, info};
-use synergy_hid::{ReportType, SynergyHid};
+use barrier_client::{
+    Actuator, ActuatorError, ClipboardData, Edge, KeyMask, LedState, Metrics, ServerInfo,
+    ServerOptions,
+};
+use log::{debug, info, warn};
+use synergy_hid::{
+    MacroEvent, MacroStep, Report, ReportDedup, ReportType, SynergyHid, MAX_REPORT_LEN,
+};
+use tokio::sync::Notify;
 use tokio_util::sync::CancellationToken;
+
+use crate::{
+    control::Shared,
+    sink::Sink,
+    writer::{DeviceState, HidWriter},
+};
+
+/// How often the keys held are checked by `release_stuck_keys`
+const STUCK_KEY_TICK: Duration = Duration::from_secs(1);
+
+/// What to do when the screen saver of the server starts or stops, nothing by default
+#[derive(Clone, Debug, Default)]
+pub struct ScreenSaverAction {
+    /// HID usages tapped when it starts, e.g. a shortcut locking the host
+    pub keys: Vec<u8>,
+    /// Named pipe receiving a "on" or "off" line, for a script watching it
+    pub pipe: Option<PathBuf>,
+}
+
 pub struct BarpiActuator {
-    width: u16,
-    height: u16,
-    x: u16,
-    y: u16,
+    // Owns the screen size and the cursor position
     hid: SynergyHid,
-    keyboard_file: File,
-    mouse_file: File,
-    consumer_file: File,
-    token: CancellationToken,
+    keyboard: HidWriter,
+    mouse: HidWriter,
+    consumer: HidWriter,
+    // Only with a gamepad function in the gadget
+    gamepad: Option<HidWriter>,
+    // LED output reports from the host, passed to `led_state_changed` by `apply_led_reports`
+    leds: LedReports,
+    screensaver: ScreenSaverAction,
+    // Named pipe receiving the edge reached by the cursor
+    edge_pipe: Option<PathBuf>,
+    type_delay: Duration,
+    // Last report written to each device, the moves changing nothing are skipped
+    dedup: ReportDedup,
+    metrics: Option<Arc<dyn Metrics>>,
+    // Notified on each report written, the status indicator flashes
+    activity: Option<Arc<Notify>>,
 }
 
 impl BarpiActuator {
+    /// The sinks write to the device files opened non-blocking, `led_file` is the keyboard
+    /// device opened again for blocking reads of the LED output reports, which start with
+    /// `led_report_id` if the keyboard has one.
+    /// A write error other than the host detaching cancels `token`.
     pub fn new(
-        width: u16,
-        height: u16,
-        flip_mouse_wheel: bool,
-        keyboard_file: File,
-        mouse_file: File,
-        consumer_file: File,
+        hid: SynergyHid,
+        keyboard: Sink,
+        mouse: Sink,
+        consumer: Sink,
+        led_file: File,
+        led_report_id: Option<u8>,
         token: CancellationToken,
     ) -> Self {
+        let leds = LedReports::default();
+        read_led_reports(led_file, led_report_id, leds.clone());
         Self {
-            width,
-            height,
-            x: 0,
-            y: 0,
-            hid: SynergyHid::new(flip_mouse_wheel),
-            keyboard_file,
-            mouse_file,
-            consumer_file,
-            token,
+            leds,
+            ..Self::with_sinks(hid, keyboard, mouse, consumer, token)
         }
     }
 
-    pub(crate) fn scale_position(&self, x: u16, y: u16) -> (u16, u16) {
-        (
-            ((x as f32) * (self.width as f32) / 0x7fff as f32).ceil() as u16,
-            ((y as f32) * (self.height as f32) / 0x7fff as f32).ceil() as u16,
+    /// Writes the reports to any writers instead of the HID device files, the LED state is not read
+    #[cfg(test)]
+    pub fn with_writers(
+        hid: SynergyHid,
+        keyboard_file: Box<dyn Write + Send>,
+        mouse_file: Box<dyn Write + Send>,
+        consumer_file: Box<dyn Write + Send>,
+        token: CancellationToken,
+    ) -> Self {
+        Self::with_sinks(
+            hid,
+            Sink::File(keyboard_file),
+            Sink::File(mouse_file),
+            Sink::File(consumer_file),
+            token,
         )
     }
 
+    /// Writes the reports to the sinks of another transport, e.g. Bluetooth. The LED state
+    /// is only read if the transport stores it in `led_reports`.
+    pub fn with_sinks(
+        hid: SynergyHid,
+        keyboard: Sink,
+        mouse: Sink,
+        consumer: Sink,
+        token: CancellationToken,
+    ) -> Self {
+        Self {
+            hid,
+            keyboard: HidWriter::new("Keyboard", keyboard, token.clone()),
+            mouse: HidWriter::new("Mouse", mouse, token.clone()),
+            consumer: HidWriter::new("Consumer", consumer, token),
+            gamepad: None,
+            leds: LedReports::default(),
+            screensaver: ScreenSaverAction::default(),
+            edge_pipe: None,
+            type_delay: Duration::ZERO,
+            dedup: ReportDedup::default(),
+            metrics: None,
+            activity: None,
+        }
+    }
+
+    /// Releases all keys and buttons, so nothing is left held on the host after exiting.
+    /// Writes to a device the host doesn't read from can block forever, so this gives up
+    /// after `timeout` and returns `false`, the writes may still be pending then.
+    pub fn shutdown(&mut self, timeout: Duration) -> bool {
+        self.release_all();
+        self.flush(timeout)
+    }
+
+    /// Forwards the first game controller of the server to the gamepad device `gamepad`,
+    /// the game controller events are dropped otherwise
+    pub fn set_gamepad(&mut self, gamepad: Sink, token: CancellationToken) {
+        self.gamepad = Some(HidWriter::new("Gamepad", gamepad, token));
+    }
+
+    /// Where the transport stores the LED output reports from the host, see
+    /// [`apply_led_reports`]
+    pub fn led_reports(&self) -> LedReports {
+        self.leds.clone()
+    }
+
+    /// Waits until the reports queued so far are written, returns `false` on timeout
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        self.devices()
+            .all(|device| device.flush(deadline.saturating_duration_since(Instant::now())))
+    }
+
+    /// `false` while the host is suspended or unplugged, the reports are written once it's back
+    pub fn host_attached(&self) -> bool {
+        self.devices()
+            .all(|device| device.state() != DeviceState::Detached)
+    }
+
+    /// Replaces the HID state and with it the screen size, e.g. after the config is reloaded.
+    /// Keys and buttons held are released first, the cursor position is kept so relative
+    /// moves after reconnecting don't start from the corner.
+    pub fn reconfigure(&mut self, mut hid: SynergyHid) {
+        self.release_all();
+        let (x, y) = self.hid.cursor_position();
+        hid.set_position(x, y);
+        hid.set_generation(self.hid.generation());
+        if let Some(leds) = self.hid.led_state() {
+            hid.set_led_state(leds);
+        }
+        self.hid = hid;
+        self.dedup.reset();
+    }
+
+    /// The display of the host changed, the cursor is clamped to it and the server is sent
+    /// this size from now on. Returns `false` if it's the size already used.
+    pub fn set_screen_size(&mut self, (width, height): (u16, u16)) -> bool {
+        if self.hid.screen_size() == (width, height) {
+            return false;
+        }
+        self.hid.set_screen_size(width, height);
+        // The same position is another absolute report on the new size
+        self.dedup.reset();
+        true
+    }
+
+    pub fn set_screensaver_action(&mut self, action: ScreenSaverAction) {
+        self.screensaver = action;
+    }
+
+    /// Named pipe receiving a "left", "right", "top" or "bottom" line when the cursor reaches
+    /// an edge, e.g. for a script switching a KVM
+    pub fn set_edge_pipe(&mut self, pipe: Option<PathBuf>) {
+        self.edge_pipe = pipe;
+    }
+
+    /// Counts the duplicate reports skipped with `metrics`, see [`Metrics::report_skipped`]
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn Metrics>>) {
+        self.metrics = metrics;
+    }
+
+    /// Notifies `activity` for each report written to the host
+    #[cfg(feature = "indicator")]
+    pub fn set_activity(&mut self, activity: Option<Arc<Notify>>) {
+        self.activity = activity;
+    }
+
+    /// Delay after each character typed with `type_text`
+    pub fn set_type_delay(&mut self, delay: Duration) {
+        self.type_delay = delay;
+    }
+
+    /// Types ASCII text with the US layout like a macro does, other characters are skipped.
+    /// Blocks until it's typed, returns `false` if a macro is running.
+    pub fn type_text(&mut self, text: &str) -> bool {
+        let delay = self.type_delay.as_millis() as u64;
+        let steps: Vec<_> = text
+            .chars()
+            .flat_map(|c| [MacroStep::Text(c.to_string()), MacroStep::Delay(delay)])
+            .collect();
+        if !self.hid.start_macro(&steps) {
+            return false;
+        }
+        self.write_pending_macro();
+        true
+    }
+
+    /// Releases all keys and buttons on the host, the reports are written even if they
+    /// didn't change, e.g. when a key is stuck because one was lost
+    pub fn release_all(&mut self) {
+        debug!("Clear all HID reports");
+        for report in self.clear_all() {
+            self.write_clear(report.as_parts(), true);
+        }
+    }
+
+    // The reports of `SynergyHid::clear_all` and the gamepad one if there is a gamepad
+    fn clear_all(&mut self) -> Vec<Report> {
+        let mut reports = self.hid.clear_all().to_vec();
+        if self.gamepad.is_some() {
+            reports.push(Report::from(self.hid.clear_report(ReportType::Gamepad)));
+        }
+        reports
+    }
+
+    /// Releases the keys held for too long, see [`SynergyHid::tick`]
+    pub fn release_stuck_keys(&mut self, now: Duration) {
+        for report in self.hid.tick(now) {
+            self.write_report(report.as_parts());
+        }
+    }
+
+    fn devices(&self) -> impl Iterator<Item = &HidWriter> {
+        [&self.keyboard, &self.mouse, &self.consumer]
+            .into_iter()
+            .chain(&self.gamepad)
+    }
+
+    // `None` for the gamepad if there is none
+    fn device(&self, report_type: ReportType) -> Option<&HidWriter> {
+        match report_type {
+            ReportType::Keyboard => Some(&self.keyboard),
+            ReportType::Mouse | ReportType::RelativeMouse => Some(&self.mouse),
+            ReportType::Consumer => Some(&self.consumer),
+            ReportType::Gamepad => self.gamepad.as_ref(),
+        }
+    }
+
+    // Always written, a key tapped again must reach the host even if its report is the same
     fn write_report(&mut self, report: (ReportType, &[u8])) {
-        let r = match report.0 {
-            ReportType::Keyboard => self.keyboard_file.write_all(report.1),
-            ReportType::Mouse => self.mouse_file.write_all(report.1),
-            ReportType::Consumer => self.consumer_file.write_all(report.1),
-        };
-        match r {
-            Ok(_) => (),
-            Err(e) => {
-                error!("Error writing report: {:?}", e);
-                self.token.cancel();
+        // Nothing changed, e.g. a key without a HID usage
+        if report.1.is_empty() {
+            return;
+        }
+        self.dedup.force(report);
+        if let Some(device) = self.device(report.0) {
+            device.write(report.1);
+        }
+        self.notify_activity();
+    }
+
+    // Skipped if it's the same as the last report of the device, e.g. releasing what is
+    // already released
+    fn write_changed(&mut self, report: (ReportType, &[u8])) {
+        if report.1.is_empty() || self.is_duplicate(report) {
+            return;
+        }
+        if let Some(device) = self.device(report.0) {
+            device.write(report.1);
+        }
+        self.notify_activity();
+    }
+
+    fn notify_activity(&self) {
+        if let Some(activity) = &self.activity {
+            activity.notify_one();
+        }
+    }
+
+    // A report of `clear_all`, the reports of the device queued before aren't written any
+    // more. Unless `force`, it's skipped if it's the same as the last report of the device,
+    // e.g. releasing what is already released
+    fn write_clear(&mut self, report: (ReportType, &[u8]), force: bool) {
+        if report.1.is_empty() {
+            return;
+        }
+        if force {
+            self.dedup.force(report);
+        } else if self.is_duplicate(report) {
+            return;
+        }
+        let generation = self.hid.generation();
+        if let Some(device) = self.device(report.0) {
+            device.write_clear(report.1, generation);
+        }
+    }
+
+    // Moves superseded by the next one can be dropped if the host is busy, and those to the
+    // position the host already has are skipped. An absolute position still queued when the
+    // next one comes isn't written at all.
+    fn write_motion(&mut self, report: (ReportType, &[u8])) {
+        if self.is_duplicate(report) {
+            return;
+        }
+        match (report.0, self.device(report.0)) {
+            (ReportType::Mouse, Some(device)) => device.write_position(report.1),
+            (_, Some(device)) => device.write_motion(report.1),
+            (_, None) => {}
+        }
+    }
+
+    fn is_duplicate(&mut self, report: (ReportType, &[u8])) -> bool {
+        if self.dedup.check(report) {
+            return false;
+        }
+        if let (Some(metrics), Some(device)) = (&self.metrics, self.device(report.0)) {
+            metrics.report_skipped(device.name());
+        }
+        true
+    }
+
+    // Only the first game controller is forwarded, there is a single gamepad
+    fn has_gamepad(&self, id: u8) -> bool {
+        if self.gamepad.is_none() {
+            return false;
+        }
+        if id != 0 {
+            debug!("Game controller {id} ignored, only the first one is forwarded");
+        }
+        id == 0
+    }
+
+    // Taps the lock keys the host doesn't have as the server before the key event
+    fn write_lock_sync(&mut self, key: u16, mask: KeyMask, button: u16) {
+        let report = &mut [0; MAX_REPORT_LEN];
+        let mut reports = vec![];
+        self.hid
+            .sync_locks(key, mask, button, report, |r| reports.push(Report::from(r)));
+        if !reports.is_empty() {
+            debug!("Lock keys toggled, HID reports: {:?}", reports);
+        }
+        for report in reports {
+            self.write_report(report.as_parts());
+        }
+    }
+
+    fn write_pending_key(&mut self) {
+        let report = &mut [0; MAX_REPORT_LEN];
+        if let Some(ret) = self.hid.pending_key(report) {
+            debug!("Half-duplex key release, HID report: {:?}", ret);
+            self.write_report(ret);
+        }
+    }
+
+    // Events received meanwhile wait for the macro, the delays block the actuator
+    fn write_pending_macro(&mut self) {
+        let report = &mut [0; MAX_REPORT_LEN];
+        while let Some(event) = self.hid.pending_macro(report) {
+            match event {
+                MacroEvent::Report(report_type, report) => {
+                    debug!("Macro, HID report: {:?}", (report_type, report));
+                    self.write_report((report_type, report));
+                }
+                MacroEvent::Delay(delay) => std::thread::sleep(delay),
             }
         }
     }
+
+    fn write_pending_motion(&mut self) {
+        let report = &mut [0; MAX_REPORT_LEN];
+        while let Some(ret) = self.hid.pending_motion(report) {
+            debug!("Pending relative move, HID report: {:?}", ret);
+            self.write_motion(ret);
+        }
+    }
 }
 
 impl Actuator for BarpiActuator {
-    fn connected(&mut self) {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
         info!("Connected");
+        Ok(())
+    }
+
+    fn connected_with(&mut self, info: ServerInfo) -> Result<(), ActuatorError> {
+        info!(
+            "Connected to {:?} server, protocol {}.{}",
+            info.protocol, info.major, info.minor
+        );
+        Ok(())
     }
 
-    fn disconnected(&mut self) {
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
         info!("Disconnected");
+        Ok(())
     }
 
     fn get_screen_size(&self) -> (u16, u16) {
-        (self.width, self.height)
+        self.hid.screen_size()
     }
 
     fn get_cursor_position(&self) -> (u16, u16) {
-        (self.x, self.y)
+        self.hid.cursor_position()
     }
 
-    fn set_cursor_position(&mut self, x: u16, y: u16) {
-        (self.x, self.y) = self.scale_position(x, y);
-        let report = &mut [0; 9];
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        let report = &mut [0; MAX_REPORT_LEN];
         let ret = self.hid.set_cursor_position(x, y, report);
         debug!("Set cursor position to {x} {y}, HID report: {:?}", ret);
-        self.write_report(ret);
+        self.write_motion(ret);
+        self.write_pending_motion();
+        Ok(())
     }
 
-    fn move_cursor(&mut self, x: i16, y: i16) {
-        self.x = (self.x as i32 + x as i32) as u16;
-        self.y = (self.y as i32 + y as i32) as u16;
-        self.set_cursor_position(self.x, self.y);
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        let report = &mut [0; MAX_REPORT_LEN];
+        let ret = self.hid.move_cursor(x, y, report);
+        debug!("Move cursor by {x} {y}, HID report: {:?}", ret);
+        self.write_motion(ret);
+        self.write_pending_motion();
+        Ok(())
     }
 
-    fn mouse_down(&mut self, button: i8) {
-        let report = &mut [0; 9];
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        let report = &mut [0; MAX_REPORT_LEN];
         let ret = self.hid.mouse_down(button, report);
         debug!("Mouse button {button} down, HID report: {:?}", ret);
         self.write_report(ret);
+        Ok(())
     }
 
-    fn mouse_up(&mut self, button: i8) {
-        let report = &mut [0; 9];
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        let report = &mut [0; MAX_REPORT_LEN];
         let ret = self.hid.mouse_up(button, report);
         debug!("Mouse button {button} up, HID report: {:?}", ret);
         self.write_report(ret);
+        Ok(())
     }
 
-    fn mouse_wheel(&mut self, x: i16, y: i16) {
-        let report = &mut [0; 9];
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        let report = &mut [0; MAX_REPORT_LEN];
         let ret = self.hid.mouse_scroll(x, y, report);
         debug!("Mouse wheel {x} {y}, HID report: {:?}", ret);
         self.write_report(ret);
+        while let Some(ret) = self.hid.pending_scroll(report) {
+            debug!("Pending mouse wheel, HID report: {:?}", ret);
+            self.write_report(ret);
+        }
+        Ok(())
     }
 
-    fn key_down(&mut self, key: u16, mask: u16, button: u16) {
-        let report = &mut [0; 9];
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.write_lock_sync(key, mask, button);
+        let report = &mut [0; MAX_REPORT_LEN];
         let ret = self.hid.key_down(key, mask, button, report);
         debug!("Key down {key} {mask} {button}, HID report: {:?}", ret);
+        let held_keys = self.hid.held_keys().count();
+        if held_keys > 6 {
+            debug!("{held_keys} keys held, keyboard report overflowed");
+        }
         self.write_report(ret);
+        self.write_pending_key();
+        self.write_pending_macro();
+        Ok(())
     }
 
-    fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
-        debug!("Key repeat {key} {mask} {button} {count}")
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.write_lock_sync(key, mask, button);
+        let report = &mut [0; MAX_REPORT_LEN];
+        let mut reports = vec![];
+        self.hid.key_repeat(key, mask, button, count, report, |r| {
+            reports.push(Report::from(r))
+        });
+        debug!(
+            "Key repeat {key} {mask} {button} {count}, HID reports: {:?}",
+            reports
+        );
+        for report in reports {
+            self.write_report(report.as_parts());
+        }
+        Ok(())
     }
 
-    fn key_up(&mut self, key: u16, mask: u16, button: u16) {
-        let report = &mut [0; 9];
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.write_lock_sync(key, mask, button);
+        let report = &mut [0; MAX_REPORT_LEN];
         let ret = self.hid.key_up(key, mask, button, report);
         debug!("Key up {key} {mask} {button}, HID report: {:?}", ret);
         self.write_report(ret);
+        self.write_pending_key();
+        Ok(())
     }
 
-    fn enter(&mut self) {
-        info!("Enter")
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        info!("Enter");
+        if !self.host_attached() {
+            warn!("The host is suspended or unplugged, events are dropped until it's back");
+        }
+        // The host may have been replugged while the screen was left
+        self.dedup.reset();
+        Ok(())
     }
 
-    fn leave(&mut self) {
+    fn leave(&mut self) -> Result<(), ActuatorError> {
         info!("Leave");
         debug!("Clear HID reports");
-        let report = &mut [0; 9];
-        let ret = self.hid.clear(ReportType::Keyboard, report);
-        self.write_report(ret);
-        let ret = self.hid.clear(ReportType::Mouse, report);
-        self.write_report(ret);
-        let ret = self.hid.clear(ReportType::Consumer, report);
-        self.write_report(ret);
+        for report in self.clear_all() {
+            self.write_clear(report.as_parts(), false);
+        }
+        Ok(())
     }
 
-    fn set_options(&mut self, opts: std::collections::HashMap<String, u32>) {
-        debug!("Set options {:#?}", opts)
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        debug!("Set options {:#?}", opts);
+        if let Some(half_duplex) = opts.half_duplex_caps_lock {
+            self.hid.set_half_duplex_caps_lock(half_duplex);
+        }
+        if let Some(half_duplex) = opts.half_duplex_num_lock {
+            self.hid.set_half_duplex_num_lock(half_duplex);
+        }
+        Ok(())
     }
 
-    fn reset_options(&mut self) {
-        debug!("Reset options")
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        debug!("Reset options");
+        self.hid.set_half_duplex_caps_lock(false);
+        self.hid.set_half_duplex_num_lock(false);
+        Ok(())
     }
 
-    fn set_clipboard(&mut self, data: ClipboardData) {
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
         info!(
             "Clipboard text:{}",
             data.text()
@@ -173,5 +568,843 @@ impl Actuator for BarpiActuator {
             "Clipboard bitmap:{}",
             data.bitmap().map(|_| "yes").unwrap_or("no")
         );
+        Ok(())
+    }
+
+    fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        // There is nowhere to put it on the gadget
+        info!(
+            "File {} of {} bytes received, dropped",
+            name_hint.as_deref().unwrap_or("<unnamed>"),
+            data.len()
+        );
+        Ok(())
+    }
+
+    fn led_state_changed(&mut self, state: LedState) -> Result<(), ActuatorError> {
+        info!("LED state changed: {:?}", state);
+        self.hid.set_led_state(state);
+        Ok(())
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        info!("Screen saver {}", if on { "on" } else { "off" });
+        if on && !self.screensaver.keys.is_empty() {
+            let keys = self.screensaver.keys.clone();
+            let report = &mut [0; MAX_REPORT_LEN];
+            let mut reports = vec![];
+            self.hid
+                .tap_keys(&keys, report, |r| reports.push(Report::from(r)));
+            debug!("Screen saver keys, HID reports: {:?}", reports);
+            for report in reports {
+                self.write_report(report.as_parts());
+            }
+        }
+        if let Some(pipe) = &self.screensaver.pipe {
+            if let Err(e) = write_pipe(pipe, if on { "on" } else { "off" }) {
+                warn!("Cannot write to {}: {:?}", pipe.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        if !self.has_gamepad(id) {
+            return Ok(());
+        }
+        let report = &mut [0; MAX_REPORT_LEN];
+        let ret = self.hid.game_buttons(buttons, report);
+        debug!("Game buttons {buttons:#06x}, HID report: {:?}", ret);
+        self.write_changed(ret);
+        Ok(())
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        if !self.has_gamepad(id) {
+            return Ok(());
+        }
+        let report = &mut [0; MAX_REPORT_LEN];
+        let ret = self.hid.game_sticks(left, right, report);
+        debug!("Game sticks {left:?} {right:?}, HID report: {:?}", ret);
+        self.write_changed(ret);
+        Ok(())
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        if !self.has_gamepad(id) {
+            return Ok(());
+        }
+        let report = &mut [0; MAX_REPORT_LEN];
+        let ret = self.hid.game_triggers(left, right, report);
+        debug!("Game triggers {left} {right}, HID report: {:?}", ret);
+        self.write_changed(ret);
+        Ok(())
+    }
+
+    fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        let edge = match edge {
+            Edge::Left => "left",
+            Edge::Right => "right",
+            Edge::Top => "top",
+            Edge::Bottom => "bottom",
+        };
+        info!("Cursor reached the {} edge", edge);
+        if let Some(pipe) = &self.edge_pipe {
+            if let Err(e) = write_pipe(pipe, edge) {
+                warn!("Cannot write to {}: {:?}", pipe.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The LED output reports of the host, stored by whatever reads them, e.g. a thread reading
+/// the keyboard device file, and taken by [`apply_led_reports`]
+#[derive(Clone, Default)]
+pub struct LedReports {
+    // Latest state not taken yet
+    latest: Arc<Mutex<Option<LedState>>>,
+    stored: Arc<Notify>,
+}
+
+impl LedReports {
+    pub fn store(&self, state: LedState) {
+        *self.latest.lock().unwrap() = Some(state);
+        self.stored.notify_one();
+    }
+
+    /// Waits for a state that wasn't taken yet, only the latest one is kept meanwhile
+    pub async fn next(&self) -> LedState {
+        loop {
+            if let Some(state) = self.latest.lock().unwrap().take() {
+                return state;
+            }
+            self.stored.notified().await;
+        }
+    }
+}
+
+/// Passes the LED output reports of the host to `led_state_changed` as soon as they're
+/// read, so the lock keys are synced against the latest state
+pub async fn apply_led_reports(actuator: Shared, leds: LedReports) {
+    loop {
+        let state = leds.next().await;
+        if let Err(e) = actuator.lock().unwrap().led_state_changed(state) {
+            warn!("Cannot apply the LED state: {}", e);
+        }
+    }
+}
+
+/// Checks the keys held on the host every second for the stuck key timeouts, they're off
+/// unless configured
+pub async fn release_stuck_keys(actuator: Shared) {
+    let started = Instant::now();
+    let mut interval = tokio::time::interval(STUCK_KEY_TICK);
+    loop {
+        interval.tick().await;
+        actuator
+            .lock()
+            .unwrap()
+            .get_mut()
+            .release_stuck_keys(started.elapsed());
+    }
+}
+
+// Non-blocking so a pipe nobody reads from doesn't stall the client, opening fails if
+// there is no reader
+fn write_pipe(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut pipe = OpenOptions::new()
+        .append(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    pipe.write_all(format!("{line}\n").as_bytes())
+}
+
+/// Dumps each report as a line of hex bytes to `out`, or to the log if there is none
+pub struct HexDump {
+    name: &'static str,
+    out: Option<Box<dyn Write + Send>>,
+}
+
+impl HexDump {
+    pub fn new(name: &'static str, out: Option<Box<dyn Write + Send>>) -> Self {
+        Self { name, out }
+    }
+}
+
+impl Write for HexDump {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let hex = buf
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        match &mut self.out {
+            Some(out) => writeln!(out, "{hex}")?,
+            None => info!("{} report: {}", self.name, hex),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.out {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// The host sets the keyboard LEDs with output reports, which can be read from the device file
+fn read_led_reports(mut file: File, report_id: Option<u8>, leds: LedReports) {
+    std::thread::spawn(move || {
+        let mut buf = [0; 8];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => {
+                    debug!("Keyboard output report: {:?}", &buf[..len]);
+                    let state = without_report_id(&buf[..len], report_id).and_then(|report| {
+                        SynergyHid::parse_output_report(ReportType::Keyboard, report)
+                    });
+                    if let Some(state) = state {
+                        leds.store(state);
+                    }
+                }
+                Err(e) => {
+                    warn!("Error reading keyboard output report: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+// The report without its ID, `None` if it's the report of another device
+fn without_report_id(report: &[u8], report_id: Option<u8>) -> Option<&[u8]> {
+    match (report_id, report) {
+        (None, report) => Some(report),
+        (Some(id), [first, rest @ ..]) if *first == id => Some(rest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use barrier_client::{
+        test_server::{MockServer, Packet},
+        Actuator, Backoff, Client, ConnectionError, Edge, KeyMask, LedState, Metrics,
+    };
+    use synergy_hid::{StuckKeyTimeouts, SynergyHid};
+    use tokio_util::sync::CancellationToken;
+
+    use super::{without_report_id, BarpiActuator, HexDump, LedReports, ScreenSaverAction};
+    use crate::sink::Sink;
+
+    type Log = Arc<Mutex<Vec<(&'static str, Vec<u8>)>>>;
+
+    // Records the reports written to all devices in order
+    struct Recorder(&'static str, Log);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.lock().unwrap().push((self.0, buf.to_vec()));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The devices are written from their own threads, only the order per device is kept
+    fn sorted(log: &Log) -> Vec<(&'static str, Vec<u8>)> {
+        let mut log = log.lock().unwrap().clone();
+        log.sort_by_key(|(device, _)| *device);
+        log
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // A device the host doesn't read from
+    struct Wedged;
+
+    impl Write for Wedged {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            std::thread::sleep(Duration::from_secs(3600));
+            Ok(0)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn actuator(
+        keyboard: Box<dyn Write + Send>,
+        mouse: Box<dyn Write + Send>,
+        consumer: Box<dyn Write + Send>,
+        token: CancellationToken,
+    ) -> BarpiActuator {
+        BarpiActuator::with_writers(
+            SynergyHid::new(1920, 1080, false),
+            keyboard,
+            mouse,
+            consumer,
+            token,
+        )
+    }
+
+    #[test]
+    fn test_shutdown_clears_all() {
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(Recorder("mouse", log.clone())),
+            Box::new(Recorder("consumer", log.clone())),
+            CancellationToken::new(),
+        );
+        actuator.key_down('a' as u16, KeyMask::empty(), 1).unwrap();
+        actuator.mouse_down(1).unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        log.lock().unwrap().clear();
+
+        assert!(actuator.shutdown(Duration::from_secs(1)));
+        assert_eq!(
+            sorted(&log),
+            [
+                ("consumer", vec![0; 9]),
+                ("keyboard", vec![0; 8]),
+                ("mouse", vec![0; 7]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let mut actuator = actuator(
+            Box::new(Wedged),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        assert!(!actuator.shutdown(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_write_error_cancels() {
+        let log = Log::default();
+        let token = CancellationToken::new();
+        let mut actuator = actuator(
+            Box::new(Broken),
+            Box::new(Recorder("mouse", log.clone())),
+            Box::new(Recorder("consumer", log.clone())),
+            token.clone(),
+        );
+        actuator.mouse_down(1).unwrap();
+        actuator.key_down('a' as u16, KeyMask::empty(), 1).unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        assert!(token.is_cancelled());
+        log.lock().unwrap().clear();
+
+        // Nothing is left held on the devices still working
+        assert!(actuator.shutdown(Duration::from_secs(1)));
+        assert_eq!(
+            sorted(&log),
+            [("consumer", vec![0; 9]), ("mouse", vec![0; 7])]
+        );
+    }
+
+    #[test]
+    fn test_gamepad() {
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        // Dropped without a gamepad
+        actuator.game_buttons(0, 0x0001).unwrap();
+        actuator.set_gamepad(
+            Sink::File(Box::new(Recorder("gamepad", log.clone()))),
+            CancellationToken::new(),
+        );
+        actuator.game_buttons(0, 0x1000).unwrap();
+        actuator.game_buttons(0, 0x1000).unwrap();
+        // Another controller
+        actuator.game_sticks(1, (100, 100), (0, 0)).unwrap();
+        actuator.game_triggers(0, 0, 255).unwrap();
+        // Written before the leave, which drops the reports still queued
+        assert!(actuator.flush(Duration::from_secs(1)));
+        actuator.leave().unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        assert_eq!(
+            sorted(&log),
+            [
+                ("gamepad", vec![0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                ("gamepad", vec![0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255]),
+                ("gamepad", vec![0; 12]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_screensaver() {
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        let pipe = std::env::temp_dir().join(format!("barpi-screensaver-{}", std::process::id()));
+        std::fs::write(&pipe, "").unwrap();
+        actuator.set_screensaver_action(ScreenSaverAction {
+            keys: vec![0xE3, 0x0F],
+            pipe: Some(pipe.clone()),
+        });
+
+        // Win+L when it starts, nothing when it stops
+        actuator.screensaver(true).unwrap();
+        actuator.screensaver(false).unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        assert_eq!(
+            sorted(&log),
+            [
+                ("keyboard", vec![0x08, 0, 0, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0x08, 0, 0x0F, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0x08, 0, 0, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0; 8]),
+            ]
+        );
+        assert_eq!(std::fs::read_to_string(&pipe).unwrap(), "on\noff\n");
+        std::fs::remove_file(pipe).unwrap();
+    }
+
+    #[test]
+    fn test_edge_pipe() {
+        let mut actuator = actuator(
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        let pipe = std::env::temp_dir().join(format!("barpi-edge-{}", std::process::id()));
+        std::fs::write(&pipe, "").unwrap();
+        actuator.set_edge_pipe(Some(pipe.clone()));
+        actuator.edge_reached(Edge::Right).unwrap();
+        actuator.edge_reached(Edge::Top).unwrap();
+        assert_eq!(std::fs::read_to_string(&pipe).unwrap(), "right\ntop\n");
+        std::fs::remove_file(pipe).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_position_survives_reconnect() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .reconnect(true)
+            .backoff(Backoff {
+                initial: Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let served = tokio::spawn(async move {
+            let first = [Packet::MouseMoveAbs { x: 960, y: 540 }.into()];
+            server.serve(first).await.unwrap();
+            // The server restarted, it sends a relative move before any position
+            let second = [
+                Packet::MouseMove { x: 10, y: -10 }.into(),
+                Packet::ErrorBusy.into(),
+            ];
+            server.serve(second).await.unwrap();
+        });
+        let mut actuator = actuator(
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        let result = client.run(&mut actuator).await;
+        assert!(matches!(result, Err(ConnectionError::Busy)));
+        served.await.unwrap();
+        assert_eq!(actuator.get_cursor_position(), (970, 530));
+    }
+
+    #[tokio::test]
+    async fn test_half_duplex_option() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let served = tokio::spawn(async move {
+            let half_duplex = |on| Packet::SetDeviceOptions([("HDCL".into(), on)].into());
+            let key = |down, id, button| match down {
+                true => Packet::KeyDown {
+                    id,
+                    mask: KeyMask::empty(),
+                    button,
+                },
+                false => Packet::KeyUp {
+                    id,
+                    mask: KeyMask::empty(),
+                    button,
+                },
+            };
+            let script = [
+                Packet::CursorEnter {
+                    x: 0,
+                    y: 0,
+                    seq_num: 1,
+                    mask: KeyMask::empty(),
+                }
+                .into(),
+                half_duplex(1).into(),
+                key(true, 0xEFE5, 2).into(),
+                key(false, 0xEFE5, 2).into(),
+                // Turned off in the same session, Caps Lock is held again
+                half_duplex(0).into(),
+                key(true, 0xEFE5, 2).into(),
+                key(true, 'a' as u16, 1).into(),
+                key(false, 'a' as u16, 1).into(),
+                key(false, 0xEFE5, 2).into(),
+                Packet::ErrorBusy.into(),
+            ];
+            server.serve(script).await.unwrap();
+        });
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        let result = client.run(&mut actuator).await;
+        assert!(matches!(result, Err(ConnectionError::Busy)));
+        served.await.unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        let reports: Vec<_> = sorted(&log).into_iter().map(|(_, r)| r).collect();
+        assert_eq!(
+            reports,
+            [
+                vec![0, 0, 0x39, 0, 0, 0, 0, 0],
+                vec![0; 8],
+                vec![0, 0, 0x39, 0, 0, 0, 0, 0],
+                vec![0; 8],
+                vec![0, 0, 0x39, 0, 0, 0, 0, 0],
+                vec![0, 0, 0x39, 0x04, 0, 0, 0, 0],
+                vec![0, 0, 0x39, 0, 0, 0, 0, 0],
+                vec![0; 8],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reconfigure_keeps_position() {
+        let mut actuator = actuator(
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        actuator.set_cursor_position(1500, 900).unwrap();
+        actuator.reconfigure(SynergyHid::new(1920, 1080, true));
+        assert_eq!(actuator.get_cursor_position(), (1500, 900));
+        // Clamped to the new screen
+        actuator.reconfigure(SynergyHid::new(1280, 720, false));
+        assert_eq!(actuator.get_cursor_position(), (1279, 719));
+    }
+
+    #[test]
+    fn test_type_text() {
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        actuator.set_type_delay(Duration::from_millis(1));
+        // Shift is held on the server, it's held again once typed
+        actuator.key_down(0xEFE1, KeyMask::SHIFT, 0x32).unwrap();
+        // The accent can't be typed with the US layout
+        assert!(actuator.type_text("Hé!\n"));
+        assert!(actuator.flush(Duration::from_secs(1)));
+        let shift = |key| vec![0x02, 0, key, 0, 0, 0, 0, 0];
+        let plain = |key| vec![0, 0, key, 0, 0, 0, 0, 0];
+        assert_eq!(
+            sorted(&log),
+            [
+                shift(0),
+                // H
+                shift(0),
+                shift(0x0B),
+                shift(0),
+                plain(0),
+                // !
+                shift(0),
+                shift(0x1E),
+                shift(0),
+                plain(0),
+                // Enter
+                plain(0x28),
+                plain(0),
+                shift(0),
+            ]
+            .map(|report| ("keyboard", report))
+        );
+    }
+
+    // Busy for the given number of writes, then records them
+    struct Busy(Arc<Mutex<u32>>, Recorder);
+
+    impl Write for Busy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut busy = self.0.lock().unwrap();
+            if *busy > 0 {
+                *busy -= 1;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_leave_enter_keeps_new_keys() {
+        let log = Log::default();
+        let busy = Arc::new(Mutex::new(50));
+        let mut actuator = actuator(
+            Box::new(Busy(busy.clone(), Recorder("keyboard", log.clone()))),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        actuator.enter().unwrap();
+        actuator
+            .key_down('x' as u16, KeyMask::empty(), 0x2D)
+            .unwrap();
+        // The host is slow to read the first key, the next reports are queued
+        while *busy.lock().unwrap() == 50 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        actuator
+            .key_down('y' as u16, KeyMask::empty(), 0x15)
+            .unwrap();
+        actuator.leave().unwrap();
+        actuator.enter().unwrap();
+        actuator
+            .key_down('a' as u16, KeyMask::empty(), 0x1E)
+            .unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        // The keys pressed before the leave are dropped, the one after it is held
+        assert_eq!(
+            sorted(&log),
+            [
+                ("keyboard", vec![0, 0, 0x1B, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0; 8]),
+                ("keyboard", vec![0, 0, 0x04, 0, 0, 0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_release_stuck_keys() {
+        let log = Log::default();
+        let hid = SynergyHid::new(1920, 1080, false).with_stuck_key_timeouts(StuckKeyTimeouts {
+            modifier: None,
+            key: Some(Duration::from_secs(60)),
+        });
+        let mut actuator = BarpiActuator::with_writers(
+            hid,
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        actuator.key_down(0xEFE3, KeyMask::empty(), 0x37).unwrap();
+        actuator
+            .key_down('a' as u16, KeyMask::empty(), 0x1E)
+            .unwrap();
+        actuator.release_stuck_keys(Duration::ZERO);
+        actuator.release_stuck_keys(Duration::from_secs(60));
+        // The key up lost on the way is expected, it writes nothing
+        actuator.key_up('a' as u16, KeyMask::empty(), 0x1E).unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        assert_eq!(
+            sorted(&log),
+            [
+                ("keyboard", vec![0x01, 0, 0, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0x01, 0, 0x04, 0, 0, 0, 0, 0]),
+                // Ctrl is held on purpose as far as the watchdog knows
+                ("keyboard", vec![0x01, 0, 0, 0, 0, 0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lock_sync() {
+        let log = Log::default();
+        let hid = SynergyHid::new(1920, 1080, false).with_lock_sync(true);
+        let mut actuator = BarpiActuator::with_writers(
+            hid,
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        actuator.led_state_changed(LedState::default()).unwrap();
+        // Caps Lock is on for the server only, tapped once before the key
+        for (key, button) in [('A' as u16, 0x1E), ('B' as u16, 0x30)] {
+            actuator.key_down(key, KeyMask::CAPS_LOCK, button).unwrap();
+            actuator.key_up(key, KeyMask::CAPS_LOCK, button).unwrap();
+        }
+        assert!(actuator.flush(Duration::from_secs(1)));
+        assert_eq!(
+            sorted(&log),
+            [
+                ("keyboard", vec![0, 0, 0x39, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0; 8]),
+                ("keyboard", vec![0, 0, 0x04, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0; 8]),
+                ("keyboard", vec![0, 0, 0x05, 0, 0, 0, 0, 0]),
+                ("keyboard", vec![0; 8]),
+            ]
+        );
+    }
+
+    // Counts the skipped reports of each device
+    #[derive(Default)]
+    struct Skipped(Mutex<Vec<&'static str>>);
+
+    impl Metrics for Skipped {
+        fn report_skipped(&self, device: &'static str) {
+            self.0.lock().unwrap().push(device);
+        }
+    }
+
+    #[test]
+    fn test_skip_duplicates() {
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(Recorder("keyboard", log.clone())),
+            Box::new(Recorder("mouse", log.clone())),
+            Box::new(Recorder("consumer", log.clone())),
+            CancellationToken::new(),
+        );
+        let skipped = Arc::new(Skipped::default());
+        actuator.set_metrics(Some(skipped.clone()));
+        actuator.enter().unwrap();
+
+        // Double taps are written in full even if the reports repeat
+        for key in ['a' as u16, 0xE0B0, 'a' as u16, 0xE0B0] {
+            actuator.key_down(key, KeyMask::empty(), 1).unwrap();
+            actuator.key_up(key, KeyMask::empty(), 1).unwrap();
+        }
+        actuator.set_cursor_position(100, 100).unwrap();
+        actuator.set_cursor_position(100, 100).unwrap();
+        // Everything is already released
+        actuator.leave().unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        let a = vec![0, 0, 0x04, 0, 0, 0, 0, 0];
+        let next = vec![0xB5, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            sorted(&log),
+            [
+                ("consumer", next.clone()),
+                ("consumer", vec![0; 9]),
+                ("consumer", next),
+                ("consumer", vec![0; 9]),
+                ("keyboard", a.clone()),
+                ("keyboard", vec![0; 8]),
+                ("keyboard", a),
+                ("keyboard", vec![0; 8]),
+                ("mouse", vec![0, 0xAC, 0x06, 0xDD, 0x0B, 0, 0]),
+            ]
+        );
+        assert_eq!(
+            *skipped.0.lock().unwrap(),
+            ["Mouse", "Keyboard", "Mouse", "Consumer"]
+        );
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let log = Log::default();
+        let mut actuator = actuator(
+            Box::new(HexDump::new(
+                "keyboard",
+                Some(Box::new(Recorder("keyboard", log.clone()))),
+            )),
+            Box::new(io::sink()),
+            Box::new(io::sink()),
+            CancellationToken::new(),
+        );
+        actuator.key_down('a' as u16, KeyMask::empty(), 1).unwrap();
+        assert!(actuator.flush(Duration::from_secs(1)));
+        let dump: Vec<u8> = log
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|(_, buf)| buf.clone())
+            .collect();
+        assert_eq!(dump, b"00 00 04 00 00 00 00 00\n");
+    }
+
+    #[tokio::test]
+    async fn test_led_reports() {
+        let leds = LedReports::default();
+        leds.store(LedState::from_bits(0x01));
+        leds.store(LedState::from_bits(0x02));
+        // Only the latest one is kept
+        assert_eq!(leds.next().await, LedState::from_bits(0x02));
+
+        let waiting = tokio::spawn({
+            let leds = leds.clone();
+            async move { leds.next().await }
+        });
+        tokio::task::yield_now().await;
+        leds.store(LedState::default());
+        assert_eq!(waiting.await.unwrap(), LedState::default());
+    }
+
+    #[test]
+    fn test_led_report_id() {
+        assert_eq!(without_report_id(&[0x02], None), Some(&[0x02][..]));
+        assert_eq!(without_report_id(&[1, 0x02], Some(1)), Some(&[0x02][..]));
+        assert_eq!(without_report_id(&[3, 0x02], Some(1)), None);
+        assert_eq!(without_report_id(&[], Some(1)), None);
     }
 }
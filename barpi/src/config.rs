@@ -0,0 +1,1350 @@
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use barrier_client::ClickAssistOptions;
+use clap::{Parser, Subcommand};
+use clap_serde_derive::{serde::Serialize, ClapSerde};
+use log::warn;
+use serde::{Deserialize, Deserializer};
+use synergy_hid::{
+    ButtonMapEntry, ServerPlatform, StuckKeyTimeouts, UnicodeInput, MAX_SCROLL_SPEED,
+};
+
+use crate::gadget::FunctionConfig;
+
+/// Port of the Barrier server when `server` has none
+pub const DEFAULT_SERVER_PORT: u16 = 24800;
+
+/// Largest screen size, the absolute mouse reports positions up to 0x7FFF
+const MAX_SCREEN_SIZE: u16 = 0x7FFF;
+
+/// Longest USB string descriptor in UTF-16 code units, its length is a byte with the header
+const MAX_USB_STRING_LEN: usize = 126;
+
+/// Longest screen name the server takes in the hello, in bytes
+const MAX_SCREEN_NAME_LEN: usize = 255;
+
+/// Replay speeds other than 0, slower ones would wait for ages between the events
+const REPLAY_SPEEDS: RangeInclusive<f32> = 0.001..=1000.0;
+
+/// A value of the config that can't work, see [`BarpiConfig::validate`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// One error listing all the problems found by [`BarpiConfig::validate`]
+pub fn invalid_config(errors: Vec<ConfigError>) -> anyhow::Error {
+    let mut message = "Invalid configuration".to_string();
+    for error in errors {
+        message.push_str(&format!("\n  {error}"));
+    }
+    anyhow!(message)
+}
+
+// Splits "host:port", the port is `None` if there is none. IPv6 addresses take a port only
+// in brackets, e.g. "[fd00::2]:24800"
+fn split_host_port(address: &str) -> Result<(&str, Option<u16>), String> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("invalid port {port:?} in {address:?}"))?;
+            (host, Some(port))
+        }
+        _ => (address, None),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(format!("no host in {address:?}"));
+    }
+    Ok((host, port))
+}
+
+// `server` with the default port if it has none
+fn server_with_port(server: &str) -> String {
+    match split_host_port(server) {
+        Ok((host, None)) if host.contains(':') => format!("[{host}]:{DEFAULT_SERVER_PORT}"),
+        Ok((host, None)) => format!("{host}:{DEFAULT_SERVER_PORT}"),
+        _ => server.to_string(),
+    }
+}
+
+// `server` in the config file, a single address or a list of them
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(Some(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(server) => vec![server],
+        OneOrMany::Many(servers) => servers,
+    }))
+}
+
+/// How the reports reach the target, see `transport`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Usb,
+    Bluetooth,
+}
+
+/// What shows the connection state, see `indicator`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Indicator {
+    /// The GPIO lines of the LEDs, see `indicator_pins`
+    Gpio(Vec<u32>),
+    Ssd1306 {
+        bus: PathBuf,
+        address: u8,
+        height: u8,
+    },
+}
+
+/// Local end of the connection to the server, see `bind`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Bind {
+    Address(SocketAddr),
+    Interface(String),
+}
+
+#[derive(Parser)]
+#[command(author, version, about)]
+pub struct Args {
+    /// Runs the client unless a command is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input files
+    pub input: Vec<std::path::PathBuf>,
+
+    /// Config file
+    #[arg(short, long = "config", default_value = "config.yml")]
+    pub config_path: std::path::PathBuf,
+
+    /// Rest of arguments
+    #[command(flatten)]
+    pub config: <BarpiConfig as ClapSerde>::Opt,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Applies the events of a recording made with `--record` to the HID devices instead of
+    /// connecting to the server
+    Replay {
+        /// Recording to replay
+        file: PathBuf,
+        /// Replay faster with a larger value, 0 doesn't wait between the events
+        #[arg(long, default_value = "1")]
+        speed: f32,
+    },
+}
+
+impl Command {
+    /// Checks the arguments like [`BarpiConfig::validate`]
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        match self {
+            Command::Replay { speed, .. } if *speed != 0.0 && !REPLAY_SPEEDS.contains(speed) => {
+                Err(vec![ConfigError {
+                    field: "speed",
+                    message: format!(
+                        "{speed} is not 0 or between {} and {}",
+                        REPLAY_SPEEDS.start(),
+                        REPLAY_SPEEDS.end()
+                    ),
+                }])
+            }
+            Command::Replay { .. } => Ok(()),
+        }
+    }
+}
+
+/// Sending SIGHUP reloads the config file and applies the new values, reconnecting only if
+/// the connection settings changed, see `connection_changed`. Fields marked as requiring a
+/// restart keep their current value until barpi is restarted.
+#[derive(ClapSerde, Serialize, Debug, Clone, PartialEq)]
+pub struct BarpiConfig {
+    /// Barrier server address in "server:port" format, the port is 24800 if not given. Given
+    /// more than once, or as a list in the config file, the servers are tried in turn and the
+    /// last one connected to first.
+    #[arg(short = 's', long, env = "BARRIER_SERVER", value_delimiter = ',')]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub server: Vec<String>,
+    /// Screen name, must be accepted by the Barrier server
+    #[arg(short = 'n', long, env = "SCREEN_NAME")]
+    pub screen_name: String,
+    /// Screen width
+    #[arg(short = 'w', long, default_value = "1920", env = "SCREEN_WIDTH")]
+    pub screen_width: u16,
+    /// Screen height
+    #[arg(short = 'e', long, default_value = "1080", env = "SCREEN_HEIGHT")]
+    pub screen_height: u16,
+    /// Use the size of the display connected to the target, read from DRM, and follow it when
+    /// it's plugged in or changes, the size above is used while there is none. Requires a
+    /// restart
+    #[arg(long, env = "AUTO_SCREEN_SIZE", num_args = 0..=1, default_missing_value = "true")]
+    pub auto_screen_size: bool,
+    /// Flip mouse wheel
+    #[arg(short = 'f', long, default_value = "false")]
+    pub flip_mouse_wheel: bool,
+    /// Flip the horizontal wheel only, whatever `flip_mouse_wheel` is
+    #[arg(long, env = "NATURAL_SCROLL_X", num_args = 0..=1, default_missing_value = "true")]
+    pub natural_scroll_x: bool,
+    /// Flip the vertical wheel only, whatever `flip_mouse_wheel` is
+    #[arg(long, env = "NATURAL_SCROLL_Y", num_args = 0..=1, default_missing_value = "true")]
+    pub natural_scroll_y: bool,
+    /// Multiplier of the wheel deltas, e.g. 3 for a target scrolling a line per notch or 0.5
+    /// for every other notch, up to 64
+    #[default(1.0)]
+    #[arg(long, env = "SCROLL_SPEED")]
+    pub scroll_speed: f32,
+    /// Use a boot protocol relative mouse instead of the absolute one, for BIOS/UEFI and KVM targets,
+    /// requires a restart
+    #[arg(long, env = "RELATIVE_MOUSE", num_args = 0..=1, default_missing_value = "true")]
+    pub relative_mouse: bool,
+    /// Report any number of keys held with a bitmap keyboard instead of the boot one limited
+    /// to 6, BIOS/UEFI won't see the keyboard, requires a restart
+    #[arg(long, env = "NKRO", num_args = 0..=1, default_missing_value = "true")]
+    pub nkro: bool,
+    /// Add a gamepad to the USB gadget, forwarding the first game controller of the server
+    /// when it sends them, requires a restart
+    #[arg(long, env = "GAMEPAD", num_args = 0..=1, default_missing_value = "true")]
+    pub gamepad: bool,
+    /// Register the consumer control, the media and power keys are dropped without it,
+    /// requires a restart
+    #[default(true)]
+    #[arg(long, env = "CONSUMER_CONTROL", num_args = 0..=1, default_missing_value = "true")]
+    pub consumer_control: bool,
+    /// Start each report with a report ID, for hosts expecting them, requires a restart
+    #[arg(long, env = "HID_REPORT_IDS", num_args = 0..=1, default_missing_value = "true")]
+    pub hid_report_ids: bool,
+    /// Put the keyboard, mouse, consumer control and gamepad in a single HID function with
+    /// report IDs, for hosts only using the first interface, requires a restart
+    #[arg(long, env = "HID_SINGLE_INTERFACE", num_args = 0..=1, default_missing_value = "true")]
+    pub hid_single_interface: bool,
+    /// Skip mouse moves superseded by moves already received, so the cursor doesn't lag behind
+    #[default(true)]
+    #[arg(long, env = "COALESCE_MOUSE_MOVES", num_args = 0..=1, default_missing_value = "true")]
+    pub coalesce_mouse_moves: bool,
+    /// Drop absolute moves of at most this many pixels within `click_window` of a mouse press
+    /// or release, for targets seeing two clicks when the cursor jitters between them, 0
+    /// disables it
+    #[arg(long, default_value = "0", env = "CLICK_JITTER")]
+    pub click_jitter: u16,
+    /// Time window in milliseconds of `click_jitter` and `drag_lock`
+    #[default(500)]
+    #[arg(long, env = "CLICK_WINDOW")]
+    pub click_window: u64,
+    /// A click quickly followed by a press keeps the button held, it's released by a release
+    /// once the cursor stopped moving for `click_window`
+    #[arg(long, env = "DRAG_LOCK", num_args = 0..=1, default_missing_value = "true")]
+    pub drag_lock: bool,
+    /// Tap Caps Lock or Num Lock on the host when its LEDs disagree with the lock state the
+    /// server sends with the keys, e.g. after it was toggled on the host's own keyboard
+    #[default(true)]
+    #[arg(long, env = "SYNC_LOCK_KEYS", num_args = 0..=1, default_missing_value = "true")]
+    pub sync_lock_keys: bool,
+    /// Ignore mouse and keyboard events received while the cursor isn't on this screen
+    #[arg(long, env = "STRICT_ENTER_GATING", num_args = 0..=1, default_missing_value = "true")]
+    pub strict_enter_gating: bool,
+    /// Local address, e.g. "192.168.1.20", or network interface, e.g. "eth0", the connection
+    /// to the server goes out of, the routing table picks it by default
+    #[arg(long, env = "BIND")]
+    pub bind: Option<String>,
+    /// Connect with TLS and only accept the server certificate with this fingerprint
+    #[arg(long, env = "TLS_FINGERPRINT")]
+    pub tls_fingerprint: Option<String>,
+    /// Connect with TLS and verify the server certificate with this PEM encoded CA bundle
+    #[arg(long, env = "TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+    /// Platform of the server, "windows", "x11" or "macos", to map the keys it sends without
+    /// a key id from their scan code
+    #[arg(long, env = "SERVER_PLATFORM")]
+    pub server_platform: Option<String>,
+    /// How characters without a key in the US layout and dead keys are typed on the host,
+    /// "linux" (Ctrl+Shift+U), "windows" (Alt and the keypad) or "skip"
+    #[arg(long, env = "UNICODE_INPUT")]
+    pub unicode_input: Option<String>,
+    /// YAML file remapping Synergy key ids to HID usages, overriding the builtin table
+    #[arg(long, env = "KEYMAP")]
+    pub keymap: Option<PathBuf>,
+    /// YAML file of key combos typing a sequence of keys or text instead, e.g. Ctrl+Alt+M
+    #[arg(long, env = "MACROS")]
+    pub macros: Option<PathBuf>,
+    /// Mouse buttons sent as another button or scrolling sideways, only in the config file,
+    /// buttons not listed keep their role
+    #[arg(skip)]
+    pub button_map: Vec<ButtonMapEntry>,
+    /// Release a modifier key held this many seconds without a repeat, e.g. when its key up
+    /// was lost, 0 disables it. Servers may not repeat modifiers, one held on purpose is
+    /// released too
+    #[arg(long, default_value = "0", env = "STUCK_MODIFIER_TIMEOUT")]
+    pub stuck_modifier_timeout: u64,
+    /// Release any other key held this many seconds without a repeat, 0 disables it
+    #[arg(long, default_value = "0", env = "STUCK_KEY_TIMEOUT")]
+    pub stuck_key_timeout: u64,
+    /// Maximum delay between reconnection attempts in seconds
+    #[default(60)]
+    #[arg(long, env = "MAX_RECONNECT_DELAY")]
+    pub max_reconnect_delay: u64,
+    /// Log the packet counts and the input latency every this many seconds, 0 disables it
+    #[arg(long, default_value = "0", env = "METRICS_INTERVAL")]
+    pub metrics_interval: u64,
+    /// HID usages tapped when the screen saver of the server starts, e.g. "0xE3,0x0F" for
+    /// Win+L to lock the target
+    #[arg(long, env = "SCREENSAVER_KEYS")]
+    pub screensaver_keys: Option<String>,
+    /// Named pipe receiving "on" or "off" when the screen saver of the server starts or stops
+    #[arg(long, env = "SCREENSAVER_PIPE")]
+    pub screensaver_pipe: Option<PathBuf>,
+    /// Notify when an absolute move brings the cursor within this many pixels of an edge of
+    /// the screen, once until it leaves that margin, 0 disables it
+    #[arg(long, default_value = "0", env = "EDGE_MARGIN")]
+    pub edge_margin: u16,
+    /// Named pipe receiving "left", "right", "top" or "bottom" when the cursor reaches an edge
+    #[arg(long, env = "EDGE_PIPE")]
+    pub edge_pipe: Option<PathBuf>,
+    /// Receive the events from barpi-bridge on this address, e.g. "0.0.0.0:24810", instead of
+    /// connecting to the Barrier server, requires a restart
+    #[arg(long, env = "LISTEN")]
+    pub listen: Option<String>,
+    /// Unix socket taking JSON commands, e.g. to release stuck keys or type text, requires a
+    /// restart. Only the user running barpi can connect to it.
+    #[arg(long, env = "CONTROL_SOCKET")]
+    pub control_socket: Option<PathBuf>,
+    /// Record the events received from the server to this file, one JSON object per line, to
+    /// replay them with `barpi replay`. Requires a restart
+    #[arg(long, env = "RECORD")]
+    pub record: Option<PathBuf>,
+    /// Delay in milliseconds after each character typed from the control socket
+    #[default(20)]
+    #[arg(long, env = "TYPE_DELAY")]
+    pub type_delay: u64,
+    /// MQTT broker, "host" or "host:port", the connection state is published to it when
+    /// barpi is built with the `mqtt` feature. Requires a restart
+    #[arg(long, env = "MQTT_BROKER")]
+    pub mqtt_broker: Option<String>,
+    #[arg(long, env = "MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+    #[arg(long, env = "MQTT_PASSWORD")]
+    pub mqtt_password: Option<String>,
+    /// Prefix of the topics, `barpi/<screen_name>` if not set
+    #[arg(long, env = "MQTT_TOPIC")]
+    pub mqtt_topic: Option<String>,
+
+    /// How the target is connected, "usb" for the USB gadget or "bluetooth" for a Bluetooth
+    /// HID device the target pairs with, when barpi is built with the `bluetooth` feature.
+    /// Requires a restart
+    #[arg(long, env = "TRANSPORT")]
+    pub transport: Option<String>,
+    /// Addresses of the Bluetooth hosts allowed to pair and connect, comma separated, any
+    /// host paired with the adapter if not set. Requires a restart
+    #[arg(long, env = "BLUETOOTH_HOSTS")]
+    pub bluetooth_hosts: Option<String>,
+    /// Seconds the adapter stays discoverable and pairable after barpi starts, it stops as
+    /// soon as a host connects. 0 only lets the hosts already paired connect. Requires a
+    /// restart
+    #[default(120)]
+    #[arg(long, env = "BLUETOOTH_PAIRING_WINDOW")]
+    pub bluetooth_pairing_window: u64,
+
+    /// Show the connection state with "gpio" LEDs or an "ssd1306" OLED, when barpi is built
+    /// with the `indicator` feature. Requires a restart
+    #[arg(long, env = "INDICATOR")]
+    pub indicator: Option<String>,
+    /// GPIO lines of the LEDs, comma separated: on while connected, on while the cursor is on
+    /// this screen and flashing when reports are written, the last ones can be left out
+    #[arg(long, env = "INDICATOR_PINS")]
+    pub indicator_pins: Option<String>,
+    /// i2c-dev bus of the OLED, /dev/i2c-1 if not set
+    #[arg(long, env = "INDICATOR_I2C_BUS")]
+    pub indicator_i2c_bus: Option<PathBuf>,
+    /// I2C address of the OLED
+    #[default(0x3C)]
+    #[arg(long, env = "INDICATOR_I2C_ADDRESS")]
+    pub indicator_i2c_address: u8,
+    /// Height of the OLED in pixels, 32 or 64
+    #[default(64)]
+    #[arg(long, env = "INDICATOR_HEIGHT")]
+    pub indicator_height: u8,
+
+    /// Don't register the USB gadget, dump the HID reports to the log or the files below instead,
+    /// for debugging on a machine without a UDC
+    #[arg(long, env = "DRY_RUN", num_args = 0..=1, default_missing_value = "true")]
+    pub dry_run: bool,
+    /// Dump the keyboard reports to this file in dry run mode
+    #[arg(long)]
+    pub keyboard_out: Option<PathBuf>,
+    /// Dump the mouse reports to this file in dry run mode
+    #[arg(long)]
+    pub mouse_out: Option<PathBuf>,
+    /// Dump the consumer control reports to this file in dry run mode
+    #[arg(long)]
+    pub consumer_out: Option<PathBuf>,
+    /// Dump the gamepad reports to this file in dry run mode
+    #[arg(long)]
+    pub gamepad_out: Option<PathBuf>,
+
+    // USB ids, require a restart
+    #[arg(hide = true, long, default_value = "3338")]
+    pub usb_vid: u16,
+    #[arg(hide = true, long, default_value = "49374")]
+    pub usb_pid: u16,
+    #[arg(hide = true, long, default_value = "0d0a.com")]
+    pub usb_manufacturer: String,
+    #[arg(hide = true, long, default_value = "BarPi HID Device")]
+    pub usb_product: String,
+    #[arg(hide = true, long, default_value = "0000000000000001")]
+    pub usb_serial: String,
+
+    // Power supply related settings, require a restart
+    /// RPi Zero W requires around 200mA without accessories, and Zero 2W around 250mA
+    #[arg(hide = true, long, default_value = "500")]
+    pub max_power_ma: u16,
+    /// Set to true if the device has external power, and the USB remote wakeup is enabled when this is true
+    #[arg(hide = true, long, default_value = "false")]
+    pub self_powered: bool,
+
+    /// Functions of the USB gadget, only in the config file, keyboard, mouse and consumer
+    /// control if empty, requires a restart
+    #[arg(skip)]
+    pub functions: Vec<FunctionConfig>,
+}
+
+impl BarpiConfig {
+    /// Reads the config file given on the command line, the command line arguments take precedence
+    pub fn load(mut args: Args) -> anyhow::Result<Self> {
+        let Ok(f) = File::open(&args.config_path) else {
+            // If there is not config file return only config parsed from clap
+            return Ok(BarpiConfig::from(&mut args.config));
+        };
+        // Parse config with serde
+        let config =
+            serde_yaml::from_reader::<_, <BarpiConfig as ClapSerde>::Opt>(BufReader::new(f))
+                .context("Error in configuration file")?;
+        // merge config already parsed from clap
+        let mut config = BarpiConfig::from(config).merge(&mut args.config);
+        config.server = config.server.iter().map(|s| server_with_port(s)).collect();
+        Ok(config)
+    }
+
+    /// Checks the values that would only fail later, e.g. when the gadget is bound, and
+    /// returns all the problems found
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+        let mut error = |field, message: String| errors.push(ConfigError { field, message });
+
+        for (field, size) in [
+            ("screen_width", self.screen_width),
+            ("screen_height", self.screen_height),
+        ] {
+            if !(1..=MAX_SCREEN_SIZE).contains(&size) {
+                error(
+                    field,
+                    format!("{size} is not between 1 and {MAX_SCREEN_SIZE}"),
+                );
+            }
+        }
+        // Unused when the events come from barpi-bridge
+        if self.listen.is_none() {
+            if self.server.is_empty() {
+                error("server", "is required".to_string());
+            }
+            for server in &self.server {
+                match split_host_port(server) {
+                    Ok((_, Some(0))) => error("server", format!("port 0 in {server:?}")),
+                    Ok(_) => {}
+                    Err(e) => error("server", e),
+                }
+            }
+        }
+        if self.screen_name.is_empty() {
+            error("screen_name", "is required".to_string());
+        } else if self.screen_name.len() > MAX_SCREEN_NAME_LEN {
+            error(
+                "screen_name",
+                format!("longer than {MAX_SCREEN_NAME_LEN} bytes"),
+            );
+        } else if self.screen_name.contains('\0') {
+            error("screen_name", "contains a NUL character".to_string());
+        }
+        for (field, string) in [
+            ("usb_manufacturer", &self.usb_manufacturer),
+            ("usb_product", &self.usb_product),
+            ("usb_serial", &self.usb_serial),
+        ] {
+            let len = string.encode_utf16().count();
+            if len > MAX_USB_STRING_LEN {
+                error(
+                    field,
+                    format!("{len} characters, USB strings have {MAX_USB_STRING_LEN} at most"),
+                );
+            }
+        }
+        if !(0.0..=MAX_SCROLL_SPEED).contains(&self.scroll_speed) {
+            error(
+                "scroll_speed",
+                format!(
+                    "{} is not between 0 and {MAX_SCROLL_SPEED}",
+                    self.scroll_speed
+                ),
+            );
+        }
+        if matches!(self.usb_vid, 0 | 0xFFFF) {
+            error("usb_vid", format!("{:#06x} is reserved", self.usb_vid));
+        }
+        if self.usb_pid == 0 {
+            error("usb_pid", "0 is reserved".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses `screensaver_keys`, a comma separated list of hex HID usages
+    pub fn screensaver_keys(&self) -> anyhow::Result<Vec<u8>> {
+        let Some(keys) = &self.screensaver_keys else {
+            return Ok(vec![]);
+        };
+        keys.split(',')
+            .map(|key| {
+                let key = key.trim();
+                u8::from_str_radix(key.trim_start_matches("0x"), 16)
+                    .with_context(|| format!("Invalid HID usage {key:?} in screensaver_keys"))
+            })
+            .collect()
+    }
+
+    /// Parses `bluetooth_hosts`, a comma separated list of addresses like "A0:B1:C2:D3:E4:F5"
+    pub fn bluetooth_hosts(&self) -> anyhow::Result<Vec<[u8; 6]>> {
+        let Some(hosts) = &self.bluetooth_hosts else {
+            return Ok(vec![]);
+        };
+        hosts
+            .split(',')
+            .map(|host| {
+                let host = host.trim();
+                let mut address = [0; 6];
+                let mut bytes = host.split(':');
+                for byte in &mut address {
+                    *byte = bytes
+                        .next()
+                        .filter(|byte| {
+                            byte.len() == 2 && byte.bytes().all(|b| b.is_ascii_hexdigit())
+                        })
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                        .with_context(|| format!("Invalid address {host:?} in bluetooth_hosts"))?;
+                }
+                if bytes.next().is_some() {
+                    bail!("Invalid address {host:?} in bluetooth_hosts");
+                }
+                Ok(address)
+            })
+            .collect()
+    }
+
+    /// Parses `bind`, anything but an address is the name of an interface
+    pub fn bind(&self) -> anyhow::Result<Option<Bind>> {
+        let Some(bind) = self.bind.as_deref().map(str::trim) else {
+            return Ok(None);
+        };
+        if let Ok(addr) = bind.parse::<SocketAddr>() {
+            return Ok(Some(Bind::Address(addr)));
+        }
+        if let Ok(ip) = bind.parse::<IpAddr>() {
+            return Ok(Some(Bind::Address(SocketAddr::new(ip, 0))));
+        }
+        // IFNAMSIZ with the NUL
+        if bind.is_empty() || bind.len() > 15 {
+            bail!("Invalid bind {bind:?}, neither an address nor an interface name");
+        }
+        Ok(Some(Bind::Interface(bind.to_string())))
+    }
+
+    /// Parses `mqtt_broker` into the host and the port, 1883 if not given
+    pub fn mqtt_broker(&self) -> anyhow::Result<Option<(String, u16)>> {
+        let Some(broker) = self.mqtt_broker.as_deref().map(str::trim) else {
+            return Ok(None);
+        };
+        let (host, port) = split_host_port(broker).map_err(|e| anyhow!("mqtt_broker: {e}"))?;
+        Ok(Some((host.to_string(), port.unwrap_or(1883))))
+    }
+
+    /// Prefix of the MQTT topics
+    pub fn mqtt_topic(&self) -> String {
+        match &self.mqtt_topic {
+            Some(topic) => topic.trim_end_matches('/').to_string(),
+            None => format!("barpi/{}", self.screen_name),
+        }
+    }
+
+    /// Parses `server_platform`, keys without an id are dropped if it isn't set
+    pub fn server_platform(&self) -> anyhow::Result<ServerPlatform> {
+        match self
+            .server_platform
+            .as_deref()
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            None => Ok(ServerPlatform::Unknown),
+            Some("windows") => Ok(ServerPlatform::Windows),
+            Some("x11" | "linux") => Ok(ServerPlatform::X11),
+            Some("macos") => Ok(ServerPlatform::MacOs),
+            Some(other) => bail!("Unknown server_platform {other:?}"),
+        }
+    }
+
+    /// Parses `transport`, the USB gadget if it isn't set
+    pub fn transport(&self) -> anyhow::Result<Transport> {
+        match self.transport.as_deref().map(str::to_lowercase).as_deref() {
+            None | Some("usb") => Ok(Transport::Usb),
+            Some("bluetooth") => Ok(Transport::Bluetooth),
+            Some(other) => bail!("Unknown transport {other:?}"),
+        }
+    }
+
+    /// Parses `indicator` with the options of its kind, `None` if it isn't set
+    pub fn indicator(&self) -> anyhow::Result<Option<Indicator>> {
+        match self.indicator.as_deref().map(str::to_lowercase).as_deref() {
+            None => Ok(None),
+            Some("gpio") => {
+                let pins: Vec<u32> = self
+                    .indicator_pins
+                    .as_deref()
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|pin| !pin.trim().is_empty())
+                    .map(|pin| {
+                        let pin = pin.trim();
+                        pin.parse()
+                            .with_context(|| format!("Invalid GPIO {pin:?} in indicator_pins"))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                if !(1..=3).contains(&pins.len()) {
+                    bail!("indicator_pins needs 1 to 3 GPIO lines, got {}", pins.len());
+                }
+                Ok(Some(Indicator::Gpio(pins)))
+            }
+            Some("ssd1306") => {
+                if !matches!(self.indicator_height, 32 | 64) {
+                    bail!(
+                        "indicator_height is 32 or 64, not {}",
+                        self.indicator_height
+                    );
+                }
+                Ok(Some(Indicator::Ssd1306 {
+                    bus: self
+                        .indicator_i2c_bus
+                        .clone()
+                        .unwrap_or_else(|| PathBuf::from("/dev/i2c-1")),
+                    address: self.indicator_i2c_address,
+                    height: self.indicator_height,
+                }))
+            }
+            Some(other) => bail!("Unknown indicator {other:?}"),
+        }
+    }
+
+    /// Parses `unicode_input`, characters without a key are dropped if it isn't set
+    pub fn unicode_input(&self) -> anyhow::Result<UnicodeInput> {
+        match self
+            .unicode_input
+            .as_deref()
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            None | Some("skip") => Ok(UnicodeInput::Skip),
+            Some("linux") => Ok(UnicodeInput::Linux),
+            Some("windows") => Ok(UnicodeInput::Windows),
+            Some(other) => bail!("Unknown unicode_input {other:?}"),
+        }
+    }
+
+    pub fn click_assist(&self) -> ClickAssistOptions {
+        ClickAssistOptions {
+            jitter: self.click_jitter,
+            window: Duration::from_millis(self.click_window),
+            drag_lock: self.drag_lock,
+        }
+    }
+
+    pub fn stuck_key_timeouts(&self) -> StuckKeyTimeouts {
+        let timeout = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        StuckKeyTimeouts {
+            modifier: timeout(self.stuck_modifier_timeout),
+            key: timeout(self.stuck_key_timeout),
+        }
+    }
+
+    /// Whether the horizontal and the vertical wheel are flipped
+    pub fn wheel_flip(&self) -> (bool, bool) {
+        (
+            self.flip_mouse_wheel || self.natural_scroll_x,
+            self.flip_mouse_wheel || self.natural_scroll_y,
+        )
+    }
+
+    /// Reads the config again for SIGHUP, see `keep_restart_fields`
+    pub fn reload(&self) -> anyhow::Result<Self> {
+        let config = Self::load(Args::try_parse()?)?;
+        config.validate().map_err(invalid_config)?;
+        Ok(self.keep_restart_fields(config))
+    }
+
+    /// Whether `new` connects to the server differently, the session is only restarted on
+    /// reload for these. The other options of the client, e.g. `coalesce_mouse_moves`, are
+    /// applied the next time the session restarts, e.g. with the reconnect command.
+    pub fn connection_changed(&self, new: &Self) -> bool {
+        self.server != new.server
+            || self.screen_name != new.screen_name
+            || self.tls_fingerprint != new.tls_fingerprint
+            || self.tls_ca != new.tls_ca
+            || self.bind != new.bind
+    }
+
+    /// `new` with the fields that can't be changed without recreating the USB gadget
+    /// taken from `self`
+    pub fn keep_restart_fields(&self, mut new: Self) -> Self {
+        macro_rules! keep {
+            ($($field:ident),*) => {
+                $(
+                    if new.$field != self.$field {
+                        warn!("{} changed, restart barpi to apply it", stringify!($field));
+                        new.$field = self.$field.clone();
+                    }
+                )*
+            };
+        }
+        keep!(
+            auto_screen_size,
+            relative_mouse,
+            nkro,
+            gamepad,
+            consumer_control,
+            hid_report_ids,
+            hid_single_interface,
+            listen,
+            record,
+            transport,
+            bluetooth_hosts,
+            bluetooth_pairing_window,
+            indicator,
+            indicator_pins,
+            indicator_i2c_bus,
+            indicator_i2c_address,
+            indicator_height,
+            dry_run,
+            keyboard_out,
+            mouse_out,
+            consumer_out,
+            gamepad_out,
+            usb_vid,
+            usb_pid,
+            usb_manufacturer,
+            usb_product,
+            usb_serial,
+            max_power_ma,
+            self_powered,
+            functions,
+            mqtt_broker,
+            mqtt_username,
+            mqtt_password,
+            mqtt_topic
+        );
+        new
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, time::Duration};
+
+    use barrier_client::ClickAssistOptions;
+    use clap::Parser;
+    use clap_serde_derive::ClapSerde;
+    use synergy_hid::{ButtonMapEntry, Pan, ServerPlatform, StuckKeyTimeouts, UnicodeInput};
+
+    use super::{
+        invalid_config, server_with_port, Args, BarpiConfig, Bind, Command, Indicator, Transport,
+    };
+    use crate::gadget::{FunctionConfig, HidRole};
+
+    #[test]
+    fn test_keep_restart_fields() {
+        let current = BarpiConfig {
+            server: vec!["old:24800".to_string()],
+            ..Default::default()
+        };
+        let new = BarpiConfig {
+            server: vec!["new:24800".to_string()],
+            screen_name: "pi".to_string(),
+            flip_mouse_wheel: true,
+            relative_mouse: true,
+            usb_product: "Other".to_string(),
+            max_power_ma: 100,
+            ..Default::default()
+        };
+        let applied = current.keep_restart_fields(new);
+        assert_eq!(applied.server, ["new:24800"]);
+        assert_eq!(applied.screen_name, "pi");
+        assert!(applied.flip_mouse_wheel);
+        assert!(!applied.relative_mouse);
+        assert_eq!(applied.usb_product, current.usb_product);
+        assert_eq!(applied.max_power_ma, current.max_power_ma);
+
+        // Nothing to apply
+        let applied = current.keep_restart_fields(BarpiConfig {
+            usb_vid: 1,
+            ..current.clone()
+        });
+        assert_eq!(applied, current);
+    }
+
+    #[test]
+    fn test_connection_changed() {
+        let current = BarpiConfig {
+            server: vec!["host:24800".to_string()],
+            screen_name: "pi".to_string(),
+            ..Default::default()
+        };
+        // Applied to the running session
+        for new in [
+            BarpiConfig {
+                scroll_speed: 2.0,
+                type_delay: 5,
+                flip_mouse_wheel: true,
+                screen_width: 1280,
+                ..current.clone()
+            },
+            BarpiConfig {
+                coalesce_mouse_moves: false,
+                ..current.clone()
+            },
+        ] {
+            assert!(!current.connection_changed(&new));
+        }
+        for new in [
+            BarpiConfig {
+                server: vec!["other:24800".to_string()],
+                ..current.clone()
+            },
+            BarpiConfig {
+                screen_name: "pi2".to_string(),
+                ..current.clone()
+            },
+            BarpiConfig {
+                tls_fingerprint: Some("AA:BB".to_string()),
+                ..current.clone()
+            },
+            BarpiConfig {
+                bind: Some("eth0".to_string()),
+                ..current.clone()
+            },
+        ] {
+            assert!(current.connection_changed(&new));
+        }
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        let replay = |speed: &str| {
+            let speed = format!("--speed={speed}");
+            let args = Args::try_parse_from(["barpi", "replay", "events.jsonl", &speed]);
+            args.unwrap().command.unwrap().validate()
+        };
+        assert_eq!(replay("1"), Ok(()));
+        assert_eq!(replay("0"), Ok(()));
+        assert_eq!(replay("0.001"), Ok(()));
+        for (speed, message) in [
+            (
+                "1e-30",
+                "speed: 0.000000000000000000000000000001 is not 0 or between 0.001 and 1000",
+            ),
+            ("-1", "speed: -1 is not 0 or between 0.001 and 1000"),
+            ("NaN", "speed: NaN is not 0 or between 0.001 and 1000"),
+        ] {
+            let errors = replay(speed).unwrap_err();
+            assert_eq!(errors[0].to_string(), message, "{speed}");
+        }
+        assert!(matches!(
+            Command::Replay {
+                file: "events.jsonl".into(),
+                speed: 2000.0
+            }
+            .validate(),
+            Err(errors) if errors.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_wheel_flip() {
+        let cfg = |flip_mouse_wheel, natural_scroll_x, natural_scroll_y| BarpiConfig {
+            flip_mouse_wheel,
+            natural_scroll_x,
+            natural_scroll_y,
+            ..Default::default()
+        };
+        assert_eq!(cfg(false, false, false).wheel_flip(), (false, false));
+        assert_eq!(cfg(false, true, false).wheel_flip(), (true, false));
+        assert_eq!(cfg(false, false, true).wheel_flip(), (false, true));
+        assert_eq!(cfg(true, false, true).wheel_flip(), (true, true));
+        assert_eq!(BarpiConfig::default().scroll_speed, 1.0);
+    }
+
+    #[test]
+    fn test_screensaver_keys() {
+        let cfg = BarpiConfig {
+            screensaver_keys: Some("0xE0, 0xe2,0F".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cfg.screensaver_keys().unwrap(), [0xE0, 0xE2, 0x0F]);
+
+        let cfg = BarpiConfig {
+            screensaver_keys: Some("0xE0,L".to_string()),
+            ..Default::default()
+        };
+        assert!(cfg.screensaver_keys().is_err());
+        assert!(BarpiConfig::default()
+            .screensaver_keys()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_bluetooth_hosts() {
+        let hosts = |hosts: &str| {
+            BarpiConfig {
+                bluetooth_hosts: Some(hosts.to_string()),
+                ..Default::default()
+            }
+            .bluetooth_hosts()
+        };
+        assert_eq!(
+            hosts("A0:B1:c2:D3:E4:F5, 00:11:22:33:44:55").unwrap(),
+            [
+                [0xA0, 0xB1, 0xC2, 0xD3, 0xE4, 0xF5],
+                [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]
+            ]
+        );
+        assert!(hosts("A0:B1:C2:D3:E4").is_err());
+        assert!(hosts("A0:B1:C2:D3:E4:F5:06").is_err());
+        assert!(hosts("A0:B1:C2:D3:E4:F").is_err());
+        assert!(hosts("A0:B1:C2:D3:E4:+5").is_err());
+        assert!(BarpiConfig::default().bluetooth_hosts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bind() {
+        let bind = |bind: &str| {
+            BarpiConfig {
+                bind: Some(bind.to_string()),
+                ..Default::default()
+            }
+            .bind()
+        };
+        assert_eq!(
+            bind("192.168.1.20").unwrap(),
+            Some(Bind::Address("192.168.1.20:0".parse().unwrap()))
+        );
+        assert_eq!(
+            bind("[fe80::1]:4000").unwrap(),
+            Some(Bind::Address("[fe80::1]:4000".parse().unwrap()))
+        );
+        assert_eq!(
+            bind(" eth0 ").unwrap(),
+            Some(Bind::Interface("eth0".to_string()))
+        );
+        assert!(bind("").is_err());
+        assert!(bind("a-very-long-interface").is_err());
+        assert_eq!(BarpiConfig::default().bind().unwrap(), None);
+    }
+
+    #[test]
+    fn test_server_platform() {
+        let platform = |name: &str| {
+            BarpiConfig {
+                server_platform: Some(name.to_string()),
+                ..Default::default()
+            }
+            .server_platform()
+        };
+        assert_eq!(platform("Windows").unwrap(), ServerPlatform::Windows);
+        assert_eq!(platform("x11").unwrap(), ServerPlatform::X11);
+        assert_eq!(platform("macos").unwrap(), ServerPlatform::MacOs);
+        assert!(platform("beos").is_err());
+        assert_eq!(
+            BarpiConfig::default().server_platform().unwrap(),
+            ServerPlatform::Unknown
+        );
+    }
+
+    #[test]
+    fn test_transport() {
+        let transport = |name: &str| {
+            BarpiConfig {
+                transport: Some(name.to_string()),
+                ..Default::default()
+            }
+            .transport()
+        };
+        assert_eq!(transport("usb").unwrap(), Transport::Usb);
+        assert_eq!(transport("Bluetooth").unwrap(), Transport::Bluetooth);
+        assert!(transport("serial").is_err());
+        assert_eq!(BarpiConfig::default().transport().unwrap(), Transport::Usb);
+    }
+
+    #[test]
+    fn test_indicator() {
+        let yaml = |yaml: &str| {
+            serde_yaml::from_str::<<BarpiConfig as ClapSerde>::Opt>(yaml)
+                .map(BarpiConfig::from)
+                .unwrap()
+                .indicator()
+        };
+        assert_eq!(yaml("screen_name: pi").unwrap(), None);
+        assert_eq!(
+            yaml("indicator: gpio\nindicator_pins: 17, 27").unwrap(),
+            Some(Indicator::Gpio(vec![17, 27]))
+        );
+        assert_eq!(
+            yaml("indicator: SSD1306\nindicator_i2c_address: 0x3D").unwrap(),
+            Some(Indicator::Ssd1306 {
+                bus: PathBuf::from("/dev/i2c-1"),
+                address: 0x3D,
+                height: 64,
+            })
+        );
+        for invalid in [
+            "indicator: gpio",
+            "indicator: gpio\nindicator_pins: 1,2,3,4",
+            "indicator: gpio\nindicator_pins: 17,led",
+            "indicator: ssd1306\nindicator_height: 48",
+            "indicator: neopixel",
+        ] {
+            assert!(yaml(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = || BarpiConfig {
+            server: vec!["host:24800".to_string()],
+            screen_name: "pi".to_string(),
+            screen_width: 1920,
+            screen_height: 1080,
+            usb_vid: 0x0d0a,
+            usb_pid: 0xc0de,
+            usb_product: "BarPi HID Device".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(valid().validate(), Ok(()));
+        // The port is added on load
+        assert_eq!(
+            BarpiConfig {
+                server: vec!["[fd00::2]".to_string()],
+                ..valid()
+            }
+            .validate(),
+            Ok(())
+        );
+        // Unused with barpi-bridge
+        assert_eq!(
+            BarpiConfig {
+                server: vec![],
+                listen: Some("0.0.0.0:24801".to_string()),
+                ..valid()
+            }
+            .validate(),
+            Ok(())
+        );
+
+        let long = "x".repeat(127);
+        let bad: [(BarpiConfig, &[&str]); 9] = [
+            (
+                BarpiConfig {
+                    screen_width: 0,
+                    screen_height: 40000,
+                    ..valid()
+                },
+                &[
+                    "screen_width: 0 is not between 1 and 32767",
+                    "screen_height: 40000 is not between 1 and 32767",
+                ],
+            ),
+            (
+                BarpiConfig {
+                    server: vec![],
+                    ..valid()
+                },
+                &["server: is required"],
+            ),
+            (
+                BarpiConfig {
+                    server: vec!["host:port".to_string()],
+                    ..valid()
+                },
+                &["server: invalid port \"port\" in \"host:port\""],
+            ),
+            (
+                BarpiConfig {
+                    server: vec![":24800".to_string()],
+                    ..valid()
+                },
+                &["server: no host in \":24800\""],
+            ),
+            (
+                BarpiConfig {
+                    server: vec!["host:24800".to_string(), "host:0".to_string()],
+                    ..valid()
+                },
+                &["server: port 0 in \"host:0\""],
+            ),
+            (
+                BarpiConfig {
+                    screen_name: String::new(),
+                    ..valid()
+                },
+                &["screen_name: is required"],
+            ),
+            (
+                BarpiConfig {
+                    scroll_speed: -1.0,
+                    ..valid()
+                },
+                &["scroll_speed: -1 is not between 0 and 64"],
+            ),
+            (
+                BarpiConfig {
+                    screen_name: "p\0i".to_string(),
+                    usb_serial: long.clone(),
+                    ..valid()
+                },
+                &[
+                    "screen_name: contains a NUL character",
+                    "usb_serial: 127 characters, USB strings have 126 at most",
+                ],
+            ),
+            (
+                BarpiConfig {
+                    usb_vid: 0xFFFF,
+                    usb_pid: 0,
+                    usb_manufacturer: long,
+                    ..valid()
+                },
+                &[
+                    "usb_manufacturer: 127 characters, USB strings have 126 at most",
+                    "usb_vid: 0xffff is reserved",
+                    "usb_pid: 0 is reserved",
+                ],
+            ),
+        ];
+        for (cfg, expected) in bad {
+            let errors = cfg.validate().unwrap_err();
+            let messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            assert_eq!(messages, expected);
+        }
+
+        let errors = BarpiConfig {
+            screen_width: 0,
+            screen_name: String::new(),
+            ..valid()
+        }
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            invalid_config(errors).to_string(),
+            "Invalid configuration\n  screen_width: 0 is not between 1 and 32767\n  screen_name: is required"
+        );
+    }
+
+    #[test]
+    fn test_server_port() {
+        let server = server_with_port;
+        assert_eq!(server("host"), "host:24800");
+        assert_eq!(server("host:24801"), "host:24801");
+        assert_eq!(server("10.0.0.1"), "10.0.0.1:24800");
+        assert_eq!(server("fd00::2"), "[fd00::2]:24800");
+        assert_eq!(server("[fd00::2]:24801"), "[fd00::2]:24801");
+        // Left for validate to report
+        assert_eq!(server("host:port"), "host:port");
+    }
+
+    #[test]
+    fn test_server_list() {
+        let servers = |yaml: &str| {
+            let opt: <BarpiConfig as ClapSerde>::Opt = serde_yaml::from_str(yaml).unwrap();
+            BarpiConfig::from(opt).server
+        };
+        assert_eq!(servers("server: desktop"), ["desktop"]);
+        assert_eq!(
+            servers("server: [desktop, \"laptop:24801\"]"),
+            ["desktop", "laptop:24801"]
+        );
+        assert!(servers("screen_name: pi").is_empty());
+
+        let args = Args::try_parse_from(["barpi", "-s", "desktop", "--server", "laptop"]).unwrap();
+        assert_eq!(BarpiConfig::from(args.config).server, ["desktop", "laptop"]);
+    }
+
+    #[test]
+    fn test_mqtt_broker() {
+        let config = |broker: &str| BarpiConfig {
+            mqtt_broker: Some(broker.to_string()),
+            screen_name: "pi".to_string(),
+            ..Default::default()
+        };
+        let broker = |broker: &str| config(broker).mqtt_broker();
+        let parsed = |host: &str, port| Some((host.to_string(), port));
+        assert_eq!(broker("mqtt.lan").unwrap(), parsed("mqtt.lan", 1883));
+        assert_eq!(broker("10.0.0.2:8883").unwrap(), parsed("10.0.0.2", 8883));
+        assert_eq!(broker("[fd00::2]:1884").unwrap(), parsed("fd00::2", 1884));
+        assert_eq!(broker("fd00::1:2").unwrap(), parsed("fd00::1:2", 1883));
+        assert!(broker("mqtt.lan:port").is_err());
+        assert!(broker(":1883").is_err());
+        assert_eq!(BarpiConfig::default().mqtt_broker().unwrap(), None);
+
+        assert_eq!(config("mqtt.lan").mqtt_topic(), "barpi/pi");
+        let topic = BarpiConfig {
+            mqtt_topic: Some("home/kvm/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(topic.mqtt_topic(), "home/kvm");
+    }
+
+    #[test]
+    fn test_unicode_input() {
+        let input = |name: &str| {
+            BarpiConfig {
+                unicode_input: Some(name.to_string()),
+                ..Default::default()
+            }
+            .unicode_input()
+        };
+        assert_eq!(input("Linux").unwrap(), UnicodeInput::Linux);
+        assert_eq!(input("windows").unwrap(), UnicodeInput::Windows);
+        assert_eq!(input("skip").unwrap(), UnicodeInput::Skip);
+        assert!(input("macos").is_err());
+        assert_eq!(
+            BarpiConfig::default().unicode_input().unwrap(),
+            UnicodeInput::Skip
+        );
+    }
+
+    #[test]
+    fn test_click_assist() {
+        let opt: <BarpiConfig as ClapSerde>::Opt = serde_yaml::from_str(
+            r#"
+            click_jitter: 2
+            drag_lock: true
+            "#,
+        )
+        .unwrap();
+        let options = BarpiConfig::from(opt).click_assist();
+        assert_eq!(options.jitter, 2);
+        assert_eq!(options.window, Duration::from_millis(500));
+        assert!(options.drag_lock);
+        assert_eq!(
+            BarpiConfig::default().click_assist(),
+            ClickAssistOptions::default()
+        );
+    }
+
+    #[test]
+    fn test_stuck_key_timeouts() {
+        let opt: <BarpiConfig as ClapSerde>::Opt = serde_yaml::from_str(
+            r#"
+            stuck_key_timeout: 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            BarpiConfig::from(opt).stuck_key_timeouts(),
+            StuckKeyTimeouts {
+                modifier: None,
+                key: Some(Duration::from_secs(60)),
+            }
+        );
+        assert!(!BarpiConfig::default().stuck_key_timeouts().is_enabled());
+    }
+
+    #[test]
+    fn test_functions() {
+        let opt: <BarpiConfig as ClapSerde>::Opt = serde_yaml::from_str(
+            r#"
+            server: "host:24800"
+            functions:
+              - type: hid
+                role: keyboard
+              - type: acm
+            "#,
+        )
+        .unwrap();
+        let cfg = BarpiConfig::from(opt);
+        assert_eq!(cfg.server, ["host:24800"]);
+        assert_eq!(
+            cfg.functions,
+            [
+                FunctionConfig::Hid {
+                    role: HidRole::Keyboard
+                },
+                FunctionConfig::Acm { console: false }
+            ]
+        );
+        assert!(BarpiConfig::default().functions.is_empty());
+    }
+
+    #[test]
+    fn test_button_map() {
+        let opt: <BarpiConfig as ClapSerde>::Opt = serde_yaml::from_str(
+            r#"
+            button_map:
+              - synergy: 2
+                button: 4
+              - synergy: 6
+                pan: left
+            "#,
+        )
+        .unwrap();
+        let cfg = BarpiConfig::from(opt);
+        assert_eq!(
+            cfg.button_map,
+            [
+                ButtonMapEntry {
+                    synergy: 2,
+                    button: Some(4),
+                    pan: None
+                },
+                ButtonMapEntry {
+                    synergy: 6,
+                    button: None,
+                    pan: Some(Pan::Left)
+                }
+            ]
+        );
+        assert!(BarpiConfig::default().button_map.is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use barrier_client::{
+    Actuator, ActuatorError, ClickAssist, Client, ClientStats, ClipboardData, ConnectionState,
+    Edge, KeyMask, LedState, ServerInfo, ServerList, ServerOptions,
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{watch, Notify},
+};
+
+use crate::client::BarpiActuator;
+
+/// The actuator driven by the client, locked by the commands in between its events
+pub type Shared = Arc<Mutex<ClickAssist<BarpiActuator>>>;
+
+/// A command of the control socket, one JSON object per line, e.g. `{"cmd":"status"}`
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    /// Replies with the connection state, the cursor position and the counters of the client
+    Status,
+    /// Releases all keys and buttons on the host
+    Clear,
+    /// Types ASCII text with the US layout, waiting `type_delay` after each character
+    Type { text: String },
+    /// Drops the connection to the server and connects again
+    Reconnect,
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    serde_json::from_str(line).map_err(|e| e.to_string())
+}
+
+/// Name of the state in the status reply, e.g. "hello_sent"
+pub fn state_name(state: &ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::HelloSent => "hello_sent",
+        ConnectionState::Connected => "connected",
+        ConnectionState::Entered => "entered",
+        ConnectionState::Left => "left",
+        ConnectionState::Disconnected { .. } => "disconnected",
+    }
+}
+
+/// Reply to a command, one JSON object per line, `ok` is false and `error` is set if it failed
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<&'static str>,
+    /// Why the connection was closed, in the disconnected state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Address of the server connected to, one of the `server` list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<(u16, u16)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+}
+
+/// The [`ClientStats`] in the status reply, they add up since barpi started
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub input_events: u64,
+    /// Milliseconds since the last keep-alive of the server, none before the first one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_keepalive_ms: Option<u64>,
+    /// Milliseconds since the last input event was injected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_input_ms: Option<u64>,
+    /// Packets received by protocol code
+    pub packets: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub fn new(stats: &ClientStats, now: Instant) -> Self {
+        let since = |at: Option<Instant>| at.map(|at| now.duration_since(at).as_millis() as u64);
+        Self {
+            packets_received: stats.packets_received(),
+            bytes_received: stats.bytes_received(),
+            packets_sent: stats.packets_sent(),
+            bytes_sent: stats.bytes_sent(),
+            input_events: stats.input_events(),
+            since_keepalive_ms: since(stats.last_keepalive()),
+            since_input_ms: since(stats.last_input()),
+            packets: stats
+                .packets_by_kind()
+                .into_iter()
+                .map(|(kind, count)| (String::from_utf8_lossy(&kind).into_owned(), count))
+                .collect(),
+        }
+    }
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    fn error<S: Into<String>>(error: S) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
+}
+
+/// What the commands act on, shared by the connections to the socket and the main loop
+pub struct Control {
+    actuator: Shared,
+    state: Mutex<(watch::Receiver<ConnectionState>, ServerList)>,
+    // Shared by the clients built after each reload
+    stats: Arc<ClientStats>,
+    reconnect: Notify,
+}
+
+impl Control {
+    pub fn new(actuator: Shared, client: &Client, stats: Arc<ClientStats>) -> Self {
+        Self {
+            actuator,
+            state: Mutex::new((client.state(), client.servers().clone())),
+            stats,
+            reconnect: Notify::new(),
+        }
+    }
+
+    /// Watches the client built after the config is reloaded
+    pub fn set_client(&self, client: &Client) {
+        *self.state.lock().unwrap() = (client.state(), client.servers().clone());
+    }
+
+    /// Completes once a reconnect command is received, also if it came in before
+    pub async fn reconnect_requested(&self) {
+        self.reconnect.notified().await
+    }
+
+    pub async fn handle(&self, command: Command) -> Reply {
+        match command {
+            Command::Status => {
+                let (state, server) = {
+                    let (state, servers) = &*self.state.lock().unwrap();
+                    let state = state.borrow().clone();
+                    (state, servers.active().map(str::to_string))
+                };
+                Reply {
+                    state: Some(state_name(&state)),
+                    reason: match state {
+                        ConnectionState::Disconnected { reason } => reason,
+                        _ => None,
+                    },
+                    server,
+                    cursor: Some(self.actuator.lock().unwrap().get_cursor_position()),
+                    stats: Some(Stats::new(&self.stats, Instant::now())),
+                    ..Reply::ok()
+                }
+            }
+            Command::Clear => {
+                self.actuator.lock().unwrap().get_mut().release_all();
+                Reply::ok()
+            }
+            Command::Type { text } => {
+                // The events of the server wait until it's typed, like for a macro
+                let actuator = self.actuator.clone();
+                let typed = tokio::task::spawn_blocking(move || {
+                    actuator.lock().unwrap().get_mut().type_text(&text)
+                })
+                .await;
+                match typed {
+                    Ok(true) => Reply::ok(),
+                    Ok(false) => Reply::error("a macro is running"),
+                    Err(e) => Reply::error(e.to_string()),
+                }
+            }
+            Command::Reconnect => {
+                self.reconnect.notify_one();
+                Reply::ok()
+            }
+        }
+    }
+}
+
+/// Listens on `path`, only the owner can connect. A socket left by a previous run is
+/// replaced, any other file is an error.
+pub fn bind_control(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    info!("Control socket listening on {}", path.display());
+    Ok(listener)
+}
+
+/// Serves the connections to the control socket, each of them can send any number of commands
+pub async fn serve_control(listener: UnixListener, control: Arc<Control>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Cannot accept control connections, error: {:?}", e);
+                return;
+            }
+        };
+        let control = control.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &control).await {
+                debug!("Control connection closed, error: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn serve_connection(stream: UnixStream, control: &Control) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match parse_command(&line) {
+            Ok(command) => {
+                debug!("Control command {:?}", command);
+                control.handle(command).await
+            }
+            Err(e) => Reply::error(e),
+        };
+        let mut reply = serde_json::to_vec(&reply)?;
+        reply.push(b'\n');
+        writer.write_all(&reply).await?;
+    }
+    Ok(())
+}
+
+/// Runs the client on the shared actuator, each event locks it
+pub struct SharedActuator(pub Shared);
+
+impl Actuator for SharedActuator {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().connected()
+    }
+
+    fn connected_with(&mut self, info: ServerInfo) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().connected_with(info)
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().disconnected()
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        self.0.lock().unwrap().get_screen_size()
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        self.0.lock().unwrap().get_cursor_position()
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().set_cursor_position(x, y)
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().move_cursor(x, y)
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().mouse_down(button)
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().mouse_up(button)
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().mouse_wheel(x, y)
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().key_down(key, mask, button)
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().key_repeat(key, mask, button, count)
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().key_up(key, mask, button)
+    }
+
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().set_options(opts)
+    }
+
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().reset_options()
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().enter()
+    }
+
+    fn enter_at(&mut self, x: u16, y: u16, mask: KeyMask) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().enter_at(x, y, mask)
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().leave()
+    }
+
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().set_clipboard(data)
+    }
+
+    fn set_clipboard_with(&mut self, id: u8, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().set_clipboard_with(id, data)
+    }
+
+    fn get_clipboard(&mut self, id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        self.0.lock().unwrap().get_clipboard(id)
+    }
+
+    fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().file_received(name_hint, data)
+    }
+
+    fn led_state_changed(&mut self, state: LedState) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().led_state_changed(state)
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().screensaver(on)
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().game_buttons(id, buttons)
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().game_sticks(id, left, right)
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().game_triggers(id, left, right)
+    }
+
+    fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.0.lock().unwrap().edge_reached(edge)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use barrier_client::{ClientStats, Metrics};
+
+    use super::{parse_command, Command, Reply, Stats};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(r#"{"cmd":"status"}"#), Ok(Command::Status));
+        assert_eq!(parse_command(r#" {"cmd": "clear"} "#), Ok(Command::Clear));
+        assert_eq!(
+            parse_command(r#"{"text":"Hi!\n","cmd":"type"}"#),
+            Ok(Command::Type {
+                text: "Hi!\n".to_string()
+            })
+        );
+        assert_eq!(
+            parse_command(r#"{"cmd":"reconnect"}"#),
+            Ok(Command::Reconnect)
+        );
+
+        for line in [
+            r#"{"cmd":"reboot"}"#,
+            r#"{"cmd":"type"}"#,
+            r#"{"text":"a"}"#,
+            "status",
+        ] {
+            assert!(parse_command(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_reply() {
+        let reply = Reply {
+            state: Some("disconnected"),
+            reason: Some("keep-alive timeout".to_string()),
+            cursor: Some((960, 540)),
+            ..Reply::ok()
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"ok":true,"state":"disconnected","reason":"keep-alive timeout","cursor":[960,540]}"#
+        );
+        let reply = Reply {
+            state: Some("entered"),
+            server: Some("laptop:24800".to_string()),
+            ..Reply::ok()
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"ok":true,"state":"entered","server":"laptop:24800"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Reply::error("a macro is running")).unwrap(),
+            r#"{"ok":false,"error":"a macro is running"}"#
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = ClientStats::new();
+        stats.packet_received(*b"CALV", 8);
+        stats.packet_received(*b"DMMV", 12);
+        stats.event_dispatched(*b"DMMV", Duration::ZERO);
+        stats.packet_sent(*b"CALV", 8);
+        let now = Instant::now() + Duration::from_secs(2);
+        let reply = Reply {
+            stats: Some(Stats::new(&stats, now)),
+            ..Reply::ok()
+        };
+        let json: serde_json::Value = serde_json::to_value(&reply).unwrap();
+        let stats = &json["stats"];
+        assert_eq!(stats["packets_received"], 2);
+        assert_eq!(stats["bytes_received"], 20);
+        assert_eq!(stats["packets_sent"], 1);
+        assert_eq!(stats["bytes_sent"], 8);
+        assert_eq!(stats["input_events"], 1);
+        assert!(stats["since_keepalive_ms"].as_u64().unwrap() >= 2000);
+        assert!(stats["since_input_ms"].as_u64().unwrap() >= 2000);
+        assert_eq!(stats["packets"], serde_json::json!({"CALV": 1, "DMMV": 1}));
+
+        let stats = Stats::new(&ClientStats::new(), Instant::now());
+        assert_eq!(
+            serde_json::to_string(&stats).unwrap(),
+            r#"{"packets_received":0,"bytes_received":0,"packets_sent":0,"bytes_sent":0,"input_events":0,"packets":{}}"#
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    mem,
+    os::fd::{FromRawFd, OwnedFd},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use barrier_client::ClientHandle;
+use log::{debug, info, warn};
+use tokio::sync::{watch, Notify};
+
+use crate::{config::BarpiConfig, control::Shared};
+
+/// Where the kernel lists the DRM cards and their connectors
+const DRM_SYSFS: &str = "/sys/class/drm";
+
+/// Time for the connector to update its modes after a hotplug uevent
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// A display connected to a DRM connector
+#[derive(Debug, PartialEq, Eq)]
+pub struct Display {
+    /// Name of the connector, e.g. "card0-HDMI-A-1"
+    pub connector: String,
+    /// The preferred mode of the display, as read from its EDID
+    pub size: (u16, u16),
+}
+
+/// Parses a line of the `modes` file of a connector, e.g. "1920x1080", or "1920x1080i" for
+/// an interlaced mode
+pub fn parse_mode(line: &str) -> Option<(u16, u16)> {
+    let (width, rest) = line.trim().split_once('x')?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let size = (width.parse().ok()?, rest[..end].parse().ok()?);
+    (size.0 > 0 && size.1 > 0).then_some(size)
+}
+
+/// The modes of a connector are listed one per line, the preferred one first
+pub fn preferred_mode(modes: &str) -> Option<(u16, u16)> {
+    modes.lines().find_map(parse_mode)
+}
+
+/// Looks for a connected display in the connectors under `drm`, the first one by name wins
+pub fn find_display(drm: &Path) -> io::Result<Option<Display>> {
+    let mut connectors = vec![];
+    for entry in fs::read_dir(drm)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // The cards themselves are "card0" and so on, their connectors "card0-HDMI-A-1"
+        if name.starts_with("card") && name.contains('-') {
+            connectors.push((name, entry.path()));
+        }
+    }
+    connectors.sort();
+    for (connector, path) in connectors {
+        let Ok(status) = fs::read_to_string(path.join("status")) else {
+            continue;
+        };
+        if status.trim() != "connected" {
+            continue;
+        }
+        let modes = fs::read_to_string(path.join("modes")).unwrap_or_default();
+        match preferred_mode(&modes) {
+            Some(size) => return Ok(Some(Display { connector, size })),
+            None => debug!("{connector} is connected but has no modes"),
+        }
+    }
+    Ok(None)
+}
+
+/// The size of the display connected to the target with `auto_screen_size`, the configured
+/// size if there is none or it's disabled
+pub fn screen_size(cfg: &BarpiConfig) -> (u16, u16) {
+    let configured = (cfg.screen_width, cfg.screen_height);
+    if !cfg.auto_screen_size {
+        return configured;
+    }
+    match find_display(Path::new(DRM_SYSFS)) {
+        Ok(Some(display)) => {
+            info!(
+                "Screen size {}x{} from the display on {}",
+                display.size.0, display.size.1, display.connector
+            );
+            display.size
+        }
+        Ok(None) => {
+            info!(
+                "No display connected, using the configured screen size {}x{}",
+                configured.0, configured.1
+            );
+            configured
+        }
+        Err(e) => {
+            warn!(
+                "Cannot read the DRM connectors, using the configured screen size {}x{}: {}",
+                configured.0, configured.1, e
+            );
+            configured
+        }
+    }
+}
+
+/// A kernel uevent is the action and the device path, then `KEY=value` fields, each of them
+/// NUL terminated
+pub fn is_drm_hotplug(uevent: &[u8]) -> bool {
+    let fields: Vec<_> = uevent.split(|&b| b == 0).collect();
+    fields.contains(&&b"SUBSYSTEM=drm"[..]) && fields.contains(&&b"HOTPLUG=1"[..])
+}
+
+/// Wakes `hotplug` on each DRM hotplug uevent, they're read on a thread of their own
+pub fn watch_hotplug(hotplug: Arc<Notify>) -> io::Result<()> {
+    let mut socket = File::from(uevent_socket()?);
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 8192];
+        loop {
+            match socket.read(&mut buf) {
+                Ok(len) if is_drm_hotplug(&buf[..len]) => {
+                    debug!("DRM hotplug uevent");
+                    hotplug.notify_one();
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "Cannot read uevents, display changes are not followed: {}",
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+// Netlink socket receiving the uevents sent by the kernel, before udev handles them
+fn uevent_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    // The group of the kernel, udev sends its own events to the second one
+    addr.nl_groups = 1;
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Applies the size of the display after each hotplug and sends it to the server with the
+/// client of `sender` if it changed. The configured size of `cfg` is used again once the
+/// display is unplugged.
+pub async fn follow_display(
+    actuator: Shared,
+    hotplug: Arc<Notify>,
+    cfg: watch::Receiver<BarpiConfig>,
+    sender: watch::Receiver<ClientHandle>,
+) {
+    loop {
+        hotplug.notified().await;
+        tokio::time::sleep(SETTLE_DELAY).await;
+        let size = screen_size(&cfg.borrow());
+        if !actuator.lock().unwrap().get_mut().set_screen_size(size) {
+            continue;
+        }
+        info!("Screen size changed to {}x{}", size.0, size.1);
+        let sender = sender.borrow().clone();
+        if let Err(e) = sender.screen_changed().await {
+            warn!("Cannot send the screen size to the server, error: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use super::{find_display, is_drm_hotplug, parse_mode, preferred_mode, Display};
+
+    // A /sys/class/drm of its own for each test
+    fn drm(name: &str, connectors: &[(&str, &str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("barpi-drm-{}-{name}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("card0")).unwrap();
+        fs::write(root.join("version"), "drm 1.1.0 20060810\n").unwrap();
+        for (connector, status, modes) in connectors {
+            let path = root.join(connector);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("status"), format!("{status}\n")).unwrap();
+            fs::write(path.join("modes"), modes).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_mode("1920x1080i\n"), Some((1920, 1080)));
+        assert_eq!(parse_mode(" 720x480 "), Some((720, 480)));
+        for line in [
+            "",
+            "1920",
+            "x1080",
+            "1920x",
+            "0x1080",
+            "1920x0",
+            "70000x1080",
+            "axb",
+        ] {
+            assert_eq!(parse_mode(line), None, "{line}");
+        }
+
+        assert_eq!(
+            preferred_mode("3840x2160\n1920x1080\n1920x1080i\n1280x720\n"),
+            Some((3840, 2160))
+        );
+        assert_eq!(preferred_mode("bogus\n1280x720\n"), Some((1280, 720)));
+        assert_eq!(preferred_mode(""), None);
+    }
+
+    #[test]
+    fn test_find_display() {
+        let root = drm(
+            "found",
+            &[
+                ("card0-Composite-1", "unknown", "720x480i\n"),
+                ("card0-HDMI-A-1", "disconnected", ""),
+                ("card0-HDMI-A-2", "connected", "2560x1440\n1920x1080\n"),
+                ("card1-HDMI-A-1", "connected", "1280x1024\n"),
+            ],
+        );
+        assert_eq!(
+            find_display(&root).unwrap(),
+            Some(Display {
+                connector: "card0-HDMI-A-2".to_string(),
+                size: (2560, 1440),
+            })
+        );
+
+        // Connected without an EDID, e.g. a KVM in between
+        let root = drm("none", &[("card0-HDMI-A-1", "connected", "")]);
+        assert_eq!(find_display(&root).unwrap(), None);
+        assert!(find_display(&root.join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_is_drm_hotplug() {
+        let uevent = b"change@/devices/platform/gpu/drm/card0\0ACTION=change\0\
+            DEVPATH=/devices/platform/gpu/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0\
+            DEVNAME=dri/card0\0DEVTYPE=drm_minor\0SEQNUM=2812\0MAJOR=226\0MINOR=0\0";
+        assert!(is_drm_hotplug(uevent));
+
+        let uevent = b"add@/devices/platform/soc/usb1/1-1\0ACTION=add\0SUBSYSTEM=usb\0";
+        assert!(!is_drm_hotplug(uevent));
+        let uevent = b"change@/devices/platform/gpu/drm/card0\0ACTION=change\0SUBSYSTEM=drm\0";
+        assert!(!is_drm_hotplug(uevent));
+    }
+}
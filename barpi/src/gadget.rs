@@ -0,0 +1,403 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use synergy_hid::{DescriptorConfig, HidInterface, KeyboardMode, MouseMode, ReportType};
+use usb_gadget::function::{
+    hid::Hid,
+    msd::{Lun, Msd},
+    serial::{Serial, SerialClass},
+    Handle,
+};
+
+/// What a HID function is used for, the actuator opens the device node of each role
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HidRole {
+    Keyboard,
+    Mouse,
+    Consumer,
+    /// Forwards the game controllers of the server, only with `gamepad`
+    Gamepad,
+}
+
+impl HidRole {
+    /// The roles every profile has, the actuator can't run without them
+    pub const REQUIRED: [HidRole; 3] = [HidRole::Keyboard, HidRole::Mouse, HidRole::Consumer];
+
+    pub fn report_type(self, mouse_mode: MouseMode) -> ReportType {
+        match (self, mouse_mode) {
+            (HidRole::Keyboard, _) => ReportType::Keyboard,
+            (HidRole::Mouse, MouseMode::Absolute) => ReportType::Mouse,
+            (HidRole::Mouse, MouseMode::Relative) => ReportType::RelativeMouse,
+            (HidRole::Consumer, _) => ReportType::Consumer,
+            (HidRole::Gamepad, _) => ReportType::Gamepad,
+        }
+    }
+}
+
+/// A function of the gadget, an entry of `functions` in the config file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FunctionConfig {
+    /// One of the HID devices the reports are written to
+    Hid { role: HidRole },
+    /// A USB serial port, `/dev/ttyGS*` on the Pi, e.g. for a login console
+    Acm {
+        #[serde(default)]
+        console: bool,
+    },
+    /// A disk or CD-ROM backed by an image file on the Pi
+    MassStorage {
+        /// Absolute path of the image
+        file: PathBuf,
+        #[serde(default)]
+        read_only: bool,
+        #[serde(default)]
+        cdrom: bool,
+        #[serde(default = "default_removable")]
+        removable: bool,
+    },
+}
+
+fn default_removable() -> bool {
+    true
+}
+
+/// Functions of the USB gadget, in the order the host sees them.
+///
+/// The keyboard, mouse and consumer control roles are required, the gamepad and the other
+/// functions are optional.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GadgetProfile {
+    functions: Vec<FunctionConfig>,
+}
+
+impl Default for GadgetProfile {
+    /// Keyboard, mouse and consumer control
+    fn default() -> Self {
+        HidRole::REQUIRED
+            .into_iter()
+            .fold(Self::empty(), |profile, role| profile.with_hid(role))
+    }
+}
+
+impl GadgetProfile {
+    pub fn empty() -> Self {
+        Self { functions: vec![] }
+    }
+
+    /// The functions of the config file, the default profile if there is none
+    pub fn from_config(functions: &[FunctionConfig]) -> Self {
+        if functions.is_empty() {
+            return Self::default();
+        }
+        functions
+            .iter()
+            .cloned()
+            .fold(Self::empty(), |profile, function| {
+                profile.with_function(function)
+            })
+    }
+
+    pub fn with_function(mut self, function: FunctionConfig) -> Self {
+        self.functions.push(function);
+        self
+    }
+
+    pub fn with_hid(self, role: HidRole) -> Self {
+        self.with_function(FunctionConfig::Hid { role })
+    }
+
+    pub fn with_acm(self, console: bool) -> Self {
+        self.with_function(FunctionConfig::Acm { console })
+    }
+
+    /// Adds a gamepad function after the others, unless the functions of the config file
+    /// already have one
+    pub fn with_gamepad(self) -> Self {
+        if self.has_role(HidRole::Gamepad) {
+            return self;
+        }
+        self.with_hid(HidRole::Gamepad)
+    }
+
+    pub fn has_role(&self, role: HidRole) -> bool {
+        self.roles().any(|r| r == role)
+    }
+
+    /// Checks that each required HID role appears exactly once, the gamepad at most once, and
+    /// the image paths are absolute
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for role in HidRole::REQUIRED.into_iter().chain([HidRole::Gamepad]) {
+            match self.roles().filter(|r| *r == role).count() {
+                0 if role == HidRole::Gamepad => {}
+                0 => bail!("The gadget profile has no HID function for the {role:?} role"),
+                1 => {}
+                _ => {
+                    bail!("The gadget profile has more than one HID function for the {role:?} role")
+                }
+            }
+        }
+        for function in &self.functions {
+            if let FunctionConfig::MassStorage { file, .. } = function {
+                if !file.is_absolute() {
+                    bail!("Mass storage image {:?} must be an absolute path", file);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn roles(&self) -> impl Iterator<Item = HidRole> + '_ {
+        self.functions.iter().filter_map(|function| match function {
+            FunctionConfig::Hid { role } => Some(*role),
+            _ => None,
+        })
+    }
+
+    /// Creates the functions, the handles are registered with the gadget and the HID
+    /// functions are looked up by role once it's bound. The HID functions are the interfaces
+    /// of `descriptors`, a role without a device is skipped and merged ones share the function
+    /// of the first role.
+    pub fn build(
+        self,
+        descriptors: &DescriptorConfig,
+    ) -> anyhow::Result<(Vec<Handle>, GadgetFunctions)> {
+        self.validate()?;
+        let interfaces = descriptors.build();
+        let mut handles = vec![];
+        let mut functions = GadgetFunctions {
+            hids: vec![],
+            roles: vec![],
+            serials: vec![],
+        };
+        for function in self.functions {
+            let handle = match function {
+                FunctionConfig::Hid { role } => {
+                    let report_type = role.report_type(descriptors.mouse);
+                    let Some(interface) = interfaces
+                        .iter()
+                        .position(|interface| interface.report(report_type).is_some())
+                    else {
+                        debug!("HID function of the {role:?} role skipped, it has no device");
+                        continue;
+                    };
+                    if let Some(index) = functions.hids.iter().position(|(i, _)| *i == interface) {
+                        functions.roles.push((role, index));
+                        continue;
+                    }
+                    let (hid, handle) = get_hid_func(&interfaces[interface], descriptors.keyboard);
+                    functions.roles.push((role, functions.hids.len()));
+                    functions.hids.push((interface, hid));
+                    handle
+                }
+                FunctionConfig::Acm { console } => {
+                    let mut builder = Serial::builder(SerialClass::Acm);
+                    builder.console = Some(console);
+                    let (serial, handle) = builder.build();
+                    functions.serials.push(serial);
+                    handle
+                }
+                FunctionConfig::MassStorage {
+                    file,
+                    read_only,
+                    cdrom,
+                    removable,
+                } => {
+                    let mut lun = Lun::new(&file)
+                        .with_context(|| format!("Invalid mass storage image {:?}", file))?;
+                    lun.read_only = read_only;
+                    lun.cdrom = cdrom;
+                    lun.removable = removable;
+                    let (_, handle) = Msd::new(lun);
+                    handle
+                }
+            };
+            handles.push(handle);
+        }
+        Ok((handles, functions))
+    }
+}
+
+/// Functions of a built profile
+pub struct GadgetFunctions {
+    // The HID functions and the index of their interface
+    hids: Vec<(usize, Hid)>,
+    // Index of the HID function of each role, roles merged into one share it
+    roles: Vec<(HidRole, usize)>,
+    serials: Vec<Serial>,
+}
+
+impl GadgetFunctions {
+    /// The HID function of `role`, wherever it is in the profile
+    pub fn hid(&self, role: HidRole) -> anyhow::Result<&Hid> {
+        let index = find_role(&self.roles, role)?;
+        Ok(&self.hids[*index].1)
+    }
+
+    /// Logs the tty of the serial functions, they exist once the gadget is bound
+    pub fn log_serials(&self) {
+        for serial in &self.serials {
+            match serial.tty() {
+                Ok(tty) => debug!("Serial function at {}", tty.display()),
+                Err(e) => debug!("Cannot find the tty of a serial function: {}", e),
+            }
+        }
+    }
+}
+
+fn find_role<T>(functions: &[(HidRole, T)], role: HidRole) -> anyhow::Result<&T> {
+    functions
+        .iter()
+        .find_map(|(r, function)| (*r == role).then_some(function))
+        .with_context(|| format!("No HID function for the {role:?} role"))
+}
+
+fn get_hid_func(interface: &HidInterface, keyboard_mode: KeyboardMode) -> (Hid, Handle) {
+    let report_type = interface.reports[0].report_type;
+    let mut builder = Hid::builder();
+    (builder.sub_class, builder.protocol) = match (report_type, keyboard_mode) {
+        // Boot reports have no ID, and there is a single device per boot interface
+        _ if interface.has_report_ids() => (0, 0),
+        // The host would expect boot reports from a boot interface
+        (ReportType::Keyboard, KeyboardMode::Nkro) => (0, 0),
+        // Boot protocol mouse
+        (ReportType::RelativeMouse, _) => (1, 2),
+        // There is no boot gamepad
+        (ReportType::Gamepad, _) => (0, 0),
+        _ => (1, 1),
+    };
+    builder.report_len = interface.report_len() as u8;
+    builder.report_desc = interface.descriptor.clone();
+    let (hid, handle) = builder.build();
+    (hid, handle)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use synergy_hid::DescriptorConfig;
+
+    use super::{find_role, FunctionConfig, GadgetProfile, HidRole};
+
+    #[test]
+    fn test_parse_functions() {
+        let functions: Vec<FunctionConfig> = serde_yaml::from_str(
+            r#"
+            - type: acm
+              console: true
+            - type: hid
+              role: consumer
+            - type: hid
+              role: keyboard
+            - type: mass_storage
+              file: /var/lib/barpi/disk.img
+              read_only: true
+            - type: hid
+              role: mouse
+            "#,
+        )
+        .unwrap();
+        let profile = GadgetProfile::from_config(&functions);
+        assert_eq!(
+            profile,
+            GadgetProfile::empty()
+                .with_acm(true)
+                .with_hid(HidRole::Consumer)
+                .with_hid(HidRole::Keyboard)
+                .with_function(FunctionConfig::MassStorage {
+                    file: PathBuf::from("/var/lib/barpi/disk.img"),
+                    read_only: true,
+                    cdrom: false,
+                    removable: true,
+                })
+                .with_hid(HidRole::Mouse)
+        );
+        profile.validate().unwrap();
+
+        assert!(
+            serde_yaml::from_str::<Vec<FunctionConfig>>("[{type: hid, role: joystick}]").is_err()
+        );
+        assert!(serde_yaml::from_str::<Vec<FunctionConfig>>("[{type: acm, speed: 9600}]").is_err());
+        assert_eq!(GadgetProfile::from_config(&[]), GadgetProfile::default());
+    }
+
+    #[test]
+    fn test_validate() {
+        GadgetProfile::default().validate().unwrap();
+        let error = GadgetProfile::empty()
+            .with_hid(HidRole::Keyboard)
+            .with_acm(false)
+            .with_hid(HidRole::Consumer)
+            .validate()
+            .unwrap_err();
+        assert!(error.to_string().contains("Mouse"), "{error}");
+        assert!(GadgetProfile::default()
+            .with_hid(HidRole::Keyboard)
+            .validate()
+            .is_err());
+        // The gamepad is optional, but only one
+        let profile = GadgetProfile::default().with_gamepad();
+        profile.validate().unwrap();
+        assert!(profile.has_role(HidRole::Gamepad));
+        assert_eq!(profile.clone().with_gamepad(), profile);
+        assert!(!GadgetProfile::default().has_role(HidRole::Gamepad));
+        assert!(profile.with_hid(HidRole::Gamepad).validate().is_err());
+        assert!(GadgetProfile::default()
+            .with_function(FunctionConfig::MassStorage {
+                file: PathBuf::from("disk.img"),
+                read_only: false,
+                cdrom: false,
+                removable: true,
+            })
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_build() {
+        let profile = GadgetProfile::default().with_acm(false);
+        let (handles, functions) = profile.clone().build(&Default::default()).unwrap();
+        assert_eq!(handles.len(), 4);
+        assert!(!std::ptr::eq(
+            functions.hid(HidRole::Keyboard).unwrap(),
+            functions.hid(HidRole::Mouse).unwrap()
+        ));
+
+        // The roles share the function of the keyboard, the first one
+        let merged = DescriptorConfig {
+            merge_into_single_interface: true,
+            ..Default::default()
+        };
+        let (handles, functions) = profile.clone().build(&merged).unwrap();
+        assert_eq!(handles.len(), 2);
+        let keyboard = functions.hid(HidRole::Keyboard).unwrap();
+        for role in [HidRole::Mouse, HidRole::Consumer] {
+            assert!(std::ptr::eq(functions.hid(role).unwrap(), keyboard));
+        }
+
+        let no_consumer = DescriptorConfig {
+            consumer: false,
+            ..Default::default()
+        };
+        let (handles, functions) = profile.build(&no_consumer).unwrap();
+        assert_eq!(handles.len(), 3);
+        assert!(functions.hid(HidRole::Consumer).is_err());
+    }
+
+    #[test]
+    fn test_find_role() {
+        // The device nodes of a profile with the HID functions after a serial one
+        let hids = [
+            (HidRole::Consumer, "/dev/hidg0"),
+            (HidRole::Mouse, "/dev/hidg1"),
+            (HidRole::Keyboard, "/dev/hidg2"),
+        ];
+        assert_eq!(*find_role(&hids, HidRole::Keyboard).unwrap(), "/dev/hidg2");
+        assert_eq!(*find_role(&hids, HidRole::Consumer).unwrap(), "/dev/hidg0");
+        assert!(find_role(&hids[1..], HidRole::Consumer).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::{
+        linux::fs::MetadataExt,
+        unix::fs::{FileTypeExt, OpenOptionsExt},
+    },
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use usb_gadget::function::hid::Hid;
+
+/// Delay between attempts to find the device node while udev creates it
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Opens the device node of a bound HID function for writing, non-blocking, see
+/// [`resolve_hidg_device_with`]
+pub fn resolve_hidg_device(hid: &Hid, timeout: Duration) -> anyhow::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).custom_flags(libc::O_NONBLOCK);
+    resolve_hidg_device_with(hid, &options, timeout)
+}
+
+/// Opens the device node of a bound HID function, waiting up to `timeout` for udev to
+/// create it.
+///
+/// The node is looked up with the device number in `/sys/dev/char`, or among the `/dev/hidg*`
+/// nodes if sysfs doesn't have it. The device number is checked again on the opened file, a
+/// node left over from a previous gadget is skipped until it's replaced.
+pub fn resolve_hidg_device_with(
+    hid: &Hid,
+    options: &OpenOptions,
+    timeout: Duration,
+) -> anyhow::Result<File> {
+    let (major, minor) = hid.device()?;
+    let (path, file) = Resolver::default().resolve(major, minor, options, timeout)?;
+    debug!("Dev file of {major}:{minor} at {:?}", path);
+    Ok(file)
+}
+
+/// Looks for device nodes under `dev`, with the sysfs of `sys`
+struct Resolver {
+    dev: PathBuf,
+    sys: PathBuf,
+    // Device number of an opened node, `None` if it isn't a character device
+    device_of: fn(&File) -> io::Result<Option<u64>>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self {
+            dev: "/dev".into(),
+            sys: "/sys".into(),
+            device_of: char_device_of,
+        }
+    }
+}
+
+/// fstat on the opened file, the path may have been replaced since it was looked up
+fn char_device_of(file: &File) -> io::Result<Option<u64>> {
+    let metadata = file.metadata()?;
+    Ok(metadata
+        .file_type()
+        .is_char_device()
+        .then(|| metadata.st_rdev()))
+}
+
+impl Resolver {
+    fn resolve(
+        &self,
+        major: u32,
+        minor: u32,
+        options: &OpenOptions,
+        timeout: Duration,
+    ) -> io::Result<(PathBuf, File)> {
+        let device = libc::makedev(major, minor);
+        let deadline = Instant::now() + timeout;
+        loop {
+            for path in self.candidates(major, minor)? {
+                // The node may be gone or not accessible yet
+                let Ok(file) = options.open(&path) else {
+                    continue;
+                };
+                match (self.device_of)(&file)? {
+                    Some(found) if found == device => return Ok((path, file)),
+                    found => debug!("Skipped {:?}, device {:?}", path, found),
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Device {major}:{minor} not found in {:?}", timeout),
+                ));
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Nodes that may be the device, the one named by sysfs first
+    fn candidates(&self, major: u32, minor: u32) -> io::Result<Vec<PathBuf>> {
+        let mut candidates = vec![];
+        let uevent = self.sys.join(format!("dev/char/{major}:{minor}/uevent"));
+        match std::fs::read_to_string(&uevent) {
+            Ok(uevent) => {
+                if let Some(name) = uevent
+                    .lines()
+                    .find_map(|line| line.strip_prefix("DEVNAME="))
+                {
+                    candidates.push(self.dev.join(name));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Cannot read {:?}: {}", uevent, e),
+        }
+        let mut nodes = vec![];
+        for entry in std::fs::read_dir(&self.dev)? {
+            let path = entry?.path();
+            let is_hidg = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("hidg"));
+            if is_hidg && !candidates.contains(&path) {
+                nodes.push(path);
+            }
+        }
+        nodes.sort();
+        candidates.extend(nodes);
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{self, File, OpenOptions},
+        io::{self, Read},
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use super::Resolver;
+
+    // The fake nodes contain their device number as "major:minor"
+    fn device_of(mut file: &File) -> io::Result<Option<u64>> {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Ok(contents
+            .trim()
+            .split_once(':')
+            .map(|(major, minor)| libc::makedev(major.parse().unwrap(), minor.parse().unwrap())))
+    }
+
+    // A /dev and a /sys of its own for each test
+    fn resolver(name: &str) -> Resolver {
+        let root = std::env::temp_dir().join(format!("barpi-hidg-{}-{name}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("dev")).unwrap();
+        fs::create_dir_all(root.join("sys/dev/char")).unwrap();
+        Resolver {
+            dev: root.join("dev"),
+            sys: root.join("sys"),
+            device_of,
+        }
+    }
+
+    fn read() -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options.read(true);
+        options
+    }
+
+    fn node(resolver: &Resolver, name: &str, device: &str) -> PathBuf {
+        let path = resolver.dev.join(name);
+        fs::write(&path, device).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_resolve_sysfs() {
+        let resolver = resolver("sysfs");
+        node(&resolver, "hidg0", "240:0");
+        let expected = node(&resolver, "hidg-kbd", "240:1");
+        let sys = resolver.sys.join("dev/char/240:1");
+        fs::create_dir_all(&sys).unwrap();
+        fs::write(sys.join("uevent"), "MAJOR=240\nMINOR=1\nDEVNAME=hidg-kbd\n").unwrap();
+
+        let (path, _) = resolver.resolve(240, 1, &read(), Duration::ZERO).unwrap();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn test_resolve_glob() {
+        let resolver = resolver("glob");
+        node(&resolver, "hidg0", "240:0");
+        let expected = node(&resolver, "hidg1", "240:1");
+        // Not a gadget node
+        node(&resolver, "hidraw1", "240:1");
+
+        let (path, _) = resolver.resolve(240, 1, &read(), Duration::ZERO).unwrap();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn test_stale_node() {
+        let resolver = resolver("stale");
+        // Left over from a previous run, sysfs already names it
+        node(&resolver, "hidg0", "240:7");
+        let sys = resolver.sys.join("dev/char/240:0");
+        fs::create_dir_all(&sys).unwrap();
+        fs::write(sys.join("uevent"), "DEVNAME=hidg0\n").unwrap();
+
+        assert_eq!(
+            resolver
+                .resolve(240, 0, &read(), Duration::ZERO)
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        // udev replaces it while waiting
+        let dev = resolver.dev.clone();
+        let replaced = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            fs::write(dev.join("new"), "240:0").unwrap();
+            fs::rename(dev.join("new"), dev.join("hidg0")).unwrap();
+        });
+        let started = Instant::now();
+        let (path, _) = resolver
+            .resolve(240, 0, &read(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(path, resolver.dev.join("hidg0"));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        replaced.join().unwrap();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let resolver = resolver("timeout");
+        let started = Instant::now();
+        let error = resolver
+            .resolve(240, 0, &read(), Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
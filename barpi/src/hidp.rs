@@ -0,0 +1,161 @@
+/// L2CAP PSM of the HID control channel
+pub const PSM_CONTROL: u16 = 0x11;
+
+/// L2CAP PSM of the HID interrupt channel, carrying the reports
+pub const PSM_INTERRUPT: u16 = 0x13;
+
+// Transaction types of the first byte of a message, the parameter is in the low nibble
+const HANDSHAKE: u8 = 0x00;
+const HID_CONTROL: u8 = 0x10;
+const GET_PROTOCOL: u8 = 0x60;
+const SET_PROTOCOL: u8 = 0x70;
+const SET_REPORT: u8 = 0x50;
+const DATA: u8 = 0xA0;
+
+const HANDSHAKE_SUCCESSFUL: u8 = 0x00;
+const HANDSHAKE_ERR_UNSUPPORTED: u8 = 0x03;
+const VIRTUAL_CABLE_UNPLUG: u8 = 0x05;
+const REPORT_INPUT: u8 = 0x01;
+const REPORT_OUTPUT: u8 = 0x02;
+const PROTOCOL_REPORT: u8 = 0x01;
+
+/// The message carrying an input report on the interrupt channel
+pub fn input_frame(id: u8, report: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(report.len() + 2);
+    frame.extend([DATA | REPORT_INPUT, id]);
+    frame.extend_from_slice(report);
+    frame
+}
+
+/// What a message from the host is about, on either channel
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostMessage<'a> {
+    /// An output report and its ID, the keyboard LEDs
+    Output(u8, &'a [u8]),
+    /// The host removed the pairing, it doesn't reconnect
+    Unplug,
+    /// Nothing for the devices, see [`control_reply`]
+    Other,
+}
+
+pub fn parse_message(message: &[u8]) -> HostMessage<'_> {
+    let Some((&header, payload)) = message.split_first() else {
+        return HostMessage::Other;
+    };
+    match (header & 0xF0, header & 0x0F, payload) {
+        (SET_REPORT | DATA, REPORT_OUTPUT, [id, data @ ..]) => HostMessage::Output(*id, data),
+        (HID_CONTROL, VIRTUAL_CABLE_UNPLUG, _) => HostMessage::Unplug,
+        _ => HostMessage::Other,
+    }
+}
+
+/// The answer to a message on the control channel, if it expects one. Only the report
+/// protocol is supported, the reports have an ID the boot protocol doesn't allow.
+pub fn control_reply(message: &[u8]) -> Option<Vec<u8>> {
+    let &header = message.first()?;
+    let reply = match (header & 0xF0, header & 0x0F) {
+        (HANDSHAKE | HID_CONTROL | DATA, _) => return None,
+        (SET_REPORT, _) | (SET_PROTOCOL, PROTOCOL_REPORT) => vec![HANDSHAKE | HANDSHAKE_SUCCESSFUL],
+        (GET_PROTOCOL, _) => vec![DATA, PROTOCOL_REPORT],
+        _ => vec![HANDSHAKE | HANDSHAKE_ERR_UNSUPPORTED],
+    };
+    Some(reply)
+}
+
+/// SDP record of the HID service in the XML format of BlueZ, advertising `descriptor`
+pub fn sdp_record(name: &str, descriptor: &[u8]) -> String {
+    let name = name
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    let descriptor: String = descriptor.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" ?>
+<record>
+  <attribute id="0x0001"><sequence><uuid value="0x1124" /></sequence></attribute>
+  <attribute id="0x0004">
+    <sequence>
+      <sequence><uuid value="0x0100" /><uint16 value="0x{PSM_CONTROL:04x}" /></sequence>
+      <sequence><uuid value="0x0011" /></sequence>
+    </sequence>
+  </attribute>
+  <attribute id="0x0005"><sequence><uuid value="0x1002" /></sequence></attribute>
+  <attribute id="0x0006">
+    <sequence><uint16 value="0x656e" /><uint16 value="0x006a" /><uint16 value="0x0100" /></sequence>
+  </attribute>
+  <attribute id="0x0009">
+    <sequence><sequence><uuid value="0x1124" /><uint16 value="0x0101" /></sequence></sequence>
+  </attribute>
+  <attribute id="0x000d">
+    <sequence>
+      <sequence>
+        <sequence><uuid value="0x0100" /><uint16 value="0x{PSM_INTERRUPT:04x}" /></sequence>
+        <sequence><uuid value="0x0011" /></sequence>
+      </sequence>
+    </sequence>
+  </attribute>
+  <attribute id="0x0100"><text value="{name}" /></attribute>
+  <attribute id="0x0101"><text value="Keyboard and mouse" /></attribute>
+  <attribute id="0x0102"><text value="barpi" /></attribute>
+  <attribute id="0x0201"><uint16 value="0x0111" /></attribute>
+  <attribute id="0x0202"><uint8 value="0xc0" /></attribute>
+  <attribute id="0x0203"><uint8 value="0x00" /></attribute>
+  <attribute id="0x0204"><boolean value="true" /></attribute>
+  <attribute id="0x0205"><boolean value="true" /></attribute>
+  <attribute id="0x0206">
+    <sequence>
+      <sequence><uint8 value="0x22" /><text encoding="hex" value="{descriptor}" /></sequence>
+    </sequence>
+  </attribute>
+  <attribute id="0x0207">
+    <sequence><sequence><uint16 value="0x0409" /><uint16 value="0x0100" /></sequence></sequence>
+  </attribute>
+  <attribute id="0x020b"><uint16 value="0x0100" /></attribute>
+  <attribute id="0x020c"><uint16 value="0x0c80" /></attribute>
+  <attribute id="0x020d"><boolean value="true" /></attribute>
+  <attribute id="0x020e"><boolean value="false" /></attribute>
+</record>
+"#
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{control_reply, input_frame, parse_message, sdp_record, HostMessage};
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(input_frame(1, &[0, 0, 4]), [0xA1, 1, 0, 0, 4]);
+
+        // The LEDs come as data on the interrupt channel or set report on the control one
+        assert_eq!(
+            parse_message(&[0xA2, 1, 0x02]),
+            HostMessage::Output(1, &[0x02])
+        );
+        assert_eq!(
+            parse_message(&[0x52, 1, 0x01]),
+            HostMessage::Output(1, &[0x01])
+        );
+        assert_eq!(control_reply(&[0x52, 1, 0x01]), Some(vec![0x00]));
+        assert_eq!(parse_message(&[0x15]), HostMessage::Unplug);
+        assert_eq!(control_reply(&[0x15]), None);
+        assert_eq!(parse_message(&[]), HostMessage::Other);
+        assert_eq!(control_reply(&[]), None);
+
+        assert_eq!(control_reply(&[0x60]), Some(vec![0xA0, 0x01]));
+        assert_eq!(control_reply(&[0x71]), Some(vec![0x00]));
+        // No boot protocol, and no get report
+        assert_eq!(control_reply(&[0x70]), Some(vec![0x03]));
+        assert_eq!(control_reply(&[0x41, 1]), Some(vec![0x03]));
+    }
+
+    #[test]
+    fn test_sdp_record() {
+        let record = sdp_record("Pi <1> & \"2\"", &[0x05, 0x01, 0xA1]);
+        assert!(record.contains(r#"<text value="Pi &lt;1&gt; &amp; &quot;2&quot;" />"#));
+        assert!(record.contains(r#"<text encoding="hex" value="0501a1" />"#));
+        assert!(record.contains(r#"<uint16 value="0x0011" />"#));
+        assert!(record.contains(r#"<uint16 value="0x0013" />"#));
+    }
+}
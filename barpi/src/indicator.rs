@@ -0,0 +1,529 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use barrier_client::{ConnectionState, ServerList};
+use log::{debug, warn};
+use tokio::{
+    select,
+    sync::{watch, Notify},
+    time::{sleep_until, Instant},
+};
+
+use crate::{config::Indicator, control::state_name};
+
+/// How long the activity LED stays on, then off at least as long before the next flash
+const FLASH_TIME: Duration = Duration::from_millis(50);
+
+/// Where the kernel exports the GPIO lines with the sysfs interface
+const GPIO_SYSFS: &str = "/sys/class/gpio";
+
+/// Time for udev to set the permissions of a line once it's exported
+const EXPORT_DELAY: Duration = Duration::from_millis(100);
+
+/// Width of the SSD1306 in pixels, its height is 32 or 64
+const OLED_WIDTH: usize = 128;
+
+/// Characters per line of the OLED, 5 pixels wide with a space
+const LINE_LEN: usize = OLED_WIDTH / 6;
+
+/// ioctl of i2c-dev setting the address of the device the writes go to
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+/// The state of barpi shown to the user, by LEDs or on a small display next to the Pi
+pub trait StatusIndicator: Send {
+    /// The connection to the server changed, `server` is the one connected to if any
+    fn set_state(&mut self, state: &ConnectionState, server: Option<&str>) -> io::Result<()>;
+    /// The cursor entered this screen or left it
+    fn set_entered(&mut self, entered: bool) -> io::Result<()>;
+    /// Reports are being written to the host, it's turned on and off for each flash
+    fn set_activity(&mut self, on: bool) -> io::Result<()>;
+}
+
+/// Whether the client is connected to a server, whatever the cursor does
+pub fn is_connected(state: &ConnectionState) -> bool {
+    matches!(
+        state,
+        ConnectionState::Connected | ConnectionState::Entered | ConnectionState::Left
+    )
+}
+
+/// The lines of text shown on a display, each at most [`LINE_LEN`] characters. The first one
+/// is a character shorter, the activity mark goes at its end.
+pub fn status_lines(state: &ConnectionState, server: Option<&str>, entered: bool) -> Vec<String> {
+    let truncate = |line: String, len: usize| line.chars().take(len).collect::<String>();
+    let mut lines = vec![
+        truncate(format!("barpi {}", state_name(state)), LINE_LEN - 1),
+        truncate(server.unwrap_or("no server").to_string(), LINE_LEN),
+    ];
+    match state {
+        ConnectionState::Disconnected {
+            reason: Some(reason),
+        } => lines.push(truncate(reason.clone(), LINE_LEN)),
+        state if is_connected(state) => {
+            let cursor = if entered {
+                "cursor here"
+            } else {
+                "cursor away"
+            };
+            lines.push(cursor.to_string());
+        }
+        _ => {}
+    }
+    lines
+}
+
+/// The frame buffer of a display of `pages` rows of 8 pixels with a line of text on each,
+/// a byte per column of 8 pixels, the top one in the low bit
+pub fn render(lines: &[String], pages: usize) -> Vec<u8> {
+    let mut frame = vec![0; OLED_WIDTH * pages];
+    for (page, line) in frame.chunks_mut(OLED_WIDTH).zip(lines) {
+        for (cell, c) in page.chunks_mut(6).zip(line.chars()) {
+            cell[..5].copy_from_slice(&glyph(c));
+        }
+    }
+    frame
+}
+
+// The columns of `c` in the 5x7 font, '?' for the characters it doesn't have
+fn glyph(c: char) -> [u8; 5] {
+    let index = match c {
+        ' '..='~' => c as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    };
+    FONT[index]
+}
+
+/// LEDs on GPIO lines through the sysfs interface, any of them can be left out
+pub struct GpioLeds {
+    connected: Option<File>,
+    entered: Option<File>,
+    activity: Option<File>,
+}
+
+impl GpioLeds {
+    /// Exports the lines of `pins`, in order the LEDs on while connected, while the cursor is
+    /// on this screen and flashing on activity. The LEDs are turned off.
+    pub fn open(pins: &[u32]) -> io::Result<Self> {
+        let mut lines = pins.iter().map(|&pin| export(Path::new(GPIO_SYSFS), pin));
+        let mut next = || lines.next().transpose();
+        let mut leds = Self {
+            connected: next()?,
+            entered: next()?,
+            activity: next()?,
+        };
+        leds.set_state(&ConnectionState::default(), None)?;
+        leds.set_entered(false)?;
+        leds.set_activity(false)?;
+        Ok(leds)
+    }
+}
+
+// The value file of `pin` set as an output, exported first if it isn't yet
+fn export(sysfs: &Path, pin: u32) -> io::Result<File> {
+    let line = sysfs.join(format!("gpio{pin}"));
+    if !line.exists() {
+        fs::write(sysfs.join("export"), pin.to_string())?;
+        std::thread::sleep(EXPORT_DELAY);
+    }
+    fs::write(line.join("direction"), "out")?;
+    OpenOptions::new().write(true).open(line.join("value"))
+}
+
+fn set_led(led: &mut Option<File>, on: bool) -> io::Result<()> {
+    match led {
+        Some(value) => value.write_all(if on { b"1" } else { b"0" }),
+        None => Ok(()),
+    }
+}
+
+impl StatusIndicator for GpioLeds {
+    fn set_state(&mut self, state: &ConnectionState, _server: Option<&str>) -> io::Result<()> {
+        set_led(&mut self.connected, is_connected(state))
+    }
+
+    fn set_entered(&mut self, entered: bool) -> io::Result<()> {
+        set_led(&mut self.entered, entered)
+    }
+
+    fn set_activity(&mut self, on: bool) -> io::Result<()> {
+        set_led(&mut self.activity, on)
+    }
+}
+
+/// An SSD1306 OLED on an I2C bus, showing the [`status_lines`]
+pub struct Ssd1306 {
+    device: File,
+    pages: usize,
+    state: ConnectionState,
+    server: Option<String>,
+    entered: bool,
+}
+
+impl Ssd1306 {
+    /// Opens the display at `address` on the i2c-dev `bus`, `height` is 32 or 64 pixels
+    pub fn open(bus: &Path, address: u8, height: u8) -> io::Result<Self> {
+        let device = OpenOptions::new().read(true).write(true).open(bus)?;
+        if unsafe { libc::ioctl(device.as_raw_fd(), I2C_SLAVE, libc::c_ulong::from(address)) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut oled = Self {
+            device,
+            pages: height as usize / 8,
+            state: ConnectionState::default(),
+            server: None,
+            entered: false,
+        };
+        let com_pins = if height == 32 { 0x02 } else { 0x12 };
+        #[rustfmt::skip]
+        let init = [
+            0xAE, // Display off
+            0xD5, 0x80, // Clock divide ratio
+            0xA8, height - 1, // Multiplex ratio
+            0xD3, 0x00, // No display offset
+            0x40, // Start line 0
+            0x8D, 0x14, // Charge pump on
+            0x20, 0x00, // Horizontal addressing
+            0xA1, 0xC8, // Columns and rows flipped, for the usual mounting
+            0xDA, com_pins,
+            0x81, 0x8F, // Contrast
+            0xD9, 0xF1, // Pre-charge period
+            0xDB, 0x40, // VCOMH deselect level
+            0xA4, 0xA6, // Show the RAM, not inverted
+            0xAF, // Display on
+        ];
+        oled.command(&init)?;
+        oled.draw()?;
+        Ok(oled)
+    }
+
+    fn command(&mut self, commands: &[u8]) -> io::Result<()> {
+        // The control byte says commands follow
+        self.device.write_all(&[&[0x00], commands].concat())
+    }
+
+    // Writes `data` to the columns and pages given
+    fn write_area(&mut self, columns: (u8, u8), pages: (u8, u8), data: &[u8]) -> io::Result<()> {
+        self.command(&[0x21, columns.0, columns.1, 0x22, pages.0, pages.1])?;
+        // The control byte says data follows
+        self.device.write_all(&[&[0x40], data].concat())
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let lines = status_lines(&self.state, self.server.as_deref(), self.entered);
+        let frame = render(&lines, self.pages);
+        self.write_area((0, OLED_WIDTH as u8 - 1), (0, self.pages as u8 - 1), &frame)
+    }
+}
+
+impl StatusIndicator for Ssd1306 {
+    fn set_state(&mut self, state: &ConnectionState, server: Option<&str>) -> io::Result<()> {
+        self.state = state.clone();
+        self.server = server.map(str::to_string);
+        self.draw()
+    }
+
+    fn set_entered(&mut self, entered: bool) -> io::Result<()> {
+        self.entered = entered;
+        self.draw()
+    }
+
+    // Only the last character of the first line, a whole frame takes too long on the bus
+    fn set_activity(&mut self, on: bool) -> io::Result<()> {
+        let column = (LINE_LEN - 1) * 6;
+        let mark = glyph(if on { '*' } else { ' ' });
+        self.write_area((column as u8, column as u8 + 4), (0, 0), &mark)
+    }
+}
+
+/// Opens the hardware of the indicator
+pub fn open(config: &Indicator) -> io::Result<Box<dyn StatusIndicator>> {
+    Ok(match config {
+        Indicator::Gpio(pins) => Box::new(GpioLeds::open(pins)?),
+        Indicator::Ssd1306 {
+            bus,
+            address,
+            height,
+        } => Box::new(Ssd1306::open(bus, *address, *height)?),
+    })
+}
+
+// Where the flash of the activity LED is at
+enum Flash {
+    Idle,
+    On(Instant),
+    Off(Instant),
+}
+
+/// Shows the state of the client watched by `states`, which changes when the config is
+/// reloaded, with the active server of `servers`. The activity LED flashes once
+/// `activity` is notified, at most once per twice [`FLASH_TIME`].
+pub async fn show_status<I: StatusIndicator + ?Sized>(
+    indicator: &mut I,
+    mut states: watch::Receiver<watch::Receiver<ConnectionState>>,
+    servers: watch::Receiver<ServerList>,
+    activity: Arc<Notify>,
+) {
+    let warn_error = |result: io::Result<()>| {
+        if let Err(e) = result {
+            warn!("Cannot update the status indicator: {:?}", e);
+        }
+    };
+    let mut state = states.borrow_and_update().clone();
+    let mut current = state.borrow_and_update().clone();
+    let mut entered = current == ConnectionState::Entered;
+    warn_error(indicator.set_state(&current, servers.borrow().active()));
+    warn_error(indicator.set_entered(entered));
+    let mut flash = Flash::Idle;
+    loop {
+        let deadline = match flash {
+            Flash::On(deadline) | Flash::Off(deadline) => Some(deadline),
+            Flash::Idle => None,
+        };
+        select! {
+            changed = state.changed() => {
+                if changed.is_err() {
+                    // The client was dropped, wait for the next one
+                    if states.changed().await.is_err() {
+                        return;
+                    }
+                    state = states.borrow_and_update().clone();
+                }
+            }
+            changed = states.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                state = states.borrow_and_update().clone();
+            }
+            _ = activity.notified(), if deadline.is_none() => {
+                if let Err(e) = indicator.set_activity(true) {
+                    debug!("Cannot flash the activity LED: {:?}", e);
+                }
+                flash = Flash::On(Instant::now() + FLASH_TIME);
+                continue;
+            }
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                flash = match flash {
+                    Flash::On(_) => {
+                        if let Err(e) = indicator.set_activity(false) {
+                            debug!("Cannot flash the activity LED: {:?}", e);
+                        }
+                        Flash::Off(Instant::now() + FLASH_TIME)
+                    }
+                    _ => Flash::Idle,
+                };
+                continue;
+            }
+        }
+        let new = state.borrow_and_update().clone();
+        if new != current {
+            warn_error(indicator.set_state(&new, servers.borrow().active()));
+        }
+        if (new == ConnectionState::Entered) != entered {
+            entered = !entered;
+            warn_error(indicator.set_entered(entered));
+        }
+        current = new;
+    }
+}
+
+/// The printable ASCII characters from the space, 5 columns each
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], // ' ' !
+    [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7F, 0x14, 0x7F, 0x14], // " #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], // $ %
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], // & '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1C, 0x00], // ( )
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], [0x08, 0x08, 0x3E, 0x08, 0x08], // * +
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], // , -
+    [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02], // . /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00], // 0 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], // 2 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], // 4 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03], // 6 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], // 8 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00], // : ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], // < =
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], // > ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], [0x7E, 0x11, 0x11, 0x11, 0x7E], // @ A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22], // B C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], // D E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], [0x3E, 0x41, 0x49, 0x49, 0x7A], // F G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00], // H I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], // J K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], [0x7F, 0x02, 0x0C, 0x02, 0x7F], // L M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E], // N O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], // P Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31], // R S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F], // T U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], // V W
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x07, 0x08, 0x70, 0x08, 0x07], // X Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00], // Z [
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], // \ ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40], // ^ _
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78], // ` a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], // b c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], [0x38, 0x54, 0x54, 0x54, 0x18], // d e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E], // f g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], // h i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], [0x7F, 0x10, 0x28, 0x44, 0x00], // j k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78], // l m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], // n o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7C], // p q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20], // r s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], // t u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], [0x3C, 0x40, 0x30, 0x40, 0x3C], // v w
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C], // x y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], // z {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], // | }
+    [0x10, 0x08, 0x08, 0x10, 0x08],                                 // ~
+];
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use barrier_client::{ConnectionState, ServerList};
+    use tokio::sync::{watch, Notify};
+
+    use super::{render, show_status, status_lines, StatusIndicator, FLASH_TIME, LINE_LEN};
+
+    #[test]
+    fn test_status_lines() {
+        assert_eq!(
+            status_lines(&ConnectionState::Connected, Some("desktop:24800"), false),
+            ["barpi connected", "desktop:24800", "cursor away"]
+        );
+        assert_eq!(
+            status_lines(&ConnectionState::Entered, Some("desktop:24800"), true),
+            ["barpi entered", "desktop:24800", "cursor here"]
+        );
+        assert_eq!(
+            status_lines(&ConnectionState::Connecting, None, false),
+            ["barpi connecting", "no server"]
+        );
+        // The reason is cut to the width of the display
+        let disconnected = ConnectionState::Disconnected {
+            reason: Some("connection refused by the server".to_string()),
+        };
+        let lines = status_lines(&disconnected, None, false);
+        assert_eq!(lines[0], "barpi disconnected");
+        assert_eq!(lines[2], "connection refused by");
+        assert_eq!(lines[2].len(), LINE_LEN);
+    }
+
+    #[test]
+    fn test_render() {
+        let frame = render(&["Hi!".to_string(), "é".to_string()], 4);
+        assert_eq!(frame.len(), 128 * 4);
+        assert_eq!(
+            frame[..18],
+            [
+                0x7F, 0x08, 0x08, 0x08, 0x7F, 0, // H
+                0x00, 0x44, 0x7D, 0x40, 0x00, 0, // i
+                0x00, 0x00, 0x5F, 0x00, 0x00, 0, // !
+            ]
+        );
+        assert!(frame[18..128].iter().all(|&column| column == 0));
+        // Not in the font
+        assert_eq!(frame[128..133], [0x02, 0x01, 0x51, 0x09, 0x06]);
+        assert!(frame[256..].iter().all(|&column| column == 0));
+    }
+
+    // Records the calls in order
+    #[derive(Clone, Default)]
+    struct Mock(Arc<Mutex<Vec<String>>>);
+
+    impl StatusIndicator for Mock {
+        fn set_state(&mut self, state: &ConnectionState, server: Option<&str>) -> io::Result<()> {
+            let call = format!("state {:?} {:?}", state, server);
+            self.0.lock().unwrap().push(call);
+            Ok(())
+        }
+
+        fn set_entered(&mut self, entered: bool) -> io::Result<()> {
+            self.0.lock().unwrap().push(format!("entered {entered}"));
+            Ok(())
+        }
+
+        fn set_activity(&mut self, on: bool) -> io::Result<()> {
+            self.0.lock().unwrap().push(format!("activity {on}"));
+            Ok(())
+        }
+    }
+
+    impl Mock {
+        async fn wait_calls(&self, len: usize) {
+            for _ in 0..100 {
+                if self.0.lock().unwrap().len() >= len {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("only {:?}", self.0.lock().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_show_status() {
+        let (state_tx, state_rx) = watch::channel(ConnectionState::default());
+        let (_states_tx, states_rx) = watch::channel(state_rx);
+        let (_servers_tx, servers_rx) = watch::channel(ServerList::new(["desktop:24800"]));
+        let activity = Arc::new(Notify::new());
+        let mock = Mock::default();
+        let mut indicator = mock.clone();
+        let flashes = activity.clone();
+        tokio::spawn(async move {
+            show_status(&mut indicator, states_rx, servers_rx, flashes).await;
+        });
+        mock.wait_calls(2).await;
+
+        // Connect, enter, type and disconnect
+        for (state, calls) in [
+            (ConnectionState::Connecting, 3),
+            (ConnectionState::Connected, 4),
+            (ConnectionState::Entered, 6),
+        ] {
+            state_tx.send_replace(state);
+            mock.wait_calls(calls).await;
+        }
+        activity.notify_one();
+        mock.wait_calls(7).await;
+        // The reports written meanwhile are one more flash once it's over
+        for _ in 0..5 {
+            activity.notify_one();
+        }
+        mock.wait_calls(10).await;
+        state_tx.send_replace(ConnectionState::Disconnected { reason: None });
+        mock.wait_calls(12).await;
+        tokio::time::sleep(FLASH_TIME * 3).await;
+        assert_eq!(
+            *mock.0.lock().unwrap(),
+            [
+                "state Disconnected { reason: None } None",
+                "entered false",
+                "state Connecting None",
+                "state Connected None",
+                "state Entered None",
+                "entered true",
+                "activity true",
+                "activity false",
+                "activity true",
+                "activity false",
+                "state Disconnected { reason: None } None",
+                "entered false",
+            ]
+        );
+    }
+}
@@ -1,83 +1,72 @@
 use std::{
-    cmp::min, env, fs::File, io::BufReader, os::linux::fs::MetadataExt, path::PathBuf,
-    thread::sleep, time::Duration,
+    cmp::min,
+    env,
+    fs::{File, OpenOptions},
+    io::{BufReader, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
 };
 
-use barrier_client::start;
+use anyhow::Context;
+use barrier_client::{
+    replay, serve_actuator, Actuator, Backoff, ClickAssist, Client, ClientStats, ClientStatus,
+    ConnectionError, ConnectionState, Fingerprint, LogMetrics, Metrics, RecordingActuator,
+    ServerList, TlsOptions,
+};
 use clap::Parser;
-use clap_serde_derive::{serde::Serialize, ClapSerde};
 use env_logger::Env;
-use log::{debug, info, warn};
-use synergy_hid::{ReportType, SynergyHid};
+use log::{debug, error, info, warn};
+use synergy_hid::{
+    ButtonMap, DescriptorConfig, KeyboardMode, KeymapOverride, MacroTable, MouseMode, ReportType,
+    SynergyHid,
+};
 use tokio::{
+    net::TcpListener,
     select,
     signal::unix::{signal, SignalKind},
+    sync::{watch, Notify},
 };
 use tokio_util::sync::CancellationToken;
-use usb_gadget::{
-    default_udc,
-    function::{hid::Hid, Handle},
-    Class, Config, Gadget, Id, RegGadget, Strings,
-};
+use usb_gadget::{default_udc, Class, Config, Gadget, Id, RegGadget, Strings};
 
+#[cfg(feature = "bluetooth")]
+mod bluetooth;
 mod client;
-
-#[derive(Parser)]
-#[command(author, version, about)]
-struct Args {
-    /// Input files
-    input: Vec<std::path::PathBuf>,
-
-    /// Config file
-    #[arg(short, long = "config", default_value = "config.yml")]
-    config_path: std::path::PathBuf,
-
-    /// Rest of arguments
-    #[command(flatten)]
-    pub config: <BarpiConfig as ClapSerde>::Opt,
-}
-
-#[derive(ClapSerde, Serialize, Debug)]
-pub struct BarpiConfig {
-    /// Barrier server address in "server:port" format
-    #[arg(short = 's', long, env = "BARRIER_SERVER")]
-    pub server: String,
-    /// Screen name, must be accepted by the Barrier server
-    #[arg(short = 'n', long, env = "SCREEN_NAME")]
-    pub screen_name: String,
-    /// Screen width
-    #[arg(short = 'w', long, default_value = "1920", env = "SCREEN_WIDTH")]
-    pub screen_width: u16,
-    /// Screen height
-    #[arg(short = 'e', long, default_value = "1080", env = "SCREEN_HEIGHT")]
-    pub screen_height: u16,
-    /// Flip mouse wheel
-    #[arg(short = 'f', long, default_value = "false")]
-    pub flip_mouse_wheel: bool,
-
-    // USB ids
-    #[arg(hide = true, long, default_value = "3338")]
-    pub usb_vid: u16,
-    #[arg(hide = true, long, default_value = "49374")]
-    pub usb_pid: u16,
-    #[arg(hide = true, long, default_value = "0d0a.com")]
-    pub usb_manufacturer: String,
-    #[arg(hide = true, long, default_value = "BarPi HID Device")]
-    pub usb_product: String,
-    #[arg(hide = true, long, default_value = "0000000000000001")]
-    pub usb_serial: String,
-
-    // Power supply related settings
-    /// RPi Zero W requires around 200mA without accessories, and Zero 2W around 250mA
-    #[arg(hide = true, long, default_value = "500")]
-    pub max_power_ma: u16,
-    /// Set to true if the device has external power, and the USB remote wakeup is enabled when this is true
-    #[arg(hide = true, long, default_value = "false")]
-    pub self_powered: bool,
-}
-
-pub fn reg(funcs: Vec<Handle>, cfg: &BarpiConfig) -> RegGadget {
-    let udc = default_udc().expect("cannot get UDC");
+mod config;
+mod control;
+mod display;
+mod gadget;
+mod hidg;
+#[cfg(any(feature = "bluetooth", test))]
+mod hidp;
+#[cfg(feature = "indicator")]
+mod indicator;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod sink;
+mod writer;
+
+use config::{invalid_config, Args, BarpiConfig, Bind, Command, Transport};
+use control::{bind_control, serve_control, Control, Shared, SharedActuator};
+use gadget::{GadgetFunctions, GadgetProfile, HidRole};
+use hidg::{resolve_hidg_device, resolve_hidg_device_with};
+use sink::Sink;
+
+/// Time to wait for the HID reports to be cleared on exit, the host may not be reading them
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time to wait for udev to create the device nodes once the gadget is bound
+const DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn reg(
+    profile: GadgetProfile,
+    cfg: &BarpiConfig,
+    descriptors: &DescriptorConfig,
+) -> anyhow::Result<(RegGadget, GadgetFunctions)> {
+    let (funcs, functions) = profile.build(descriptors)?;
+    let udc = default_udc().context("cannot get UDC")?;
 
     let mut config = Config::new("config");
     if cfg.max_power_ma > 500 {
@@ -98,7 +87,7 @@ pub fn reg(funcs: Vec<Handle>, cfg: &BarpiConfig) -> RegGadget {
     )
     .with_config(config)
     .bind(&udc)
-    .expect("cannot bind to UDC");
+    .context("cannot bind to UDC")?;
 
     println!(
         "bound USB gadget {} at {} to {}",
@@ -108,8 +97,9 @@ pub fn reg(funcs: Vec<Handle>, cfg: &BarpiConfig) -> RegGadget {
     );
 
     sleep(Duration::from_secs(3));
+    functions.log_serials();
 
-    reg
+    Ok((reg, functions))
 }
 
 pub fn unreg(mut reg: RegGadget) -> std::io::Result<bool> {
@@ -123,124 +113,545 @@ pub fn unreg(mut reg: RegGadget) -> std::io::Result<bool> {
     }
 }
 
-pub fn get_dev(prefix: &str, major: libc::c_uint, minor: libc::c_uint) -> anyhow::Result<PathBuf> {
-    for entry in glob::glob(&format!("/dev/{prefix}*")).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                let dev = std::fs::metadata(&path)
-                    .expect("Failed to read metadata")
-                    .st_rdev();
-                if dev == libc::makedev(major, minor) {
-                    return Ok(path);
-                }
-            }
-            Err(e) => return Err(e)?,
-        }
+fn get_tls_options(cfg: &BarpiConfig) -> anyhow::Result<Option<TlsOptions>> {
+    if let Some(fingerprint) = &cfg.tls_fingerprint {
+        let fingerprint: Fingerprint = fingerprint.parse()?;
+        return Ok(Some(TlsOptions::with_fingerprint(fingerprint)));
     }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        format!("Device {major}:{minor} not found"),
-    ))?
+    if let Some(ca) = &cfg.tls_ca {
+        let pem = std::fs::read(ca)?;
+        // Verified against the name of the first server
+        let server = cfg.server.first().map_or("", String::as_str);
+        let host = server
+            .rsplit_once(':')
+            .map(|(host, _)| host)
+            .unwrap_or(server);
+        return Ok(Some(TlsOptions::with_ca_bundle(pem, host)));
+    }
+    Ok(None)
 }
 
-pub fn get_dev_for_hid(hid: &Hid) -> anyhow::Result<PathBuf> {
-    let (major, minor) = hid.device()?;
-    get_dev("hid", major, minor)
+#[cfg(feature = "mqtt")]
+fn start_mqtt(
+    cfg: &BarpiConfig,
+    states: watch::Receiver<watch::Receiver<ConnectionState>>,
+) -> anyhow::Result<()> {
+    let Some((host, port)) = cfg.mqtt_broker()? else {
+        return Ok(());
+    };
+    let credentials = cfg
+        .mqtt_username
+        .clone()
+        .map(|username| (username, cfg.mqtt_password.clone().unwrap_or_default()));
+    let topic = cfg.mqtt_topic();
+    info!("Publishing the state to {}:{} under {}", host, port, topic);
+    mqtt::spawn_publisher(
+        host,
+        port,
+        credentials,
+        topic,
+        format!("barpi-{}", cfg.screen_name),
+        states,
+    );
+    Ok(())
 }
 
-fn get_hid_func(report_type: ReportType) -> (Hid, Handle) {
-    let (report_len, descriptor) = SynergyHid::get_report_descriptor(report_type);
-    let mut builder = Hid::builder();
-    builder.protocol = 1;
-    builder.sub_class = 1;
-    builder.report_len = report_len;
-    builder.report_desc = descriptor.to_vec();
-    let (hid, handle) = builder.build();
-    (hid, handle)
+#[cfg(not(feature = "mqtt"))]
+fn start_mqtt(
+    cfg: &BarpiConfig,
+    _states: watch::Receiver<watch::Receiver<ConnectionState>>,
+) -> anyhow::Result<()> {
+    if cfg.mqtt_broker.is_some() {
+        warn!("mqtt_broker is ignored, barpi is built without the mqtt feature");
+    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+/// Opens the status indicator and shows the state of the clients on it, the actuator notifies
+/// it of the reports written
+#[cfg(feature = "indicator")]
+fn start_indicator(
+    cfg: &BarpiConfig,
+    client: &mut client::BarpiActuator,
+    states: watch::Receiver<watch::Receiver<ConnectionState>>,
+    servers: watch::Receiver<ServerList>,
+) -> anyhow::Result<()> {
+    let Some(config) = cfg.indicator()? else {
+        return Ok(());
+    };
+    // The KVM works without it, e.g. when the display isn't plugged in
+    let mut indicator = match indicator::open(&config) {
+        Ok(indicator) => indicator,
+        Err(e) => {
+            warn!("Cannot open the status indicator {:?}: {:?}", config, e);
+            return Ok(());
+        }
+    };
+    info!("Showing the state with {:?}", config);
+    let activity = Arc::new(Notify::new());
+    client.set_activity(Some(activity.clone()));
+    tokio::spawn(async move {
+        indicator::show_status(&mut *indicator, states, servers, activity).await;
+    });
+    Ok(())
+}
 
-    let mut args = Args::parse();
+#[cfg(not(feature = "indicator"))]
+fn start_indicator(
+    cfg: &BarpiConfig,
+    _client: &mut client::BarpiActuator,
+    _states: watch::Receiver<watch::Receiver<ConnectionState>>,
+    _servers: watch::Receiver<ServerList>,
+) -> anyhow::Result<()> {
+    if cfg.indicator.is_some() {
+        warn!("indicator is ignored, barpi is built without the indicator feature");
+    }
+    Ok(())
+}
 
-    let cfg = if let Ok(f) = File::open(&args.config_path) {
-        // Parse config with serde
-        match serde_yaml::from_reader::<_, <BarpiConfig as ClapSerde>::Opt>(BufReader::new(f)) {
-            // merge config already parsed from clap
-            Ok(config) => BarpiConfig::from(config).merge(&mut args.config),
-            Err(err) => panic!("Error in configuration file:\n{}", err),
-        }
+/// Registers the Bluetooth HID device of `descriptors`, the reports of the actuator returned
+/// go to the host once it connects
+#[cfg(feature = "bluetooth")]
+async fn start_bluetooth(
+    cfg: &BarpiConfig,
+    hid: SynergyHid,
+    descriptors: &DescriptorConfig,
+    token: CancellationToken,
+) -> anyhow::Result<client::BarpiActuator> {
+    let hosts = cfg
+        .bluetooth_hosts()?
+        .into_iter()
+        .map(bluer::Address::new)
+        .collect();
+    let bluetooth = bluetooth::BluetoothHid::register(
+        &cfg.usb_product,
+        descriptors,
+        hosts,
+        Duration::from_secs(cfg.bluetooth_pairing_window),
+    )
+    .await?;
+    let sink = |role: HidRole| bluetooth.sink(role.report_type(descriptors.mouse));
+    let consumer = if descriptors.consumer {
+        sink(HidRole::Consumer)
     } else {
-        // If there is not config file return only config parsed from clap
-        BarpiConfig::from(&mut args.config)
+        Sink::discard()
     };
+    let mut client = client::BarpiActuator::with_sinks(
+        hid,
+        sink(HidRole::Keyboard),
+        sink(HidRole::Mouse),
+        consumer,
+        token.clone(),
+    );
+    if descriptors.gamepad {
+        client.set_gamepad(sink(HidRole::Gamepad), token);
+    }
+    bluetooth.serve(client.led_reports());
+    Ok(client)
+}
 
-    usb_gadget::remove_all().expect("cannot remove all gadgets");
+#[cfg(not(feature = "bluetooth"))]
+async fn start_bluetooth(
+    _cfg: &BarpiConfig,
+    _hid: SynergyHid,
+    _descriptors: &DescriptorConfig,
+    _token: CancellationToken,
+) -> anyhow::Result<client::BarpiActuator> {
+    anyhow::bail!("transport is bluetooth, but barpi is built without the bluetooth feature")
+}
 
-    let (keyboard, keyboard_func) = get_hid_func(ReportType::Keyboard);
-    let (mouse, mouse_func) = get_hid_func(ReportType::Mouse);
-    let (consumer, consumer_func) = get_hid_func(ReportType::Consumer);
+fn get_keymap(cfg: &BarpiConfig) -> anyhow::Result<KeymapOverride> {
+    match &cfg.keymap {
+        Some(path) => {
+            let keymap = serde_yaml::from_reader(BufReader::new(File::open(path)?))?;
+            info!("Keymap loaded from {}", path.display());
+            Ok(keymap)
+        }
+        None => Ok(KeymapOverride::default()),
+    }
+}
 
-    let reg = reg(vec![keyboard_func, mouse_func, consumer_func], &cfg);
+fn get_macros(cfg: &BarpiConfig) -> anyhow::Result<MacroTable> {
+    match &cfg.macros {
+        Some(path) => {
+            let macros = serde_yaml::from_reader(BufReader::new(File::open(path)?))?;
+            info!("Macros loaded from {}", path.display());
+            Ok(macros)
+        }
+        None => Ok(MacroTable::default()),
+    }
+}
+
+/// Device nodes of the HID functions, opened by `register_gadget`
+struct DeviceFiles {
+    keyboard: File,
+    mouse: File,
+    consumer: Option<File>,
+    // The keyboard device opened again for reading
+    leds: File,
+    gamepad: Option<File>,
+}
+
+/// Registers the gadget of the profile and opens the keyboard and mouse devices for writing,
+/// and the keyboard device again for reading. The consumer control and gamepad devices are
+/// opened too if `descriptors` has them. Devices merged into one function open its device
+/// node each.
+fn register_gadget(
+    cfg: &BarpiConfig,
+    profile: GadgetProfile,
+    descriptors: &DescriptorConfig,
+) -> anyhow::Result<(RegGadget, DeviceFiles)> {
+    usb_gadget::remove_all().context("cannot remove all gadgets")?;
+
+    let (reg, functions) = reg(profile, cfg, descriptors)?;
+    let keyboard = functions.hid(HidRole::Keyboard)?;
+    let mouse = functions.hid(HidRole::Mouse)?;
 
     debug!(
         "HID keyboard device {:?} at {}",
         keyboard.device()?,
         keyboard.status().path().unwrap().display()
     );
-    let keyboard_path = get_dev_for_hid(&keyboard)?;
-    debug!("Dev file at {:?}", keyboard_path);
+    let fk = resolve_hidg_device(keyboard, DEVICE_TIMEOUT)?;
+    // The keyboard device is also read for the LED output reports, that read blocks
+    let fl = resolve_hidg_device_with(keyboard, OpenOptions::new().read(true), DEVICE_TIMEOUT)?;
 
     debug!(
         "HID mouse device {:?} at {}",
         mouse.device()?,
         mouse.status().path().unwrap().display()
     );
-    let mouse_path = get_dev_for_hid(&mouse)?;
-    debug!("Dev file at {:?}", mouse_path);
+    let fm = resolve_hidg_device(mouse, DEVICE_TIMEOUT)?;
+
+    let fc = if descriptors.consumer {
+        let consumer = functions.hid(HidRole::Consumer)?;
+        debug!(
+            "HID consumer control device {:?} at {}",
+            consumer.device()?,
+            consumer.status().path().unwrap().display()
+        );
+        Some(resolve_hidg_device(consumer, DEVICE_TIMEOUT)?)
+    } else {
+        None
+    };
 
-    debug!(
-        "HID consumer control device {:?} at {}",
-        consumer.device()?,
-        consumer.status().path().unwrap().display()
-    );
-    let consumer_path = get_dev_for_hid(&consumer)?;
-    debug!("Dev file at {:?}", consumer_path);
+    let fg = if descriptors.gamepad {
+        let gamepad = functions.hid(HidRole::Gamepad)?;
+        debug!(
+            "HID gamepad device {:?} at {}",
+            gamepad.device()?,
+            gamepad.status().path().unwrap().display()
+        );
+        Some(resolve_hidg_device(gamepad, DEVICE_TIMEOUT)?)
+    } else {
+        None
+    };
+    let files = DeviceFiles {
+        keyboard: fk,
+        mouse: fm,
+        consumer: fc,
+        leds: fl,
+        gamepad: fg,
+    };
+    Ok((reg, files))
+}
+
+/// Reports are dumped as hex to `path`, or logged if there is none
+fn dry_run_writer(
+    name: &'static str,
+    path: &Option<PathBuf>,
+) -> anyhow::Result<Box<dyn Write + Send>> {
+    let out: Option<Box<dyn Write + Send>> = match path {
+        Some(path) => Some(Box::new(File::create(path)?)),
+        None => None,
+    };
+    Ok(Box::new(client::HexDump::new(name, out)))
+}
 
-    let fk = std::fs::File::create(keyboard_path)?;
-    let fm = std::fs::File::create(mouse_path)?;
-    let fc = std::fs::File::create(consumer_path)?;
+// The hook is also handed to the actuator, to count the duplicate reports it skips
+fn build_client(
+    cfg: &BarpiConfig,
+    stats: &Arc<ClientStats>,
+) -> anyhow::Result<(Client, Option<Arc<dyn Metrics>>)> {
+    let mut builder = Client::builder()
+        .servers(&cfg.server)
+        .screen_name(&cfg.screen_name)
+        .reconnect(true)
+        .coalesce_moves(cfg.coalesce_mouse_moves)
+        .strict_enter_gating(cfg.strict_enter_gating)
+        .edge_margin(cfg.edge_margin)
+        .backoff(Backoff {
+            max: Duration::from_secs(cfg.max_reconnect_delay),
+            ..Default::default()
+        })
+        .on_status(|status| match status {
+            ClientStatus::UnknownScreenName(name) => error!(
+                "The Barrier server has no screen named {:?}, please add it to the screens of the server configuration or change screen_name",
+                name
+            ),
+            status => info!("Connection status: {:?}", status),
+        });
+    let mut metrics: Option<Arc<dyn Metrics>> = None;
+    if cfg.metrics_interval > 0 {
+        let log = Arc::new(LogMetrics::new(Duration::from_secs(cfg.metrics_interval)));
+        builder = builder.metrics((log.clone(), stats.clone()));
+        metrics = Some(log);
+    } else {
+        builder = builder.metrics(stats.clone());
+    }
+    match cfg.bind()? {
+        Some(Bind::Address(addr)) => builder = builder.local_address(addr),
+        Some(Bind::Interface(interface)) => builder = builder.bind_interface(interface),
+        None => {}
+    }
+    if let Some(tls) = get_tls_options(cfg)? {
+        builder = builder.tls(tls);
+    }
+    Ok((builder.build()?, metrics))
+}
+
+async fn run_client<A: Actuator + Send>(
+    barrier: &Client,
+    listener: Option<&TcpListener>,
+    client: &mut A,
+    cfg: &BarpiConfig,
+) {
+    if let Some(listener) = listener {
+        if let Err(e) = serve_actuator(listener, client).await {
+            error!("Cannot accept bridge connections, error: {:?}", e);
+        }
+        return;
+    }
+    match barrier.run(client).await {
+        Ok(_) => {}
+        Err(ConnectionError::FingerprintMismatch(fingerprint)) => {
+            error!(
+                "Server certificate fingerprint {} does not match the configured one, please check the Barrier server",
+                fingerprint
+            );
+        }
+        Err(ConnectionError::Busy) => {
+            error!(
+                "Another client is connected as {}, please use a different screen name",
+                cfg.screen_name
+            );
+        }
+        Err(ConnectionError::IncompatibleVersion { major, minor }) => {
+            error!(
+                "Server protocol version {}.{} is not supported, please upgrade the Barrier server",
+                major, minor
+            );
+        }
+        Err(e) if e.is_fatal() => {
+            error!("Giving up connecting to the server, error: {:?}", e);
+        }
+        Err(e) => {
+            error!("Cannot connect to the server, error: {:?}", e);
+        }
+    }
+}
+fn get_screensaver_action(cfg: &BarpiConfig) -> anyhow::Result<client::ScreenSaverAction> {
+    Ok(client::ScreenSaverAction {
+        keys: cfg.screensaver_keys()?,
+        pipe: cfg.screensaver_pipe.clone(),
+    })
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let mut args = Args::parse();
+    let command = args.command.take();
+    let cfg = BarpiConfig::load(args)?;
+    cfg.validate().map_err(invalid_config)?;
+    if let Some(command) = &command {
+        command.validate().map_err(invalid_config)?;
+    }
+
+    let stats = Arc::new(ClientStats::new());
+    let (mut barrier, metrics) = build_client(&cfg, &stats)?;
+    let mut keymap = get_keymap(&cfg)?;
+    let mut macros = get_macros(&cfg)?;
+    let mut server_platform = cfg.server_platform()?;
+    let mut unicode_input = cfg.unicode_input()?;
+
+    let mouse_mode = if cfg.relative_mouse {
+        MouseMode::Relative
+    } else {
+        MouseMode::Absolute
+    };
+    let keyboard_mode = if cfg.nkro {
+        KeyboardMode::Nkro
+    } else {
+        KeyboardMode::Boot
+    };
+    let (screen_width, screen_height) = display::screen_size(&cfg);
+    let (flip_x, flip_y) = cfg.wheel_flip();
+    let hid = SynergyHid::with_keymap(
+        screen_width,
+        screen_height,
+        cfg.flip_mouse_wheel,
+        mouse_mode,
+        keymap.clone(),
+    )
+    .with_keyboard_mode(keyboard_mode)
+    .with_server_platform(server_platform)
+    .with_unicode_input(unicode_input)
+    .with_lock_sync(cfg.sync_lock_keys)
+    .with_macros(macros.clone())
+    .with_button_map(ButtonMap::from(cfg.button_map.clone()))
+    .with_stuck_key_timeouts(cfg.stuck_key_timeouts())
+    .with_wheel_flip(flip_x, flip_y)
+    .with_scroll_speed(cfg.scroll_speed, cfg.scroll_speed);
+
+    // Checked in dry run mode too, so a broken profile doesn't wait for the next deployment
+    let mut profile = GadgetProfile::from_config(&cfg.functions);
+    if cfg.gamepad {
+        profile = profile.with_gamepad();
+    }
+    profile.validate()?;
+    let descriptors = DescriptorConfig {
+        use_report_ids: cfg.hid_report_ids,
+        merge_into_single_interface: cfg.hid_single_interface,
+        keyboard: keyboard_mode,
+        mouse: mouse_mode,
+        consumer: cfg.consumer_control,
+        gamepad: profile.has_role(HidRole::Gamepad),
+    };
+    let transport = cfg.transport()?;
 
     let token = CancellationToken::new();
 
     let cloned_token: CancellationToken = token.clone();
-    let mut client = client::BarpiActuator::new(
-        cfg.screen_width,
-        cfg.screen_width,
-        cfg.flip_mouse_wheel,
-        fk,
-        fm,
-        fc,
-        cloned_token,
-    );
+    let (reg, mut client) = if cfg.dry_run {
+        info!("Dry run, HID reports are dumped instead of written to a USB gadget");
+        let sink = |role: HidRole, name, path| {
+            let id = descriptors.report_id(role.report_type(mouse_mode));
+            anyhow::Ok(Sink::file(dry_run_writer(name, path)?, id))
+        };
+        let consumer = if descriptors.consumer {
+            sink(HidRole::Consumer, "Consumer", &cfg.consumer_out)?
+        } else {
+            Sink::discard()
+        };
+        let mut client = client::BarpiActuator::with_sinks(
+            hid,
+            sink(HidRole::Keyboard, "Keyboard", &cfg.keyboard_out)?,
+            sink(HidRole::Mouse, "Mouse", &cfg.mouse_out)?,
+            consumer,
+            cloned_token.clone(),
+        );
+        if descriptors.gamepad {
+            client.set_gamepad(
+                sink(HidRole::Gamepad, "Gamepad", &cfg.gamepad_out)?,
+                cloned_token,
+            );
+        }
+        (None, client)
+    } else if transport == Transport::Bluetooth {
+        let client = start_bluetooth(&cfg, hid, &descriptors, cloned_token).await?;
+        (None, client)
+    } else {
+        let (reg, files) = register_gadget(&cfg, profile, &descriptors)?;
+        let sink = |file: File, role: HidRole| {
+            Sink::file(
+                Box::new(file),
+                descriptors.report_id(role.report_type(mouse_mode)),
+            )
+        };
+        let mut client = client::BarpiActuator::new(
+            hid,
+            sink(files.keyboard, HidRole::Keyboard),
+            sink(files.mouse, HidRole::Mouse),
+            files
+                .consumer
+                .map_or_else(Sink::discard, |fc| sink(fc, HidRole::Consumer)),
+            files.leds,
+            descriptors.report_id(ReportType::Keyboard),
+            cloned_token.clone(),
+        );
+        if let Some(fg) = files.gamepad {
+            client.set_gamepad(sink(fg, HidRole::Gamepad), cloned_token);
+        }
+        (Some(reg), client)
+    };
+    client.set_screensaver_action(get_screensaver_action(&cfg)?);
+    client.set_edge_pipe(cfg.edge_pipe.clone());
+    client.set_metrics(metrics);
+    client.set_type_delay(Duration::from_millis(cfg.type_delay));
+
+    // The state of the client built after each reload and its servers, published to the MQTT
+    // broker and shown by the status indicator
+    let (state_tx, state_rx) = watch::channel(barrier.state());
+    let (servers_tx, servers_rx) = watch::channel(barrier.servers().clone());
+    start_indicator(&cfg, &mut client, state_rx.clone(), servers_rx)?;
+    let leds = client.led_reports();
+    let actuator: Shared = Arc::new(Mutex::new(ClickAssist::new(client, cfg.click_assist())));
+    tokio::spawn(client::apply_led_reports(actuator.clone(), leds));
+
+    if let Some(Command::Replay { file, speed }) = command {
+        info!("Replaying {} at {}x speed", file.display(), speed);
+        if let Err(e) = replay(&file, &mut SharedActuator(actuator.clone()), speed).await {
+            error!("Cannot replay {}, error: {:?}", file.display(), e);
+        }
+        return shutdown(&actuator, None, reg);
+    }
 
-    let main_task = async move {
-        loop {
-            match start(&cfg.server, &cfg.screen_name, &mut client).await {
-                Ok(_) => {}
-                Err(e) => {
-                    warn!(
-                        "Disconnected from the server, error: {:?}, reconnecting in 1 second...",
-                        e
-                    );
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
-            }
+    // Recorded before the click assist, a replay goes through it again
+    let recording = match &cfg.record {
+        Some(path) => {
+            info!("Recording the events to {}", path.display());
+            Some(RecordingActuator::create(
+                SharedActuator(actuator.clone()),
+                path,
+            )?)
         }
+        None => None,
     };
 
+    // The events come from barpi-bridge instead of the Barrier server
+    let listener = match &cfg.listen {
+        Some(addr) => {
+            info!("Waiting for barpi-bridge on {}", addr);
+            Some(TcpListener::bind(addr).await?)
+        }
+        None => None,
+    };
+
+    // Commands from the control socket lock the actuator in between the events
+    let control = Arc::new(Control::new(actuator.clone(), &barrier, stats.clone()));
+    let control_socket = cfg.control_socket.clone();
+    if let Some(path) = &control_socket {
+        tokio::spawn(serve_control(bind_control(path)?, control.clone()));
+    }
+
+    start_mqtt(&cfg, state_rx)?;
+
+    // SIGHUP reloads the config, the connection picks it up through the watch channel and
+    // only reconnects if the connection settings changed
+    let (cfg_tx, mut cfg_rx) = watch::channel(cfg);
+
+    // The screen size follows the display of the target, the server is sent the new one
+    let (sender_tx, sender_rx) = watch::channel(barrier.sender());
+    if cfg_rx.borrow().auto_screen_size {
+        let hotplug = Arc::new(Notify::new());
+        match display::watch_hotplug(hotplug.clone()) {
+            Ok(()) => {
+                tokio::spawn(display::follow_display(
+                    actuator.clone(),
+                    hotplug,
+                    cfg_rx.clone(),
+                    sender_rx,
+                ));
+            }
+            Err(e) => warn!(
+                "Cannot watch for displays plugged in, the screen size is only read on start and reload: {:?}",
+                e
+            ),
+        }
+    }
+
     let cloned_token: CancellationToken = token.clone();
     tokio::task::spawn(async move {
         let mut sigterm = signal(SignalKind::terminate()).unwrap();
@@ -250,25 +661,180 @@ async fn main() -> anyhow::Result<()> {
             select! {
                 _ = sigterm.recv() => info!("Recieve SIGTERM, shutting down..."),
                 _ = sigint.recv() => info!("Recieve SIGINT, shutting down..."),
-                _ = sighup.recv() => info!("Recieve SIGHUP, shutting down..."),
+                _ = sighup.recv() => {
+                    info!("Recieve SIGHUP, reloading config...");
+                    let reloaded = cfg_tx.borrow().reload();
+                    match reloaded {
+                        Ok(cfg) => {
+                            cfg_tx.send_if_modified(|current| {
+                                let modified = *current != cfg;
+                                *current = cfg;
+                                modified
+                            });
+                        }
+                        Err(e) => error!("Cannot reload config, keeping the current one: {:?}", e),
+                    }
+                    continue;
+                }
             };
             cloned_token.cancel();
         }
     });
 
+    // Ticks even when disabled, a reload can turn the timeouts on
+    tokio::spawn(client::release_stuck_keys(actuator.clone()));
+
+    let client = actuator.clone();
     let join_handle = tokio::spawn(async move {
-        select! {
-            _ = token.cancelled() => (),
-            _ = main_task => (),
+        let mut cfg = cfg_rx.borrow_and_update().clone();
+        let mut shared = SharedActuator(client.clone());
+        let mut recording = recording;
+        loop {
+            // The session keeps going through the reloads that don't change the connection
+            let session_cfg = cfg.clone();
+            let reconnect = {
+                let session = async {
+                    match &mut recording {
+                        Some(recording) => {
+                            run_client(&barrier, listener.as_ref(), recording, &session_cfg).await
+                        }
+                        None => {
+                            run_client(&barrier, listener.as_ref(), &mut shared, &session_cfg).await
+                        }
+                    }
+                };
+                tokio::pin!(session);
+                loop {
+                    select! {
+                        _ = token.cancelled() => break false,
+                        _ = &mut session => break false,
+                        _ = control.reconnect_requested() => {
+                            info!("Reconnecting to {} on request", cfg.server.join(", "));
+                            // The session is dropped without disconnecting the actuator
+                            client.lock().unwrap().get_mut().release_all();
+                            break true;
+                        }
+                        Ok(()) = cfg_rx.changed() => {
+                            let new_cfg = cfg_rx.borrow_and_update().clone();
+                            let size = display::screen_size(&cfg);
+                            match get_keymap(&new_cfg) {
+                                Ok(new_keymap) => keymap = new_keymap,
+                                Err(e) => error!("Cannot load keymap, keeping the current one: {:?}", e),
+                            }
+                            match get_macros(&new_cfg) {
+                                Ok(new_macros) => macros = new_macros,
+                                Err(e) => error!("Cannot load macros, keeping the current ones: {:?}", e),
+                            }
+                            match new_cfg.server_platform() {
+                                Ok(platform) => server_platform = platform,
+                                Err(e) => error!("{:?}, keeping the current server platform", e),
+                            }
+                            match new_cfg.unicode_input() {
+                                Ok(input) => unicode_input = input,
+                                Err(e) => error!("{:?}, keeping the current unicode input", e),
+                            }
+                            let (screen_width, screen_height) = display::screen_size(&new_cfg);
+                            let (flip_x, flip_y) = new_cfg.wheel_flip();
+                            apply_config(
+                                &client,
+                                &new_cfg,
+                                SynergyHid::with_keymap(
+                                    screen_width,
+                                    screen_height,
+                                    new_cfg.flip_mouse_wheel,
+                                    mouse_mode,
+                                    keymap.clone(),
+                                )
+                                .with_keyboard_mode(keyboard_mode)
+                                .with_server_platform(server_platform)
+                                .with_unicode_input(unicode_input)
+                                .with_lock_sync(new_cfg.sync_lock_keys)
+                                .with_macros(macros.clone())
+                                .with_button_map(ButtonMap::from(new_cfg.button_map.clone()))
+                                .with_stuck_key_timeouts(new_cfg.stuck_key_timeouts())
+                                .with_wheel_flip(flip_x, flip_y)
+                                .with_scroll_speed(new_cfg.scroll_speed, new_cfg.scroll_speed),
+                            );
+                            let connection_changed = cfg.connection_changed(&new_cfg);
+                            cfg = new_cfg;
+                            if connection_changed {
+                                info!("Config reloaded, reconnecting to {}", cfg.server.join(", "));
+                                break true;
+                            }
+                            info!("Config reloaded");
+                            if (screen_width, screen_height) != size {
+                                if let Err(e) = barrier.sender().screen_changed().await {
+                                    warn!("Cannot send the new screen size: {:?}", e);
+                                }
+                            }
+                        }
+                    }
+                }
+            };
+            if !reconnect {
+                break;
+            }
+            // Also applies the options of the client that don't need a reconnection
+            match build_client(&cfg, &stats) {
+                Ok((new_barrier, metrics)) => {
+                    barrier = new_barrier;
+                    control.set_client(&barrier);
+                    state_tx.send_replace(barrier.state());
+                    servers_tx.send_replace(barrier.servers().clone());
+                    sender_tx.send_replace(barrier.sender());
+                    client.lock().unwrap().get_mut().set_metrics(metrics);
+                }
+                Err(e) => error!(
+                    "Cannot apply connection settings, keeping the current ones: {:?}",
+                    e
+                ),
+            }
         }
     });
 
-    match join_handle.await {
-        Ok(_) => {}
-        Err(e) => {
-            warn!("Error: {:?}", e);
-        }
+    if let Err(e) = join_handle.await {
+        warn!("Error: {:?}", e);
+    }
+    shutdown(&actuator, control_socket, reg)
+}
+
+/// Applies a reloaded config to the running actuator, the keys held are released
+fn apply_config(actuator: &Shared, cfg: &BarpiConfig, hid: SynergyHid) {
+    let mut actuator = actuator.lock().unwrap();
+    let client = actuator.get_mut();
+    client.reconfigure(hid);
+    match get_screensaver_action(cfg) {
+        Ok(action) => client.set_screensaver_action(action),
+        Err(e) => error!(
+            "Cannot apply screen saver settings, keeping the current ones: {:?}",
+            e
+        ),
+    }
+    client.set_edge_pipe(cfg.edge_pipe.clone());
+    client.set_type_delay(Duration::from_millis(cfg.type_delay));
+    actuator.set_click_options(cfg.click_assist());
+}
+
+/// Releases what is held on the host, then removes the control socket and the gadget
+fn shutdown(
+    actuator: &Shared,
+    control_socket: Option<PathBuf>,
+    reg: Option<RegGadget>,
+) -> anyhow::Result<()> {
+    // Must be done before the gadget is unregistered, after the text being typed if any
+    if !actuator
+        .lock()
+        .unwrap()
+        .get_mut()
+        .shutdown(SHUTDOWN_TIMEOUT)
+    {
+        warn!("Timed out clearing HID reports");
+    }
+    if let Some(path) = control_socket {
+        std::fs::remove_file(path).ok();
+    }
+    if let Some(reg) = reg {
+        unreg(reg)?;
     }
-    unreg(reg)?;
     Ok(())
 }
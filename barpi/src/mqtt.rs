@@ -0,0 +1,324 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use barrier_client::ConnectionState;
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use tokio::{
+    select,
+    sync::{watch, Notify},
+    time::sleep,
+};
+
+use crate::control::state_name;
+
+/// Messages waiting for the broker, the oldest are dropped past this
+const OUTBOX_LEN: usize = 32;
+
+/// Delay before connecting to the broker again, doubled up to `MAX_RETRY_DELAY`
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Published by the broker when the connection to barpi is lost
+const OFFLINE: &str = r#"{"state":"offline"}"#;
+
+/// A retained message, `topic` is under the prefix
+#[derive(Debug, PartialEq, Eq)]
+pub struct Message {
+    pub topic: &'static str,
+    pub payload: String,
+}
+
+#[derive(Serialize)]
+struct StatePayload<'a> {
+    state: &'static str,
+    /// Why the connection was closed, in the disconnected state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct EventPayload {
+    event: &'static str,
+    /// Seconds since the Unix epoch
+    at: u64,
+}
+
+/// The messages for a change from `previous` to `state` at `at`, every topic but
+/// `last_event` if `previous` is `None`, e.g. after connecting to the broker
+pub fn messages(
+    previous: Option<&ConnectionState>,
+    state: &ConnectionState,
+    at: u64,
+) -> Vec<Message> {
+    let entered = |state: &ConnectionState| *state == ConnectionState::Entered;
+    let mut messages = vec![];
+    if previous != Some(state) {
+        let reason = match state {
+            ConnectionState::Disconnected { reason } => reason.as_deref(),
+            _ => None,
+        };
+        messages.push(Message {
+            topic: "state",
+            payload: serde_json::to_string(&StatePayload {
+                state: state_name(state),
+                reason,
+            })
+            .unwrap(),
+        });
+    }
+    if previous.map(entered) != Some(entered(state)) {
+        messages.push(Message {
+            topic: "entered",
+            payload: entered(state).to_string(),
+        });
+    }
+    let event = match state {
+        ConnectionState::Entered => Some("enter"),
+        ConnectionState::Left => Some("leave"),
+        _ => None,
+    };
+    if let (Some(previous), Some(event)) = (previous, event) {
+        if previous != state {
+            messages.push(Message {
+                topic: "last_event",
+                payload: serde_json::to_string(&EventPayload { event, at }).unwrap(),
+            });
+        }
+    }
+    messages
+}
+
+/// Messages waiting for the broker, the state changes never wait for it
+#[derive(Default)]
+struct Outbox {
+    messages: Mutex<VecDeque<Message>>,
+    pushed: Notify,
+}
+
+impl Outbox {
+    fn push(&self, messages: Vec<Message>) {
+        let mut queue = self.messages.lock().unwrap();
+        for message in messages {
+            if queue.len() == OUTBOX_LEN {
+                let dropped = queue.pop_front();
+                debug!("MQTT outbox full, dropped {:?}", dropped);
+            }
+            queue.push_back(message);
+        }
+        self.pushed.notify_one();
+    }
+
+    async fn pop(&self) -> Message {
+        loop {
+            if let Some(message) = self.messages.lock().unwrap().pop_front() {
+                return message;
+            }
+            self.pushed.notified().await;
+        }
+    }
+}
+
+/// Connects to the MQTT broker and publishes the state of the client watched by `states`,
+/// which changes when the config is reloaded. The connection to the broker is retried on
+/// its own, whatever the state of the connection to the server.
+pub fn spawn_publisher(
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    topic: String,
+    client_id: String,
+    states: watch::Receiver<watch::Receiver<ConnectionState>>,
+) {
+    let mut options = MqttOptions::new(client_id, host.clone(), port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        format!("{topic}/state"),
+        OFFLINE,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some((username, password)) = credentials {
+        options.set_credentials(username, password);
+    }
+    let (client, mut event_loop) = AsyncClient::new(options, OUTBOX_LEN);
+    let outbox = Arc::new(Outbox::default());
+    let connected = Arc::new(Notify::new());
+
+    // Drives the connection to the broker, the requests of the client go through it
+    let on_connect = connected.clone();
+    tokio::spawn(async move {
+        let mut delay = RETRY_DELAY;
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to the MQTT broker {}:{}", host, port);
+                    delay = RETRY_DELAY;
+                    on_connect.notify_one();
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "MQTT broker {}:{}: {}, retrying in {:?}",
+                        host, port, e, delay
+                    );
+                    sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    });
+
+    let publisher = outbox.clone();
+    tokio::spawn(async move {
+        loop {
+            let message = publisher.pop().await;
+            let topic = format!("{topic}/{}", message.topic);
+            if let Err(e) = client
+                .publish(topic, QoS::AtLeastOnce, true, message.payload)
+                .await
+            {
+                warn!("Cannot publish to the MQTT broker: {}", e);
+            }
+        }
+    });
+
+    tokio::spawn(watch_state(states, connected, outbox));
+}
+
+// Queues the messages for each state change, and all of them on connecting to the broker as
+// the last will may have replaced the state
+async fn watch_state(
+    mut states: watch::Receiver<watch::Receiver<ConnectionState>>,
+    connected: Arc<Notify>,
+    outbox: Arc<Outbox>,
+) {
+    let mut state = states.borrow_and_update().clone();
+    let mut current = state.borrow_and_update().clone();
+    loop {
+        let previous = select! {
+            _ = connected.notified() => None,
+            changed = state.changed() => {
+                if changed.is_err() {
+                    // The client was dropped, wait for the next one
+                    if states.changed().await.is_err() {
+                        return;
+                    }
+                    state = states.borrow_and_update().clone();
+                }
+                Some(current.clone())
+            }
+            changed = states.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                state = states.borrow_and_update().clone();
+                Some(current.clone())
+            }
+        };
+        current = state.borrow_and_update().clone();
+        outbox.push(messages(previous.as_ref(), &current, unix_time()));
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use barrier_client::ConnectionState;
+
+    use super::{messages, Message, Outbox, OUTBOX_LEN};
+
+    fn message(topic: &'static str, payload: &str) -> Message {
+        Message {
+            topic,
+            payload: payload.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let disconnected = ConnectionState::Disconnected {
+            reason: Some("connection reset".to_string()),
+        };
+        // Everything but the last event on connecting to the broker
+        assert_eq!(
+            messages(None, &ConnectionState::Entered, 100),
+            [
+                message("state", r#"{"state":"entered"}"#),
+                message("entered", "true"),
+            ]
+        );
+        assert_eq!(
+            messages(None, &disconnected, 100),
+            [
+                message(
+                    "state",
+                    r#"{"state":"disconnected","reason":"connection reset"}"#
+                ),
+                message("entered", "false"),
+            ]
+        );
+
+        assert_eq!(
+            messages(
+                Some(&ConnectionState::Connected),
+                &ConnectionState::Entered,
+                100
+            ),
+            [
+                message("state", r#"{"state":"entered"}"#),
+                message("entered", "true"),
+                message("last_event", r#"{"event":"enter","at":100}"#),
+            ]
+        );
+        assert_eq!(
+            messages(Some(&ConnectionState::Entered), &ConnectionState::Left, 160),
+            [
+                message("state", r#"{"state":"left"}"#),
+                message("entered", "false"),
+                message("last_event", r#"{"event":"leave","at":160}"#),
+            ]
+        );
+        // Still out of focus
+        assert_eq!(
+            messages(Some(&ConnectionState::Left), &disconnected, 200),
+            [message(
+                "state",
+                r#"{"state":"disconnected","reason":"connection reset"}"#
+            )]
+        );
+        assert_eq!(
+            messages(
+                Some(&ConnectionState::Connected),
+                &ConnectionState::Connected,
+                200
+            ),
+            []
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let outbox = Outbox::default();
+        let states = (0..OUTBOX_LEN + 2)
+            .map(|n| message("state", &n.to_string()))
+            .collect();
+        outbox.push(states);
+        // The oldest are dropped
+        assert_eq!(outbox.pop().await, message("state", "2"));
+        for _ in 1..OUTBOX_LEN {
+            outbox.pop().await;
+        }
+        assert!(outbox.messages.lock().unwrap().is_empty());
+    }
+}
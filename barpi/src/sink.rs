@@ -0,0 +1,197 @@
+use std::io::{self, Write};
+
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+/// Where a [`HidWriter`](crate::writer::HidWriter) sends the reports of its device.
+///
+/// Whatever the transport, the errors are the ones of a hidg device file opened
+/// non-blocking: `WouldBlock` while the host isn't reading, `ESHUTDOWN` or `ENODEV` while
+/// it's detached. The writer retries those, any other error fails the device.
+pub trait ReportSink: Send {
+    /// Sends one whole report
+    fn send_report(&mut self, report: &[u8]) -> io::Result<()>;
+}
+
+/// The sinks of the `transport` config option, the writer and the actuator are the same
+/// for all of them
+pub enum Sink {
+    /// A hidg device file of the USB gadget, or the dump of a dry run
+    File(Box<dyn Write + Send>),
+    /// A hidg device file with report IDs, each report is written after the ID of its device
+    WithReportId(u8, Box<dyn Write + Send>),
+    /// A device of the Bluetooth HID connection, its reports go to the interrupt channel
+    #[cfg(feature = "bluetooth")]
+    Bluetooth(crate::bluetooth::Channel),
+    #[cfg(test)]
+    Memory(MemorySink),
+}
+
+impl Sink {
+    /// A file sink, the reports start with `report_id` if there is one
+    pub fn file(file: Box<dyn Write + Send>, report_id: Option<u8>) -> Self {
+        match report_id {
+            Some(id) => Sink::WithReportId(id, file),
+            None => Sink::File(file),
+        }
+    }
+
+    /// Drops the reports, for a device that isn't registered
+    pub fn discard() -> Self {
+        Sink::File(Box::new(io::sink()))
+    }
+}
+
+impl ReportSink for Sink {
+    fn send_report(&mut self, report: &[u8]) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.write_all(report),
+            // A single write, hidg takes each write as a whole report
+            Sink::WithReportId(id, file) => {
+                let mut frame = Vec::with_capacity(report.len() + 1);
+                frame.push(*id);
+                frame.extend_from_slice(report);
+                file.write_all(&frame)
+            }
+            #[cfg(feature = "bluetooth")]
+            Sink::Bluetooth(channel) => channel.send_report(report),
+            #[cfg(test)]
+            Sink::Memory(memory) => memory.send_report(report),
+        }
+    }
+}
+
+/// Keeps the reports, and rejects them like a detached host until `attach` is called
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    // Whether the host is attached and the reports it got
+    host: Arc<Mutex<(bool, Vec<Vec<u8>>)>>,
+}
+
+#[cfg(test)]
+impl MemorySink {
+    pub fn attached() -> Self {
+        let sink = Self::default();
+        sink.attach();
+        sink
+    }
+
+    pub fn attach(&self) {
+        self.host.lock().unwrap().0 = true;
+    }
+
+    pub fn detach(&self) {
+        self.host.lock().unwrap().0 = false;
+    }
+
+    pub fn reports(&self) -> Vec<Vec<u8>> {
+        self.host.lock().unwrap().1.clone()
+    }
+}
+
+#[cfg(test)]
+impl ReportSink for MemorySink {
+    fn send_report(&mut self, report: &[u8]) -> io::Result<()> {
+        let mut host = self.host.lock().unwrap();
+        if !host.0 {
+            return Err(io::Error::from_raw_os_error(libc::ESHUTDOWN));
+        }
+        host.1.push(report.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::{MemorySink, ReportSink, Sink};
+    use crate::writer::{DeviceState, HidWriter};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Keeps each write separately, a report must not be split
+    #[derive(Clone, Default)]
+    struct Writes(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn wait_attached(writer: &HidWriter) {
+        for _ in 0..100 {
+            if writer.state() == DeviceState::Attached {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("{} device still {:?}", writer.name(), writer.state());
+    }
+
+    #[test]
+    fn test_sinks() {
+        let file = Writes::default();
+        let memory = MemorySink::attached();
+        let with_id = Writes::default();
+        for mut sink in [
+            Sink::file(Box::new(file.clone()), None),
+            Sink::file(Box::new(with_id.clone()), Some(2)),
+            Sink::Memory(memory.clone()),
+            Sink::discard(),
+        ] {
+            sink.send_report(&[1, 2, 3]).unwrap();
+            sink.send_report(&[4]).unwrap();
+        }
+        assert_eq!(*file.0.lock().unwrap(), [vec![1, 2, 3], vec![4]]);
+        assert_eq!(*with_id.0.lock().unwrap(), [vec![2, 1, 2, 3], vec![2, 4]]);
+        assert_eq!(memory.reports(), [vec![1, 2, 3], vec![4]]);
+
+        memory.detach();
+        let e = Sink::Memory(memory.clone()).send_report(&[5]).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ESHUTDOWN));
+        assert_eq!(memory.reports().len(), 2);
+    }
+
+    #[test]
+    fn test_reconnect() {
+        // Not connected yet, e.g. the Bluetooth host didn't pair
+        let memory = MemorySink::default();
+        let token = CancellationToken::new();
+        let writer = HidWriter::new("test", Sink::Memory(memory.clone()), token.clone());
+        writer.write(&[1]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(writer.state(), DeviceState::Detached);
+
+        // The latest report is written once it connects
+        writer.write(&[2]);
+        memory.attach();
+        wait_attached(&writer);
+        writer.write(&[3]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(memory.reports(), [vec![2], vec![3]]);
+
+        // And again after it disconnects, without a new writer
+        memory.detach();
+        writer.write(&[4]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(writer.state(), DeviceState::Detached);
+        memory.attach();
+        wait_attached(&writer);
+        assert_eq!(memory.reports(), [vec![2], vec![3], vec![4]]);
+        assert!(!token.is_cancelled());
+    }
+}
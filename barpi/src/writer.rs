@@ -0,0 +1,505 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use tokio_util::sync::CancellationToken;
+
+use crate::sink::{ReportSink, Sink};
+
+/// Reports waiting to be written, the host reads a report per poll interval of 1-10ms
+const QUEUE_SIZE: usize = 64;
+
+/// Delay between attempts to write a report the device didn't accept
+const RETRY_INTERVAL: Duration = Duration::from_millis(2);
+
+/// A device that doesn't accept a report for this long is considered detached
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Delay between attempts to write to a detached device
+const DETACHED_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// State of a HID device as seen by its writer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceState {
+    Attached,
+    /// The host is suspended or unplugged, the latest report is written once it's back
+    Detached,
+    /// Writing failed for another reason, the token has been cancelled
+    Failed,
+}
+
+enum Message {
+    /// The report, its kind and the generation of the clear it came after
+    Report(Vec<u8>, Kind, u64),
+    Flush(mpsc::Sender<()>),
+}
+
+enum Kind {
+    /// Written even if the device is busy, e.g. a key press
+    Required,
+    /// Dropped if the device is busy, e.g. a relative move
+    Motion,
+    /// An absolute position, also dropped once the flag is set by the next report queued
+    /// being a position too
+    Position(Arc<AtomicBool>),
+}
+
+impl Kind {
+    fn droppable(&self) -> bool {
+        !matches!(self, Kind::Required)
+    }
+}
+
+/// Writes the reports of one device to its [`Sink`] on a dedicated thread, so a host not
+/// reading them doesn't block the client.
+///
+/// A hidg device file should be opened non-blocking. A report the device doesn't accept
+/// right away is retried, unless it's a mouse move that the next one supersedes. An
+/// absolute position queued right before another one isn't written at all, see
+/// `write_position`. The reports still queued when a clear is written are dropped, see
+/// `write_clear`.
+pub struct HidWriter {
+    name: &'static str,
+    tx: SyncSender<Message>,
+    state: Arc<Mutex<DeviceState>>,
+    // Generation of the last clear, the reports queued with an older one are stale
+    cleared: Arc<AtomicU64>,
+    // Flag of the last report queued if it's a position, set once another position follows
+    last_position: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl HidWriter {
+    pub fn new(name: &'static str, out: Sink, token: CancellationToken) -> Self {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let state = Arc::new(Mutex::new(DeviceState::Attached));
+        let cleared = Arc::new(AtomicU64::new(0));
+        let mut worker = Worker {
+            name,
+            out,
+            state: state.clone(),
+            cleared: cleared.clone(),
+            token,
+        };
+        std::thread::spawn(move || worker.run(rx));
+        Self {
+            name,
+            tx,
+            state,
+            cleared,
+            last_position: Mutex::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn state(&self) -> DeviceState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Queues a report that must be written, e.g. a key press
+    pub fn write(&self, report: &[u8]) {
+        self.send(report, Kind::Required)
+    }
+
+    /// Queues a report that can be dropped if the device is busy, e.g. a relative mouse move
+    pub fn write_motion(&self, report: &[u8]) {
+        self.send(report, Kind::Motion)
+    }
+
+    /// Queues an absolute mouse position, dropped like a motion if the device is busy, or if
+    /// it's still queued when the next position is. A report queued in between, e.g. a
+    /// click, keeps it.
+    pub fn write_position(&self, report: &[u8]) {
+        self.send(report, Kind::Position(Arc::new(AtomicBool::new(false))))
+    }
+
+    /// Queues a report clearing the device, of the `generation` of
+    /// [`SynergyHid::generation`](synergy_hid::SynergyHid::generation). The reports queued
+    /// before and not written yet are dropped, those queued after are written.
+    pub fn write_clear(&self, report: &[u8], generation: u64) {
+        self.cleared.fetch_max(generation, Relaxed);
+        self.send(report, Kind::Required)
+    }
+
+    fn send(&self, report: &[u8], kind: Kind) {
+        let generation = self.cleared.load(Relaxed);
+        let position = match &kind {
+            Kind::Position(superseded) => Some(superseded.clone()),
+            _ => None,
+        };
+        match self
+            .tx
+            .try_send(Message::Report(report.to_vec(), kind, generation))
+        {
+            Ok(_) => {
+                // Set after the new one is queued, the cursor always gets to the latest
+                let mut last = self.last_position.lock().unwrap();
+                if let (Some(previous), Some(_)) = (last.as_ref(), &position) {
+                    previous.store(true, Relaxed);
+                }
+                *last = position;
+            }
+            Err(TrySendError::Full(_)) => {
+                warn!("{} device is not keeping up, report dropped", self.name);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("{} writer stopped, report dropped", self.name);
+            }
+        }
+    }
+
+    /// Waits until the reports queued so far are written or dropped, returns `false` on timeout
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (done_tx, done_rx) = mpsc::channel();
+        let mut message = Message::Flush(done_tx);
+        // Wait for room in the queue, the writer may be stuck
+        loop {
+            match self.tx.try_send(message) {
+                Ok(_) => break,
+                Err(TrySendError::Full(m)) if Instant::now() < deadline => {
+                    message = m;
+                    std::thread::sleep(RETRY_INTERVAL);
+                }
+                Err(TrySendError::Full(_)) => return false,
+                Err(TrySendError::Disconnected(_)) => return true,
+            }
+        }
+        done_rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .is_ok()
+    }
+}
+
+enum Outcome {
+    Written,
+    Busy,
+    Detached,
+    Failed(io::Error),
+}
+
+struct Worker {
+    name: &'static str,
+    out: Sink,
+    state: Arc<Mutex<DeviceState>>,
+    cleared: Arc<AtomicU64>,
+    token: CancellationToken,
+}
+
+impl Worker {
+    fn run(&mut self, rx: Receiver<Message>) {
+        // Latest report not written yet because the device is detached, reports are
+        // the whole device state so the older ones don't matter
+        let mut pending: Option<Vec<u8>> = None;
+        let mut last_attempt = Instant::now();
+        loop {
+            let message = if pending.is_some() {
+                let retry_in = DETACHED_RETRY_INTERVAL.saturating_sub(last_attempt.elapsed());
+                match rx.recv_timeout(retry_in) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            } else {
+                match rx.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return,
+                }
+            };
+            match message {
+                Some(Message::Flush(done)) => {
+                    done.send(()).ok();
+                }
+                Some(Message::Report(report, ..)) if self.state() == DeviceState::Failed => {
+                    debug!("{} device failed, report dropped: {:?}", self.name, report);
+                }
+                // The clear queued after it releases everything anyway
+                Some(Message::Report(report, _, generation))
+                    if generation < self.cleared.load(Relaxed) =>
+                {
+                    debug!("{} report before a clear dropped: {:?}", self.name, report);
+                }
+                Some(Message::Report(report, Kind::Position(superseded), _))
+                    if superseded.load(Relaxed) =>
+                {
+                    debug!(
+                        "{} position superseded, report dropped: {:?}",
+                        self.name, report
+                    );
+                }
+                Some(Message::Report(report, ..)) if pending.is_some() => {
+                    pending = Some(report);
+                }
+                Some(Message::Report(report, kind, _)) => {
+                    pending = self.write(report, kind.droppable());
+                    last_attempt = Instant::now();
+                }
+                None => {}
+            }
+            // Reports keep coming while the host is suspended, retry on time regardless
+            match pending.take() {
+                Some(report) if last_attempt.elapsed() >= DETACHED_RETRY_INTERVAL => {
+                    pending = self.retry(report);
+                    last_attempt = Instant::now();
+                }
+                report => pending = report,
+            }
+        }
+    }
+
+    fn state(&self) -> DeviceState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set_state(&self, state: DeviceState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    // Returns the report if it must be written once the device is back
+    fn write(&mut self, report: Vec<u8>, droppable: bool) -> Option<Vec<u8>> {
+        let started = Instant::now();
+        loop {
+            match self.attempt(&report) {
+                Outcome::Written => return None,
+                Outcome::Busy if droppable => {
+                    debug!("{} device busy, motion report dropped", self.name);
+                    return None;
+                }
+                Outcome::Busy if started.elapsed() < STALL_TIMEOUT => {
+                    std::thread::sleep(RETRY_INTERVAL);
+                }
+                Outcome::Busy | Outcome::Detached => {
+                    warn!("{} device detached, waiting for the host", self.name);
+                    self.set_state(DeviceState::Detached);
+                    return Some(report);
+                }
+                Outcome::Failed(e) => {
+                    self.fail(e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn retry(&mut self, report: Vec<u8>) -> Option<Vec<u8>> {
+        match self.attempt(&report) {
+            Outcome::Written => {
+                info!("{} device attached again", self.name);
+                self.set_state(DeviceState::Attached);
+                None
+            }
+            Outcome::Busy | Outcome::Detached => Some(report),
+            Outcome::Failed(e) => {
+                self.fail(e);
+                None
+            }
+        }
+    }
+
+    fn attempt(&mut self, report: &[u8]) -> Outcome {
+        match self.out.send_report(report) {
+            Ok(_) => Outcome::Written,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Outcome::Busy,
+            Err(e) if is_detached(&e) => Outcome::Detached,
+            Err(e) => Outcome::Failed(e),
+        }
+    }
+
+    fn fail(&mut self, e: io::Error) {
+        error!("Error writing {} report: {:?}", self.name, e);
+        self.set_state(DeviceState::Failed);
+        self.token.cancel();
+    }
+}
+
+// The gadget returns these while the host is suspended or the cable is unplugged
+fn is_detached(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ESHUTDOWN) | Some(libc::ENODEV))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::{DeviceState, HidWriter};
+    use crate::sink::Sink;
+
+    // Fails the writes with the scripted errors, then accepts everything
+    #[derive(Clone, Default)]
+    struct Scripted {
+        errors: Arc<Mutex<VecDeque<io::Error>>>,
+        written: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Scripted {
+        fn fail_with(&self, errors: impl IntoIterator<Item = io::Error>) {
+            self.errors.lock().unwrap().extend(errors);
+        }
+
+        fn written(&self) -> Vec<Vec<u8>> {
+            std::mem::take(&mut *self.written.lock().unwrap())
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(e) = self.errors.lock().unwrap().pop_front() {
+                return Err(e);
+            }
+            self.written.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn would_block() -> io::Error {
+        io::ErrorKind::WouldBlock.into()
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_busy() {
+        let out = Scripted::default();
+        let writer = HidWriter::new(
+            "test",
+            Sink::File(Box::new(out.clone())),
+            CancellationToken::new(),
+        );
+
+        // Motion is dropped, keys are retried
+        out.fail_with([would_block()]);
+        writer.write_motion(&[1]);
+        out.fail_with([would_block(), would_block()]);
+        writer.write(&[2]);
+        writer.write_motion(&[3]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(out.written(), [vec![2], vec![3]]);
+        assert_eq!(writer.state(), DeviceState::Attached);
+    }
+
+    #[test]
+    fn test_detached() {
+        let out = Scripted::default();
+        let token = CancellationToken::new();
+        let writer = HidWriter::new("test", Sink::File(Box::new(out.clone())), token.clone());
+
+        out.fail_with((0..2).map(|_| io::Error::from_raw_os_error(libc::ESHUTDOWN)));
+        writer.write(&[1]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(writer.state(), DeviceState::Detached);
+        // Only the latest report is written once the host is back
+        writer.write_motion(&[2]);
+        writer.write(&[3]);
+        for _ in 0..100 {
+            if writer.state() == DeviceState::Attached {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(writer.state(), DeviceState::Attached);
+        writer.write(&[4]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(out.written(), [vec![3], vec![4]]);
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_clear_drops_stale() {
+        let out = Scripted::default();
+        let writer = HidWriter::new(
+            "test",
+            Sink::File(Box::new(out.clone())),
+            CancellationToken::new(),
+        );
+
+        // Busy with the first report while the others are queued
+        out.fail_with((0..50).map(|_| would_block()));
+        writer.write(&[1]);
+        while out.errors.lock().unwrap().len() == 50 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        writer.write(&[2]);
+        writer.write_motion(&[3]);
+        writer.write_clear(&[0], 1);
+        writer.write(&[4]);
+        // An older clear changes nothing
+        writer.write_clear(&[0], 0);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(out.written(), [vec![1], vec![0], vec![4], vec![0]]);
+    }
+
+    #[test]
+    fn test_superseded_positions() {
+        let out = Scripted::default();
+        let writer = HidWriter::new(
+            "test",
+            Sink::File(Box::new(out.clone())),
+            CancellationToken::new(),
+        );
+
+        // Busy with the first report while the others are queued
+        out.fail_with((0..50).map(|_| would_block()));
+        writer.write(&[1]);
+        while out.errors.lock().unwrap().len() == 50 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        writer.write_position(&[2]);
+        writer.write_position(&[3]);
+        // The click is where the cursor was before it
+        writer.write(&[4]);
+        writer.write_position(&[5]);
+        // Relative moves add up, they're never superseded
+        writer.write_motion(&[6]);
+        writer.write_motion(&[7]);
+        writer.write_position(&[8]);
+        writer.write_position(&[9]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(
+            out.written(),
+            [
+                vec![1],
+                vec![3],
+                vec![4],
+                vec![5],
+                vec![6],
+                vec![7],
+                vec![9]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed() {
+        let out = Scripted::default();
+        let token = CancellationToken::new();
+        let writer = HidWriter::new("test", Sink::File(Box::new(out.clone())), token.clone());
+
+        out.fail_with([io::ErrorKind::BrokenPipe.into()]);
+        writer.write(&[1]);
+        writer.write(&[2]);
+        assert!(writer.flush(TIMEOUT));
+        assert_eq!(writer.state(), DeviceState::Failed);
+        assert!(token.is_cancelled());
+        assert!(out.written().is_empty());
+    }
+}
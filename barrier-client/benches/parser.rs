@@ -0,0 +1,83 @@
+//! Throughput of the packet reader of the client over streams built in advance. Compare a
+//! change against the tree before it with
+//!
+//! ```text
+//! cargo bench -p barrier-client --features test-server --bench parser -- --save-baseline before
+//! # apply the change
+//! cargo bench -p barrier-client --features test-server --bench parser -- --baseline before
+//! ```
+
+use barrier_client::{
+    test_server::{parse_wire, Packet},
+    ClipboardData, KeyMask,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+fn wire(packets: impl IntoIterator<Item = Packet>) -> Vec<u8> {
+    let mut wire = vec![];
+    for packet in packets {
+        packet.encode(&mut wire);
+    }
+    wire
+}
+
+// A fast mouse sweeping the screen, the bulk of what a server sends
+fn mouse_moves() -> Vec<u8> {
+    wire((0..10_000u16).map(|i| Packet::MouseMoveAbs {
+        x: i % 1920,
+        y: i % 1080,
+    }))
+}
+
+// Typing with a modifier held now and then
+fn key_burst() -> Vec<u8> {
+    wire((0..2_500u16).flat_map(|i| {
+        let id = 'a' as u16 + i % 26;
+        let mask = if i % 8 == 0 {
+            KeyMask::SHIFT
+        } else {
+            KeyMask::empty()
+        };
+        let button = 30 + i % 26;
+        [
+            Packet::KeyDown { id, mask, button },
+            Packet::KeyUp { id, mask, button },
+        ]
+    }))
+}
+
+// 1 MiB of text, sent in chunks of 32 KiB
+fn clipboard() -> Vec<u8> {
+    let text = "barpi ".repeat(1024 * 1024 / 6);
+    wire([Packet::SetClipboard {
+        id: 0,
+        seq_num: 1,
+        data: ClipboardData::builder().text(text).build(),
+    }])
+}
+
+fn parser(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("parse_wire");
+    for (name, wire) in [
+        ("mouse_moves", mouse_moves()),
+        ("key_burst", key_burst()),
+        ("clipboard", clipboard()),
+    ] {
+        group.throughput(Throughput::Bytes(wire.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                runtime
+                    .block_on(parse_wire(black_box(&wire), |packet| {
+                        black_box(packet);
+                    }))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parser);
+criterion_main!(benches);
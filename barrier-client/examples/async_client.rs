@@ -0,0 +1,131 @@
+//! Same as `dummy_client` with an [`AsyncActuator`], for actuators awaiting their output,
+//! e.g. a serial port. The events are written as text lines to stdout here.
+use barrier_client::{self, async_trait, start_async, ActuatorError, AsyncActuator, KeyMask};
+use env_logger::Env;
+use tokio::io::{AsyncWrite, AsyncWriteExt, Stdout};
+
+#[cfg(feature = "clipboard")]
+use barrier_client::ClipboardData;
+
+struct LineActuator<W> {
+    out: W,
+    width: u16,
+    height: u16,
+    x: u16,
+    y: u16,
+}
+
+impl<W: AsyncWrite + Send + Unpin> LineActuator<W> {
+    async fn send(&mut self, line: String) -> Result<(), ActuatorError> {
+        let write = async {
+            self.out.write_all(line.as_bytes()).await?;
+            self.out.write_all(b"\n").await?;
+            self.out.flush().await
+        };
+        write
+            .await
+            .map_err(|e| ActuatorError::io("stdout write", e))
+    }
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Send + Sync + Unpin> AsyncActuator for LineActuator<W> {
+    async fn connected(&mut self) -> Result<(), ActuatorError> {
+        self.send("connected".to_string()).await
+    }
+
+    async fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.send("disconnected".to_string()).await
+    }
+
+    async fn get_screen_size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    async fn get_cursor_position(&self) -> (u16, u16) {
+        (self.x, self.y)
+    }
+
+    async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        (self.x, self.y) = (x, y);
+        self.send(format!("move {x} {y}")).await
+    }
+
+    async fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.send(format!("mouse down {button}")).await
+    }
+
+    async fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.send(format!("mouse up {button}")).await
+    }
+
+    async fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.send(format!("wheel {x} {y}")).await
+    }
+
+    async fn key_down(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ActuatorError> {
+        self.send(format!("key down {key} {mask} {button}")).await
+    }
+
+    async fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.send(format!("key repeat {key} {mask} {button} {count}"))
+            .await
+    }
+
+    async fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.send(format!("key up {key} {mask} {button}")).await
+    }
+
+    #[cfg(feature = "barrier-options")]
+    async fn set_options(
+        &mut self,
+        opts: barrier_client::ServerOptions,
+    ) -> Result<(), ActuatorError> {
+        self.send(format!("options {:?}", opts)).await
+    }
+
+    #[cfg(feature = "barrier-options")]
+    async fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.send("reset options".to_string()).await
+    }
+
+    async fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.send("enter".to_string()).await
+    }
+
+    async fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.send("leave".to_string()).await
+    }
+
+    #[cfg(feature = "clipboard")]
+    async fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        let len = data.text().map(|s| s.len()).unwrap_or_default();
+        self.send(format!("clipboard {len} bytes of text")).await
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let mut actuator: LineActuator<Stdout> = LineActuator {
+        out: tokio::io::stdout(),
+        width: 1920,
+        height: 1080,
+        x: 0,
+        y: 0,
+    };
+    start_async("192.168.2.59:24800", "BARPI", &mut actuator)
+        .await
+        .unwrap();
+}
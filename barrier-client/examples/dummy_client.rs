@@ -1,4 +1,4 @@
-use barrier_client::{self, start, Actuator};
+use barrier_client::{self, start, Actuator, ActuatorError, KeyMask};
 use env_logger::Env;
 use log::info;
 
@@ -11,16 +11,18 @@ struct DummyActuator {
     x: u16,
     y: u16,
     #[cfg(feature = "barrier-options")]
-    options: std::collections::HashMap<String, u32>,
+    options: barrier_client::ServerOptions,
 }
 
 impl Actuator for DummyActuator {
-    fn connected(&mut self) {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
         info!("Connected");
+        Ok(())
     }
 
-    fn disconnected(&mut self) {
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
         info!("Disconnected");
+        Ok(())
     }
 
     fn get_screen_size(&self) -> (u16, u16) {
@@ -31,64 +33,82 @@ impl Actuator for DummyActuator {
         (self.x, self.y)
     }
 
-    fn set_cursor_position(&mut self, x: u16, y: u16) {
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
         self.x = x;
         self.y = y;
         info!("Set cursor position to {x} {y}");
+        Ok(())
     }
 
-    fn move_cursor(&mut self, x: i16, y: i16) {
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
         self.x = (self.x as i32 + x as i32) as u16;
         self.y = (self.y as i32 + y as i32) as u16;
         info!("Move cursor by {x} {y}, now at {} {}", self.x, self.y);
+        Ok(())
     }
 
-    fn mouse_down(&mut self, button: i8) {
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
         info!("Mouse down {button}");
+        Ok(())
     }
 
-    fn mouse_up(&mut self, button: i8) {
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
         info!("Mouse up {button}");
+        Ok(())
     }
 
-    fn mouse_wheel(&mut self, x: i16, y: i16) {
-        info!("Mouse wheel {x} {y}")
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        info!("Mouse wheel {x} {y}");
+        Ok(())
     }
 
-    fn key_down(&mut self, key: u16, mask: u16, button: u16) {
-        info!("Key down {key} {mask} {button}")
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        info!("Key down {key} {mask} {button}");
+        Ok(())
     }
 
-    fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
-        info!("Key repeat {key} {mask} {button} {count}")
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        info!("Key repeat {key} {mask} {button} {count}");
+        Ok(())
     }
 
-    fn key_up(&mut self, key: u16, mask: u16, button: u16) {
-        info!("Key up {key} {mask} {button}")
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        info!("Key up {key} {mask} {button}");
+        Ok(())
     }
 
     #[cfg(feature = "barrier-options")]
-    fn set_options(&mut self, opts: std::collections::HashMap<String, u32>) {
+    fn set_options(&mut self, opts: barrier_client::ServerOptions) -> Result<(), ActuatorError> {
         self.options = opts;
-        info!("Set options {:#?}", self.options)
+        info!("Set options {:#?}", self.options);
+        Ok(())
     }
 
     #[cfg(feature = "barrier-options")]
-    fn reset_options(&mut self) {
-        self.options.clear();
-        info!("Reset options")
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.options = Default::default();
+        info!("Reset options");
+        Ok(())
     }
 
-    fn enter(&mut self) {
-        info!("Enter")
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        info!("Enter");
+        Ok(())
     }
 
-    fn leave(&mut self) {
-        info!("Leave")
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        info!("Leave");
+        Ok(())
     }
 
     #[cfg(feature = "clipboard")]
-    fn set_clipboard(&mut self, data: ClipboardData) {
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
         info!(
             "Clipboard text:{}",
             data.text()
@@ -102,9 +122,11 @@ impl Actuator for DummyActuator {
                 .unwrap_or(String::from("<None>")),
         );
         info!(
-            "Clipboard bitmap:{}",
-            data.bitmap().map(|_| "yes").unwrap_or("no")
+            "Clipboard formats:{:?}, {} bytes",
+            data.formats(),
+            data.len_bytes()
         );
+        Ok(())
     }
 }
 
@@ -117,7 +139,7 @@ async fn main() {
         x: 0,
         y: 0,
         #[cfg(feature = "barrier-options")]
-        options: std::collections::HashMap::new(),
+        options: Default::default(),
     };
     start("192.168.2.59:24800", String::from("BARPI"), &mut actuator)
         .await
@@ -1,93 +1,437 @@
+use barrier_proto::clamp_move;
+pub use barrier_proto::{KeyMask, LedState, Protocol, ServerInfo};
 use serde::{Deserialize, Serialize};
 
+use crate::ActuatorError;
 #[cfg(feature = "clipboard")]
 use crate::ClipboardData;
+#[cfg(feature = "barrier-options")]
+use crate::ServerOptions;
+
+/// Edge of the screen, see [`ClientBuilder::edge_margin`](crate::ClientBuilder::edge_margin)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
 
+/// Applies the events received from the server.
+///
+/// An error returned by a callback ends the connection with
+/// [`ConnectionError::ActuatorError`](crate::ConnectionError::ActuatorError) unless
+/// [`ClientBuilder::on_actuator_error`](crate::ClientBuilder::on_actuator_error) says
+/// otherwise, `disconnected` is still called so the actuator can release what is held. The
+/// errors of the clipboard, file, language and connection callbacks are only logged.
 pub trait Actuator {
-    fn connected(&mut self);
+    fn connected(&mut self) -> Result<(), ActuatorError>;
+
+    /// Called after the handshake instead of `connected`, for actuators depending on the
+    /// protocol version, the default calls `connected`. The version is the one negotiated
+    /// with the server, not the one it announced.
+    fn connected_with(&mut self, _info: ServerInfo) -> Result<(), ActuatorError> {
+        self.connected()
+    }
 
-    fn disconnected(&mut self);
+    fn disconnected(&mut self) -> Result<(), ActuatorError>;
 
+    /// Asked each time the server queries the screen info, see
+    /// [`ClientHandle::screen_changed`](crate::ClientHandle::screen_changed) to tell it first
     fn get_screen_size(&self) -> (u16, u16);
 
     fn get_cursor_position(&self) -> (u16, u16);
 
-    fn set_cursor_position(&mut self, x: u16, y: u16);
+    /// The position is in screen coordinates as sent by the server, within `get_screen_size`
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError>;
 
-    fn move_cursor(&mut self, x: i16, y: i16) {
-        let (cx, cy) = self.get_cursor_position();
-        self.set_cursor_position((cx as i32 + x as i32) as u16, (cy as i32 + y as i32) as u16);
+    /// Relative move as sent with DMRM, the default moves to the current position plus the
+    /// delta, clamped to the screen
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        let (x, y) = offset_position(self.get_cursor_position(), self.get_screen_size(), x, y);
+        self.set_cursor_position(x, y)
     }
 
-    fn mouse_down(&mut self, button: i8);
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError>;
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError>;
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError>;
+
+    #[cfg(not(feature = "raw-mask"))]
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError>;
+
+    #[cfg(not(feature = "raw-mask"))]
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError>;
+
+    #[cfg(not(feature = "raw-mask"))]
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError>;
+
+    /// With the `raw-mask` feature the key events default to the `*_raw` methods taking the
+    /// mask as it was before [`KeyMask`], for actuators not ported yet
+    #[cfg(feature = "raw-mask")]
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        #[allow(deprecated)]
+        self.key_down_raw(key, mask.bits(), button)
+    }
 
-    fn mouse_up(&mut self, button: i8);
+    #[cfg(feature = "raw-mask")]
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        #[allow(deprecated)]
+        self.key_repeat_raw(key, mask.bits(), button, count)
+    }
 
-    fn mouse_wheel(&mut self, x: i16, y: i16);
+    #[cfg(feature = "raw-mask")]
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        #[allow(deprecated)]
+        self.key_up_raw(key, mask.bits(), button)
+    }
 
-    fn key_down(&mut self, key: u16, mask: u16, button: u16);
+    #[cfg(feature = "raw-mask")]
+    #[deprecated(note = "implement `key_down` with a `KeyMask` instead")]
+    fn key_down_raw(&mut self, _key: u16, _mask: u16, _button: u16) -> Result<(), ActuatorError> {
+        Ok(())
+    }
 
-    fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16);
+    #[cfg(feature = "raw-mask")]
+    #[deprecated(note = "implement `key_repeat` with a `KeyMask` instead")]
+    fn key_repeat_raw(
+        &mut self,
+        _key: u16,
+        _mask: u16,
+        _button: u16,
+        _count: u16,
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
 
-    fn key_up(&mut self, key: u16, mask: u16, button: u16);
+    #[cfg(feature = "raw-mask")]
+    #[deprecated(note = "implement `key_up` with a `KeyMask` instead")]
+    fn key_up_raw(&mut self, _key: u16, _mask: u16, _button: u16) -> Result<(), ActuatorError> {
+        Ok(())
+    }
 
+    /// The server set options with DSOP, the client has already applied the heartbeat
     #[cfg(feature = "barrier-options")]
-    fn set_options(&mut self, opts: std::collections::HashMap<String, u32>);
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError>;
 
     #[cfg(feature = "barrier-options")]
-    fn reset_options(&mut self);
+    fn reset_options(&mut self) -> Result<(), ActuatorError>;
+
+    fn enter(&mut self) -> Result<(), ActuatorError>;
+
+    /// The cursor entered the screen at `x`, `y` with the modifiers in `mask` held, the default
+    /// calls `enter` and moves the cursor there, so a position tracked from relative moves
+    /// starts over from where the server has it
+    fn enter_at(&mut self, x: u16, y: u16, _mask: KeyMask) -> Result<(), ActuatorError> {
+        self.enter()?;
+        self.set_cursor_position(x, y)
+    }
 
-    fn enter(&mut self);
+    fn leave(&mut self) -> Result<(), ActuatorError>;
 
-    fn leave(&mut self);
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError>;
 
+    /// Called instead of `set_clipboard` with the id of the clipboard, 0 for the clipboard
+    /// and 1 for the primary selection of X11, the default calls `set_clipboard` for both
     #[cfg(feature = "clipboard")]
-    fn set_clipboard(&mut self, data: ClipboardData);
+    fn set_clipboard_with(&mut self, _id: u8, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.set_clipboard(data)
+    }
+
+    /// The local clipboard `id` to send to the server when the cursor leaves, once the client
+    /// has been told it changed with `ClientHandle::clipboard_changed`. `None` if there is
+    /// nothing new to send, which the default always returns.
+    #[cfg(feature = "clipboard")]
+    fn get_clipboard(&mut self, _id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        Ok(None)
+    }
+
+    /// A file dragged onto this screen has been received, `name_hint` is the name it had on
+    /// the server if it was sent. The default drops it.
+    #[cfg(feature = "file-transfer")]
+    fn file_received(
+        &mut self,
+        _name_hint: Option<String>,
+        _data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The target host changed its LEDs, e.g. Caps Lock was toggled
+    fn led_state_changed(&mut self, _state: LedState) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The screen saver of the server started or stopped
+    fn screensaver(&mut self, _on: bool) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// Buttons held on game controller `id` of the server, a bit per button
+    fn game_buttons(&mut self, _id: u8, _buttons: u16) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// Position of the sticks of game controller `id` as `(x, y)`, up and right are positive
+    fn game_sticks(
+        &mut self,
+        _id: u8,
+        _left: (i16, i16),
+        _right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// Position of the triggers of game controller `id`, 0 when released
+    fn game_triggers(&mut self, _id: u8, _left: u8, _right: u8) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The cursor moved within the edge margin of the screen, called again only once it has
+    /// left the margin. Only with [`ClientBuilder::edge_margin`](crate::ClientBuilder::edge_margin)
+    /// set.
+    fn edge_reached(&mut self, _edge: Edge) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+    /// Keyboard languages of the server as two letter codes, e.g. to pick the keymap the
+    /// keys are typed with. Only sent by servers speaking protocol 1.8 or newer.
+    fn language_sync(&mut self, _languages: Vec<String>) -> Result<(), ActuatorError> {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "async-actuator")]
 #[async_trait::async_trait]
 pub trait AsyncActuator {
-    async fn connected(&mut self);
+    async fn connected(&mut self) -> Result<(), ActuatorError>;
+
+    /// Called after the handshake instead of `connected`, for actuators depending on the
+    /// protocol version, the default calls `connected`. The version is the one negotiated
+    /// with the server, not the one it announced.
+    async fn connected_with(&mut self, _info: ServerInfo) -> Result<(), ActuatorError> {
+        self.connected().await
+    }
 
-    async fn disconnected(&mut self);
+    async fn disconnected(&mut self) -> Result<(), ActuatorError>;
 
+    /// Asked each time the server queries the screen info, see
+    /// [`ClientHandle::screen_changed`](crate::ClientHandle::screen_changed) to tell it first
     async fn get_screen_size(&self) -> (u16, u16);
 
     async fn get_cursor_position(&self) -> (u16, u16);
 
-    async fn set_cursor_position(&mut self, x: u16, y: u16);
+    /// The position is in screen coordinates as sent by the server, within `get_screen_size`
+    async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError>;
 
-    async fn move_cursor(&mut self, x: i16, y: i16) {
-        let (cx, cy) = self.get_cursor_position().await;
-        self.set_cursor_position((cx as i32 + x as i32) as u16, (cy as i32 + y as i32) as u16)
-            .await;
+    /// Relative move as sent with DMRM, the default moves to the current position plus the
+    /// delta, clamped to the screen
+    async fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        let position = self.get_cursor_position().await;
+        let (x, y) = offset_position(position, self.get_screen_size().await, x, y);
+        self.set_cursor_position(x, y).await
     }
 
-    async fn mouse_down(&mut self, button: i8);
+    async fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError>;
 
-    async fn mouse_up(&mut self, button: i8);
+    async fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError>;
 
-    async fn mouse_wheel(&mut self, x: i16, y: i16);
+    async fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError>;
 
-    async fn key_down(&mut self, key: u16, mask: u16, button: u16);
+    #[cfg(not(feature = "raw-mask"))]
+    async fn key_down(&mut self, key: u16, mask: KeyMask, button: u16)
+        -> Result<(), ActuatorError>;
 
-    async fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16);
+    #[cfg(not(feature = "raw-mask"))]
+    async fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError>;
 
-    async fn key_up(&mut self, key: u16, mask: u16, button: u16);
+    #[cfg(not(feature = "raw-mask"))]
+    async fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError>;
 
+    /// See [`Actuator::key_down`] for the `raw-mask` feature
+    #[cfg(feature = "raw-mask")]
+    async fn key_down(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ActuatorError> {
+        #[allow(deprecated)]
+        self.key_down_raw(key, mask.bits(), button).await
+    }
+
+    #[cfg(feature = "raw-mask")]
+    async fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        #[allow(deprecated)]
+        self.key_repeat_raw(key, mask.bits(), button, count).await
+    }
+
+    #[cfg(feature = "raw-mask")]
+    async fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        #[allow(deprecated)]
+        self.key_up_raw(key, mask.bits(), button).await
+    }
+
+    #[cfg(feature = "raw-mask")]
+    #[deprecated(note = "implement `key_down` with a `KeyMask` instead")]
+    async fn key_down_raw(
+        &mut self,
+        _key: u16,
+        _mask: u16,
+        _button: u16,
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    #[cfg(feature = "raw-mask")]
+    #[deprecated(note = "implement `key_repeat` with a `KeyMask` instead")]
+    async fn key_repeat_raw(
+        &mut self,
+        _key: u16,
+        _mask: u16,
+        _button: u16,
+        _count: u16,
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    #[cfg(feature = "raw-mask")]
+    #[deprecated(note = "implement `key_up` with a `KeyMask` instead")]
+    async fn key_up_raw(
+        &mut self,
+        _key: u16,
+        _mask: u16,
+        _button: u16,
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The server set options with DSOP, the client has already applied the heartbeat
     #[cfg(feature = "barrier-options")]
-    async fn set_options(&mut self, opts: std::collections::HashMap<String,u32>);
+    async fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError>;
 
     #[cfg(feature = "barrier-options")]
-    async fn reset_options(&mut self);
+    async fn reset_options(&mut self) -> Result<(), ActuatorError>;
+
+    async fn enter(&mut self) -> Result<(), ActuatorError>;
+
+    /// The cursor entered the screen at `x`, `y` with the modifiers in `mask` held, the default
+    /// calls `enter` and moves the cursor there
+    async fn enter_at(&mut self, x: u16, y: u16, _mask: KeyMask) -> Result<(), ActuatorError> {
+        self.enter().await?;
+        self.set_cursor_position(x, y).await
+    }
+
+    async fn leave(&mut self) -> Result<(), ActuatorError>;
 
-    async fn enter(&mut self);
+    #[cfg(feature = "clipboard")]
+    async fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError>;
 
-    async fn leave(&mut self);
+    /// Called instead of `set_clipboard` with the id of the clipboard, the default calls
+    /// `set_clipboard` for both
+    #[cfg(feature = "clipboard")]
+    async fn set_clipboard_with(
+        &mut self,
+        _id: u8,
+        data: ClipboardData,
+    ) -> Result<(), ActuatorError> {
+        self.set_clipboard(data).await
+    }
 
+    /// The local clipboard `id` to send to the server when the cursor leaves, the default
+    /// has nothing to send
     #[cfg(feature = "clipboard")]
-    async fn set_clipboard(&mut self, data: ClipboardData);
+    async fn get_clipboard(&mut self, _id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        Ok(None)
+    }
+
+    /// A file dragged onto this screen has been received, the default drops it
+    #[cfg(feature = "file-transfer")]
+    async fn file_received(
+        &mut self,
+        _name_hint: Option<String>,
+        _data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The target host changed its LEDs, e.g. Caps Lock was toggled
+    async fn led_state_changed(&mut self, _state: LedState) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The screen saver of the server started or stopped
+    async fn screensaver(&mut self, _on: bool) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// Buttons held on game controller `id` of the server, see [`Actuator::game_buttons`]
+    async fn game_buttons(&mut self, _id: u8, _buttons: u16) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// Position of the sticks of game controller `id`, see [`Actuator::game_sticks`]
+    async fn game_sticks(
+        &mut self,
+        _id: u8,
+        _left: (i16, i16),
+        _right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// Position of the triggers of game controller `id`, 0 when released
+    async fn game_triggers(&mut self, _id: u8, _left: u8, _right: u8) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    /// The cursor moved within the edge margin of the screen, see [`Actuator::edge_reached`]
+    async fn edge_reached(&mut self, _edge: Edge) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+    /// Keyboard languages of the server, see [`Actuator::language_sync`]
+    async fn language_sync(&mut self, _languages: Vec<String>) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+}
+
+/// `position` moved by `x`, `y` without leaving a screen of `size`
+pub(crate) fn offset_position(
+    position: (u16, u16),
+    size: (u16, u16),
+    x: i16,
+    y: i16,
+) -> (u16, u16) {
+    (
+        clamp_move(position.0, x, size.0.saturating_sub(1)),
+        clamp_move(position.1, y, size.1.saturating_sub(1)),
+    )
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -114,25 +458,52 @@ pub enum ActuatorMessage {
     },
     KeyDown {
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
     },
     KeyRepeat {
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
         count: u16,
     },
     KeyUp {
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
     },
     Enter,
     Leave,
+    LedStateChanged {
+        state: LedState,
+    },
+    ScreenSaver {
+        on: bool,
+    },
+    GameButtons {
+        id: u8,
+        buttons: u16,
+    },
+    GameSticks {
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    },
+    GameTriggers {
+        id: u8,
+        left: u8,
+        right: u8,
+    },
+    EdgeReached {
+        edge: Edge,
+    },
+    LanguageSync {
+        languages: Vec<String>,
+    },
+    // The variants of a feature come last, the index of the others doesn't depend on them
     #[cfg(feature = "barrier-options")]
     SetOptions {
-        opts: std::collections::HashMap<String, u32>,
+        opts: ServerOptions,
     },
     #[cfg(feature = "barrier-options")]
     ResetOptions,
@@ -149,3 +520,24 @@ pub enum ActuatorMessage {
         data: Vec<u8>,
     },
 }
+
+#[cfg(test)]
+mod test {
+    use super::offset_position;
+
+    #[test]
+    fn test_offset_position() {
+        assert_eq!(offset_position((10, 10), (1920, 1080), -3, 4), (7, 14));
+        assert_eq!(offset_position((10, 10), (1920, 1080), -30, -40), (0, 0));
+        assert_eq!(
+            offset_position((1900, 1070), (1920, 1080), i16::MAX, 20),
+            (1919, 1079)
+        );
+        // Far off the screen doesn't wrap around
+        assert_eq!(
+            offset_position((u16::MAX, 0), (1920, 1080), 1, 0),
+            (1919, 0)
+        );
+        assert_eq!(offset_position((0, 0), (0, 0), 5, 5), (0, 0));
+    }
+}
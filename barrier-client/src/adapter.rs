@@ -0,0 +1,337 @@
+use async_trait::async_trait;
+
+#[cfg(feature = "async-actuator")]
+use crate::AsyncActuator;
+#[cfg(feature = "clipboard")]
+use crate::ClipboardData;
+#[cfg(feature = "barrier-options")]
+use crate::ServerOptions;
+
+use super::{Actuator, ActuatorError, Edge, KeyMask, ServerInfo};
+
+/// Lets the client loop drive both [`Actuator`] and `AsyncActuator` implementations.
+#[async_trait]
+pub(crate) trait ActuatorAdapter: Send {
+    async fn connected(&mut self, info: ServerInfo) -> Result<(), ActuatorError>;
+
+    async fn disconnected(&mut self) -> Result<(), ActuatorError>;
+
+    async fn get_screen_size(&mut self) -> (u16, u16);
+
+    async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError>;
+
+    async fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError>;
+
+    async fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError>;
+
+    async fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError>;
+
+    async fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError>;
+
+    async fn key_down(&mut self, key: u16, mask: KeyMask, button: u16)
+        -> Result<(), ActuatorError>;
+
+    async fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError>;
+
+    async fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError>;
+
+    #[cfg(feature = "barrier-options")]
+    async fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError>;
+
+    #[cfg(feature = "barrier-options")]
+    async fn reset_options(&mut self) -> Result<(), ActuatorError>;
+
+    async fn enter(&mut self, x: u16, y: u16, mask: KeyMask) -> Result<(), ActuatorError>;
+
+    async fn leave(&mut self) -> Result<(), ActuatorError>;
+
+    async fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError>;
+
+    async fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError>;
+
+    async fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError>;
+
+    async fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError>;
+
+    async fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError>;
+
+    async fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError>;
+
+    #[cfg(feature = "clipboard")]
+    async fn set_clipboard(&mut self, id: u8, data: ClipboardData) -> Result<(), ActuatorError>;
+
+    #[cfg(feature = "clipboard")]
+    async fn get_clipboard(&mut self, id: u8) -> Result<Option<ClipboardData>, ActuatorError>;
+
+    #[cfg(feature = "file-transfer")]
+    async fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError>;
+}
+
+pub(crate) struct SyncAdapter<'a, A>(pub &'a mut A);
+
+#[async_trait]
+impl<A: Actuator + Send> ActuatorAdapter for SyncAdapter<'_, A> {
+    async fn connected(&mut self, info: ServerInfo) -> Result<(), ActuatorError> {
+        self.0.connected_with(info)
+    }
+
+    async fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.0.disconnected()
+    }
+
+    async fn get_screen_size(&mut self) -> (u16, u16) {
+        self.0.get_screen_size()
+    }
+
+    async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.0.set_cursor_position(x, y)
+    }
+
+    async fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.move_cursor(x, y)
+    }
+
+    async fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.mouse_down(button)
+    }
+
+    async fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.mouse_up(button)
+    }
+
+    async fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.mouse_wheel(x, y)
+    }
+
+    async fn key_down(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ActuatorError> {
+        self.0.key_down(key, mask, button)
+    }
+
+    async fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.0.key_repeat(key, mask, button, count)
+    }
+
+    async fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.0.key_up(key, mask, button)
+    }
+
+    #[cfg(feature = "barrier-options")]
+    async fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        self.0.set_options(opts)
+    }
+
+    #[cfg(feature = "barrier-options")]
+    async fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.0.reset_options()
+    }
+
+    async fn enter(&mut self, x: u16, y: u16, mask: KeyMask) -> Result<(), ActuatorError> {
+        self.0.enter_at(x, y, mask)
+    }
+
+    async fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.0.leave()
+    }
+
+    async fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.0.screensaver(on)
+    }
+
+    async fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.0.game_buttons(id, buttons)
+    }
+
+    async fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.0.game_sticks(id, left, right)
+    }
+
+    async fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.0.game_triggers(id, left, right)
+    }
+
+    async fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.0.edge_reached(edge)
+    }
+
+    async fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.0.language_sync(languages)
+    }
+
+    #[cfg(feature = "clipboard")]
+    async fn set_clipboard(&mut self, id: u8, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.0.set_clipboard_with(id, data)
+    }
+
+    #[cfg(feature = "clipboard")]
+    async fn get_clipboard(&mut self, id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        self.0.get_clipboard(id)
+    }
+
+    #[cfg(feature = "file-transfer")]
+    async fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        self.0.file_received(name_hint, data)
+    }
+}
+
+#[cfg(feature = "async-actuator")]
+pub(crate) struct AsyncAdapter<'a, A>(pub &'a mut A);
+
+#[cfg(feature = "async-actuator")]
+#[async_trait]
+impl<A: AsyncActuator + Send + Sync> ActuatorAdapter for AsyncAdapter<'_, A> {
+    async fn connected(&mut self, info: ServerInfo) -> Result<(), ActuatorError> {
+        self.0.connected_with(info).await
+    }
+
+    async fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.0.disconnected().await
+    }
+
+    async fn get_screen_size(&mut self) -> (u16, u16) {
+        self.0.get_screen_size().await
+    }
+
+    async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.0.set_cursor_position(x, y).await
+    }
+
+    async fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.move_cursor(x, y).await
+    }
+
+    async fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.mouse_down(button).await
+    }
+
+    async fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.mouse_up(button).await
+    }
+
+    async fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.mouse_wheel(x, y).await
+    }
+
+    async fn key_down(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ActuatorError> {
+        self.0.key_down(key, mask, button).await
+    }
+
+    async fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.0.key_repeat(key, mask, button, count).await
+    }
+
+    async fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.0.key_up(key, mask, button).await
+    }
+
+    #[cfg(feature = "barrier-options")]
+    async fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        self.0.set_options(opts).await
+    }
+
+    #[cfg(feature = "barrier-options")]
+    async fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.0.reset_options().await
+    }
+
+    async fn enter(&mut self, x: u16, y: u16, mask: KeyMask) -> Result<(), ActuatorError> {
+        self.0.enter_at(x, y, mask).await
+    }
+
+    async fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.0.leave().await
+    }
+
+    async fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.0.screensaver(on).await
+    }
+
+    async fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.0.game_buttons(id, buttons).await
+    }
+
+    async fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.0.game_sticks(id, left, right).await
+    }
+
+    async fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.0.game_triggers(id, left, right).await
+    }
+
+    async fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.0.edge_reached(edge).await
+    }
+
+    async fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.0.language_sync(languages).await
+    }
+
+    #[cfg(feature = "clipboard")]
+    async fn set_clipboard(&mut self, id: u8, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.0.set_clipboard_with(id, data).await
+    }
+
+    #[cfg(feature = "clipboard")]
+    async fn get_clipboard(&mut self, id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        self.0.get_clipboard(id).await
+    }
+
+    #[cfg(feature = "file-transfer")]
+    async fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        self.0.file_received(name_hint, data).await
+    }
+}
@@ -0,0 +1,424 @@
+//! A client on `std::net` for targets without tokio.
+//!
+//! It speaks the same protocol as [`crate::start`] through the shared codec, one thread
+//! per connection, the keep-alive timeout is the read timeout of the socket.
+//!
+//! ```no_run
+//! # fn run<A: barrier_client::blocking::BlockingActuator>(actor: &mut A) {
+//! let result = barrier_client::blocking::start("192.168.1.2:24800", "pi", actor);
+//! # }
+//! ```
+
+use std::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+#[cfg(feature = "file-transfer")]
+use barrier_proto::{file_name, FileStage, FileTransfer};
+use barrier_proto::{packet_size, parse_body};
+#[cfg(feature = "barrier-options")]
+use log::info;
+use log::{debug, error, warn};
+
+#[cfg(feature = "clipboard")]
+use crate::codec::DEFAULT_MAX_CLIPBOARD_SIZE;
+#[cfg(feature = "file-transfer")]
+use crate::codec::DEFAULT_MAX_FILE_SIZE;
+use crate::codec::{
+    check_screen_name, encode_hello, hello_size, parse_hello, DEFAULT_MAX_PACKET_SIZE,
+    KEEPALIVES_UNTIL_DEATH, KEEPALIVE_INTERVAL, MAX_HELLO_SIZE,
+};
+#[cfg(feature = "barrier-options")]
+use crate::{options::relative_move, ServerModes, ServerOptions};
+use crate::{ConnectionError, Packet, ServerInfo};
+
+/// The actuator driven by the blocking client, the calls are the same as for the async one
+pub use crate::Actuator as BlockingActuator;
+
+/// Connects to the server and drives `actor` until the connection ends, like [`crate::start`]
+pub fn start<A: BlockingActuator, Addr: ToSocketAddrs, S: AsRef<str>>(
+    addr: Addr,
+    device_name: S,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    check_screen_name(device_name.as_ref())?;
+    let stream = TcpStream::connect(addr)?;
+    // Turn off Nagle, this may not be available on ESP-IDF, so ignore the error.
+    stream.set_nodelay(true).ok();
+    session(stream, device_name.as_ref(), actor)
+}
+
+fn read_size<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut size = [0; 4];
+    reader.read_exact(&mut size)?;
+    Ok(u32::from_be_bytes(size))
+}
+
+fn handshake(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    device_name: &str,
+) -> Result<ServerInfo, ConnectionError> {
+    let size = hello_size(read_size(reader)?)?;
+    let mut hello = [0; MAX_HELLO_SIZE];
+    let hello = &mut hello[..size];
+    reader.read_exact(hello)?;
+    let info = parse_hello(hello)
+        .inspect_err(|_| {
+            error!("Got invalid hello {:?}", String::from_utf8_lossy(hello));
+        })?
+        .negotiated();
+
+    let mut reply = vec![];
+    encode_hello(info, device_name, &mut reply);
+    writer.write_all(&reply)?;
+    writer.flush()?;
+    Ok(info)
+}
+
+fn send(writer: &mut impl Write, packet: Packet) -> io::Result<()> {
+    let mut buf = vec![];
+    packet.encode(&mut buf);
+    writer.write_all(&buf)?;
+    writer.flush()
+}
+
+fn session<A: BlockingActuator>(
+    stream: TcpStream,
+    device_name: &str,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    stream.set_read_timeout(Some(KEEPALIVE_INTERVAL * KEEPALIVES_UNTIL_DEATH))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let info = handshake(&mut reader, &mut writer, device_name)?;
+    let result = match actor.connected_with(info) {
+        Ok(()) => dispatch_packets(&mut reader, &mut writer, device_name, info, actor),
+        Err(e) => Err(e.into()),
+    };
+    // Also after an actuator error, to release what is held
+    if let Err(e) = actor.disconnected() {
+        warn!("Actuator failed to disconnect: {}", e);
+    }
+    result
+}
+
+/// Applies the packets to the actuator until the connection ends
+fn dispatch_packets<A: BlockingActuator>(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut BufWriter<TcpStream>,
+    device_name: &str,
+    info: ServerInfo,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    let keepalive_timeout = KEEPALIVE_INTERVAL * KEEPALIVES_UNTIL_DEATH;
+    #[cfg(feature = "clipboard")]
+    let mut clipboard_stage = crate::ClipboardStage::new();
+    #[cfg(feature = "file-transfer")]
+    let mut file_stage = FileStage::None;
+    // Name of the file being transferred, from the last DDRG
+    #[cfg(feature = "file-transfer")]
+    let mut name_hint: Option<String> = None;
+    #[cfg(feature = "barrier-options")]
+    let mut modes = ServerModes::default();
+    // Last position the server sent, the relative mouse moves start from it
+    #[cfg(feature = "barrier-options")]
+    let mut position = (0, 0);
+    let mut buf = vec![];
+
+    loop {
+        let packet = read_size(reader)
+            .map_err(ConnectionError::from)
+            .and_then(|size| Ok(packet_size(size, DEFAULT_MAX_PACKET_SIZE)?))
+            .and_then(|size| {
+                buf.resize(size, 0);
+                reader.read_exact(&mut buf)?;
+                Ok(parse_body(
+                    &buf,
+                    #[cfg(feature = "clipboard")]
+                    &mut clipboard_stage,
+                    #[cfg(feature = "clipboard")]
+                    DEFAULT_MAX_CLIPBOARD_SIZE,
+                )?)
+            });
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(ConnectionError::TcpError(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                warn!("Nothing received from the server in {keepalive_timeout:?}");
+                return Err(ConnectionError::Timeout);
+            }
+            Err(_) => break,
+        };
+        // The server has no business sending it
+        if !info.supports(packet.version()) {
+            let (major, minor) = packet.version();
+            debug!("Dropped a packet of version {major}.{minor}, newer than the session");
+            continue;
+        }
+        match packet {
+            Packet::QueryInfo => {
+                // Asked again when the server's configuration changes, the size may have too
+                let (w, h) = actor.get_screen_size();
+                let info = Packet::DeviceInfo {
+                    x: 0,
+                    y: 0,
+                    w,
+                    h,
+                    _dummy: 0,
+                    mx: 0,
+                    my: 0,
+                };
+                send(writer, info)?;
+            }
+            Packet::KeepAlive => {
+                send(writer, Packet::KeepAlive)?;
+            }
+            #[cfg(feature = "barrier-options")]
+            Packet::MouseMoveAbs { x, y } if modes.relative_mouse_moves => {
+                let (dx, dy) = relative_move(std::mem::replace(&mut position, (x, y)), (x, y));
+                if (dx, dy) != (0, 0) {
+                    actor.move_cursor(dx, dy)?;
+                }
+            }
+            Packet::MouseMoveAbs { x, y } => {
+                #[cfg(feature = "barrier-options")]
+                {
+                    position = (x, y);
+                }
+                actor.set_cursor_position(x, y)?
+            }
+            Packet::MouseMove { x, y } => actor.move_cursor(x, y)?,
+            Packet::KeyUp { id, mask, button } => actor.key_up(id, mask, button)?,
+            Packet::KeyDown { id, mask, button } => actor.key_down(id, mask, button)?,
+            Packet::KeyRepeat {
+                id,
+                mask,
+                button,
+                count,
+            } => actor.key_repeat(id, mask, button, count)?,
+            Packet::MouseDown { id } => actor.mouse_down(id)?,
+            Packet::MouseUp { id } => actor.mouse_up(id)?,
+            Packet::MouseWheel { x_delta, y_delta } => actor.mouse_wheel(x_delta, y_delta)?,
+            #[cfg(feature = "barrier-options")]
+            Packet::ResetOptions => {
+                writer.get_ref().set_read_timeout(Some(keepalive_timeout))?;
+                modes = ServerModes::default();
+                actor.reset_options()?;
+            }
+            #[cfg(feature = "barrier-options")]
+            Packet::SetDeviceOptions(opts) => {
+                let opts = ServerOptions::from(opts);
+                if let Some(rate) = opts.heartbeat.filter(|rate| !rate.is_zero()) {
+                    let timeout = rate * KEEPALIVES_UNTIL_DEATH;
+                    debug!("Keep-alive timeout set to {:?}", timeout);
+                    writer.get_ref().set_read_timeout(Some(timeout))?;
+                }
+                if modes.update(&opts) {
+                    info!("Server options changed: {:?}", modes);
+                }
+                actor.set_options(opts)?;
+            }
+            Packet::CursorEnter { x, y, mask, .. } => {
+                #[cfg(feature = "barrier-options")]
+                {
+                    position = (x, y);
+                }
+                actor.enter_at(x, y, mask)?
+            }
+            Packet::CursorLeave => actor.leave()?,
+            Packet::ScreenSaver { on } => actor.screensaver(on)?,
+            Packet::GameButtons { id, buttons } => actor.game_buttons(id, buttons)?,
+            Packet::GameSticks { id, x1, y1, x2, y2 } => {
+                actor.game_sticks(id, (x1, y1), (x2, y2))?
+            }
+            Packet::GameTriggers { id, t1, t2 } => actor.game_triggers(id, t1, t2)?,
+            Packet::LanguageSync { languages } => actor.language_sync(languages)?,
+            #[cfg(feature = "clipboard")]
+            Packet::SetClipboard { id, data, .. } => {
+                if !data.is_empty() {
+                    debug!("Clipboard: id:{id}, data:...");
+                    if data.is_truncated() {
+                        warn!(
+                            "Clipboard {id} truncated to the limit: {:?}",
+                            data.truncated()
+                        );
+                    }
+                    actor.set_clipboard_with(id, data)?;
+                }
+            }
+            #[cfg(feature = "clipboard")]
+            Packet::ClipboardTooLarge { id, size } => {
+                warn!("Clipboard {id} discarded, {size} bytes is larger than the limit");
+            }
+            #[cfg(feature = "file-transfer")]
+            Packet::DragInfo { files } => {
+                debug!("Files dragged: {:?}", files);
+                // Only the first one is transferred
+                name_hint = files.first().map(|path| file_name(path).to_string());
+            }
+            #[cfg(feature = "file-transfer")]
+            Packet::FileChunk { mark, data } => {
+                match file_stage.push(mark, &data, DEFAULT_MAX_FILE_SIZE) {
+                    FileTransfer::Pending => {}
+                    FileTransfer::Received(data) => {
+                        debug!("File received: {:?}, {} bytes", name_hint, data.len());
+                        actor.file_received(name_hint.take(), data)?;
+                    }
+                    FileTransfer::TooLarge { size } => {
+                        warn!("File discarded, {size} bytes is larger than the limit");
+                    }
+                }
+            }
+            Packet::ErrorUnknownDevice => {
+                let error = ConnectionError::UnknownScreenName(device_name.to_string());
+                return server_error(error);
+            }
+            Packet::ErrorBusy => return server_error(ConnectionError::Busy),
+            Packet::ErrorBadProtocol => return server_error(ConnectionError::BadProtocol),
+            Packet::ErrorIncompatibleVersion { major, minor } => {
+                let error = ConnectionError::IncompatibleVersion { major, minor };
+                return server_error(error);
+            }
+            Packet::Close => return server_error(ConnectionError::Closed),
+            Packet::InfoAck
+            | Packet::GrabClipboard { .. }
+            | Packet::DeviceInfo { .. }
+            | Packet::ClientNoOp => {}
+            Packet::Unknown(cmd) => {
+                debug!(
+                    "Unknown packet: {}",
+                    core::str::from_utf8(&cmd).unwrap_or("????")
+                );
+            }
+        }
+    }
+    Err(ConnectionError::Disconnected)
+}
+
+// The server closes the connection after sending an error
+fn server_error(error: ConnectionError) -> Result<(), ConnectionError> {
+    warn!("Server closed the connection: {}", error);
+    Err(error)
+}
+
+// The mock server is async, the client runs on a blocking thread next to it
+#[cfg(all(test, feature = "tokio"))]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        test_server::{MockServer, MockSession, Packet, Recorder, Step},
+        ConnectionError, KeyMask,
+    };
+
+    async fn run(script: Vec<Step>) -> (Result<(), ConnectionError>, MockSession, Vec<String>) {
+        let server = MockServer::bind().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let (result, recorder) = tokio::task::spawn_blocking(move || {
+            let mut recorder = Recorder::default();
+            (super::start(addr, "test", &mut recorder), recorder)
+        })
+        .await
+        .unwrap();
+        (result, served.await.unwrap().unwrap(), recorder.0)
+    }
+
+    #[tokio::test]
+    async fn test_happy_path() {
+        let script = vec![
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Packet::MouseMove { x: -3, y: 4 }.into(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::ScreenSaver { on: true }.into(),
+            Packet::CursorLeave.into(),
+        ];
+        let (result, session, events) = run(script).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(session.screen_name, "test");
+        assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+        assert_eq!(
+            events,
+            [
+                "enter",
+                "abs 10 10",
+                "rel -3 4",
+                "key 97",
+                "screensaver true",
+                "leave"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_echo() {
+        let script = vec![
+            Packet::KeepAlive.into(),
+            Step::Wait(Duration::from_millis(10)),
+            Packet::KeepAlive.into(),
+        ];
+        let (_, session, _) = run(script).await;
+        assert_eq!(session.received, [Packet::KeepAlive, Packet::KeepAlive]);
+    }
+
+    #[tokio::test]
+    async fn test_busy() {
+        let (result, _, events) = run(vec![Packet::ErrorBusy.into()]).await;
+        assert!(matches!(result, Err(ConnectionError::Busy)));
+        assert!(events.is_empty());
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[tokio::test]
+    async fn test_clipboard() {
+        let data = crate::ClipboardData::from_raw(b"Hello".to_vec(), vec![], vec![]);
+        let script = vec![Packet::SetClipboard {
+            id: 0,
+            seq_num: 1,
+            data,
+        }
+        .into()];
+        let (_, _, events) = run(script).await;
+        assert_eq!(events, ["clipboard 0 Hello"]);
+    }
+
+    #[cfg(feature = "barrier-options")]
+    #[tokio::test]
+    async fn test_relative_moves_option() {
+        let option = |value| Packet::SetDeviceOptions([("MDLT".into(), value)].into());
+        let script = vec![
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            option(1).into(),
+            Packet::MouseMoveAbs { x: 20, y: 5 }.into(),
+            option(0).into(),
+            Packet::MouseMoveAbs { x: 30, y: 30 }.into(),
+        ];
+        let (_, _, events) = run(script).await;
+        assert_eq!(events, ["enter", "abs 10 10", "rel 10 -5", "abs 30 30"]);
+    }
+}
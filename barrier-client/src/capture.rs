@@ -0,0 +1,301 @@
+//! Capture of the packets exchanged with the server, to replay a failing session offline.
+//!
+//! The file starts with `BCAP` and a 16-bit version, then has one record per packet: the
+//! direction (`<` received, `>` sent), the time in microseconds since the Unix epoch as a
+//! 64-bit integer, the size of the packet on the wire and the number of bytes kept as 32-bit
+//! integers, all big endian, then the bytes kept. The hello isn't captured.
+
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, BufWriter, Read, Write},
+    path::PathBuf,
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+
+const MAGIC: &[u8; 4] = b"BCAP";
+const VERSION: u16 = 1;
+
+/// Path of the capture file if set, see [`CaptureOptions::from_env`]
+pub const CAPTURE_ENV: &str = "BARRIER_CAPTURE";
+
+// Size prefix, code, id, sequence number, mark and length of the clipboard chunk
+const CLIPBOARD_HEADER_LEN: usize = 4 + 4 + 1 + 4 + 1 + 4;
+
+// Records queued for the writer thread, the next ones are dropped when it falls behind
+const QUEUE_SIZE: usize = 1024;
+
+/// Where and how to capture the packets, set with [`crate::ClientBuilder::capture`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureOptions {
+    path: PathBuf,
+    max_clipboard_len: usize,
+}
+
+impl CaptureOptions {
+    /// Appends to the file at `path`, it's created if needed
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            max_clipboard_len: 256,
+        }
+    }
+
+    /// Options to capture to the file named by the `BARRIER_CAPTURE` environment variable
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(CAPTURE_ENV)
+            .filter(|path| !path.is_empty())
+            .map(Self::new)
+    }
+
+    /// Keep this many bytes of the clipboard data of each chunk, default is 256. The
+    /// truncated chunks can't be replayed, but the clipboard may hold anything.
+    pub fn max_clipboard_len(mut self, len: usize) -> Self {
+        self.max_clipboard_len = len;
+        self
+    }
+
+    /// Opens the file, the records are written by a thread so the client never waits for it
+    pub(crate) fn start(&self) -> io::Result<Capture> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_be_bytes())?;
+        }
+        let (records, rx) = sync_channel(QUEUE_SIZE);
+        let writer = thread::Builder::new()
+            .name("barrier-capture".into())
+            .spawn(move || write_records(BufWriter::new(file), rx))?;
+        debug!("Capturing the packets to {:?}", self.path);
+        Ok(Capture {
+            records: Some(records),
+            writer: Some(writer),
+            max_clipboard_len: self.max_clipboard_len,
+            dropped: 0,
+        })
+    }
+}
+
+// Flushes whenever the queue is empty, until the capture is dropped
+fn write_records<W: Write>(mut out: W, records: Receiver<Vec<u8>>) {
+    while let Ok(record) = records.recv() {
+        let mut result = out.write_all(&record);
+        while let Ok(record) = records.try_recv() {
+            result = result.and_then(|_| out.write_all(&record));
+        }
+        if let Err(e) = result.and_then(|_| out.flush()) {
+            warn!("Cannot write the capture, stopping it: {}", e);
+            return;
+        }
+    }
+}
+
+/// Records the frames of a connection, see [`CaptureOptions`]
+pub(crate) struct Capture {
+    records: Option<SyncSender<Vec<u8>>>,
+    // Only joined by the tests, the thread stops on its own otherwise
+    #[cfg_attr(not(test), allow(dead_code))]
+    writer: Option<JoinHandle<()>>,
+    max_clipboard_len: usize,
+    dropped: usize,
+}
+
+impl Capture {
+    /// Queues a whole packet, size prefix included
+    pub fn record(&mut self, received: bool, frame: &[u8]) {
+        let Some(records) = &self.records else {
+            return;
+        };
+        let kept = match frame.get(4..8) {
+            Some(b"DCLP") => frame
+                .len()
+                .min(CLIPBOARD_HEADER_LEN + self.max_clipboard_len),
+            _ => frame.len(),
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut record = Vec::with_capacity(1 + 8 + 4 + 4 + kept);
+        record.push(if received { b'<' } else { b'>' });
+        record.extend_from_slice(&time.to_be_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        record.extend_from_slice(&(kept as u32).to_be_bytes());
+        record.extend_from_slice(&frame[..kept]);
+        match records.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    warn!("The capture falls behind, dropping packets");
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => self.records = None,
+        }
+    }
+
+    /// Waits for the records queued to be written
+    #[cfg(test)]
+    pub fn finish(mut self) {
+        self.records = None;
+        if let Some(writer) = self.writer.take() {
+            writer.join().unwrap();
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        // The thread writes what is queued and stops on its own
+        if self.dropped > 0 {
+            warn!("{} packets missing from the capture", self.dropped);
+        }
+    }
+}
+
+/// One record of a capture
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// Received from the server, or sent to it
+    pub received: bool,
+    pub time: SystemTime,
+    /// Size of the packet on the wire
+    pub wire_len: usize,
+    /// The packet, size prefix included, possibly truncated
+    pub data: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// The clipboard data has been cut to the capture's `max_clipboard_len`
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.wire_len
+    }
+}
+
+/// Reads all the records of a capture file
+pub fn read_capture<R: Read>(mut capture: R) -> io::Result<Vec<CapturedFrame>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let mut header = [0; 6];
+    capture.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a capture"));
+    }
+    if u16::from_be_bytes([header[4], header[5]]) != VERSION {
+        return Err(invalid("unsupported capture version"));
+    }
+
+    let mut frames = vec![];
+    let mut record = [0; 1 + 8 + 4 + 4];
+    loop {
+        match capture.read_exact(&mut record[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(frames),
+            Err(e) => return Err(e),
+        }
+        capture.read_exact(&mut record[1..])?;
+        let received = match record[0] {
+            b'<' => true,
+            b'>' => false,
+            _ => return Err(invalid("unknown direction")),
+        };
+        let micros = u64::from_be_bytes(record[1..9].try_into().unwrap());
+        let wire_len = u32::from_be_bytes(record[9..13].try_into().unwrap()) as usize;
+        let kept = u32::from_be_bytes(record[13..17].try_into().unwrap()) as usize;
+        if kept > wire_len {
+            return Err(invalid("record longer than its packet"));
+        }
+        let mut data = vec![0; kept];
+        capture.read_exact(&mut data)?;
+        frames.push(CapturedFrame {
+            received,
+            time: UNIX_EPOCH + Duration::from_micros(micros),
+            wire_len,
+            data,
+        });
+    }
+}
+
+/// The first bytes of a packet in hex, for the trace logs
+pub(crate) struct HexDump<'a>(pub &'a [u8]);
+
+// Enough for the fields of every packet but the clipboard and the files
+const HEX_DUMP_LEN: usize = 64;
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().take(HEX_DUMP_LEN).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        if self.0.len() > HEX_DUMP_LEN {
+            write!(f, " ... ({} more)", self.0.len() - HEX_DUMP_LEN)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_capture, CaptureOptions, HexDump};
+    use crate::Packet;
+
+    #[test]
+    fn test_capture() {
+        let path = std::env::temp_dir().join(format!("barrier-capture-{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let mut capture = CaptureOptions::new(&path)
+            .max_clipboard_len(2)
+            .start()
+            .unwrap();
+        let mut frame = vec![];
+        Packet::QueryInfo.encode(&mut frame);
+        capture.record(true, &frame);
+        let mut keepalive = vec![];
+        Packet::KeepAlive.encode(&mut keepalive);
+        capture.record(false, &keepalive);
+        // A clipboard chunk with 5 bytes of data
+        let mut chunk = vec![0, 0, 0, 19];
+        chunk.extend_from_slice(b"DCLP");
+        chunk.extend_from_slice(&[0, 0, 0, 0, 1, 2, 0, 0, 0, 5]);
+        chunk.extend_from_slice(b"Hello");
+        capture.record(true, &chunk);
+        capture.finish();
+
+        let frames = read_capture(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].received);
+        assert_eq!(frames[0].data, frame);
+        assert!(!frames[0].is_truncated());
+        assert!(!frames[1].received);
+        assert_eq!(frames[1].data, keepalive);
+        assert!(frames[2].is_truncated());
+        assert_eq!(frames[2].wire_len, chunk.len());
+        assert_eq!(frames[2].data, chunk[..chunk.len() - 3]);
+    }
+
+    #[test]
+    fn test_read_capture() {
+        assert!(read_capture(&b"PCAP\x00\x01"[..]).is_err());
+        assert!(read_capture(&b"BCAP\x00\x01"[..]).unwrap().is_empty());
+        // Cut in the middle of a record
+        assert!(read_capture(&b"BCAP\x00\x01<\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(HexDump(&[0, 0x1f, 0xff]).to_string(), "00 1f ff");
+        let dump = HexDump(&[0xab; 100]).to_string();
+        assert!(dump.starts_with("ab ab"));
+        assert!(dump.ends_with("ab ... (36 more)"));
+    }
+}
@@ -0,0 +1,348 @@
+use log::{debug, error};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+#[cfg(feature = "clipboard")]
+use crate::ClipboardData;
+use crate::{
+    actuator::offset_position, Actuator, ActuatorError, ActuatorMessage, Edge, KeyMask, LedState,
+};
+
+/// What [`ChannelActuator`] does with a message when the channel is full
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OnFull {
+    /// Drop the message, fine if losing a few moves is better than lagging behind
+    #[default]
+    Drop,
+    /// Drop the message and keep the error, see [`ChannelActuator::take_error`]
+    Error,
+}
+
+/// Forwards every callback as an [`ActuatorMessage`], e.g. to pass the events on to another
+/// device, [`dispatch`] applies them to an actuator on the receiving side.
+pub struct ChannelActuator {
+    screen_width: u16,
+    screen_height: u16,
+    cursor_x: u16,
+    cursor_y: u16,
+    tx: Sender<ActuatorMessage>,
+    on_full: OnFull,
+    error: Option<TrySendError<ActuatorMessage>>,
+}
+
+impl ChannelActuator {
+    pub fn new(screen_width: u16, screen_height: u16, tx: Sender<ActuatorMessage>) -> Self {
+        Self {
+            screen_width,
+            screen_height,
+            cursor_x: 0,
+            cursor_y: 0,
+            tx,
+            on_full: OnFull::default(),
+            error: None,
+        }
+    }
+
+    pub fn on_full(mut self, on_full: OnFull) -> Self {
+        self.on_full = on_full;
+        self
+    }
+
+    /// The first message that couldn't be sent since the last call, a closed channel is
+    /// always reported, and also returned as [`ActuatorError::Closed`]
+    pub fn take_error(&mut self) -> Option<TrySendError<ActuatorMessage>> {
+        self.error.take()
+    }
+
+    fn send(&mut self, msg: ActuatorMessage) -> Result<(), ActuatorError> {
+        match self.tx.try_send(msg) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(msg)) if self.on_full == OnFull::Drop => {
+                debug!("Channel full, dropping {:?}", msg);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Cannot send actuator message: {}", e);
+                let closed = matches!(e, TrySendError::Closed(_));
+                self.error.get_or_insert(e);
+                if closed {
+                    Err(ActuatorError::Closed)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+impl Actuator for ChannelActuator {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Connected)
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Disconnected)
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        (self.screen_width, self.screen_height)
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        (self.cursor_x, self.cursor_y)
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::SetCursorPosition { x, y })?;
+        self.cursor_x = x;
+        self.cursor_y = y;
+        Ok(())
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MoveCursor { x, y })?;
+        (self.cursor_x, self.cursor_y) = offset_position(
+            (self.cursor_x, self.cursor_y),
+            (self.screen_width, self.screen_height),
+            x,
+            y,
+        );
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MouseDown { button })
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MouseUp { button })
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MouseWheel { x, y })
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::KeyDown { key, mask, button })
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::KeyRepeat {
+            key,
+            mask,
+            button,
+            count,
+        })
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::KeyUp { key, mask, button })
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn set_options(&mut self, opts: crate::ServerOptions) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::SetOptions { opts })
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::ResetOptions)
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Enter)
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Leave)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        for msg in clipboard_messages(data) {
+            self.send(msg)?;
+        }
+        Ok(())
+    }
+
+    fn led_state_changed(&mut self, state: LedState) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::LedStateChanged { state })
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::ScreenSaver { on })
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::GameButtons { id, buttons })
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::GameSticks { id, left, right })
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::GameTriggers { id, left, right })
+    }
+
+    fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::EdgeReached { edge })
+    }
+
+    fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::LanguageSync { languages })
+    }
+}
+
+/// One message per format present
+#[cfg(feature = "clipboard")]
+pub(crate) fn clipboard_messages(data: ClipboardData) -> Vec<ActuatorMessage> {
+    let mut messages = vec![];
+    if let Some(data) = data.text() {
+        messages.push(ActuatorMessage::SetClipboardText { data });
+    }
+    if let Some(data) = data.html() {
+        messages.push(ActuatorMessage::SetClipboardHtml { data });
+    }
+    if let Some(data) = data.bitmap() {
+        messages.push(ActuatorMessage::SetClipboardBitmap {
+            data: data.to_vec(),
+        });
+    }
+    messages
+}
+
+/// Applies a message received from a [`ChannelActuator`] to `actor`.
+///
+/// Each clipboard format arrives in its own message and is set on its own.
+pub async fn dispatch<A: Actuator>(
+    msg: ActuatorMessage,
+    actor: &mut A,
+) -> Result<(), ActuatorError> {
+    match msg {
+        ActuatorMessage::Connected => actor.connected(),
+        ActuatorMessage::Disconnected => actor.disconnected(),
+        ActuatorMessage::SetCursorPosition { x, y } => actor.set_cursor_position(x, y),
+        ActuatorMessage::MoveCursor { x, y } => actor.move_cursor(x, y),
+        ActuatorMessage::MouseDown { button } => actor.mouse_down(button),
+        ActuatorMessage::MouseUp { button } => actor.mouse_up(button),
+        ActuatorMessage::MouseWheel { x, y } => actor.mouse_wheel(x, y),
+        ActuatorMessage::KeyDown { key, mask, button } => actor.key_down(key, mask, button),
+        ActuatorMessage::KeyRepeat {
+            key,
+            mask,
+            button,
+            count,
+        } => actor.key_repeat(key, mask, button, count),
+        ActuatorMessage::KeyUp { key, mask, button } => actor.key_up(key, mask, button),
+        ActuatorMessage::Enter => actor.enter(),
+        ActuatorMessage::Leave => actor.leave(),
+        #[cfg(feature = "barrier-options")]
+        ActuatorMessage::SetOptions { opts } => actor.set_options(opts),
+        #[cfg(feature = "barrier-options")]
+        ActuatorMessage::ResetOptions => actor.reset_options(),
+        #[cfg(feature = "clipboard")]
+        ActuatorMessage::SetClipboardText { data } => {
+            actor.set_clipboard(ClipboardData::from(data))
+        }
+        #[cfg(feature = "clipboard")]
+        ActuatorMessage::SetClipboardHtml { data } => {
+            actor.set_clipboard(ClipboardData::builder().html(data).build())
+        }
+        #[cfg(feature = "clipboard")]
+        ActuatorMessage::SetClipboardBitmap { data } => {
+            actor.set_clipboard(ClipboardData::builder().bitmap(data).build())
+        }
+        ActuatorMessage::LedStateChanged { state } => actor.led_state_changed(state),
+        ActuatorMessage::ScreenSaver { on } => actor.screensaver(on),
+        ActuatorMessage::GameButtons { id, buttons } => actor.game_buttons(id, buttons),
+        ActuatorMessage::GameSticks { id, left, right } => actor.game_sticks(id, left, right),
+        ActuatorMessage::GameTriggers { id, left, right } => actor.game_triggers(id, left, right),
+        ActuatorMessage::EdgeReached { edge } => actor.edge_reached(edge),
+        ActuatorMessage::LanguageSync { languages } => actor.language_sync(languages),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc::{channel, error::TrySendError};
+
+    use super::{dispatch, ChannelActuator, OnFull};
+    use crate::{
+        test_server::{drive, Recorder},
+        Actuator, ActuatorError, ActuatorMessage,
+    };
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let (tx, mut rx) = channel(64);
+        let mut actuator = ChannelActuator::new(1920, 1080, tx);
+        drive(&mut actuator);
+        assert_eq!(actuator.get_cursor_position(), (90, 205));
+        drop(actuator);
+
+        let mut recorder = Recorder::default();
+        while let Some(msg) = rx.recv().await {
+            let json = serde_json::to_string(&msg).unwrap();
+            let msg: ActuatorMessage = serde_json::from_str(&json).unwrap();
+            dispatch(msg, &mut recorder).await.unwrap();
+        }
+
+        let mut expected = Recorder::default();
+        drive(&mut expected);
+        assert_eq!(recorder.0, expected.0);
+    }
+
+    #[tokio::test]
+    async fn test_position_at_edges() {
+        let (tx, _rx) = channel(64);
+        let mut actuator = ChannelActuator::new(1920, 1080, tx);
+        actuator.move_cursor(-10, 5).unwrap();
+        assert_eq!(actuator.get_cursor_position(), (0, 5));
+        actuator.set_cursor_position(1900, 1000).unwrap();
+        actuator.move_cursor(100, 100).unwrap();
+        assert_eq!(actuator.get_cursor_position(), (1919, 1079));
+        // No drift coming back
+        actuator.move_cursor(-19, -79).unwrap();
+        assert_eq!(actuator.get_cursor_position(), (1900, 1000));
+    }
+
+    #[tokio::test]
+    async fn test_channel_full() {
+        let (tx, _rx) = channel(1);
+        let mut actuator = ChannelActuator::new(1920, 1080, tx);
+        actuator.enter().unwrap();
+        actuator.leave().unwrap();
+        assert!(actuator.take_error().is_none());
+
+        let (tx, _rx) = channel(1);
+        let mut actuator = ChannelActuator::new(1920, 1080, tx).on_full(OnFull::Error);
+        actuator.enter().unwrap();
+        actuator.mouse_down(1).unwrap();
+        actuator.mouse_up(1).unwrap();
+        assert!(matches!(
+            actuator.take_error(),
+            Some(TrySendError::Full(ActuatorMessage::MouseDown { button: 1 }))
+        ));
+        assert!(actuator.take_error().is_none());
+
+        let (tx, rx) = channel(1);
+        let mut actuator = ChannelActuator::new(1920, 1080, tx);
+        drop(rx);
+        assert!(matches!(actuator.enter(), Err(ActuatorError::Closed)));
+        assert!(matches!(
+            actuator.take_error(),
+            Some(TrySendError::Closed(ActuatorMessage::Enter))
+        ));
+    }
+}
@@ -0,0 +1,560 @@
+//! Keeps clicks on the spot for targets that lose double clicks when the absolute position
+//! jitters between them, and optionally locks the button held for drags.
+
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "clipboard")]
+use crate::ClipboardData;
+#[cfg(feature = "barrier-options")]
+use crate::ServerOptions;
+use crate::{Actuator, ActuatorError, Edge, KeyMask, LedState, ServerInfo};
+
+/// Thresholds of [`ClickAssist`], the default passes everything through
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClickAssistOptions {
+    /// Absolute moves of at most this many pixels from where a button was pressed or
+    /// released are dropped within `window`, 0 disables it
+    pub jitter: u16,
+    /// How long the cursor sticks after a press or release, and the longest time between the
+    /// clicks of a drag lock
+    pub window: Duration,
+    /// A click followed by a press keeps the button held until it's released once the
+    /// cursor stopped moving for `window`
+    pub drag_lock: bool,
+}
+
+impl Default for ClickAssistOptions {
+    fn default() -> Self {
+        Self {
+            jitter: 0,
+            window: Duration::from_millis(500),
+            drag_lock: false,
+        }
+    }
+}
+
+// Last press or release, the cursor sticks until the window is over or it moves away
+#[derive(Clone, Copy, Debug)]
+struct ButtonEvent {
+    button: i8,
+    down: bool,
+    at: Instant,
+}
+
+/// Wraps an actuator and conditions the mouse buttons and the absolute moves around them,
+/// see [`ClickAssistOptions`]. Everything else is passed through untouched.
+pub struct ClickAssist<A> {
+    inner: A,
+    options: ClickAssistOptions,
+    last_button: Option<ButtonEvent>,
+    // Last absolute position forwarded, `None` after a relative move
+    position: Option<(u16, u16)>,
+    // Position dropped as jitter, forwarded with the next event after the window
+    pending: Option<(u16, u16)>,
+    // Release ending a click, a press of the same button soon after locks it
+    clicked: Option<ButtonEvent>,
+    // Button held by the drag lock and when it was locked
+    locked: Option<(i8, Instant)>,
+    moved_at: Option<Instant>,
+}
+
+impl<A: Actuator> ClickAssist<A> {
+    pub fn new(inner: A, options: ClickAssistOptions) -> Self {
+        Self {
+            inner,
+            options,
+            last_button: None,
+            position: None,
+            pending: None,
+            clicked: None,
+            locked: None,
+            moved_at: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Applies new thresholds, a button held by the drag lock stays held until released
+    pub fn set_click_options(&mut self, options: ClickAssistOptions) {
+        self.options = options;
+    }
+
+    fn within_window(&self, at: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(at) < self.options.window
+    }
+
+    fn sticky(&self, now: Instant) -> bool {
+        self.last_button
+            .is_some_and(|event| self.within_window(event.at, now))
+    }
+
+    fn reset(&mut self) {
+        self.last_button = None;
+        self.position = None;
+        self.pending = None;
+        self.clicked = None;
+        self.locked = None;
+        self.moved_at = None;
+    }
+
+    // The position dropped as jitter, once the cursor doesn't stick anymore
+    fn flush_pending(&mut self, now: Instant) -> Result<(), ActuatorError> {
+        if self.sticky(now) {
+            return Ok(());
+        }
+        match self.pending.take() {
+            Some((x, y)) => {
+                self.position = Some((x, y));
+                self.inner.set_cursor_position(x, y)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// [`Actuator::set_cursor_position`] received at `now`
+    pub fn set_cursor_position_at(
+        &mut self,
+        x: u16,
+        y: u16,
+        now: Instant,
+    ) -> Result<(), ActuatorError> {
+        if self.options.jitter > 0 && self.sticky(now) {
+            let (cx, cy) = self
+                .position
+                .unwrap_or_else(|| self.inner.get_cursor_position());
+            if cx.abs_diff(x).max(cy.abs_diff(y)) <= self.options.jitter {
+                self.pending = Some((x, y));
+                return Ok(());
+            }
+        }
+        // Moved away, the next press isn't a double click
+        self.last_button = None;
+        self.clicked = None;
+        self.pending = None;
+        self.moved_at = Some(now);
+        self.position = Some((x, y));
+        self.inner.set_cursor_position(x, y)
+    }
+
+    /// [`Actuator::mouse_down`] received at `now`
+    pub fn mouse_down_at(&mut self, button: i8, now: Instant) -> Result<(), ActuatorError> {
+        self.flush_pending(now)?;
+        self.last_button = Some(ButtonEvent {
+            button,
+            down: true,
+            at: now,
+        });
+        if self.locked.is_some_and(|(locked, _)| locked == button) {
+            // Still held on the target
+            return Ok(());
+        }
+        let clicked = self.clicked.take();
+        if self.options.drag_lock
+            && clicked.is_some_and(|up| up.button == button && self.within_window(up.at, now))
+        {
+            self.locked = Some((button, now));
+        }
+        self.inner.mouse_down(button)
+    }
+
+    /// [`Actuator::mouse_up`] received at `now`
+    pub fn mouse_up_at(&mut self, button: i8, now: Instant) -> Result<(), ActuatorError> {
+        self.flush_pending(now)?;
+        if let Some((locked, locked_at)) = self.locked.filter(|(locked, _)| *locked == button) {
+            let moving = self
+                .moved_at
+                .is_some_and(|at| at >= locked_at && self.within_window(at, now));
+            if moving {
+                self.last_button = None;
+                return Ok(());
+            }
+            self.locked = None;
+            self.last_button = Some(ButtonEvent {
+                button: locked,
+                down: false,
+                at: now,
+            });
+            return self.inner.mouse_up(button);
+        }
+        let press = self.last_button.filter(|event| {
+            event.button == button && event.down && self.within_window(event.at, now)
+        });
+        let release = ButtonEvent {
+            button,
+            down: false,
+            at: now,
+        };
+        self.clicked = press.map(|_| release);
+        self.last_button = Some(release);
+        self.inner.mouse_up(button)
+    }
+}
+
+impl<A: Actuator> Actuator for ClickAssist<A> {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        self.inner.connected()
+    }
+
+    fn connected_with(&mut self, info: ServerInfo) -> Result<(), ActuatorError> {
+        self.inner.connected_with(info)
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.reset();
+        self.inner.disconnected()
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        self.inner.get_screen_size()
+    }
+
+    /// Where the server has the cursor, the position dropped as jitter included
+    fn get_cursor_position(&self) -> (u16, u16) {
+        self.pending
+            .unwrap_or_else(|| self.inner.get_cursor_position())
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.set_cursor_position_at(x, y, Instant::now())
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        // Relative moves have no jitter to drop
+        self.flush_pending(Instant::now())?;
+        self.moved_at = Some(Instant::now());
+        self.position = None;
+        self.inner.move_cursor(x, y)
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.mouse_down_at(button, Instant::now())
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.mouse_up_at(button, Instant::now())
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.inner.mouse_wheel(x, y)
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.inner.key_down(key, mask, button)
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.inner.key_repeat(key, mask, button, count)
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.inner.key_up(key, mask, button)
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        self.inner.set_options(opts)
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.inner.reset_options()
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.reset();
+        self.inner.enter()
+    }
+
+    fn enter_at(&mut self, x: u16, y: u16, mask: KeyMask) -> Result<(), ActuatorError> {
+        self.reset();
+        self.position = Some((x, y));
+        self.inner.enter_at(x, y, mask)
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        // The server has the button released already
+        if let Some((button, _)) = self.locked {
+            self.inner.mouse_up(button)?;
+        }
+        self.reset();
+        self.inner.leave()
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.inner.set_clipboard(data)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard_with(&mut self, id: u8, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.inner.set_clipboard_with(id, data)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn get_clipboard(&mut self, id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        self.inner.get_clipboard(id)
+    }
+
+    #[cfg(feature = "file-transfer")]
+    fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        self.inner.file_received(name_hint, data)
+    }
+
+    fn led_state_changed(&mut self, state: LedState) -> Result<(), ActuatorError> {
+        self.inner.led_state_changed(state)
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.inner.screensaver(on)
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.inner.game_buttons(id, buttons)
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.inner.game_sticks(id, left, right)
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.inner.game_triggers(id, left, right)
+    }
+
+    fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.inner.edge_reached(edge)
+    }
+
+    fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.inner.language_sync(languages)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{ClickAssist, ClickAssistOptions};
+    use crate::{test_server::Recorder, Actuator, KeyMask};
+
+    enum Event {
+        Move(u16, u16),
+        Down,
+        Up,
+        Wheel,
+        Key,
+    }
+    use Event::*;
+
+    // Replays the events at their time in milliseconds, returns what reaches the actuator
+    fn run(options: ClickAssistOptions, script: &[(u64, Event)]) -> Vec<String> {
+        let start = Instant::now();
+        let mut assist = ClickAssist::new(Recorder::default(), options);
+        for (ms, event) in script {
+            let now = start + Duration::from_millis(*ms);
+            match event {
+                Move(x, y) => assist.set_cursor_position_at(*x, *y, now),
+                Down => assist.mouse_down_at(1, now),
+                Up => assist.mouse_up_at(1, now),
+                Wheel => assist.mouse_wheel(0, 120),
+                Key => assist.key_down(0x61, KeyMask::empty(), 38),
+            }
+            .unwrap();
+        }
+        assist.into_inner().0
+    }
+
+    fn jitter() -> ClickAssistOptions {
+        ClickAssistOptions {
+            jitter: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_double_click_jitter() {
+        let script = [
+            (0, Move(100, 100)),
+            (10, Down),
+            (20, Move(101, 100)),
+            (80, Up),
+            (100, Move(100, 101)),
+            (150, Down),
+            (160, Move(102, 99)),
+            (200, Up),
+            // Over the window, the moves are forwarded again
+            (800, Move(101, 101)),
+            (900, Move(110, 100)),
+        ];
+        assert_eq!(
+            run(jitter(), &script),
+            [
+                "abs 100 100",
+                "down 1",
+                "up 1",
+                "down 1",
+                "up 1",
+                "abs 101 101",
+                "abs 110 100",
+            ]
+        );
+        // Passed through by default
+        assert_eq!(
+            run(ClickAssistOptions::default(), &script[..4]),
+            ["abs 100 100", "down 1", "abs 101 100", "up 1"]
+        );
+    }
+
+    #[test]
+    fn test_drag_not_held_back() {
+        let script = [
+            (0, Move(100, 100)),
+            (10, Down),
+            (20, Move(101, 100)),
+            // Moved away, the following moves aren't jitter anymore
+            (30, Move(110, 100)),
+            (40, Move(111, 100)),
+            (50, Up),
+            (60, Move(111, 101)),
+            // The press is late, the position dropped is forwarded first
+            (600, Down),
+        ];
+        assert_eq!(
+            run(jitter(), &script),
+            [
+                "abs 100 100",
+                "down 1",
+                "abs 110 100",
+                "abs 111 100",
+                "up 1",
+                "abs 111 101",
+                "down 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pass_through() {
+        let script = [
+            (0, Move(100, 100)),
+            (10, Down),
+            (20, Wheel),
+            (30, Key),
+            (40, Move(101, 100)),
+            (50, Up),
+        ];
+        assert_eq!(
+            run(jitter(), &script),
+            ["abs 100 100", "down 1", "wheel 0 120", "key 97", "up 1"]
+        );
+    }
+
+    #[test]
+    fn test_drag_lock() {
+        let options = ClickAssistOptions {
+            drag_lock: true,
+            ..Default::default()
+        };
+        let script = [
+            (0, Move(100, 100)),
+            (10, Down),
+            (50, Up),
+            // Click and press, locked
+            (150, Down),
+            (200, Move(200, 100)),
+            (250, Move(300, 100)),
+            // Released while moving, still held
+            (260, Up),
+            (400, Move(400, 100)),
+            // The press is swallowed, the release once the cursor stopped isn't
+            (1000, Down),
+            (1050, Up),
+        ];
+        assert_eq!(
+            run(options.clone(), &script),
+            [
+                "abs 100 100",
+                "down 1",
+                "up 1",
+                "down 1",
+                "abs 200 100",
+                "abs 300 100",
+                "abs 400 100",
+                "up 1",
+            ]
+        );
+
+        // A double click without moving stays a double click
+        let script = [
+            (0, Move(100, 100)),
+            (10, Down),
+            (50, Up),
+            (150, Down),
+            (200, Up),
+        ];
+        assert_eq!(
+            run(options.clone(), &script),
+            ["abs 100 100", "down 1", "up 1", "down 1", "up 1"]
+        );
+
+        // Too slow for a lock
+        let script = [
+            (10, Down),
+            (50, Up),
+            (700, Down),
+            (710, Move(200, 100)),
+            (720, Up),
+        ];
+        assert_eq!(
+            run(options, &script),
+            ["down 1", "up 1", "down 1", "abs 200 100", "up 1"]
+        );
+    }
+
+    #[test]
+    fn test_leave_releases_lock() {
+        let mut assist = ClickAssist::new(
+            Recorder::default(),
+            ClickAssistOptions {
+                drag_lock: true,
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assist.mouse_down_at(1, at(0)).unwrap();
+        assist.mouse_up_at(1, at(10)).unwrap();
+        assist.mouse_down_at(1, at(20)).unwrap();
+        assist.set_cursor_position_at(200, 100, at(30)).unwrap();
+        assist.mouse_up_at(1, at(40)).unwrap();
+        assist.leave().unwrap();
+        assert_eq!(
+            assist.into_inner().0,
+            ["down 1", "up 1", "down 1", "abs 200 100", "up 1", "leave"]
+        );
+    }
+}
@@ -1,241 +1,1662 @@
-use log::{debug, error};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "barrier-options")]
+use log::info;
+use log::{debug, error, warn};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpStream, ToSocketAddrs},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch, Mutex,
+    },
+    task::{JoinError, JoinHandle},
+    time::{self, sleep, timeout_at},
 };
 
+#[cfg(feature = "clipboard")]
+use barrier_proto::CLIPBOARD_COUNT;
+#[cfg(feature = "file-transfer")]
+use barrier_proto::{file_name, FileStage, FileTransfer};
+
+#[cfg(feature = "clipboard")]
+use crate::codec::DEFAULT_MAX_CLIPBOARD_SIZE;
+#[cfg(feature = "file-transfer")]
+use crate::codec::DEFAULT_MAX_FILE_SIZE;
+#[cfg(feature = "socks")]
+use crate::proxy::{connect_proxy, Proxy};
+#[cfg(feature = "tls")]
+use crate::tls::{connect_tls, TlsOptions};
 #[cfg(feature = "async-actuator")]
-use crate::actuator::AsyncActuator;
+use crate::{actuator::AsyncActuator, adapter::AsyncAdapter};
+#[cfg(feature = "barrier-options")]
+use crate::{options::relative_move, ServerModes, ServerOptions};
+
+use super::{
+    adapter::{ActuatorAdapter, SyncAdapter},
+    codec::{
+        check_screen_name, encode_hello, hello_size, parse_hello, DEFAULT_MAX_PACKET_SIZE,
+        KEEPALIVES_UNTIL_DEATH, KEEPALIVE_INTERVAL, MAX_HELLO_SIZE,
+    },
+    edge::EdgeDetector,
+    metrics::MetricsHook,
+    reconnect::StatusCallback,
+    Actuator, ActuatorError, ActuatorErrorAction, Backoff, CaptureOptions, ClientStatus,
+    ConnectionError, ConnectionState, KeyMask, Metrics, Packet, PacketError, PacketReader,
+    PacketStream, PacketWriter, ServerInfo, ServerList,
+};
+
+/// Default of [`ClientBuilder::write_timeout`]
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default of [`ClientBuilder::handshake_timeout`]
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default of [`ClientBuilder::connect_timeout`]
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Default of [`ClientBuilder::actuator_queue_size`]
+const DEFAULT_ACTUATOR_QUEUE_SIZE: usize = 256;
+
+/// TCP keep-alive probes on the connection to the server. They find a server that crashed
+/// or lost power, the connection is then half-open and nothing else may notice for hours.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// Time the connection is idle before the first probe, default is 10 seconds
+    pub idle: Duration,
+    /// Time between probes not answered, default is 5 seconds. Not set on every platform.
+    pub interval: Duration,
+    /// Probes not answered before the connection is dropped, default is 3. Not set on every
+    /// platform.
+    pub retries: u32,
+}
+
+impl Default for TcpKeepalive {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(10),
+            interval: Duration::from_secs(5),
+            retries: 3,
+        }
+    }
+}
+
+/// What the client does when an actuator callback fails, see
+/// [`ClientBuilder::on_actuator_error`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ActuatorErrorPolicy {
+    /// End the connection with [`ConnectionError::ActuatorError`], the client reconnects if
+    /// enabled
+    #[default]
+    Abort,
+    /// Log the error and go on with the next event, the failed one is lost
+    SkipEvent,
+    /// Call the actuator again up to `attempts` times, `delay` apart, then abort
+    Retry { attempts: u32, delay: Duration },
+}
+
+/// Per connection settings
+#[derive(Clone, Debug)]
+struct SessionOptions {
+    keepalive_timeout: Duration,
+    write_timeout: Duration,
+    handshake_timeout: Duration,
+    coalesce_moves: bool,
+    strict_enter_gating: bool,
+    edge_margin: Option<u16>,
+    #[cfg(feature = "clipboard")]
+    max_clipboard_size: usize,
+    #[cfg(feature = "file-transfer")]
+    max_file_size: usize,
+    max_packet_size: usize,
+    resync: bool,
+    capture: Option<CaptureOptions>,
+    inline_actuator: bool,
+    actuator_queue_size: usize,
+    on_actuator_error: ActuatorErrorPolicy,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            keepalive_timeout: KEEPALIVE_INTERVAL * KEEPALIVES_UNTIL_DEATH,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            coalesce_moves: false,
+            strict_enter_gating: false,
+            edge_margin: None,
+            #[cfg(feature = "clipboard")]
+            max_clipboard_size: DEFAULT_MAX_CLIPBOARD_SIZE,
+            #[cfg(feature = "file-transfer")]
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            resync: true,
+            capture: CaptureOptions::from_env(),
+            inline_actuator: false,
+            actuator_queue_size: DEFAULT_ACTUATOR_QUEUE_SIZE,
+            on_actuator_error: ActuatorErrorPolicy::Abort,
+        }
+    }
+}
+
+/// Barrier client, created with [`ClientBuilder`]
+#[derive(Clone, Debug)]
+pub struct Client {
+    servers: ServerList,
+    screen_name: String,
+    options: SessionOptions,
+    connect_timeout: Duration,
+    tcp_keepalive: Option<TcpKeepalive>,
+    bind: LocalBind,
+    reconnect: bool,
+    backoff: Backoff,
+    on_status: Option<StatusCallback>,
+    metrics: Option<MetricsHook>,
+    #[cfg(feature = "socks")]
+    proxy: Option<Proxy>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsOptions>,
+    // Events sent to the server with a `ClientHandle`
+    upstream: mpsc::Sender<Packet>,
+    upstream_rx: Arc<Mutex<mpsc::Receiver<Packet>>>,
+    // Published by the running client, shared by the clones
+    state: Arc<watch::Sender<ConnectionState>>,
+}
+
+/// Sends events from this screen to the server, e.g. to wake the server screen or to switch
+/// screens with a hotkey typed on the keyboard of the target. Obtained with
+/// [`Client::sender`].
+///
+/// Events are sent once the client is connected and the server acknowledged the screen
+/// info, those queued when the connection is lost are dropped.
+#[derive(Clone, Debug)]
+pub struct ClientHandle {
+    upstream: mpsc::Sender<Packet>,
+    state: watch::Receiver<ConnectionState>,
+}
+
+impl ClientHandle {
+    /// Watches the state of the connection, same as [`Client::state`]
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    pub async fn send_key_down(
+        &self,
+        id: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ConnectionError> {
+        self.send(Packet::KeyDown { id, mask, button }).await
+    }
+
+    pub async fn send_key_up(
+        &self,
+        id: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ConnectionError> {
+        self.send(Packet::KeyUp { id, mask, button }).await
+    }
+
+    /// Presses and releases the key
+    pub async fn send_key(
+        &self,
+        id: u16,
+        mask: KeyMask,
+        button: u16,
+    ) -> Result<(), ConnectionError> {
+        self.send_key_down(id, mask, button).await?;
+        self.send_key_up(id, mask, button).await
+    }
+
+    pub async fn send_mouse_down(&self, button: i8) -> Result<(), ConnectionError> {
+        self.send(Packet::MouseDown { id: button }).await
+    }
+
+    pub async fn send_mouse_up(&self, button: i8) -> Result<(), ConnectionError> {
+        self.send(Packet::MouseUp { id: button }).await
+    }
+
+    /// Moves the cursor to a position in the coordinates of the server
+    pub async fn send_mouse_move(&self, x: u16, y: u16) -> Result<(), ConnectionError> {
+        self.send(Packet::MouseMoveAbs { x, y }).await
+    }
+
+    /// The local clipboard `id` changed, 0 for the clipboard and 1 for the primary selection.
+    /// The server is told this screen owns it, and it's sent with
+    /// [`Actuator::get_clipboard`] when the cursor leaves.
+    #[cfg(feature = "clipboard")]
+    pub async fn clipboard_changed(&self, id: u8) -> Result<(), ConnectionError> {
+        // The sequence number of the last enter is filled in when it's sent
+        self.send(Packet::GrabClipboard { id, seq_num: 0 }).await
+    }
+
+    /// The size returned by `get_screen_size` changed, e.g. a display has been plugged in.
+    /// The server is sent the new screen info without waiting for it to ask again.
+    pub async fn screen_changed(&self) -> Result<(), ConnectionError> {
+        // The size is read from the actuator when it's sent
+        self.send(Packet::DeviceInfo {
+            x: 0,
+            y: 0,
+            w: 0,
+            h: 0,
+            _dummy: 0,
+            mx: 0,
+            my: 0,
+        })
+        .await
+    }
+
+    /// Waits for room in the queue, fails once the client is dropped
+    async fn send(&self, packet: Packet) -> Result<(), ConnectionError> {
+        self.upstream
+            .send(packet)
+            .await
+            .map_err(|_| ConnectionError::Disconnected)
+    }
+}
+
+/// Events queued by the handles before they are written
+const UPSTREAM_QUEUE_SIZE: usize = 64;
+
+/// Packets of the actuator side queued for the writer, the replies of the reader go first
+const WRITE_QUEUE_SIZE: usize = 16;
+
+#[derive(Clone, Debug, Default)]
+pub struct ClientBuilder {
+    servers: Vec<String>,
+    screen_name: Option<String>,
+    keepalive_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    handshake_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    tcp_keepalive: Option<Option<TcpKeepalive>>,
+    local_address: Option<SocketAddr>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    bind_interface: Option<String>,
+    coalesce_moves: bool,
+    strict_enter_gating: bool,
+    edge_margin: Option<u16>,
+    #[cfg(feature = "clipboard")]
+    max_clipboard_size: Option<usize>,
+    #[cfg(feature = "file-transfer")]
+    max_file_size: Option<usize>,
+    max_packet_size: Option<usize>,
+    resync: Option<bool>,
+    capture: Option<CaptureOptions>,
+    inline_actuator: bool,
+    actuator_queue_size: Option<usize>,
+    on_actuator_error: ActuatorErrorPolicy,
+    reconnect: bool,
+    backoff: Option<Backoff>,
+    on_status: Option<StatusCallback>,
+    metrics: Option<MetricsHook>,
+    #[cfg(feature = "socks")]
+    proxy: Option<Proxy>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsOptions>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Barrier server address in "server:port" format, replaces the list set with `servers`
+    pub fn server<S: Into<String>>(mut self, addr: S) -> Self {
+        self.servers = vec![addr.into()];
+        self
+    }
+
+    /// Barrier servers in "server:port" format, e.g. of two computers that are not always
+    /// on. They are tried in turn until one accepts the connection, see [`ServerList`].
+    pub fn servers<I: IntoIterator<Item = S>, S: Into<String>>(mut self, addrs: I) -> Self {
+        self.servers = addrs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Screen name, must be one of the screens of the Barrier server configuration. It can't
+    /// be empty, longer than 255 bytes or contain a NUL character.
+    pub fn screen_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.screen_name = Some(name.into());
+        self
+    }
+
+    /// Drop the connection if nothing is received from the server within this duration,
+    /// default is 3 times the keep-alive interval. The server can change the interval
+    /// with the HBRT option, the timeout then follows it.
+    pub fn keepalive_timeout(mut self, timeout: Duration) -> Self {
+        self.keepalive_timeout = Some(timeout);
+        self
+    }
+
+    /// Drop the connection if a packet can't be sent within this duration, default is 10
+    /// seconds. The send buffer stays full when the server is gone without closing the
+    /// connection, writes would wait forever otherwise.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Drop the connection if the server doesn't reply to the hello within this duration,
+    /// default is 5 seconds. It replies right away with QINF, or with an error such as
+    /// [`ConnectionError::UnknownScreenName`] if it rejects the screen.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up on an address of the server if the connection isn't accepted within this
+    /// duration, default is 3 seconds. The next address the server name resolves to is tried,
+    /// then the next server. With a proxy it's the connection to the proxy.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// TCP keep-alive probes of the connection, `None` turns them off, see
+    /// [`TcpKeepalive::default`] for the defaults. With a proxy they only reach the proxy.
+    pub fn tcp_keepalive(mut self, keepalive: Option<TcpKeepalive>) -> Self {
+        self.tcp_keepalive = Some(keepalive);
+        self
+    }
+
+    /// Bind the connection to this local address before connecting, e.g. to pin it to one of
+    /// the network interfaces, default is to let the routing table pick it. The port is
+    /// usually 0. The server addresses of the same family are tried first, binding fails
+    /// with [`ConnectionError::BindFailed`]. With a proxy it's the connection to the proxy.
+    pub fn local_address(mut self, addr: SocketAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// Bind the connection to this network interface, e.g. "eth0", with `SO_BINDTODEVICE`.
+    /// It requires `CAP_NET_RAW` before Linux 5.7.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn bind_interface<S: Into<String>>(mut self, interface: S) -> Self {
+        self.bind_interface = Some(interface.into());
+        self
+    }
+
+    /// Collapse mouse moves that are already received into one, so the actuator doesn't fall
+    /// behind when the server sends moves faster than they can be applied, default is `false`.
+    /// Other events are never reordered.
+    pub fn coalesce_moves(mut self, coalesce: bool) -> Self {
+        self.coalesce_moves = coalesce;
+        self
+    }
+
+    /// Drop mouse and keyboard events received while the cursor isn't on this screen, from
+    /// connecting until the server sends CINN and after COUT, default is `false`. Clipboard,
+    /// options and keep-alives still go through. Leave it off for Barrier's relative moves
+    /// on secondary screens, which are sent without entering.
+    pub fn strict_enter_gating(mut self, strict: bool) -> Self {
+        self.strict_enter_gating = strict;
+        self
+    }
+
+    /// Call [`Actuator::edge_reached`] when an absolute move brings the cursor within
+    /// `margin` pixels of an edge of the screen, default is off, as is 0. Relative moves aren't
+    /// checked, the client doesn't know where they end.
+    pub fn edge_margin(mut self, margin: u16) -> Self {
+        self.edge_margin = Some(margin);
+        self
+    }
+
+    /// Discard clipboard data larger than this many bytes, default is 1 MiB. With the
+    /// `bounded-clipboard` feature each format is truncated to this size instead, see
+    /// [`ClipboardData::truncated`](crate::ClipboardData::truncated).
+    #[cfg(feature = "clipboard")]
+    pub fn max_clipboard_size(mut self, size: usize) -> Self {
+        self.max_clipboard_size = Some(size);
+        self
+    }
+
+    /// Discard files dragged onto this screen larger than this many bytes, default is 16 MiB
+    #[cfg(feature = "file-transfer")]
+    pub fn max_file_size(mut self, size: usize) -> Self {
+        self.max_file_size = Some(size);
+        self
+    }
+
+    /// Drop the connection when the server sends a packet larger than this many bytes, default
+    /// is 4 MiB, or 64 KiB without the clipboard and file-transfer features. The clipboard and
+    /// files are sent in chunks smaller than this, see `max_clipboard_size` and
+    /// `max_file_size` to limit their total size. With `resync` the client first looks for
+    /// the next packet within this many bytes.
+    pub fn max_packet_size(mut self, size: usize) -> Self {
+        self.max_packet_size = Some(size);
+        self
+    }
+
+    /// Skip to the next packet when the size or the code of the one received can't be right,
+    /// e.g. after a packet was parsed with the wrong length, default is `true`. Otherwise the
+    /// connection is dropped. It is dropped anyway after 16 unknown packets in a row.
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.resync = Some(resync);
+        self
+    }
+
+    /// Append the packets exchanged with the server to a file, to replay a failing session
+    /// with `test_server::replay`. Default is the file named by
+    /// the `BARRIER_CAPTURE` environment variable if set, nothing is captured otherwise.
+    pub fn capture(mut self, capture: CaptureOptions) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Call the actuator from the loop reading the connection, default is `false`. A slow
+    /// call then holds back the reads, and the replies to the keep-alives with them: the
+    /// server drops the client when it takes a few seconds.
+    ///
+    /// Otherwise the packets are read and written by tasks of their own and the actuator
+    /// takes the events from a queue, see `actuator_queue_size`. The query of the screen info
+    /// is then answered with the size the actuator reported when connecting or last sent
+    /// with [`ClientHandle::screen_changed`]. A blocking actuator only leaves the tasks
+    /// running on a multi-threaded runtime.
+    pub fn inline_actuator(mut self, inline: bool) -> Self {
+        self.inline_actuator = inline;
+        self
+    }
+
+    /// Events read and not applied yet by the actuator, default is 256. When the queue is
+    /// full the absolute moves replace each other and the relative ones are added up until
+    /// there is room, reported as dropped to [`Metrics::input_dropped`]. The other events wait
+    /// for room and nothing more is read meanwhile. The events are applied in the order
+    /// they are received either way.
+    pub fn actuator_queue_size(mut self, size: usize) -> Self {
+        self.actuator_queue_size = Some(size);
+        self
+    }
+
+    /// What to do when an actuator callback for an event fails, default is to end the
+    /// connection. Each failure is reported to [`Metrics::actuator_error`] with what was
+    /// done about it.
+    ///
+    /// The errors of the clipboard, file, language and connection callbacks are always logged
+    /// and skipped, they don't stop the input. While waiting to retry nothing else is applied,
+    /// and with `inline_actuator` nothing is read either.
+    pub fn on_actuator_error(mut self, policy: ActuatorErrorPolicy) -> Self {
+        self.on_actuator_error = policy;
+        self
+    }
+
+    /// Reconnect to the server when the connection is lost, default is `false`
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Delays between reconnection attempts, see [`Backoff::default`] for the defaults
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Called when connecting, connected, and before waiting to reconnect
+    pub fn on_status<F: Fn(ClientStatus) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.on_status = Some(StatusCallback(Arc::new(f)));
+        self
+    }
+
+    /// Report the packets received and the time spent in the actuator, see [`Metrics`]
+    pub fn metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(MetricsHook(Arc::new(metrics)));
+        self
+    }
+
+    /// Connect to the server through a proxy, default is to connect directly. TLS, if
+    /// enabled, is negotiated with the server through it.
+    #[cfg(feature = "socks")]
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Wrap the connection in TLS, required if SSL is enabled on the Barrier server
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn build(self) -> Result<Client, ConnectionError> {
+        let default = SessionOptions::default();
+        let (upstream, upstream_rx) = mpsc::channel(UPSTREAM_QUEUE_SIZE);
+        let screen_name = self
+            .screen_name
+            .ok_or(ConnectionError::MissingOption("screen_name"))?;
+        check_screen_name(&screen_name)?;
+        if self.servers.is_empty() {
+            return Err(ConnectionError::MissingOption("server"));
+        }
+        Ok(Client {
+            servers: ServerList::new(self.servers),
+            screen_name,
+            options: SessionOptions {
+                keepalive_timeout: self.keepalive_timeout.unwrap_or(default.keepalive_timeout),
+                write_timeout: self.write_timeout.unwrap_or(default.write_timeout),
+                handshake_timeout: self.handshake_timeout.unwrap_or(default.handshake_timeout),
+                coalesce_moves: self.coalesce_moves,
+                strict_enter_gating: self.strict_enter_gating,
+                edge_margin: self.edge_margin,
+                #[cfg(feature = "clipboard")]
+                max_clipboard_size: self
+                    .max_clipboard_size
+                    .unwrap_or(default.max_clipboard_size),
+                #[cfg(feature = "file-transfer")]
+                max_file_size: self.max_file_size.unwrap_or(default.max_file_size),
+                max_packet_size: self.max_packet_size.unwrap_or(default.max_packet_size),
+                resync: self.resync.unwrap_or(default.resync),
+                capture: self.capture.or(default.capture),
+                inline_actuator: self.inline_actuator,
+                actuator_queue_size: self
+                    .actuator_queue_size
+                    .unwrap_or(default.actuator_queue_size),
+                on_actuator_error: self.on_actuator_error,
+            },
+            connect_timeout: self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            tcp_keepalive: self.tcp_keepalive.unwrap_or(Some(TcpKeepalive::default())),
+            bind: LocalBind {
+                address: self.local_address,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                interface: self.bind_interface,
+            },
+            reconnect: self.reconnect,
+            backoff: self.backoff.unwrap_or_default(),
+            on_status: self.on_status,
+            metrics: self.metrics,
+            #[cfg(feature = "socks")]
+            proxy: self.proxy,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            upstream,
+            upstream_rx: Arc::new(Mutex::new(upstream_rx)),
+            state: Arc::new(watch::Sender::new(ConnectionState::default())),
+        })
+    }
+}
+
+impl Client {
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Handle to send events to the server while the client runs
+    pub fn sender(&self) -> ClientHandle {
+        ClientHandle {
+            upstream: self.upstream.clone(),
+            state: self.state.subscribe(),
+        }
+    }
 
-use super::{Actuator, ConnectionError, Packet, PacketReader, PacketStream, PacketWriter};
+    /// Watches the state of the connection, e.g. to drive a status LED. Can be taken before
+    /// [`Client::run`], the receiver sees every state the client goes through after that
+    /// unless several changes happen before it gets to look.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// The servers the client connects to, with the one it is connected to
+    pub fn servers(&self) -> &ServerList {
+        &self.servers
+    }
+
+    /// Connects to the server and drives the actuator until the connection is closed,
+    /// or until a non-recoverable error occurs if reconnecting is enabled.
+    ///
+    /// The future can be dropped at any time to stop the client, e.g. when a cancellation
+    /// token fires, this also stops waiting to reconnect.
+    pub async fn run<A: Actuator + Send>(&self, actor: &mut A) -> Result<(), ConnectionError> {
+        self.run_adapter(&mut SyncAdapter(actor)).await
+    }
+
+    #[cfg(feature = "async-actuator")]
+    pub async fn run_async<A: AsyncActuator + Send + Sync>(
+        &self,
+        actor: &mut A,
+    ) -> Result<(), ConnectionError> {
+        self.run_adapter(&mut AsyncAdapter(actor)).await
+    }
+
+    async fn run_adapter<H: ActuatorAdapter>(&self, actor: &mut H) -> Result<(), ConnectionError> {
+        // Clones of the client share the handles, only one of them sends the events
+        let mut upstream = self.upstream_rx.try_lock().ok();
+        if upstream.is_none() {
+            warn!("Another clone of the client is running, it sends the events of the handles");
+        }
+        let mut failures = 0;
+        loop {
+            let mut connected_at = None;
+            let result = self
+                .run_once(actor, upstream.as_deref_mut(), &mut connected_at)
+                .await;
+            // Not sent on this connection, they would be stale on the next one
+            if let Some(upstream) = upstream.as_deref_mut() {
+                while upstream.try_recv().is_ok() {}
+            }
+            self.servers.disconnected();
+            set_state(
+                &self.state,
+                ConnectionState::Disconnected {
+                    reason: result.as_ref().err().map(ToString::to_string),
+                },
+            );
+            match result {
+                Err(e) if self.reconnect && !e.is_fatal() => {
+                    if connected_at.is_some_and(|t| t.elapsed() >= self.backoff.reset_after) {
+                        failures = 0;
+                    }
+                    if let ConnectionError::UnknownScreenName(name) = &e {
+                        self.report(ClientStatus::UnknownScreenName(name.clone()));
+                    }
+                    let delay = self.backoff.delay(failures);
+                    failures = failures.saturating_add(1);
+                    warn!(
+                        "Disconnected from the server, error: {:?}, reconnecting in {:?}...",
+                        e, delay
+                    );
+                    self.report(ClientStatus::BackingOff(delay));
+                    sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn run_once<H: ActuatorAdapter>(
+        &self,
+        actor: &mut H,
+        upstream: Option<&mut mpsc::Receiver<Packet>>,
+        connected_at: &mut Option<Instant>,
+    ) -> Result<(), ConnectionError> {
+        self.report(ClientStatus::Connecting);
+        set_state(&self.state, ConnectionState::Connecting);
+        let stream = self.open_stream().await?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let stream = connect_tls(stream, tls).await?;
+            return self
+                .start_session(stream, upstream, actor, connected_at)
+                .await;
+        }
+        self.start_session(stream, upstream, actor, connected_at)
+            .await
+    }
+
+    // Connected once the server's hello is answered, a server accepting the connection but
+    // never saying hello doesn't reset the backoff
+    async fn start_session<
+        H: ActuatorAdapter,
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    >(
+        &self,
+        mut stream: S,
+        upstream: Option<&mut mpsc::Receiver<Packet>>,
+        actor: &mut H,
+        connected_at: &mut Option<Instant>,
+    ) -> Result<(), ConnectionError> {
+        let info = handshake(&mut stream, &self.screen_name, &self.state).await?;
+        *connected_at = Some(Instant::now());
+        self.report(ClientStatus::Connected);
+        serve_session(
+            stream,
+            &self.screen_name,
+            info,
+            &self.options,
+            self.hook(),
+            &self.state,
+            upstream,
+            actor,
+        )
+        .await
+    }
+
+    // Tries the servers in turn, the error of the first one is returned if none of them
+    // accepts the connection
+    async fn open_stream(&self) -> Result<TcpStream, ConnectionError> {
+        let mut error = None;
+        for (index, server) in self.servers.order() {
+            match self.open_server(server).await {
+                Ok(stream) => {
+                    debug!("Connected to {}", server);
+                    self.servers.connected(index);
+                    return Ok(stream);
+                }
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(e) => {
+                    if self.servers.servers().len() > 1 {
+                        warn!("Cannot connect to {}, error: {}", server, e);
+                    }
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        // The builder doesn't take an empty list
+        Err(error.unwrap_or(ConnectionError::MissingOption("server")))
+    }
+
+    async fn open_server(&self, server: &str) -> Result<TcpStream, ConnectionError> {
+        let timeout = Some(self.connect_timeout);
+        #[cfg(feature = "socks")]
+        let stream = match &self.proxy {
+            Some(proxy) => connect_proxy(proxy, server, &self.bind, timeout).await?,
+            None => connect(server, &self.bind, timeout).await?,
+        };
+        #[cfg(not(feature = "socks"))]
+        let stream = connect(server, &self.bind, timeout).await?;
+        if let Some(keepalive) = &self.tcp_keepalive {
+            if let Err(e) = set_tcp_keepalive(&stream, keepalive) {
+                warn!("Cannot set the TCP keep-alive, error: {}", e);
+            }
+        }
+        Ok(stream)
+    }
+
+    fn hook(&self) -> Option<Arc<dyn Metrics>> {
+        self.metrics.as_ref().map(|MetricsHook(m)| m.clone())
+    }
+
+    fn report(&self, status: ClientStatus) {
+        debug!("Client status: {:?}", status);
+        if let Some(StatusCallback(f)) = &self.on_status {
+            f(status);
+        }
+    }
+}
+
+/// Local end of the connection, see [`ClientBuilder::local_address`]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LocalBind {
+    address: Option<SocketAddr>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    interface: Option<String>,
+}
 
-pub async fn start<A: Actuator, Addr: ToSocketAddrs, S: AsRef<str>>(
+/// Tries each address `addr` resolves to, those of the family of the local address first,
+/// giving up on each after `timeout`
+pub(crate) async fn connect<Addr: ToSocketAddrs>(
+    addr: Addr,
+    bind: &LocalBind,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, ConnectionError> {
+    let mut addrs: Vec<_> = lookup_host(addr).await?.collect();
+    if let Some(local) = bind.address {
+        addrs.sort_by_key(|addr| addr.is_ipv4() != local.is_ipv4());
+    }
+    // The first error is kept, the addresses of the other family would only fail to bind
+    let mut error = None;
+    for addr in addrs {
+        let connected = match timeout {
+            Some(timeout) => time::timeout(timeout, connect_addr(addr, bind))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out").into())
+                }),
+            None => connect_addr(addr, bind).await,
+        };
+        match connected {
+            Ok(stream) => {
+                // Turn off Nagle, this may not be available on ESP-IDF, so ignore the error.
+                stream.set_nodelay(true).ok();
+                return Ok(stream);
+            }
+            Err(e) => {
+                debug!("Cannot connect to {}, error: {}", addr, e);
+                error.get_or_insert(e);
+            }
+        }
+    }
+    Err(error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address for the server").into()
+    }))
+}
+
+async fn connect_addr(addr: SocketAddr, bind: &LocalBind) -> Result<TcpStream, ConnectionError> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if let Some(interface) = &bind.interface {
+        socket
+            .bind_device(Some(interface.as_bytes()))
+            .map_err(|source| ConnectionError::BindFailed {
+                local: format!("interface {interface}"),
+                source,
+            })?;
+    }
+    if let Some(local) = bind.address {
+        socket
+            .bind(local)
+            .map_err(|source| ConnectionError::BindFailed {
+                local: local.to_string(),
+                source,
+            })?;
+    }
+    Ok(socket.connect(addr).await?)
+}
+
+fn set_tcp_keepalive(stream: &TcpStream, keepalive: &TcpKeepalive) -> io::Result<()> {
+    let params = socket2::TcpKeepalive::new().with_time(keepalive.idle);
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "windows",
+    ))]
+    let params = params.with_interval(keepalive.interval);
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+    ))]
+    let params = params.with_retries(keepalive.retries);
+    socket2::SockRef::from(stream).set_tcp_keepalive(&params)
+}
+
+async fn handshake<S: AsyncRead + AsyncWrite + Send + Unpin>(
+    stream: &mut S,
+    device_name: &str,
+    state: &watch::Sender<ConnectionState>,
+) -> Result<ServerInfo, ConnectionError> {
+    let size = hello_size(stream.read_packet_size().await?)?;
+    let mut hello = [0; MAX_HELLO_SIZE];
+    let hello = &mut hello[..size];
+    stream.read_exact(hello).await?;
+    let server = parse_hello(hello).inspect_err(|_| {
+        error!("Got invalid hello {:?}", String::from_utf8_lossy(hello));
+    })?;
+
+    // Old servers drop clients newer than them, the newer ones speak the older versions
+    let info = server.negotiated();
+    if info != server {
+        debug!(
+            "Server speaks {}.{}, using {}.{}",
+            server.major, server.minor, info.major, info.minor
+        );
+    }
+    let mut reply = vec![];
+    encode_hello(info, device_name, &mut reply);
+    stream.write_all(&reply).await?;
+    stream.flush().await?;
+    set_state(state, ConnectionState::HelloSent);
+    Ok(info)
+}
+
+pub async fn start<A: Actuator + Send, Addr: ToSocketAddrs, S: AsRef<str>>(
     addr: Addr,
     device_name: S,
     actor: &mut A,
 ) -> Result<(), ConnectionError> {
-    let screen_size: (u16, u16) = actor.get_screen_size();
+    check_screen_name(device_name.as_ref())?;
+    let stream = connect(addr, &LocalBind::default(), None).await?;
+    session(
+        stream,
+        device_name.as_ref(),
+        &SessionOptions::default(),
+        None,
+        &watch::Sender::new(ConnectionState::default()),
+        None,
+        &mut SyncAdapter(actor),
+    )
+    .await
+}
 
-    let mut stream = TcpStream::connect(addr).await?;
-    // Turn off Nagle, this may not be available on ESP-IDF, so ignore the error.
-    stream.set_nodelay(true).ok();
+/// Same as [`start`], but over a connection opened by the caller, e.g. through a tunnel or a
+/// proxy the client doesn't support
+pub async fn start_with_stream<
+    A: Actuator + Send,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    N: AsRef<str>,
+>(
+    stream: S,
+    device_name: N,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    check_screen_name(device_name.as_ref())?;
+    session(
+        stream,
+        device_name.as_ref(),
+        &SessionOptions::default(),
+        None,
+        &watch::Sender::new(ConnectionState::default()),
+        None,
+        &mut SyncAdapter(actor),
+    )
+    .await
+}
 
-    let _size = stream.read_packet_size().await?;
-    if stream.read_bytes_fixed::<7>().await? == [b'B', b'a', b'r', b'r', b'i', b'e', b'r'] {
-        debug!("Got hello");
-    } else {
-        error!("Got invalid hello");
-        return Err(ConnectionError::ProtocolError(
-            crate::error::PacketError::FormatError,
-        ));
-    }
-    let major = stream.read_u16().await?;
-    let minor = stream.read_u16().await?;
-    debug!("Got hello {major}:{minor}");
+/// Same as [`start`], but the connection is wrapped in TLS, as required by Barrier servers with SSL enabled.
+#[cfg(feature = "tls")]
+pub async fn start_tls<A: Actuator + Send, Addr: ToSocketAddrs, S: AsRef<str>>(
+    addr: Addr,
+    device_name: S,
+    tls: &TlsOptions,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    check_screen_name(device_name.as_ref())?;
+    let stream = connect_tls(connect(addr, &LocalBind::default(), None).await?, tls).await?;
+    session(
+        stream,
+        device_name.as_ref(),
+        &SessionOptions::default(),
+        None,
+        &watch::Sender::new(ConnectionState::default()),
+        None,
+        &mut SyncAdapter(actor),
+    )
+    .await
+}
 
-    stream
-        .write_u32("Barrier".len() as u32 + 2 + 2 + 4 + device_name.as_ref().bytes().len() as u32)
-        .await?;
-    stream.write_all(b"Barrier").await?;
-    stream.write_u16(1).await?;
-    stream.write_u16(6).await?;
-    stream.write_str(device_name.as_ref()).await?;
+#[cfg(feature = "async-actuator")]
+pub async fn start_async<A: AsyncActuator + Send + Sync, Addr: ToSocketAddrs, S: AsRef<str>>(
+    addr: Addr,
+    device_name: S,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    check_screen_name(device_name.as_ref())?;
+    let stream = connect(addr, &LocalBind::default(), None).await?;
+    session(
+        stream,
+        device_name.as_ref(),
+        &SessionOptions::default(),
+        None,
+        &watch::Sender::new(ConnectionState::default()),
+        None,
+        &mut AsyncAdapter(actor),
+    )
+    .await
+}
 
-    actor.connected();
+/// Same as [`start_async`], but the connection is wrapped in TLS.
+#[cfg(all(feature = "async-actuator", feature = "tls"))]
+pub async fn start_tls_async<A: AsyncActuator + Send + Sync, Addr: ToSocketAddrs, S: AsRef<str>>(
+    addr: Addr,
+    device_name: S,
+    tls: &TlsOptions,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    check_screen_name(device_name.as_ref())?;
+    let stream = connect_tls(connect(addr, &LocalBind::default(), None).await?, tls).await?;
+    session(
+        stream,
+        device_name.as_ref(),
+        &SessionOptions::default(),
+        None,
+        &watch::Sender::new(ConnectionState::default()),
+        None,
+        &mut AsyncAdapter(actor),
+    )
+    .await
+}
 
-    #[cfg(feature = "clipboard")]
-    let mut clipboard_stage = crate::ClipboardStage::None;
+async fn session<H: ActuatorAdapter, S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    mut stream: S,
+    device_name: &str,
+    options: &SessionOptions,
+    metrics: Option<Arc<dyn Metrics>>,
+    state: &watch::Sender<ConnectionState>,
+    upstream: Option<&mut mpsc::Receiver<Packet>>,
+    actor: &mut H,
+) -> Result<(), ConnectionError> {
+    let info = handshake(&mut stream, device_name, state).await?;
+    serve_session(
+        stream,
+        device_name,
+        info,
+        options,
+        metrics,
+        state,
+        upstream,
+        actor,
+    )
+    .await
+}
 
-    let mut packet_stream = PacketStream::new(stream);
-    while let Ok(packet) = packet_stream
-        .read(
-            #[cfg(feature = "clipboard")]
-            &mut clipboard_stage,
+// The session after the handshake
+#[allow(clippy::too_many_arguments)]
+async fn serve_session<H: ActuatorAdapter, S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    stream: S,
+    device_name: &str,
+    info: ServerInfo,
+    options: &SessionOptions,
+    metrics: Option<Arc<dyn Metrics>>,
+    state: &watch::Sender<ConnectionState>,
+    upstream: Option<&mut mpsc::Receiver<Packet>>,
+    actor: &mut H,
+) -> Result<(), ConnectionError> {
+    // The events may still go through, e.g. the device only attaches later
+    if let Err(e) = actor.connected(info).await {
+        skipped(metrics.as_deref(), "connected", e);
+    }
+    let result = if options.inline_actuator {
+        dispatch_packets(
+            stream,
+            device_name,
+            info,
+            options,
+            metrics.clone(),
+            state,
+            upstream,
+            actor,
         )
         .await
-    {
+    } else {
+        dispatch_queued(
+            stream,
+            device_name,
+            info,
+            options,
+            metrics.clone(),
+            state,
+            upstream,
+            actor,
+        )
+        .await
+    };
+    // Also after an actuator error, to release what is held
+    if let Err(e) = actor.disconnected().await {
+        skipped(metrics.as_deref(), "disconnected", e);
+    }
+    result
+}
+
+fn packet_stream<S: AsyncRead + AsyncWrite + Send + Unpin>(
+    stream: S,
+    options: &SessionOptions,
+) -> PacketStream<S> {
+    PacketStream::new(
+        stream,
+        #[cfg(feature = "clipboard")]
+        options.max_clipboard_size,
+    )
+    .max_packet_size(options.max_packet_size)
+    .resync(options.resync)
+    .capture(options.capture.as_ref().and_then(|capture| {
+        capture
+            .start()
+            .inspect_err(|e| warn!("Cannot capture the packets: {}", e))
+            .ok()
+    }))
+}
+
+/// Applies the packets to the actuator between the reads, and writes the events from
+/// `upstream` in between, see [`ClientBuilder::inline_actuator`]
+#[allow(clippy::too_many_arguments)]
+async fn dispatch_packets<H: ActuatorAdapter, S: AsyncRead + AsyncWrite + Send + Unpin>(
+    stream: S,
+    device_name: &str,
+    info: ServerInfo,
+    options: &SessionOptions,
+    metrics: Option<Arc<dyn Metrics>>,
+    state: &watch::Sender<ConnectionState>,
+    mut upstream: Option<&mut mpsc::Receiver<Packet>>,
+    actor: &mut H,
+) -> Result<(), ConnectionError> {
+    let mut reader = Reader::new(
+        packet_stream(stream, options),
+        device_name,
+        info,
+        options,
+        metrics.clone(),
+    );
+    let mut dispatcher = Dispatcher::new(options, metrics.clone(), state);
+    let (limit, hook) = (options.write_timeout, metrics.as_deref());
+    loop {
+        let received = tokio::select! {
+            received = reader.next() => received?,
+            Some(packet) = next_upstream(&mut upstream), if dispatcher.info_acked => {
+                let Some(packet) = dispatcher.outgoing(packet, actor).await else {
+                    continue;
+                };
+                if matches!(packet, Packet::DeviceInfo { .. }) {
+                    reader.info_sent();
+                }
+                debug!("Sending {:?}", packet);
+                write_packet(&mut reader.packet_stream, packet, limit, hook).await?;
+                continue;
+            }
+        };
+        let Some(Received { packet, kind, at }) = received else {
+            continue;
+        };
         match packet {
             Packet::QueryInfo => {
-                packet_stream
-                    .write(Packet::DeviceInfo {
-                        x: 0,
-                        y: 0,
-                        w: screen_size.0,
-                        h: screen_size.1,
-                        _dummy: 0,
-                        mx: 0,
-                        my: 0,
-                    })
-                    .await
-                    .map_err(|e| {
-                        actor.disconnected();
-                        e
-                    })?;
+                // Asked again when the server's configuration changes, the size may have too
+                let info = device_info(actor).await;
+                write_packet(&mut reader.packet_stream, info, limit, hook).await?;
+                reader.info_sent();
             }
             Packet::KeepAlive => {
-                packet_stream.write(Packet::KeepAlive).await.map_err(|e| {
-                    actor.disconnected();
-                    e
-                })?;
+                write_packet(&mut reader.packet_stream, Packet::KeepAlive, limit, hook).await?;
             }
-            Packet::MouseMoveAbs { x, y } => {
-                let abs_x = ((x as f32) * (0x7fff as f32 / (screen_size.0 as f32))).ceil() as u16;
-                let abs_y = ((y as f32) * (0x7fff as f32 / (screen_size.1 as f32))).ceil() as u16;
-                actor.set_cursor_position(abs_x, abs_y);
+            packet => {
+                for packet in dispatcher.apply(packet, actor).await? {
+                    write_packet(&mut reader.packet_stream, packet, limit, hook).await?;
+                }
             }
-            Packet::MouseMove { x, y } => {
-                actor.move_cursor(x, y);
+        }
+        dispatched(hook, kind, at);
+    }
+}
+
+/// Reads and writes the packets on tasks of their own while the actuator takes the events
+/// from a queue, a slow actuator doesn't hold back the replies the server waits for
+#[allow(clippy::too_many_arguments)]
+async fn dispatch_queued<H: ActuatorAdapter, S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    stream: S,
+    device_name: &str,
+    info: ServerInfo,
+    options: &SessionOptions,
+    metrics: Option<Arc<dyn Metrics>>,
+    state: &watch::Sender<ConnectionState>,
+    upstream: Option<&mut mpsc::Receiver<Packet>>,
+    actor: &mut H,
+) -> Result<(), ConnectionError> {
+    let (read_half, write_half) = packet_stream(stream, options).split();
+    let (replies_tx, replies) = mpsc::unbounded_channel();
+    let (packets_tx, packets) = mpsc::channel(WRITE_QUEUE_SIZE);
+    let (events_tx, events) = mpsc::channel(options.actuator_queue_size.max(1));
+    let (screen_size_tx, screen_size) = watch::channel(actor.get_screen_size().await);
+    let mut writer = Task(tokio::spawn(write_packets(
+        write_half,
+        replies,
+        packets,
+        options.write_timeout,
+        metrics.clone(),
+    )));
+    let mut reader = Task(tokio::spawn(read_packets(
+        Reader::new(read_half, device_name, info, options, metrics.clone()),
+        replies_tx,
+        events_tx,
+        screen_size,
+    )));
+    let mut dispatcher = Dispatcher::new(options, metrics.clone(), state);
+    let applied = apply_events(
+        events,
+        &packets_tx,
+        &screen_size_tx,
+        upstream,
+        &mut dispatcher,
+        metrics.as_deref(),
+        actor,
+    );
+    let result = tokio::select! {
+        result = applied => result,
+        written = &mut writer.0 => return joined(written).and(Err(ConnectionError::Disconnected)),
+    };
+    match result {
+        // The writer stopped, with an error of its own
+        Err(ConnectionError::Disconnected) if writer.0.is_finished() => {
+            return joined((&mut writer.0).await).and(Err(ConnectionError::Disconnected));
+        }
+        Err(e) => return Err(e),
+        Ok(()) => {}
+    }
+    // The reader stopped after queueing its last event, the packets sent since by the
+    // actuator side are still written, e.g. the clipboard as the cursor leaves
+    drop(packets_tx);
+    let written = joined((&mut writer.0).await);
+    joined((&mut reader.0).await)
+        .and(written)
+        .and(Err(ConnectionError::Disconnected))
+}
+
+/// Answers the keep-alives and the queries of the server, and queues the other packets for
+/// the actuator. Returns once the writer or the actuator side is gone, the errors otherwise.
+async fn read_packets<S: PacketReader>(
+    mut reader: Reader<S>,
+    replies: mpsc::UnboundedSender<Packet>,
+    events: mpsc::Sender<Received>,
+    screen_size: watch::Receiver<(u16, u16)>,
+) -> Result<(), ConnectionError> {
+    // A move that didn't fit in the queue, those read until there is room are merged into it
+    let mut pending: Option<Received> = None;
+    loop {
+        let received = tokio::select! {
+            received = reader.next() => received?,
+            permit = events.reserve(), if pending.is_some() => {
+                let (Ok(permit), Some(pending)) = (permit, pending.take()) else {
+                    return Ok(());
+                };
+                permit.send(pending);
+                continue;
             }
-            Packet::KeyUp { id, mask, button } => {
-                actor.key_up(id, mask, button);
+        };
+        let Some(received) = received else {
+            continue;
+        };
+        let reply = match &received.packet {
+            // The size is the one the actuator reported last, when connecting or with
+            // `ClientHandle::screen_changed`
+            Packet::QueryInfo => {
+                let size = *screen_size.borrow();
+                reader.info_sent();
+                Some(screen_info(size))
             }
-            Packet::KeyDown { id, mask, button } => {
-                actor.key_down(id, mask, button);
+            Packet::KeepAlive => Some(Packet::KeepAlive),
+            Packet::DeviceInfo { .. } | Packet::ClientNoOp | Packet::Unknown(_) => None,
+            _ => {
+                if let Some(moved) = &mut pending {
+                    if merge_move(moved, &received.packet) {
+                        if let Some(metrics) = &reader.metrics {
+                            metrics.input_dropped(received.kind);
+                        }
+                        continue;
+                    }
+                }
+                // The events after the move wait for it
+                if let Some(moved) = pending.take() {
+                    if events.send(moved).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                match events.try_send(received) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(received))
+                        if matches!(
+                            received.packet,
+                            Packet::MouseMoveAbs { .. } | Packet::MouseMove { .. }
+                        ) =>
+                    {
+                        debug!("Actuator queue full, merging the moves until there is room");
+                        pending = Some(received);
+                    }
+                    Err(TrySendError::Full(received)) => {
+                        if events.send(received).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(TrySendError::Closed(_)) => return Ok(()),
+                }
+                continue;
             }
-            Packet::KeyRepeat {
-                id,
-                mask,
-                button,
-                count,
-            } => {
-                actor.key_repeat(id, mask, button, count);
+        };
+        if let Some(reply) = reply {
+            if replies.send(reply).is_err() {
+                return Ok(());
             }
-            Packet::MouseDown { id } => {
-                actor.mouse_down(id);
+        }
+        dispatched(reader.metrics.as_deref(), received.kind, received.at);
+    }
+}
+
+/// Writes the replies of the reader ahead of the packets of the actuator side, until both
+/// are gone
+async fn write_packets<S: PacketWriter>(
+    mut packet_stream: PacketStream<S>,
+    mut replies: mpsc::UnboundedReceiver<Packet>,
+    mut packets: mpsc::Receiver<Packet>,
+    limit: Duration,
+    metrics: Option<Arc<dyn Metrics>>,
+) -> Result<(), ConnectionError> {
+    loop {
+        let packet = tokio::select! {
+            biased;
+            Some(packet) = replies.recv() => packet,
+            Some(packet) = packets.recv() => {
+                debug!("Sending {:?}", packet);
+                packet
             }
-            Packet::MouseUp { id } => {
-                actor.mouse_up(id);
+            else => return Ok(()),
+        };
+        write_packet(&mut packet_stream, packet, limit, metrics.as_deref()).await?;
+    }
+}
+
+/// Applies the queued events to the actuator, and sends the events from `upstream` once the
+/// server acknowledged the screen info. Returns when the reader stops.
+async fn apply_events<H: ActuatorAdapter>(
+    mut events: mpsc::Receiver<Received>,
+    packets: &mpsc::Sender<Packet>,
+    screen_size: &watch::Sender<(u16, u16)>,
+    mut upstream: Option<&mut mpsc::Receiver<Packet>>,
+    dispatcher: &mut Dispatcher<'_>,
+    metrics: Option<&dyn Metrics>,
+    actor: &mut H,
+) -> Result<(), ConnectionError> {
+    let send = |packet| async move {
+        packets
+            .send(packet)
+            .await
+            .map_err(|_| ConnectionError::Disconnected)
+    };
+    loop {
+        tokio::select! {
+            received = events.recv() => {
+                let Some(Received { packet, kind, at }) = received else {
+                    return Ok(());
+                };
+                for packet in dispatcher.apply(packet, actor).await? {
+                    send(packet).await?;
+                }
+                dispatched(metrics, kind, at);
             }
-            Packet::MouseWheel { x_delta, y_delta } => {
-                actor.mouse_wheel(x_delta, y_delta);
+            Some(packet) = next_upstream(&mut upstream), if dispatcher.info_acked => {
+                let Some(packet) = dispatcher.outgoing(packet, actor).await else {
+                    continue;
+                };
+                // The next query is answered with it
+                if let Packet::DeviceInfo { w, h, .. } = packet {
+                    screen_size.send_replace((w, h));
+                }
+                send(packet).await?;
             }
-            Packet::InfoAck => { //Ignore
+        }
+    }
+}
+
+/// A packet read by [`Reader`]
+struct Received {
+    packet: Packet,
+    // Code of the packet, the last one read if moves were coalesced
+    kind: [u8; 4],
+    // When it was read, only with metrics
+    at: Option<Instant>,
+}
+
+/// Reads the packets of a session, keeps track of the keep-alive timeout and of the cursor
+/// for the enter gating
+struct Reader<S> {
+    packet_stream: PacketStream<S>,
+    // For the errors of the server rejecting it
+    screen_name: String,
+    // The negotiated version, the packets of newer ones are dropped
+    info: ServerInfo,
+    #[cfg(feature = "clipboard")]
+    clipboard_stage: crate::ClipboardStage,
+    // The one set until the server changes it
+    #[cfg_attr(not(feature = "barrier-options"), allow(dead_code))]
+    default_keepalive_timeout: Duration,
+    keepalive_timeout: Duration,
+    handshake_timeout: Duration,
+    // The keep-alive timeout runs from the last packet, not from the last event sent
+    heard_at: time::Instant,
+    // Nothing received since the hello, the handshake timeout applies
+    awaiting_reply: bool,
+    coalesce_moves: bool,
+    strict_enter_gating: bool,
+    // The cursor is on this screen, between CINN and COUT
+    entered: bool,
+    // Sent the screen info, waiting for the acknowledgement
+    info_sent: Option<Instant>,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl<S: PacketReader> Reader<S> {
+    fn new(
+        packet_stream: PacketStream<S>,
+        screen_name: &str,
+        info: ServerInfo,
+        options: &SessionOptions,
+        metrics: Option<Arc<dyn Metrics>>,
+    ) -> Self {
+        Self {
+            packet_stream,
+            screen_name: screen_name.to_string(),
+            info,
+            #[cfg(feature = "clipboard")]
+            clipboard_stage: crate::ClipboardStage::new(),
+            default_keepalive_timeout: options.keepalive_timeout,
+            keepalive_timeout: options.keepalive_timeout,
+            handshake_timeout: options.handshake_timeout,
+            heard_at: time::Instant::now(),
+            awaiting_reply: true,
+            coalesce_moves: options.coalesce_moves,
+            strict_enter_gating: options.strict_enter_gating,
+            entered: false,
+            info_sent: None,
+            metrics,
+        }
+    }
+
+    /// The next packet for the caller, `None` if it has been dropped. Fails on the errors
+    /// sent by the server. Cancel safe, a packet is read whole or not at all.
+    async fn next(&mut self) -> Result<Option<Received>, ConnectionError> {
+        let timeout = if self.awaiting_reply {
+            self.handshake_timeout
+        } else {
+            self.keepalive_timeout
+        };
+        let received = timeout_at(
+            self.heard_at + timeout,
+            self.packet_stream.read(
+                #[cfg(feature = "clipboard")]
+                &mut self.clipboard_stage,
+            ),
+        )
+        .await;
+        let Ok(packet) = received else {
+            if self.awaiting_reply {
+                let elapsed = self.heard_at.elapsed();
+                warn!(
+                    "No reply from the server {:?} after the hello, check that the screen name {:?} is in its configuration",
+                    elapsed, self.screen_name
+                );
+                return Err(ConnectionError::HandshakeTimeout(elapsed));
             }
-            #[cfg(feature = "barrier-options")]
-            Packet::ResetOptions => {
-                actor.reset_options();
+            warn!(
+                "Nothing received from the server in {:?}",
+                self.keepalive_timeout
+            );
+            return Err(ConnectionError::Timeout);
+        };
+        let mut packet = match packet {
+            Ok(packet) => packet,
+            Err(PacketError::Resynchronized { skipped }) => {
+                warn!(
+                    "Stream out of sync, skipped {} bytes to the next packet",
+                    skipped
+                );
+                return Ok(None);
             }
-            #[cfg(feature = "barrier-options")]
-            Packet::SetDeviceOptions(opts) => {
-                actor.set_options(opts);
+            Err(e @ PacketError::TooManyUnknown(_)) => {
+                warn!("Giving up on the stream: {}", e);
+                return Err(e.into());
             }
-            Packet::CursorEnter { .. } => {
-                actor.enter();
+            Err(_) => return Err(ConnectionError::Disconnected),
+        };
+        self.heard_at = time::Instant::now();
+        self.awaiting_reply = false;
+        // Only read the clock if someone is interested
+        let at = self.metrics.as_ref().map(|metrics| {
+            let (kind, wire_bytes) = self.packet_stream.last_received();
+            metrics.packet_received(kind, wire_bytes);
+            Instant::now()
+        });
+        if self.coalesce_moves {
+            packet = coalesce_moves(
+                packet,
+                &mut self.packet_stream,
+                self.metrics.as_deref(),
+                #[cfg(feature = "clipboard")]
+                &mut self.clipboard_stage,
+            )
+            .await
+            .map_err(|_| ConnectionError::Disconnected)?;
+        }
+        let kind = self.packet_stream.last_received().0;
+        if self.strict_enter_gating && !self.entered && packet.is_input() {
+            debug!(
+                "Dropped {} received while not entered",
+                String::from_utf8_lossy(&kind)
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.input_dropped(kind);
             }
-            Packet::CursorLeave => {
-                actor.leave();
+            return Ok(None);
+        }
+        // The server has no business sending it
+        if !self.info.supports(packet.version()) {
+            debug!(
+                "Dropped {}, newer than protocol {}.{}",
+                String::from_utf8_lossy(&kind),
+                self.info.major,
+                self.info.minor
+            );
+            return Ok(None);
+        }
+        match &packet {
+            Packet::CursorEnter { .. } => self.entered = true,
+            Packet::CursorLeave => self.entered = false,
+            Packet::InfoAck => {
+                if let (Some(metrics), Some(sent)) = (&self.metrics, self.info_sent.take()) {
+                    metrics.round_trip(sent.elapsed());
+                }
             }
-            Packet::GrabClipboard { .. } => {}
-            #[cfg(feature = "clipboard")]
-            Packet::SetClipboard { id, data } => {
-                if !data.is_empty() {
-                    debug!("Clipboard: id:{id}, data:...");
-                    actor.set_clipboard(data);
+            #[cfg(feature = "barrier-options")]
+            Packet::ResetOptions => self.keepalive_timeout = self.default_keepalive_timeout,
+            #[cfg(feature = "barrier-options")]
+            Packet::SetDeviceOptions(opts) => {
+                let opts = ServerOptions::from(opts.clone());
+                if let Some(rate) = opts.heartbeat.filter(|rate| !rate.is_zero()) {
+                    self.keepalive_timeout = rate * KEEPALIVES_UNTIL_DEATH;
+                    debug!("Keep-alive timeout set to {:?}", self.keepalive_timeout);
                 }
             }
-            Packet::DeviceInfo { .. } | Packet::ErrorUnknownDevice | Packet::ClientNoOp => {
-                // Server only packets
+            Packet::ErrorUnknownDevice => {
+                let error = ConnectionError::UnknownScreenName(self.screen_name.clone());
+                return Err(server_error(error));
             }
+            Packet::ErrorBusy => return Err(server_error(ConnectionError::Busy)),
+            Packet::ErrorBadProtocol => return Err(server_error(ConnectionError::BadProtocol)),
+            &Packet::ErrorIncompatibleVersion { major, minor } => {
+                let error = ConnectionError::IncompatibleVersion { major, minor };
+                return Err(server_error(error));
+            }
+            Packet::Close => return Err(server_error(ConnectionError::Closed)),
             Packet::Unknown(cmd) => {
                 debug!(
                     "Unknown packet: {}",
-                    core::str::from_utf8(&cmd).unwrap_or("????")
+                    core::str::from_utf8(cmd).unwrap_or("????")
                 );
             }
+            _ => {}
         }
+        Ok(Some(Received { packet, kind, at }))
     }
-    actor.disconnected();
-    Err(ConnectionError::Disconnected)
-}
 
-#[cfg(feature = "async-actuator")]
-pub async fn start_async<A: AsyncActuator + Send + Unpin, Addr: ToSocketAddrs>(
-    addr: Addr,
-    device_name: String,
-    actor: &mut A,
-) -> Result<(), ConnectionError> {
-    let screen_size: (u16, u16) = actor.get_screen_size().await;
-
-    let mut stream = TcpStream::connect(addr).await?;
-    // Turn off Nagle, this may not be available on ESP-IDF, so ignore the error.
-    stream.set_nodelay(true).ok();
-
-    let _size = stream.read_packet_size().await?;
-    if stream.read_bytes_fixed::<7>().await? == [b'B', b'a', b'r', b'r', b'i', b'e', b'r'] {
-        debug!("Got hello");
-    } else {
-        error!("Got invalid hello");
-        return Err(ConnectionError::ProtocolError(
-            crate::error::PacketError::FormatError,
-        ));
+    // The round trip is measured until the server acknowledges it
+    fn info_sent(&mut self) {
+        if self.metrics.is_some() {
+            self.info_sent = Some(Instant::now());
+        }
     }
-    let major = stream.read_u16().await?;
-    let minor = stream.read_u16().await?;
-    debug!("Got hello {major}:{minor}");
-
-    stream
-        .write_u32("Barrier".len() as u32 + 2 + 2 + 4 + device_name.bytes().len() as u32)
-        .await?;
-    stream.write_all(b"Barrier").await?;
-    stream.write_u16(1).await?;
-    stream.write_u16(6).await?;
-    stream.write_str(&device_name).await?;
+}
 
-    actor.connected().await;
+/// Awaits an actuator call for an event, made again as long as the policy of `$on_error`
+/// retries it. Returns from the function with the error if the connection is to end, the
+/// event is skipped otherwise.
+macro_rules! actuate {
+    ($on_error:expr, $callback:literal, $call:expr) => {{
+        let mut attempt = 0;
+        while let Err(e) = $call.await {
+            match $on_error.failed($callback, e, attempt)? {
+                Some(delay) => sleep(delay).await,
+                None => break,
+            }
+            attempt += 1;
+        }
+    }};
+}
 
+/// Applies the packets to the actuator, with what it needs to remember between them
+struct Dispatcher<'a> {
+    state: &'a watch::Sender<ConnectionState>,
+    on_error: OnError,
+    edges: Option<EdgeDetector>,
+    // Sequence number of the last CINN, the clipboard packets sent refer to it
     #[cfg(feature = "clipboard")]
-    let mut clipboard_stage = crate::ClipboardStage::None;
-    let mut packet_stream = PacketStream::new(stream);
-    while let Ok(packet) = packet_stream
-        .read(
+    seq_num: u32,
+    // Clipboards grabbed on this screen and not grabbed by another one since
+    #[cfg(feature = "clipboard")]
+    owned: [bool; CLIPBOARD_COUNT],
+    #[cfg(feature = "file-transfer")]
+    file_stage: FileStage,
+    // Name of the file being transferred, from the last DDRG
+    #[cfg(feature = "file-transfer")]
+    name_hint: Option<String>,
+    #[cfg(feature = "file-transfer")]
+    max_file_size: usize,
+    // The server only takes events once it has acknowledged the screen info
+    info_acked: bool,
+    #[cfg(feature = "barrier-options")]
+    modes: ServerModes,
+    // Last position the server sent, the relative mouse moves start from it
+    #[cfg(feature = "barrier-options")]
+    position: (u16, u16),
+}
+
+impl<'a> Dispatcher<'a> {
+    fn new(
+        options: &SessionOptions,
+        metrics: Option<Arc<dyn Metrics>>,
+        state: &'a watch::Sender<ConnectionState>,
+    ) -> Self {
+        Self {
+            state,
+            on_error: OnError {
+                policy: options.on_actuator_error,
+                metrics,
+            },
+            edges: options.edge_margin.map(EdgeDetector::new),
             #[cfg(feature = "clipboard")]
-            &mut clipboard_stage,
-        )
-        .await
-    {
+            seq_num: 0,
+            #[cfg(feature = "clipboard")]
+            owned: [false; CLIPBOARD_COUNT],
+            #[cfg(feature = "file-transfer")]
+            file_stage: FileStage::None,
+            #[cfg(feature = "file-transfer")]
+            name_hint: None,
+            #[cfg(feature = "file-transfer")]
+            max_file_size: options.max_file_size,
+            info_acked: false,
+            #[cfg(feature = "barrier-options")]
+            modes: ServerModes::default(),
+            #[cfg(feature = "barrier-options")]
+            position: (0, 0),
+        }
+    }
+
+    /// Calls the actuator for `packet`, returns the packets to send back, i.e. the clipboards
+    /// taken by the server as the cursor leaves. Those the reader handles are ignored.
+    async fn apply<H: ActuatorAdapter>(
+        &mut self,
+        packet: Packet,
+        actor: &mut H,
+    ) -> Result<Vec<Packet>, ConnectionError> {
         match packet {
-            Packet::QueryInfo => {
-                match packet_stream
-                    .write(Packet::DeviceInfo {
-                        x: 0,
-                        y: 0,
-                        w: screen_size.0,
-                        h: screen_size.1,
-                        _dummy: 0,
-                        mx: 0,
-                        my: 0,
-                    })
-                    .await
-                {
-                    Ok(_) => Ok(()),
-                    Err(e) => {
-                        actor.disconnected().await;
-                        Err(e)
-                    }
-                }?;
-            }
-            Packet::KeepAlive => {
-                match packet_stream.write(Packet::KeepAlive).await {
-                    Ok(_) => Ok(()),
-                    Err(e) => {
-                        actor.disconnected().await;
-                        Err(e)
-                    }
-                }?;
-            }
             Packet::MouseMoveAbs { x, y } => {
-                let abs_x = ((x as f32) * (0x7fff as f32 / (screen_size.0 as f32))).ceil() as u16;
-                let abs_y = ((y as f32) * (0x7fff as f32 / (screen_size.1 as f32))).ceil() as u16;
-                actor.set_cursor_position(abs_x, abs_y).await;
+                actuate!(
+                    self.on_error,
+                    "set_cursor_position",
+                    self.move_to(x, y, actor)
+                );
+                if let Some(edges) = &mut self.edges {
+                    for edge in edges.update((x, y), actor.get_screen_size().await) {
+                        actuate!(self.on_error, "edge_reached", actor.edge_reached(edge));
+                    }
+                }
             }
             Packet::MouseMove { x, y } => {
-                actor.move_cursor(x, y).await;
+                actuate!(self.on_error, "move_cursor", actor.move_cursor(x, y));
             }
             Packet::KeyUp { id, mask, button } => {
-                actor.key_up(id, mask, button).await;
+                actuate!(self.on_error, "key_up", actor.key_up(id, mask, button));
             }
             Packet::KeyDown { id, mask, button } => {
-                actor.key_down(id, mask, button).await;
+                actuate!(self.on_error, "key_down", actor.key_down(id, mask, button));
             }
             Packet::KeyRepeat {
                 id,
@@ -243,52 +1664,894 @@ pub async fn start_async<A: AsyncActuator + Send + Unpin, Addr: ToSocketAddrs>(
                 button,
                 count,
             } => {
-                actor.key_repeat(id, mask, button, count).await;
+                actuate!(
+                    self.on_error,
+                    "key_repeat",
+                    actor.key_repeat(id, mask, button, count)
+                );
             }
             Packet::MouseDown { id } => {
-                actor.mouse_down(id).await;
+                actuate!(self.on_error, "mouse_down", actor.mouse_down(id));
             }
             Packet::MouseUp { id } => {
-                actor.mouse_up(id).await;
+                actuate!(self.on_error, "mouse_up", actor.mouse_up(id));
             }
             Packet::MouseWheel { x_delta, y_delta } => {
-                actor.mouse_wheel(x_delta, y_delta).await;
+                actuate!(
+                    self.on_error,
+                    "mouse_wheel",
+                    actor.mouse_wheel(x_delta, y_delta)
+                );
             }
-            Packet::InfoAck => { //Ignore
+            Packet::InfoAck => {
+                // Also sent after the screen info asked again, the cursor may be here then
+                if !self.info_acked {
+                    set_state(self.state, ConnectionState::Connected);
+                }
+                self.info_acked = true;
             }
             #[cfg(feature = "barrier-options")]
             Packet::ResetOptions => {
-                actor.reset_options().await;
+                self.modes = ServerModes::default();
+                actuate!(self.on_error, "reset_options", actor.reset_options());
             }
             #[cfg(feature = "barrier-options")]
             Packet::SetDeviceOptions(opts) => {
-                actor.set_options(opts).await;
+                let opts = ServerOptions::from(opts);
+                if self.modes.update(&opts) {
+                    info!("Server options changed: {:?}", self.modes);
+                }
+                actuate!(
+                    self.on_error,
+                    "set_options",
+                    actor.set_options(opts.clone())
+                );
             }
-            Packet::CursorEnter { .. } => {
-                actor.enter().await;
+            #[cfg_attr(not(feature = "clipboard"), allow(unused_variables))]
+            Packet::CursorEnter {
+                x,
+                y,
+                mask,
+                seq_num,
+            } => {
+                #[cfg(feature = "clipboard")]
+                {
+                    self.seq_num = seq_num;
+                }
+                #[cfg(feature = "barrier-options")]
+                {
+                    self.position = (x, y);
+                }
+                actuate!(self.on_error, "enter", actor.enter(x, y, mask));
+                if let Some(edges) = &mut self.edges {
+                    edges.reset((x, y), actor.get_screen_size().await);
+                }
+                set_state(self.state, ConnectionState::Entered);
             }
             Packet::CursorLeave => {
-                actor.leave().await;
+                actuate!(self.on_error, "leave", actor.leave());
+                set_state(self.state, ConnectionState::Left);
+                // The server takes the clipboards this screen owns as it leaves
+                #[cfg(feature = "clipboard")]
+                {
+                    let mut clipboards = vec![];
+                    for id in 0..CLIPBOARD_COUNT as u8 {
+                        if !self.owned[id as usize] {
+                            continue;
+                        }
+                        match actor.get_clipboard(id).await {
+                            Ok(Some(data)) => {
+                                debug!("Sending clipboard {id}");
+                                let seq_num = self.seq_num;
+                                clipboards.push(Packet::SetClipboard { id, seq_num, data });
+                            }
+                            Ok(None) => {}
+                            Err(e) => skipped(self.on_error.metrics.as_deref(), "get_clipboard", e),
+                        }
+                    }
+                    return Ok(clipboards);
+                }
+            }
+            Packet::ScreenSaver { on } => {
+                actuate!(self.on_error, "screensaver", actor.screensaver(on));
+            }
+            Packet::GameButtons { id, buttons } => {
+                actuate!(
+                    self.on_error,
+                    "game_buttons",
+                    actor.game_buttons(id, buttons)
+                );
+            }
+            Packet::GameSticks { id, x1, y1, x2, y2 } => {
+                actuate!(
+                    self.on_error,
+                    "game_sticks",
+                    actor.game_sticks(id, (x1, y1), (x2, y2))
+                );
+            }
+            Packet::GameTriggers { id, t1, t2 } => {
+                actuate!(
+                    self.on_error,
+                    "game_triggers",
+                    actor.game_triggers(id, t1, t2)
+                );
+            }
+            Packet::LanguageSync { languages } => {
+                debug!("Server languages: {:?}", languages);
+                if let Err(e) = actor.language_sync(languages).await {
+                    skipped(self.on_error.metrics.as_deref(), "language_sync", e);
+                }
+            }
+            #[cfg_attr(not(feature = "clipboard"), allow(unused_variables))]
+            Packet::GrabClipboard { id, .. } => {
+                // Another screen owns it now
+                #[cfg(feature = "clipboard")]
+                if let Some(owned) = self.owned.get_mut(id as usize) {
+                    *owned = false;
+                }
             }
-            Packet::GrabClipboard { .. } => {}
             #[cfg(feature = "clipboard")]
-            Packet::SetClipboard { id, data } => {
-                if !data.is_empty() {
-                    debug!("Clipboard: id:{id}, data:...");
-                    actor.set_clipboard(data).await;
+            Packet::SetClipboard { id, data, .. } if !data.is_empty() => {
+                debug!("Clipboard: id:{id}, data:...");
+                if data.is_truncated() {
+                    warn!(
+                        "Clipboard {id} truncated to the limit: {:?}",
+                        data.truncated()
+                    );
+                }
+                if let Err(e) = actor.set_clipboard(id, data).await {
+                    skipped(self.on_error.metrics.as_deref(), "set_clipboard", e);
                 }
             }
-            Packet::DeviceInfo { .. } | Packet::ErrorUnknownDevice | Packet::ClientNoOp => {
-                // Server only packets
+            #[cfg(feature = "clipboard")]
+            Packet::ClipboardTooLarge { id, size } => {
+                warn!("Clipboard {id} discarded, {size} bytes is larger than the limit");
             }
-            Packet::Unknown(cmd) => {
-                debug!(
-                    "Unknown packet: {}",
-                    core::str::from_utf8(&cmd).unwrap_or("????")
+            #[cfg(feature = "file-transfer")]
+            Packet::DragInfo { files } => {
+                debug!("Files dragged: {:?}", files);
+                // Only the first one is transferred
+                self.name_hint = files.first().map(|path| file_name(path).to_string());
+            }
+            #[cfg(feature = "file-transfer")]
+            Packet::FileChunk { mark, data } => {
+                match self.file_stage.push(mark, &data, self.max_file_size) {
+                    FileTransfer::Pending => {}
+                    FileTransfer::Received(data) => {
+                        debug!("File received: {:?}, {} bytes", self.name_hint, data.len());
+                        let received = actor.file_received(self.name_hint.take(), data).await;
+                        if let Err(e) = received {
+                            skipped(self.on_error.metrics.as_deref(), "file_received", e);
+                        }
+                    }
+                    FileTransfer::TooLarge { size } => {
+                        warn!("File discarded, {size} bytes is larger than the limit");
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(vec![])
+    }
+
+    // With the relative mouse moves option the cursor is moved by the difference from the
+    // last position instead of being set to it
+    #[cfg(feature = "barrier-options")]
+    async fn move_to<H: ActuatorAdapter>(
+        &mut self,
+        x: u16,
+        y: u16,
+        actor: &mut H,
+    ) -> Result<(), ActuatorError> {
+        if !self.modes.relative_mouse_moves {
+            self.position = (x, y);
+            return actor.set_cursor_position(x, y).await;
+        }
+        let (dx, dy) = relative_move(self.position, (x, y));
+        if (dx, dy) != (0, 0) {
+            actor.move_cursor(dx, dy).await?;
+        }
+        // Only once moved, the move is made again by a retry or added to the next one
+        self.position = (x, y);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "barrier-options"))]
+    async fn move_to<H: ActuatorAdapter>(
+        &mut self,
+        x: u16,
+        y: u16,
+        actor: &mut H,
+    ) -> Result<(), ActuatorError> {
+        actor.set_cursor_position(x, y).await
+    }
+
+    /// An event of the handles as it's sent, `None` if it's dropped
+    async fn outgoing<H: ActuatorAdapter>(
+        &mut self,
+        packet: Packet,
+        actor: &mut H,
+    ) -> Option<Packet> {
+        #[cfg(feature = "clipboard")]
+        let packet = grab_clipboard(packet, &mut self.owned, self.seq_num)?;
+        // The screen info is queued without the size, the actuator has the new one
+        match packet {
+            Packet::DeviceInfo { .. } => Some(device_info(actor).await),
+            packet => Some(packet),
+        }
+    }
+}
+
+/// The policy for the actuator errors, and the hook told what was done about them
+struct OnError {
+    policy: ActuatorErrorPolicy,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl OnError {
+    /// The delay before calling `callback` again after it failed `attempt` times before,
+    /// `None` to skip the event, the error if the connection is to end
+    fn failed(
+        &self,
+        callback: &'static str,
+        error: ActuatorError,
+        attempt: u32,
+    ) -> Result<Option<Duration>, ConnectionError> {
+        let report = |action| {
+            if let Some(metrics) = &self.metrics {
+                metrics.actuator_error(callback, action);
+            }
+        };
+        match self.policy {
+            ActuatorErrorPolicy::SkipEvent => {
+                warn!("Actuator {} failed, event skipped: {}", callback, error);
+                report(ActuatorErrorAction::Skipped);
+                Ok(None)
+            }
+            ActuatorErrorPolicy::Retry { attempts, delay } if attempt < attempts => {
+                warn!(
+                    "Actuator {} failed, retrying in {:?}: {}",
+                    callback, delay, error
+                );
+                report(ActuatorErrorAction::Retried);
+                Ok(Some(delay))
+            }
+            _ => {
+                report(ActuatorErrorAction::Aborted);
+                Err(error.into())
+            }
+        }
+    }
+}
+
+// A callback whose errors are skipped whatever the policy, i.e. the clipboard, file,
+// language and connection ones
+fn skipped(metrics: Option<&dyn Metrics>, callback: &'static str, error: ActuatorError) {
+    warn!("Actuator {} failed: {}", callback, error);
+    if let Some(metrics) = metrics {
+        metrics.actuator_error(callback, ActuatorErrorAction::Skipped);
+    }
+}
+
+// Merges a move into the one waiting for room in the queue if they are of the same kind,
+// absolute moves replace it and relative ones are added to it
+fn merge_move(pending: &mut Received, packet: &Packet) -> bool {
+    match (&mut pending.packet, packet) {
+        (Packet::MouseMoveAbs { x, y }, &Packet::MouseMoveAbs { x: to_x, y: to_y }) => {
+            (*x, *y) = (to_x, to_y);
+        }
+        (Packet::MouseMove { x, y }, &Packet::MouseMove { x: dx, y: dy }) => {
+            (*x, *y) = (x.saturating_add(dx), y.saturating_add(dy));
+        }
+        _ => return false,
+    }
+    true
+}
+
+// The actuator is done with a packet read at `at`
+fn dispatched(metrics: Option<&dyn Metrics>, kind: [u8; 4], at: Option<Instant>) {
+    if let (Some(metrics), Some(at)) = (metrics, at) {
+        metrics.event_dispatched(kind, at.elapsed());
+    }
+}
+
+/// A task of the session, aborted when the session ends or is dropped
+struct Task<T>(JoinHandle<T>);
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// The tasks are never aborted before they are joined, their panics are the session's
+fn joined<T>(joined: Result<T, JoinError>) -> T {
+    joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+// Published even without receivers, one may subscribe later
+fn set_state(state: &watch::Sender<ConnectionState>, new: ConnectionState) {
+    debug!("Connection state: {:?}", new);
+    state.send_replace(new);
+}
+
+/// Next event of the handles, never ready without them
+async fn next_upstream(upstream: &mut Option<&mut mpsc::Receiver<Packet>>) -> Option<Packet> {
+    match upstream {
+        Some(upstream) => upstream.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// The screen info with the size the actuator reports now
+async fn device_info<H: ActuatorAdapter>(actor: &mut H) -> Packet {
+    screen_info(actor.get_screen_size().await)
+}
+
+fn screen_info((w, h): (u16, u16)) -> Packet {
+    Packet::DeviceInfo {
+        x: 0,
+        y: 0,
+        w,
+        h,
+        _dummy: 0,
+        mx: 0,
+        my: 0,
+    }
+}
+
+// Takes the ownership of the clipboard changed on a handle, with the sequence number of the
+// last enter
+#[cfg(feature = "clipboard")]
+fn grab_clipboard(
+    packet: Packet,
+    owned: &mut [bool; CLIPBOARD_COUNT],
+    seq_num: u32,
+) -> Option<Packet> {
+    match packet {
+        Packet::GrabClipboard { id, .. } => {
+            let Some(owned) = owned.get_mut(id as usize) else {
+                warn!("Unknown clipboard {id} changed, ignored");
+                return None;
+            };
+            *owned = true;
+            Some(Packet::GrabClipboard { id, seq_num })
+        }
+        packet => Some(packet),
+    }
+}
+
+// The server closes the connection after sending an error
+fn server_error(error: ConnectionError) -> ConnectionError {
+    warn!("Server closed the connection: {}", error);
+    error
+}
+
+/// Writes `packet` unless the server stops reading for `limit`. A server gone without closing
+/// the connection leaves the send buffer full, the write would never complete.
+async fn write_packet<S: PacketWriter>(
+    packet_stream: &mut PacketStream<S>,
+    packet: Packet,
+    limit: Duration,
+    metrics: Option<&dyn Metrics>,
+) -> Result<(), ConnectionError> {
+    match time::timeout(limit, packet_stream.write(packet)).await {
+        Ok(written) => {
+            written?;
+            if let Some(metrics) = metrics {
+                let (kind, wire_bytes) = packet_stream.last_sent();
+                metrics.packet_sent(kind, wire_bytes);
+            }
+            Ok(())
+        }
+        Err(_) => {
+            warn!("Nothing could be sent to the server in {:?}", limit);
+            Err(ConnectionError::Timeout)
+        }
+    }
+}
+
+/// Replaces a move with the following ones as long as they are already received
+async fn coalesce_moves<S: PacketReader>(
+    mut packet: Packet,
+    packet_stream: &mut PacketStream<S>,
+    metrics: Option<&dyn Metrics>,
+    #[cfg(feature = "clipboard")] clipboard_stage: &mut crate::ClipboardStage,
+) -> Result<Packet, PacketError> {
+    loop {
+        packet = match (packet, packet_stream.peek_buffered()) {
+            (Packet::MouseMoveAbs { .. }, Some(code)) if &code == b"DMMV" => {
+                packet_stream
+                    .read(
+                        #[cfg(feature = "clipboard")]
+                        clipboard_stage,
+                    )
+                    .await?
+            }
+            (Packet::MouseMove { x, y }, Some(code)) if &code == b"DMRM" => match packet_stream
+                .read(
+                    #[cfg(feature = "clipboard")]
+                    clipboard_stage,
+                )
+                .await?
+            {
+                Packet::MouseMove { x: dx, y: dy } => Packet::MouseMove {
+                    x: x.saturating_add(dx),
+                    y: y.saturating_add(dy),
+                },
+                packet => packet,
+            },
+            (packet, _) => return Ok(packet),
+        };
+        if let Some(metrics) = metrics {
+            let (kind, wire_bytes) = packet_stream.last_received();
+            metrics.packet_received(kind, wire_bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{
+        connect, handshake, session, set_tcp_keepalive, watch, LocalBind, SessionOptions,
+        TcpKeepalive,
+    };
+    use crate::{
+        adapter::SyncAdapter, test_server::Recorder, ConnectionError, Metrics, Protocol, ServerInfo,
+    };
+
+    fn packet(code: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut buf = ((code.len() + payload.len()) as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(code);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    // Runs a session against a server sending `packets` at once after the handshake
+    async fn run_session(
+        packets: Vec<u8>,
+        options: SessionOptions,
+        metrics: Option<Arc<dyn Metrics>>,
+    ) -> Vec<String> {
+        let (mut server, client) = duplex(64 * 1024);
+        let server = tokio::spawn(async move {
+            let mut hello = b"Barrier".to_vec();
+            hello.extend_from_slice(&[0, 1, 0, 6]);
+            server.write_u32(hello.len() as u32).await.unwrap();
+            server.write_all(&hello).await.unwrap();
+            let size = server.read_u32().await.unwrap();
+            server
+                .read_exact(&mut vec![0; size as usize])
+                .await
+                .unwrap();
+            server.write_all(&packets).await.unwrap();
+            // Keep reading so the replies don't fail
+            server.shutdown().await.unwrap();
+            server.read_to_end(&mut vec![]).await.unwrap();
+        });
+        let mut recorder = Recorder::default();
+        let result = session(
+            client,
+            "test",
+            &options,
+            metrics,
+            &watch::Sender::new(Default::default()),
+            None,
+            &mut SyncAdapter(&mut recorder),
+        )
+        .await;
+        assert!(result.is_err());
+        server.await.unwrap();
+        recorder.0
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_moves() {
+        let packets = [
+            packet(b"DMMV", &[0, 10, 0, 10]),
+            packet(b"DMMV", &[0, 20, 0, 20]),
+            packet(b"DMDN", &[1]),
+            packet(b"DMMV", &[0, 30, 0, 30]),
+            packet(b"DMMV", &[0, 40, 0, 40]),
+            packet(b"DMRM", &[0, 1, 0, 1]),
+            packet(b"DMRM", &[0, 2, 0xff, 0xfe]),
+            packet(b"DKDN", &[0, 0x61, 0, 0, 0, 1]),
+            packet(b"DMRM", &[0, 3, 0, 3]),
+            packet(b"DMUP", &[1]),
+        ]
+        .concat();
+
+        let events = run_session(packets.clone(), SessionOptions::default(), None).await;
+        assert_eq!(
+            events,
+            [
+                "abs 10 10",
+                "abs 20 20",
+                "down 1",
+                "abs 30 30",
+                "abs 40 40",
+                "rel 1 1",
+                "rel 2 -2",
+                "key 97",
+                "rel 3 3",
+                "up 1",
+            ]
+        );
+
+        let options = SessionOptions {
+            coalesce_moves: true,
+            ..Default::default()
+        };
+        let events = run_session(packets, options, None).await;
+        assert_eq!(
+            events,
+            [
+                "abs 20 20",
+                "down 1",
+                "abs 40 40",
+                "rel 3 -1",
+                "key 97",
+                "rel 3 3",
+                "up 1",
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct MetricsRecorder(Mutex<Vec<String>>);
+
+    impl Metrics for MetricsRecorder {
+        fn packet_received(&self, kind: [u8; 4], wire_bytes: usize) {
+            let kind = String::from_utf8_lossy(&kind);
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("recv {kind} {wire_bytes}"));
+        }
+
+        fn event_dispatched(&self, kind: [u8; 4], _elapsed: Duration) {
+            let kind = String::from_utf8_lossy(&kind);
+            self.0.lock().unwrap().push(format!("done {kind}"));
+        }
+
+        fn round_trip(&self, _rtt: Duration) {
+            self.0.lock().unwrap().push("rtt".to_string());
+        }
+
+        fn input_dropped(&self, kind: [u8; 4]) {
+            let kind = String::from_utf8_lossy(&kind);
+            self.0.lock().unwrap().push(format!("drop {kind}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let packets = [
+            packet(b"QINF", &[]),
+            packet(b"CIAK", &[]),
+            packet(b"DMMV", &[0, 10, 0, 10]),
+            packet(b"DMMV", &[0, 20, 0, 20]),
+            packet(b"DKDN", &[0, 0x61, 0, 0, 0, 1]),
+        ]
+        .concat();
+        let expected = [
+            "recv QINF 8",
+            "done QINF",
+            "recv CIAK 8",
+            "rtt",
+            "done CIAK",
+            "recv DMMV 12",
+            "recv DMMV 12",
+            "done DMMV",
+            "recv DKDN 14",
+            "done DKDN",
+        ];
+        let metrics = Arc::new(MetricsRecorder::default());
+        let options = SessionOptions {
+            coalesce_moves: true,
+            inline_actuator: true,
+            ..Default::default()
+        };
+        let events = run_session(packets.clone(), options, Some(metrics.clone())).await;
+        assert_eq!(events, ["abs 20 20", "key 97"]);
+        assert_eq!(*metrics.0.lock().unwrap(), expected);
+
+        // The reader gets ahead of the actuator, the events are the same
+        let metrics = Arc::new(MetricsRecorder::default());
+        let options = SessionOptions {
+            coalesce_moves: true,
+            ..Default::default()
+        };
+        let events = run_session(packets, options, Some(metrics.clone())).await;
+        assert_eq!(events, ["abs 20 20", "key 97"]);
+        let mut recorded = metrics.0.lock().unwrap().clone();
+        recorded.sort();
+        let mut expected = expected.to_vec();
+        expected.sort();
+        assert_eq!(recorded, expected);
+    }
+
+    #[tokio::test]
+    async fn test_strict_enter_gating() {
+        let enter = packet(b"CINN", &[0, 10, 0, 10, 0, 0, 0, 1, 0, 0]);
+        let packets = [
+            packet(b"DKDN", &[0, 0x61, 0, 0, 0, 1]),
+            packet(b"DMMV", &[0, 10, 0, 10]),
+            packet(b"CSEC", &[1]),
+            enter.clone(),
+            packet(b"DKDN", &[0, 0x62, 0, 0, 0, 2]),
+            packet(b"COUT", &[]),
+            packet(b"DMRM", &[0, 1, 0, 1]),
+            packet(b"DMDN", &[1]),
+            packet(b"DMWM", &[0, 0, 0, 120]),
+            packet(b"DKUP", &[0, 0x61, 0, 0, 0, 1]),
+        ]
+        .concat();
+
+        // Barrier's relative moves on secondary screens need the events before entering
+        let events = run_session(packets.clone(), SessionOptions::default(), None).await;
+        assert_eq!(events.len(), 11);
+
+        let metrics = Arc::new(MetricsRecorder::default());
+        let options = SessionOptions {
+            strict_enter_gating: true,
+            ..Default::default()
+        };
+        let events = run_session(packets, options, Some(metrics.clone())).await;
+        assert_eq!(
+            events,
+            ["screensaver true", "enter", "abs 10 10", "key 98", "leave"]
+        );
+        let drops: Vec<String> = metrics
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.starts_with("drop"))
+            .cloned()
+            .collect();
+        assert_eq!(
+            drops,
+            [
+                "drop DKDN",
+                "drop DMMV",
+                "drop DMRM",
+                "drop DMDN",
+                "drop DMWM",
+                "drop DKUP"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_game_controller() {
+        let packets = [
+            packet(b"CINN", &[0, 10, 0, 10, 0, 0, 0, 1, 0, 0]),
+            packet(b"DGBT", &[0, 0x10, 0x01]),
+            packet(b"DGST", &[1, 0x80, 0, 0x7F, 0xFF, 0, 0, 0xFF, 0xFE]),
+            packet(b"DGTR", &[0, 255, 3]),
+        ]
+        .concat();
+        let events = run_session(packets, SessionOptions::default(), None).await;
+        assert_eq!(
+            events[2..],
+            [
+                "game 0 buttons 0x1001",
+                "game 1 sticks (-32768, 32767) (0, -2)",
+                "game 0 triggers 255 3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edge_reached() {
+        // The recorder's screen is 0x7fff wide, entering at the left edge doesn't reach it
+        let packets = [
+            packet(b"CINN", &[0, 0, 0x01, 0xf4, 0, 0, 0, 1, 0, 0]),
+            packet(b"DMMV", &[0, 5, 0x01, 0xf4]),
+            packet(b"DMMV", &[0, 100, 0x01, 0xf4]),
+            packet(b"DMMV", &[0, 3, 0x01, 0xf4]),
+            packet(b"DMMV", &[0, 2, 0x01, 0xf4]),
+            packet(b"DMMV", &[0x7f, 0xf8, 0x01, 0xf4]),
+            packet(b"DMMV", &[0x7f, 0xfe, 0x01, 0xf4]),
+        ]
+        .concat();
+        let options = SessionOptions {
+            edge_margin: Some(16),
+            ..Default::default()
+        };
+        let events = run_session(packets, options, None).await;
+        assert_eq!(
+            events,
+            [
+                "enter",
+                "abs 0 500",
+                "abs 5 500",
+                "abs 100 500",
+                "abs 3 500",
+                "edge Left",
+                "abs 2 500",
+                "abs 32760 500",
+                "edge Right",
+                "abs 32766 500",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resync() {
+        let packets = [
+            packet(b"DMMV", &[0, 5, 0, 5]),
+            // A clipboard chunk shorter than it said
+            vec![0, 0, 0x10, 0, b'D', b'C', 0, 1, 2],
+            packet(b"DMMV", &[0, 6, 0, 6]),
+            packet(b"DMDN", &[1]),
+        ]
+        .concat();
+        let events = run_session(packets.clone(), SessionOptions::default(), None).await;
+        assert_eq!(events, ["abs 5 5", "abs 6 6", "down 1"]);
+
+        let options = SessionOptions {
+            resync: false,
+            ..Default::default()
+        };
+        let events = run_session(packets, options, None).await;
+        assert_eq!(events, ["abs 5 5"]);
+    }
+
+    // Runs the handshake against `hello`, returns the result and the client's reply
+    async fn run_handshake(hello: &[u8]) -> (Result<ServerInfo, crate::ConnectionError>, Vec<u8>) {
+        let (mut server, mut client) = duplex(1024);
+        server.write_all(hello).await.unwrap();
+        let result = handshake(&mut client, "test", &watch::Sender::new(Default::default())).await;
+        drop(client);
+        let mut reply = vec![];
+        server.read_to_end(&mut reply).await.unwrap();
+        (result, reply)
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        for (name, protocol) in [
+            (b"Barrier", Protocol::Barrier),
+            (b"Synergy", Protocol::Synergy),
+        ] {
+            // The older of the two versions, then the screen name
+            for (server, negotiated) in [(3, 3), (6, 6), (8, 8), (9, 8)] {
+                let (result, reply) = run_handshake(&packet(name, &[0, 1, 0, server])[..]).await;
+                assert_eq!(
+                    result.unwrap(),
+                    ServerInfo {
+                        protocol,
+                        major: 1,
+                        minor: negotiated as u16,
+                    }
+                );
+                let expected = packet(
+                    name,
+                    &[b"\0\x01\0", &[negotiated][..], b"\0\0\0\x04test"].concat(),
                 );
+                assert_eq!(reply, expected);
             }
         }
+
+        let (result, reply) = run_handshake(&packet(b"Garbage", &[0, 1, 0, 6])[..]).await;
+        assert!(result.is_err());
+        assert!(reply.is_empty());
+
+        let (result, _) = run_handshake(&[0xff, 0xff, 0xff, 0xff, 0, 0]).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        // A server that stopped reading, the replies to its keep-alives fill the buffer
+        let (mut server, client) = duplex(64);
+        let server = tokio::spawn(async move {
+            let mut hello = b"Barrier".to_vec();
+            hello.extend_from_slice(&[0, 1, 0, 6]);
+            server.write_u32(hello.len() as u32).await.unwrap();
+            server.write_all(&hello).await.unwrap();
+            let size = server.read_u32().await.unwrap();
+            server
+                .read_exact(&mut vec![0; size as usize])
+                .await
+                .unwrap();
+            for _ in 0..16 {
+                server.write_all(&packet(b"CALV", &[])).await.unwrap();
+            }
+            std::future::pending::<()>().await;
+        });
+        let options = SessionOptions {
+            write_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+        let result = session(
+            client,
+            "test",
+            &options,
+            None,
+            &watch::Sender::new(Default::default()),
+            None,
+            &mut SyncAdapter(&mut Recorder::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let keepalive = TcpKeepalive {
+            idle: Duration::from_secs(20),
+            interval: Duration::from_secs(4),
+            retries: 2,
+        };
+        set_tcp_keepalive(&stream, &keepalive).unwrap();
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.tcp_keepalive_time().unwrap(), keepalive.idle);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.tcp_keepalive_interval().unwrap(), keepalive.interval);
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), keepalive.retries);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bind = LocalBind {
+            address: Some("127.0.0.2:0".parse().unwrap()),
+            ..Default::default()
+        };
+        let stream = connect(listener.local_addr().unwrap(), &bind, None)
+            .await
+            .unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.ip().to_string(), "127.0.0.2");
+        assert_eq!(stream.local_addr().unwrap(), peer);
+
+        // Not an address of this host
+        let bind = LocalBind {
+            address: Some("192.0.2.1:0".parse().unwrap()),
+            ..Default::default()
+        };
+        let result = connect(listener.local_addr().unwrap(), &bind, None).await;
+        assert!(
+            matches!(&result, Err(ConnectionError::BindFailed { local, .. }) if local == "192.0.2.1:0"),
+            "{result:?}"
+        );
+
+        // The IPv6 address of the name can't be bound to an IPv4 one, the IPv4 one is tried
+        let port = listener.local_addr().unwrap().port();
+        let bind = LocalBind {
+            address: Some(SocketAddr::from(([127, 0, 0, 2], 0))),
+            ..Default::default()
+        };
+        connect(format!("localhost:{port}"), &bind, None)
+            .await
+            .unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.ip().to_string(), "127.0.0.2");
     }
-    actor.disconnected().await;
-    Err(ConnectionError::Disconnected)
 }
@@ -0,0 +1,144 @@
+//! The wire format is in `barrier_proto`, shared by the tokio and the blocking clients.
+//!
+//! This adds the limits of the clients and the conversions to their errors.
+
+use std::time::Duration;
+
+pub(crate) use barrier_proto::{hello_size, parse_hello, MAX_HELLO_SIZE};
+
+use crate::{ConnectionError, PacketError, ServerInfo};
+
+/// Barrier servers send CALV every 3 seconds unless told otherwise by the HBRT option
+pub(crate) const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Number of missed keep-alive packets before the connection is considered dead
+pub(crate) const KEEPALIVES_UNTIL_DEATH: u32 = 3;
+
+/// Packets larger than this are rejected, Barrier sends the clipboard in chunks of 32 KiB
+/// but older servers send it in one packet, files are sent in chunks of 512 KiB
+#[cfg(any(feature = "clipboard", feature = "file-transfer"))]
+pub(crate) const DEFAULT_MAX_PACKET_SIZE: usize = 4 * 1024 * 1024;
+#[cfg(not(any(feature = "clipboard", feature = "file-transfer")))]
+pub(crate) const DEFAULT_MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// Clipboard data larger than this is discarded, big images can easily exhaust the memory of small devices
+#[cfg(feature = "clipboard")]
+pub(crate) const DEFAULT_MAX_CLIPBOARD_SIZE: usize = 1024 * 1024;
+
+/// Files larger than this are discarded
+#[cfg(feature = "file-transfer")]
+pub(crate) const DEFAULT_MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Longest screen name sent in the hello, in bytes
+pub(crate) const MAX_SCREEN_NAME_LEN: usize = 255;
+
+/// Rejects the names the server can't have in its configuration, before connecting
+pub(crate) fn check_screen_name(name: &str) -> Result<(), ConnectionError> {
+    if name.is_empty() {
+        return Err(ConnectionError::InvalidScreenName("empty"));
+    }
+    if name.len() > MAX_SCREEN_NAME_LEN {
+        return Err(ConnectionError::InvalidScreenName("longer than 255 bytes"));
+    }
+    if name.contains('\0') {
+        return Err(ConnectionError::InvalidScreenName(
+            "contains a NUL character",
+        ));
+    }
+    Ok(())
+}
+
+/// Appends the reply to the hello, in the dialect of the server and the negotiated version
+pub(crate) fn encode_hello(info: ServerInfo, device_name: &str, out: &mut Vec<u8>) {
+    let start = out.len();
+    let len = barrier_proto::encode_hello(info, device_name, &mut []);
+    out.resize(start + len, 0);
+    barrier_proto::encode_hello(info, device_name, &mut out[start..]);
+}
+
+impl From<barrier_proto::Error> for PacketError {
+    fn from(error: barrier_proto::Error) -> Self {
+        use barrier_proto::Error;
+        match error {
+            // The stream ended before the packet did
+            Error::Incomplete { .. } => {
+                PacketError::IoError(std::io::ErrorKind::UnexpectedEof.into())
+            }
+            Error::Format { code } => PacketError::FormatError { code },
+            Error::InsufficientData { needed, available } => {
+                PacketError::InsufficientDataError { needed, available }
+            }
+            Error::PacketTooSmall(size) => PacketError::PacketTooSmall(size),
+            Error::PacketTooLarge(size) => PacketError::PacketTooLarge(size),
+        }
+    }
+}
+
+impl From<barrier_proto::Error> for ConnectionError {
+    fn from(error: barrier_proto::Error) -> Self {
+        ConnectionError::ProtocolError(error.into())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use barrier_proto::packet_size;
+
+    use super::{check_screen_name, encode_hello};
+    use crate::{ConnectionError, PacketError, Protocol, ServerInfo};
+
+    pub(crate) fn wire(size: u32, code: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        [&size.to_be_bytes()[..], code, payload].concat()
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            PacketError::from(packet_size(3, 100).unwrap_err()),
+            PacketError::PacketTooSmall(3)
+        ));
+        let error = PacketError::from(barrier_proto::Error::FORMAT.with_code(*b"DKDN"));
+        assert!(matches!(error, PacketError::FormatError { code: Some(code) } if &code == b"DKDN"));
+        assert_eq!(error.to_string(), "packet \"DKDN\" did not match format");
+        let error = PacketError::from(barrier_proto::Error::Incomplete { needed: 8 });
+        assert!(
+            matches!(error, PacketError::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_check_screen_name() {
+        assert!(check_screen_name("pi").is_ok());
+        assert!(check_screen_name(&"é".repeat(127)).is_ok());
+        for name in [
+            String::new(),
+            "a".repeat(256),
+            "é".repeat(128),
+            "p\0i".to_string(),
+        ] {
+            assert!(
+                matches!(
+                    check_screen_name(&name),
+                    Err(ConnectionError::InvalidScreenName(_))
+                ),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hello() {
+        let mut hello = b"CALV".to_vec();
+        let info = ServerInfo {
+            protocol: Protocol::Barrier,
+            major: 1,
+            minor: 3,
+        };
+        encode_hello(info, "pi", &mut hello);
+        assert_eq!(&hello[..4], b"CALV");
+        assert_eq!(hello[4..].len(), 21);
+        assert_eq!(&hello[8..15], b"Barrier");
+        assert_eq!(&hello[15..19], &[0, 1, 0, 3]);
+        assert_eq!(&hello[23..], b"pi");
+    }
+}
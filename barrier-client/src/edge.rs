@@ -0,0 +1,112 @@
+use crate::Edge;
+
+const EDGES: [Edge; 4] = [Edge::Left, Edge::Right, Edge::Top, Edge::Bottom];
+
+/// Tells when the cursor gets within `margin` pixels of an edge of the screen. An edge is
+/// reached once as the cursor moves into its margin, then again only after leaving it, so a
+/// cursor resting against the edge doesn't fire on every move.
+#[derive(Clone, Debug)]
+pub(crate) struct EdgeDetector {
+    margin: u16,
+    // The cursor is within the margin of each edge of `EDGES`
+    inside: [bool; 4],
+}
+
+impl EdgeDetector {
+    pub fn new(margin: u16) -> Self {
+        Self {
+            margin,
+            inside: [false; 4],
+        }
+    }
+
+    /// The edges whose margin the cursor moved into, going to `position` on a screen of `size`
+    pub fn update(&mut self, position: (u16, u16), size: (u16, u16)) -> impl Iterator<Item = Edge> {
+        let inside = self.inside_at(position, size);
+        let entered = [0, 1, 2, 3].map(|i| inside[i] && !self.inside[i]);
+        self.inside = inside;
+        EDGES
+            .into_iter()
+            .zip(entered)
+            .filter_map(|(edge, entered)| entered.then_some(edge))
+    }
+
+    /// The cursor entered the screen at `position`, usually at the edge it crossed from the
+    /// other screen, which isn't reached until the cursor leaves its margin and comes back
+    pub fn reset(&mut self, position: (u16, u16), size: (u16, u16)) {
+        self.inside = self.inside_at(position, size);
+    }
+
+    fn inside_at(&self, (x, y): (u16, u16), (width, height): (u16, u16)) -> [bool; 4] {
+        if self.margin == 0 {
+            return [false; 4];
+        }
+        [
+            x < self.margin,
+            x >= width.saturating_sub(self.margin),
+            y < self.margin,
+            y >= height.saturating_sub(self.margin),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EdgeDetector;
+    use crate::Edge;
+
+    const SIZE: (u16, u16) = (1920, 1080);
+
+    fn sweep(edges: &mut EdgeDetector, positions: &[(u16, u16)]) -> Vec<Edge> {
+        positions
+            .iter()
+            .flat_map(|&position| edges.update(position, SIZE).collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut edges = EdgeDetector::new(4);
+        // Left to right and back, each edge fires once while the cursor stays in its margin
+        let right: Vec<_> = (0..1920).step_by(2).map(|x| (x, 540)).collect();
+        let left: Vec<_> = right.iter().rev().copied().collect();
+        assert_eq!(sweep(&mut edges, &right), [Edge::Left, Edge::Right]);
+        assert_eq!(sweep(&mut edges, &[(1919, 540), (1917, 540)]), []);
+        assert_eq!(sweep(&mut edges, &left), [Edge::Left]);
+
+        // Top to bottom, the left edge is still held
+        let down: Vec<_> = (0..1080).map(|y| (0, y)).collect();
+        assert_eq!(sweep(&mut edges, &down), [Edge::Top, Edge::Bottom]);
+        // Into the corner across the screen
+        assert_eq!(
+            sweep(&mut edges, &[(960, 540), (1919, 0)]),
+            [Edge::Right, Edge::Top]
+        );
+    }
+
+    #[test]
+    fn test_rearm() {
+        let mut edges = EdgeDetector::new(10);
+        assert_eq!(
+            sweep(&mut edges, &[(5, 500), (0, 500), (9, 500)]),
+            [Edge::Left]
+        );
+        // Leaving the margin re-arms the edge
+        assert_eq!(sweep(&mut edges, &[(10, 500), (9, 500)]), [Edge::Left]);
+        assert_eq!(
+            sweep(&mut edges, &[(1910, 500), (1909, 500), (1910, 500)]),
+            [Edge::Right, Edge::Right]
+        );
+
+        // Entering at the left edge doesn't reach it
+        edges.reset((0, 500), SIZE);
+        assert_eq!(
+            sweep(&mut edges, &[(1, 500), (20, 500), (2, 500)]),
+            [Edge::Left]
+        );
+
+        // A margin of 0 never fires
+        let mut edges = EdgeDetector::new(0);
+        assert_eq!(sweep(&mut edges, &[(0, 0), (1919, 1079)]), []);
+    }
+}
@@ -1,4 +1,4 @@
-use std::io;
+use std::{borrow::Cow, io, time::Duration};
 
 use thiserror::Error;
 
@@ -6,12 +6,66 @@ use thiserror::Error;
 pub enum PacketError {
     #[error("io error")]
     IoError(#[from] io::Error),
-    #[error("did not match format")]
-    FormatError,
-    #[error("not enough data")]
-    InsufficientDataError,
-    #[error("Packet too small")]
-    PacketTooSmall,
+    /// `code` is the packet code when the error is in the fields of a packet
+    #[error("{} did not match format", describe_code(.code))]
+    FormatError { code: Option<[u8; 4]> },
+    #[error("not enough data, {needed} bytes needed but {available} left")]
+    InsufficientDataError { needed: usize, available: usize },
+    #[error("packet of {0} bytes is too small")]
+    PacketTooSmall(usize),
+    #[error("packet of {0} bytes exceeds the size limit")]
+    PacketTooLarge(usize),
+    /// The stream was out of sync, `skipped` bytes were dropped to get to the next packet and
+    /// it can be read again
+    #[error("stream out of sync, {skipped} bytes skipped to the next packet")]
+    Resynchronized { skipped: usize },
+    #[error("{0} unknown packets in a row")]
+    TooManyUnknown(usize),
+}
+
+impl PacketError {
+    /// Format error without a packet code
+    #[cfg(any(test, feature = "test-server", feature = "remote-actuator"))]
+    pub(crate) const FORMAT: PacketError = PacketError::FormatError { code: None };
+}
+
+fn describe_code(code: &Option<[u8; 4]>) -> String {
+    match code {
+        Some(code) => format!("packet {:?}", String::from_utf8_lossy(code)),
+        None => "data".to_string(),
+    }
+}
+
+/// Returned by the actuator callbacks, by default the client stops and reports it as
+/// [`ConnectionError::ActuatorError`]
+#[derive(Error, Debug)]
+pub enum ActuatorError {
+    /// `context` says what was being done, e.g. "hidg0 write"
+    #[error("{context} failed")]
+    IoError {
+        context: Cow<'static, str>,
+        #[source]
+        source: io::Error,
+    },
+    /// Whatever applies the events is gone, e.g. the receiving end of a channel
+    #[error("actuator closed")]
+    Closed,
+    #[error("{0}")]
+    Other(Cow<'static, str>),
+}
+
+impl ActuatorError {
+    /// An I/O error with what was being done, e.g. `ActuatorError::io("hidg0 write", err)`
+    pub fn io<C: Into<Cow<'static, str>>>(context: C, source: io::Error) -> Self {
+        ActuatorError::IoError {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn other<M: Into<Cow<'static, str>>>(message: M) -> Self {
+        ActuatorError::Other(message.into())
+    }
 }
 
 #[derive(Error, Debug)]
@@ -20,6 +74,140 @@ pub enum ConnectionError {
     Disconnected,
     #[error("tcp connection failed")]
     TcpError(#[from] io::Error),
+    /// The connection can't be bound to the local address or interface, e.g. it has no
+    /// address yet
+    #[error("cannot bind to {local}")]
+    BindFailed {
+        local: String,
+        #[source]
+        source: io::Error,
+    },
     #[error("invalid data received")]
     ProtocolError(#[from] PacketError),
+    #[error("actuator failed")]
+    ActuatorError(#[from] ActuatorError),
+    #[error("keep-alive or write timeout")]
+    Timeout,
+    /// The server sent nothing for this long after the hello, not even an error
+    #[error("no reply from the server {0:?} after the hello")]
+    HandshakeTimeout(Duration),
+    #[error("missing client option {0}")]
+    MissingOption(&'static str),
+    #[error("invalid screen name: {0}")]
+    InvalidScreenName(&'static str),
+    /// The server has no screen of this name in its configuration
+    #[error("screen name {0:?} unknown to the server")]
+    UnknownScreenName(String),
+    #[error("another client with the same screen name is connected")]
+    Busy,
+    #[error("server reported a protocol error")]
+    BadProtocol,
+    #[error("server protocol version {major}.{minor} is incompatible")]
+    IncompatibleVersion { major: u16, minor: u16 },
+    #[error("connection closed by the server")]
+    Closed,
+    #[cfg(feature = "tls")]
+    #[error("tls handshake failed")]
+    TlsError(#[source] io::Error),
+    #[cfg(feature = "tls")]
+    #[error("invalid tls configuration: {0}")]
+    TlsConfigError(String),
+    #[cfg(feature = "tls")]
+    #[error("server certificate fingerprint {0} does not match the configured one")]
+    FingerprintMismatch(String),
+    #[cfg(feature = "socks")]
+    #[error("cannot connect to the proxy")]
+    ProxyUnreachable(#[source] io::Error),
+    #[cfg(feature = "socks")]
+    #[error("proxy rejected the credentials")]
+    ProxyAuthFailed,
+    #[cfg(feature = "socks")]
+    #[error("proxy cannot connect to the server: {0}")]
+    ProxyConnectFailed(String),
+    #[cfg(feature = "socks")]
+    #[error("invalid proxy configuration: {0}")]
+    ProxyConfigError(String),
+    #[cfg(feature = "socks")]
+    #[error("proxy handshake failed: {0}")]
+    ProxyProtocolError(String),
+}
+
+impl ConnectionError {
+    /// Retrying won't help, the configuration or the server has to be changed first
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            ConnectionError::MissingOption(_)
+            | ConnectionError::InvalidScreenName(_)
+            | ConnectionError::Busy
+            | ConnectionError::IncompatibleVersion { .. } => true,
+            #[cfg(feature = "tls")]
+            ConnectionError::TlsConfigError(_) | ConnectionError::FingerprintMismatch(_) => true,
+            #[cfg(feature = "socks")]
+            ConnectionError::ProxyAuthFailed | ConnectionError::ProxyConfigError(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error, io, time::Duration};
+
+    use super::{ActuatorError, ConnectionError, PacketError};
+
+    #[test]
+    fn test_is_fatal() {
+        assert!(ConnectionError::MissingOption("server").is_fatal());
+        assert!(ConnectionError::InvalidScreenName("empty").is_fatal());
+        assert!(ConnectionError::Busy.is_fatal());
+        assert!(ConnectionError::IncompatibleVersion { major: 1, minor: 8 }.is_fatal());
+        #[cfg(feature = "tls")]
+        assert!(ConnectionError::FingerprintMismatch(String::new()).is_fatal());
+        #[cfg(feature = "socks")]
+        assert!(ConnectionError::ProxyAuthFailed.is_fatal());
+
+        assert!(!ConnectionError::Disconnected.is_fatal());
+        assert!(!ConnectionError::Timeout.is_fatal());
+        assert!(!ConnectionError::HandshakeTimeout(Duration::from_secs(5)).is_fatal());
+        // The screen can be added to the server while the client retries
+        assert!(!ConnectionError::UnknownScreenName("pi".to_string()).is_fatal());
+        assert!(!ConnectionError::BadProtocol.is_fatal());
+        assert!(!ConnectionError::Closed.is_fatal());
+        assert!(!ConnectionError::ProtocolError(PacketError::FORMAT).is_fatal());
+        assert!(!ConnectionError::ActuatorError(ActuatorError::Closed).is_fatal());
+        // The interface may get its address later, e.g. from DHCP
+        let error = ConnectionError::BindFailed {
+            local: "192.168.1.2:0".to_string(),
+            source: io::Error::from(io::ErrorKind::AddrNotAvailable),
+        };
+        assert!(!error.is_fatal());
+        #[cfg(feature = "socks")]
+        assert!(!ConnectionError::ProxyConnectFailed(String::new()).is_fatal());
+    }
+
+    #[test]
+    fn test_sources() {
+        let error = ConnectionError::from(ActuatorError::io(
+            "hidg0 write",
+            io::Error::new(io::ErrorKind::BrokenPipe, "gone"),
+        ));
+        assert_eq!(error.to_string(), "actuator failed");
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "hidg0 write failed");
+        assert_eq!(source.source().unwrap().to_string(), "gone");
+
+        let error = PacketError::FormatError {
+            code: Some(*b"DKDN"),
+        };
+        assert_eq!(error.to_string(), "packet \"DKDN\" did not match format");
+        assert_eq!(PacketError::FORMAT.to_string(), "data did not match format");
+        let error = PacketError::InsufficientDataError {
+            needed: 4,
+            available: 1,
+        };
+        assert_eq!(
+            error.to_string(),
+            "not enough data, 4 bytes needed but 1 left"
+        );
+    }
 }
@@ -1,28 +1,114 @@
 mod actuator;
-mod client;
+mod click_assist;
+#[cfg_attr(
+    not(any(feature = "tokio", feature = "blocking")),
+    allow(dead_code, unused_imports)
+)]
+mod codec;
 mod error;
-mod packet;
+
+#[cfg_attr(
+    not(any(feature = "tokio", feature = "blocking")),
+    allow(unused_imports)
+)]
+pub(crate) use barrier_proto::Packet;
+pub use error::{ActuatorError, ConnectionError, PacketError};
+
+#[cfg(feature = "async-actuator")]
+pub use actuator::AsyncActuator;
+pub use actuator::{Actuator, ActuatorMessage, Edge, KeyMask, LedState, Protocol, ServerInfo};
+/// The attribute `AsyncActuator` implementations need, in the version the trait is built with
+#[cfg(feature = "async-actuator")]
+pub use async_trait::async_trait;
+pub use click_assist::{ClickAssist, ClickAssistOptions};
+
+#[cfg(feature = "tokio")]
+mod adapter;
+#[cfg(feature = "tokio")]
+mod capture;
+#[cfg(feature = "tokio")]
+mod channel_act;
+#[cfg(feature = "tokio")]
+mod client;
+#[cfg(feature = "tokio")]
+mod edge;
+#[cfg(feature = "tokio")]
+mod metrics;
+#[cfg(feature = "tokio")]
 mod packet_io;
+#[cfg(feature = "tokio")]
 mod packet_stream;
+#[cfg(feature = "tokio")]
+mod reconnect;
+#[cfg(feature = "tokio")]
+mod servers;
+#[cfg(feature = "tokio")]
+mod stats;
 
-pub(crate) use error::{ConnectionError, PacketError};
-pub(crate) use packet::Packet;
+#[cfg(feature = "tokio")]
 pub(crate) use packet_io::{PacketReader, PacketWriter};
+#[cfg(feature = "tokio")]
 pub(crate) use packet_stream::PacketStream;
 
-pub use actuator::{Actuator, ActuatorMessage};
-pub use client::start;
-#[cfg(feature = "async-actuator")]
-pub use actuator::AsyncActuator;
-#[cfg(feature = "async-actuator")]
+#[cfg(feature = "tokio")]
+pub use capture::{read_capture, CaptureOptions, CapturedFrame, CAPTURE_ENV};
+#[cfg(feature = "tokio")]
+pub use channel_act::{dispatch, ChannelActuator, OnFull};
+#[cfg(all(feature = "async-actuator", feature = "tokio"))]
 pub use client::start_async;
+#[cfg(feature = "tokio")]
+pub use client::{
+    start, start_with_stream, ActuatorErrorPolicy, Client, ClientBuilder, ClientHandle,
+    TcpKeepalive,
+};
+#[cfg(feature = "tokio")]
+pub use metrics::{ActuatorErrorAction, LogMetrics, Metrics};
+#[cfg(feature = "tokio")]
+pub use reconnect::{Backoff, ClientStatus, ConnectionState};
+#[cfg(feature = "tokio")]
+pub use servers::ServerList;
+#[cfg(feature = "tokio")]
+pub use stats::ClientStats;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use client::start_tls;
+#[cfg(all(feature = "async-actuator", feature = "tls"))]
+pub use client::start_tls_async;
+#[cfg(feature = "tls")]
+pub use tls::{Fingerprint, InvalidFingerprint, TlsOptions, TlsVerification};
+
+#[cfg(feature = "socks")]
+mod proxy;
+#[cfg(feature = "socks")]
+pub use proxy::{Proxy, ProxyAuth};
+
+#[cfg(feature = "barrier-options")]
+mod options;
+#[cfg(feature = "barrier-options")]
+pub use options::{ServerModes, ServerOptions};
 
 #[cfg(feature = "clipboard")]
-mod clipboard;
-#[cfg(feature = "clipboard")]
-pub use clipboard::ClipboardData;
+pub(crate) use barrier_proto::ClipboardStage;
 #[cfg(feature = "clipboard")]
-pub(crate) use clipboard::ClipboardStage;
+pub use barrier_proto::{ClipboardData, ClipboardDataBuilder, ClipboardFormat};
+
+#[cfg(feature = "remote-actuator")]
+mod remote_act;
+#[cfg(feature = "remote-actuator")]
+pub use remote_act::{serve_actuator, serve_connection, RemoteActuator, DEFAULT_MTU};
+
+#[cfg(feature = "record")]
+mod record;
+#[cfg(feature = "record")]
+pub use record::{replay, RecordingActuator, DEFAULT_MAX_BITMAP_SIZE};
+
+#[cfg(all(any(test, feature = "test-server"), feature = "tokio"))]
+pub mod test_server;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,379 @@
+use std::{
+    fmt::{Debug, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+/// What the client did about an actuator callback that failed, see
+/// [`crate::ClientBuilder::on_actuator_error`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ActuatorErrorAction {
+    /// The callback is called again after the delay of the policy
+    Retried,
+    /// The event is lost, the client goes on with the next one
+    Skipped,
+    /// The connection ends with the error
+    Aborted,
+}
+
+/// Instrumentation hook set by [`crate::ClientBuilder::metrics`], every method does nothing
+/// by default.
+///
+/// The client doesn't read the clock at all if no hook is set.
+pub trait Metrics: Send + Sync {
+    /// A packet with the 4 bytes protocol code `kind` has been read, `wire_bytes` includes
+    /// the length prefix. Every chunk of the clipboard data is reported on its own.
+    fn packet_received(&self, kind: [u8; 4], wire_bytes: usize) {
+        let _ = (kind, wire_bytes);
+    }
+
+    /// A packet with the code `kind` has been written to the server, `wire_bytes` includes
+    /// the length prefix. The hello isn't reported.
+    fn packet_sent(&self, kind: [u8; 4], wire_bytes: usize) {
+        let _ = (kind, wire_bytes);
+    }
+
+    /// The actuator returned from the call for a packet of `kind`, `elapsed` is counted from
+    /// the moment the packet was read, so it includes the time spent coalescing mouse moves.
+    fn event_dispatched(&self, kind: [u8; 4], elapsed: Duration) {
+        let _ = (kind, elapsed);
+    }
+
+    /// The server acknowledged the screen info sent for its query after `rtt`.
+    ///
+    /// Barrier doesn't answer the keep-alives echoed back, so this is the only round trip
+    /// the client can measure, it happens on connecting and whenever the server asks again.
+    fn round_trip(&self, rtt: Duration) {
+        let _ = rtt;
+    }
+
+    /// A mouse or keyboard packet of `kind` has been dropped because the cursor wasn't on
+    /// this screen, see [`crate::ClientBuilder::strict_enter_gating`], or a move has been
+    /// merged into the previous one as the actuator queue was full, see
+    /// [`crate::ClientBuilder::actuator_queue_size`].
+    fn input_dropped(&self, kind: [u8; 4]) {
+        let _ = kind;
+    }
+
+    /// The actuator skipped a report to `device` identical to the last one it wrote, e.g. the
+    /// same mouse position sent again. Called by the actuators sharing the hook, not the client.
+    fn report_skipped(&self, device: &'static str) {
+        let _ = device;
+    }
+
+    /// The actuator `callback`, e.g. "key_down", returned an error and the client took
+    /// `action`. Persistent failures show up as a stream of these, whatever the policy.
+    fn actuator_error(&self, callback: &'static str, action: ActuatorErrorAction) {
+        let _ = (callback, action);
+    }
+}
+
+/// A hook shared with the actuator, e.g. to count the reports it skips
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn packet_received(&self, kind: [u8; 4], wire_bytes: usize) {
+        (**self).packet_received(kind, wire_bytes)
+    }
+
+    fn packet_sent(&self, kind: [u8; 4], wire_bytes: usize) {
+        (**self).packet_sent(kind, wire_bytes)
+    }
+
+    fn event_dispatched(&self, kind: [u8; 4], elapsed: Duration) {
+        (**self).event_dispatched(kind, elapsed)
+    }
+
+    fn round_trip(&self, rtt: Duration) {
+        (**self).round_trip(rtt)
+    }
+
+    fn input_dropped(&self, kind: [u8; 4]) {
+        (**self).input_dropped(kind)
+    }
+
+    fn report_skipped(&self, device: &'static str) {
+        (**self).report_skipped(device)
+    }
+
+    fn actuator_error(&self, callback: &'static str, action: ActuatorErrorAction) {
+        (**self).actuator_error(callback, action)
+    }
+}
+
+/// Both hooks are called, e.g. `(log, stats)` to log the metrics and keep the
+/// [`ClientStats`](crate::ClientStats)
+impl<A: Metrics, B: Metrics> Metrics for (A, B) {
+    fn packet_received(&self, kind: [u8; 4], wire_bytes: usize) {
+        self.0.packet_received(kind, wire_bytes);
+        self.1.packet_received(kind, wire_bytes);
+    }
+
+    fn packet_sent(&self, kind: [u8; 4], wire_bytes: usize) {
+        self.0.packet_sent(kind, wire_bytes);
+        self.1.packet_sent(kind, wire_bytes);
+    }
+
+    fn event_dispatched(&self, kind: [u8; 4], elapsed: Duration) {
+        self.0.event_dispatched(kind, elapsed);
+        self.1.event_dispatched(kind, elapsed);
+    }
+
+    fn round_trip(&self, rtt: Duration) {
+        self.0.round_trip(rtt);
+        self.1.round_trip(rtt);
+    }
+
+    fn input_dropped(&self, kind: [u8; 4]) {
+        self.0.input_dropped(kind);
+        self.1.input_dropped(kind);
+    }
+
+    fn report_skipped(&self, device: &'static str) {
+        self.0.report_skipped(device);
+        self.1.report_skipped(device);
+    }
+
+    fn actuator_error(&self, callback: &'static str, action: ActuatorErrorAction) {
+        self.0.actuator_error(callback, action);
+        self.1.actuator_error(callback, action);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct MetricsHook(pub Arc<dyn Metrics>);
+
+impl Debug for MetricsHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MetricsHook")
+    }
+}
+
+// Values below this many microseconds get a bucket each
+const LINEAR_BUCKETS: usize = 16;
+
+// Every power of 2 above is split in 8 buckets, so the error is below 12.5%
+const SUB_BUCKET_BITS: u32 = 3;
+
+// Enough for more than an hour
+const BUCKETS: usize = 256;
+
+/// Latency histogram with logarithmic buckets in microseconds
+#[derive(Clone, Debug)]
+pub(crate) struct Histogram {
+    counts: [u64; BUCKETS],
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: Duration) {
+        let us = value.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[Self::bucket(us).min(BUCKETS - 1)] += 1;
+        self.total += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, `None` if nothing was recorded
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((self.total as f64 * q).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Duration::from_micros(Self::upper_bound(bucket)));
+            }
+        }
+        unreachable!()
+    }
+
+    fn bucket(us: u64) -> usize {
+        if us < LINEAR_BUCKETS as u64 {
+            return us as usize;
+        }
+        let shift = (63 - us.leading_zeros()) - SUB_BUCKET_BITS;
+        ((shift as usize) << SUB_BUCKET_BITS) + (us >> shift) as usize
+    }
+
+    fn upper_bound(bucket: usize) -> u64 {
+        if bucket < LINEAR_BUCKETS {
+            return bucket as u64 + 1;
+        }
+        let shift = (bucket >> SUB_BUCKET_BITS) as u32 - 1;
+        let mantissa = (bucket & ((1 << SUB_BUCKET_BITS) - 1)) as u64 + (1 << SUB_BUCKET_BITS);
+        (mantissa + 1) << shift
+    }
+}
+
+#[derive(Default)]
+struct Window {
+    packets: u64,
+    wire_bytes: u64,
+    // Number of packets per protocol code, there are only a few kinds
+    kinds: Vec<([u8; 4], u64)>,
+    dispatch: Histogram,
+    round_trip: Option<Duration>,
+    dropped: u64,
+    skipped: u64,
+    actuator_errors: u64,
+}
+
+/// [`Metrics`] logging the packet counts and the dispatch latencies at the info level,
+/// once per interval.
+///
+/// The log is written from the client task when an event is dispatched after the interval
+/// has passed, so nothing is logged while the connection is idle.
+pub struct LogMetrics {
+    interval: Duration,
+    state: Mutex<(Instant, Window)>,
+}
+
+impl LogMetrics {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new((Instant::now(), Window::default())),
+        }
+    }
+}
+
+impl Metrics for LogMetrics {
+    fn packet_received(&self, kind: [u8; 4], wire_bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        let window = &mut state.1;
+        window.packets += 1;
+        window.wire_bytes += wire_bytes as u64;
+        match window.kinds.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, count)) => *count += 1,
+            None => window.kinds.push((kind, 1)),
+        }
+    }
+
+    fn event_dispatched(&self, _kind: [u8; 4], elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.1.dispatch.record(elapsed);
+        if state.0.elapsed() < self.interval {
+            return;
+        }
+        let window = std::mem::take(&mut state.1);
+        state.0 = Instant::now();
+        drop(state);
+        info!("{}", summary(&window));
+    }
+
+    fn round_trip(&self, rtt: Duration) {
+        self.state.lock().unwrap().1.round_trip = Some(rtt);
+    }
+
+    fn input_dropped(&self, _kind: [u8; 4]) {
+        self.state.lock().unwrap().1.dropped += 1;
+    }
+
+    fn report_skipped(&self, _device: &'static str) {
+        self.state.lock().unwrap().1.skipped += 1;
+    }
+
+    fn actuator_error(&self, _callback: &'static str, _action: ActuatorErrorAction) {
+        self.state.lock().unwrap().1.actuator_errors += 1;
+    }
+}
+
+fn summary(window: &Window) -> String {
+    let mut line = format!(
+        "{} packets, {} bytes, {} events",
+        window.packets,
+        window.wire_bytes,
+        window.dispatch.count()
+    );
+    if let (Some(p50), Some(p99)) = (
+        window.dispatch.quantile(0.5),
+        window.dispatch.quantile(0.99),
+    ) {
+        write!(line, ", latency p50 {:?} p99 {:?}", p50, p99).ok();
+    }
+    if let Some(rtt) = window.round_trip {
+        write!(line, ", round trip {:?}", rtt).ok();
+    }
+    if window.dropped > 0 {
+        write!(line, ", {} dropped before entering", window.dropped).ok();
+    }
+    if window.skipped > 0 {
+        write!(line, ", {} duplicate reports skipped", window.skipped).ok();
+    }
+    if window.actuator_errors > 0 {
+        write!(line, ", {} actuator errors", window.actuator_errors).ok();
+    }
+    let mut kinds = window.kinds.clone();
+    kinds.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (kind, count) in kinds {
+        write!(line, ", {} {}", String::from_utf8_lossy(&kind), count).ok();
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{summary, Histogram, Window, BUCKETS};
+
+    #[test]
+    fn test_histogram() {
+        // Buckets are contiguous and each value is below the upper bound of its bucket
+        let mut last = 0;
+        for us in 0..100_000u64 {
+            let bucket = Histogram::bucket(us);
+            assert!(bucket == last || bucket == last + 1, "{us}");
+            assert!(us < Histogram::upper_bound(bucket));
+            assert!(us as f64 >= Histogram::upper_bound(bucket) as f64 / 1.125 - 1.0);
+            last = bucket;
+        }
+        assert!(Histogram::bucket(3_600_000_000) < BUCKETS);
+
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        for us in 1..=100 {
+            histogram.record(Duration::from_micros(us));
+        }
+        histogram.record(Duration::from_secs(10));
+        assert_eq!(histogram.count(), 101);
+        // 51 is in the 48..52 bucket
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(52)));
+        assert_eq!(histogram.quantile(0.99), Some(Duration::from_micros(104)));
+        assert!(histogram.quantile(1.0).unwrap() >= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut window = Window {
+            packets: 3,
+            wire_bytes: 40,
+            kinds: vec![(*b"CALV", 1), (*b"DMMV", 2)],
+            round_trip: Some(Duration::from_millis(2)),
+            dropped: 4,
+            skipped: 5,
+            actuator_errors: 2,
+            ..Default::default()
+        };
+        window.dispatch.record(Duration::from_micros(5));
+        window.dispatch.record(Duration::from_micros(5));
+        assert_eq!(
+            summary(&window),
+            "3 packets, 40 bytes, 2 events, latency p50 6µs p99 6µs, round trip 2ms, 4 dropped before entering, 5 duplicate reports skipped, 2 actuator errors, DMMV 2, CALV 1"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Options the server sets with DSOP, only the options sent are `Some`.
+///
+/// Each DSOP adds to the options already set, until the server resets them all with CROP.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerOptions {
+    /// HBRT, the keep-alive interval
+    pub heartbeat: Option<Duration>,
+    /// SCSS, the screen saver of the client follows the one of the server
+    pub screen_saver_sync: Option<bool>,
+    /// HDCL, Caps Lock is a toggle on the server, the key down turns it on and the key
+    /// up turns it off, so each has to be sent as a press and release
+    pub half_duplex_caps_lock: Option<bool>,
+    /// HDNL, same as `half_duplex_caps_lock` for Num Lock
+    pub half_duplex_num_lock: Option<bool>,
+    /// CLPS, the clipboard is shared with this screen
+    pub clipboard_sharing: Option<bool>,
+    /// MDLT, the server's relativeMouseMoves, the cursor follows the moves of the server's
+    /// mouse instead of jumping to its position, see [`ServerModes`]
+    pub relative_mouse_moves: Option<bool>,
+    /// Options not known above by their 4 characters name
+    pub other: HashMap<String, u32>,
+}
+
+impl ServerOptions {
+    /// The options in the DSOP form, the inverse of the `From` conversion
+    pub fn to_map(&self) -> HashMap<String, u32> {
+        let mut map = self.other.clone();
+        if let Some(heartbeat) = self.heartbeat {
+            map.insert("HBRT".to_string(), heartbeat.as_millis() as u32);
+        }
+        for (name, value) in [
+            ("SCSS", self.screen_saver_sync),
+            ("HDCL", self.half_duplex_caps_lock),
+            ("HDNL", self.half_duplex_num_lock),
+            ("CLPS", self.clipboard_sharing),
+            ("MDLT", self.relative_mouse_moves),
+        ] {
+            if let Some(value) = value {
+                map.insert(name.to_string(), value as u32);
+            }
+        }
+        map
+    }
+}
+
+impl From<HashMap<String, u32>> for ServerOptions {
+    fn from(mut map: HashMap<String, u32>) -> Self {
+        let mut flag = |name: &str| map.remove(name).map(|value| value != 0);
+        Self {
+            screen_saver_sync: flag("SCSS"),
+            half_duplex_caps_lock: flag("HDCL"),
+            half_duplex_num_lock: flag("HDNL"),
+            clipboard_sharing: flag("CLPS"),
+            relative_mouse_moves: flag("MDLT"),
+            // In milliseconds
+            heartbeat: map
+                .remove("HBRT")
+                .map(|rate| Duration::from_millis(rate as u64)),
+            other: map,
+        }
+    }
+}
+
+/// The options of a DSOP packet
+impl From<BTreeMap<String, u32>> for ServerOptions {
+    fn from(map: BTreeMap<String, u32>) -> Self {
+        Self::from(map.into_iter().collect::<HashMap<_, _>>())
+    }
+}
+
+/// What the options set so far change in the way the client applies the events, kept by
+/// the client loop for the session. Each DSOP only changes the modes it sends, CROP turns
+/// them all off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerModes {
+    /// The absolute moves are passed to the actuator as the move from the last position,
+    /// with `move_cursor`
+    pub relative_mouse_moves: bool,
+    /// Caps Lock toggles on each key down and key up, the actuator applies it
+    pub half_duplex_caps_lock: bool,
+    /// Same as `half_duplex_caps_lock` for Num Lock
+    pub half_duplex_num_lock: bool,
+}
+
+impl ServerModes {
+    /// Applies the options of a DSOP, returns whether a mode changed
+    pub fn update(&mut self, opts: &ServerOptions) -> bool {
+        let before = *self;
+        for (mode, option) in [
+            (&mut self.relative_mouse_moves, opts.relative_mouse_moves),
+            (&mut self.half_duplex_caps_lock, opts.half_duplex_caps_lock),
+            (&mut self.half_duplex_num_lock, opts.half_duplex_num_lock),
+        ] {
+            if let Some(option) = option {
+                *mode = option;
+            }
+        }
+        *self != before
+    }
+}
+
+/// The relative move from `from` to `to` in the range of DMRM, for `relative_mouse_moves`
+pub(crate) fn relative_move(from: (u16, u16), to: (u16, u16)) -> (i16, i16) {
+    let delta = |from: u16, to: u16| (to as i32 - from as i32).clamp(-0x8000, 0x7fff) as i16;
+    (delta(from.0, to.0), delta(from.1, to.1))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{ServerModes, ServerOptions};
+
+    #[test]
+    fn test_from_map() {
+        let map: HashMap<String, u32> = [
+            ("HBRT", 5000),
+            ("HDCL", 1),
+            ("HDNL", 0),
+            ("CLPS", 1),
+            ("MDLT", 1),
+            ("XTRA", 42),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        let options = ServerOptions::from(map.clone());
+        assert_eq!(
+            options,
+            ServerOptions {
+                heartbeat: Some(Duration::from_secs(5)),
+                screen_saver_sync: None,
+                half_duplex_caps_lock: Some(true),
+                half_duplex_num_lock: Some(false),
+                clipboard_sharing: Some(true),
+                relative_mouse_moves: Some(true),
+                other: [("XTRA".to_string(), 42)].into(),
+            }
+        );
+        assert_eq!(options.to_map(), map);
+
+        assert_eq!(
+            ServerOptions::from(HashMap::new()),
+            ServerOptions::default()
+        );
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut modes = ServerModes::default();
+        let relative = ServerOptions {
+            relative_mouse_moves: Some(true),
+            ..Default::default()
+        };
+        assert!(modes.update(&relative));
+        assert!(!modes.update(&relative));
+        // The options not sent are kept
+        let caps = ServerOptions {
+            half_duplex_caps_lock: Some(true),
+            heartbeat: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert!(modes.update(&caps));
+        assert!(!modes.update(&ServerOptions::default()));
+        assert_eq!(
+            modes,
+            ServerModes {
+                relative_mouse_moves: true,
+                half_duplex_caps_lock: true,
+                half_duplex_num_lock: false,
+            }
+        );
+        assert!(modes.update(&ServerOptions {
+            relative_mouse_moves: Some(false),
+            ..Default::default()
+        }));
+        assert!(!modes.relative_mouse_moves);
+    }
+}
@@ -1,293 +1,570 @@
-#[cfg(feature = "clipboard")]
-use log::{debug, warn};
-use tokio::io::{AsyncRead, AsyncReadExt};
+use std::sync::{Arc, Mutex};
+
+use log::{log_enabled, trace, Level};
+use tokio::io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+
+use barrier_proto::{find_packet, is_plausible_code, parse_packet, peek_code, Error};
 
 #[cfg(feature = "clipboard")]
-use crate::{clipboard::parse_clipboard, ClipboardStage};
+use crate::ClipboardStage;
+use crate::{
+    capture::{Capture, HexDump},
+    codec::DEFAULT_MAX_PACKET_SIZE,
+};
 
 use super::{Packet, PacketError, PacketReader, PacketWriter};
 
-pub struct PacketStream<S: PacketReader + PacketWriter> {
+/// Reads and writes packets on an async stream, the parsing is done by `barrier_proto`
+pub struct PacketStream<S> {
     stream: S,
+    #[cfg(feature = "clipboard")]
+    max_clipboard_size: usize,
+    max_packet_size: usize,
+    resync: bool,
+    max_unknown_packets: usize,
+    // Unknown packets read since the last known one
+    unknown_packets: usize,
+    // Received and not parsed yet from `start`, nothing is lost if a read is cancelled
+    received: Vec<u8>,
+    // The packets before it are parsed, they're dropped before reading more instead of
+    // moving the rest of the buffer after each packet
+    start: usize,
+    // The packet being written, reused to save allocations
+    sent: Vec<u8>,
+    last_received: ([u8; 4], usize),
+    // Shared by the halves of a split stream
+    capture: Option<Arc<Mutex<Capture>>>,
 }
 
-impl<S: PacketReader + PacketWriter> PacketStream<S> {
-    pub fn new(stream: S) -> Self {
-        Self { stream }
+impl<S> PacketStream<S> {
+    pub fn new(stream: S, #[cfg(feature = "clipboard")] max_clipboard_size: usize) -> Self {
+        Self {
+            stream,
+            #[cfg(feature = "clipboard")]
+            max_clipboard_size,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            resync: true,
+            max_unknown_packets: DEFAULT_MAX_UNKNOWN_PACKETS,
+            unknown_packets: 0,
+            received: Vec::with_capacity(READ_SIZE),
+            start: 0,
+            sent: vec![],
+            last_received: ([0; 4], 0),
+            capture: None,
+        }
     }
 
-    pub async fn read(
-        &mut self,
-        #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
-    ) -> Result<Packet, PacketError> {
-        let size = self.stream.read_packet_size().await?;
-        if size < 4 {
-            let mut buf = [0; 4];
-            self.stream.read_exact(&mut buf[0..size as usize]).await?;
-            return Err(PacketError::PacketTooSmall);
-        }
-        Self::do_read(
-            &mut self.stream,
-            size as usize,
+    /// Record the packets read and written, see [`crate::CaptureOptions`]
+    pub fn capture(mut self, capture: Option<Capture>) -> Self {
+        self.capture = capture.map(|capture| Arc::new(Mutex::new(capture)));
+        self
+    }
+
+    /// Reject packets larger than this many bytes, the size from the wire is trusted otherwise
+    pub fn max_packet_size(mut self, size: usize) -> Self {
+        self.max_packet_size = size;
+        self
+    }
+
+    /// Skip to the next packet when a size or a code read can't be one, instead of failing.
+    /// The read skipping returns [`PacketError::Resynchronized`], the next one the packet.
+    /// The bytes skipped are limited to the max packet size, default is on.
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.resync = resync;
+        self
+    }
+
+    /// Fail with [`PacketError::TooManyUnknown`] after this many unknown packets in a row,
+    /// past a few the stream is more likely out of sync than the server newer
+    #[cfg(test)]
+    pub fn max_unknown_packets(mut self, count: usize) -> Self {
+        self.max_unknown_packets = count;
+        self
+    }
+
+    // Same settings over another stream, nothing received
+    fn with_stream<T>(&self, stream: T) -> PacketStream<T> {
+        PacketStream {
+            stream,
             #[cfg(feature = "clipboard")]
-            clipboard_stage,
-        )
-        .await
+            max_clipboard_size: self.max_clipboard_size,
+            max_packet_size: self.max_packet_size,
+            resync: self.resync,
+            max_unknown_packets: self.max_unknown_packets,
+            unknown_packets: 0,
+            received: vec![],
+            start: 0,
+            sent: vec![],
+            last_received: ([0; 4], 0),
+            capture: self.capture.clone(),
+        }
     }
 
-    async fn do_read<T: AsyncRead + Send + Unpin>(
-        chunk: &mut T,
-        mut limit: usize,
+    // Traces and captures the first `size` bytes of the buffer of the direction
+    fn log_frame(&mut self, received: bool, size: usize) {
+        let frame = if received {
+            &self.received[self.start..self.start + size]
+        } else {
+            &self.sent[..size]
+        };
+        if log_enabled!(Level::Trace) {
+            trace!(
+                "{} {} {} bytes: {}",
+                if received { "<-" } else { "->" },
+                String::from_utf8_lossy(frame.get(4..8).unwrap_or_default()),
+                size,
+                HexDump(frame)
+            );
+        }
+        if let Some(capture) = &self.capture {
+            capture.lock().unwrap().record(received, frame);
+        }
+    }
+}
+
+impl<S: PacketReader + PacketWriter> PacketStream<S> {
+    /// Splits the stream to read and write from tasks of their own. What has been received
+    /// and not read yet goes to the reading half, the capture is shared.
+    pub fn split(self) -> (PacketStream<ReadHalf<S>>, PacketStream<WriteHalf<S>>) {
+        let settings = self.with_stream(());
+        let (reader, writer) = split(self.stream);
+        let mut reader = settings.with_stream(reader);
+        reader.received = self.received;
+        reader.start = self.start;
+        reader.unknown_packets = self.unknown_packets;
+        reader.last_received = self.last_received;
+        let writer = settings.with_stream(writer);
+        (reader, writer)
+    }
+}
+
+impl<S: PacketReader> PacketStream<S> {
+    /// Code and size on the wire of the packet read last
+    pub fn last_received(&self) -> ([u8; 4], usize) {
+        self.last_received
+    }
+
+    /// Code of the next packet if it has already been received completely
+    pub fn peek_buffered(&self) -> Option<[u8; 4]> {
+        peek_code(&self.received[self.start..])
+    }
+
+    pub async fn read(
+        &mut self,
         #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
     ) -> Result<Packet, PacketError> {
-        let code: [u8; 4] = chunk.read_bytes_fixed().await?;
-        limit -= 4;
-
-        let packet = match code.as_ref() {
-            b"QINF" => Packet::QueryInfo,
-            b"CIAK" => Packet::InfoAck,
-            b"CALV" => Packet::KeepAlive,
-            #[cfg(feature = "barrier-options")]
-            b"CROP" => Packet::ResetOptions,
-            #[cfg(feature = "barrier-options")]
-            b"DSOP" => {
-                let num_items = chunk.read_u32().await?;
-                limit -= 4;
-                let num_opts = num_items / 2;
-                let mut options: std::collections::HashMap<String, u32> =
-                    std::collections::HashMap::new();
-                // Currently only HBRT(Heartbeat interval) is supported
-                for _ in 0..num_opts {
-                    let opt: [u8; 4] = chunk.read_bytes_fixed().await?;
-                    limit -= 4;
-                    let val = chunk.read_u32().await?;
-                    limit -= 4;
-                    options.insert(String::from_utf8_lossy(&opt).into_owned(), val);
+        loop {
+            if self.resync {
+                if let Some(code) = self.received.get(self.start + 4..self.start + 8) {
+                    if !is_plausible_code(code.try_into().unwrap()) {
+                        return self.resynchronize(Error::FORMAT).await;
+                    }
                 }
-                Packet::SetDeviceOptions(options)
-            }
-            b"EUNK" => Packet::ErrorUnknownDevice,
-            b"DMMV" => {
-                let x = chunk.read_u16().await?;
-                limit -= 2;
-                let y = chunk.read_u16().await?;
-                limit -= 2;
-                Packet::MouseMoveAbs { x, y }
             }
-            b"DMRM" => {
-                let x = chunk.read_i16().await?;
-                limit -= 2;
-                let y = chunk.read_i16().await?;
-                limit -= 2;
-                Packet::MouseMove { x, y }
-            }
-            b"CINN" => {
-                let x = chunk.read_u16().await?;
-                limit -= 2;
-                let y = chunk.read_u16().await?;
-                limit -= 2;
-                let seq_num = chunk.read_u32().await?;
-                limit -= 4;
-                let mask = chunk.read_u16().await?;
-                limit -= 2;
-                Packet::CursorEnter {
-                    x,
-                    y,
-                    seq_num,
-                    mask,
+            let parsed = parse_packet(
+                &self.received[self.start..],
+                self.max_packet_size,
+                #[cfg(feature = "clipboard")]
+                clipboard_stage,
+                #[cfg(feature = "clipboard")]
+                self.max_clipboard_size,
+            );
+            match parsed {
+                Ok((packet, size)) => {
+                    let code = self.received[self.start + 4..self.start + 8]
+                        .try_into()
+                        .unwrap();
+                    self.last_received = (code, size);
+                    self.log_frame(true, size);
+                    self.start += size;
+                    if self.start == self.received.len() {
+                        self.received.clear();
+                        self.start = 0;
+                    }
+                    // Don't hold on to a large chunk, the chunks of a clipboard reuse it
+                    #[cfg(feature = "clipboard")]
+                    let idle = clipboard_stage.is_idle();
+                    #[cfg(not(feature = "clipboard"))]
+                    let idle = true;
+                    if idle
+                        && self.received.capacity() > 64 * 1024
+                        && self.received.len() - self.start < READ_SIZE
+                    {
+                        self.compact();
+                        self.received.shrink_to(READ_SIZE);
+                    }
+                    if !matches!(packet, Packet::Unknown(_)) {
+                        self.unknown_packets = 0;
+                    } else if self.unknown_packets < self.max_unknown_packets {
+                        self.unknown_packets += 1;
+                    } else {
+                        return Err(PacketError::TooManyUnknown(self.unknown_packets + 1));
+                    }
+                    return Ok(packet);
                 }
-            }
-            b"COUT" => Packet::CursorLeave,
-            b"CCLP" => {
-                let id = chunk.read_u8().await?;
-                limit -= 1;
-                let seq_num = chunk.read_u32().await?;
-                limit -= 4;
-                Packet::GrabClipboard { id, seq_num }
-            }
-            #[cfg(feature = "clipboard")]
-            b"DCLP" => {
-                let id = chunk.read_u8().await?;
-                limit -= 1;
-                let _seq_num = chunk.read_u32().await?;
-                limit -= 4;
-                let mark = chunk.read_u8().await?;
-                limit -= 1;
-                // chunk.read_to_end(&mut buf).await?;
-                let buf = if limit > 0 {
-                    let mut buf = vec![0; limit];
-                    chunk.read_exact(&mut buf).await?;
-                    buf
-                } else {
-                    vec![]
-                };
-                limit = 0;
-                debug!("Chunk: {id}, {mark} {}", buf.len());
-
-                // mark 1 is the total length string in ASCII
-                // mark 2 is the actual data and is split into chunks
-                // mark 3 is an empty chunk
-                debug!("Current Clipboard stage: {}", clipboard_stage.stage());
-                *clipboard_stage = match mark {
-                    1 => match clipboard_stage {
-                        ClipboardStage::None => {
-                            debug!("0 -> 1");
-                            let _sz = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
-                            let expected_size = String::from_utf8_lossy(&buf[4..])
-                                .parse::<u32>()
-                                .map_err(|_| PacketError::FormatError)?;
-                            debug!("Expected clipboard size: {}", expected_size);
-                            ClipboardStage::Mark1 { id, data: vec![] }
-                        }
-                        ClipboardStage::Mark3 { id, .. } => {
-                            debug!("3 -> 1");
-                            ClipboardStage::Mark1 {
-                                id: *id,
-                                data: vec![],
-                            }
-                        }
-                        _ => {
-                            warn!(
-                                "Unexpected clipboard stage transition from {} to 1",
-                                clipboard_stage.stage()
-                            );
-                            ClipboardStage::None
-                        }
-                    },
-                    2 => match clipboard_stage {
-                        ClipboardStage::Mark1 { id, data } => {
-                            debug!("1 -> 2");
-                            ClipboardStage::Mark2 {
-                                id: *id,
-                                data: {
-                                    data.extend_from_slice(&buf);
-                                    data.to_vec()
-                                },
-                            }
-                        }
-                        ClipboardStage::Mark2 { id, data } => {
-                            debug!("2 -> 2");
-
-                            ClipboardStage::Mark2 {
-                                id: *id,
-                                data: {
-                                    data.extend_from_slice(&buf);
-                                    data.to_vec()
-                                },
-                            }
-                        }
-                        _ => {
-                            warn!(
-                                "Unexpected clipboard stage transition from {} to 2",
-                                clipboard_stage.stage()
-                            );
-                            ClipboardStage::None
-                        }
-                    },
-                    3 => match clipboard_stage {
-                        ClipboardStage::Mark1 { id, data } => {
-                            debug!("1 -> 3");
-                            ClipboardStage::Mark3 {
-                                id: *id,
-                                data: {
-                                    data.extend_from_slice(&buf);
-                                    data.to_vec()
-                                },
-                            }
-                        }
-                        ClipboardStage::Mark2 { id, data } => {
-                            debug!("2 -> 3");
-                            ClipboardStage::Mark3 {
-                                id: *id,
-                                data: {
-                                    data.extend_from_slice(&buf);
-                                    data.to_vec()
-                                },
-                            }
-                        }
-                        _ => {
-                            warn!(
-                                "Unexpected clipboard stage transition from {} to 3",
-                                clipboard_stage.stage()
-                            );
-                            ClipboardStage::None
-                        }
-                    },
-                    _ => {
-                        warn!("Unexpected clipboard mark: {}", mark);
-                        ClipboardStage::None
+                Err(Error::Incomplete { needed }) => {
+                    self.compact();
+                    self.received
+                        .reserve(needed.max(READ_SIZE) - self.received.len());
+                    if self.stream.read_buf(&mut self.received).await? == 0 {
+                        return Err(Error::Incomplete { needed }.into());
                     }
-                };
-                match clipboard_stage {
-                    ClipboardStage::Mark3 { id, data } => Packet::SetClipboard {
-                        id: *id,
-                        data: parse_clipboard(data).await?,
-                    },
-                    _ => Packet::ClientNoOp,
                 }
+                Err(e @ (Error::PacketTooSmall(_) | Error::PacketTooLarge(_))) if self.resync => {
+                    return self.resynchronize(e).await;
+                }
+                Err(e) => return Err(e.into()),
             }
+        }
+    }
 
-            b"DMUP" => {
-                let id = chunk.read_i8().await?;
-                limit -= 1;
-                Packet::MouseUp { id }
-            }
-            b"DMDN" => {
-                let id = chunk.read_i8().await?;
-                limit -= 1;
-                Packet::MouseDown { id }
+    // Drops the packets parsed from the buffer
+    fn compact(&mut self) {
+        self.received.drain(..self.start);
+        self.start = 0;
+    }
+
+    // Drops the bytes received up to the next known packet, the size at the start of the
+    // buffer is not one. Fails with `error` if there is none within the max packet size.
+    async fn resynchronize(&mut self, error: Error) -> Result<Packet, PacketError> {
+        self.compact();
+        let mut skipped = 0;
+        // The start of the buffer is known to be wrong only the first time
+        let mut from = 1;
+        loop {
+            let found = find_packet(&self.received[from..], self.max_packet_size)
+                .map(|offset| from + offset)
+                .filter(|offset| skipped + offset <= self.max_packet_size);
+            if let Some(offset) = found {
+                self.received.drain(..offset);
+                skipped += offset;
+                return Err(PacketError::Resynchronized { skipped });
             }
-            b"DKUP" => {
-                let id = chunk.read_u16().await?;
-                limit -= 2;
-                let mask = chunk.read_u16().await?;
-                limit -= 2;
-                let button = chunk.read_u16().await?;
-                limit -= 2;
-                Packet::KeyUp { id, mask, button }
+            // The last 7 bytes may be the start of the next packet
+            let dropped = self.received.len().saturating_sub(7).max(from);
+            self.received.drain(..dropped);
+            skipped += dropped;
+            from = 0;
+            if skipped > self.max_packet_size {
+                return Err(error.into());
             }
-            b"DKDN" => {
-                let id = chunk.read_u16().await?;
-                limit -= 2;
-                let mask = chunk.read_u16().await?;
-                limit -= 2;
-                let button = chunk.read_u16().await?;
-                limit -= 2;
-                Packet::KeyDown { id, mask, button }
+            self.received.reserve(READ_SIZE);
+            if self.stream.read_buf(&mut self.received).await? == 0 {
+                return Err(error.into());
             }
-            b"DKRP" => {
-                let id = chunk.read_u16().await?;
-                limit -= 2;
-                let mask = chunk.read_u16().await?;
-                limit -= 2;
-                let count = chunk.read_u16().await?;
-                limit -= 2;
-                let button = chunk.read_u16().await?;
-                limit -= 2;
-                Packet::KeyRepeat {
-                    id,
-                    mask,
-                    button,
-                    count,
+        }
+    }
+}
+
+impl<S: PacketWriter> PacketStream<S> {
+    /// Code and size on the wire of the packet written last
+    pub fn last_sent(&self) -> ([u8; 4], usize) {
+        let code = self.sent.get(4..8).and_then(|code| code.try_into().ok());
+        (code.unwrap_or_default(), self.sent.len())
+    }
+
+    pub async fn write(&mut self, packet: Packet) -> Result<(), PacketError> {
+        self.sent.clear();
+        packet.encode(&mut self.sent);
+        self.log_frame(false, self.sent.len());
+        self.stream.write_all(&self.sent).await?;
+        // TLS streams buffer the written records
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Closes the write half, the peer sees the end of the stream
+    #[cfg(any(test, feature = "test-server"))]
+    pub async fn shutdown(&mut self) -> Result<(), PacketError> {
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Bytes read at once, the size of the buffer of a `BufReader`
+const READ_SIZE: usize = 8 * 1024;
+
+/// Unknown packets in a row before giving up on the stream
+const DEFAULT_MAX_UNKNOWN_PACKETS: usize = 16;
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::{AsyncRead, ReadBuf};
+
+    use super::{PacketStream, DEFAULT_MAX_PACKET_SIZE, READ_SIZE};
+    use crate::{codec::test::wire, KeyMask, Packet, PacketError};
+
+    // Reads the first packet of `wire`
+    async fn read_one(wire: Vec<u8>, max_packet_size: usize) -> Result<Packet, PacketError> {
+        let mut stream = PacketStream::new(
+            std::io::Cursor::new(wire),
+            #[cfg(feature = "clipboard")]
+            usize::MAX,
+        )
+        .max_packet_size(max_packet_size);
+        stream
+            .read(
+                #[cfg(feature = "clipboard")]
+                &mut crate::ClipboardStage::new(),
+            )
+            .await
+    }
+
+    fn server_errors() -> [Packet; 5] {
+        [
+            Packet::ErrorUnknownDevice,
+            Packet::ErrorBusy,
+            Packet::ErrorBadProtocol,
+            Packet::ErrorIncompatibleVersion { major: 1, minor: 8 },
+            Packet::Close,
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_server_errors() {
+        for (packet, expected) in server_errors().into_iter().zip(server_errors()) {
+            let mut buf = vec![];
+            packet.encode(&mut buf);
+            let mut stream = PacketStream::new(
+                std::io::Cursor::new(buf),
+                #[cfg(feature = "clipboard")]
+                usize::MAX,
+            );
+            let read = stream
+                .read(
+                    #[cfg(feature = "clipboard")]
+                    &mut crate::ClipboardStage::new(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(read, expected);
+        }
+    }
+
+    // Hands out at most the second field of bytes per read, so packets straddle the reads
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let len = self.1.min(self.0.len()).min(buf.remaining());
+            let (head, rest) = self.0.split_at(len);
+            buf.put_slice(head);
+            self.0 = rest;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_sizes() {
+        let packets = vec![
+            Packet::MouseMoveAbs { x: 1, y: 2 },
+            Packet::KeyDown {
+                id: 0x61,
+                mask: KeyMask::SHIFT,
+                button: 38,
+            },
+            Packet::KeepAlive,
+            #[cfg(feature = "clipboard")]
+            Packet::SetClipboard {
+                id: 0,
+                seq_num: 3,
+                data: crate::ClipboardData::builder()
+                    .text("x".repeat(100 * 1024))
+                    .build(),
+            },
+            Packet::DeviceInfo {
+                x: 0,
+                y: 0,
+                w: 1920,
+                h: 1080,
+                _dummy: 0,
+                mx: 5,
+                my: 6,
+            },
+            Packet::MouseMove { x: -3, y: 4 },
+        ];
+        let mut wire = vec![];
+        for packet in &packets {
+            packet.encode(&mut wire);
+        }
+        for step in [1, 7, 12, 4096, READ_SIZE + 5, usize::MAX] {
+            let mut stream = PacketStream::new(
+                Trickle(&wire, step),
+                #[cfg(feature = "clipboard")]
+                usize::MAX,
+            );
+            #[cfg(feature = "clipboard")]
+            let mut clipboard_stage = crate::ClipboardStage::new();
+            let mut read = vec![];
+            while let Ok(packet) = stream
+                .read(
+                    #[cfg(feature = "clipboard")]
+                    &mut clipboard_stage,
+                )
+                .await
+            {
+                // The first chunks of the clipboard
+                if packet != Packet::ClientNoOp {
+                    read.push(packet);
+                }
+                if step == usize::MAX && read.len() == 1 {
+                    assert_eq!(stream.last_received(), (*b"DMMV", 12));
+                    assert_eq!(stream.peek_buffered(), Some(*b"DKDN"));
                 }
             }
-            b"DMWM" => {
-                let x_delta = chunk.read_i16().await?;
-                limit -= 2;
-                let y_delta = chunk.read_i16().await?;
-                limit -= 2;
-                Packet::MouseWheel { x_delta, y_delta }
+            assert_eq!(read, packets, "{step}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_packet_too_large() {
+        assert!(matches!(
+            read_one(wire(u32::MAX, b"DCLP", &[]), DEFAULT_MAX_PACKET_SIZE).await,
+            Err(PacketError::PacketTooLarge(0xFFFFFFFF))
+        ));
+        let payload = [0; 12];
+        assert!(matches!(
+            read_one(wire(16, b"DMMV", &payload), 15).await,
+            Err(PacketError::PacketTooLarge(16))
+        ));
+        assert!(read_one(wire(16, b"DMMV", &payload), 16).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_truncated() {
+        // Longer than the data received
+        assert!(matches!(
+            read_one(wire(100, b"CALV", &[0; 10]), DEFAULT_MAX_PACKET_SIZE).await,
+            Err(PacketError::IoError(_))
+        ));
+        // Shorter than its fields, the next packet is left alone
+        let mut buf = wire(6, b"DMMV", &[0, 1]);
+        buf.extend(wire(4, b"CALV", &[]));
+        assert!(matches!(
+            read_one(buf, DEFAULT_MAX_PACKET_SIZE).await,
+            Err(PacketError::InsufficientDataError { .. })
+        ));
+    }
+
+    // Reads `wire` to the end or the first error the stream can't go on after
+    async fn read_all(wire: Vec<u8>, resync: bool) -> Vec<String> {
+        let mut stream = PacketStream::new(
+            std::io::Cursor::new(wire),
+            #[cfg(feature = "clipboard")]
+            usize::MAX,
+        )
+        .max_packet_size(64)
+        .max_unknown_packets(2)
+        .resync(resync);
+        let mut read = vec![];
+        loop {
+            let packet = stream
+                .read(
+                    #[cfg(feature = "clipboard")]
+                    &mut crate::ClipboardStage::new(),
+                )
+                .await;
+            match packet {
+                Ok(packet) => read.push(format!("{packet:?}")),
+                Err(PacketError::IoError(_)) => return read,
+                Err(e @ PacketError::Resynchronized { .. }) => read.push(e.to_string()),
+                Err(e) => {
+                    read.push(e.to_string());
+                    return read;
+                }
             }
-            _ => Packet::Unknown(code),
-        };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resync() {
+        let moved = "MouseMoveAbs { x: 1, y: 2 }";
+        let mut buf = wire(8, b"DMMV", &[0, 1, 0, 2]);
+        // A chunk parsed with the wrong length leaves the stream in its payload
+        buf.extend([0, 0, 0x12, 0x34, 0xAB, 0xCD, b'x', 0, 0, 0]);
+        buf.extend(wire(4, b"CALV", &[]));
+        buf.extend(wire(8, b"DMMV", &[0, 1, 0, 2]));
+        assert_eq!(
+            read_all(buf.clone(), true).await,
+            [
+                moved,
+                "stream out of sync, 10 bytes skipped to the next packet",
+                "KeepAlive",
+                moved
+            ]
+        );
+        assert_eq!(
+            read_all(buf, false).await,
+            [moved, "packet of 4660 bytes exceeds the size limit"]
+        );
 
-        // Discard the rest of the packet
-        chunk.discard_exact(limit).await?;
+        // A sane size but no code
+        let mut buf = wire(6, b"D\0\xff\x01", &[0, 0]);
+        buf.extend(wire(4, b"CALV", &[]));
+        assert_eq!(
+            read_all(buf, true).await,
+            [
+                "stream out of sync, 10 bytes skipped to the next packet",
+                "KeepAlive"
+            ]
+        );
 
-        Ok(packet)
+        // Nothing known within the max packet size, the next packet is too far
+        let mut buf = wire(1000, b"DCLP", &[0xAA; 100]);
+        buf.extend(wire(4, b"CALV", &[]));
+        assert_eq!(
+            read_all(buf.clone(), true).await,
+            ["packet of 1000 bytes exceeds the size limit"]
+        );
+        buf.drain(8..60);
+        assert_eq!(
+            read_all(buf, true).await,
+            [
+                "stream out of sync, 56 bytes skipped to the next packet",
+                "KeepAlive"
+            ]
+        );
     }
 
-    pub async fn write(&mut self, packet: Packet) -> Result<(), PacketError> {
-        packet.write_wire(&mut self.stream).await
+    #[tokio::test]
+    async fn test_too_many_unknown() {
+        let unknown = || wire(4, b"XXXX", &[]);
+        let buf = [
+            unknown(),
+            unknown(),
+            wire(4, b"CALV", &[]),
+            unknown(),
+            unknown(),
+        ]
+        .concat();
+        assert_eq!(
+            read_all(buf, true).await,
+            [
+                "Unknown([88, 88, 88, 88])",
+                "Unknown([88, 88, 88, 88])",
+                "KeepAlive",
+                "Unknown([88, 88, 88, 88])",
+                "Unknown([88, 88, 88, 88])"
+            ]
+        );
+        let buf = [unknown(), unknown(), unknown(), wire(4, b"CALV", &[])].concat();
+        assert_eq!(
+            read_all(buf, true).await,
+            [
+                "Unknown([88, 88, 88, 88])",
+                "Unknown([88, 88, 88, 88])",
+                "3 unknown packets in a row"
+            ]
+        );
     }
 }
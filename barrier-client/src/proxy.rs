@@ -0,0 +1,227 @@
+use std::{fmt, time::Duration};
+
+use log::debug;
+use tokio::net::TcpStream;
+use tokio_socks::{tcp::Socks5Stream, Error};
+
+use super::{
+    client::{connect, LocalBind},
+    ConnectionError,
+};
+
+/// Proxy the connection to the server goes through, see
+/// [`ClientBuilder::proxy`](crate::ClientBuilder::proxy)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Proxy {
+    /// SOCKS5 proxy in "host:port" format, e.g. `ssh -D` on a jump host. The server address
+    /// is resolved by the proxy, so names only known behind it can be used.
+    Socks5 {
+        addr: String,
+        auth: Option<ProxyAuth>,
+    },
+}
+
+/// User name and password of the proxy
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opens a connection to `server` through the proxy, the stream returned carries the
+/// server's bytes once the proxy handshake is done
+pub(crate) async fn connect_proxy(
+    proxy: &Proxy,
+    server: &str,
+    bind: &LocalBind,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, ConnectionError> {
+    let Proxy::Socks5 { addr, auth } = proxy;
+    let socket = connect(addr.as_str(), bind, timeout)
+        .await
+        .map_err(|e| match e {
+            ConnectionError::TcpError(e) => ConnectionError::ProxyUnreachable(e),
+            e => e,
+        })?;
+    let stream = match auth {
+        Some(auth) => {
+            Socks5Stream::connect_with_password_and_socket(
+                socket,
+                server,
+                &auth.username,
+                &auth.password,
+            )
+            .await
+        }
+        None => Socks5Stream::connect_with_socket(socket, server).await,
+    }
+    .map_err(proxy_error)?;
+    debug!("Connected to {} through the proxy {}", server, addr);
+    Ok(stream.into_inner())
+}
+
+fn proxy_error(e: Error) -> ConnectionError {
+    match e {
+        Error::NoAcceptableAuthMethods
+        | Error::UnknownAuthMethod
+        | Error::PasswordAuthFailure(_)
+        | Error::AuthorizationRequired => ConnectionError::ProxyAuthFailed,
+        Error::GeneralSocksServerFailure
+        | Error::ConnectionNotAllowedByRuleset
+        | Error::NetworkUnreachable
+        | Error::HostUnreachable
+        | Error::ConnectionRefused
+        | Error::TtlExpired => ConnectionError::ProxyConnectFailed(e.to_string()),
+        Error::InvalidTargetAddress(_) | Error::InvalidAuthValues(_) => {
+            ConnectionError::ProxyConfigError(e.to_string())
+        }
+        e => ConnectionError::ProxyProtocolError(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{Proxy, ProxyAuth};
+    use crate::{
+        test_server::{MockServer, Packet, Recorder},
+        Client, ConnectionError,
+    };
+
+    const AUTH: (&str, &str) = ("barpi", "secret");
+
+    // Accepts one client, checks the credentials and tunnels to the requested address.
+    // Only IPv4 and domain targets, the domain is resolved here like a real proxy does.
+    async fn socks_server(listener: TcpListener) {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut header = [0; 2];
+        client.read_exact(&mut header).await.unwrap();
+        let mut methods = vec![0; header[1] as usize];
+        client.read_exact(&mut methods).await.unwrap();
+        assert!(methods.contains(&2), "password auth offered");
+        client.write_all(&[5, 2]).await.unwrap();
+
+        assert_eq!(client.read_u8().await.unwrap(), 1, "auth version");
+        let mut credentials = vec![];
+        for _ in 0..2 {
+            let mut value = vec![0; client.read_u8().await.unwrap() as usize];
+            client.read_exact(&mut value).await.unwrap();
+            credentials.push(String::from_utf8(value).unwrap());
+        }
+        if credentials != [AUTH.0, AUTH.1] {
+            client.write_all(&[1, 1]).await.unwrap();
+            return;
+        }
+        client.write_all(&[1, 0]).await.unwrap();
+
+        let mut request = [0; 4];
+        client.read_exact(&mut request).await.unwrap();
+        let host = match request[3] {
+            1 => {
+                let mut ip = [0; 4];
+                client.read_exact(&mut ip).await.unwrap();
+                std::net::Ipv4Addr::from(ip).to_string()
+            }
+            3 => {
+                let len = client.read_u8().await.unwrap();
+                let mut name = vec![0; len as usize];
+                client.read_exact(&mut name).await.unwrap();
+                String::from_utf8(name).unwrap()
+            }
+            atyp => panic!("address type {atyp}"),
+        };
+        let target = format!("{}:{}", host, client.read_u16().await.unwrap());
+        let Ok(mut server) = TcpStream::connect(&target).await else {
+            client
+                .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            return;
+        };
+        client
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        copy_bidirectional(&mut client, &mut server).await.ok();
+    }
+
+    async fn run(server: &str, auth: (&str, &str)) -> (Result<(), ConnectionError>, Vec<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = Proxy::Socks5 {
+            addr: listener.local_addr().unwrap().to_string(),
+            auth: Some(ProxyAuth {
+                username: auth.0.into(),
+                password: auth.1.into(),
+            }),
+        };
+        let proxied = tokio::spawn(socks_server(listener));
+        let client = Client::builder()
+            .server(server)
+            .screen_name("test")
+            .proxy(proxy)
+            .build()
+            .unwrap();
+        let mut recorder = Recorder::default();
+        let result = client.run(&mut recorder).await;
+        proxied.await.unwrap();
+        (result, recorder.0)
+    }
+
+    #[tokio::test]
+    async fn test_socks5() {
+        let server = MockServer::bind().await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        let served = tokio::spawn(async move {
+            let script = [Packet::MouseMoveAbs { x: 20, y: 30 }.into()];
+            server.serve(script).await.unwrap()
+        });
+        // The name is resolved by the proxy
+        let (result, events) = run(&format!("localhost:{port}"), AUTH).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(served.await.unwrap().screen_name, "test");
+        assert_eq!(events, ["abs 20 30"]);
+    }
+
+    #[tokio::test]
+    async fn test_socks5_errors() {
+        let (result, _) = run("127.0.0.1:24800", ("barpi", "wrong")).await;
+        assert!(matches!(result, Err(ConnectionError::ProxyAuthFailed)));
+
+        // Nothing listens there anymore
+        let closed: SocketAddr = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let (result, _) = run(&closed.to_string(), AUTH).await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::ProxyConnectFailed(_))
+        ));
+
+        let client = Client::builder()
+            .server("127.0.0.1:24800")
+            .screen_name("test")
+            .proxy(Proxy::Socks5 {
+                addr: closed.to_string(),
+                auth: None,
+            })
+            .build()
+            .unwrap();
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::ProxyUnreachable(_))));
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Exponential backoff between connection attempts
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    /// Delay after the first failure, doubled after each following one
+    pub initial: Duration,
+    /// Upper bound of the delay
+    pub max: Duration,
+    /// A connection lasting longer than this after the handshake starts over from `initial`
+    pub reset_after: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            reset_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the retry after `failures` consecutive failures, with jitter so
+    /// clients don't reconnect at the same time after a server restart
+    pub(crate) fn delay(&self, failures: u32) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.max);
+        delay.mul_f64(0.5 + 0.5 * jitter())
+    }
+}
+
+// Good enough to spread reconnects, not worth a dependency on `rand`
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    nanos as f64 / 1_000_000_000.0
+}
+
+/// Connection status reported to the callback set by [`crate::ClientBuilder::on_status`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientStatus {
+    Connecting,
+    /// The server's hello is answered
+    Connected,
+    /// Waiting for the given delay before reconnecting
+    BackingOff(Duration),
+    /// The server has no screen of this name, the client keeps reconnecting in case it's
+    /// added to the server configuration
+    UnknownScreenName(String),
+}
+
+/// Where the client is in the protocol, watched with [`crate::Client::state`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Opening the connection, TLS included
+    Connecting,
+    /// Replied to the server's hello, the screen info isn't acknowledged yet
+    HelloSent,
+    /// The server acknowledged the screen info, it sends the events from now on
+    Connected,
+    /// The cursor entered this screen
+    Entered,
+    /// The cursor left this screen
+    Left,
+    /// The connection is closed, `reason` is the error that closed it, `None` before the
+    /// client first runs
+    Disconnected { reason: Option<String> },
+}
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        ConnectionState::Disconnected { reason: None }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct StatusCallback(pub Arc<dyn Fn(ClientStatus) + Send + Sync>);
+
+impl Debug for StatusCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StatusCallback")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+            reset_after: Duration::from_secs(30),
+        };
+        for (failures, expected) in [(0, 1), (1, 2), (2, 4), (3, 8), (4, 10), (100, 10)] {
+            let delay = backoff.delay(failures);
+            let expected = Duration::from_secs(expected);
+            assert!(delay >= expected / 2 && delay <= expected, "{delay:?}");
+        }
+    }
+}
@@ -0,0 +1,543 @@
+//! Records the calls an actuator receives to replay them later, e.g. to reproduce a key
+//! stuck on the host without the server and the timing of the user who reported it.
+//!
+//! A recording is a JSON object per line, the time of the call in microseconds since the
+//! recording started and the call as an [`ActuatorMessage`], e.g.
+//! `{"t":1520,"call":{"KeyDown":{"key":97,"mask":1,"button":38}}}`. Clipboard bitmaps are
+//! base64 encoded.
+
+use std::{
+    fs::File,
+    io::{self, LineWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+#[cfg(feature = "clipboard")]
+use crate::{channel_act::clipboard_messages, ClipboardData};
+use crate::{dispatch, Actuator, ActuatorError, ActuatorMessage, Edge, KeyMask, LedState};
+
+/// Clipboard bitmaps larger than this are recorded without their data
+pub const DEFAULT_MAX_BITMAP_SIZE: usize = 1024 * 1024;
+
+/// A line of a recording
+#[derive(Serialize, Deserialize)]
+struct Line {
+    t: u64,
+    call: Call,
+}
+
+// The bitmap is tried first, it only differs from the message by its data being a string
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Call {
+    #[cfg(feature = "clipboard")]
+    Bitmap(Bitmap),
+    Message(ActuatorMessage),
+}
+
+#[cfg(feature = "clipboard")]
+#[derive(Serialize, Deserialize)]
+enum Bitmap {
+    SetClipboardBitmap {
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+    },
+}
+
+#[cfg(feature = "clipboard")]
+mod base64_data {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
+    }
+}
+
+impl From<Call> for ActuatorMessage {
+    fn from(call: Call) -> Self {
+        match call {
+            #[cfg(feature = "clipboard")]
+            Call::Bitmap(Bitmap::SetClipboardBitmap { data }) => {
+                ActuatorMessage::SetClipboardBitmap { data }
+            }
+            Call::Message(msg) => msg,
+        }
+    }
+}
+
+/// Forwards every call to the inner actuator and appends it to a recording, see
+/// [`replay`] to apply it again.
+///
+/// Calls without an [`ActuatorMessage`] are recorded as the messages a replay needs to do
+/// the same, e.g. `enter_at` as `Enter` and `SetCursorPosition`. The clipboard id, the
+/// clipboard sent to the server and the files received are not recorded.
+///
+/// A call is recorded before it's forwarded, so a recording ends with the call the actuator
+/// failed on. Failing to write the recording doesn't fail the call, it's logged and the
+/// recording stops.
+pub struct RecordingActuator<A, W: Write = LineWriter<File>> {
+    inner: A,
+    out: Option<W>,
+    started: Instant,
+    max_bitmap_size: usize,
+}
+
+impl<A> RecordingActuator<A> {
+    /// Records to `path`, a line is written as soon as its call is received so the recording
+    /// is complete even if the process is killed
+    pub fn create<P: AsRef<Path>>(inner: A, path: P) -> io::Result<Self> {
+        let out = LineWriter::new(File::create(path)?);
+        Ok(Self::new(inner, out))
+    }
+}
+
+impl<A, W: Write> RecordingActuator<A, W> {
+    pub fn new(inner: A, out: W) -> Self {
+        Self {
+            inner,
+            out: Some(out),
+            started: Instant::now(),
+            max_bitmap_size: DEFAULT_MAX_BITMAP_SIZE,
+        }
+    }
+
+    /// Clipboard bitmaps larger than this are recorded with empty data, default is
+    /// [`DEFAULT_MAX_BITMAP_SIZE`]
+    pub fn with_max_bitmap_size(mut self, max_bitmap_size: usize) -> Self {
+        self.max_bitmap_size = max_bitmap_size;
+        self
+    }
+
+    pub fn get_ref(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn record(&mut self, msg: ActuatorMessage) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        let call = match msg {
+            #[cfg(feature = "clipboard")]
+            ActuatorMessage::SetClipboardBitmap { mut data } => {
+                if data.len() > self.max_bitmap_size {
+                    warn!(
+                        "Clipboard bitmap of {} bytes recorded without its data",
+                        data.len()
+                    );
+                    data.clear();
+                }
+                Call::Bitmap(Bitmap::SetClipboardBitmap { data })
+            }
+            msg => Call::Message(msg),
+        };
+        let line = Line {
+            t: self.started.elapsed().as_micros() as u64,
+            call,
+        };
+        let written = serde_json::to_writer(&mut *out, &line)
+            .map_err(io::Error::from)
+            .and_then(|_| out.write_all(b"\n"));
+        if let Err(e) = written {
+            warn!("Cannot write the recording, it's stopped: {}", e);
+            self.out = None;
+        }
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn record_clipboard(&mut self, data: &ClipboardData) {
+        for msg in clipboard_messages(data.clone()) {
+            self.record(msg);
+        }
+    }
+}
+
+impl<A: Actuator, W: Write> Actuator for RecordingActuator<A, W> {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::Connected);
+        self.inner.connected()
+    }
+
+    fn connected_with(&mut self, info: crate::ServerInfo) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::Connected);
+        self.inner.connected_with(info)
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::Disconnected);
+        self.inner.disconnected()
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        self.inner.get_screen_size()
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        self.inner.get_cursor_position()
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::SetCursorPosition { x, y });
+        self.inner.set_cursor_position(x, y)
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::MoveCursor { x, y });
+        self.inner.move_cursor(x, y)
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::MouseDown { button });
+        self.inner.mouse_down(button)
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::MouseUp { button });
+        self.inner.mouse_up(button)
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::MouseWheel { x, y });
+        self.inner.mouse_wheel(x, y)
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::KeyDown { key, mask, button });
+        self.inner.key_down(key, mask, button)
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::KeyRepeat {
+            key,
+            mask,
+            button,
+            count,
+        });
+        self.inner.key_repeat(key, mask, button, count)
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::KeyUp { key, mask, button });
+        self.inner.key_up(key, mask, button)
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn set_options(&mut self, opts: crate::ServerOptions) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::SetOptions { opts: opts.clone() });
+        self.inner.set_options(opts)
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::ResetOptions);
+        self.inner.reset_options()
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::Enter);
+        self.inner.enter()
+    }
+
+    fn enter_at(&mut self, x: u16, y: u16, mask: KeyMask) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::Enter);
+        self.record(ActuatorMessage::SetCursorPosition { x, y });
+        self.inner.enter_at(x, y, mask)
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::Leave);
+        self.inner.leave()
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.record_clipboard(&data);
+        self.inner.set_clipboard(data)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard_with(&mut self, id: u8, data: ClipboardData) -> Result<(), ActuatorError> {
+        self.record_clipboard(&data);
+        self.inner.set_clipboard_with(id, data)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn get_clipboard(&mut self, id: u8) -> Result<Option<ClipboardData>, ActuatorError> {
+        self.inner.get_clipboard(id)
+    }
+
+    #[cfg(feature = "file-transfer")]
+    fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        self.inner.file_received(name_hint, data)
+    }
+
+    fn led_state_changed(&mut self, state: LedState) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::LedStateChanged { state });
+        self.inner.led_state_changed(state)
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::ScreenSaver { on });
+        self.inner.screensaver(on)
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::GameButtons { id, buttons });
+        self.inner.game_buttons(id, buttons)
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::GameSticks { id, left, right });
+        self.inner.game_sticks(id, left, right)
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::GameTriggers { id, left, right });
+        self.inner.game_triggers(id, left, right)
+    }
+
+    fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::EdgeReached { edge });
+        self.inner.edge_reached(edge)
+    }
+
+    fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.record(ActuatorMessage::LanguageSync {
+            languages: languages.clone(),
+        });
+        self.inner.language_sync(languages)
+    }
+}
+
+/// Applies the calls recorded in `path` to `actor` with the delays they were received with,
+/// divided by `speed`, e.g. 2 replays twice as fast and 0 doesn't wait at all. Returns the
+/// number of calls replayed.
+///
+/// A line that can't be parsed, a delay too long once divided by `speed` or a call the
+/// actuator fails stops the replay with the error.
+pub async fn replay<P: AsRef<Path>, A: Actuator>(
+    path: P,
+    actor: &mut A,
+    speed: f32,
+) -> Result<usize, ActuatorError> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| ActuatorError::io("recording open", e))?;
+    let mut lines = BufReader::new(file).lines();
+    let (mut last, mut number, mut replayed) = (0, 0, 0);
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| ActuatorError::io("recording read", e))?
+    {
+        number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: Line = serde_json::from_str(&line)
+            .map_err(|e| ActuatorError::other(format!("line {number} of the recording: {e}")))?;
+        if speed > 0.0 {
+            let delay = Duration::from_micros(line.t.saturating_sub(last));
+            let delay =
+                Duration::try_from_secs_f64(delay.as_secs_f64() / speed as f64).map_err(|e| {
+                    ActuatorError::other(format!("line {number} at speed {speed}: {e}"))
+                })?;
+            tokio::time::sleep(delay).await;
+        }
+        last = line.t;
+        dispatch(line.call.into(), actor).await?;
+        replayed += 1;
+    }
+    info!("Replayed {replayed} calls from {}", path.display());
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use super::{replay, RecordingActuator};
+    use crate::{
+        test_server::{MockServer, Packet, Recorder, Step},
+        Actuator, Client, ConnectionError, KeyMask,
+    };
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "barrier-client-record-{}-{name}.jsonl",
+            std::process::id()
+        ))
+    }
+
+    // The calls of a recording without their time
+    fn calls(path: &PathBuf) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert!(line["t"].is_u64(), "{line}");
+                line["call"].clone()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let script = vec![
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Packet::MouseMoveAbs { x: 20, y: 30 }.into(),
+            Packet::MouseDown { id: 1 }.into(),
+            Packet::MouseUp { id: 1 }.into(),
+            Step::Wait(Duration::from_millis(200)),
+            Packet::KeyDown {
+                id: 0x41,
+                mask: KeyMask::SHIFT,
+                button: 38,
+            }
+            .into(),
+            Packet::KeyRepeat {
+                id: 0x41,
+                mask: KeyMask::SHIFT,
+                button: 38,
+                count: 2,
+            }
+            .into(),
+            Packet::KeyUp {
+                id: 0x41,
+                mask: KeyMask::SHIFT,
+                button: 38,
+            }
+            .into(),
+            Packet::MouseWheel {
+                x_delta: 0,
+                y_delta: 120,
+            }
+            .into(),
+            Packet::CursorLeave.into(),
+        ];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let recorded = path("session");
+        let mut recording = RecordingActuator::create(Recorder::default(), &recorded).unwrap();
+        let result = client.run(&mut recording).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        served.await.unwrap().unwrap();
+        let events = recording.into_inner().0;
+
+        let replayed = path("replayed");
+        let mut recording = RecordingActuator::create(Recorder::default(), &replayed).unwrap();
+        let started = Instant::now();
+        let count = replay(&recorded, &mut recording, 2.0).await.unwrap();
+        // The wait in the script, twice as fast
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(count, calls(&recorded).len());
+        assert_eq!(calls(&replayed), calls(&recorded));
+        assert_eq!(recording.into_inner().0, events);
+        // The wait would last longer than a Duration
+        assert!(replay(&recorded, &mut Recorder::default(), 1e-30)
+            .await
+            .is_err());
+        assert_eq!(
+            calls(&recorded)[..4],
+            [
+                serde_json::json!("Connected"),
+                serde_json::json!("Enter"),
+                serde_json::json!({"SetCursorPosition": {"x": 10, "y": 10}}),
+                serde_json::json!({"SetCursorPosition": {"x": 20, "y": 30}}),
+            ]
+        );
+        assert!(calls(&recorded)
+            .contains(&serde_json::json!({"KeyDown": {"key": 0x41, "mask": 1, "button": 38}})));
+        assert_eq!(
+            calls(&recorded).last(),
+            Some(&serde_json::json!("Disconnected"))
+        );
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[tokio::test]
+    async fn test_record_bitmap() {
+        let recorded = path("bitmap");
+        let mut recording = RecordingActuator::create(Recorder::default(), &recorded)
+            .unwrap()
+            .with_max_bitmap_size(4);
+        let bitmap = |data: &[u8]| {
+            crate::ClipboardData::builder()
+                .bitmap(data.to_vec())
+                .build()
+        };
+        recording.set_clipboard(bitmap(b"BM\x01\x02")).unwrap();
+        recording.set_clipboard(bitmap(b"BM\x01\x02\x03")).unwrap();
+        recording.set_clipboard("text".to_string().into()).unwrap();
+        drop(recording);
+        assert_eq!(
+            calls(&recorded),
+            [
+                serde_json::json!({"SetClipboardBitmap": {"data": "Qk0BAg=="}}),
+                serde_json::json!({"SetClipboardBitmap": {"data": ""}}),
+                serde_json::json!({"SetClipboardText": {"data": "text"}}),
+            ]
+        );
+
+        let mut recorder = Recorder::default();
+        assert_eq!(replay(&recorded, &mut recorder, 0.0).await.unwrap(), 3);
+        assert_eq!(recorder.0, ["clipboard ", "clipboard ", "clipboard text"]);
+
+        fs::write(&recorded, "{\"t\":0,\"call\":\"Enter\"}\n{\"t\":1}\n").unwrap();
+        let error = replay(&recorded, &mut recorder, 0.0).await.unwrap_err();
+        assert!(
+            error.to_string().starts_with("line 2 of the recording"),
+            "{error}"
+        );
+    }
+}
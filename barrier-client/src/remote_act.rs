@@ -0,0 +1,580 @@
+//! Forwards actuator calls to another device over TCP, see [`RemoteActuator`] and
+//! [`serve_actuator`].
+//!
+//! Each [`ActuatorMessage`] is serialized with postcard and sent in frames of a 4 bytes
+//! big-endian length, a flag byte and the payload. A message larger than the MTU is split
+//! into frames, all but the last one flagged with `MORE_FRAMES`. The messages are told apart
+//! by the index of their variant, both ends must be built with the same `clipboard` and
+//! `barrier-options` features.
+
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpListener,
+};
+
+use crate::{
+    actuator::offset_position, dispatch, Actuator, ActuatorError, ActuatorMessage, ConnectionError,
+    Edge, KeyMask, LedState, PacketError,
+};
+#[cfg(feature = "clipboard")]
+use crate::{channel_act::clipboard_messages, ClipboardData};
+
+/// Default size limit of a frame, fits in an Ethernet packet
+pub const DEFAULT_MTU: usize = 1400;
+
+// Length prefix and flag byte
+const FRAME_HEADER_LEN: usize = 5;
+// Leaves room for a few bytes of payload per frame
+const MIN_MTU: usize = 16;
+// Set on all frames of a message but the last one
+const MORE_FRAMES: u8 = 0x01;
+// A message being reassembled larger than this is rejected
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// Messages waiting for the writer thread, more fail until it catches up
+const QUEUE_SIZE: usize = 64;
+
+/// Sends every callback to a [`serve_actuator`] on another device.
+///
+/// The messages are queued for a writer thread, which connects and writes them so a slow or
+/// dead peer doesn't stall the client. When the connection is lost the messages are dropped
+/// until reconnecting succeeds, at most one attempt is made per retry interval. The failure
+/// is returned by the next call, which isn't sent, like one made while the queue is full.
+pub struct RemoteActuator {
+    addr: String,
+    screen_width: u16,
+    screen_height: u16,
+    cursor_x: u16,
+    cursor_y: u16,
+    mtu: usize,
+    retry_interval: Duration,
+    // Started with the first message, once the options are set
+    writer: Option<Writer>,
+}
+
+impl RemoteActuator {
+    /// `addr` is resolved on every connection attempt, the connection is made with the first message
+    pub fn new<S: Into<String>>(addr: S, screen_width: u16, screen_height: u16) -> Self {
+        Self {
+            addr: addr.into(),
+            screen_width,
+            screen_height,
+            cursor_x: 0,
+            cursor_y: 0,
+            mtu: DEFAULT_MTU,
+            retry_interval: Duration::from_secs(1),
+            writer: None,
+        }
+    }
+
+    /// Frames are at most `mtu` bytes including their header, default is [`DEFAULT_MTU`]
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu.max(MIN_MTU);
+        self
+    }
+
+    /// Minimum time between connection attempts, default is 1 second
+    pub fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(&self.writer, Some(writer) if writer.link.connected.load(Relaxed))
+    }
+
+    fn send(&mut self, msg: ActuatorMessage) -> Result<(), ActuatorError> {
+        let payload = postcard::to_stdvec(&msg)
+            .map_err(|e| ActuatorError::other(format!("cannot encode {msg:?}: {e}")))?;
+        let writer = self
+            .writer
+            .get_or_insert_with(|| Writer::spawn(self.addr.clone(), self.mtu, self.retry_interval));
+        if let Some(e) = writer.link.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        match writer.tx.try_send(payload) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(ActuatorError::io(
+                format!("write to {}", self.addr),
+                io::ErrorKind::WouldBlock.into(),
+            )),
+            Err(TrySendError::Disconnected(_)) => Err(ActuatorError::Closed),
+        }
+    }
+}
+
+// The queue of the writer thread, it stops once this is dropped and the messages queued
+// are written
+struct Writer {
+    tx: SyncSender<Vec<u8>>,
+    link: Arc<Link>,
+}
+
+impl Writer {
+    fn spawn(addr: String, mtu: usize, retry_interval: Duration) -> Self {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let link = Arc::new(Link::default());
+        let mut worker = Worker {
+            addr,
+            mtu,
+            retry_interval,
+            link: link.clone(),
+            stream: None,
+            last_attempt: None,
+        };
+        std::thread::spawn(move || worker.run(rx));
+        Self { tx, link }
+    }
+}
+
+// State of the connection, shared with the writer thread
+#[derive(Default)]
+struct Link {
+    connected: AtomicBool,
+    // Failure not returned yet, the first one since the last call
+    error: Mutex<Option<ActuatorError>>,
+}
+
+struct Worker {
+    addr: String,
+    mtu: usize,
+    retry_interval: Duration,
+    link: Arc<Link>,
+    stream: Option<TcpStream>,
+    last_attempt: Option<Instant>,
+}
+
+impl Worker {
+    fn run(&mut self, rx: Receiver<Vec<u8>>) {
+        for payload in rx {
+            if self.stream.is_none() && !self.reconnect() {
+                debug!("Not connected to {}, dropping a message", self.addr);
+                continue;
+            }
+            let Some(stream) = &mut self.stream else {
+                continue;
+            };
+            if let Err(e) = write_frames(stream, &payload, self.mtu) {
+                warn!("Cannot send to {}, error: {}", self.addr, e);
+                self.stream = None;
+                self.link.connected.store(false, Relaxed);
+                // Waits for the retry interval before connecting again
+                self.last_attempt = Some(Instant::now());
+                self.fail(ActuatorError::io(format!("write to {}", self.addr), e));
+            }
+        }
+    }
+
+    fn reconnect(&mut self) -> bool {
+        if matches!(self.last_attempt, Some(t) if t.elapsed() < self.retry_interval) {
+            return false;
+        }
+        self.last_attempt = Some(Instant::now());
+        match connect(&self.addr) {
+            Ok(stream) => {
+                info!("Connected to {}", self.addr);
+                self.stream = Some(stream);
+                self.link.connected.store(true, Relaxed);
+                true
+            }
+            Err(e) => {
+                warn!("Cannot connect to {}, error: {}", self.addr, e);
+                self.fail(ActuatorError::io(format!("connect to {}", self.addr), e));
+                false
+            }
+        }
+    }
+
+    // Keeps the first failure until a call returns it
+    fn fail(&self, e: ActuatorError) {
+        self.link.error.lock().unwrap().get_or_insert(e);
+    }
+}
+
+impl Actuator for RemoteActuator {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Connected)
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Disconnected)
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        (self.screen_width, self.screen_height)
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        (self.cursor_x, self.cursor_y)
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::SetCursorPosition { x, y })?;
+        self.cursor_x = x;
+        self.cursor_y = y;
+        Ok(())
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MoveCursor { x, y })?;
+        (self.cursor_x, self.cursor_y) = offset_position(
+            (self.cursor_x, self.cursor_y),
+            (self.screen_width, self.screen_height),
+            x,
+            y,
+        );
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MouseDown { button })
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MouseUp { button })
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::MouseWheel { x, y })
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::KeyDown { key, mask, button })
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::KeyRepeat {
+            key,
+            mask,
+            button,
+            count,
+        })
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::KeyUp { key, mask, button })
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn set_options(&mut self, opts: crate::ServerOptions) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::SetOptions { opts })
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::ResetOptions)
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Enter)
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::Leave)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        for msg in clipboard_messages(data) {
+            self.send(msg)?;
+        }
+        Ok(())
+    }
+
+    fn led_state_changed(&mut self, state: LedState) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::LedStateChanged { state })
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::ScreenSaver { on })
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::GameButtons { id, buttons })
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::GameSticks { id, left, right })
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::GameTriggers { id, left, right })
+    }
+
+    fn edge_reached(&mut self, edge: Edge) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::EdgeReached { edge })
+    }
+
+    fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.send(ActuatorMessage::LanguageSync { languages })
+    }
+}
+
+/// Accepts connections from [`RemoteActuator`]s one at a time and applies the messages to
+/// `actor`, only returns if accepting fails. A lost connection is handled like a disconnect
+/// from the server, so nothing stays pressed.
+pub async fn serve_actuator<A: Actuator>(
+    listener: &TcpListener,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+        info!("Remote actuator connected from {}", peer);
+        match serve_connection(stream, actor).await {
+            Ok(()) => info!("Remote actuator {} disconnected", peer),
+            Err(e) => warn!("Remote actuator {} disconnected, error: {}", peer, e),
+        }
+        if let Err(e) = actor.disconnected() {
+            warn!("Actuator failed to disconnect: {}", e);
+        }
+    }
+}
+
+/// Applies the messages read from `stream` to `actor` until the stream is closed
+pub async fn serve_connection<S: AsyncRead + Unpin, A: Actuator>(
+    mut stream: S,
+    actor: &mut A,
+) -> Result<(), ConnectionError> {
+    let mut buf = vec![];
+    while let Some(msg) = read_message(&mut stream, &mut buf).await? {
+        dispatch(msg, actor).await?;
+    }
+    Ok(())
+}
+
+// Reassembles the next message, `None` if the stream is closed between messages.
+// Messages that can't be decoded are skipped, e.g. ones with a feature disabled here.
+async fn read_message<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+) -> Result<Option<ActuatorMessage>, PacketError> {
+    buf.clear();
+    loop {
+        let len = match stream.read_u32().await {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && buf.is_empty() => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if len == 0 {
+            return Err(PacketError::PacketTooSmall(0));
+        }
+        if buf.len() + len > MAX_MESSAGE_LEN {
+            return Err(PacketError::FORMAT);
+        }
+        let flags = stream.read_u8().await?;
+        let start = buf.len();
+        buf.resize(start + len - 1, 0);
+        stream.read_exact(&mut buf[start..]).await?;
+        if flags & MORE_FRAMES != 0 {
+            continue;
+        }
+        match postcard::from_bytes(buf) {
+            Ok(msg) => return Ok(Some(msg)),
+            Err(e) => {
+                warn!("Skipping a message that can't be decoded: {}", e);
+                buf.clear();
+            }
+        }
+    }
+}
+
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not resolved"))?;
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+fn write_frames<W: Write>(out: &mut W, msg: &[u8], mtu: usize) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(mtu);
+    let mut chunks = msg.chunks(mtu - FRAME_HEADER_LEN).peekable();
+    while let Some(chunk) = chunks.next() {
+        let flags = if chunks.peek().is_some() {
+            MORE_FRAMES
+        } else {
+            0
+        };
+        frame.clear();
+        frame.extend_from_slice(&(chunk.len() as u32 + 1).to_be_bytes());
+        frame.push(flags);
+        frame.extend_from_slice(chunk);
+        out.write_all(&frame)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::{net::TcpListener, time::sleep};
+
+    use super::{read_message, serve_connection, write_frames, RemoteActuator};
+    use crate::{
+        test_server::{drive, Recorder},
+        Actuator, ActuatorMessage, Edge, LedState,
+    };
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut actuator = RemoteActuator::new(addr, 1920, 1080).mtu(16);
+        drive(&mut actuator);
+        assert_eq!(actuator.get_cursor_position(), (90, 205));
+        drop(actuator);
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut recorder = Recorder::default();
+        serve_connection(stream, &mut recorder).await.unwrap();
+
+        let mut expected = Recorder::default();
+        drive(&mut expected);
+        assert_eq!(recorder.0, expected.0);
+    }
+
+    #[tokio::test]
+    async fn test_frames() {
+        let mut messages = vec![
+            ActuatorMessage::Connected,
+            ActuatorMessage::MoveCursor { x: -1, y: 300 },
+            ActuatorMessage::LedStateChanged {
+                state: LedState {
+                    caps_lock: true,
+                    kana: true,
+                    ..Default::default()
+                },
+            },
+            ActuatorMessage::ScreenSaver { on: true },
+            ActuatorMessage::EdgeReached { edge: Edge::Bottom },
+            ActuatorMessage::LanguageSync {
+                languages: vec!["en".into(), "uk".into()],
+            },
+            ActuatorMessage::GameSticks {
+                id: 1,
+                left: (-32768, 5),
+                right: (0, 32767),
+            },
+        ];
+        #[cfg(feature = "clipboard")]
+        messages.push(ActuatorMessage::SetClipboardBitmap {
+            data: (0..1000).map(|i| i as u8).collect(),
+        });
+        #[cfg(feature = "barrier-options")]
+        messages.push(ActuatorMessage::SetOptions {
+            opts: crate::ServerOptions {
+                heartbeat: Some(std::time::Duration::from_secs(5)),
+                half_duplex_caps_lock: Some(true),
+                ..Default::default()
+            },
+        });
+
+        let mut wire = vec![];
+        for msg in &messages {
+            let buf = postcard::to_stdvec(msg).unwrap();
+            write_frames(&mut wire, &buf, 64).unwrap();
+        }
+
+        // No frame exceeds the MTU
+        let mut frames = 0;
+        let mut rest = wire.as_slice();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            assert!(4 + len <= 64);
+            rest = &rest[4 + len..];
+            frames += 1;
+        }
+        assert!(frames > messages.len());
+
+        let mut stream = wire.as_slice();
+        let mut buf = vec![];
+        for msg in &messages {
+            let read = read_message(&mut stream, &mut buf).await.unwrap().unwrap();
+            assert_eq!(format!("{:?}", read), format!("{:?}", msg));
+        }
+        assert!(read_message(&mut stream, &mut buf).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let retry_interval = Duration::from_millis(100);
+        let mut actuator = RemoteActuator::new(addr, 1920, 1080).retry_interval(retry_interval);
+        actuator.enter().unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(actuator.is_connected());
+
+        // The peer goes away, a later call returns the failure of the writes
+        drop(stream);
+        let mut failed = false;
+        for _ in 0..100 {
+            if actuator.mouse_down(1).is_err() {
+                failed = true;
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(failed);
+        assert!(!actuator.is_connected());
+
+        // Connected again once the retry interval passed
+        sleep(retry_interval).await;
+        actuator.leave().unwrap();
+        drop(actuator);
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut recorder = Recorder::default();
+        serve_connection(stream, &mut recorder).await.unwrap();
+        assert_eq!(recorder.0, ["leave"]);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        // Nothing listens on the port of a listener dropped
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut actuator = RemoteActuator::new(addr.to_string(), 1920, 1080);
+        actuator.enter().unwrap();
+        let mut failed = false;
+        for _ in 0..100 {
+            if actuator.leave().is_err() {
+                failed = true;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(failed);
+        assert!(!actuator.is_connected());
+    }
+}
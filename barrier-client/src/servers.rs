@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+
+/// Addresses of the Barrier servers the client tries in turn, set with
+/// [`crate::ClientBuilder::servers`]. Each connection attempt goes through the list once,
+/// starting with the last server connected to, and the client only backs off once they all
+/// failed. Clones share the selection, e.g. to show the active server while the client runs.
+#[derive(Clone, Debug)]
+pub struct ServerList {
+    servers: Vec<String>,
+    selection: Arc<Mutex<Selection>>,
+}
+
+#[derive(Debug, Default)]
+struct Selection {
+    // Index of the server connected to last, tried first
+    last_good: usize,
+    active: Option<usize>,
+}
+
+impl ServerList {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(servers: I) -> Self {
+        Self {
+            servers: servers.into_iter().map(Into::into).collect(),
+            selection: Default::default(),
+        }
+    }
+
+    /// The addresses in "server:port" format, in the configured order
+    pub fn servers(&self) -> &[String] {
+        &self.servers
+    }
+
+    /// The server of the current connection, `None` while disconnected
+    pub fn active(&self) -> Option<&str> {
+        let active = self.selection.lock().unwrap().active?;
+        Some(&self.servers[active])
+    }
+
+    /// The servers in the order of the next attempt, with their index in the list
+    pub(crate) fn order(&self) -> impl Iterator<Item = (usize, &str)> {
+        let start = self.selection.lock().unwrap().last_good;
+        let len = self.servers.len();
+        (0..len).map(move |n| {
+            let index = (start + n) % len;
+            (index, self.servers[index].as_str())
+        })
+    }
+
+    pub(crate) fn connected(&self, index: usize) {
+        let mut selection = self.selection.lock().unwrap();
+        selection.last_good = index;
+        selection.active = Some(index);
+    }
+
+    pub(crate) fn disconnected(&self) {
+        self.selection.lock().unwrap().active = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ServerList;
+
+    fn order(servers: &ServerList) -> Vec<&str> {
+        servers.order().map(|(_, server)| server).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let servers = ServerList::new(["desktop:24800", "laptop:24800", "spare:24800"]);
+        assert_eq!(
+            order(&servers),
+            ["desktop:24800", "laptop:24800", "spare:24800"]
+        );
+        assert_eq!(servers.active(), None);
+
+        // The one that answered last goes first, the others follow in turn
+        servers.connected(1);
+        assert_eq!(servers.active(), Some("laptop:24800"));
+        assert_eq!(
+            order(&servers),
+            ["laptop:24800", "spare:24800", "desktop:24800"]
+        );
+        servers.disconnected();
+        assert_eq!(servers.active(), None);
+        assert_eq!(
+            order(&servers),
+            ["laptop:24800", "spare:24800", "desktop:24800"]
+        );
+
+        servers.connected(0);
+        assert_eq!(
+            order(&servers),
+            ["desktop:24800", "laptop:24800", "spare:24800"]
+        );
+        // Shared by the clones
+        let clone = servers.clone();
+        servers.connected(2);
+        assert_eq!(clone.active(), Some("spare:24800"));
+        assert_eq!(
+            order(&clone),
+            ["spare:24800", "desktop:24800", "laptop:24800"]
+        );
+    }
+}
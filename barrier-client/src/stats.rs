@@ -0,0 +1,252 @@
+use std::{
+    array,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+use super::{ActuatorErrorAction, Metrics};
+
+// Packet kinds counted apart, the others are only in the totals
+const KIND_SLOTS: usize = 48;
+
+// Codes of the packets the actuator injects on the host
+const INPUT_KINDS: [[u8; 4]; 11] = [
+    *b"DMMV", *b"DMRM", *b"DMDN", *b"DMUP", *b"DMWM", *b"DKDN", *b"DKRP", *b"DKUP", *b"DGBT",
+    *b"DGST", *b"DGTR",
+];
+
+/// [`Metrics`] keeping counters and the time of the last keep-alive and input event, e.g.
+/// for a health check. Shared with an `Arc`, it can be read from any thread while the
+/// client runs:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use barrier_client::{Client, ClientStats};
+/// let stats = Arc::new(ClientStats::new());
+/// let client = Client::builder()
+///     .server("server:24800")
+///     .screen_name("pi")
+///     .metrics(stats.clone())
+///     .build();
+/// ```
+///
+/// The values are updated with relaxed atomics, a read may miss the last packet or see the
+/// packet count ahead of the bytes. They add up over the connections of the client.
+#[derive(Debug)]
+pub struct ClientStats {
+    created: Instant,
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    input_events: AtomicU64,
+    actuator_errors: AtomicU64,
+    skipped_events: AtomicU64,
+    // Microseconds since `created` plus one, 0 until it happens
+    last_keepalive: AtomicU64,
+    last_input: AtomicU64,
+    // Code of the kind as a big endian u32, 0 for a free slot, and its count
+    kinds: [(AtomicU32, AtomicU64); KIND_SLOTS],
+}
+
+impl Default for ClientStats {
+    fn default() -> Self {
+        Self {
+            created: Instant::now(),
+            packets_received: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            packets_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            input_events: AtomicU64::new(0),
+            actuator_errors: AtomicU64::new(0),
+            skipped_events: AtomicU64::new(0),
+            last_keepalive: AtomicU64::new(0),
+            last_input: AtomicU64::new(0),
+            kinds: array::from_fn(|_| (AtomicU32::new(0), AtomicU64::new(0))),
+        }
+    }
+}
+
+impl ClientStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Packets read from the server, each chunk of the clipboard data counts
+    pub fn packets_received(&self) -> u64 {
+        self.packets_received.load(Relaxed)
+    }
+
+    /// Bytes of the packets read, with their length prefix
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Relaxed)
+    }
+
+    /// Packets sent to the server after the hello
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent.load(Relaxed)
+    }
+
+    /// Bytes of the packets sent, with their length prefix
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Relaxed)
+    }
+
+    /// Mouse, keyboard and game controller events the actuator returned from
+    pub fn input_events(&self) -> u64 {
+        self.input_events.load(Relaxed)
+    }
+
+    /// Actuator calls that returned an error, retried or not
+    pub fn actuator_errors(&self) -> u64 {
+        self.actuator_errors.load(Relaxed)
+    }
+
+    /// Events lost to an actuator error the connection went on after, see
+    /// [`ClientBuilder::on_actuator_error`](crate::ClientBuilder::on_actuator_error)
+    pub fn skipped_events(&self) -> u64 {
+        self.skipped_events.load(Relaxed)
+    }
+
+    /// When the last keep-alive of the server was read
+    pub fn last_keepalive(&self) -> Option<Instant> {
+        self.instant(&self.last_keepalive)
+    }
+
+    /// When the actuator returned from the last input event, see [`Self::input_events`]
+    pub fn last_input(&self) -> Option<Instant> {
+        self.instant(&self.last_input)
+    }
+
+    /// Packets read of each kind, by protocol code
+    pub fn packets_by_kind(&self) -> Vec<([u8; 4], u64)> {
+        self.kinds
+            .iter()
+            .map(|(code, count)| (code.load(Relaxed), count.load(Relaxed)))
+            .take_while(|&(code, _)| code != 0)
+            .map(|(code, count)| (code.to_be_bytes(), count))
+            .collect()
+    }
+
+    fn now(&self) -> u64 {
+        self.created.elapsed().as_micros().min(u64::MAX as u128 - 1) as u64 + 1
+    }
+
+    fn instant(&self, at: &AtomicU64) -> Option<Instant> {
+        match at.load(Relaxed) {
+            0 => None,
+            us => Some(self.created + Duration::from_micros(us - 1)),
+        }
+    }
+
+    fn count_kind(&self, kind: [u8; 4]) {
+        let code = u32::from_be_bytes(kind);
+        for (slot, count) in &self.kinds {
+            // Slots are taken in order, the first free one is for a new kind
+            let taken = match slot.load(Relaxed) {
+                0 => match slot.compare_exchange(0, code, Relaxed, Relaxed) {
+                    Ok(_) => code,
+                    Err(taken) => taken,
+                },
+                taken => taken,
+            };
+            if taken == code {
+                count.fetch_add(1, Relaxed);
+                return;
+            }
+        }
+    }
+}
+
+impl Metrics for ClientStats {
+    fn packet_received(&self, kind: [u8; 4], wire_bytes: usize) {
+        self.packets_received.fetch_add(1, Relaxed);
+        self.bytes_received.fetch_add(wire_bytes as u64, Relaxed);
+        self.count_kind(kind);
+        if &kind == b"CALV" {
+            self.last_keepalive.store(self.now(), Relaxed);
+        }
+    }
+
+    fn packet_sent(&self, _kind: [u8; 4], wire_bytes: usize) {
+        self.packets_sent.fetch_add(1, Relaxed);
+        self.bytes_sent.fetch_add(wire_bytes as u64, Relaxed);
+    }
+
+    fn event_dispatched(&self, kind: [u8; 4], _elapsed: Duration) {
+        if INPUT_KINDS.contains(&kind) {
+            self.input_events.fetch_add(1, Relaxed);
+            self.last_input.store(self.now(), Relaxed);
+        }
+    }
+
+    fn actuator_error(&self, _callback: &'static str, action: ActuatorErrorAction) {
+        self.actuator_errors.fetch_add(1, Relaxed);
+        if action == ActuatorErrorAction::Skipped {
+            self.skipped_events.fetch_add(1, Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::{ClientStats, KIND_SLOTS};
+    use crate::{ActuatorErrorAction, Metrics};
+
+    #[test]
+    fn test_client_stats() {
+        let stats = ClientStats::new();
+        assert_eq!(stats.last_keepalive(), None);
+        assert_eq!(stats.last_input(), None);
+        assert!(stats.packets_by_kind().is_empty());
+
+        stats.packet_received(*b"CALV", 8);
+        stats.packet_received(*b"DMMV", 12);
+        stats.packet_received(*b"DMMV", 12);
+        stats.event_dispatched(*b"CALV", Duration::ZERO);
+        stats.event_dispatched(*b"DMMV", Duration::ZERO);
+        stats.packet_sent(*b"CALV", 8);
+        stats.actuator_error("key_down", ActuatorErrorAction::Retried);
+        stats.actuator_error("key_down", ActuatorErrorAction::Skipped);
+        assert_eq!(stats.packets_received(), 3);
+        assert_eq!(stats.bytes_received(), 32);
+        assert_eq!(stats.packets_sent(), 1);
+        assert_eq!(stats.bytes_sent(), 8);
+        assert_eq!(stats.input_events(), 1);
+        assert_eq!(stats.actuator_errors(), 2);
+        assert_eq!(stats.skipped_events(), 1);
+        assert_eq!(stats.packets_by_kind(), [(*b"CALV", 1), (*b"DMMV", 2)]);
+        let keepalive = stats.last_keepalive().unwrap();
+        assert!(stats.last_input().unwrap() >= keepalive);
+
+        // Kinds past the slots are only in the totals
+        for n in 0..KIND_SLOTS as u32 {
+            stats.packet_received((0x4100_0000 + n).to_be_bytes(), 8);
+        }
+        assert_eq!(stats.packets_by_kind().len(), KIND_SLOTS);
+        assert_eq!(stats.packets_received(), 3 + KIND_SLOTS as u64);
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let stats = Arc::new(ClientStats::new());
+        let reader = {
+            let stats = stats.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                while last < 10_000 {
+                    let packets = stats.packets_received();
+                    assert!(packets >= last);
+                    last = packets;
+                }
+            })
+        };
+        for _ in 0..10_000 {
+            stats.packet_received(*b"DMMV", 12);
+        }
+        reader.join().unwrap();
+        assert_eq!(stats.packets_by_kind(), [(*b"DMMV", 10_000)]);
+    }
+}
@@ -0,0 +1,1698 @@
+//! A minimal in-process Barrier server to test clients and actuators against.
+//!
+//! The server accepts one connection, performs the hello and the QINF/DINF exchange,
+//! then replays a script of packets and records everything the client sends back.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), barrier_client::ConnectionError> {
+//! use barrier_client::{
+//!     test_server::{MockServer, Packet},
+//!     KeyMask,
+//! };
+//!
+//! let server = MockServer::bind().await?;
+//! let addr = server.local_addr()?;
+//! let script = vec![Packet::CursorEnter { x: 0, y: 0, seq_num: 1, mask: KeyMask::empty() }.into()];
+//! let served = tokio::spawn(async move { server.serve(script).await });
+//! // Run the client against `addr` here
+//! let session = served.await.unwrap()?;
+//! assert_eq!(session.screen_name, "test");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    time::{sleep, timeout_at, Instant},
+};
+
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+use crate::{read_capture, ConnectionError, PacketError, PacketReader, PacketStream};
+#[cfg(test)]
+use crate::{ActuatorError, KeyMask};
+pub use barrier_proto::Packet;
+
+/// One step of the script replayed by [`MockServer::serve`]
+#[derive(Debug)]
+pub enum Step {
+    /// Send a packet to the client
+    Send(Packet),
+    /// Pause before the next step, e.g. to let the client time out
+    Wait(Duration),
+    /// Wait for the client to send a packet, the session fails with
+    /// [`ConnectionError::Timeout`] if it doesn't within the duration. The packets read
+    /// meanwhile are recorded as well.
+    Expect(Packet, Duration),
+}
+
+impl From<Packet> for Step {
+    fn from(packet: Packet) -> Self {
+        Step::Send(packet)
+    }
+}
+
+/// What the client sent during a session
+#[derive(Debug)]
+pub struct MockSession {
+    /// Screen name from the hello
+    pub screen_name: String,
+    /// Protocol version from the hello as `(major, minor)`
+    pub version: (u16, u16),
+    /// Screen size from DINF, `None` if the screen has been rejected
+    pub screen_size: Option<(u16, u16)>,
+    /// Packets received after DINF until the client closed the connection, the clipboard
+    /// chunks are received as one `SetClipboard`
+    pub received: Vec<Packet>,
+}
+
+pub struct MockServer {
+    listener: TcpListener,
+    screens: Option<Vec<String>>,
+    reply_delay: Duration,
+    version: (u16, u16),
+}
+
+impl MockServer {
+    /// Listens on a free port of the loopback interface
+    pub async fn bind() -> Result<Self, ConnectionError> {
+        Ok(Self {
+            listener: TcpListener::bind("127.0.0.1:0").await?,
+            screens: None,
+            reply_delay: Duration::ZERO,
+            version: (1, 6),
+        })
+    }
+
+    /// Only accept these screen names, others are rejected with EUNK like a real server does,
+    /// default is to accept any name
+    pub fn with_screens<I: IntoIterator<Item = S>, S: Into<String>>(mut self, screens: I) -> Self {
+        self.screens = Some(screens.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Wait this long before replying to the hello of the client, e.g. to let the client time
+    /// out, default is to reply right away
+    pub fn with_reply_delay(mut self, delay: Duration) -> Self {
+        self.reply_delay = delay;
+        self
+    }
+
+    /// Announce this protocol version as `(major, minor)` and reject clients replying with a
+    /// newer one with EICV like a real server does, default is 1.6 like Barrier
+    pub fn with_version(mut self, major: u16, minor: u16) -> Self {
+        self.version = (major, minor);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ConnectionError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves one client, the write half is closed after the last step so the client
+    /// sees the server going away, then the packets sent back are collected until the
+    /// client closes the connection.
+    pub async fn serve<I: IntoIterator<Item = Step>>(
+        &self,
+        script: I,
+    ) -> Result<MockSession, ConnectionError> {
+        let (stream, _) = self.listener.accept().await?;
+        self.serve_stream(stream, script).await
+    }
+
+    /// Same as [`MockServer::serve`], over a connection the test opened, e.g. one end of
+    /// `tokio::io::duplex`
+    pub async fn serve_stream<
+        S: AsyncRead + AsyncWrite + Send + Unpin,
+        I: IntoIterator<Item = Step>,
+    >(
+        &self,
+        mut stream: S,
+        script: I,
+    ) -> Result<MockSession, ConnectionError> {
+        let (major, minor) = self.version;
+        let mut hello = b"Barrier".to_vec();
+        hello.extend_from_slice(&major.to_be_bytes());
+        hello.extend_from_slice(&minor.to_be_bytes());
+        stream.write_u32(hello.len() as u32).await?;
+        stream.write_all(&hello).await?;
+
+        let _size = stream.read_packet_size().await?;
+        let mut protocol = [0; 7];
+        stream.read_exact(&mut protocol).await?;
+        if &protocol != b"Barrier" {
+            return Err(PacketError::FORMAT.into());
+        }
+        let version = (stream.read_u16().await?, stream.read_u16().await?);
+        let len = stream.read_u32().await?;
+        let mut name = vec![0; len as usize];
+        stream.read_exact(&mut name).await?;
+        let mut session = MockSession {
+            screen_name: String::from_utf8_lossy(&name).into_owned(),
+            version,
+            screen_size: None,
+            received: vec![],
+        };
+
+        // The client may send clipboard data, accept whatever it sends
+        let mut stream = PacketStream::new(
+            stream,
+            #[cfg(feature = "clipboard")]
+            usize::MAX,
+        );
+        #[cfg(feature = "clipboard")]
+        let mut clipboard_stage = crate::ClipboardStage::new();
+
+        sleep(self.reply_delay).await;
+        if version > self.version {
+            stream
+                .write(Packet::ErrorIncompatibleVersion { major, minor })
+                .await?;
+            return Ok(session);
+        }
+        if let Some(screens) = &self.screens {
+            if !screens.contains(&session.screen_name) {
+                stream.write(Packet::ErrorUnknownDevice).await?;
+                return Ok(session);
+            }
+        }
+
+        stream.write(Packet::QueryInfo).await?;
+        match stream
+            .read(
+                #[cfg(feature = "clipboard")]
+                &mut clipboard_stage,
+            )
+            .await?
+        {
+            Packet::DeviceInfo { w, h, .. } => session.screen_size = Some((w, h)),
+            _ => return Err(PacketError::FORMAT.into()),
+        }
+        stream.write(Packet::InfoAck).await?;
+
+        for step in script {
+            match step {
+                Step::Send(packet) => stream.write(packet).await?,
+                Step::Wait(duration) => sleep(duration).await,
+                Step::Expect(expected, within) => {
+                    let deadline = Instant::now() + within;
+                    loop {
+                        let read = stream.read(
+                            #[cfg(feature = "clipboard")]
+                            &mut clipboard_stage,
+                        );
+                        let packet = timeout_at(deadline, read)
+                            .await
+                            .map_err(|_| ConnectionError::Timeout)??;
+                        let found = packet == expected;
+                        if packet != Packet::ClientNoOp {
+                            session.received.push(packet);
+                        }
+                        if found {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        // The client may have closed the connection already
+        stream.shutdown().await.ok();
+
+        while let Ok(packet) = stream
+            .read(
+                #[cfg(feature = "clipboard")]
+                &mut clipboard_stage,
+            )
+            .await
+        {
+            // The first chunks of the clipboard data, it's received with the last one
+            if packet != Packet::ClientNoOp {
+                session.received.push(packet);
+            }
+        }
+        Ok(session)
+    }
+}
+
+/// Parses the packets received in a capture like the client does, to reproduce a parser bug
+/// without the server, see [`crate::ClientBuilder::capture`]. The clipboard chunks truncated
+/// by the capture are skipped.
+pub async fn replay<R: std::io::Read>(capture: R) -> Result<Vec<Packet>, ConnectionError> {
+    let mut wire = vec![];
+    for frame in read_capture(capture)? {
+        if frame.received && !frame.is_truncated() {
+            wire.extend(frame.data);
+        }
+    }
+    let mut packets = vec![];
+    parse_wire(&wire, |packet| packets.push(packet)).await?;
+    Ok(packets)
+}
+
+/// Parses the packets of `wire` with the reader of the client and passes them to
+/// `on_packet`, the clipboard chunks before the last one as [`Packet::ClientNoOp`]. Used by
+/// the benchmarks of the parser, nothing but the parsing is timed.
+pub async fn parse_wire(wire: &[u8], mut on_packet: impl FnMut(Packet)) -> Result<(), PacketError> {
+    let mut stream = PacketStream::new(
+        wire,
+        #[cfg(feature = "clipboard")]
+        usize::MAX,
+    );
+    #[cfg(feature = "clipboard")]
+    let mut clipboard_stage = crate::ClipboardStage::new();
+    let mut parsed = 0;
+    while parsed < wire.len() {
+        on_packet(
+            stream
+                .read(
+                    #[cfg(feature = "clipboard")]
+                    &mut clipboard_stage,
+                )
+                .await?,
+        );
+        parsed += stream.last_received().1;
+    }
+    Ok(())
+}
+
+/// Records the calls from the client as readable events, the screen size can be changed
+/// through the second field while the client runs. Setting the clipboard blocks for the
+/// third one, like a write to a slow device.
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct Recorder(
+    pub Vec<String>,
+    pub Arc<Mutex<(u16, u16)>>,
+    #[cfg_attr(not(feature = "clipboard"), allow(dead_code))] pub Duration,
+);
+
+#[cfg(test)]
+impl Default for Recorder {
+    fn default() -> Self {
+        Self(
+            vec![],
+            Arc::new(Mutex::new((0x7fff, 0x7fff))),
+            Duration::ZERO,
+        )
+    }
+}
+
+#[cfg(test)]
+impl crate::Actuator for Recorder {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        *self.1.lock().unwrap()
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        (0, 0)
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.0.push(format!("abs {x} {y}"));
+        Ok(())
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.push(format!("rel {x} {y}"));
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.push(format!("down {button}"));
+        Ok(())
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.0.push(format!("up {button}"));
+        Ok(())
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.0.push(format!("wheel {x} {y}"));
+        Ok(())
+    }
+
+    fn key_down(&mut self, key: u16, _mask: KeyMask, _button: u16) -> Result<(), ActuatorError> {
+        self.0.push(format!("key {key}"));
+        Ok(())
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        _mask: KeyMask,
+        _button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        self.0.push(format!("repeat {key} {count}"));
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: u16, _mask: KeyMask, _button: u16) -> Result<(), ActuatorError> {
+        self.0.push(format!("key up {key}"));
+        Ok(())
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn set_options(&mut self, _opts: crate::ServerOptions) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    #[cfg(feature = "barrier-options")]
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.0.push("enter".to_string());
+        Ok(())
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.0.push("leave".to_string());
+        Ok(())
+    }
+
+    fn screensaver(&mut self, on: bool) -> Result<(), ActuatorError> {
+        self.0.push(format!("screensaver {on}"));
+        Ok(())
+    }
+
+    fn game_buttons(&mut self, id: u8, buttons: u16) -> Result<(), ActuatorError> {
+        self.0.push(format!("game {id} buttons {buttons:#x}"));
+        Ok(())
+    }
+
+    fn game_sticks(
+        &mut self,
+        id: u8,
+        left: (i16, i16),
+        right: (i16, i16),
+    ) -> Result<(), ActuatorError> {
+        self.0.push(format!("game {id} sticks {left:?} {right:?}"));
+        Ok(())
+    }
+
+    fn game_triggers(&mut self, id: u8, left: u8, right: u8) -> Result<(), ActuatorError> {
+        self.0.push(format!("game {id} triggers {left} {right}"));
+        Ok(())
+    }
+
+    fn edge_reached(&mut self, edge: crate::Edge) -> Result<(), ActuatorError> {
+        self.0.push(format!("edge {edge:?}"));
+        Ok(())
+    }
+
+    fn language_sync(&mut self, languages: Vec<String>) -> Result<(), ActuatorError> {
+        self.0.push(format!("languages {}", languages.join(" ")));
+        Ok(())
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, data: crate::ClipboardData) -> Result<(), ActuatorError> {
+        self.0
+            .push(format!("clipboard {}", data.text().unwrap_or_default()));
+        Ok(())
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard_with(
+        &mut self,
+        id: u8,
+        data: crate::ClipboardData,
+    ) -> Result<(), ActuatorError> {
+        std::thread::sleep(self.2);
+        self.0.push(format!(
+            "clipboard {id} {}",
+            data.text().unwrap_or_default()
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn get_clipboard(&mut self, id: u8) -> Result<Option<crate::ClipboardData>, ActuatorError> {
+        self.0.push(format!("get clipboard {id}"));
+        Ok(Some(format!("local {id}").into()))
+    }
+
+    #[cfg(feature = "file-transfer")]
+    fn file_received(
+        &mut self,
+        name_hint: Option<String>,
+        data: Vec<u8>,
+    ) -> Result<(), ActuatorError> {
+        let sum = data.iter().map(|&b| b as u32).sum::<u32>();
+        self.0.push(format!(
+            "file {} {} {sum}",
+            name_hint.unwrap_or_default(),
+            data.len()
+        ));
+        Ok(())
+    }
+}
+
+/// Calls the main callbacks of `actor`, an actuator forwarding them is checked against a
+/// [`Recorder`] driven the same way
+#[cfg(test)]
+pub(crate) fn drive<A: crate::Actuator>(actor: &mut A) {
+    actor.enter().unwrap();
+    actor.set_cursor_position(100, 200).unwrap();
+    actor.move_cursor(-10, 5).unwrap();
+    actor.mouse_down(1).unwrap();
+    actor.mouse_up(1).unwrap();
+    actor.mouse_wheel(0, -120).unwrap();
+    actor.key_down(0x61, KeyMask::SHIFT, 38).unwrap();
+    actor.key_repeat(0x61, KeyMask::SHIFT, 38, 3).unwrap();
+    actor.key_up(0x61, KeyMask::SHIFT, 38).unwrap();
+    #[cfg(feature = "clipboard")]
+    actor
+        .set_clipboard(crate::ClipboardData::from_raw(
+            "Hello, a long enough text to take a few frames".into(),
+            vec![],
+            vec![],
+        ))
+        .unwrap();
+    actor.leave().unwrap();
+    actor.screensaver(true).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{MockServer, MockSession, Packet, Recorder, Step};
+    use crate::{
+        start, start_with_stream, ActuatorError, ActuatorErrorPolicy, Backoff, ChannelActuator,
+        Client, ClientStats, ClientStatus, ConnectionError, ConnectionState, KeyMask,
+    };
+
+    // Runs `start` against a mock server replaying `script`
+    async fn run(
+        server: MockServer,
+        script: Vec<Step>,
+    ) -> (Result<(), ConnectionError>, MockSession, Vec<String>) {
+        let addr = server.local_addr().unwrap();
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let mut recorder = Recorder::default();
+        let result = start(addr, "test", &mut recorder).await;
+        (result, served.await.unwrap().unwrap(), recorder.0)
+    }
+
+    #[tokio::test]
+    async fn test_happy_path() {
+        let script = vec![
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Packet::MouseMoveAbs { x: 20, y: 30 }.into(),
+            Packet::MouseDown { id: 1 }.into(),
+            Packet::MouseUp { id: 1 }.into(),
+            Packet::MouseWheel {
+                x_delta: 0,
+                y_delta: 120,
+            }
+            .into(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::KeyRepeat {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+                count: 2,
+            }
+            .into(),
+            Packet::KeyUp {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::CursorLeave.into(),
+        ];
+        let (result, session, events) = run(MockServer::bind().await.unwrap(), script).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(session.screen_name, "test");
+        assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+        assert!(session.received.is_empty());
+        assert_eq!(
+            events,
+            [
+                "enter",
+                // The position from CINN
+                "abs 10 10",
+                "abs 20 30",
+                "down 1",
+                "up 1",
+                "wheel 0 120",
+                "key 97",
+                "repeat 97 2",
+                "key up 97",
+                "leave",
+            ]
+        );
+    }
+
+    #[cfg(feature = "barrier-options")]
+    #[tokio::test]
+    async fn test_relative_moves_option() {
+        let option = |name: &str, value| Packet::SetDeviceOptions([(name.into(), value)].into());
+        let script = vec![
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Packet::MouseMoveAbs { x: 20, y: 30 }.into(),
+            option("MDLT", 1).into(),
+            Packet::MouseMoveAbs { x: 25, y: 20 }.into(),
+            // Other options keep the mode
+            option("HDCL", 1).into(),
+            Packet::MouseMoveAbs { x: 30, y: 20 }.into(),
+            Packet::MouseMoveAbs { x: 30, y: 20 }.into(),
+            Packet::MouseMove { x: -1, y: 1 }.into(),
+            Packet::ResetOptions.into(),
+            Packet::MouseMoveAbs { x: 40, y: 40 }.into(),
+            option("MDLT", 1).into(),
+            Packet::MouseMoveAbs { x: 0, y: 40 }.into(),
+            option("MDLT", 0).into(),
+            Packet::MouseMoveAbs { x: 5, y: 5 }.into(),
+        ];
+        let (_, _, events) = run(MockServer::bind().await.unwrap(), script).await;
+        assert_eq!(
+            events,
+            [
+                "enter",
+                "abs 10 10",
+                "abs 20 30",
+                "rel 5 -10",
+                "rel 5 0",
+                "rel -1 1",
+                "abs 40 40",
+                "rel -40 0",
+                "abs 5 5",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version() {
+        let script = || {
+            vec![
+                Packet::LanguageSync {
+                    languages: vec!["en".into(), "fr".into()],
+                }
+                .into(),
+                Packet::CursorEnter {
+                    x: 10,
+                    y: 10,
+                    seq_num: 1,
+                    mask: KeyMask::empty(),
+                }
+                .into(),
+                Packet::KeyDown {
+                    id: 0x61,
+                    mask: KeyMask::empty(),
+                    button: 38,
+                }
+                .into(),
+                Packet::KeepAlive.into(),
+            ]
+        };
+
+        // An old server, the LSYN it shouldn't send is dropped
+        let server = MockServer::bind().await.unwrap().with_version(1, 3);
+        let (result, session, events) = run(server, script()).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(session.version, (1, 3));
+        assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+        assert_eq!(session.received, [Packet::KeepAlive]);
+        assert_eq!(events, ["enter", "abs 10 10", "key 97"]);
+
+        let server = MockServer::bind().await.unwrap().with_version(1, 8);
+        let (_, session, events) = run(server, script()).await;
+        assert_eq!(session.version, (1, 8));
+        assert_eq!(events, ["languages en fr", "enter", "abs 10 10", "key 97"]);
+
+        // A newer server speaks our version
+        let server = MockServer::bind().await.unwrap().with_version(1, 9);
+        let (_, session, events) = run(server, script()).await;
+        assert_eq!(session.version, (1, 8));
+        assert_eq!(events[0], "languages en fr");
+
+        // Barrier, LSYN came later
+        let (_, session, events) = run(MockServer::bind().await.unwrap(), script()).await;
+        assert_eq!(session.version, (1, 6));
+        assert_eq!(events, ["enter", "abs 10 10", "key 97"]);
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_echo() {
+        let script = vec![Packet::KeepAlive.into(), Packet::KeepAlive.into()];
+        let (_, session, _) = run(MockServer::bind().await.unwrap(), script).await;
+        assert_eq!(session.received, [Packet::KeepAlive, Packet::KeepAlive]);
+    }
+
+    #[tokio::test]
+    async fn test_client_stats() {
+        let server = MockServer::bind().await.unwrap();
+        let stats = Arc::new(ClientStats::new());
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .metrics(stats.clone())
+            .build()
+            .unwrap();
+        let script = vec![
+            Packet::KeepAlive.into(),
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Packet::MouseMoveAbs { x: 20, y: 30 }.into(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::KeyUp {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::KeepAlive.into(),
+            Packet::CursorLeave.into(),
+        ];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        served.await.unwrap().unwrap();
+
+        let kinds: Vec<_> = stats
+            .packets_by_kind()
+            .into_iter()
+            .map(|(kind, count)| (String::from_utf8_lossy(&kind).into_owned(), count))
+            .collect();
+        let count = |kind: &str, count| (kind.to_string(), count);
+        assert_eq!(
+            kinds,
+            [
+                count("QINF", 1),
+                count("CIAK", 1),
+                count("CALV", 2),
+                count("CINN", 1),
+                count("DMMV", 1),
+                count("DKDN", 1),
+                count("DKUP", 1),
+                count("COUT", 1),
+            ]
+        );
+        assert_eq!(stats.packets_received(), 9);
+        assert_eq!(stats.input_events(), 3);
+        // The screen info and the keep-alives echoed
+        assert_eq!(stats.packets_sent(), 3);
+        assert_eq!(stats.bytes_sent(), 22 + 2 * 8);
+        assert!(stats.last_keepalive().is_some());
+        assert!(stats.last_input().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_timeout() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .keepalive_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let served =
+            tokio::spawn(
+                async move { server.serve([Step::Wait(Duration::from_millis(500))]).await },
+            );
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        assert!(served.await.unwrap().unwrap().received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_to_server() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let handle = client.sender();
+        // Queued until the server has acknowledged the screen info
+        handle.send_key(0x61, KeyMask::empty(), 38).await.unwrap();
+        handle.send_mouse_move(100, 200).await.unwrap();
+        let served =
+            tokio::spawn(
+                async move { server.serve([Step::Wait(Duration::from_millis(200))]).await },
+            );
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(
+            served.await.unwrap().unwrap().received,
+            [
+                Packet::KeyDown {
+                    id: 0x61,
+                    mask: KeyMask::empty(),
+                    button: 38
+                },
+                Packet::KeyUp {
+                    id: 0x61,
+                    mask: KeyMask::empty(),
+                    button: 38
+                },
+                Packet::MouseMoveAbs { x: 100, y: 200 },
+            ]
+        );
+
+        drop(client);
+        assert!(matches!(
+            handle.send_mouse_down(1).await,
+            Err(ConnectionError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_screen_changed() {
+        // The query is answered with the size the queued actuator reported last
+        for (inline, queried) in [(true, (1920, 1080)), (false, (0x7fff, 0x7fff))] {
+            let server = MockServer::bind().await.unwrap();
+            let client = Client::builder()
+                .server(server.local_addr().unwrap().to_string())
+                .screen_name("test")
+                .inline_actuator(inline)
+                .build()
+                .unwrap();
+            let handle = client.sender();
+            let mut recorder = Recorder::default();
+            let screen_size = recorder.1.clone();
+            let script = [
+                Step::Wait(Duration::from_millis(100)),
+                // The server asks again after its configuration changed
+                Packet::QueryInfo.into(),
+                Step::Wait(Duration::from_millis(200)),
+            ];
+            let served = tokio::spawn(async move { server.serve(script).await });
+            let changed = tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                *screen_size.lock().unwrap() = (1920, 1080);
+                // A display plugged in after the query
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                *screen_size.lock().unwrap() = (3840, 2160);
+                handle.screen_changed().await.unwrap();
+            });
+            let result = client.run(&mut recorder).await;
+            assert!(matches!(result, Err(ConnectionError::Disconnected)));
+            changed.await.unwrap();
+            let session = served.await.unwrap().unwrap();
+            assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+            let info = |(w, h)| Packet::DeviceInfo {
+                x: 0,
+                y: 0,
+                w,
+                h,
+                _dummy: 0,
+                mx: 0,
+                my: 0,
+            };
+            assert_eq!(
+                session.received,
+                [info(queried), info((3840, 2160))],
+                "inline: {inline}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_start_with_stream() {
+        let server = MockServer::bind().await.unwrap();
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let script = vec![Packet::MouseMoveAbs { x: 20, y: 30 }.into()];
+        let served = tokio::spawn(async move { server.serve_stream(server_end, script).await });
+        let mut recorder = Recorder::default();
+        let result = start_with_stream(client_end, "test", &mut recorder).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        let session = served.await.unwrap().unwrap();
+        assert_eq!(session.screen_name, "test");
+        assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+        assert_eq!(recorder.0, ["abs 20 30"]);
+    }
+
+    #[tokio::test]
+    async fn test_connection_state() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let mut state = client.state();
+        assert_eq!(
+            *state.borrow_and_update(),
+            ConnectionState::Disconnected { reason: None }
+        );
+        // The client yields while waiting for the server between each change
+        let watched = tokio::spawn(async move {
+            let mut states = vec![];
+            while state.changed().await.is_ok() {
+                states.push(state.borrow_and_update().clone());
+            }
+            states
+        });
+        let wait = || Step::Wait(Duration::from_millis(50));
+        let script = [
+            wait(),
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            wait(),
+            Packet::CursorLeave.into(),
+            wait(),
+        ];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        served.await.unwrap().unwrap();
+        drop(client);
+        assert_eq!(
+            watched.await.unwrap(),
+            [
+                ConnectionState::Connecting,
+                ConnectionState::HelloSent,
+                ConnectionState::Connected,
+                ConnectionState::Entered,
+                ConnectionState::Left,
+                ConnectionState::Disconnected {
+                    reason: Some("Disconnected".to_string())
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let path = std::env::temp_dir().join(format!("barrier-replay-{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .capture(crate::CaptureOptions::new(&path))
+            .build()
+            .unwrap();
+        let script = || {
+            [
+                Packet::CursorEnter {
+                    x: 1,
+                    y: 2,
+                    seq_num: 3,
+                    mask: KeyMask::empty(),
+                },
+                Packet::MouseMoveAbs { x: 20, y: 30 },
+                Packet::KeepAlive,
+                Packet::CursorLeave,
+            ]
+        };
+        let served = tokio::spawn(async move { server.serve(script().map(Step::Send)).await });
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        served.await.unwrap().unwrap();
+        drop(client);
+        // The capture is written by another thread
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let capture = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let mut expected = vec![Packet::QueryInfo, Packet::InfoAck];
+        expected.extend(script());
+        assert_eq!(super::replay(&capture[..]).await.unwrap(), expected);
+        // The screen info and the keep-alive echoed back
+        let sent = crate::read_capture(&capture[..]).unwrap();
+        assert_eq!(sent.iter().filter(|frame| !frame.received).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_screen_name() {
+        let server = MockServer::bind().await.unwrap().with_screens(["other"]);
+        let (result, session, events) = run(server, vec![]).await;
+        assert!(matches!(result, Err(ConnectionError::UnknownScreenName(name)) if name == "test"));
+        assert_eq!(session.screen_name, "test");
+        assert_eq!(session.screen_size, None);
+        assert!(events.is_empty());
+
+        // Reported before reconnecting
+        let server = MockServer::bind().await.unwrap().with_screens(["other"]);
+        let statuses = Arc::new(Mutex::new(vec![]));
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .reconnect(true)
+            .on_status({
+                let statuses = statuses.clone();
+                move |status| statuses.lock().unwrap().push(status)
+            })
+            .build()
+            .unwrap();
+        let served = tokio::spawn(async move { server.serve(vec![]).await });
+        let mut recorder = Recorder::default();
+        tokio::time::timeout(Duration::from_millis(200), client.run(&mut recorder))
+            .await
+            .unwrap_err();
+        served.await.unwrap().unwrap();
+        assert_eq!(
+            statuses.lock().unwrap()[..3],
+            [
+                ClientStatus::Connecting,
+                ClientStatus::Connected,
+                ClientStatus::UnknownScreenName("test".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connected_after_hello() {
+        // Accepts the connection but never says hello
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let statuses = Arc::new(Mutex::new(vec![]));
+        let client = Client::builder()
+            .server(listener.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .on_status({
+                let statuses = statuses.clone();
+                move |status| statuses.lock().unwrap().push(status)
+            })
+            .build()
+            .unwrap();
+        let mut recorder = Recorder::default();
+        tokio::time::timeout(Duration::from_millis(200), client.run(&mut recorder))
+            .await
+            .unwrap_err();
+        assert_eq!(*statuses.lock().unwrap(), [ClientStatus::Connecting]);
+    }
+
+    // Address of a port nothing listens on
+    async fn closed_port() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_server_failover() {
+        let down = closed_port().await;
+        let server = MockServer::bind().await.unwrap();
+        let up = server.local_addr().unwrap().to_string();
+        let client = Client::builder()
+            .servers([down.clone(), up.clone()])
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let served =
+            tokio::spawn(
+                async move { server.serve([Step::Wait(Duration::from_millis(100))]).await },
+            );
+        let mut state = client.state();
+        let servers = client.servers().clone();
+        let active = tokio::spawn(async move {
+            state
+                .wait_for(|state| *state == ConnectionState::Connected)
+                .await
+                .unwrap();
+            servers.active().map(str::to_string)
+        });
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        served.await.unwrap().unwrap();
+        assert_eq!(active.await.unwrap(), Some(up.clone()));
+        assert_eq!(client.servers().active(), None);
+        // The server that answered is tried first next time
+        let order: Vec<_> = client.servers().order().map(|(_, s)| s).collect();
+        assert_eq!(order, [up, down]);
+    }
+
+    #[tokio::test]
+    async fn test_all_servers_down() {
+        let statuses = Arc::new(Mutex::new(vec![]));
+        let client = Client::builder()
+            .servers([closed_port().await, closed_port().await])
+            .screen_name("test")
+            .reconnect(true)
+            .backoff(Backoff {
+                initial: Duration::from_millis(20),
+                max: Duration::from_secs(1),
+                reset_after: Duration::from_secs(30),
+            })
+            .on_status({
+                let statuses = statuses.clone();
+                move |status| statuses.lock().unwrap().push(status)
+            })
+            .build()
+            .unwrap();
+        let mut recorder = Recorder::default();
+        tokio::time::timeout(Duration::from_millis(300), client.run(&mut recorder))
+            .await
+            .unwrap_err();
+        // Both servers fail before each wait, which doubles after each round
+        let statuses = statuses.lock().unwrap();
+        for (round, statuses) in statuses.as_chunks::<2>().0.iter().take(3).enumerate() {
+            let expected = Duration::from_millis(20 << round);
+            assert_eq!(statuses[0], ClientStatus::Connecting);
+            assert!(
+                matches!(statuses[1], ClientStatus::BackingOff(delay) if delay >= expected / 2 && delay <= expected),
+                "{statuses:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let server = MockServer::bind()
+            .await
+            .unwrap()
+            .with_reply_delay(Duration::from_secs(2));
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .handshake_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let served = tokio::spawn(async move { server.serve(vec![]).await });
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(
+            matches!(result, Err(ConnectionError::HandshakeTimeout(elapsed)) if elapsed >= Duration::from_millis(100)),
+            "{result:?}"
+        );
+        // The client is gone by the time the server replies
+        assert!(served.await.unwrap().is_err());
+
+        // A reply within the timeout is fine
+        let server = MockServer::bind()
+            .await
+            .unwrap()
+            .with_reply_delay(Duration::from_millis(100));
+        let (result, session, _) = run(server, vec![]).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_screen_name() {
+        let server = MockServer::bind().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        for name in ["", "p\0i", &"a".repeat(256)] {
+            let result = Client::builder()
+                .server(addr.to_string())
+                .screen_name(name)
+                .build();
+            assert!(matches!(result, Err(ConnectionError::InvalidScreenName(_))));
+            // Rejected before connecting, nothing accepts the connection
+            let result = start(addr, name, &mut Recorder::default()).await;
+            assert!(matches!(result, Err(ConnectionError::InvalidScreenName(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_busy() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .reconnect(true)
+            .build()
+            .unwrap();
+        let served = tokio::spawn(async move { server.serve([Packet::ErrorBusy.into()]).await });
+        // Fatal, the client gives up instead of reconnecting
+        let result = client.run(&mut Recorder::default()).await;
+        assert!(matches!(result, Err(ConnectionError::Busy)));
+        served.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_actuator_error() {
+        let server = MockServer::bind().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let script = vec![Packet::CursorEnter {
+            x: 10,
+            y: 10,
+            seq_num: 1,
+            mask: KeyMask::empty(),
+        }
+        .into()];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        // Nothing receives the messages, every callback fails
+        let (tx, _) = tokio::sync::mpsc::channel(1);
+        let mut actuator = ChannelActuator::new(1920, 1080, tx);
+        let result = start(addr, "test", &mut actuator).await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::ActuatorError(ActuatorError::Closed))
+        ));
+        // Only skipped when connecting, the enter ends the connection
+        let session = served.await.unwrap().unwrap();
+        assert_eq!(session.screen_size, Some((1920, 1080)));
+    }
+
+    // Fails every third input call, the events of the others are recorded
+    #[derive(Default)]
+    struct FlakyActuator(Recorder, u32);
+
+    impl FlakyActuator {
+        fn call(&mut self) -> Result<(), ActuatorError> {
+            self.1 += 1;
+            match self.1 % 3 {
+                0 => Err(ActuatorError::other("flaky")),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl crate::Actuator for FlakyActuator {
+        fn connected(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::connected(&mut self.0)
+        }
+
+        fn disconnected(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::disconnected(&mut self.0)
+        }
+
+        fn get_screen_size(&self) -> (u16, u16) {
+            crate::Actuator::get_screen_size(&self.0)
+        }
+
+        fn get_cursor_position(&self) -> (u16, u16) {
+            crate::Actuator::get_cursor_position(&self.0)
+        }
+
+        fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::set_cursor_position(&mut self.0, x, y)
+        }
+
+        fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::mouse_down(&mut self.0, button)
+        }
+
+        fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::mouse_up(&mut self.0, button)
+        }
+
+        fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::mouse_wheel(&mut self.0, x, y)
+        }
+
+        fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::key_down(&mut self.0, key, mask, button)
+        }
+
+        fn key_repeat(
+            &mut self,
+            key: u16,
+            mask: KeyMask,
+            button: u16,
+            count: u16,
+        ) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::key_repeat(&mut self.0, key, mask, button, count)
+        }
+
+        fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+            self.call()?;
+            crate::Actuator::key_up(&mut self.0, key, mask, button)
+        }
+
+        #[cfg(feature = "barrier-options")]
+        fn set_options(&mut self, opts: crate::ServerOptions) -> Result<(), ActuatorError> {
+            crate::Actuator::set_options(&mut self.0, opts)
+        }
+
+        #[cfg(feature = "barrier-options")]
+        fn reset_options(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::reset_options(&mut self.0)
+        }
+
+        fn enter(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::enter(&mut self.0)
+        }
+
+        fn leave(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::leave(&mut self.0)
+        }
+
+        #[cfg(feature = "clipboard")]
+        fn set_clipboard(&mut self, data: crate::ClipboardData) -> Result<(), ActuatorError> {
+            crate::Actuator::set_clipboard(&mut self.0, data)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_actuator_error_policy() {
+        let run_policy = |policy| async move {
+            let script = (1..=3).flat_map(|id| {
+                [
+                    Packet::MouseDown { id }.into(),
+                    Packet::MouseUp { id }.into(),
+                ]
+            });
+            let server = MockServer::bind().await.unwrap();
+            let stats = Arc::new(ClientStats::new());
+            let client = Client::builder()
+                .server(server.local_addr().unwrap().to_string())
+                .screen_name("test")
+                .on_actuator_error(policy)
+                .metrics(stats.clone())
+                .build()
+                .unwrap();
+            let served = tokio::spawn(async move { server.serve(script).await });
+            let mut actuator = FlakyActuator::default();
+            let result = client.run(&mut actuator).await;
+            // The server may still be writing when the client aborts
+            served.await.unwrap().ok();
+            (result, actuator.0 .0, stats)
+        };
+
+        let (result, events, stats) = run_policy(ActuatorErrorPolicy::Abort).await;
+        assert!(matches!(result, Err(ConnectionError::ActuatorError(_))));
+        assert_eq!(events, ["down 1", "up 1"]);
+        assert_eq!(stats.actuator_errors(), 1);
+        assert_eq!(stats.skipped_events(), 0);
+
+        // The connection lasts until the server closes it, without the failed events
+        let (result, events, stats) = run_policy(ActuatorErrorPolicy::SkipEvent).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(events, ["down 1", "up 1", "up 2", "down 3"]);
+        assert_eq!(stats.actuator_errors(), 2);
+        assert_eq!(stats.skipped_events(), 2);
+
+        let retry = ActuatorErrorPolicy::Retry {
+            attempts: 1,
+            delay: Duration::from_millis(1),
+        };
+        let (result, events, stats) = run_policy(retry).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        assert_eq!(
+            events,
+            ["down 1", "up 1", "down 2", "up 2", "down 3", "up 3"]
+        );
+        assert_eq!(stats.actuator_errors(), 2);
+        assert_eq!(stats.skipped_events(), 0);
+    }
+
+    // Forwards to the recorder after yielding, like an actuator awaiting its writes
+    #[cfg(feature = "async-actuator")]
+    struct AsyncRecorder(Recorder);
+
+    #[cfg(feature = "async-actuator")]
+    #[crate::async_trait]
+    impl crate::AsyncActuator for AsyncRecorder {
+        async fn connected(&mut self) -> Result<(), ActuatorError> {
+            tokio::task::yield_now().await;
+            crate::Actuator::connected(&mut self.0)
+        }
+
+        async fn disconnected(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::disconnected(&mut self.0)
+        }
+
+        async fn get_screen_size(&self) -> (u16, u16) {
+            crate::Actuator::get_screen_size(&self.0)
+        }
+
+        async fn get_cursor_position(&self) -> (u16, u16) {
+            crate::Actuator::get_cursor_position(&self.0)
+        }
+
+        async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+            tokio::task::yield_now().await;
+            crate::Actuator::set_cursor_position(&mut self.0, x, y)
+        }
+
+        async fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+            crate::Actuator::mouse_down(&mut self.0, button)
+        }
+
+        async fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+            crate::Actuator::mouse_up(&mut self.0, button)
+        }
+
+        async fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+            crate::Actuator::mouse_wheel(&mut self.0, x, y)
+        }
+
+        async fn key_down(
+            &mut self,
+            key: u16,
+            mask: KeyMask,
+            button: u16,
+        ) -> Result<(), ActuatorError> {
+            tokio::task::yield_now().await;
+            crate::Actuator::key_down(&mut self.0, key, mask, button)
+        }
+
+        async fn key_repeat(
+            &mut self,
+            key: u16,
+            mask: KeyMask,
+            button: u16,
+            count: u16,
+        ) -> Result<(), ActuatorError> {
+            crate::Actuator::key_repeat(&mut self.0, key, mask, button, count)
+        }
+
+        async fn key_up(
+            &mut self,
+            key: u16,
+            mask: KeyMask,
+            button: u16,
+        ) -> Result<(), ActuatorError> {
+            crate::Actuator::key_up(&mut self.0, key, mask, button)
+        }
+
+        #[cfg(feature = "barrier-options")]
+        async fn set_options(&mut self, opts: crate::ServerOptions) -> Result<(), ActuatorError> {
+            crate::Actuator::set_options(&mut self.0, opts)
+        }
+
+        #[cfg(feature = "barrier-options")]
+        async fn reset_options(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::reset_options(&mut self.0)
+        }
+
+        async fn enter(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::enter(&mut self.0)
+        }
+
+        async fn leave(&mut self) -> Result<(), ActuatorError> {
+            crate::Actuator::leave(&mut self.0)
+        }
+
+        #[cfg(feature = "clipboard")]
+        async fn set_clipboard(&mut self, data: crate::ClipboardData) -> Result<(), ActuatorError> {
+            crate::Actuator::set_clipboard(&mut self.0, data)
+        }
+    }
+
+    #[cfg(feature = "async-actuator")]
+    #[tokio::test]
+    async fn test_async_actuator() {
+        let script = vec![
+            Packet::CursorEnter {
+                x: 10,
+                y: 10,
+                seq_num: 1,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Packet::MouseMoveAbs { x: 20, y: 30 }.into(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::KeyUp {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            Packet::CursorLeave.into(),
+        ];
+        let server = MockServer::bind().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let mut recorder = AsyncRecorder(Recorder::default());
+        let result = crate::start_async(addr, "test", &mut recorder).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        let session = served.await.unwrap().unwrap();
+        assert_eq!(session.screen_size, Some((0x7fff, 0x7fff)));
+        assert_eq!(
+            recorder.0 .0,
+            [
+                "enter",
+                "abs 10 10",
+                "abs 20 30",
+                "key 97",
+                "key up 97",
+                "leave"
+            ]
+        );
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[tokio::test]
+    async fn test_clipboard() {
+        let data = crate::ClipboardData::from;
+        let script = vec![
+            Packet::SetClipboard {
+                id: 0,
+                seq_num: 1,
+                data: data("Hello"),
+            }
+            .into(),
+            // The primary selection
+            Packet::SetClipboard {
+                id: 1,
+                seq_num: 1,
+                data: data("World"),
+            }
+            .into(),
+        ];
+        let (_, _, events) = run(MockServer::bind().await.unwrap(), script).await;
+        assert_eq!(events, ["clipboard 0 Hello", "clipboard 1 World"]);
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[tokio::test]
+    async fn test_clipboard_ownership() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let handle = client.sender();
+        let script = [
+            Packet::CursorEnter {
+                x: 0,
+                y: 0,
+                seq_num: 7,
+                mask: KeyMask::empty(),
+            }
+            .into(),
+            Step::Wait(Duration::from_millis(200)),
+            // Another screen grabbed the selection
+            Packet::GrabClipboard { id: 1, seq_num: 7 }.into(),
+            Packet::CursorLeave.into(),
+        ];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let changed = tokio::spawn(async move {
+            // Both change while the cursor is on this screen
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            handle.clipboard_changed(0).await.unwrap();
+            handle.clipboard_changed(1).await.unwrap();
+        });
+        let mut recorder = Recorder::default();
+        let result = client.run(&mut recorder).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        changed.await.unwrap();
+        assert_eq!(
+            served.await.unwrap().unwrap().received,
+            [
+                Packet::GrabClipboard { id: 0, seq_num: 7 },
+                Packet::GrabClipboard { id: 1, seq_num: 7 },
+                // Only the clipboard is still owned as the cursor leaves
+                Packet::SetClipboard {
+                    id: 0,
+                    seq_num: 7,
+                    data: "local 0".into(),
+                },
+            ]
+        );
+        assert_eq!(recorder.0, ["enter", "abs 0 0", "leave", "get clipboard 0"]);
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_slow_actuator() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .build()
+            .unwrap();
+        let script = [
+            Packet::SetClipboard {
+                id: 0,
+                seq_num: 1,
+                data: "slow".into(),
+            }
+            .into(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: KeyMask::empty(),
+                button: 38,
+            }
+            .into(),
+            // Barrier drops the clients not answering its keep-alives in time
+            Packet::KeepAlive.into(),
+            Step::Expect(Packet::KeepAlive, Duration::from_secs(1)),
+            Step::Wait(Duration::from_millis(500)),
+            Packet::KeepAlive.into(),
+            Step::Expect(Packet::KeepAlive, Duration::from_secs(1)),
+        ];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let mut recorder = Recorder {
+            2: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let result = client.run(&mut recorder).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        let session = served.await.unwrap().unwrap();
+        assert_eq!(session.received, [Packet::KeepAlive, Packet::KeepAlive]);
+        // The key waited for the clipboard
+        assert_eq!(recorder.0, ["clipboard 0 slow", "key 97"]);
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_actuator_queue_full() {
+        let server = MockServer::bind().await.unwrap();
+        let client = Client::builder()
+            .server(server.local_addr().unwrap().to_string())
+            .screen_name("test")
+            .actuator_queue_size(1)
+            .build()
+            .unwrap();
+        let clipboard = |data: &str| Packet::SetClipboard {
+            id: 0,
+            seq_num: 1,
+            data: data.into(),
+        };
+        let key = KeyMask::empty();
+        // The actuator is busy with the clipboard while the next packets are read
+        let wait = || Step::Wait(Duration::from_millis(50));
+        let script = [
+            clipboard("a").into(),
+            wait(),
+            Packet::MouseMoveAbs { x: 10, y: 10 }.into(),
+            Packet::MouseMoveAbs { x: 20, y: 20 }.into(),
+            Packet::MouseMoveAbs { x: 30, y: 30 }.into(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: key,
+                button: 38,
+            }
+            .into(),
+            clipboard("b").into(),
+            wait(),
+            Packet::MouseMove { x: 1, y: 1 }.into(),
+            Packet::MouseMove { x: 2, y: -2 }.into(),
+            Packet::MouseMove { x: 3, y: 3 }.into(),
+            Packet::KeyUp {
+                id: 0x61,
+                mask: key,
+                button: 38,
+            }
+            .into(),
+        ];
+        let served = tokio::spawn(async move { server.serve(script).await });
+        let mut recorder = Recorder {
+            2: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let result = client.run(&mut recorder).await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        served.await.unwrap().unwrap();
+        // The first move is queued, the next ones wait for room, merged
+        assert_eq!(
+            recorder.0,
+            [
+                "clipboard 0 a",
+                "abs 10 10",
+                "abs 30 30",
+                "key 97",
+                "clipboard 0 b",
+                "rel 1 1",
+                "rel 5 1",
+                "key up 97",
+            ]
+        );
+    }
+
+    #[cfg(feature = "file-transfer")]
+    #[tokio::test]
+    async fn test_file_transfer() {
+        let data: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
+        let mut script = vec![Packet::DragInfo {
+            files: vec!["C:\\Users\\me\\a.bin".into(), "/tmp/b.txt".into()],
+        }
+        .into()];
+        let size = data.len().to_string().into_bytes();
+        script.push(
+            Packet::FileChunk {
+                mark: 1,
+                data: size,
+            }
+            .into(),
+        );
+        for chunk in data.chunks(barrier_proto::FILE_CHUNK_SIZE) {
+            script.push(
+                Packet::FileChunk {
+                    mark: 2,
+                    data: chunk.to_vec(),
+                }
+                .into(),
+            );
+        }
+        script.push(
+            Packet::FileChunk {
+                mark: 3,
+                data: vec![],
+            }
+            .into(),
+        );
+        let (_, _, events) = run(MockServer::bind().await.unwrap(), script).await;
+        let sum = data.iter().map(|&b| b as u32).sum::<u32>();
+        assert_eq!(events, [format!("file a.bin 1000000 {sum}")]);
+    }
+}
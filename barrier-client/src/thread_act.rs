@@ -1,11 +1,12 @@
 use std::{
-    collections::HashMap,
     marker::PhantomData,
     sync::mpsc::{sync_channel, SyncSender},
     thread,
 };
 
-use super::Actuator;
+use log::warn;
+
+use super::{actuator::offset_position, Actuator, ActuatorError, KeyMask, ServerOptions};
 
 pub enum ActMsg {
     Connected,
@@ -30,22 +31,22 @@ pub enum ActMsg {
     },
     KeyDown {
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
     },
     KeyRepeat {
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
         count: u16,
     },
     KeyUp {
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
     },
     SetOptions {
-        opts: HashMap<String, u32>,
+        opts: ServerOptions,
     },
     ResetOptions,
     Enter,
@@ -65,31 +66,38 @@ impl<T: Actuator + Send + 'static> ThreadedActuator<T> {
     pub fn new(screen_width: u16, screen_height: u16, mut actuator: T) -> Self {
         let (tx, rx) = sync_channel(16);
         let builder = thread::Builder::new().stack_size(16384);
-        builder.spawn(move || {
-            while let Ok(msg) = rx.recv() {
-                match msg {
-                    ActMsg::Connected => actuator.connected(),
-                    ActMsg::Disconnected => actuator.disconnected(),
-                    ActMsg::SetCursorPosition { x, y } => actuator.set_cursor_position(x, y),
-                    ActMsg::MoveCursor { x, y } => actuator.move_cursor(x, y),
-                    ActMsg::MouseDown { button } => actuator.mouse_down(button),
-                    ActMsg::MouseUp { button } => actuator.mouse_up(button),
-                    ActMsg::MouseWheel { x, y } => actuator.mouse_wheel(x, y),
-                    ActMsg::KeyDown { key, mask, button } => actuator.key_down(key, mask, button),
-                    ActMsg::KeyRepeat {
-                        key,
-                        mask,
-                        button,
-                        count,
-                    } => actuator.key_repeat(key, mask, button, count),
-                    ActMsg::KeyUp { key, mask, button } => actuator.key_up(key, mask, button),
-                    ActMsg::SetOptions { opts } => actuator.set_options(opts),
-                    ActMsg::ResetOptions => actuator.reset_options(),
-                    ActMsg::Enter => actuator.enter(),
-                    ActMsg::Leave => actuator.leave(),
+        builder
+            .spawn(move || {
+                while let Ok(msg) = rx.recv() {
+                    let result = match msg {
+                        ActMsg::Connected => actuator.connected(),
+                        ActMsg::Disconnected => actuator.disconnected(),
+                        ActMsg::SetCursorPosition { x, y } => actuator.set_cursor_position(x, y),
+                        ActMsg::MoveCursor { x, y } => actuator.move_cursor(x, y),
+                        ActMsg::MouseDown { button } => actuator.mouse_down(button),
+                        ActMsg::MouseUp { button } => actuator.mouse_up(button),
+                        ActMsg::MouseWheel { x, y } => actuator.mouse_wheel(x, y),
+                        ActMsg::KeyDown { key, mask, button } => {
+                            actuator.key_down(key, mask, button)
+                        }
+                        ActMsg::KeyRepeat {
+                            key,
+                            mask,
+                            button,
+                            count,
+                        } => actuator.key_repeat(key, mask, button, count),
+                        ActMsg::KeyUp { key, mask, button } => actuator.key_up(key, mask, button),
+                        ActMsg::SetOptions { opts } => actuator.set_options(opts),
+                        ActMsg::ResetOptions => actuator.reset_options(),
+                        ActMsg::Enter => actuator.enter(),
+                        ActMsg::Leave => actuator.leave(),
+                    };
+                    if let Err(e) = result {
+                        warn!("Actuator failed: {}", e);
+                    }
                 }
-            }
-        }).expect("Failed to create actuator thread");
+            })
+            .expect("Failed to create actuator thread");
 
         Self {
             screen_width,
@@ -105,17 +113,17 @@ impl<T: Actuator + Send + 'static> ThreadedActuator<T> {
         self.tx.clone()
     }
 
-    fn send(&self, msg: ActMsg) {
-        self.tx.send(msg).unwrap()
+    fn send(&self, msg: ActMsg) -> Result<(), ActuatorError> {
+        self.tx.send(msg).map_err(|_| ActuatorError::Closed)
     }
 }
 
 impl<T: Actuator + Send + 'static> Actuator for ThreadedActuator<T> {
-    fn connected(&mut self) {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
         self.send(ActMsg::Connected)
     }
 
-    fn disconnected(&mut self) {
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
         self.send(ActMsg::Disconnected)
     }
 
@@ -127,35 +135,47 @@ impl<T: Actuator + Send + 'static> Actuator for ThreadedActuator<T> {
         (self.cursor_x, self.cursor_y)
     }
 
-    fn set_cursor_position(&mut self, x: u16, y: u16) {
-        self.send(ActMsg::SetCursorPosition { x, y });
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.send(ActMsg::SetCursorPosition { x, y })?;
         self.cursor_x = x;
         self.cursor_y = y;
+        Ok(())
     }
 
-    fn move_cursor(&mut self, x: i16, y: i16) {
-        self.send(ActMsg::MoveCursor { x, y });
-        self.cursor_x = self.cursor_x.wrapping_add_signed(x);
-        self.cursor_y = self.cursor_y.wrapping_add_signed(y);
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.send(ActMsg::MoveCursor { x, y })?;
+        (self.cursor_x, self.cursor_y) = offset_position(
+            (self.cursor_x, self.cursor_y),
+            (self.screen_width, self.screen_height),
+            x,
+            y,
+        );
+        Ok(())
     }
 
-    fn mouse_down(&mut self, button: i8) {
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
         self.send(ActMsg::MouseDown { button })
     }
 
-    fn mouse_up(&mut self, button: i8) {
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
         self.send(ActMsg::MouseUp { button })
     }
 
-    fn mouse_wheel(&mut self, x: i16, y: i16) {
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
         self.send(ActMsg::MouseWheel { x, y })
     }
 
-    fn key_down(&mut self, key: u16, mask: u16, button: u16) {
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
         self.send(ActMsg::KeyDown { key, mask, button })
     }
 
-    fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
         self.send(ActMsg::KeyRepeat {
             key,
             mask,
@@ -164,23 +184,23 @@ impl<T: Actuator + Send + 'static> Actuator for ThreadedActuator<T> {
         })
     }
 
-    fn key_up(&mut self, key: u16, mask: u16, button: u16) {
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
         self.send(ActMsg::KeyUp { key, mask, button })
     }
 
-    fn set_options(&mut self, opts: HashMap<String, u32>) {
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
         self.send(ActMsg::SetOptions { opts })
     }
 
-    fn reset_options(&mut self) {
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
         self.send(ActMsg::ResetOptions)
     }
 
-    fn enter(&mut self) {
+    fn enter(&mut self) -> Result<(), ActuatorError> {
         self.send(ActMsg::Enter)
     }
 
-    fn leave(&mut self) {
+    fn leave(&mut self) -> Result<(), ActuatorError> {
         self.send(ActMsg::Leave)
     }
 }
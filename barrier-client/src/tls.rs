@@ -0,0 +1,283 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use log::{debug, warn};
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+        SignatureScheme,
+    },
+    TlsConnector,
+};
+
+use super::ConnectionError;
+
+/// Fingerprint of the server certificate, as shown in the Barrier server's SSL settings.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Fingerprint {
+    /// Used by Barrier 2.3 and older
+    Sha1([u8; 20]),
+    /// Used by Barrier 2.4+ and Input Leap
+    Sha256([u8; 32]),
+}
+
+impl Fingerprint {
+    fn of(&self, cert: &[u8]) -> Fingerprint {
+        match self {
+            Fingerprint::Sha1(_) => {
+                let digest =
+                    ::ring::digest::digest(&::ring::digest::SHA1_FOR_LEGACY_USE_ONLY, cert);
+                Fingerprint::Sha1(digest.as_ref().try_into().unwrap())
+            }
+            Fingerprint::Sha256(_) => {
+                let digest = ::ring::digest::digest(&::ring::digest::SHA256, cert);
+                Fingerprint::Sha256(digest.as_ref().try_into().unwrap())
+            }
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Fingerprint::Sha1(fp) => fp,
+            Fingerprint::Sha256(fp) => fp,
+        }
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.as_bytes().iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid certificate fingerprint")]
+pub struct InvalidFingerprint;
+
+impl FromStr for Fingerprint {
+    type Err = InvalidFingerprint;
+
+    /// Parses "AA:BB:..." or plain hex, the length decides between SHA1 and SHA256.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: Vec<u8> = s
+            .bytes()
+            .filter(|c| !matches!(c, b':' | b' '))
+            .collect();
+        if !hex.len().is_multiple_of(2) {
+            return Err(InvalidFingerprint);
+        }
+        let bytes = hex
+            .chunks(2)
+            .map(|c| {
+                core::str::from_utf8(c)
+                    .ok()
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+                    .ok_or(InvalidFingerprint)
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        match bytes.len() {
+            20 => Ok(Fingerprint::Sha1(bytes.try_into().unwrap())),
+            32 => Ok(Fingerprint::Sha256(bytes.try_into().unwrap())),
+            _ => Err(InvalidFingerprint),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TlsVerification {
+    /// PEM encoded CA certificates used to verify the server certificate
+    CaBundle(Vec<u8>),
+    /// Only accept the server certificate with this fingerprint, Barrier uses self-signed certificates
+    Fingerprint(Fingerprint),
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsOptions {
+    pub verification: TlsVerification,
+    /// Name used for SNI and to verify the certificate against a CA bundle
+    pub server_name: String,
+}
+
+impl TlsOptions {
+    pub fn with_fingerprint(fingerprint: Fingerprint) -> Self {
+        Self {
+            verification: TlsVerification::Fingerprint(fingerprint),
+            // Barrier generates its self-signed certificate with this CN
+            server_name: String::from("Barrier"),
+        }
+    }
+
+    pub fn with_ca_bundle<S: Into<String>>(pem: Vec<u8>, server_name: S) -> Self {
+        Self {
+            verification: TlsVerification::CaBundle(pem),
+            server_name: server_name.into(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("certificate fingerprint {0} does not match")]
+struct FingerprintMismatch(Fingerprint);
+
+#[derive(Debug)]
+struct FingerprintVerifier {
+    fingerprint: Fingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let actual = self.fingerprint.of(end_entity.as_ref());
+        if actual == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                OtherError(Arc::new(FingerprintMismatch(actual))),
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn client_config(opts: &TlsOptions) -> Result<ClientConfig, ConnectionError> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| ConnectionError::TlsConfigError(e.to_string()))?;
+    let config = match &opts.verification {
+        TlsVerification::CaBundle(pem) => {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                let cert = cert.map_err(|e| ConnectionError::TlsConfigError(e.to_string()))?;
+                roots
+                    .add(cert)
+                    .map_err(|e| ConnectionError::TlsConfigError(e.to_string()))?;
+            }
+            if roots.is_empty() {
+                return Err(ConnectionError::TlsConfigError(String::from(
+                    "no certificate found in CA bundle",
+                )));
+            }
+            builder.with_root_certificates(roots)
+        }
+        TlsVerification::Fingerprint(fingerprint) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(FingerprintVerifier {
+                fingerprint: fingerprint.clone(),
+                provider,
+            })),
+    };
+    Ok(config.with_no_client_auth())
+}
+
+pub(crate) async fn connect_tls(
+    stream: TcpStream,
+    opts: &TlsOptions,
+) -> Result<TlsStream<TcpStream>, ConnectionError> {
+    let connector = TlsConnector::from(Arc::new(client_config(opts)?));
+    let server_name = ServerName::try_from(opts.server_name.clone())
+        .map_err(|e| ConnectionError::TlsConfigError(e.to_string()))?;
+    match connector.connect(server_name, stream).await {
+        Ok(stream) => {
+            debug!("TLS handshake done");
+            Ok(stream)
+        }
+        Err(e) => {
+            let mismatch = e
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<rustls::Error>())
+                .and_then(|e| match e {
+                    rustls::Error::InvalidCertificate(CertificateError::Other(other)) => {
+                        other.0.downcast_ref::<FingerprintMismatch>()
+                    }
+                    _ => None,
+                });
+            match mismatch {
+                Some(FingerprintMismatch(actual)) => {
+                    warn!("Server certificate fingerprint is {actual}");
+                    Err(ConnectionError::FingerprintMismatch(actual.to_string()))
+                }
+                None => Err(ConnectionError::TlsError(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fingerprint;
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let sha1 = "1F:2E:3D:4C:5B:6A:79:88:97:A6:B5:C4:D3:E2:F1:00:11:22:33:44";
+        let fp: Fingerprint = sha1.parse().unwrap();
+        assert!(matches!(fp, Fingerprint::Sha1(_)));
+        assert_eq!(fp.to_string(), sha1);
+
+        let sha256 = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+        let fp: Fingerprint = sha256.parse().unwrap();
+        assert!(matches!(fp, Fingerprint::Sha256(_)));
+        assert_eq!(fp.to_string().replace(':', ""), sha256.to_uppercase());
+
+        assert!("1F:2E:3D".parse::<Fingerprint>().is_err());
+        assert!("zz".repeat(20).parse::<Fingerprint>().is_err());
+    }
+}
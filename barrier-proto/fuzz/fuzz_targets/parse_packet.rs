@@ -0,0 +1,30 @@
+//! Feeds the bytes to the parser as if received from the server, packet after packet. Any
+//! result is fine but a panic, or a packet taking other than the size written in front of it.
+#![no_main]
+
+use barrier_proto::{parse_packet, ClipboardStage, Error};
+use libfuzzer_sys::fuzz_target;
+
+// Small enough for the inputs to reach the limits
+const MAX_PACKET_SIZE: usize = 4096;
+const MAX_CLIPBOARD_SIZE: usize = 1024;
+
+fuzz_target!(|data: &[u8]| {
+    let mut stage = ClipboardStage::new();
+    let mut buf = data;
+    loop {
+        match parse_packet(buf, MAX_PACKET_SIZE, &mut stage, MAX_CLIPBOARD_SIZE) {
+            Ok((_, len)) => {
+                let size = u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize;
+                assert_eq!(len, 4 + size);
+                buf = &buf[len..];
+            }
+            Err(Error::Incomplete { needed }) => {
+                assert!(needed > buf.len());
+                break;
+            }
+            // The stream is out of sync, the client reconnects
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,236 @@
+//! Conversion of the clipboard bitmap to and from PNG.
+//!
+//! Barrier sends bitmaps as a DIB, a BMP file without the file header: a 40 bytes
+//! BITMAPINFOHEADER followed by the pixels in BGR or BGRA, rows padded to 4 bytes and
+//! bottom-up unless the height is negative.
+
+use crate::ClipboardData;
+
+/// Size of BITMAPINFOHEADER, the header Barrier uses
+const INFO_HEADER_SIZE: usize = 40;
+
+/// No compression, the pixels follow the header
+const BI_RGB: u32 = 0;
+
+/// 32 bits pixels with color masks after the header, Windows uses it for BGRA
+const BI_BITFIELDS: u32 = 3;
+
+struct BitmapHeader {
+    width: u32,
+    height: u32,
+    top_down: bool,
+    bits_per_pixel: u16,
+    // Offset of the pixels from the start of the header
+    offset: usize,
+}
+
+impl BitmapHeader {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let u16_at = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?));
+        let u32_at = |at: usize| Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?));
+
+        let header_size = u32_at(0)? as usize;
+        if header_size < INFO_HEADER_SIZE {
+            return None;
+        }
+        let width = u32_at(4)? as i32;
+        let height = u32_at(8)? as i32;
+        let bits_per_pixel = u16_at(14)?;
+        let compression = u32_at(16)?;
+        let offset = match (bits_per_pixel, compression) {
+            (24 | 32, BI_RGB) => header_size,
+            // The red, green and blue masks, assumed to be the usual BGRA ones
+            (32, BI_BITFIELDS) if header_size == INFO_HEADER_SIZE => header_size + 12,
+            (32, BI_BITFIELDS) => header_size,
+            _ => return None,
+        };
+        if width <= 0 || height == 0 {
+            return None;
+        }
+        Some(Self {
+            width: width as u32,
+            height: height.unsigned_abs(),
+            top_down: height < 0,
+            bits_per_pixel,
+            offset,
+        })
+    }
+
+    fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel as usize).div_ceil(32) * 4
+    }
+}
+
+impl ClipboardData {
+    /// Width and height of the bitmap, `None` if there is none or its format isn't supported
+    pub fn bitmap_dimensions(&self) -> Option<(u32, u32)> {
+        let header = BitmapHeader::parse(self.bitmap()?)?;
+        Some((header.width, header.height))
+    }
+
+    /// The bitmap encoded as a RGBA PNG, `None` if there is none or it can't be decoded
+    pub fn bitmap_as_png(&self) -> Option<Vec<u8>> {
+        let data = self.bitmap()?;
+        let header = BitmapHeader::parse(data)?;
+        let stride = header.stride();
+        let pixels = data.get(header.offset..header.offset + stride * header.height as usize)?;
+        let bytes_per_pixel = header.bits_per_pixel as usize / 8;
+
+        let mut rgba = Vec::with_capacity(header.width as usize * header.height as usize * 4);
+        for y in 0..header.height as usize {
+            let row = if header.top_down {
+                y
+            } else {
+                header.height as usize - 1 - y
+            };
+            let row = &pixels[row * stride..][..header.width as usize * bytes_per_pixel];
+            for pixel in row.chunks_exact(bytes_per_pixel) {
+                // 24 bits bitmaps have no alpha
+                let alpha = pixel.get(3).copied().unwrap_or(0xFF);
+                rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+            }
+        }
+        // Most applications put opaque images with the alpha unset
+        if bytes_per_pixel == 4 && rgba.as_chunks::<4>().0.iter().all(|[.., a]| *a == 0) {
+            rgba.as_chunks_mut::<4>()
+                .0
+                .iter_mut()
+                .for_each(|pixel| pixel[3] = 0xFF);
+        }
+
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, header.width, header.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().ok()?;
+        writer.write_image_data(&rgba).ok()?;
+        writer.finish().ok()?;
+        Some(png)
+    }
+
+    /// Clipboard data with the PNG as its bitmap, for sending an image to the server.
+    /// `None` if the PNG can't be decoded.
+    pub fn from_png(png: &[u8]) -> Option<Self> {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().ok()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).ok()?;
+        let pixels = &buf[..info.buffer_size()];
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut bitmap = Vec::with_capacity(INFO_HEADER_SIZE + width * height * 4);
+        bitmap.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        bitmap.extend_from_slice(&(width as i32).to_le_bytes());
+        // Bottom-up like most bitmaps
+        bitmap.extend_from_slice(&(height as i32).to_le_bytes());
+        bitmap.extend_from_slice(&1u16.to_le_bytes());
+        bitmap.extend_from_slice(&32u16.to_le_bytes());
+        bitmap.extend_from_slice(&BI_RGB.to_le_bytes());
+        bitmap.extend_from_slice(&((width * height * 4) as u32).to_le_bytes());
+        // Resolution and palette, unused
+        bitmap.extend_from_slice(&[0; 16]);
+
+        let channels = info.color_type.samples();
+        for row in pixels.chunks_exact(info.line_size).take(height).rev() {
+            for pixel in row.chunks_exact(channels) {
+                let (r, g, b, a) = match *pixel {
+                    [l] => (l, l, l, 0xFF),
+                    [l, a] => (l, l, l, a),
+                    [r, g, b] => (r, g, b, 0xFF),
+                    [r, g, b, a] => (r, g, b, a),
+                    _ => return None,
+                };
+                bitmap.extend_from_slice(&[b, g, r, a]);
+            }
+        }
+        Some(Self {
+            bitmap,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ClipboardData;
+
+    // A 3x2 gradient, the odd width exercises the row padding of 24 bits bitmaps
+    fn rgba() -> Vec<u8> {
+        (0..6u8)
+            .flat_map(|i| [i * 40, 255 - i * 40, i * 10, 0x80 + i])
+            .collect()
+    }
+
+    fn png(rgba: &[u8], color: png::ColorType) -> Vec<u8> {
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, 3, 2);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(rgba).unwrap();
+        writer.finish().unwrap();
+        png
+    }
+
+    fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(info.buffer_size());
+        (info.width, info.height, buf)
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let data = ClipboardData::from_png(&png(&rgba(), png::ColorType::Rgba)).unwrap();
+        assert_eq!(data.bitmap_dimensions(), Some((3, 2)));
+        assert_eq!(decode(&data.bitmap_as_png().unwrap()), (3, 2, rgba()));
+    }
+
+    #[test]
+    fn test_24_bits_top_down() {
+        let rgba = rgba();
+        // Rows of 9 bytes padded to 12
+        let mut bitmap = vec![0; 40];
+        bitmap[0] = 40;
+        bitmap[4..8].copy_from_slice(&3i32.to_le_bytes());
+        bitmap[8..12].copy_from_slice(&(-2i32).to_le_bytes());
+        bitmap[14] = 24;
+        for row in rgba.as_chunks::<12>().0 {
+            for pixel in row.as_chunks::<4>().0 {
+                bitmap.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+            bitmap.extend_from_slice(&[0; 3]);
+        }
+        let data = ClipboardData {
+            bitmap,
+            ..Default::default()
+        };
+        assert_eq!(data.bitmap_dimensions(), Some((3, 2)));
+        let opaque: Vec<u8> = rgba
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF])
+            .collect();
+        assert_eq!(decode(&data.bitmap_as_png().unwrap()), (3, 2, opaque));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(ClipboardData::default().bitmap_as_png(), None);
+        let data = ClipboardData {
+            bitmap: vec![40, 0, 0],
+            ..Default::default()
+        };
+        assert_eq!(data.bitmap_dimensions(), None);
+        assert_eq!(ClipboardData::from_png(b"not a png"), None);
+
+        // Truncated pixels
+        let mut data = ClipboardData::from_png(&png(&rgba(), png::ColorType::Rgba)).unwrap();
+        data.bitmap.truncate(50);
+        assert_eq!(data.bitmap_dimensions(), Some((3, 2)));
+        assert_eq!(data.bitmap_as_png(), None);
+    }
+}
@@ -0,0 +1,869 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{codec::Fields, Error};
+
+// Same as Barrier, larger clipboard data is split into multiple chunks
+pub(crate) const CLIPBOARD_CHUNK_SIZE: usize = 32 * 1024;
+
+/// Number of clipboards, 0 is the clipboard and 1 the primary selection of X11
+pub const CLIPBOARD_COUNT: usize = 2;
+
+/// Clipboard data being received for each clipboard. The server may send the chunks of the
+/// clipboard and the selection back to back, each is assembled on its own.
+#[derive(Debug, Default)]
+pub struct ClipboardStage {
+    transfers: [Transfer; CLIPBOARD_COUNT],
+}
+
+/// The chunks of a clipboard received so far
+#[derive(Debug, Default)]
+pub(crate) enum Transfer {
+    #[default]
+    None,
+    Mark1(Staging),
+    Mark2(Staging),
+    /// The data is over the size limit, the rest of the chunks are skipped
+    Discard(usize),
+}
+
+impl Transfer {
+    pub fn mark(&self) -> u8 {
+        match self {
+            Transfer::None => 0,
+            Transfer::Mark1(_) => 1,
+            Transfer::Mark2(_) | Transfer::Discard(_) => 2,
+        }
+    }
+}
+
+impl ClipboardStage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark of the last chunk received for clipboard `id`, 0 if none is being received
+    pub fn stage(&self, id: u8) -> u8 {
+        self.transfers.get(id as usize).map_or(0, Transfer::mark)
+    }
+
+    /// No clipboard is being received
+    pub fn is_idle(&self) -> bool {
+        self.transfers
+            .iter()
+            .all(|transfer| matches!(transfer, Transfer::None))
+    }
+
+    /// `None` for the ids the server doesn't have
+    pub(crate) fn transfer(&mut self, id: u8) -> Option<&mut Transfer> {
+        self.transfers.get_mut(id as usize)
+    }
+}
+
+/// Formats of the clipboard data, with their id on the wire
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum ClipboardFormat {
+    /// UTF-8 text
+    Text = 0,
+    /// UTF-8 HTML fragment
+    Html = 1,
+    /// Device independent bitmap, a BMP file without its file header
+    Bitmap = 2,
+}
+
+// The formats present, indexed by a bit per format
+const FORMATS: [&[ClipboardFormat]; 8] = {
+    use ClipboardFormat::{Bitmap, Html, Text};
+    [
+        &[],
+        &[Text],
+        &[Html],
+        &[Text, Html],
+        &[Bitmap],
+        &[Text, Bitmap],
+        &[Html, Bitmap],
+        &[Text, Html, Bitmap],
+    ]
+};
+
+/// Content of a clipboard, in each of the formats Barrier supports.
+///
+/// Built with [`ClipboardData::builder`], or from a `String` for text only. The serde
+/// representation is stable, the bytes of each format with an empty list for a missing one.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipboardData {
+    pub(crate) text: Vec<u8>,
+    pub(crate) html: Vec<u8>,
+    pub(crate) bitmap: Vec<u8>,
+    // A bit per format cut at the size limit, only with `bounded-clipboard`
+    #[serde(skip)]
+    pub(crate) truncated: u8,
+}
+
+/// Sets the formats of a [`ClipboardData`] one by one, see [`ClipboardData::builder`]
+#[derive(Clone, Debug, Default)]
+pub struct ClipboardDataBuilder {
+    data: ClipboardData,
+}
+
+impl ClipboardDataBuilder {
+    pub fn text<S: Into<String>>(mut self, text: S) -> Self {
+        self.data.text = text.into().into_bytes();
+        self
+    }
+
+    pub fn html<S: Into<String>>(mut self, html: S) -> Self {
+        self.data.html = html.into().into_bytes();
+        self
+    }
+
+    /// A device independent bitmap, see `from_png` with the `image` feature to convert a PNG
+    pub fn bitmap<B: Into<Vec<u8>>>(mut self, bitmap: B) -> Self {
+        self.data.bitmap = bitmap.into();
+        self
+    }
+
+    pub fn build(self) -> ClipboardData {
+        self.data
+    }
+}
+
+impl ClipboardData {
+    pub fn builder() -> ClipboardDataBuilder {
+        ClipboardDataBuilder::default()
+    }
+
+    /// Clipboard with the bytes of each format as sent on the wire, empty for a missing format
+    pub fn from_raw(text: Vec<u8>, html: Vec<u8>, bitmap: Vec<u8>) -> Self {
+        Self {
+            text,
+            html,
+            bitmap,
+            truncated: 0,
+        }
+    }
+
+    /// Formats present, in the order of their id
+    pub fn formats(&self) -> &'static [ClipboardFormat] {
+        let present = [&self.text, &self.html, &self.bitmap]
+            .iter()
+            .enumerate()
+            .filter(|(_, data)| !data.is_empty())
+            .fold(0, |bits, (i, _)| bits | 1 << i);
+        FORMATS[present]
+    }
+
+    /// Total size of the formats, without the framing of the wire
+    pub fn len_bytes(&self) -> usize {
+        self.text.len() + self.html.len() + self.bitmap.len()
+    }
+
+    pub fn raw_text(&self) -> &[u8] {
+        &self.text
+    }
+
+    pub fn raw_html(&self) -> &[u8] {
+        &self.html
+    }
+
+    pub fn text(&self) -> Option<String> {
+        if self.text.is_empty() {
+            None
+        } else {
+            Some(String::from_utf8_lossy(self.text.as_slice()).into_owned())
+        }
+    }
+
+    pub fn html(&self) -> Option<String> {
+        if self.html.is_empty() {
+            None
+        } else {
+            Some(String::from_utf8_lossy(self.html.as_slice()).into_owned())
+        }
+    }
+
+    pub fn bitmap(&self) -> Option<&[u8]> {
+        if self.bitmap.is_empty() {
+            None
+        } else {
+            Some(&self.bitmap)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.html.is_empty() && self.bitmap.is_empty()
+    }
+
+    /// Formats cut at the size limit when received, the text and the HTML end on a whole
+    /// character and a bitmap is dropped. Always empty without `bounded-clipboard`.
+    pub fn truncated(&self) -> &'static [ClipboardFormat] {
+        FORMATS[self.truncated as usize]
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.truncated != 0
+    }
+
+    /// Serializes into the format list sent in DCLP chunks
+    pub(crate) fn marshal(&self) -> Vec<u8> {
+        let formats = [
+            (ClipboardFormat::Text, &self.text),
+            (ClipboardFormat::Html, &self.html),
+            (ClipboardFormat::Bitmap, &self.bitmap),
+        ];
+        let formats = formats.iter().filter(|(_, data)| !data.is_empty());
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(formats.clone().count() as u32).to_be_bytes());
+        for (format, data) in formats {
+            buf.extend_from_slice(&(*format as u32).to_be_bytes());
+            buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            buf.extend_from_slice(data);
+        }
+        buf
+    }
+}
+
+impl From<String> for ClipboardData {
+    fn from(text: String) -> Self {
+        Self::builder().text(text).build()
+    }
+}
+
+impl From<&str> for ClipboardData {
+    fn from(text: &str) -> Self {
+        Self::builder().text(text).build()
+    }
+}
+
+// Only the sizes, the content may be large or private
+impl fmt::Debug for ClipboardData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("ClipboardData");
+        f.field("text", &format_args!("{} bytes", self.text.len()))
+            .field("html", &format_args!("{} bytes", self.html.len()))
+            .field("bitmap", &format_args!("{} bytes", self.bitmap.len()));
+        if self.is_truncated() {
+            f.field("truncated", &self.truncated());
+        }
+        f.finish()
+    }
+}
+
+/// The clipboard received so far, the chunks are put together and parsed once the last one
+/// arrived. A clipboard over the size limit is discarded whole.
+#[cfg(not(feature = "bounded-clipboard"))]
+#[derive(Debug)]
+pub(crate) struct Staging(Vec<u8>);
+
+#[cfg(not(feature = "bounded-clipboard"))]
+impl Staging {
+    /// `None` if the clipboard announced is over the limit
+    pub fn new(expected_size: usize, max_clipboard_size: usize) -> Option<Self> {
+        (expected_size <= max_clipboard_size).then(|| Self(Vec::with_capacity(expected_size)))
+    }
+
+    /// Bytes received so far
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Adds a chunk, `false` if the clipboard goes over the limit
+    pub fn push(&mut self, chunk: &[u8], max_clipboard_size: usize) -> Result<bool, Error> {
+        if self.0.len() + chunk.len() > max_clipboard_size {
+            return Ok(false);
+        }
+        self.0.extend_from_slice(chunk);
+        Ok(true)
+    }
+
+    pub fn finish(self) -> Result<ClipboardData, Error> {
+        parse_clipboard(&self.0)
+    }
+}
+
+/// The clipboard received so far, the formats are parsed as the chunks arrive and each keeps
+/// up to the size limit. Nothing grows past it whatever the server sends.
+#[cfg(feature = "bounded-clipboard")]
+#[derive(Debug)]
+pub(crate) struct Staging {
+    data: ClipboardData,
+    max_format_size: usize,
+    // Bytes received so far, kept or not
+    received: usize,
+    // The fields being read, the number of formats or the id and the length of one
+    header: [u8; 8],
+    header_len: usize,
+    // Formats still to come, `None` until their number has been read
+    formats_left: Option<u32>,
+    // Format being received and the number of its bytes still to come
+    current: Option<(ClipboardFormat, usize)>,
+}
+
+#[cfg(feature = "bounded-clipboard")]
+impl Staging {
+    /// Never `None`, a clipboard over the limit is truncated
+    pub fn new(_expected_size: usize, max_clipboard_size: usize) -> Option<Self> {
+        Some(Self {
+            data: ClipboardData::default(),
+            max_format_size: max_clipboard_size,
+            received: 0,
+            header: [0; 8],
+            header_len: 0,
+            formats_left: None,
+            current: None,
+        })
+    }
+
+    /// Bytes received so far
+    pub fn len(&self) -> usize {
+        self.received
+    }
+
+    /// Adds a chunk, always `true` since nothing is kept over the limit
+    pub fn push(&mut self, mut chunk: &[u8], _max_clipboard_size: usize) -> Result<bool, Error> {
+        self.received += chunk.len();
+        while !chunk.is_empty() {
+            if let Some((format, left)) = &mut self.current {
+                let (data, rest) = chunk.split_at(chunk.len().min(*left));
+                *left -= data.len();
+                let format = *format;
+                if *left == 0 {
+                    self.current = None;
+                }
+                self.keep(format, data);
+                chunk = rest;
+                continue;
+            }
+            // Bytes past the last format are ignored
+            let needed = match self.formats_left {
+                Some(0) => break,
+                Some(_) => 8,
+                None => 4,
+            };
+            let len = (needed - self.header_len).min(chunk.len());
+            self.header[self.header_len..self.header_len + len].copy_from_slice(&chunk[..len]);
+            self.header_len += len;
+            chunk = &chunk[len..];
+            if self.header_len < needed {
+                break;
+            }
+            self.header_len = 0;
+            let mut fields = Fields(&self.header[..needed]);
+            let Some(formats_left) = &mut self.formats_left else {
+                self.formats_left = Some(fields.u32()?);
+                continue;
+            };
+            *formats_left -= 1;
+            let format = match fields.u32()? {
+                0 => ClipboardFormat::Text,
+                1 => ClipboardFormat::Html,
+                2 => ClipboardFormat::Bitmap,
+                _ => Err(Error::FORMAT)?,
+            };
+            let len = fields.u32()? as usize;
+            let max_format_size = self.max_format_size;
+            let kept = self.format_mut(format);
+            kept.reserve_exact(len.min(max_format_size.saturating_sub(kept.len())));
+            self.current = Some((format, len));
+        }
+        Ok(true)
+    }
+
+    /// Truncated data is taken as is, like without `bounded-clipboard`
+    pub fn finish(self) -> Result<ClipboardData, Error> {
+        match self.formats_left {
+            Some(0) => Ok(self.data),
+            _ => Err(Error::FORMAT),
+        }
+    }
+
+    fn format_mut(&mut self, format: ClipboardFormat) -> &mut Vec<u8> {
+        match format {
+            ClipboardFormat::Text => &mut self.data.text,
+            ClipboardFormat::Html => &mut self.data.html,
+            ClipboardFormat::Bitmap => &mut self.data.bitmap,
+        }
+    }
+
+    // Keeps what fits under the limit, the rest of a truncated format is dropped
+    fn keep(&mut self, format: ClipboardFormat, data: &[u8]) {
+        let bit = 1 << format as u8;
+        if self.data.truncated & bit != 0 {
+            return;
+        }
+        let max_format_size = self.max_format_size;
+        let kept = self.format_mut(format);
+        let room = max_format_size.saturating_sub(kept.len());
+        if data.len() <= room {
+            kept.extend_from_slice(data);
+            return;
+        }
+        kept.extend_from_slice(&data[..room]);
+        match format {
+            ClipboardFormat::Text | ClipboardFormat::Html => kept.truncate(whole_chars(kept)),
+            // Can't be decoded without all of its pixels
+            ClipboardFormat::Bitmap => *kept = Vec::new(),
+        }
+        self.data.truncated |= bit;
+    }
+}
+
+/// Length of the UTF-8 `text` without its last character if it has been cut
+#[cfg(feature = "bounded-clipboard")]
+fn whole_chars(text: &[u8]) -> usize {
+    // A character takes 4 bytes at most, it starts with a byte other than 0b10xxxxxx
+    let start = text.len().saturating_sub(4);
+    let Some(last) = text[start..].iter().rposition(|&b| b & 0xC0 != 0x80) else {
+        return text.len();
+    };
+    match core::str::from_utf8(&text[start + last..]) {
+        Err(e) if e.error_len().is_none() => start + last,
+        _ => text.len(),
+    }
+}
+
+#[cfg(not(feature = "bounded-clipboard"))]
+pub(crate) fn parse_clipboard(buf: &[u8]) -> Result<ClipboardData, Error> {
+    let mut fields = Fields(buf);
+    let mut ret = ClipboardData::default();
+    let num_formats = fields.u32()?;
+
+    for _ in 0..num_formats {
+        let format = fields.u32()?;
+        let length = fields.u32()? as usize;
+
+        let format = match format {
+            0 => ClipboardFormat::Text,
+            1 => ClipboardFormat::Html,
+            2 => ClipboardFormat::Bitmap,
+            _ => Err(Error::FORMAT)?,
+        };
+
+        // Truncated data is taken as is
+        let data = fields.take(length.min(fields.0.len()))?;
+        match format {
+            ClipboardFormat::Text => ret.text.extend_from_slice(data),
+            ClipboardFormat::Html => ret.html.extend_from_slice(data),
+            ClipboardFormat::Bitmap => ret.bitmap.extend_from_slice(data),
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClipboardData, ClipboardFormat, ClipboardStage};
+    use crate::{parse_packet, Packet};
+
+    // Parses the packets one after the other, like a client reading them
+    fn parse_all(mut wire: &[u8], max_clipboard_size: usize) -> Vec<Packet> {
+        let mut stage = ClipboardStage::new();
+        let mut packets = vec![];
+        while !wire.is_empty() {
+            let (packet, size) =
+                parse_packet(wire, usize::MAX, &mut stage, max_clipboard_size).unwrap();
+            packets.push(packet);
+            wire = &wire[size..];
+        }
+        packets
+    }
+
+    fn round_trip(id: u8, seq_num: u32, data: ClipboardData) -> (usize, Packet) {
+        round_trip_with_limit(id, seq_num, data, usize::MAX)
+    }
+
+    fn round_trip_with_limit(
+        id: u8,
+        seq_num: u32,
+        data: ClipboardData,
+        max_clipboard_size: usize,
+    ) -> (usize, Packet) {
+        let mut buf = Vec::new();
+        Packet::SetClipboard { id, seq_num, data }.encode(&mut buf);
+        let mut packets = parse_all(&buf, max_clipboard_size);
+        let chunks = packets.len();
+        // Only the last chunk completes the clipboard
+        assert!(packets[..chunks - 1]
+            .iter()
+            .all(|packet| *packet == Packet::ClientNoOp));
+        (chunks, packets.pop().unwrap())
+    }
+
+    #[test]
+    fn test_clipboard_round_trip() {
+        let data = ClipboardData {
+            text: b"Hello".to_vec(),
+            html: b"<b>Hello</b>".to_vec(),
+            ..Default::default()
+        };
+        match round_trip(0, 42, data) {
+            (3, Packet::SetClipboard { id, seq_num, data }) => {
+                assert_eq!(id, 0);
+                assert_eq!(seq_num, 42);
+                assert_eq!(data.text().as_deref(), Some("Hello"));
+                assert_eq!(data.html().as_deref(), Some("<b>Hello</b>"));
+                assert_eq!(data.bitmap(), None);
+            }
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_clipboard_round_trip_chunked() {
+        let data = ClipboardData {
+            text: "x".repeat(40000).into_bytes(),
+            bitmap: (0..80000).map(|i| i as u8).collect(),
+            ..Default::default()
+        };
+        let expected_chunks =
+            2 + (4 + 8 + 40000 + 8 + 80000usize).div_ceil(super::CLIPBOARD_CHUNK_SIZE);
+        match round_trip(1, 7, data) {
+            (chunks, Packet::SetClipboard { id, seq_num, data }) => {
+                assert_eq!(chunks, expected_chunks);
+                assert_eq!(id, 1);
+                assert_eq!(seq_num, 7);
+                assert_eq!(data.raw_text().len(), 40000);
+                assert_eq!(
+                    data.bitmap(),
+                    Some((0..80000).map(|i| i as u8).collect::<Vec<_>>().as_slice())
+                );
+            }
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+
+    // Splits a wire into its packets, size included
+    fn split(mut wire: &[u8]) -> Vec<&[u8]> {
+        let mut packets = vec![];
+        while !wire.is_empty() {
+            let size = 4 + u32::from_be_bytes(wire[..4].try_into().unwrap()) as usize;
+            let (packet, rest) = wire.split_at(size);
+            packets.push(packet);
+            wire = rest;
+        }
+        packets
+    }
+
+    #[test]
+    fn test_interleaved_clipboards() {
+        let clipboard = ClipboardData::from("x".repeat(40000));
+        let selection = ClipboardData::from("selected");
+        let mut wire = vec![];
+        Packet::SetClipboard {
+            id: 0,
+            seq_num: 3,
+            data: clipboard.clone(),
+        }
+        .encode(&mut wire);
+        let mut wire1 = vec![];
+        Packet::SetClipboard {
+            id: 1,
+            seq_num: 3,
+            data: selection.clone(),
+        }
+        .encode(&mut wire1);
+        let (chunks, chunks1) = (split(&wire), split(&wire1));
+        assert_eq!((chunks.len(), chunks1.len()), (4, 3));
+
+        // The selection starts and ends in the middle of the clipboard
+        let interleaved = [
+            chunks[0], chunks1[0], chunks[1], chunks1[1], chunks1[2], chunks[2], chunks[3],
+        ];
+        let mut stage = ClipboardStage::new();
+        let mut packets = vec![];
+        for (i, chunk) in interleaved.into_iter().enumerate() {
+            let (packet, size) = parse_packet(chunk, usize::MAX, &mut stage, usize::MAX).unwrap();
+            assert_eq!(size, chunk.len());
+            if packet != Packet::ClientNoOp {
+                packets.push(packet);
+            }
+            if i == 2 {
+                assert_eq!((stage.stage(0), stage.stage(1)), (2, 1));
+            }
+        }
+        assert_eq!(
+            packets,
+            [
+                Packet::SetClipboard {
+                    id: 1,
+                    seq_num: 3,
+                    data: selection
+                },
+                Packet::SetClipboard {
+                    id: 0,
+                    seq_num: 3,
+                    data: clipboard
+                }
+            ]
+        );
+        assert!(stage.is_idle());
+
+        // A selection sent again from the start leaves the clipboard alone, a third
+        // clipboard doesn't exist
+        let mut wire2 = vec![];
+        Packet::SetClipboard {
+            id: 2,
+            seq_num: 3,
+            data: ClipboardData::from("other"),
+        }
+        .encode(&mut wire2);
+        let chunks2 = split(&wire2);
+        let wire = [
+            chunks[0], chunks[1], chunks1[0], chunks1[1], chunks2[0], chunks2[1], chunks2[2],
+            chunks1[0], chunks[2], chunks[3],
+        ]
+        .concat();
+        let packets = parse_all(&wire, usize::MAX);
+        assert!(packets[..9]
+            .iter()
+            .all(|packet| *packet == Packet::ClientNoOp));
+        assert!(matches!(&packets[9], Packet::SetClipboard { id: 0, .. }));
+        assert_eq!(ClipboardStage::new().stage(2), 0);
+    }
+
+    #[test]
+    fn test_builder() {
+        let data = ClipboardData::builder()
+            .text("Hello")
+            .html(String::from("<b>Hello</b>"))
+            .bitmap(vec![1, 2, 3])
+            .build();
+        assert_eq!(
+            data.formats(),
+            [
+                ClipboardFormat::Text,
+                ClipboardFormat::Html,
+                ClipboardFormat::Bitmap
+            ]
+        );
+        assert_eq!(data.len_bytes(), 5 + 12 + 3);
+        assert_eq!(
+            data,
+            ClipboardData::from_raw(b"Hello".to_vec(), b"<b>Hello</b>".to_vec(), vec![1, 2, 3])
+        );
+        assert_eq!(
+            format!("{data:?}"),
+            "ClipboardData { text: 5 bytes, html: 12 bytes, bitmap: 3 bytes }"
+        );
+
+        let html = ClipboardData::builder().html("<i>x</i>").build();
+        assert_eq!(html.formats(), [ClipboardFormat::Html]);
+        assert_eq!(html.text(), None);
+        assert_eq!(
+            ClipboardData::from("Hello").formats(),
+            [ClipboardFormat::Text]
+        );
+        assert_eq!(
+            ClipboardData::from(String::from("Hello")).text().as_deref(),
+            Some("Hello")
+        );
+        assert!(ClipboardData::default().formats().is_empty());
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let data = ClipboardData::builder()
+            .text("Hello")
+            .bitmap(vec![0xAA])
+            .build();
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(
+            json,
+            r#"{"text":[72,101,108,108,111],"html":[],"bitmap":[170]}"#
+        );
+        assert_eq!(serde_json::from_str::<ClipboardData>(&json).unwrap(), data);
+    }
+
+    #[test]
+    fn test_empty_clipboard_round_trip() {
+        match round_trip(0, 1, ClipboardData::default()) {
+            (_, Packet::SetClipboard { data, .. }) => assert!(data.is_empty()),
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+
+    #[cfg(not(feature = "bounded-clipboard"))]
+    #[test]
+    fn test_clipboard_size_limit() {
+        let data = || ClipboardData {
+            text: b"Hello".to_vec(),
+            ..Default::default()
+        };
+        // Number of formats, format id, length and the text
+        let size = 4 + 4 + 4 + 5;
+        match round_trip_with_limit(0, 1, data(), size) {
+            (_, Packet::SetClipboard { data, .. }) => {
+                assert_eq!(data.text().as_deref(), Some("Hello"))
+            }
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+        match round_trip_with_limit(0, 1, data(), size - 1) {
+            (_, Packet::ClipboardTooLarge { id: 0, size: s }) => assert_eq!(s, size),
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+
+    #[cfg(not(feature = "bounded-clipboard"))]
+    #[test]
+    fn test_clipboard_over_limit_chunked() {
+        let data = |len| ClipboardData {
+            bitmap: vec![0xAA; len],
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        for (seq_num, len) in [(1, 100000), (2, 10)] {
+            Packet::SetClipboard {
+                id: 0,
+                seq_num,
+                data: data(len),
+            }
+            .encode(&mut buf);
+        }
+        let packets: Vec<Packet> = parse_all(&buf, 50000)
+            .into_iter()
+            .filter(|packet| *packet != Packet::ClientNoOp)
+            .collect();
+        // The stream recovers after discarding the large clipboard
+        match packets.as_slice() {
+            [Packet::ClipboardTooLarge { id: 0, size }, Packet::SetClipboard {
+                seq_num: 2, data, ..
+            }] => {
+                assert_eq!(*size, 4 + 8 + 100000);
+                assert_eq!(data.bitmap(), Some([0xAA; 10].as_ref()));
+            }
+            packets => panic!("Unexpected packets {:?}", packets),
+        }
+    }
+
+    #[cfg(feature = "bounded-clipboard")]
+    #[test]
+    fn test_bounded_clipboard() {
+        let data = || ClipboardData {
+            text: b"Hello".to_vec(),
+            html: "é".repeat(100).into_bytes(),
+            bitmap: vec![0xAA; 1000],
+            ..Default::default()
+        };
+        // Each format on its own, the bitmap doesn't take the room of the text
+        match round_trip_with_limit(0, 1, data(), 101) {
+            (_, Packet::SetClipboard { data, .. }) => {
+                assert_eq!(data.text().as_deref(), Some("Hello"));
+                // Without the character cut in half
+                assert_eq!(data.html(), Some("é".repeat(50)));
+                assert_eq!(data.bitmap(), None);
+                assert_eq!(
+                    data.truncated(),
+                    [ClipboardFormat::Html, ClipboardFormat::Bitmap]
+                );
+                assert!(!data.is_empty());
+                assert_eq!(
+                    format!("{data:?}"),
+                    "ClipboardData { text: 5 bytes, html: 100 bytes, bitmap: 0 bytes, truncated: [Html, Bitmap] }"
+                );
+            }
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+        match round_trip_with_limit(0, 1, data(), 1000) {
+            (_, Packet::SetClipboard { data: received, .. }) => {
+                assert!(!received.is_truncated());
+                assert_eq!(received, data());
+            }
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+        match round_trip_with_limit(0, 1, data(), 0) {
+            (_, Packet::SetClipboard { data, .. }) => {
+                assert!(data.is_empty());
+                assert_eq!(data.truncated().len(), 3);
+            }
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+
+    #[cfg(feature = "bounded-clipboard")]
+    #[test]
+    fn test_bounded_clipboard_chunked() {
+        let mut wire = vec![];
+        Packet::SetClipboard {
+            id: 0,
+            seq_num: 1,
+            data: ClipboardData {
+                text: b"x".repeat(100000),
+                bitmap: vec![0xAA; 100000],
+                ..Default::default()
+            },
+        }
+        .encode(&mut wire);
+        Packet::SetClipboard {
+            id: 0,
+            seq_num: 2,
+            data: ClipboardData::from("Hello"),
+        }
+        .encode(&mut wire);
+        Packet::KeepAlive.encode(&mut wire);
+
+        // The bytes dropped are still taken from the stream
+        let mut stage = ClipboardStage::new();
+        let mut packets = vec![];
+        for chunk in split(&wire) {
+            let (packet, size) = parse_packet(chunk, usize::MAX, &mut stage, 50000).unwrap();
+            assert_eq!(size, chunk.len());
+            if packet != Packet::ClientNoOp {
+                packets.push(packet);
+            }
+        }
+        match packets.as_slice() {
+            [Packet::SetClipboard {
+                seq_num: 1, data, ..
+            }, Packet::SetClipboard {
+                seq_num: 2,
+                data: next,
+                ..
+            }, Packet::KeepAlive] => {
+                assert_eq!(data.raw_text(), b"x".repeat(50000));
+                assert_eq!(data.bitmap(), None);
+                assert_eq!(
+                    data.truncated(),
+                    [ClipboardFormat::Text, ClipboardFormat::Bitmap]
+                );
+                assert_eq!(next.text().as_deref(), Some("Hello"));
+                assert!(!next.is_truncated());
+            }
+            packets => panic!("Unexpected packets {:?}", packets),
+        }
+        assert!(stage.is_idle());
+    }
+
+    #[cfg(feature = "bounded-clipboard")]
+    #[test]
+    fn test_staging_byte_by_byte() {
+        let data = ClipboardData::builder()
+            .text("Hello")
+            .html("<b>Hello</b>")
+            .bitmap(vec![1, 2, 3])
+            .build();
+        let marshalled = data.marshal();
+        let mut staging = super::Staging::new(marshalled.len(), 8).unwrap();
+        for byte in &marshalled {
+            assert!(staging.push(&[*byte], 8).unwrap());
+        }
+        assert_eq!(staging.len(), marshalled.len());
+        let received = staging.finish().unwrap();
+        assert_eq!(received.raw_text(), b"Hello");
+        assert_eq!(received.raw_html(), b"<b>Hello");
+        assert_eq!(received.bitmap(), Some([1, 2, 3].as_ref()));
+        assert_eq!(received.truncated(), [ClipboardFormat::Html]);
+
+        // Formats missing
+        let mut staging = super::Staging::new(0, 8).unwrap();
+        staging.push(&marshalled[..20], 8).unwrap();
+        assert!(staging.finish().is_err());
+        let mut staging = super::Staging::new(0, 8).unwrap();
+        // Unknown format
+        assert!(staging
+            .push(&[0, 0, 0, 1, 0, 0, 0, 9, 0, 0, 0, 0], 8)
+            .is_err());
+    }
+}
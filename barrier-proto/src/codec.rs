@@ -0,0 +1,968 @@
+//! Parsing of the packets, each is a big endian u32 size followed by a 4 bytes code and
+//! the fields.
+
+#[cfg(feature = "barrier-options")]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "clipboard")]
+use log::{debug, warn};
+
+#[cfg(feature = "file-transfer")]
+use crate::file::parse_drag_info;
+#[cfg(feature = "clipboard")]
+use crate::{
+    clipboard::{Staging, Transfer},
+    ClipboardStage,
+};
+use crate::{Error, KeyMask, Packet};
+
+/// Fields of a packet read in order, reading past the end means the packet lied about its size
+pub(crate) struct Fields<'a>(pub &'a [u8]);
+
+impl<'a> Fields<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::InsufficientData {
+                needed: len,
+                available: self.0.len(),
+            });
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn i8(&mut self) -> Result<i8, Error> {
+        Ok(self.u8()? as i8)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn i16(&mut self) -> Result<i16, Error> {
+        Ok(i16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+}
+
+/// Checks the size read in front of a packet, returns the number of bytes that follow it
+pub fn packet_size(size: u32, max_packet_size: usize) -> Result<usize, Error> {
+    let size = size as usize;
+    if size < 4 {
+        return Err(Error::PacketTooSmall(size));
+    }
+    if size > max_packet_size {
+        return Err(Error::PacketTooLarge(size));
+    }
+    Ok(size)
+}
+
+/// Parses the first packet in `buf`, returns it with the number of bytes it took.
+///
+/// [`Error::Incomplete`] says how many bytes are needed, the size is checked against
+/// `max_packet_size` as soon as it has been received. Any other error leaves the stream out
+/// of sync.
+pub fn parse_packet(
+    buf: &[u8],
+    max_packet_size: usize,
+    #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
+    #[cfg(feature = "clipboard")] max_clipboard_size: usize,
+) -> Result<(Packet, usize), Error> {
+    let Some(size) = buf.first_chunk() else {
+        return Err(Error::Incomplete { needed: 4 });
+    };
+    let size = packet_size(u32::from_be_bytes(*size), max_packet_size)?;
+    // Only overflows on 16 and 32 bits targets
+    let needed = size.saturating_add(4);
+    let Some(packet) = buf.get(4..needed) else {
+        return Err(Error::Incomplete { needed });
+    };
+    let packet = parse_body(
+        packet,
+        #[cfg(feature = "clipboard")]
+        clipboard_stage,
+        #[cfg(feature = "clipboard")]
+        max_clipboard_size,
+    )?;
+    Ok((packet, needed))
+}
+
+/// Code of the first packet in `buf` if it has been received completely
+pub fn peek_code(buf: &[u8]) -> Option<[u8; 4]> {
+    let size = u32::from_be_bytes(*buf.first_chunk()?) as usize;
+    if size < 4 {
+        return None;
+    }
+    buf.get(4..size.saturating_add(4))?.first_chunk().copied()
+}
+
+/// Codes of the packets the client understands, with any feature
+const KNOWN_CODES: [&[u8; 4]; 31] = [
+    b"QINF", b"CIAK", b"CALV", b"CNOP", b"DINF", b"CROP", b"DSOP", b"EUNK", b"EBSY", b"EBAD",
+    b"EICV", b"CBYE", b"DMMV", b"DMRM", b"CINN", b"COUT", b"CSEC", b"CCLP", b"DCLP", b"DMUP",
+    b"DMDN", b"DKUP", b"DKDN", b"DKRP", b"DMWM", b"DFTR", b"DDRG", b"DGBT", b"DGST", b"DGTR",
+    b"LSYN",
+];
+
+/// Whether `code` can be a packet code at all, they are made of ASCII upper case letters and
+/// digits. Anything else means the size in front of it was not a packet size.
+pub fn is_plausible_code(code: &[u8; 4]) -> bool {
+    code.iter()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+/// Offset of the first known packet in `buf`, a size from 4 to `max_packet_size` followed by
+/// the code of a packet the client understands. Used to skip the garbage read once the
+/// stream is out of sync, `None` if there is none yet.
+pub fn find_packet(buf: &[u8], max_packet_size: usize) -> Option<usize> {
+    buf.windows(8).position(|window| {
+        let size = u32::from_be_bytes(window[..4].try_into().unwrap());
+        let code: &[u8; 4] = window[4..].try_into().unwrap();
+        packet_size(size, max_packet_size).is_ok() && KNOWN_CODES.contains(&code)
+    })
+}
+
+/// Parses a packet without its size, bytes after the known fields are ignored.
+///
+/// Clipboard chunks are collected in `clipboard_stage`, the last one returns the whole
+/// clipboard and the others `ClientNoOp`.
+pub fn parse_body(
+    packet: &[u8],
+    #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
+    #[cfg(feature = "clipboard")] max_clipboard_size: usize,
+) -> Result<Packet, Error> {
+    let mut fields = Fields(packet);
+    let code: [u8; 4] = fields.array()?;
+    parse_fields(
+        code,
+        fields,
+        #[cfg(feature = "clipboard")]
+        clipboard_stage,
+        #[cfg(feature = "clipboard")]
+        max_clipboard_size,
+    )
+    .map_err(|e| e.with_code(code))
+}
+
+fn parse_fields(
+    code: [u8; 4],
+    mut fields: Fields,
+    #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
+    #[cfg(feature = "clipboard")] max_clipboard_size: usize,
+) -> Result<Packet, Error> {
+    let packet = match code.as_ref() {
+        b"QINF" => Packet::QueryInfo,
+        b"CIAK" => Packet::InfoAck,
+        b"CALV" => Packet::KeepAlive,
+        b"CNOP" => Packet::ClientNoOp,
+        b"DINF" => {
+            let mut values = [0u16; 7];
+            for value in values.iter_mut() {
+                *value = fields.u16()?;
+            }
+            let [x, y, w, h, _dummy, mx, my] = values;
+            Packet::DeviceInfo {
+                x,
+                y,
+                w,
+                h,
+                _dummy,
+                mx,
+                my,
+            }
+        }
+        #[cfg(feature = "barrier-options")]
+        b"CROP" => Packet::ResetOptions,
+        #[cfg(feature = "barrier-options")]
+        b"DSOP" => {
+            let num_items = fields.u32()?;
+            let num_opts = num_items / 2;
+            let mut options = BTreeMap::new();
+            for _ in 0..num_opts {
+                let opt: [u8; 4] = fields.array()?;
+                let val = fields.u32()?;
+                options.insert(String::from_utf8_lossy(&opt).into_owned(), val);
+            }
+            Packet::SetDeviceOptions(options)
+        }
+        b"EUNK" => Packet::ErrorUnknownDevice,
+        b"EBSY" => Packet::ErrorBusy,
+        b"EBAD" => Packet::ErrorBadProtocol,
+        b"EICV" => Packet::ErrorIncompatibleVersion {
+            major: fields.u16()?,
+            minor: fields.u16()?,
+        },
+        b"CBYE" => Packet::Close,
+        b"DMMV" => Packet::MouseMoveAbs {
+            x: fields.u16()?,
+            y: fields.u16()?,
+        },
+        b"DMRM" => Packet::MouseMove {
+            x: fields.i16()?,
+            y: fields.i16()?,
+        },
+        b"CINN" => Packet::CursorEnter {
+            x: fields.u16()?,
+            y: fields.u16()?,
+            seq_num: fields.u32()?,
+            mask: KeyMask::from_bits_retain(fields.u16()?),
+        },
+        b"COUT" => Packet::CursorLeave,
+        b"CSEC" => Packet::ScreenSaver {
+            on: fields.u8()? != 0,
+        },
+        b"DGBT" => Packet::GameButtons {
+            id: fields.u8()?,
+            buttons: fields.u16()?,
+        },
+        b"DGST" => Packet::GameSticks {
+            id: fields.u8()?,
+            x1: fields.i16()?,
+            y1: fields.i16()?,
+            x2: fields.i16()?,
+            y2: fields.i16()?,
+        },
+        b"DGTR" => Packet::GameTriggers {
+            id: fields.u8()?,
+            t1: fields.u8()?,
+            t2: fields.u8()?,
+        },
+        b"CCLP" => Packet::GrabClipboard {
+            id: fields.u8()?,
+            seq_num: fields.u32()?,
+        },
+        #[cfg(feature = "clipboard")]
+        b"DCLP" => parse_clipboard_chunk(fields, clipboard_stage, max_clipboard_size)?,
+        b"DMUP" => Packet::MouseUp { id: fields.i8()? },
+        b"DMDN" => Packet::MouseDown { id: fields.i8()? },
+        b"DKUP" => Packet::KeyUp {
+            id: fields.u16()?,
+            mask: KeyMask::from_bits_retain(fields.u16()?),
+            button: fields.u16()?,
+        },
+        b"DKDN" => Packet::KeyDown {
+            id: fields.u16()?,
+            mask: KeyMask::from_bits_retain(fields.u16()?),
+            button: fields.u16()?,
+        },
+        b"DKRP" => {
+            let id = fields.u16()?;
+            let mask = KeyMask::from_bits_retain(fields.u16()?);
+            let count = fields.u16()?;
+            let button = fields.u16()?;
+            Packet::KeyRepeat {
+                id,
+                mask,
+                button,
+                count,
+            }
+        }
+        b"DMWM" => Packet::MouseWheel {
+            x_delta: fields.i16()?,
+            y_delta: fields.i16()?,
+        },
+        #[cfg(feature = "file-transfer")]
+        b"DFTR" => {
+            let mark = fields.u8()?;
+            let len = fields.u32()? as usize;
+            Packet::FileChunk {
+                mark,
+                data: fields.take(len)?.to_vec(),
+            }
+        }
+        #[cfg(feature = "file-transfer")]
+        b"DDRG" => {
+            let count = fields.u16()?;
+            let len = fields.u32()? as usize;
+            Packet::DragInfo {
+                files: parse_drag_info(count, fields.take(len)?),
+            }
+        }
+        #[cfg(feature = "alloc")]
+        b"LSYN" => {
+            let len = fields.u32()? as usize;
+            Packet::LanguageSync {
+                languages: parse_languages(fields.take(len)?),
+            }
+        }
+        _ => Packet::Unknown(code),
+    };
+    Ok(packet)
+}
+
+/// The languages of LSYN are two letter codes put together, e.g. "enfr"
+#[cfg(feature = "alloc")]
+fn parse_languages(list: &[u8]) -> Vec<String> {
+    list.chunks(2)
+        .map(|code| String::from_utf8_lossy(code).into_owned())
+        .collect()
+}
+
+#[cfg(feature = "clipboard")]
+fn parse_clipboard_chunk(
+    mut fields: Fields,
+    clipboard_stage: &mut ClipboardStage,
+    max_clipboard_size: usize,
+) -> Result<Packet, Error> {
+    let id = fields.u8()?;
+    let seq_num = fields.u32()?;
+    let mark = fields.u8()?;
+    let len = fields.u32()? as usize;
+    let chunk = fields.take(len)?;
+    debug!("Chunk: {id}, {mark} {len}");
+    let Some(transfer) = clipboard_stage.transfer(id) else {
+        warn!("Chunk of unknown clipboard {id}, ignored");
+        return Ok(Packet::ClientNoOp);
+    };
+
+    // mark 1 is the total length string in ASCII
+    // mark 2 is the actual data and is split into chunks
+    // mark 3 is an empty chunk
+    let (next, packet) = match (mark, core::mem::take(transfer)) {
+        // A new sequence drops any incomplete one of the same clipboard
+        (1, _) => {
+            // The length of usize::MAX in decimal
+            if len > 20 {
+                return Err(Error::FORMAT);
+            }
+            let expected_size = String::from_utf8_lossy(chunk)
+                .parse::<usize>()
+                .map_err(|_| Error::FORMAT)?;
+            debug!("Expected clipboard size: {}", expected_size);
+            match Staging::new(expected_size, max_clipboard_size) {
+                Some(data) => (Transfer::Mark1(data), Packet::ClientNoOp),
+                None => (Transfer::Discard(0), Packet::ClientNoOp),
+            }
+        }
+        (2, Transfer::Mark1(mut data) | Transfer::Mark2(mut data)) => {
+            if data.push(chunk, max_clipboard_size)? {
+                (Transfer::Mark2(data), Packet::ClientNoOp)
+            } else {
+                debug!("Clipboard size limit exceeded, discarding");
+                (Transfer::Discard(data.len() + len), Packet::ClientNoOp)
+            }
+        }
+        (2, Transfer::Discard(size)) => (Transfer::Discard(size + len), Packet::ClientNoOp),
+        (3, Transfer::Mark1(data) | Transfer::Mark2(data)) => {
+            let packet = Packet::SetClipboard {
+                id,
+                seq_num,
+                data: data.finish()?,
+            };
+            (Transfer::None, packet)
+        }
+        (3, Transfer::Discard(size)) => (Transfer::None, Packet::ClipboardTooLarge { id, size }),
+        (mark, transfer) => {
+            warn!(
+                "Unexpected clipboard {} stage transition from {} to {}",
+                id,
+                transfer.mark(),
+                mark
+            );
+            (Transfer::None, Packet::ClientNoOp)
+        }
+    };
+    *transfer = next;
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*, strategy::Union};
+
+    use super::{
+        find_packet, is_plausible_code, packet_size, parse_body, parse_packet, peek_code,
+        KNOWN_CODES,
+    };
+    use crate::{encode_packet, Error, KeyMask, Packet};
+
+    /// Parses a packet as received, with its size
+    fn parse(wire: &[u8]) -> Result<Packet, Error> {
+        let size = u32::from_be_bytes(wire[..4].try_into().unwrap());
+        packet_size(size, usize::MAX)?;
+        let packet = wire.get(4..4 + size as usize).ok_or(Error::FORMAT)?;
+        parse_body(
+            packet,
+            #[cfg(feature = "clipboard")]
+            &mut crate::ClipboardStage::new(),
+            #[cfg(feature = "clipboard")]
+            usize::MAX,
+        )
+    }
+
+    fn parse_first(buf: &[u8]) -> Result<(Packet, usize), Error> {
+        parse_packet(
+            buf,
+            usize::MAX,
+            #[cfg(feature = "clipboard")]
+            &mut crate::ClipboardStage::new(),
+            #[cfg(feature = "clipboard")]
+            usize::MAX,
+        )
+    }
+
+    fn wire(size: u32, code: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        [&size.to_be_bytes()[..], code, payload].concat()
+    }
+
+    /// Every packet of fixed size with its payload on the wire
+    fn packets() -> Vec<(Packet, &'static [u8; 4], Vec<u8>)> {
+        vec![
+            (Packet::QueryInfo, b"QINF", vec![]),
+            (
+                Packet::DeviceInfo {
+                    x: 0,
+                    y: 0,
+                    w: 1920,
+                    h: 1080,
+                    _dummy: 0,
+                    mx: 5,
+                    my: 6,
+                },
+                b"DINF",
+                vec![0, 0, 0, 0, 7, 128, 4, 56, 0, 0, 0, 5, 0, 6],
+            ),
+            (Packet::InfoAck, b"CIAK", vec![]),
+            (Packet::KeepAlive, b"CALV", vec![]),
+            (Packet::ClientNoOp, b"CNOP", vec![]),
+            #[cfg(feature = "barrier-options")]
+            (Packet::ResetOptions, b"CROP", vec![]),
+            (Packet::ErrorUnknownDevice, b"EUNK", vec![]),
+            (Packet::ErrorBusy, b"EBSY", vec![]),
+            (Packet::ErrorBadProtocol, b"EBAD", vec![]),
+            (
+                Packet::ErrorIncompatibleVersion { major: 1, minor: 8 },
+                b"EICV",
+                vec![0, 1, 0, 8],
+            ),
+            (Packet::Close, b"CBYE", vec![]),
+            (
+                Packet::MouseMoveAbs { x: 1, y: 0x102 },
+                b"DMMV",
+                vec![0, 1, 1, 2],
+            ),
+            (
+                Packet::MouseMove { x: -3, y: 4 },
+                b"DMRM",
+                vec![0xFF, 0xFD, 0, 4],
+            ),
+            (
+                Packet::CursorEnter {
+                    x: 1,
+                    y: 2,
+                    seq_num: 3,
+                    mask: KeyMask::ALT,
+                },
+                b"CINN",
+                vec![0, 1, 0, 2, 0, 0, 0, 3, 0, 4],
+            ),
+            (Packet::CursorLeave, b"COUT", vec![]),
+            (Packet::ScreenSaver { on: true }, b"CSEC", vec![1]),
+            (Packet::ScreenSaver { on: false }, b"CSEC", vec![0]),
+            (
+                Packet::GrabClipboard { id: 1, seq_num: 7 },
+                b"CCLP",
+                vec![1, 0, 0, 0, 7],
+            ),
+            (Packet::MouseUp { id: 1 }, b"DMUP", vec![1]),
+            (Packet::MouseDown { id: 3 }, b"DMDN", vec![3]),
+            (
+                Packet::KeyUp {
+                    id: 0x61,
+                    mask: KeyMask::SHIFT,
+                    button: 38,
+                },
+                b"DKUP",
+                vec![0, 0x61, 0, 1, 0, 38],
+            ),
+            (
+                Packet::KeyDown {
+                    id: 0x61,
+                    mask: KeyMask::SHIFT,
+                    button: 38,
+                },
+                b"DKDN",
+                vec![0, 0x61, 0, 1, 0, 38],
+            ),
+            (
+                Packet::KeyRepeat {
+                    id: 0x61,
+                    mask: KeyMask::SHIFT,
+                    button: 38,
+                    count: 2,
+                },
+                b"DKRP",
+                // The count comes before the button
+                vec![0, 0x61, 0, 1, 0, 2, 0, 38],
+            ),
+            (
+                Packet::MouseWheel {
+                    x_delta: 0,
+                    y_delta: -120,
+                },
+                b"DMWM",
+                vec![0, 0, 0xFF, 0x88],
+            ),
+            (
+                Packet::GameButtons {
+                    id: 0,
+                    buttons: 0x1001,
+                },
+                b"DGBT",
+                vec![0, 0x10, 0x01],
+            ),
+            (
+                Packet::GameSticks {
+                    id: 1,
+                    x1: -32768,
+                    y1: 32767,
+                    x2: 0,
+                    y2: -2,
+                },
+                b"DGST",
+                vec![1, 0x80, 0, 0x7F, 0xFF, 0, 0, 0xFF, 0xFE],
+            ),
+            (
+                Packet::GameTriggers {
+                    id: 0,
+                    t1: 255,
+                    t2: 3,
+                },
+                b"DGTR",
+                vec![0, 255, 3],
+            ),
+            #[cfg(feature = "file-transfer")]
+            (
+                Packet::FileChunk {
+                    mark: 2,
+                    data: vec![1, 2, 3],
+                },
+                b"DFTR",
+                vec![2, 0, 0, 0, 3, 1, 2, 3],
+            ),
+            #[cfg(feature = "file-transfer")]
+            (
+                Packet::DragInfo {
+                    files: vec!["/a".into(), "b".into()],
+                },
+                b"DDRG",
+                vec![0, 2, 0, 0, 0, 5, b'/', b'a', b',', b'b', b','],
+            ),
+            #[cfg(feature = "alloc")]
+            (
+                Packet::LanguageSync {
+                    languages: vec!["en".into(), "fr".into()],
+                },
+                b"LSYN",
+                vec![0, 0, 0, 4, b'e', b'n', b'f', b'r'],
+            ),
+            (Packet::Unknown(*b"XXXX"), b"XXXX", vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_packets() {
+        for (packet, code, payload) in packets() {
+            let expected = wire(4 + payload.len() as u32, code, &payload);
+            let mut buf = [0; 32];
+            let len = encode_packet(&packet, &mut buf);
+            assert_eq!(&buf[..len], expected, "{packet:?}");
+            assert_eq!(parse_first(&expected), Ok((packet, len)));
+        }
+    }
+
+    #[test]
+    fn test_incomplete() {
+        for (_, code, payload) in packets() {
+            let mut buf = wire(4 + payload.len() as u32, code, &payload);
+            let len = buf.len();
+            for received in 0..len {
+                let needed = if received < 4 { 4 } else { len };
+                assert_eq!(
+                    parse_first(&buf[..received]),
+                    Err(Error::Incomplete { needed })
+                );
+                assert_eq!(peek_code(&buf[..received]), None);
+            }
+            // The next packet is left alone
+            buf.extend(wire(4, b"CALV", &[]));
+            assert_eq!(parse_first(&buf).unwrap().1, len);
+            assert_eq!(peek_code(&buf), Some(*code));
+        }
+    }
+
+    #[test]
+    fn test_find_packet() {
+        for (packet, code, payload) in packets() {
+            assert!(is_plausible_code(code));
+            let mut buf = vec![0xFF, b'D', 0, 0];
+            buf.extend(wire(4 + payload.len() as u32, code, &payload));
+            let expected = match packet {
+                Packet::Unknown(_) => None,
+                _ => Some(4),
+            };
+            assert_eq!(find_packet(&buf, usize::MAX), expected, "{packet:?}");
+        }
+        // The code after a sane size has to be known
+        let mut buf = wire(4, b"ABCD", &[]);
+        buf.extend(wire(4, b"CALV", &[]));
+        assert_eq!(find_packet(&buf, usize::MAX), Some(8));
+        // As well as the size
+        assert_eq!(find_packet(&wire(9, b"CALV", &[]), 8), None);
+        assert_eq!(find_packet(&wire(3, b"CALV", &[]), 8), None);
+        assert_eq!(find_packet(b"\0\0\0\x04CAL", 8), None);
+
+        for code in [b"DMM\0", b"dmmv", b"DM V", b"\xffMMV"] {
+            assert!(!is_plausible_code(code));
+        }
+        assert!(is_plausible_code(b"LSY2"));
+    }
+
+    #[test]
+    fn test_encode_too_small() {
+        let packet = Packet::MouseMoveAbs { x: 1, y: 2 };
+        let mut buf = [0xAA; 6];
+        assert_eq!(encode_packet(&packet, &mut buf), 12);
+        assert_eq!(buf, [0, 0, 0, 8, b'D', b'M']);
+        assert_eq!(encode_packet(&packet, &mut []), 12);
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[test]
+    fn test_encode_decoder_only() {
+        let packet = Packet::ClipboardTooLarge { id: 0, size: 1 };
+        let mut buf = [0xAA; 4];
+        assert_eq!(encode_packet(&packet, &mut buf), 0);
+        assert_eq!(buf, [0xAA; 4]);
+        let mut out = vec![];
+        packet.encode(&mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_game_controller() {
+        // A pad pressing A, pushing the left stick up left and half pulling the right trigger
+        let capture: &[u8] = &[
+            0, 0, 0, 7, b'D', b'G', b'B', b'T', 0, 0x10, 0x00, //
+            0, 0, 0, 13, b'D', b'G', b'S', b'T', 0, 0xC0, 0x01, 0x7F, 0xFF, 0, 0, 0, 0, //
+            0, 0, 0, 7, b'D', b'G', b'T', b'R', 0, 0, 0x80, //
+            0, 0, 0, 7, b'D', b'G', b'B', b'T', 0, 0, 0,
+        ];
+        let mut packets = vec![];
+        let mut buf = capture;
+        while !buf.is_empty() {
+            let (packet, len) = parse_first(buf).unwrap();
+            assert!(packet.is_input());
+            packets.push(packet);
+            buf = &buf[len..];
+        }
+        assert_eq!(
+            packets,
+            [
+                Packet::GameButtons {
+                    id: 0,
+                    buttons: 0x1000
+                },
+                Packet::GameSticks {
+                    id: 0,
+                    x1: -16383,
+                    y1: 32767,
+                    x2: 0,
+                    y2: 0
+                },
+                Packet::GameTriggers {
+                    id: 0,
+                    t1: 0,
+                    t2: 128
+                },
+                Packet::GameButtons { id: 0, buttons: 0 },
+            ]
+        );
+    }
+
+    #[cfg(feature = "barrier-options")]
+    #[test]
+    fn test_options() {
+        let payload = [
+            &[0, 0, 0, 4][..],
+            b"HBRT",
+            &[0, 0, 0x13, 0x88],
+            b"SCSS",
+            &[0, 0, 0, 1],
+        ]
+        .concat();
+        let expected = wire(4 + payload.len() as u32, b"DSOP", &payload);
+        let options = [("HBRT".into(), 5000), ("SCSS".into(), 1)].into();
+        let packet = Packet::SetDeviceOptions(options);
+        assert_eq!(parse(&expected).unwrap(), packet);
+        let mut buf = vec![];
+        packet.encode(&mut buf);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(packet_size(3, 100), Err(Error::PacketTooSmall(3)));
+        assert_eq!(
+            packet_size(u32::MAX, 100),
+            Err(Error::PacketTooLarge(0xFFFFFFFF))
+        );
+        assert_eq!(packet_size(100, 100).unwrap(), 100);
+        // Checked before the packet is received
+        assert_eq!(
+            parse_packet(
+                &wire(16, b"DMMV", &[]),
+                15,
+                #[cfg(feature = "clipboard")]
+                &mut crate::ClipboardStage::new(),
+                #[cfg(feature = "clipboard")]
+                usize::MAX,
+            ),
+            Err(Error::PacketTooLarge(16))
+        );
+        assert_eq!(
+            parse_first(&wire(2, b"DMMV", &[])),
+            Err(Error::PacketTooSmall(2))
+        );
+    }
+
+    #[test]
+    fn test_lying_size() {
+        // Shorter than the fields
+        assert_eq!(
+            parse(&wire(6, b"DMMV", &[0, 1])),
+            Err(Error::InsufficientData {
+                needed: 2,
+                available: 0
+            })
+        );
+        // Tagged with the code of the packet
+        #[cfg(feature = "clipboard")]
+        assert_eq!(
+            parse(&wire(15, b"DCLP", &[0, 0, 0, 0, 0, 1, 0, 0, 0, 1, b'x'])),
+            Err(Error::Format {
+                code: Some(*b"DCLP")
+            })
+        );
+        // Bytes past the fields are ignored
+        assert_eq!(
+            parse(&wire(10, b"DMMV", &[0, 1, 0, 2, 0xFF, 0xFF])).unwrap(),
+            Packet::MouseMoveAbs { x: 1, y: 2 }
+        );
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let codes: [&[u8; 4]; 22] = [
+            b"DINF", b"DSOP", b"EICV", b"DMMV", b"DMRM", b"CINN", b"CSEC", b"CCLP", b"DCLP",
+            b"DMUP", b"DMDN", b"DKUP", b"DKDN", b"DKRP", b"DMWM", b"DFTR", b"DDRG", b"DGBT",
+            b"DGST", b"DGTR", b"LSYN", b"XXXX",
+        ];
+        // xorshift, the same sequence on every run
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for code in codes {
+            for _ in 0..200 {
+                let payload: Vec<u8> = (0..random() % 40).map(|_| random() as u8).collect();
+                // Sizes around the actual one, including some way off
+                let size = match random() % 4 {
+                    0 => random() as u32,
+                    _ => (payload.len() as u32 + 4 + (random() % 16) as u32).saturating_sub(8),
+                };
+                // Any result but a panic
+                let _ = parse(&wire(size, code, &payload));
+                let _ = parse_first(&wire(size, code, &payload));
+            }
+        }
+    }
+
+    /// Any packet with the values its fields can take on the wire
+    fn any_packet() -> impl Strategy<Value = Packet> {
+        let mask = || any::<u16>().prop_map(KeyMask::from_bits_retain);
+        #[allow(unused_mut)]
+        let mut packets = vec![
+            Just(Packet::QueryInfo).boxed(),
+            any::<[u16; 7]>()
+                .prop_map(|[x, y, w, h, _dummy, mx, my]| Packet::DeviceInfo {
+                    x,
+                    y,
+                    w,
+                    h,
+                    _dummy,
+                    mx,
+                    my,
+                })
+                .boxed(),
+            Just(Packet::InfoAck).boxed(),
+            Just(Packet::KeepAlive).boxed(),
+            Just(Packet::ClientNoOp).boxed(),
+            Just(Packet::ErrorUnknownDevice).boxed(),
+            Just(Packet::ErrorBusy).boxed(),
+            Just(Packet::ErrorBadProtocol).boxed(),
+            any::<(u16, u16)>()
+                .prop_map(|(major, minor)| Packet::ErrorIncompatibleVersion { major, minor })
+                .boxed(),
+            Just(Packet::Close).boxed(),
+            any::<(u8, u32)>()
+                .prop_map(|(id, seq_num)| Packet::GrabClipboard { id, seq_num })
+                .boxed(),
+            (any::<(u16, u16, u32)>(), mask())
+                .prop_map(|((x, y, seq_num), mask)| Packet::CursorEnter {
+                    x,
+                    y,
+                    seq_num,
+                    mask,
+                })
+                .boxed(),
+            any::<i8>().prop_map(|id| Packet::MouseUp { id }).boxed(),
+            any::<i8>().prop_map(|id| Packet::MouseDown { id }).boxed(),
+            (any::<(u16, u16)>(), mask())
+                .prop_map(|((id, button), mask)| Packet::KeyUp { id, mask, button })
+                .boxed(),
+            (any::<(u16, u16)>(), mask())
+                .prop_map(|((id, button), mask)| Packet::KeyDown { id, mask, button })
+                .boxed(),
+            (any::<(u16, u16, u16)>(), mask())
+                .prop_map(|((id, button, count), mask)| Packet::KeyRepeat {
+                    id,
+                    mask,
+                    button,
+                    count,
+                })
+                .boxed(),
+            any::<(i16, i16)>()
+                .prop_map(|(x_delta, y_delta)| Packet::MouseWheel { x_delta, y_delta })
+                .boxed(),
+            Just(Packet::CursorLeave).boxed(),
+            any::<(u16, u16)>()
+                .prop_map(|(x, y)| Packet::MouseMoveAbs { x, y })
+                .boxed(),
+            any::<(i16, i16)>()
+                .prop_map(|(x, y)| Packet::MouseMove { x, y })
+                .boxed(),
+            any::<bool>()
+                .prop_map(|on| Packet::ScreenSaver { on })
+                .boxed(),
+            any::<(u8, u16)>()
+                .prop_map(|(id, buttons)| Packet::GameButtons { id, buttons })
+                .boxed(),
+            any::<(u8, [i16; 4])>()
+                .prop_map(|(id, [x1, y1, x2, y2])| Packet::GameSticks { id, x1, y1, x2, y2 })
+                .boxed(),
+            any::<(u8, u8, u8)>()
+                .prop_map(|(id, t1, t2)| Packet::GameTriggers { id, t1, t2 })
+                .boxed(),
+            // The codes the client doesn't know, whatever its features
+            any::<[u8; 4]>()
+                .prop_filter("known code", |code| !KNOWN_CODES.contains(&code))
+                .prop_map(Packet::Unknown)
+                .boxed(),
+        ];
+        #[cfg(feature = "barrier-options")]
+        packets.extend([
+            Just(Packet::ResetOptions).boxed(),
+            // The names are 4 characters on the wire
+            collection::btree_map("[A-Z0-9]{4}", any::<u32>(), 0..8)
+                .prop_map(Packet::SetDeviceOptions)
+                .boxed(),
+        ]);
+        #[cfg(feature = "clipboard")]
+        {
+            let bytes = || collection::vec(any::<u8>(), 0..64);
+            // Also larger than a chunk, it's split in several packets
+            let bitmap = prop_oneof![
+                bytes(),
+                (any::<u8>(), 0..64usize).prop_map(|(b, extra)| vec![
+                    b;
+                    crate::CLIPBOARD_CHUNK_SIZE
+                        + extra
+                ]),
+            ];
+            packets.push(
+                (
+                    0..crate::CLIPBOARD_COUNT as u8,
+                    any::<u32>(),
+                    bytes(),
+                    bytes(),
+                    bitmap,
+                )
+                    .prop_map(|(id, seq_num, text, html, bitmap)| Packet::SetClipboard {
+                        id,
+                        seq_num,
+                        data: crate::ClipboardData::from_raw(text, html, bitmap),
+                    })
+                    .boxed(),
+            );
+        }
+        #[cfg(feature = "file-transfer")]
+        packets.extend([
+            (any::<u8>(), collection::vec(any::<u8>(), 0..64))
+                .prop_map(|(mark, data)| Packet::FileChunk { mark, data })
+                .boxed(),
+            // The paths are separated by commas, empty ones are skipped
+            collection::vec("[^,]{1,16}", 0..4)
+                .prop_map(|files| Packet::DragInfo { files })
+                .boxed(),
+        ]);
+        #[cfg(feature = "alloc")]
+        packets.push(
+            collection::vec("[a-z]{2}", 0..4)
+                .prop_map(|languages| Packet::LanguageSync { languages })
+                .boxed(),
+        );
+        Union::new(packets)
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(packet in any_packet()) {
+            let mut wire = vec![0; encode_packet(&packet, &mut [])];
+            encode_packet(&packet, &mut wire);
+
+            // Read back like a client does, the clipboard chunks but the last are no-ops
+            #[cfg(feature = "clipboard")]
+            let mut stage = crate::ClipboardStage::new();
+            let mut packets = vec![];
+            let mut buf = &wire[..];
+            while !buf.is_empty() {
+                let (packet, len) = parse_packet(
+                    buf,
+                    usize::MAX,
+                    #[cfg(feature = "clipboard")]
+                    &mut stage,
+                    #[cfg(feature = "clipboard")]
+                    usize::MAX,
+                )
+                .unwrap();
+                // Exactly the size written in front of it
+                prop_assert_eq!(len, 4 + u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize);
+                packets.push(packet);
+                buf = &buf[len..];
+            }
+            let last = packets.pop();
+            prop_assert!(packets.iter().all(|p| *p == Packet::ClientNoOp), "{:?}", packets);
+            prop_assert_eq!(last, Some(packet));
+        }
+    }
+}
@@ -0,0 +1,42 @@
+/// The coordinate `current` moved by `delta` as sent with DMRM, saturated at 0 and at
+/// `max`, the last pixel of the screen. A position already past `max` ends up at `max`.
+pub fn clamp_move(current: u16, delta: i16, max: u16) -> u16 {
+    (current as i32 + delta as i32).clamp(0, max as i32) as u16
+}
+
+#[cfg(test)]
+mod test {
+    use super::clamp_move;
+
+    #[test]
+    fn test_clamp_move() {
+        assert_eq!(clamp_move(10, -3, 1919), 7);
+        assert_eq!(clamp_move(10, 3, 1919), 13);
+
+        // Left or up from the origin stays there instead of wrapping to the far edge
+        assert_eq!(clamp_move(0, -1, 1919), 0);
+        assert_eq!(clamp_move(0, i16::MIN, 1919), 0);
+
+        // And right or down from the last pixel
+        assert_eq!(clamp_move(1919, 1, 1919), 1919);
+        assert_eq!(clamp_move(1919, i16::MAX, 1919), 1919);
+        assert_eq!(clamp_move(1919, -1, 1919), 1918);
+
+        // Past the range of i16 and u16 when added up
+        assert_eq!(clamp_move(u16::MAX, i16::MAX, u16::MAX), u16::MAX);
+        assert_eq!(clamp_move(40000, i16::MAX, u16::MAX), u16::MAX);
+        assert_eq!(clamp_move(40000, i16::MIN, u16::MAX), 7232);
+        assert_eq!(clamp_move(u16::MAX, 1, 1919), 1919);
+        let mut x = 0;
+        for _ in 0..3 {
+            x = clamp_move(x, i16::MAX, u16::MAX);
+        }
+        assert_eq!(x, u16::MAX);
+        for _ in 0..3 {
+            x = clamp_move(x, i16::MIN, u16::MAX);
+        }
+        assert_eq!(x, 0);
+
+        assert_eq!(clamp_move(5, 5, 0), 0);
+    }
+}
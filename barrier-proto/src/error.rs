@@ -0,0 +1,91 @@
+use core::fmt;
+
+/// Why bytes could not be parsed, all but `Incomplete` mean the stream is out of sync
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// `needed` bytes from the start of the buffer are required, more have to be received
+    Incomplete {
+        needed: usize,
+    },
+    /// `code` is the packet code when the error is in the fields of a packet
+    Format {
+        code: Option<[u8; 4]>,
+    },
+    /// A field runs past the end of the packet, it lied about its size
+    InsufficientData {
+        needed: usize,
+        available: usize,
+    },
+    PacketTooSmall(usize),
+    PacketTooLarge(usize),
+}
+
+impl Error {
+    /// Format error without a packet code, e.g. in the hello
+    pub const FORMAT: Error = Error::Format { code: None };
+
+    /// Tags a format error with the code of the packet it occurred in
+    pub fn with_code(self, code: [u8; 4]) -> Self {
+        match self {
+            Error::Format { code: None } => Error::Format { code: Some(code) },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Incomplete { needed } => write!(f, "incomplete packet, {needed} bytes needed"),
+            Error::Format { code: Some(code) } => {
+                f.write_str("packet \"")?;
+                for &c in code {
+                    write!(f, "{}", core::ascii::escape_default(c))?;
+                }
+                f.write_str("\" did not match format")
+            }
+            Error::Format { code: None } => f.write_str("data did not match format"),
+            Error::InsufficientData { needed, available } => write!(
+                f,
+                "not enough data, {needed} bytes needed but {available} left"
+            ),
+            Error::PacketTooSmall(size) => write!(f, "packet of {size} bytes is too small"),
+            Error::PacketTooLarge(size) => {
+                write!(f, "packet of {size} bytes exceeds the size limit")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Error::FORMAT.with_code(*b"DKDN").to_string(),
+            "packet \"DKDN\" did not match format"
+        );
+        assert_eq!(
+            Error::FORMAT.with_code(*b"D\x00\"\xff").to_string(),
+            "packet \"D\\x00\\\"\\xff\" did not match format"
+        );
+        // Only format errors are tagged, and only once
+        assert_eq!(
+            Error::FORMAT
+                .with_code(*b"DKDN")
+                .with_code(*b"DCLP")
+                .to_string(),
+            "packet \"DKDN\" did not match format"
+        );
+        assert_eq!(
+            Error::PacketTooSmall(3).with_code(*b"DKDN"),
+            Error::PacketTooSmall(3)
+        );
+        assert_eq!(Error::FORMAT.to_string(), "data did not match format");
+    }
+}
@@ -0,0 +1,214 @@
+//! File transfer after a file was dragged onto this screen.
+//!
+//! The server first sends DDRG with the paths of the dragged files, then the content of the
+//! first one in DFTR chunks: mark 1 is the total size in ASCII, mark 2 the data split into
+//! chunks and mark 3 an empty chunk ending the file.
+
+use alloc::{string::String, vec::Vec};
+
+use log::{debug, warn};
+
+/// Largest chunk sent, Barrier splits files in chunks of 512 KiB
+pub const FILE_CHUNK_SIZE: usize = 512 * 1024;
+
+/// What a DFTR chunk did to the transfer
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileTransfer {
+    /// More chunks are expected
+    Pending,
+    /// The last chunk has been received
+    Received(Vec<u8>),
+    /// The file exceeded the size limit and has been discarded
+    TooLarge { size: usize },
+}
+
+/// Assembles a file from its DFTR chunks
+#[derive(Debug, Default)]
+pub enum FileStage {
+    #[default]
+    None,
+    Receiving {
+        data: Vec<u8>,
+    },
+    /// The file is over the size limit, the rest of the chunks are skipped
+    Discard {
+        size: usize,
+    },
+}
+
+impl FileStage {
+    /// Adds the chunk of a `Packet::FileChunk`, files larger than `max_file_size` are discarded
+    pub fn push(&mut self, mark: u8, chunk: &[u8], max_file_size: usize) -> FileTransfer {
+        let (stage, transfer) = match (mark, core::mem::take(self)) {
+            // A new transfer drops any incomplete one
+            (1, _) => {
+                let expected_size = core::str::from_utf8(chunk)
+                    .ok()
+                    .and_then(|size| size.parse::<usize>().ok());
+                debug!("Expected file size: {:?}", expected_size);
+                match expected_size {
+                    Some(size) if size > max_file_size => FileStage::Discard { size: 0 },
+                    // Don't trust the size for the allocation
+                    Some(size) => FileStage::Receiving {
+                        data: Vec::with_capacity(size.min(FILE_CHUNK_SIZE)),
+                    },
+                    None => FileStage::Receiving { data: Vec::new() },
+                }
+                .pending()
+            }
+            (2, FileStage::Receiving { mut data }) => {
+                if data.len() + chunk.len() > max_file_size {
+                    debug!("File size limit exceeded, discarding");
+                    FileStage::Discard {
+                        size: data.len() + chunk.len(),
+                    }
+                    .pending()
+                } else {
+                    data.extend_from_slice(chunk);
+                    FileStage::Receiving { data }.pending()
+                }
+            }
+            (2, FileStage::Discard { size }) => FileStage::Discard {
+                size: size + chunk.len(),
+            }
+            .pending(),
+            (3, FileStage::Receiving { data }) => (FileStage::None, FileTransfer::Received(data)),
+            (3, FileStage::Discard { size }) => (FileStage::None, FileTransfer::TooLarge { size }),
+            (mark, stage) => {
+                warn!("Unexpected file chunk {} in {:?}", mark, stage);
+                (FileStage::None, FileTransfer::Pending)
+            }
+        };
+        *self = stage;
+        transfer
+    }
+
+    fn pending(self) -> (Self, FileTransfer) {
+        (self, FileTransfer::Pending)
+    }
+}
+
+/// Splits the file list of DDRG, each path is followed by a comma
+pub(crate) fn parse_drag_info(count: u16, list: &[u8]) -> Vec<String> {
+    list.split(|&c| c == b',')
+        .take(count as usize)
+        .filter(|path| !path.is_empty())
+        .map(|path| String::from_utf8_lossy(path).into_owned())
+        .collect()
+}
+
+/// The name of the file at `path`, sent by a server on Windows or a Unix
+pub fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{file_name, parse_drag_info, FileStage, FileTransfer};
+    use crate::{parse_packet, Packet};
+
+    /// The DFTR chunks of `data` as sent by the server, announced as `size` bytes
+    fn chunks(size: usize, data: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut wire = vec![];
+        let size = size.to_string().into_bytes();
+        Packet::FileChunk {
+            mark: 1,
+            data: size,
+        }
+        .encode(&mut wire);
+        for chunk in data.chunks(chunk_size) {
+            Packet::FileChunk {
+                mark: 2,
+                data: chunk.to_vec(),
+            }
+            .encode(&mut wire);
+        }
+        Packet::FileChunk {
+            mark: 3,
+            data: vec![],
+        }
+        .encode(&mut wire);
+        wire
+    }
+
+    // Parses the packets and feeds the chunks, like a client reading them
+    fn replay(mut wire: &[u8], stage: &mut FileStage, max_file_size: usize) -> Vec<FileTransfer> {
+        let mut transfers = vec![];
+        while !wire.is_empty() {
+            let (packet, size) = parse_packet(
+                wire,
+                usize::MAX,
+                #[cfg(feature = "clipboard")]
+                &mut crate::ClipboardStage::new(),
+                #[cfg(feature = "clipboard")]
+                usize::MAX,
+            )
+            .unwrap();
+            let Packet::FileChunk { mark, data } = packet else {
+                panic!("Unexpected packet {:?}", packet);
+            };
+            transfers.push(stage.push(mark, &data, max_file_size));
+            wire = &wire[size..];
+        }
+        transfers
+    }
+
+    #[test]
+    fn test_chunked_transfer() {
+        let data: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        let mut stage = FileStage::None;
+        let mut transfers = replay(&chunks(data.len(), &data, 4096), &mut stage, usize::MAX);
+        // Size, 3 chunks and the end
+        assert_eq!(transfers.len(), 5);
+        assert_eq!(transfers.pop(), Some(FileTransfer::Received(data)));
+        assert!(transfers.iter().all(|t| *t == FileTransfer::Pending));
+        assert!(matches!(stage, FileStage::None));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut stage = FileStage::None;
+        // Announced larger than the limit
+        let transfers = replay(&chunks(100, &[0xAA; 100], 30), &mut stage, 99);
+        assert_eq!(
+            transfers.last(),
+            Some(&FileTransfer::TooLarge { size: 100 })
+        );
+
+        // Lying about its size
+        let transfers = replay(&chunks(10, &[0xAA; 100], 30), &mut stage, 50);
+        assert_eq!(
+            transfers.last(),
+            Some(&FileTransfer::TooLarge { size: 100 })
+        );
+
+        // The next transfer goes through
+        let transfers = replay(&chunks(50, &[0xBB; 50], 30), &mut stage, 50);
+        assert_eq!(
+            transfers.last(),
+            Some(&FileTransfer::Received(vec![0xBB; 50]))
+        );
+    }
+
+    #[test]
+    fn test_unexpected_chunk() {
+        let mut stage = FileStage::None;
+        assert_eq!(stage.push(2, b"data", 100), FileTransfer::Pending);
+        assert_eq!(stage.push(3, b"", 100), FileTransfer::Pending);
+        assert!(matches!(stage, FileStage::None));
+    }
+
+    #[test]
+    fn test_drag_info() {
+        assert_eq!(
+            parse_drag_info(2, b"/home/me/a.txt,C:\\Users\\me\\b.png,"),
+            ["/home/me/a.txt", "C:\\Users\\me\\b.png"]
+        );
+        // The count wins over the list
+        assert_eq!(parse_drag_info(1, b"a,b,"), ["a"]);
+        assert_eq!(parse_drag_info(3, b""), Vec::<String>::new());
+        assert_eq!(file_name("/home/me/a.txt"), "a.txt");
+        assert_eq!(file_name("C:\\Users\\me\\b.png"), "b.png");
+        assert_eq!(file_name("c.txt"), "c.txt");
+    }
+}
@@ -0,0 +1,166 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::Fields,
+    packet::{Put, Sink},
+    Error,
+};
+
+/// Longest protocol name accepted in the hello, "Barrier" and "Synergy" are both 7 bytes
+const MAX_HELLO_NAME_LEN: usize = 16;
+
+/// Longest hello accepted, without the size
+pub const MAX_HELLO_SIZE: usize = MAX_HELLO_NAME_LEN + 4;
+
+/// Newest protocol version spoken by the client as `(major, minor)`, 1.8 added the
+/// language sync
+pub const PROTOCOL_VERSION: (u16, u16) = (1, 8);
+
+/// Dialect of the server, they only differ in the hello
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Protocol {
+    Barrier,
+    /// Synergy 1.x and Input Leap builds greeting with "Synergy"
+    Synergy,
+}
+
+/// What the server announced in its hello
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub protocol: Protocol,
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ServerInfo {
+    /// The version both ends speak, the older of the server's and [`PROTOCOL_VERSION`]
+    pub fn negotiated(self) -> Self {
+        let (major, minor) = (self.major, self.minor).min(PROTOCOL_VERSION);
+        Self {
+            major,
+            minor,
+            ..self
+        }
+    }
+
+    /// Whether the version is `version` or newer
+    pub fn supports(&self, version: (u16, u16)) -> bool {
+        (self.major, self.minor) >= version
+    }
+}
+
+/// Checks the size read in front of the hello, returns the number of bytes that follow it
+pub fn hello_size(size: u32) -> Result<usize, Error> {
+    let size = size as usize;
+    if !(4..=MAX_HELLO_SIZE).contains(&size) {
+        debug!("Got invalid hello, size {size}");
+        return Err(Error::FORMAT);
+    }
+    Ok(size)
+}
+
+/// Parses the hello of the server, the protocol name followed by the major and minor version
+pub fn parse_hello(hello: &[u8]) -> Result<ServerInfo, Error> {
+    let mut fields = Fields(hello);
+    let name = fields.take(hello.len().saturating_sub(4))?;
+    let protocol = match name {
+        b"Barrier" => Protocol::Barrier,
+        b"Synergy" => Protocol::Synergy,
+        _ => {
+            debug!("Got invalid hello {:?}", name);
+            return Err(Error::FORMAT);
+        }
+    };
+    let major = fields.u16()?;
+    let minor = fields.u16()?;
+    debug!("Got hello {:?} {major}:{minor}", protocol);
+    Ok(ServerInfo {
+        protocol,
+        major,
+        minor,
+    })
+}
+
+/// Writes the reply to the hello in the dialect and the version of `info`, with its size.
+/// The version is the [negotiated](ServerInfo::negotiated) one, older servers drop clients
+/// newer than them.
+///
+/// Returns the size of the reply, if it is larger than `out` nothing past `out` is written.
+pub fn encode_hello(info: ServerInfo, device_name: &str, out: &mut [u8]) -> usize {
+    let name: &[u8] = match info.protocol {
+        Protocol::Barrier => b"Barrier",
+        Protocol::Synergy => b"Synergy",
+    };
+    let size = name.len() + 2 + 2 + 4 + device_name.len();
+    let mut out = Sink::new(out);
+    out.put(&(size as u32).to_be_bytes());
+    out.put(name);
+    out.put(&info.major.to_be_bytes());
+    out.put(&info.minor.to_be_bytes());
+    out.put(&(device_name.len() as u32).to_be_bytes());
+    out.put(device_name.as_bytes());
+    out.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_hello, hello_size, parse_hello, Protocol, ServerInfo, PROTOCOL_VERSION};
+
+    const SYNERGY_1_6: ServerInfo = ServerInfo {
+        protocol: Protocol::Synergy,
+        major: 1,
+        minor: 6,
+    };
+
+    #[test]
+    fn test_hello() {
+        let mut hello = [0; 32];
+        let len = encode_hello(SYNERGY_1_6, "pi", &mut hello);
+        assert_eq!(len, 21);
+        assert_eq!(
+            hello_size(u32::from_be_bytes(hello[..4].try_into().unwrap())).unwrap(),
+            17
+        );
+        // The reply carries the screen name after the version
+        let info = parse_hello(&hello[4..15]).unwrap();
+        assert_eq!(info.protocol, Protocol::Synergy);
+        assert_eq!((info.major, info.minor), (1, 6));
+
+        assert!(hello_size(100).is_err());
+        assert!(parse_hello(b"Barrie\x00\x01\x00\x06").is_err());
+        assert!(parse_hello(b"Barrier\x00\x01").is_err());
+    }
+
+    #[test]
+    fn test_hello_too_long() {
+        let mut hello = [0; 8];
+        let info = ServerInfo {
+            protocol: Protocol::Barrier,
+            ..SYNERGY_1_6
+        };
+        assert_eq!(encode_hello(info, "pi", &mut hello), 21);
+        assert_eq!(&hello, b"\x00\x00\x00\x11Barr");
+    }
+
+    #[test]
+    fn test_negotiated() {
+        let version = |major, minor| {
+            let info = ServerInfo {
+                major,
+                minor,
+                ..SYNERGY_1_6
+            }
+            .negotiated();
+            (info.major, info.minor)
+        };
+        assert_eq!(version(1, 3), (1, 3));
+        assert_eq!(version(1, 6), (1, 6));
+        assert_eq!(version(1, 9), PROTOCOL_VERSION);
+        assert_eq!(version(2, 0), PROTOCOL_VERSION);
+
+        assert!(SYNERGY_1_6.supports((1, 6)));
+        assert!(SYNERGY_1_6.supports((1, 3)));
+        assert!(!SYNERGY_1_6.supports((1, 8)));
+    }
+}
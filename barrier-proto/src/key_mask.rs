@@ -0,0 +1,128 @@
+use core::fmt;
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+/// Modifiers and lock keys held with a key or when the cursor enters, the `KeyModifierMask`
+/// of Synergy as sent in DKDN, DKUP, DKRP and CINN. Bits without a name are kept, see
+/// [`KeyMask::from_bits_retain`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeyMask(u16);
+
+bitflags! {
+    impl KeyMask: u16 {
+        const SHIFT = 0x0001;
+        const CONTROL = 0x0002;
+        const ALT = 0x0004;
+        /// Command on macOS, sent for the Windows key by some servers
+        const META = 0x0008;
+        /// The Windows key
+        const SUPER = 0x0010;
+        /// Right Alt on layouts where it selects the third level, e.g. for `€` or `@`
+        const ALT_GR = 0x0020;
+        const CAPS_LOCK = 0x1000;
+        const NUM_LOCK = 0x2000;
+        const SCROLL_LOCK = 0x4000;
+    }
+}
+
+impl KeyMask {
+    /// The keys held, the lock states aside
+    pub const MODIFIERS: KeyMask = KeyMask(0x003F);
+
+    /// Whether a modifier is held, lock states don't count
+    pub fn has_modifier(self) -> bool {
+        self.intersects(Self::MODIFIERS)
+    }
+
+    /// The modifier byte of a HID keyboard report, with the left keys except Right Alt for
+    /// AltGr. Meta and Super are both the GUI key, lock states are not modifiers.
+    pub fn to_hid_modifier_byte(self) -> u8 {
+        [
+            (KeyMask::CONTROL, 0x01),
+            (KeyMask::SHIFT, 0x02),
+            (KeyMask::ALT, 0x04),
+            (KeyMask::META, 0x08),
+            (KeyMask::SUPER, 0x08),
+            (KeyMask::ALT_GR, 0x40),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .fold(0, |byte, (_, bit)| byte | bit)
+    }
+}
+
+/// The flags set, e.g. `SHIFT | CAPS_LOCK`, unknown bits in hex and nothing if none is set
+impl fmt::Display for KeyMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
+impl From<u16> for KeyMask {
+    fn from(bits: u16) -> Self {
+        KeyMask::from_bits_retain(bits)
+    }
+}
+
+impl From<KeyMask> for u16 {
+    fn from(mask: KeyMask) -> Self {
+        mask.bits()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyMask;
+
+    #[test]
+    fn test_bits() {
+        for (mask, bits, hid) in [
+            (KeyMask::SHIFT, 0x0001, 0x02),
+            (KeyMask::CONTROL, 0x0002, 0x01),
+            (KeyMask::ALT, 0x0004, 0x04),
+            (KeyMask::META, 0x0008, 0x08),
+            (KeyMask::SUPER, 0x0010, 0x08),
+            (KeyMask::ALT_GR, 0x0020, 0x40),
+            (KeyMask::CAPS_LOCK, 0x1000, 0),
+            (KeyMask::NUM_LOCK, 0x2000, 0),
+            (KeyMask::SCROLL_LOCK, 0x4000, 0),
+        ] {
+            assert_eq!(mask.bits(), bits, "{mask:?}");
+            assert_eq!(KeyMask::from(bits), mask);
+            assert_eq!(u16::from(mask), bits);
+            assert_eq!(mask.to_hid_modifier_byte(), hid, "{mask:?}");
+            assert_eq!(mask.has_modifier(), hid != 0, "{mask:?}");
+        }
+    }
+
+    #[test]
+    fn test_combined() {
+        let mask = KeyMask::from_bits_retain(0x1013);
+        assert_eq!(
+            mask,
+            KeyMask::SHIFT | KeyMask::CONTROL | KeyMask::SUPER | KeyMask::CAPS_LOCK
+        );
+        assert_eq!(mask.to_hid_modifier_byte(), 0x0B);
+        assert!(mask.has_modifier());
+        assert!(!(KeyMask::CAPS_LOCK | KeyMask::NUM_LOCK).has_modifier());
+        assert_eq!(
+            (KeyMask::META | KeyMask::SUPER).to_hid_modifier_byte(),
+            0x08
+        );
+
+        // Level 5 lock and the bits above have no name but go through
+        let mask = KeyMask::from(0x8041);
+        assert_eq!(mask.bits(), 0x8041);
+        assert_eq!(mask.to_hid_modifier_byte(), 0x02);
+        assert_eq!(KeyMask::from_bits(0x8041), None);
+        assert_eq!(KeyMask::from_bits_truncate(0x8041), KeyMask::SHIFT);
+
+        assert_eq!(mask.to_string(), "SHIFT | 0x8040");
+        assert_eq!(KeyMask::empty().to_string(), "");
+
+        assert_eq!(serde_json::to_string(&mask).unwrap(), "32833");
+        assert_eq!(serde_json::from_str::<KeyMask>("32833").unwrap(), mask);
+    }
+}
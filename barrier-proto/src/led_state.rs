@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// Lock key indicators of the target host, set by the host with the output report of a boot
+/// keyboard
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedState {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+    pub scroll_lock: bool,
+    pub compose: bool,
+    pub kana: bool,
+}
+
+impl LedState {
+    /// The LEDs of the output report byte, the bits above Kana are ignored
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            num_lock: bits & 0x01 != 0,
+            caps_lock: bits & 0x02 != 0,
+            scroll_lock: bits & 0x04 != 0,
+            compose: bits & 0x08 != 0,
+            kana: bits & 0x10 != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LedState;
+
+    #[test]
+    fn test_from_bits() {
+        assert_eq!(LedState::from_bits(0), LedState::default());
+        assert_eq!(
+            LedState::from_bits(0xE3),
+            LedState {
+                num_lock: true,
+                caps_lock: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            LedState::from_bits(0x1C),
+            LedState {
+                scroll_lock: true,
+                compose: true,
+                kana: true,
+                ..Default::default()
+            }
+        );
+    }
+}
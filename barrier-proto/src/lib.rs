@@ -0,0 +1,44 @@
+//! The Barrier protocol without any I/O.
+//!
+//! Bytes received from the server are handed to [`parse_packet`], which returns the packet
+//! and the number of bytes it took, or [`Error::Incomplete`] until enough bytes have
+//! arrived. Packets are written with [`encode_packet`] into a buffer of the caller. Only
+//! the clipboard, the options and the file transfer need the `alloc` feature, everything
+//! else runs on `no_std` targets without an allocator.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+mod codec;
+mod cursor;
+mod error;
+mod hello;
+mod key_mask;
+mod led_state;
+mod packet;
+
+pub use codec::{find_packet, is_plausible_code, packet_size, parse_body, parse_packet, peek_code};
+pub use cursor::clamp_move;
+pub use error::Error;
+pub use hello::{
+    encode_hello, hello_size, parse_hello, Protocol, ServerInfo, MAX_HELLO_SIZE, PROTOCOL_VERSION,
+};
+pub use key_mask::KeyMask;
+pub use led_state::LedState;
+pub use packet::{encode_packet, Packet};
+
+#[cfg(feature = "clipboard")]
+mod clipboard;
+#[cfg(feature = "clipboard")]
+pub(crate) use clipboard::CLIPBOARD_CHUNK_SIZE;
+#[cfg(feature = "clipboard")]
+pub use clipboard::{
+    ClipboardData, ClipboardDataBuilder, ClipboardFormat, ClipboardStage, CLIPBOARD_COUNT,
+};
+#[cfg(feature = "file-transfer")]
+mod file;
+#[cfg(feature = "file-transfer")]
+pub use file::{file_name, FileStage, FileTransfer, FILE_CHUNK_SIZE};
+#[cfg(feature = "image")]
+mod bitmap;
@@ -0,0 +1,401 @@
+#[cfg(feature = "barrier-options")]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::KeyMask;
+#[cfg(feature = "clipboard")]
+use crate::{ClipboardData, CLIPBOARD_CHUNK_SIZE};
+
+/// A packet sent by the server or the client, the hello aside
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    QueryInfo,
+    DeviceInfo {
+        x: u16,
+        y: u16,
+        w: u16,
+        h: u16,
+        _dummy: u16,
+        mx: u16, // x position of the mouse on the secondary screen
+        my: u16, // y position of the mouse on the secondary screen
+    },
+    InfoAck,
+    KeepAlive,
+    ClientNoOp,
+    #[cfg(feature = "barrier-options")]
+    ResetOptions,
+    #[cfg(feature = "barrier-options")]
+    SetDeviceOptions(BTreeMap<String, u32>),
+    ErrorUnknownDevice,
+    /// Another client with the same screen name is connected
+    ErrorBusy,
+    /// The server received something it didn't expect
+    ErrorBadProtocol,
+    /// The server doesn't support our protocol version, it sends its own version
+    ErrorIncompatibleVersion {
+        major: u16,
+        minor: u16,
+    },
+    /// The server is closing the connection
+    Close,
+    GrabClipboard {
+        id: u8,
+        seq_num: u32,
+    },
+    #[cfg(feature = "clipboard")]
+    SetClipboard {
+        id: u8,
+        seq_num: u32,
+        data: ClipboardData,
+    },
+    /// Clipboard data exceeded the size limit and has been discarded. Only decoded, it's
+    /// never sent and encodes to nothing.
+    #[cfg(feature = "clipboard")]
+    ClipboardTooLarge {
+        id: u8,
+        size: usize,
+    },
+    CursorEnter {
+        x: u16,
+        y: u16,
+        seq_num: u32,
+        mask: KeyMask,
+    },
+    MouseUp {
+        id: i8,
+    },
+    MouseDown {
+        id: i8,
+    },
+    KeyUp {
+        id: u16,
+        mask: KeyMask,
+        button: u16,
+    },
+    KeyDown {
+        id: u16,
+        mask: KeyMask,
+        button: u16,
+    },
+    KeyRepeat {
+        id: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    },
+    MouseWheel {
+        x_delta: i16,
+        y_delta: i16,
+    },
+    CursorLeave,
+    MouseMoveAbs {
+        x: u16,
+        y: u16,
+    },
+    MouseMove {
+        x: i16,
+        y: i16,
+    },
+    /// The screen saver of the server started or stopped
+    ScreenSaver {
+        on: bool,
+    },
+    /// Buttons held on game controller `id`, a bit per button
+    GameButtons {
+        id: u8,
+        buttons: u16,
+    },
+    /// Position of the two sticks of game controller `id`, up and right are positive
+    GameSticks {
+        id: u8,
+        x1: i16,
+        y1: i16,
+        x2: i16,
+        y2: i16,
+    },
+    /// Position of the two triggers of game controller `id`, 0 when released
+    GameTriggers {
+        id: u8,
+        t1: u8,
+        t2: u8,
+    },
+    /// A chunk of the file being transferred, see [`FileStage`](crate::FileStage)
+    #[cfg(feature = "file-transfer")]
+    FileChunk {
+        mark: u8,
+        data: Vec<u8>,
+    },
+    /// Files were dragged onto this screen, the first one is transferred next
+    #[cfg(feature = "file-transfer")]
+    DragInfo {
+        files: Vec<String>,
+    },
+    /// Keyboard languages of the server as two letter codes, e.g. "en", since 1.8
+    #[cfg(feature = "alloc")]
+    LanguageSync {
+        languages: Vec<String>,
+    },
+    Unknown([u8; 4]),
+}
+
+impl Packet {
+    /// Appends the packet as sent on the wire, the clipboard takes several packets.
+    /// `ClipboardTooLarge` is only decoded, nothing is appended for it.
+    #[cfg(feature = "alloc")]
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.put(out);
+    }
+
+    /// Moves, buttons, wheel and keys, the packets typing on the screen
+    pub fn is_input(&self) -> bool {
+        matches!(
+            self,
+            Packet::MouseMoveAbs { .. }
+                | Packet::MouseMove { .. }
+                | Packet::MouseDown { .. }
+                | Packet::MouseUp { .. }
+                | Packet::MouseWheel { .. }
+                | Packet::KeyDown { .. }
+                | Packet::KeyRepeat { .. }
+                | Packet::KeyUp { .. }
+                | Packet::GameButtons { .. }
+                | Packet::GameSticks { .. }
+                | Packet::GameTriggers { .. }
+        )
+    }
+
+    /// Protocol version that introduced the packet as `(major, minor)`, the server doesn't
+    /// send it to clients speaking an older one
+    pub fn version(&self) -> (u16, u16) {
+        match self {
+            #[cfg(feature = "file-transfer")]
+            Packet::FileChunk { .. } | Packet::DragInfo { .. } => (1, 5),
+            #[cfg(feature = "alloc")]
+            Packet::LanguageSync { .. } => (1, 8),
+            _ => (1, 0),
+        }
+    }
+
+    fn put(&self, out: &mut impl Put) {
+        match *self {
+            Packet::QueryInfo => put_header(out, b"QINF", 0),
+            Packet::DeviceInfo {
+                x,
+                y,
+                w,
+                h,
+                _dummy,
+                mx,
+                my,
+            } => put_u16s(out, b"DINF", [x, y, w, h, _dummy, mx, my]),
+            Packet::ClientNoOp => put_header(out, b"CNOP", 0),
+            Packet::Unknown(ref code) => put_header(out, code, 0),
+            Packet::InfoAck => put_header(out, b"CIAK", 0),
+            Packet::KeepAlive => put_header(out, b"CALV", 0),
+            Packet::ErrorUnknownDevice => put_header(out, b"EUNK", 0),
+            Packet::ErrorBusy => put_header(out, b"EBSY", 0),
+            Packet::ErrorBadProtocol => put_header(out, b"EBAD", 0),
+            Packet::ErrorIncompatibleVersion { major, minor } => {
+                put_u16s(out, b"EICV", [major, minor])
+            }
+            Packet::Close => put_header(out, b"CBYE", 0),
+            Packet::MouseMoveAbs { x, y } => put_u16s(out, b"DMMV", [x, y]),
+            #[cfg(feature = "clipboard")]
+            Packet::SetClipboard {
+                id,
+                seq_num,
+                ref data,
+            } => {
+                // mark 1 is the total length string in ASCII
+                // mark 2 is the actual data and is split into chunks
+                // mark 3 is an empty chunk
+                let data = data.marshal();
+                let size = alloc::string::ToString::to_string(&data.len());
+                put_clipboard_chunk(out, id, seq_num, 1, size.as_bytes());
+                for chunk in data.chunks(CLIPBOARD_CHUNK_SIZE) {
+                    put_clipboard_chunk(out, id, seq_num, 2, chunk);
+                }
+                put_clipboard_chunk(out, id, seq_num, 3, &[]);
+            }
+            #[cfg(feature = "barrier-options")]
+            Packet::ResetOptions => put_header(out, b"CROP", 0),
+            #[cfg(feature = "barrier-options")]
+            Packet::SetDeviceOptions(ref options) => {
+                // Options are written as a list of alternating names and values
+                put_header(out, b"DSOP", 4 + options.len() * 8);
+                out.put(&(options.len() as u32 * 2).to_be_bytes());
+                for (name, value) in options {
+                    let mut opt = [b' '; 4];
+                    let len = name.len().min(4);
+                    opt[..len].copy_from_slice(&name.as_bytes()[..len]);
+                    out.put(&opt);
+                    out.put(&value.to_be_bytes());
+                }
+            }
+            Packet::GrabClipboard { id, seq_num } => {
+                put_header(out, b"CCLP", 1 + 4);
+                out.put(&[id]);
+                out.put(&seq_num.to_be_bytes());
+            }
+            Packet::CursorEnter {
+                x,
+                y,
+                seq_num,
+                mask,
+            } => {
+                put_header(out, b"CINN", 2 + 2 + 4 + 2);
+                out.put(&x.to_be_bytes());
+                out.put(&y.to_be_bytes());
+                out.put(&seq_num.to_be_bytes());
+                out.put(&mask.bits().to_be_bytes());
+            }
+            Packet::CursorLeave => put_header(out, b"COUT", 0),
+            Packet::MouseUp { id } => {
+                put_header(out, b"DMUP", 1);
+                out.put(&id.to_be_bytes());
+            }
+            Packet::MouseDown { id } => {
+                put_header(out, b"DMDN", 1);
+                out.put(&id.to_be_bytes());
+            }
+            Packet::KeyUp { id, mask, button } => put_u16s(out, b"DKUP", [id, mask.bits(), button]),
+            Packet::KeyDown { id, mask, button } => {
+                put_u16s(out, b"DKDN", [id, mask.bits(), button])
+            }
+            Packet::KeyRepeat {
+                id,
+                mask,
+                button,
+                count,
+            } => put_u16s(out, b"DKRP", [id, mask.bits(), count, button]),
+            Packet::MouseWheel { x_delta, y_delta } => {
+                put_u16s(out, b"DMWM", [x_delta as u16, y_delta as u16])
+            }
+            Packet::MouseMove { x, y } => put_u16s(out, b"DMRM", [x as u16, y as u16]),
+            Packet::ScreenSaver { on } => {
+                put_header(out, b"CSEC", 1);
+                out.put(&[on as u8]);
+            }
+            Packet::GameButtons { id, buttons } => {
+                put_header(out, b"DGBT", 1 + 2);
+                out.put(&[id]);
+                out.put(&buttons.to_be_bytes());
+            }
+            Packet::GameSticks { id, x1, y1, x2, y2 } => {
+                put_header(out, b"DGST", 1 + 4 * 2);
+                out.put(&[id]);
+                for axis in [x1, y1, x2, y2] {
+                    out.put(&axis.to_be_bytes());
+                }
+            }
+            Packet::GameTriggers { id, t1, t2 } => {
+                put_header(out, b"DGTR", 1 + 1 + 1);
+                out.put(&[id, t1, t2]);
+            }
+            #[cfg(feature = "file-transfer")]
+            Packet::FileChunk { mark, ref data } => {
+                put_header(out, b"DFTR", 1 + 4 + data.len());
+                out.put(&[mark]);
+                out.put(&(data.len() as u32).to_be_bytes());
+                out.put(data);
+            }
+            #[cfg(feature = "file-transfer")]
+            Packet::DragInfo { ref files } => {
+                let len: usize = files.iter().map(|path| path.len() + 1).sum();
+                put_header(out, b"DDRG", 2 + 4 + len);
+                out.put(&(files.len() as u16).to_be_bytes());
+                out.put(&(len as u32).to_be_bytes());
+                for path in files {
+                    out.put(path.as_bytes());
+                    out.put(b",");
+                }
+            }
+            #[cfg(feature = "alloc")]
+            Packet::LanguageSync { ref languages } => {
+                let len: usize = languages.iter().map(String::len).sum();
+                put_header(out, b"LSYN", 4 + len);
+                out.put(&(len as u32).to_be_bytes());
+                for language in languages {
+                    out.put(language.as_bytes());
+                }
+            }
+            // Not a packet of the protocol, only reported by the decoder
+            #[cfg(feature = "clipboard")]
+            Packet::ClipboardTooLarge { .. } => {}
+        }
+    }
+}
+
+/// Writes `packet` as sent on the wire into `out`, without allocating but for the clipboard.
+///
+/// Returns the size of the packet, if it is larger than `out` nothing past `out` is written.
+/// `ClipboardTooLarge` is only decoded, its size is 0.
+pub fn encode_packet(packet: &Packet, out: &mut [u8]) -> usize {
+    let mut out = Sink::new(out);
+    packet.put(&mut out);
+    out.len()
+}
+
+/// Where the encoded bytes go
+pub(crate) trait Put {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+#[cfg(feature = "alloc")]
+impl Put for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// Fills a slice, counting the bytes that don't fit
+pub(crate) struct Sink<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Sink<'a> {
+    pub fn new(out: &'a mut [u8]) -> Self {
+        Self { out, len: 0 }
+    }
+
+    /// Bytes put so far, including those that didn't fit
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Put for Sink<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        if let Some(room) = self.out.get_mut(self.len..) {
+            let fits = bytes.len().min(room.len());
+            room[..fits].copy_from_slice(&bytes[..fits]);
+        }
+        self.len += bytes.len();
+    }
+}
+
+fn put_header(out: &mut impl Put, code: &[u8; 4], payload_len: usize) {
+    out.put(&(4 + payload_len as u32).to_be_bytes());
+    out.put(code);
+}
+
+fn put_u16s<const N: usize>(out: &mut impl Put, code: &[u8; 4], values: [u16; N]) {
+    put_header(out, code, N * 2);
+    for value in values {
+        out.put(&value.to_be_bytes());
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn put_clipboard_chunk(out: &mut impl Put, id: u8, seq_num: u32, mark: u8, data: &[u8]) {
+    put_header(out, b"DCLP", 1 + 4 + 1 + 4 + data.len());
+    out.put(&[id]);
+    out.put(&seq_num.to_be_bytes());
+    out.put(&[mark]);
+    out.put(&(data.len() as u32).to_be_bytes());
+    out.put(data);
+}
@@ -1,99 +1,113 @@
-use barrier_client::{self, start, Actuator, ClipboardData};
+use barrier_client::{self, start, Actuator, ActuatorError, ClipboardData, KeyMask};
 use env_logger::Env;
 use log::info;
 
 use synergy_hid::SynergyHid;
 
 struct DummyActuator {
-    width: u16,
-    height: u16,
-    x: u16,
-    y: u16,
     hid: SynergyHid,
 }
 
 impl Actuator for DummyActuator {
-    fn connected(&mut self) {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
         info!("Connected");
+        Ok(())
     }
 
-    fn disconnected(&mut self) {
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
         info!("Disconnected");
+        Ok(())
     }
 
     fn get_screen_size(&self) -> (u16, u16) {
-        (self.width, self.height)
+        self.hid.screen_size()
     }
 
     fn get_cursor_position(&self) -> (u16, u16) {
-        (self.x, self.y)
+        self.hid.cursor_position()
     }
 
-    fn set_cursor_position(&mut self, x: u16, y: u16) {
-        self.x = x;
-        self.y = y;
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
         let report = &mut [0; 9];
         let ret = self.hid.set_cursor_position(x, y, report);
         info!("Set cursor position to {x} {y}, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn move_cursor(&mut self, x: i16, y: i16) {
-        self.x = (self.x as i32 + x as i32) as u16;
-        self.y = (self.y as i32 + y as i32) as u16;
-        self.set_cursor_position(self.x, self.y);
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        let report = &mut [0; 9];
+        let ret = self.hid.move_cursor(x, y, report);
+        info!("Move cursor by {x} {y}, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn mouse_down(&mut self, button: i8) {
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
         let report = &mut [0; 9];
         let ret = self.hid.mouse_down(button, report);
         info!("Mouse button {button} down, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn mouse_up(&mut self, button: i8) {
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
         let report = &mut [0; 9];
         let ret = self.hid.mouse_up(button, report);
         info!("Mouse button {button} up, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn mouse_wheel(&mut self, x: i16, y: i16) {
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
         let report = &mut [0; 9];
         let ret = self.hid.mouse_scroll(x, y, report);
         info!("Mouse wheel {x} {y}, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn key_down(&mut self, key: u16, mask: u16, button: u16) {
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
         let report = &mut [0; 9];
         let ret = self.hid.key_down(key, mask, button, report);
         info!("Key down {key} {mask} {button}, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
-        info!("Key repeat {key} {mask} {button} {count}")
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        info!("Key repeat {key} {mask} {button} {count}");
+        Ok(())
     }
 
-    fn key_up(&mut self, key: u16, mask: u16, button: u16) {
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
         let report = &mut [0; 9];
         let ret = self.hid.key_up(key, mask, button, report);
         info!("Key up {key} {mask} {button}, HID report: {:?}", ret);
+        Ok(())
     }
 
-    fn enter(&mut self) {
-        info!("Enter")
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        info!("Enter");
+        Ok(())
     }
 
-    fn leave(&mut self) {
-        info!("Leave")
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        info!("Leave");
+        Ok(())
     }
 
-    fn set_options(&mut self, opts: std::collections::HashMap<String, u32>) {
-        info!("Set options {:#?}", opts)
+    fn set_options(&mut self, opts: barrier_client::ServerOptions) -> Result<(), ActuatorError> {
+        info!("Set options {:#?}", opts);
+        Ok(())
     }
 
-    fn reset_options(&mut self) {
-        info!("Reset options")
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        info!("Reset options");
+        Ok(())
     }
 
-    fn set_clipboard(&mut self, data: ClipboardData) {
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
         info!(
             "Clipboard text:{}",
             data.text()
@@ -107,9 +121,11 @@ impl Actuator for DummyActuator {
                 .unwrap_or(String::from("<None>")),
         );
         info!(
-            "Clipboard bitmap:{}",
-            data.bitmap().map(|_| "yes").unwrap_or("no")
+            "Clipboard formats:{:?}, {} bytes",
+            data.formats(),
+            data.len_bytes()
         );
+        Ok(())
     }
 }
 
@@ -117,11 +133,7 @@ impl Actuator for DummyActuator {
 async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
     let mut actuator = DummyActuator {
-        width: 1920,
-        height: 1080,
-        x: 0,
-        y: 0,
-        hid: SynergyHid::new(false),
+        hid: SynergyHid::new(1920, 1080, false),
     };
     start("192.168.2.59:24800", String::from("BARPI"), &mut actuator)
         .await
@@ -0,0 +1,27 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    ffi_header();
+}
+
+/// Writes `synergy_hid.h` for the C ABI next to the other build outputs in `OUT_DIR`
+#[cfg(feature = "ffi")]
+fn ffi_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let mut config = cbindgen::Config::default();
+    config.language = cbindgen::Language::C;
+    config.include_guard = Some("SYNERGY_HID_H".into());
+    config.cpp_compat = true;
+    config.usize_is_size_t = true;
+    // The variants are global in C, e.g. SynergyHidStatus_Ok
+    config.enumeration.prefix_with_name = true;
+    // Only the C ABI, the instance is opaque
+    config.after_includes = Some("typedef struct SynergyHid SynergyHid;".into());
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(std::path::Path::new(&crate_dir).join("src/ffi.rs"))
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file(std::path::Path::new(&out_dir).join("synergy_hid.h"));
+}
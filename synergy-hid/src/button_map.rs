@@ -0,0 +1,195 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use serde::{Deserialize, Serialize};
+
+use crate::synergy_mouse_button;
+
+/// Direction of the horizontal wheel, the AC Pan usage of the mouse
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pan {
+    Left,
+    Right,
+}
+
+/// What a Synergy mouse button does on the host
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ButtonTarget {
+    /// HID button from 1 to 8, 1 is the left button, 2 the right one and 3 the middle one
+    Button(u8),
+    /// A notch of the horizontal wheel on each press, nothing on the release
+    Pan(Pan),
+    /// The button is ignored
+    None,
+}
+
+impl ButtonTarget {
+    /// Bit of the button in the mouse reports, 0 for the other targets
+    pub fn bit(self) -> u8 {
+        match self {
+            ButtonTarget::Button(button @ 1..=8) => 1 << (button - 1),
+            _ => 0,
+        }
+    }
+}
+
+/// One entry of `button_map`, a button with neither `button` nor `pan` is ignored
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ButtonMapEntry {
+    /// Synergy button id, 1 left, 2 middle, 3 right, 4 back, 5 forward and so on up to 8
+    pub synergy: i8,
+    /// HID button from 1 to 8, 1 left, 2 right, 3 middle, 4 back, 5 forward
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub button: Option<u8>,
+    /// Scroll the horizontal wheel instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pan: Option<Pan>,
+}
+
+/// Synergy to HID mouse button mapping, buttons not listed keep the builtin one which sends
+/// buttons 1 to 8 as the HID buttons of the same role
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "Vec<ButtonMapEntry>")]
+pub struct ButtonMap {
+    buttons: BTreeMap<i8, ButtonTarget>,
+}
+
+impl ButtonMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `synergy` to `target`, ids below 1 are never sent and are skipped
+    pub fn insert(&mut self, synergy: i8, target: ButtonTarget) {
+        if synergy < 1 {
+            warn!("Invalid mouse button {} in the button map", synergy);
+            return;
+        }
+        let target = match target {
+            ButtonTarget::Button(button) if !(1..=8).contains(&button) => {
+                warn!(
+                    "Mouse button {} is mapped to HID button {}, only 1 to 8 exist",
+                    synergy, button
+                );
+                ButtonTarget::None
+            }
+            target => target,
+        };
+        self.buttons.insert(synergy, target);
+    }
+
+    pub fn get(&self, synergy: i8) -> ButtonTarget {
+        if let Some(target) = self.buttons.get(&synergy) {
+            return *target;
+        }
+        match synergy_mouse_button(synergy) {
+            0 => ButtonTarget::None,
+            button => ButtonTarget::Button(button),
+        }
+    }
+
+    /// The button is mapped to nothing on purpose
+    pub fn is_ignored(&self, synergy: i8) -> bool {
+        self.buttons.get(&synergy) == Some(&ButtonTarget::None)
+    }
+}
+
+impl FromIterator<(i8, ButtonTarget)> for ButtonMap {
+    fn from_iter<T: IntoIterator<Item = (i8, ButtonTarget)>>(iter: T) -> Self {
+        let mut map = Self::new();
+        for (synergy, target) in iter {
+            map.insert(synergy, target);
+        }
+        map
+    }
+}
+
+impl From<Vec<ButtonMapEntry>> for ButtonMap {
+    fn from(entries: Vec<ButtonMapEntry>) -> Self {
+        entries
+            .into_iter()
+            .map(|entry| {
+                let target = match (entry.button, entry.pan) {
+                    (Some(button), pan) => {
+                        if pan.is_some() {
+                            warn!(
+                                "Mouse button {} has both a button and a pan, using the button",
+                                entry.synergy
+                            );
+                        }
+                        ButtonTarget::Button(button)
+                    }
+                    (None, Some(pan)) => ButtonTarget::Pan(pan),
+                    (None, None) => ButtonTarget::None,
+                };
+                (entry.synergy, target)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ButtonMap, ButtonTarget, Pan};
+
+    #[test]
+    fn test_button_map() {
+        let map: ButtonMap = serde_yaml::from_str(
+            r#"
+            # Swap the middle button and the back one
+            - synergy: 2
+              button: 4
+            - synergy: 4
+              button: 3
+            # Forward and the next one scroll sideways
+            - synergy: 5
+              pan: left
+            - synergy: 6
+              pan: right
+            # Ignored
+            - synergy: 7
+            # Invalid, skipped
+            - synergy: 0
+              button: 1
+            - synergy: -1
+              button: 1
+            - synergy: 8
+              button: 9
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(map.get(1), ButtonTarget::Button(1));
+        assert_eq!(map.get(2), ButtonTarget::Button(4));
+        assert_eq!(map.get(3), ButtonTarget::Button(2));
+        assert_eq!(map.get(4), ButtonTarget::Button(3));
+        assert_eq!(map.get(5), ButtonTarget::Pan(Pan::Left));
+        assert_eq!(map.get(6), ButtonTarget::Pan(Pan::Right));
+        assert_eq!(map.get(7), ButtonTarget::None);
+        assert!(map.is_ignored(7));
+        assert!(!map.is_ignored(0));
+        assert_eq!(map.get(8), ButtonTarget::None);
+        assert_eq!(map.get(0), ButtonTarget::None);
+        assert_eq!(map.get(-1), ButtonTarget::None);
+        assert_eq!(map.get(9), ButtonTarget::None);
+
+        // The builtin mapping
+        let map = ButtonMap::new();
+        for (synergy, bit) in [
+            (1, 0x01),
+            (2, 0x04),
+            (3, 0x02),
+            (4, 0x08),
+            (5, 0x10),
+            (6, 0x20),
+            (7, 0x40),
+            (8, 0x80),
+            (9, 0),
+            (0, 0),
+            (-128, 0),
+        ] {
+            assert_eq!(map.get(synergy).bit(), bit, "{synergy}");
+        }
+        assert_eq!(ButtonTarget::Pan(Pan::Left).bit(), 0);
+    }
+}
@@ -0,0 +1,72 @@
+use alloc::vec::Vec;
+
+use crate::KeyCode;
+
+/// Key id and usage of the key held on each server button. Only a few keys are held at once,
+/// so a list takes less memory than a table of every button and any button id fits.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ServerButtons(Vec<(u16, u16, KeyCode)>);
+
+impl ServerButtons {
+    pub fn get(&self, button: u16) -> Option<(u16, KeyCode)> {
+        self.0
+            .iter()
+            .find(|(b, ..)| *b == button)
+            .map(|&(_, key, hid)| (key, hid))
+    }
+
+    /// `None` forgets the key held on `button`
+    pub fn set(&mut self, button: u16, held: Option<(u16, KeyCode)>) {
+        let index = self.0.iter().position(|(b, ..)| *b == button);
+        match (index, held) {
+            (Some(index), Some((key, hid))) => self.0[index] = (button, key, hid),
+            (Some(index), None) => {
+                self.0.swap_remove(index);
+            }
+            (None, Some((key, hid))) => self.0.push((button, key, hid)),
+            (None, None) => {}
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Buttons held and the key id of each, by button
+    pub fn held(&self) -> Vec<(u16, u16)> {
+        let mut held: Vec<_> = self
+            .0
+            .iter()
+            .map(|&(button, key, _)| (button, key))
+            .collect();
+        held.sort_unstable();
+        held
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ServerButtons;
+    use crate::KeyCode;
+
+    #[test]
+    fn test_server_buttons() {
+        let mut buttons = ServerButtons::default();
+        buttons.set(30, Some((0x61, KeyCode::Key(0x04))));
+        buttons.set(2, Some((0x62, KeyCode::Key(0x05))));
+        // Beyond the 512 buttons of the former table
+        buttons.set(0xFFFF, Some((0x63, KeyCode::Key(0x06))));
+        assert_eq!(buttons.get(2), Some((0x62, KeyCode::Key(0x05))));
+        assert_eq!(buttons.get(0xFFFF), Some((0x63, KeyCode::Key(0x06))));
+        assert_eq!(buttons.held(), [(2, 0x62), (30, 0x61), (0xFFFF, 0x63)]);
+
+        buttons.set(30, Some((0x41, KeyCode::Key(0x04))));
+        buttons.set(2, None);
+        buttons.set(3, None);
+        assert_eq!(buttons.get(2), None);
+        assert_eq!(buttons.held(), [(30, 0x41), (0xFFFF, 0x63)]);
+
+        buttons.clear();
+        assert_eq!(buttons.held(), []);
+    }
+}
@@ -0,0 +1,328 @@
+use alloc::{format, string::String, vec::Vec};
+
+use serde::Deserialize;
+
+use crate::{
+    keycodes::{
+        ASCII_2_HID, HID_KEY_ALT_LEFT, HID_KEY_CONTROL_LEFT, HID_KEY_KEYPAD_0, HID_KEY_KEYPAD_1,
+        HID_KEY_KEYPAD_ADD, HID_KEY_SHIFT_LEFT, HID_KEY_U,
+    },
+    KeyCode, MacroStep,
+};
+
+/// How the characters without a key in the US layout are typed, as their hex code in a
+/// sequence the input method of the host understands
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeInput {
+    /// They're dropped like any key without a HID usage, dead keys included
+    #[default]
+    Skip,
+    /// Ctrl+Shift+U, the hex code and Space, understood by GTK and IBus
+    Linux,
+    /// Alt held while typing the keypad + and the hex code, once per UTF-16 unit. Windows
+    /// only takes it with the `EnableHexNumpad` value of `HKCU\Control Panel\Input Method`
+    /// set to "1", which needs a new login.
+    Windows,
+}
+
+impl UnicodeInput {
+    /// The macro steps typing `c`, none when skipped
+    pub fn steps(self, c: char) -> Vec<MacroStep> {
+        let mut steps = Vec::new();
+        match self {
+            UnicodeInput::Skip => {}
+            UnicodeInput::Linux => {
+                for key in [HID_KEY_CONTROL_LEFT, HID_KEY_SHIFT_LEFT, HID_KEY_U] {
+                    steps.push(MacroStep::Press(key));
+                }
+                for key in [HID_KEY_U, HID_KEY_SHIFT_LEFT, HID_KEY_CONTROL_LEFT] {
+                    steps.push(MacroStep::Release(key));
+                }
+                steps.push(MacroStep::Text(format!("{:x} ", c as u32)));
+            }
+            UnicodeInput::Windows => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    steps.push(MacroStep::Press(HID_KEY_ALT_LEFT));
+                    steps.push(MacroStep::Press(HID_KEY_KEYPAD_ADD));
+                    steps.push(MacroStep::Release(HID_KEY_KEYPAD_ADD));
+                    for digit in format!("{:x}", unit).chars() {
+                        match digit.to_digit(10) {
+                            // The digits of the top row would be taken as decimal
+                            Some(digit) => {
+                                let key = keypad_digit(digit as u8);
+                                steps.push(MacroStep::Press(key));
+                                steps.push(MacroStep::Release(key));
+                            }
+                            None => steps.push(MacroStep::Text(digit.into())),
+                        }
+                    }
+                    steps.push(MacroStep::Release(HID_KEY_ALT_LEFT));
+                }
+            }
+        }
+        steps
+    }
+
+    /// The macro steps typing `text`, the ASCII characters with their key
+    pub fn text_steps(self, text: &str) -> Vec<MacroStep> {
+        let mut steps = Vec::new();
+        for c in text.chars() {
+            match ASCII_2_HID.get(c as usize) {
+                Some(&[key, _]) if key != 0 => steps.push(MacroStep::Text(c.into())),
+                _ => steps.extend(self.steps(c)),
+            }
+        }
+        steps
+    }
+}
+
+fn keypad_digit(digit: u8) -> u8 {
+    match digit {
+        0 => HID_KEY_KEYPAD_0,
+        digit => HID_KEY_KEYPAD_1 + digit - 1,
+    }
+}
+
+// The combining mark Synergy sends for each dead key, the accent it types alone, the letters
+// it composes with and the composed letters in the same order
+const DEAD_KEYS: [(char, char, &str, &str); 13] = [
+    ('\u{300}', '`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    (
+        '\u{301}',
+        '´',
+        "aceinosuyzACEINOSUYZ",
+        "áćéíńóśúýźÁĆÉÍŃÓŚÚÝŹ",
+    ),
+    ('\u{302}', '^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ('\u{303}', '~', "anoANO", "ãñõÃÑÕ"),
+    ('\u{304}', '¯', "aeiouAEIOU", "āēīōūĀĒĪŌŪ"),
+    ('\u{306}', '˘', "agAG", "ăğĂĞ"),
+    ('\u{307}', '˙', "ezEZ", "ėżĖŻ"),
+    ('\u{308}', '¨', "aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+    ('\u{30A}', '˚', "auAU", "åůÅŮ"),
+    ('\u{30B}', '˝', "ouOU", "őűŐŰ"),
+    ('\u{30C}', 'ˇ', "cdenrstzCDENRSTZ", "čďěňřšťžČĎĚŇŘŠŤŽ"),
+    ('\u{327}', '¸', "cstCST", "çşţÇŞŢ"),
+    ('\u{328}', '˛', "aeAE", "ąęĄĘ"),
+];
+
+/// What a key down turned into
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Composed {
+    /// A key pressed as usual
+    Key,
+    /// Nothing typed until the next key, after a dead key or the first half of a surrogate
+    /// pair
+    Pending,
+    /// Characters to type with the Unicode input
+    Text(String),
+}
+
+/// Composes the dead keys with the next key and joins the surrogate pairs, the key ids of
+/// DKDN are 16 bits so the characters past U+FFFF come in two key downs
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Composer {
+    // Combining mark of the dead key pressed last
+    dead: Option<char>,
+    high_surrogate: Option<u16>,
+}
+
+impl Composer {
+    /// `hid` is the usage `id` is mapped to, a character without one is typed
+    pub fn key_down(&mut self, id: u16, hid: KeyCode) -> Composed {
+        let c = match id {
+            0xD800..=0xDBFF => {
+                self.high_surrogate = Some(id);
+                return Composed::Pending;
+            }
+            0xDC00..=0xDFFF => self
+                .high_surrogate
+                .take()
+                .and_then(|high| char::decode_utf16([high, id]).next()?.ok()),
+            _ => {
+                self.high_surrogate = None;
+                is_character(id)
+                    .then(|| char::from_u32(id.into()))
+                    .flatten()
+            }
+        };
+        let Some(c) = c else {
+            // e.g. Shift for a capital after the dead key, which stays pending
+            return Composed::Key;
+        };
+        if let Some(&(mark, accent, ..)) = DEAD_KEYS.iter().find(|(mark, ..)| *mark == c) {
+            return match self.dead.replace(mark) {
+                // The same dead key twice types the accent
+                Some(pending) if pending == mark => {
+                    self.dead = None;
+                    Composed::Text(accent.into())
+                }
+                Some(pending) => Composed::Text(spacing_accent(pending).into()),
+                None => Composed::Pending,
+            };
+        }
+        if let Some(mark) = self.dead.take() {
+            let mut text = String::new();
+            match compose(mark, c) {
+                Some(composed) => text.push(composed),
+                None => {
+                    text.push(spacing_accent(mark));
+                    if c != ' ' {
+                        text.push(c);
+                    }
+                }
+            }
+            return Composed::Text(text);
+        }
+        match hid {
+            KeyCode::None => Composed::Text(c.into()),
+            _ => Composed::Key,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+// Key ids of characters, the special keys of Synergy are in the private use area
+fn is_character(id: u16) -> bool {
+    matches!(id, 0x20..=0x7E | 0xA0..=0xD7FF | 0xF900..=0xFFFD)
+}
+
+// The precomposed character of `base` with the combining `mark`
+fn compose(mark: char, base: char) -> Option<char> {
+    let (_, _, bases, composed) = DEAD_KEYS.iter().find(|(m, ..)| *m == mark)?;
+    let index = bases.chars().position(|b| b == base)?;
+    composed.chars().nth(index)
+}
+
+fn spacing_accent(mark: char) -> char {
+    DEAD_KEYS
+        .iter()
+        .find(|(m, ..)| *m == mark)
+        .map_or(mark, |&(_, accent, ..)| accent)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compose, Composed, Composer, UnicodeInput, DEAD_KEYS};
+    use crate::{
+        keycodes::{
+            HID_KEY_ALT_LEFT, HID_KEY_CONTROL_LEFT, HID_KEY_E, HID_KEY_KEYPAD_0, HID_KEY_KEYPAD_9,
+            HID_KEY_KEYPAD_ADD, HID_KEY_SHIFT_LEFT, HID_KEY_U,
+        },
+        synergy_to_hid, KeyCode, MacroStep,
+    };
+
+    fn text(s: &str) -> Composed {
+        Composed::Text(s.into())
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        for (mark, accent, bases, composed) in DEAD_KEYS {
+            assert_eq!(bases.chars().count(), composed.chars().count(), "{accent}");
+            for (base, composed) in bases.chars().zip(composed.chars()) {
+                assert_eq!(compose(mark, base), Some(composed));
+            }
+        }
+        assert_eq!(compose('\u{301}', 'x'), None);
+        assert_eq!(compose('\u{30D}', 'a'), None);
+
+        let mut composer = Composer::default();
+        let mut key_down = |id: u16| composer.key_down(id, synergy_to_hid(id));
+        assert_eq!(key_down(0x301), Composed::Pending);
+        assert_eq!(key_down('e' as u16), text("é"));
+        // The Shift of a capital keeps the dead key pending
+        assert_eq!(key_down(0x308), Composed::Pending);
+        assert_eq!(key_down(0xEFE1), Composed::Key);
+        assert_eq!(key_down('U' as u16), text("Ü"));
+        assert_eq!(key_down('e' as u16), Composed::Key);
+        // Space or the same dead key again type the accent
+        assert_eq!(key_down(0x302), Composed::Pending);
+        assert_eq!(key_down(' ' as u16), text("^"));
+        assert_eq!(key_down(0x300), Composed::Pending);
+        assert_eq!(key_down(0x300), text("`"));
+        // The accent is typed before a letter it doesn't compose with or another dead key
+        assert_eq!(key_down(0x303), Composed::Pending);
+        assert_eq!(key_down('x' as u16), text("~x"));
+        assert_eq!(key_down(0x30C), Composed::Pending);
+        assert_eq!(key_down(0x301), text("ˇ"));
+        assert_eq!(key_down('c' as u16), text("ć"));
+    }
+
+    #[test]
+    fn test_unicode_key_ids() {
+        let mut composer = Composer::default();
+        let mut key_down = |id: u16| composer.key_down(id, synergy_to_hid(id));
+        assert_eq!(key_down(0xE9), text("é"));
+        assert_eq!(key_down(0x20AC), text("€"));
+        // U+1F600 as a surrogate pair
+        assert_eq!(key_down(0xD83D), Composed::Pending);
+        assert_eq!(key_down(0xDE00), text("😀"));
+        // A lone half is dropped
+        assert_eq!(key_down(0xDE00), Composed::Key);
+        assert_eq!(key_down(0xD83D), Composed::Pending);
+        assert_eq!(key_down('a' as u16), Composed::Key);
+        assert_eq!(key_down(0xDE00), Composed::Key);
+        // Special keys and the keys of the layout are pressed
+        assert_eq!(key_down(0xEF0D), Composed::Key);
+        assert_eq!(key_down(0xE05F), Composed::Key);
+        assert_eq!(
+            composer.key_down('e' as u16, KeyCode::Key(HID_KEY_E)),
+            Composed::Key
+        );
+    }
+
+    #[test]
+    fn test_unicode_input_steps() {
+        use MacroStep::{Press, Release};
+
+        assert_eq!(UnicodeInput::Skip.steps('é'), []);
+        assert_eq!(
+            UnicodeInput::Linux.steps('é'),
+            [
+                Press(HID_KEY_CONTROL_LEFT),
+                Press(HID_KEY_SHIFT_LEFT),
+                Press(HID_KEY_U),
+                Release(HID_KEY_U),
+                Release(HID_KEY_SHIFT_LEFT),
+                Release(HID_KEY_CONTROL_LEFT),
+                MacroStep::Text("e9 ".into()),
+            ]
+        );
+        assert_eq!(
+            UnicodeInput::Windows.steps('é'),
+            [
+                Press(HID_KEY_ALT_LEFT),
+                Press(HID_KEY_KEYPAD_ADD),
+                Release(HID_KEY_KEYPAD_ADD),
+                MacroStep::Text("e".into()),
+                Press(HID_KEY_KEYPAD_9),
+                Release(HID_KEY_KEYPAD_9),
+                Release(HID_KEY_ALT_LEFT),
+            ]
+        );
+        // One sequence per UTF-16 unit, d83d then de00
+        let steps = UnicodeInput::Windows.steps('😀');
+        assert_eq!(steps.len(), 20);
+        assert_eq!(steps[10..13], steps[..3]);
+        assert_eq!(
+            steps[15..17],
+            [Press(HID_KEY_KEYPAD_0), Release(HID_KEY_KEYPAD_0)]
+        );
+        assert_eq!(
+            UnicodeInput::Linux.steps('😀').last(),
+            Some(&MacroStep::Text("1f600 ".into()))
+        );
+
+        let steps = UnicodeInput::Linux.text_steps("´e");
+        assert_eq!(steps.len(), 8);
+        assert_eq!(
+            steps[6..],
+            [MacroStep::Text("b4 ".into()), MacroStep::Text("e".into())]
+        );
+    }
+}
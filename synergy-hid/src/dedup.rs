@@ -0,0 +1,115 @@
+use crate::{Report, ReportType};
+
+/// Remembers the last report written for each type, to skip the ones that change nothing on
+/// the host, e.g. the same absolute position sent again while the mouse is still
+#[derive(Clone, Debug, Default)]
+pub struct ReportDedup {
+    // Indexed by the report id minus one
+    last: [Option<Report>; 5],
+    skipped: u64,
+}
+
+impl ReportDedup {
+    /// `true` if `report` has to be written, it's then remembered as the last one of its type.
+    /// Relative moves and wheel reports are always written, each of them moves the host
+    /// cursor or scrolls again.
+    pub fn check(&mut self, report: (ReportType, &[u8])) -> bool {
+        let last = &mut self.last[report.0 as usize - 1];
+        if !is_relative(report) && last.is_some_and(|last| last.as_parts() == report) {
+            self.skipped += 1;
+            return false;
+        }
+        *last = Some(Report::from(report));
+        true
+    }
+
+    /// Remembers a report written whatever the last one was, e.g. a key press that must reach
+    /// the host even if its report didn't change
+    pub fn force(&mut self, report: (ReportType, &[u8])) {
+        self.last[report.0 as usize - 1] = Some(Report::from(report));
+    }
+
+    /// Forgets the reports written, e.g. the host may have been replugged meanwhile
+    pub fn reset(&mut self) {
+        self.last = [None; 5];
+    }
+
+    /// Number of reports `check` skipped
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+// Reports with a delta, sending one twice moves twice
+fn is_relative((report_type, bytes): (ReportType, &[u8])) -> bool {
+    match report_type {
+        ReportType::RelativeMouse => true,
+        // Wheel and pan of the absolute mouse
+        ReportType::Mouse => bytes.iter().skip(5).any(|b| *b != 0),
+        ReportType::Keyboard | ReportType::Consumer | ReportType::Gamepad => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReportDedup;
+    use crate::{KeyMask, MouseMode, ReportType, SynergyHid};
+
+    #[test]
+    fn test_dedup_moves() {
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut dedup = ReportDedup::default();
+        let mut report = [0; SynergyHid::MAX_REPORT_LEN];
+        assert!(dedup.check(hid.set_cursor_position(100, 100, &mut report)));
+        assert!(!dedup.check(hid.set_cursor_position(100, 100, &mut report)));
+        assert!(dedup.check(hid.set_cursor_position(101, 100, &mut report)));
+        // The same scroll twice scrolls twice
+        assert!(dedup.check(hid.mouse_scroll(0, 120, &mut report)));
+        assert!(dedup.check(hid.mouse_scroll(0, 120, &mut report)));
+        // Back to the position without scrolling
+        assert!(dedup.check(hid.set_cursor_position(101, 100, &mut report)));
+        assert!(!dedup.check(hid.set_cursor_position(101, 100, &mut report)));
+        assert_eq!(dedup.skipped(), 2);
+
+        dedup.reset();
+        assert!(dedup.check(hid.set_cursor_position(101, 100, &mut report)));
+
+        let mut hid = SynergyHid::with_mouse_mode(1920, 1080, false, MouseMode::Relative);
+        assert!(dedup.check(hid.move_cursor(1, 0, &mut report)));
+        assert!(dedup.check(hid.move_cursor(1, 0, &mut report)));
+    }
+
+    #[test]
+    fn test_dedup_double_tap() {
+        // The press and release of a key tapped twice are forced, the clear after is skipped
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut dedup = ReportDedup::default();
+        let mut report = [0; SynergyHid::MAX_REPORT_LEN];
+        let mut written = vec![];
+        for _ in 0..2 {
+            for down in [true, false] {
+                let ret = match down {
+                    true => hid.key_down(0xE0B0, KeyMask::empty(), 1, &mut report),
+                    false => hid.key_up(0xE0B0, KeyMask::empty(), 1, &mut report),
+                };
+                dedup.force(ret);
+                written.push(ret.1.to_vec());
+            }
+        }
+        assert_eq!(
+            written,
+            [
+                vec![0xB5, 0, 0, 0, 0, 0, 0, 0, 0],
+                vec![0; 9],
+                vec![0xB5, 0, 0, 0, 0, 0, 0, 0, 0],
+                vec![0; 9],
+            ]
+        );
+        for report in hid.clear_all() {
+            if report.report_type() == ReportType::Consumer {
+                assert!(!dedup.check(report.as_parts()));
+            }
+        }
+        assert_eq!(dedup.skipped(), 1);
+    }
+}
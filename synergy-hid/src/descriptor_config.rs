@@ -0,0 +1,297 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{KeyboardMode, MouseMode, ReportType, SynergyHid};
+
+// Report descriptor items, the low 2 bits of the prefix are the size of the data
+const COLLECTION: u8 = 0xA0;
+const END_COLLECTION: u8 = 0xC0;
+const APPLICATION: u8 = 0x01;
+const REPORT_ID: u8 = 0x85;
+const LONG_ITEM: u8 = 0xFE;
+
+/// The HID devices to register and how, for hosts picky about the descriptors. The default
+/// is a device per interface without report IDs, the descriptors [`SynergyHid`] has for
+/// each report type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorConfig {
+    /// Each report starts with its report ID, see [`ReportType::report_id`]
+    pub use_report_ids: bool,
+    /// All the devices in one interface, the reports always have an ID then since the host
+    /// couldn't tell them apart otherwise
+    pub merge_into_single_interface: bool,
+    pub keyboard: KeyboardMode,
+    pub mouse: MouseMode,
+    /// Without it the consumer control reports have no device to go to
+    pub consumer: bool,
+    pub gamepad: bool,
+}
+
+impl Default for DescriptorConfig {
+    fn default() -> Self {
+        Self {
+            use_report_ids: false,
+            merge_into_single_interface: false,
+            keyboard: KeyboardMode::Boot,
+            mouse: MouseMode::Absolute,
+            consumer: true,
+            gamepad: false,
+        }
+    }
+}
+
+/// A report of an interface, as returned by [`SynergyHid`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReportInfo {
+    pub report_type: ReportType,
+    /// The byte written before the report, if the interface has report IDs
+    pub id: Option<u8>,
+    /// Length without the ID, the one of [`SynergyHid::report_len`]
+    pub len: usize,
+}
+
+/// A HID interface to register, e.g. a function of the USB gadget
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HidInterface {
+    pub descriptor: Vec<u8>,
+    pub reports: Vec<ReportInfo>,
+}
+
+impl HidInterface {
+    /// Length of the longest report with its ID, the report length of the interface
+    pub fn report_len(&self) -> usize {
+        self.reports
+            .iter()
+            .map(|report| report.len + report.id.is_some() as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn has_report_ids(&self) -> bool {
+        self.reports.iter().any(|report| report.id.is_some())
+    }
+
+    pub fn report(&self, report_type: ReportType) -> Option<&ReportInfo> {
+        self.reports
+            .iter()
+            .find(|report| report.report_type == report_type)
+    }
+}
+
+impl DescriptorConfig {
+    pub fn has_report_ids(&self) -> bool {
+        self.use_report_ids || self.merge_into_single_interface
+    }
+
+    /// The report types of the devices, in the order of the interfaces
+    pub fn report_types(&self) -> Vec<ReportType> {
+        let mouse = match self.mouse {
+            MouseMode::Absolute => ReportType::Mouse,
+            MouseMode::Relative => ReportType::RelativeMouse,
+        };
+        let mut report_types = vec![ReportType::Keyboard, mouse];
+        if self.consumer {
+            report_types.push(ReportType::Consumer);
+        }
+        if self.gamepad {
+            report_types.push(ReportType::Gamepad);
+        }
+        report_types
+    }
+
+    /// The report ID of `report_type`, `None` without report IDs
+    pub fn report_id(&self, report_type: ReportType) -> Option<u8> {
+        self.has_report_ids().then(|| report_type.report_id())
+    }
+
+    /// The interfaces to register, a single one if merged. Their reports are those of a
+    /// [`SynergyHid`] in the same keyboard and mouse mode, the ID must be written before
+    /// each of them when there is one.
+    pub fn build(&self) -> Vec<HidInterface> {
+        let interfaces = self.report_types().into_iter().map(|report_type| {
+            let (len, descriptor) = SynergyHid::get_report_descriptor(report_type, self.keyboard);
+            let id = self.report_id(report_type);
+            HidInterface {
+                descriptor: match id {
+                    Some(id) => with_report_id(descriptor, id),
+                    None => descriptor.to_vec(),
+                },
+                reports: vec![ReportInfo {
+                    report_type,
+                    id,
+                    len: len as usize,
+                }],
+            }
+        });
+        if !self.merge_into_single_interface {
+            return interfaces.collect();
+        }
+        let merged = interfaces.fold(
+            HidInterface {
+                descriptor: vec![],
+                reports: vec![],
+            },
+            |mut merged, interface| {
+                merged.descriptor.extend(interface.descriptor);
+                merged.reports.extend(interface.reports);
+                merged
+            },
+        );
+        vec![merged]
+    }
+}
+
+// The report ID item goes first in each top level application collection
+pub(crate) fn with_report_id(descriptor: &[u8], id: u8) -> Vec<u8> {
+    let mut out = Vec::with_capacity(descriptor.len() + 2);
+    let mut depth = 0usize;
+    let mut rest = descriptor;
+    while let Some(&prefix) = rest.first() {
+        let len = match prefix {
+            LONG_ITEM => 3 + rest.get(1).copied().unwrap_or(0) as usize,
+            _ => 1 + [0, 1, 2, 4][(prefix & 0x03) as usize],
+        };
+        let (item, next) = rest.split_at(len.min(rest.len()));
+        out.extend_from_slice(item);
+        match prefix & 0xFC {
+            COLLECTION => {
+                if depth == 0 && item.get(1) == Some(&APPLICATION) {
+                    out.extend([REPORT_ID, id]);
+                }
+                depth += 1;
+            }
+            END_COLLECTION => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        rest = next;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{with_report_id, DescriptorConfig, ReportInfo};
+    use crate::{
+        descriptors::{
+            ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR, BOOT_KEYBOARD_REPORT_DESCRIPTOR,
+            CONSUMER_CONTROL_REPORT_DESCRIPTOR, GAMEPAD_REPORT_DESCRIPTOR,
+            NKRO_KEYBOARD_REPORT_DESCRIPTOR, RELATIVE_WHEEL_MOUSE_REPORT_DESCRIPTOR,
+        },
+        KeyboardMode, MouseMode, ReportType,
+    };
+
+    #[test]
+    fn test_default_descriptors() {
+        // The same bytes as before there was a config, hosts must see no difference
+        let interfaces = DescriptorConfig::default().build();
+        let descriptors: Vec<&[u8]> = interfaces.iter().map(|i| &i.descriptor[..]).collect();
+        assert_eq!(
+            descriptors,
+            [
+                BOOT_KEYBOARD_REPORT_DESCRIPTOR,
+                ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR,
+                CONSUMER_CONTROL_REPORT_DESCRIPTOR,
+            ]
+        );
+        let lens: Vec<usize> = interfaces.iter().map(|i| i.report_len()).collect();
+        assert_eq!(lens, [8, 7, 9]);
+        assert!(interfaces.iter().all(|i| !i.has_report_ids()));
+
+        let interfaces = DescriptorConfig {
+            keyboard: KeyboardMode::Nkro,
+            mouse: MouseMode::Relative,
+            gamepad: true,
+            ..Default::default()
+        }
+        .build();
+        let descriptors: Vec<&[u8]> = interfaces.iter().map(|i| &i.descriptor[..]).collect();
+        assert_eq!(
+            descriptors,
+            [
+                NKRO_KEYBOARD_REPORT_DESCRIPTOR,
+                RELATIVE_WHEEL_MOUSE_REPORT_DESCRIPTOR,
+                CONSUMER_CONTROL_REPORT_DESCRIPTOR,
+                GAMEPAD_REPORT_DESCRIPTOR,
+            ]
+        );
+        let lens: Vec<usize> = interfaces.iter().map(|i| i.report_len()).collect();
+        assert_eq!(lens, [16, 5, 9, 12]);
+    }
+
+    #[test]
+    fn test_report_ids() {
+        let config = DescriptorConfig {
+            use_report_ids: true,
+            consumer: false,
+            ..Default::default()
+        };
+        let interfaces = config.build();
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(
+            interfaces[0].descriptor,
+            with_report_id(BOOT_KEYBOARD_REPORT_DESCRIPTOR, 1)
+        );
+        assert_eq!(
+            interfaces[1].reports,
+            [ReportInfo {
+                report_type: ReportType::Mouse,
+                id: Some(2),
+                len: 7,
+            }]
+        );
+        assert_eq!(interfaces[1].report_len(), 8);
+        assert!(interfaces[1].report(ReportType::Consumer).is_none());
+        assert_eq!(config.report_id(ReportType::Mouse), Some(2));
+        assert_eq!(
+            DescriptorConfig::default().report_id(ReportType::Mouse),
+            None
+        );
+
+        // Merged, the IDs are there even if not asked for
+        let config = DescriptorConfig {
+            merge_into_single_interface: true,
+            keyboard: KeyboardMode::Nkro,
+            ..Default::default()
+        };
+        let interfaces = config.build();
+        assert_eq!(interfaces.len(), 1);
+        let merged = &interfaces[0];
+        let ids: Vec<u8> = merged
+            .descriptor
+            .windows(2)
+            .filter(|item| item[0] == 0x85)
+            .map(|item| item[1])
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(
+            merged.descriptor.len(),
+            NKRO_KEYBOARD_REPORT_DESCRIPTOR.len()
+                + ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR.len()
+                + CONSUMER_CONTROL_REPORT_DESCRIPTOR.len()
+                + 6
+        );
+        assert_eq!(merged.report_len(), 17);
+        assert_eq!(merged.report(ReportType::Consumer).unwrap().id, Some(3));
+        assert_eq!(config.report_id(ReportType::Keyboard), Some(1));
+    }
+
+    #[test]
+    fn test_with_report_id() {
+        // Only in the top level application collection
+        let descriptor = [
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x02, // Usage (Mouse)
+            0xA1, 0x01, // Collection (Application)
+            0xA1, 0x00, //   Collection (Physical)
+            0x26, 0xFF, 0x7F, // Logical Maximum (32767)
+            0xC0, //   End Collection
+            0xC0, // End Collection
+        ];
+        assert_eq!(
+            with_report_id(&descriptor, 2),
+            [
+                0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x85, 0x02, 0xA1, 0x00, 0x26, 0xFF, 0x7F, 0xC0,
+                0xC0
+            ]
+        );
+    }
+}
@@ -41,6 +41,42 @@ pub const ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR: &[u8] = &[
     0xC0,              // End Collection
 ];
 
+// Boot protocol compatible, the first 3 bytes are buttons, X and Y
+#[rustfmt::skip]
+pub const RELATIVE_WHEEL_MOUSE_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop),
+    0x09, 0x02,        // Usage (Mouse),
+    0xA1, 0x01,        // Collection (Application),
+    0x09, 0x01,        //   Usage (Pointer),
+    0xA1, 0x00,        //   Collection (Physical),
+
+    0x05, 0x09,        //     Usage Page (Buttons),
+    0x19, 0x01,        //     Usage Minimum (1),
+    0x29, 0x08,        //     Usage Maximum (8),
+    0x15, 0x00,        //     Logical Minimum (0),
+    0x25, 0x01,        //     Logical Maximum (1),
+    0x95, 0x08,        //     Report Count (8),
+    0x75, 0x01,        //     Report Size (1),
+    0x81, 0x02,        //     Input (Data, Variable, Absolute),
+
+    0x05, 0x01,        //     Usage Page (Generic Desktop),
+    0x09, 0x30,        //     Usage (X),
+    0x09, 0x31,        //     Usage (Y),
+    0x09, 0x38,        //     Usage (Wheel)
+    0x15, 0x81,        //     Logical Minimum (-127),
+    0x25, 0x7F,        //     Logical Maximum (127),
+    0x75, 0x08,        //     Report Size (8),
+    0x95, 0x03,        //     Report Count (3),
+    0x81, 0x06,        //     Input (Data, Variable, Relative),
+    0x05, 0x0C,        //     Usage Page (Consumer)
+    0x0A, 0x38, 0x02,  //     Usage (AC Pan)
+    0x95, 0x01,        //     Report Count (1)
+    0x81, 0x06,        //     Input (Data, Variable, Relative),
+
+    0xC0,              //   End Collection
+    0xC0,              // End Collection
+];
+
 pub const BOOT_KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
     0x05, 0x01, // Usage Page (Generic Desktop),
     0x09, 0x06, // Usage (Keyboard),
@@ -61,7 +97,7 @@ pub const BOOT_KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
     0x05, 0x08, //     Usage Page (LEDs),
     0x19, 0x01, //     Usage Minimum (1),
     0x29, 0x05, //     Usage Maximum (5),
-    0x91, 0x02, //     Output (Data, Variable, Absolute), ;LED report
+    0x91, 0x02, //     Output (Data, Variable, Absolute), ;LED report, see LedState
     0x95, 0x01, //     Report Count (1),
     0x75, 0x03, //     Report Size (3),
     0x91, 0x01, //     Output (Constant), ;LED report padding
@@ -76,17 +112,100 @@ pub const BOOT_KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
     0xC0, // End Collection
 ];
 
+// Same modifiers and LEDs as the boot keyboard, followed by a bit per usage up to 0x77
+#[rustfmt::skip]
+pub const NKRO_KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop),
+    0x09, 0x06,        // Usage (Keyboard),
+    0xA1, 0x01,        // Collection (Application),
+    0x75, 0x01,        //     Report Size (1),
+    0x95, 0x08,        //     Report Count (8),
+    0x05, 0x07,        //     Usage Page (Key Codes),
+    0x19, 0xE0,        //     Usage Minimum (224),
+    0x29, 0xE7,        //     Usage Maximum (231),
+    0x15, 0x00,        //     Logical Minimum (0),
+    0x25, 0x01,        //     Logical Maximum (1),
+    0x81, 0x02,        //     Input (Data, Variable, Absolute), ;Modifier byte
+    0x95, 0x05,        //     Report Count (5),
+    0x75, 0x01,        //     Report Size (1),
+    0x05, 0x08,        //     Usage Page (LEDs),
+    0x19, 0x01,        //     Usage Minimum (1),
+    0x29, 0x05,        //     Usage Maximum (5),
+    0x91, 0x02,        //     Output (Data, Variable, Absolute), ;LED report, see LedState
+    0x95, 0x01,        //     Report Count (1),
+    0x75, 0x03,        //     Report Size (3),
+    0x91, 0x01,        //     Output (Constant), ;LED report padding
+    0x95, 0x78,        //     Report Count (120),
+    0x75, 0x01,        //     Report Size (1),
+    0x15, 0x00,        //     Logical Minimum (0),
+    0x25, 0x01,        //     Logical Maximum (1),
+    0x05, 0x07,        //     Usage Page (Key Codes),
+    0x19, 0x00,        //     Usage Minimum (0),
+    0x29, 0x77,        //     Usage Maximum (119),
+    0x81, 0x02,        //     Input (Data, Variable, Absolute), ;Key bitmap
+    0xC0,              // End Collection
+];
+
+// Buttons 1 to 16, the sticks on X, Y and Rx, Ry, the triggers on Z and Rz. The sticks are
+// centered on 0 so -32768 is left out, see `stick_axis`.
+#[rustfmt::skip]
+pub const GAMEPAD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop),
+    0x09, 0x05,        // Usage (Game Pad),
+    0xA1, 0x01,        // Collection (Application),
+    0x05, 0x09,        //   Usage Page (Buttons),
+    0x19, 0x01,        //   Usage Minimum (1),
+    0x29, 0x10,        //   Usage Maximum (16),
+    0x15, 0x00,        //   Logical Minimum (0),
+    0x25, 0x01,        //   Logical Maximum (1),
+    0x95, 0x10,        //   Report Count (16),
+    0x75, 0x01,        //   Report Size (1),
+    0x81, 0x02,        //   Input (Data, Variable, Absolute),
+
+    0x05, 0x01,        //   Usage Page (Generic Desktop),
+    0x09, 0x30,        //   Usage (X),
+    0x09, 0x31,        //   Usage (Y),
+    0x09, 0x33,        //   Usage (Rx),
+    0x09, 0x34,        //   Usage (Ry),
+    0x16, 0x01, 0x80,  //   Logical Minimum (-32767),
+    0x26, 0xFF, 0x7F,  //   Logical Maximum (32767),
+    0x95, 0x04,        //   Report Count (4),
+    0x75, 0x10,        //   Report Size (16),
+    0x81, 0x02,        //   Input (Data, Variable, Absolute),
+
+    0x09, 0x32,        //   Usage (Z),
+    0x09, 0x35,        //   Usage (Rz),
+    0x15, 0x00,        //   Logical Minimum (0),
+    0x26, 0xFF, 0x00,  //   Logical Maximum (255),
+    0x95, 0x02,        //   Report Count (2),
+    0x75, 0x08,        //   Report Size (8),
+    0x81, 0x02,        //   Input (Data, Variable, Absolute),
+    0xC0,              // End Collection
+];
+
+// The consumer usages up to AC Desktop Show All Applications, then the System Control keys.
+// Linux maps them anywhere, Windows only handles System Control in its own collection.
 #[rustfmt::skip]
 pub const CONSUMER_CONTROL_REPORT_DESCRIPTOR: &[u8] = &[
     0x05, 0x0C, // Usage Page (Consumer),
     0x09, 0x01, // Usage (Consumer Control),
     0xA1, 0x01, // Collection (Application),
     0x75, 0x10, //     Report Size(16)
-    0x95, 0x01, //     Report Count(1)
+    0x95, 0x04, //     Report Count(4)
     0x15, 0x00, //     Logical Minimum(0)
-    0x26, 0x9C, 0x02, //     Logical Maximum(0x029C)
+    0x26, 0xA2, 0x02, //     Logical Maximum(0x02A2)
     0x19, 0x00, //     Usage Minimum(0)
-    0x2A, 0x9C, 0x02, //     Usage Maximum(0x029C)
+    0x2A, 0xA2, 0x02, //     Usage Maximum(0x02A2)
     0x81, 0x00, //     Input (Array, Data, Variable)
+    0x05, 0x01, //     Usage Page (Generic Desktop),
+    0x19, 0x81, //     Usage Minimum (System Power Down),
+    0x29, 0x83, //     Usage Maximum (System Wake Up),
+    0x15, 0x00, //     Logical Minimum (0),
+    0x25, 0x01, //     Logical Maximum (1),
+    0x75, 0x01, //     Report Size (1),
+    0x95, 0x03, //     Report Count (3),
+    0x81, 0x02, //     Input (Data, Variable, Absolute), ;System Control bits
+    0x95, 0x05, //     Report Count (5),
+    0x81, 0x01, //     Input (Constant), ;Padding
     0xC0, // End Collection
 ];
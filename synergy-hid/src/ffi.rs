@@ -0,0 +1,277 @@
+//! C ABI for the report generation, the header `synergy_hid.h` is generated by cbindgen in
+//! `OUT_DIR` when building with the `ffi` feature.
+//!
+//! Reports are written to a buffer of the caller of at least [`SYNERGY_HID_MAX_REPORT_LEN`]
+//! bytes, the type and the length of the report are returned in the out-params. A length of
+//! 0 means there is nothing to write to the device. Panics don't cross the boundary, they
+//! are returned as [`SynergyHidStatus::Panic`].
+#![allow(clippy::too_many_arguments)]
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::{KeyMask, KeyboardMode, ReportType, SynergyHid, MAX_REPORT_LEN};
+
+/// Size of the report buffers passed to the functions
+pub const SYNERGY_HID_MAX_REPORT_LEN: usize = 16;
+
+/// Report types returned in `out_type`, the values of [`ReportType`]
+pub const SYNERGY_HID_REPORT_KEYBOARD: u8 = 1;
+pub const SYNERGY_HID_REPORT_MOUSE: u8 = 2;
+pub const SYNERGY_HID_REPORT_CONSUMER: u8 = 3;
+pub const SYNERGY_HID_REPORT_RELATIVE_MOUSE: u8 = 4;
+pub const SYNERGY_HID_REPORT_GAMEPAD: u8 = 5;
+
+// The header is generated from this file alone, the values are repeated here
+const _: () = assert!(SYNERGY_HID_MAX_REPORT_LEN == MAX_REPORT_LEN);
+const _: () = assert!(SYNERGY_HID_REPORT_KEYBOARD == ReportType::Keyboard as u8);
+const _: () = assert!(SYNERGY_HID_REPORT_MOUSE == ReportType::Mouse as u8);
+const _: () = assert!(SYNERGY_HID_REPORT_CONSUMER == ReportType::Consumer as u8);
+const _: () = assert!(SYNERGY_HID_REPORT_RELATIVE_MOUSE == ReportType::RelativeMouse as u8);
+const _: () = assert!(SYNERGY_HID_REPORT_GAMEPAD == ReportType::Gamepad as u8);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SynergyHidStatus {
+    Ok = 0,
+    NullPointer = 1,
+    /// The report buffer is shorter than `SYNERGY_HID_MAX_REPORT_LEN`
+    BufferTooSmall = 2,
+    InvalidReportType = 3,
+    /// The call panicked, the state of the instance may be inconsistent
+    Panic = 4,
+}
+
+fn report_type(report_type: u8) -> Option<ReportType> {
+    match report_type {
+        SYNERGY_HID_REPORT_KEYBOARD => Some(ReportType::Keyboard),
+        SYNERGY_HID_REPORT_MOUSE => Some(ReportType::Mouse),
+        SYNERGY_HID_REPORT_CONSUMER => Some(ReportType::Consumer),
+        SYNERGY_HID_REPORT_RELATIVE_MOUSE => Some(ReportType::RelativeMouse),
+        SYNERGY_HID_REPORT_GAMEPAD => Some(ReportType::Gamepad),
+        _ => None,
+    }
+}
+
+/// Runs `f` with the instance and the buffer of the caller, writing the type and length of
+/// the report returned
+///
+/// # Safety
+/// The pointers must be null or valid, `report` for `report_len` bytes.
+unsafe fn with_report<F>(
+    hid: *mut SynergyHid,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+    f: F,
+) -> SynergyHidStatus
+where
+    F: for<'a> FnOnce(&mut SynergyHid, &'a mut [u8]) -> (ReportType, &'a [u8]),
+{
+    if hid.is_null() || report.is_null() || out_type.is_null() || out_len.is_null() {
+        return SynergyHidStatus::NullPointer;
+    }
+    if report_len < SYNERGY_HID_MAX_REPORT_LEN {
+        return SynergyHidStatus::BufferTooSmall;
+    }
+    let hid = &mut *hid;
+    let report = std::slice::from_raw_parts_mut(report, report_len);
+    match catch_unwind(AssertUnwindSafe(|| {
+        let (report_type, report) = f(hid, report);
+        (report_type, report.len())
+    })) {
+        Ok((report_type, len)) => {
+            *out_type = report_type as u8;
+            *out_len = len;
+            SynergyHidStatus::Ok
+        }
+        Err(_) => SynergyHidStatus::Panic,
+    }
+}
+
+/// Creates an instance for a `width` x `height` screen with a boot keyboard and an absolute
+/// mouse, returns null if it can't be created. Free it with `synergy_hid_free`.
+#[no_mangle]
+pub extern "C" fn synergy_hid_new(
+    width: u16,
+    height: u16,
+    flip_mouse_wheel: bool,
+) -> *mut SynergyHid {
+    if width == 0 || height == 0 {
+        return std::ptr::null_mut();
+    }
+    catch_unwind(|| Box::into_raw(Box::new(SynergyHid::new(width, height, flip_mouse_wheel))))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// # Safety
+/// `hid` must be null or returned by `synergy_hid_new` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_free(hid: *mut SynergyHid) {
+    if !hid.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(hid))));
+    }
+}
+
+/// # Safety
+/// `hid` must be a live instance, `report` valid for `report_len` bytes and the out-params
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_key_down(
+    hid: *mut SynergyHid,
+    key: u16,
+    mask: u16,
+    button: u16,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.key_down(key, KeyMask::from_bits_retain(mask), button, report)
+    })
+}
+
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_key_up(
+    hid: *mut SynergyHid,
+    key: u16,
+    mask: u16,
+    button: u16,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.key_up(key, KeyMask::from_bits_retain(mask), button, report)
+    })
+}
+
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_mouse_down(
+    hid: *mut SynergyHid,
+    button: i8,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.mouse_down(button, report)
+    })
+}
+
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_mouse_up(
+    hid: *mut SynergyHid,
+    button: i8,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.mouse_up(button, report)
+    })
+}
+
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_mouse_scroll(
+    hid: *mut SynergyHid,
+    x: i16,
+    y: i16,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.mouse_scroll(x, y, report)
+    })
+}
+
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_set_cursor_position(
+    hid: *mut SynergyHid,
+    x: u16,
+    y: u16,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.set_cursor_position(x, y, report)
+    })
+}
+
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_move_cursor(
+    hid: *mut SynergyHid,
+    x: i16,
+    y: i16,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.move_cursor(x, y, report)
+    })
+}
+
+/// Releases everything on the report of `report_type`, a `SYNERGY_HID_REPORT_*` value
+///
+/// # Safety
+/// See `synergy_hid_key_down`.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_clear(
+    hid: *mut SynergyHid,
+    report_type: u8,
+    report: *mut u8,
+    report_len: usize,
+    out_type: *mut u8,
+    out_len: *mut usize,
+) -> SynergyHidStatus {
+    let Some(report_type) = self::report_type(report_type) else {
+        return SynergyHidStatus::InvalidReportType;
+    };
+    with_report(hid, report, report_len, out_type, out_len, |hid, report| {
+        hid.clear(report_type, report)
+    })
+}
+
+/// Returns the descriptor of the report of `report_type`, a `SYNERGY_HID_REPORT_*` value, and writes
+/// its length to `len`. The descriptor is static, it must not be freed. Returns null if the
+/// type is unknown.
+///
+/// # Safety
+/// `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn synergy_hid_report_descriptor(
+    report_type: u8,
+    len: *mut usize,
+) -> *const u8 {
+    let Some(report_type) = self::report_type(report_type) else {
+        return std::ptr::null();
+    };
+    if len.is_null() {
+        return std::ptr::null();
+    }
+    let (_, descriptor) = SynergyHid::get_report_descriptor(report_type, KeyboardMode::Boot);
+    *len = descriptor.len();
+    descriptor.as_ptr()
+}
@@ -1,3 +1,8 @@
+use alloc::vec::Vec;
+
+/// Size of the absolute mouse report, buttons, x, y, wheel and pan
+pub const ABS_MOUSE_REPORT_LEN: usize = 7;
+
 #[derive(Debug, Default)]
 pub struct AbsMouseReport {
     button: u8,
@@ -6,35 +11,43 @@ pub struct AbsMouseReport {
 }
 
 impl AbsMouseReport {
-    pub fn move_to(&mut self, x: u16, y: u16) -> [u8; 7] {
+    pub fn move_to(&mut self, x: u16, y: u16) -> [u8; ABS_MOUSE_REPORT_LEN] {
         self.x = x;
         self.y = y;
         self.send(None, None)
     }
 
-    pub fn mouse_down(&mut self, button: u8) -> [u8; 7] {
+    pub fn mouse_down(&mut self, button: u8) -> [u8; ABS_MOUSE_REPORT_LEN] {
         self.button |= button;
         self.send(None, None)
     }
 
-    pub fn mouse_up(&mut self, button: u8) -> [u8; 7] {
+    pub fn mouse_up(&mut self, button: u8) -> [u8; ABS_MOUSE_REPORT_LEN] {
         self.button &= !button;
         self.send(None, None)
     }
 
-    pub fn mouse_wheel(&mut self, scroll: i8, pan: i8) -> [u8; 7] {
+    pub fn mouse_wheel(&mut self, scroll: i8, pan: i8) -> [u8; ABS_MOUSE_REPORT_LEN] {
         self.send(scroll, pan)
     }
 
-    pub fn clear(&mut self) -> [u8; 7] {
+    pub fn clear(&mut self) -> [u8; ABS_MOUSE_REPORT_LEN] {
         self.button = 0;
         self.send(None, None)
     }
 
-    fn send<S: Into<Option<i8>>, P: Into<Option<i8>>>(&self, scroll: S, pan: P) -> [u8; 7] {
+    pub fn buttons(&self) -> u8 {
+        self.button
+    }
+
+    fn send<S: Into<Option<i8>>, P: Into<Option<i8>>>(
+        &self,
+        scroll: S,
+        pan: P,
+    ) -> [u8; ABS_MOUSE_REPORT_LEN] {
         let scroll = scroll.into().unwrap_or(0);
         let pan = pan.into().unwrap_or(0);
-        let mut report = [0u8; 7];
+        let mut report = [0u8; ABS_MOUSE_REPORT_LEN];
         report[0] = self.button;
         report[1] = (self.x & 0xff) as u8;
         report[2] = (self.x >> 8) as u8;
@@ -46,81 +59,151 @@ impl AbsMouseReport {
     }
 }
 
+/// Size of the boot protocol relative mouse report, buttons, x, y, wheel and pan
+pub const REL_MOUSE_REPORT_LEN: usize = 5;
+
+#[derive(Debug, Default)]
+pub struct RelMouseReport {
+    button: u8,
+}
+
+impl RelMouseReport {
+    pub fn move_by(&mut self, x: i8, y: i8) -> [u8; REL_MOUSE_REPORT_LEN] {
+        self.send(x, y, 0, 0)
+    }
+
+    pub fn mouse_down(&mut self, button: u8) -> [u8; REL_MOUSE_REPORT_LEN] {
+        self.button |= button;
+        self.send(0, 0, 0, 0)
+    }
+
+    pub fn mouse_up(&mut self, button: u8) -> [u8; REL_MOUSE_REPORT_LEN] {
+        self.button &= !button;
+        self.send(0, 0, 0, 0)
+    }
+
+    pub fn mouse_wheel(&mut self, scroll: i8, pan: i8) -> [u8; REL_MOUSE_REPORT_LEN] {
+        self.send(0, 0, scroll, pan)
+    }
+
+    pub fn clear(&mut self) -> [u8; REL_MOUSE_REPORT_LEN] {
+        self.button = 0;
+        self.send(0, 0, 0, 0)
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.button
+    }
+
+    fn send(&self, x: i8, y: i8, scroll: i8, pan: i8) -> [u8; REL_MOUSE_REPORT_LEN] {
+        [self.button, x as u8, y as u8, scroll as u8, pan as u8]
+    }
+}
+
+/// Size of the boot keyboard report, the modifiers, a reserved byte and 6 keys
+pub const BOOT_KEYBOARD_REPORT_LEN: usize = 8;
+
 #[derive(Debug, Default)]
 pub struct KeyboardReport {
     modifier: u8,
-    keycode: [u8; 6],
+    // Modifiers required by the server's modifier mask but not pressed
+    synthetic: u8,
+    // All non-modifier keys held, in the order they were pressed
+    keys: Vec<u8>,
 }
 
+// Usage reported in all key slots when more than 6 keys are held
+const HID_KEY_ERROR_ROLL_OVER: u8 = 0x01;
+
 impl KeyboardReport {
-    pub fn press(&mut self, key: u8) -> [u8; 8] {
+    pub fn press(&mut self, key: u8) -> [u8; BOOT_KEYBOARD_REPORT_LEN] {
         match self.get_modifier(key) {
             Some(modifier) => self.modifier |= modifier,
             None => {
                 // Don't add the same key twice
-                for i in 0..6 {
-                    if self.keycode[i] == key {
-                        return self.send();
-                    }
-                }
-
-                let mut found = false;
-                for i in 0..6 {
-                    if self.keycode[i] == 0 {
-                        self.keycode[i] = key;
-                        found = true;
-                        break;
-                    }
-                }
-                if !found {
-                    // roll over the first key
-                    for i in 1..6 {
-                        self.keycode.swap(i - 1, i);
-                    }
-                    self.keycode[6 - 1] = key;
+                if !self.keys.contains(&key) {
+                    self.keys.push(key);
                 }
             }
         }
         self.send()
     }
 
-    pub fn release(&mut self, key: u8) -> [u8; 8] {
+    pub fn release(&mut self, key: u8) -> [u8; BOOT_KEYBOARD_REPORT_LEN] {
         match self.get_modifier(key) {
-            Some(modifier) => self.modifier &= !modifier,
-            None => {
-                for i in 0..6 {
-                    if self.keycode[i] == key {
-                        self.keycode[i] = 0;
-                        break;
-                    }
-                }
-                // Compact the keycode array
-                let mut pos = 0;
-                for i in 0..6 {
-                    if self.keycode[i] != 0 {
-                        self.keycode.swap(i, pos);
-                        pos += 1;
-                    }
-                }
+            Some(modifier) => {
+                self.modifier &= !modifier;
+                self.synthetic &= !modifier;
             }
+            None => self.keys.retain(|k| *k != key),
         }
         self.send()
     }
 
-    pub fn clear(&mut self) -> [u8; 8] {
+    /// Non-modifier keys held in the order they were pressed, the report overflows if there are more than 6
+    pub fn held_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keys.iter().copied()
+    }
+
+    /// Modifier bits of the keys held, without the synthetic ones
+    pub fn modifiers(&self) -> u8 {
+        self.modifier
+    }
+
+    /// Modifier bits applied from the server's mask without their key held
+    pub fn synthetic_modifiers(&self) -> u8 {
+        self.synthetic
+    }
+
+    /// Nothing is reported as pressed
+    pub fn is_idle(&self) -> bool {
+        self.modifier | self.synthetic == 0 && self.keys.is_empty()
+    }
+
+    pub fn clear(&mut self) -> [u8; BOOT_KEYBOARD_REPORT_LEN] {
         self.modifier = 0;
-        self.keycode = [0; 6];
+        self.synthetic = 0;
+        self.keys.clear();
         self.send()
     }
 
-    fn send(&self) -> [u8; 8] {
-        let mut report = [0u8; 8];
-        report[0] = self.modifier;
+    /// The report as last sent
+    pub fn current(&self) -> [u8; BOOT_KEYBOARD_REPORT_LEN] {
+        self.send()
+    }
+
+    /// The report as last sent with the keys in ascending order, so it only depends on the
+    /// keys held and not on the order they were pressed
+    pub fn canonical(&self) -> [u8; BOOT_KEYBOARD_REPORT_LEN] {
+        let mut report = self.send();
+        // The roll over error is in every slot already
+        if self.keys.len() <= 6 {
+            report[2..(2 + self.keys.len())].sort_unstable();
+        }
+        report
+    }
+
+    /// Modifiers that are not pressed are applied to the following reports, until changed
+    pub fn set_synthetic_modifiers(&mut self, modifiers: u8) {
+        self.synthetic = modifiers & !self.modifier;
+    }
+
+    fn send(&self) -> [u8; BOOT_KEYBOARD_REPORT_LEN] {
+        let mut report = [0u8; BOOT_KEYBOARD_REPORT_LEN];
+        report[0] = self.modifier | self.synthetic;
         report[1] = 0;
-        report[2..(6 + 2)].copy_from_slice(&self.keycode);
+        if self.keys.len() > 6 {
+            report[2..(6 + 2)].fill(HID_KEY_ERROR_ROLL_OVER);
+        } else {
+            report[2..(2 + self.keys.len())].copy_from_slice(&self.keys);
+        }
         report
     }
 
+    pub fn is_modifier(&self, key: u8) -> bool {
+        self.get_modifier(key).is_some()
+    }
+
     fn get_modifier(&self, key: u8) -> Option<u8> {
         match key {
             0xE0 => Some(0x01), // Left Control
@@ -136,31 +219,202 @@ impl KeyboardReport {
     }
 }
 
+/// Highest usage in the NKRO bitmap, the keys of a full size keyboard up to F24 and Menu
+pub const NKRO_MAX_USAGE: u8 = 0x77;
+
+/// Size of the NKRO keyboard report, the modifiers and a bit per usage up to `NKRO_MAX_USAGE`
+pub const NKRO_REPORT_LEN: usize = 1 + (NKRO_MAX_USAGE as usize + 1) / 8;
+
+/// The keys of a [`KeyboardReport`] as a bitmap, so any number of them can be held.
+///
+/// BIOS/UEFI only understand the boot keyboard, keys above `NKRO_MAX_USAGE` such as the
+/// international ones can't be reported.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NkroKeyboardReport {
+    modifier: u8,
+    bitmap: [u8; NKRO_REPORT_LEN - 1],
+}
+
+impl NkroKeyboardReport {
+    pub fn to_bytes(&self) -> [u8; NKRO_REPORT_LEN] {
+        let mut report = [0u8; NKRO_REPORT_LEN];
+        report[0] = self.modifier;
+        report[1..].copy_from_slice(&self.bitmap);
+        report
+    }
+}
+
+impl From<&KeyboardReport> for NkroKeyboardReport {
+    fn from(keyboard: &KeyboardReport) -> Self {
+        let mut report = Self {
+            modifier: keyboard.modifier | keyboard.synthetic,
+            ..Default::default()
+        };
+        for key in keyboard.held_keys().filter(|key| *key <= NKRO_MAX_USAGE) {
+            report.bitmap[key as usize / 8] |= 1 << (key % 8);
+        }
+        report
+    }
+}
+
+// Number of consumer usages that can be held at the same time
+const CONSUMER_SLOTS: usize = 4;
+
+/// Size of the consumer control report, a 16-bit usage per slot and the System Control bits
+pub const CONSUMER_REPORT_LEN: usize = 2 * CONSUMER_SLOTS + 1;
+
+/// Generic Desktop usages of the System Control keys, reported as bits after the slots
+pub const SYSTEM_POWER_DOWN: u8 = 0x81;
+pub const SYSTEM_SLEEP: u8 = 0x82;
+pub const SYSTEM_WAKE_UP: u8 = 0x83;
+
+/// Up to 4 consumer usages held at the same time, e.g. Play/Pause and Volume Up, and the
+/// System Control keys
 #[derive(Debug, Default)]
 pub struct ConsumerReport {
-    code: u16,
+    codes: [u16; CONSUMER_SLOTS],
+    // Bit 0 is System Power Down
+    system: u8,
 }
 
 impl ConsumerReport {
-    pub fn press(&mut self, code: u16) -> [u8; 2] {
-        self.code = code;
+    /// Usages beyond the 4th are dropped until a slot is free
+    pub fn press(&mut self, code: u16) -> [u8; CONSUMER_REPORT_LEN] {
+        if !self.codes.contains(&code) {
+            match self.codes.iter_mut().find(|c| **c == 0) {
+                Some(slot) => *slot = code,
+                None => warn!("Too many consumer keys held, dropping {:#06x}", code),
+            }
+        }
+        self.send()
+    }
+
+    /// Only `code` is released, the other usages stay in the order they were pressed
+    pub fn release(&mut self, code: u16) -> [u8; CONSUMER_REPORT_LEN] {
+        if let Some(pos) = self.codes.iter().position(|c| *c == code) {
+            self.codes.copy_within(pos + 1.., pos);
+            self.codes[CONSUMER_SLOTS - 1] = 0;
+        }
         self.send()
     }
 
-    pub fn release(&mut self) -> [u8; 2] {
-        self.code = 0;
+    /// Presses a System Control usage, those outside Power Down..=Wake Up are ignored
+    pub fn press_system(&mut self, usage: u8) -> [u8; CONSUMER_REPORT_LEN] {
+        self.system |= Self::system_bit(usage);
         self.send()
     }
 
-    pub fn clear(&mut self) -> [u8; 2] {
-        self.code = 0;
+    pub fn release_system(&mut self, usage: u8) -> [u8; CONSUMER_REPORT_LEN] {
+        self.system &= !Self::system_bit(usage);
         self.send()
     }
 
-    fn send(&self) -> [u8; 2] {
-        let mut report = [0u8; 2];
-        report[0] = (self.code & 0xff) as u8;
-        report[1] = (self.code >> 8) as u8;
+    pub fn clear(&mut self) -> [u8; CONSUMER_REPORT_LEN] {
+        self.codes = [0; CONSUMER_SLOTS];
+        self.system = 0;
+        self.send()
+    }
+
+    /// Usages held in the order they were pressed
+    pub fn held(&self) -> impl Iterator<Item = u16> + '_ {
+        self.codes.iter().copied().take_while(|c| *c != 0)
+    }
+
+    /// System Control usages held, in ascending order
+    pub fn held_system(&self) -> impl Iterator<Item = u8> + '_ {
+        (SYSTEM_POWER_DOWN..=SYSTEM_WAKE_UP)
+            .filter(|usage| self.system & Self::system_bit(*usage) != 0)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.codes[0] == 0 && self.system == 0
+    }
+
+    fn system_bit(usage: u8) -> u8 {
+        match usage {
+            SYSTEM_POWER_DOWN..=SYSTEM_WAKE_UP => 1 << (usage - SYSTEM_POWER_DOWN),
+            _ => {
+                warn!("Unknown System Control usage {:#04x}", usage);
+                0
+            }
+        }
+    }
+
+    fn send(&self) -> [u8; CONSUMER_REPORT_LEN] {
+        let mut report = [0u8; CONSUMER_REPORT_LEN];
+        for (chunk, code) in report.as_chunks_mut::<2>().0.iter_mut().zip(self.codes) {
+            *chunk = code.to_le_bytes();
+        }
+        report[2 * CONSUMER_SLOTS] = self.system;
         report
     }
 }
+
+/// Size of the gamepad report, 16 buttons, the X and Y axes of both sticks and the triggers
+pub const GAMEPAD_REPORT_LEN: usize = 2 + 4 * 2 + 2;
+
+/// A gamepad with 16 buttons, two sticks and two triggers, as sent by the server for the
+/// game controllers. The buttons are the bits of XInput, e.g. A is the 13th.
+#[derive(Debug, Default)]
+pub struct GamepadReport {
+    buttons: u16,
+    // X, Y, Rx and Ry in the logical range of the descriptor
+    axes: [i16; 4],
+    // Z and Rz
+    triggers: [u8; 2],
+}
+
+impl GamepadReport {
+    pub fn set_buttons(&mut self, buttons: u16) -> [u8; GAMEPAD_REPORT_LEN] {
+        self.buttons = buttons;
+        self.send()
+    }
+
+    /// `left` and `right` are the X and Y of each stick as sent by the server
+    pub fn set_sticks(&mut self, left: (i16, i16), right: (i16, i16)) -> [u8; GAMEPAD_REPORT_LEN] {
+        self.axes = [
+            stick_axis(left.0, false),
+            stick_axis(left.1, true),
+            stick_axis(right.0, false),
+            stick_axis(right.1, true),
+        ];
+        self.send()
+    }
+
+    pub fn set_triggers(&mut self, left: u8, right: u8) -> [u8; GAMEPAD_REPORT_LEN] {
+        self.triggers = [left, right];
+        self.send()
+    }
+
+    /// Releases the buttons and the triggers and centers the sticks
+    pub fn clear(&mut self) -> [u8; GAMEPAD_REPORT_LEN] {
+        *self = Self::default();
+        self.send()
+    }
+
+    fn send(&self) -> [u8; GAMEPAD_REPORT_LEN] {
+        let mut report = [0u8; GAMEPAD_REPORT_LEN];
+        report[..2].copy_from_slice(&self.buttons.to_le_bytes());
+        for (chunk, axis) in report[2..10]
+            .as_chunks_mut::<2>()
+            .0
+            .iter_mut()
+            .zip(self.axes)
+        {
+            *chunk = axis.to_le_bytes();
+        }
+        report[10..].copy_from_slice(&self.triggers);
+        report
+    }
+}
+
+/// Maps a stick axis from the server, -32768 to 32767 with Y up, to the logical range of the
+/// gamepad descriptor, -32767 to 32767 centered on 0 with Y down
+pub fn stick_axis(value: i16, y: bool) -> i16 {
+    let value = value.max(-i16::MAX);
+    if y {
+        -value
+    } else {
+        value
+    }
+}
@@ -41,6 +41,7 @@ const EXT_TAB: [u8; 256] = [
 ];
 // 0xE000 - 0xE0FF
 const MEDIA_TAB: [u16; 256] = [
+    0x0000, 0x00B8, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
@@ -48,7 +49,6 @@ const MEDIA_TAB: [u16; 256] = [
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
-    0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0032,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
@@ -56,8 +56,8 @@ const MEDIA_TAB: [u16; 256] = [
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0224, 0x0225,
     0x0227, 0x0226, 0x0221, 0x022A, 0x0223, 0x00E2, 0x00EA, 0x00E9, 0x00B5, 0x00B6, 0x00B7, 0x00CD,
-    0x018A, 0x0000, 0x0000, 0x0000, 0x0070, 0x006F, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
-    0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+    0x018A, 0x0183, 0x0194, 0x0192, 0x0070, 0x006F, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+    0x029F, 0x02A2, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
     0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
@@ -348,12 +348,17 @@ pub enum KeyCode {
     None,
     Key(u8),
     Consumer(u16),
+    /// Generic Desktop System Control usage, e.g. 0x82 for System Sleep
+    System(u8),
 }
 
 pub fn synergy_to_hid(id: u16) -> KeyCode {
     if id == 0xEE20 {
         // HACK: Synergy sends kKeyLeftTab(0xEE20) when the pressing GUI+SHIFT+TAB, but kKeyTab when pressing GUI+TAB.
         KeyCode::Key(0x2B)
+    } else if id == 0xE05F {
+        // kKeySleep is System Sleep, hosts act on it like the sleep button of a keyboard
+        KeyCode::System(crate::SYSTEM_SLEEP)
     } else if id < 0x100 {
         if TABLE[id as usize] == 0 {
             KeyCode::None
@@ -377,24 +382,18 @@ pub fn synergy_to_hid(id: u16) -> KeyCode {
     }
 }
 
-// MOUSE_BUTTON_LEFT     = TU_BIT(0), ///< Left button
-// MOUSE_BUTTON_RIGHT    = TU_BIT(1), ///< Right button
-// MOUSE_BUTTON_MIDDLE   = TU_BIT(2), ///< Middle button
-// MOUSE_BUTTON_BACKWARD = TU_BIT(3), ///< Backward button,
-// MOUSE_BUTTON_FORWARD  = TU_BIT(4), ///< Forward button,
-
+/// HID button of a Synergy button, 0 if it has none. Synergy has the middle button second
+/// and the right one third, the other buttons keep their number up to the 8 of the report.
 pub fn synergy_mouse_button(button: i8) -> u8 {
     match button {
-        // MOUSE_BUTTON_LEFT
-        1 => 0x01,
-        // MOUSE_BUTTON_MIDDLE
-        2 => 0x04,
-        // MOUSE_BUTTON_RIGHT
-        3 => 0x02,
-        // MOUSE_BUTTON_BACKWARD
-        4 => 0x08,
-        // MOUSE_BUTTON_FORWARD
-        5 => 0x10,
+        // Left
+        1 => 1,
+        // Middle
+        2 => 3,
+        // Right
+        3 => 2,
+        // Back, forward and the extra buttons
+        4..=8 => button as u8,
         _ => 0,
     }
 }
@@ -0,0 +1,148 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use serde::Deserialize;
+
+use crate::{synergy_to_hid, KeyCode};
+
+/// One entry of a keymap file, a key with none of `key`, `consumer` and `system` is suppressed
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeymapEntry {
+    /// Synergy key id, e.g. 0xEFE5 for Caps Lock
+    pub synergy: u16,
+    /// HID keyboard usage
+    #[serde(default)]
+    pub key: Option<u8>,
+    /// HID consumer control usage
+    #[serde(default)]
+    pub consumer: Option<u16>,
+    /// Generic Desktop System Control usage, 0x81 Power Down, 0x82 Sleep or 0x83 Wake Up
+    #[serde(default)]
+    pub system: Option<u8>,
+}
+
+/// Synergy to HID mapping overriding the builtin table, keys not listed fall through to it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "Vec<KeymapEntry>")]
+pub struct KeymapOverride {
+    keys: BTreeMap<u16, KeyCode>,
+}
+
+impl KeymapOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `synergy` to `code`, `KeyCode::None` suppresses the key
+    pub fn insert(&mut self, synergy: u16, code: KeyCode) {
+        if !is_known(synergy) {
+            warn!("Unknown Synergy key {:#06x} in the keymap", synergy);
+        }
+        self.keys.insert(synergy, code);
+    }
+
+    pub fn get(&self, synergy: u16) -> KeyCode {
+        self.keys
+            .get(&synergy)
+            .copied()
+            .unwrap_or_else(|| synergy_to_hid(synergy))
+    }
+
+    /// The key is mapped to nothing on purpose
+    pub fn is_suppressed(&self, synergy: u16) -> bool {
+        self.keys.get(&synergy) == Some(&KeyCode::None)
+    }
+}
+
+impl FromIterator<(u16, KeyCode)> for KeymapOverride {
+    fn from_iter<T: IntoIterator<Item = (u16, KeyCode)>>(iter: T) -> Self {
+        let mut keymap = Self::new();
+        for (synergy, code) in iter {
+            keymap.insert(synergy, code);
+        }
+        keymap
+    }
+}
+
+impl From<Vec<KeymapEntry>> for KeymapOverride {
+    fn from(entries: Vec<KeymapEntry>) -> Self {
+        entries
+            .into_iter()
+            .map(|entry| {
+                let codes = [
+                    entry.key.map(KeyCode::Key),
+                    entry.consumer.map(KeyCode::Consumer),
+                    entry.system.map(KeyCode::System),
+                ];
+                let mut codes = codes.into_iter().flatten();
+                let code = codes.next().unwrap_or(KeyCode::None);
+                if codes.next().is_some() {
+                    warn!(
+                        "Synergy key {:#06x} has more than one usage, using {:?}",
+                        entry.synergy, code
+                    );
+                }
+                (entry.synergy, code)
+            })
+            .collect()
+    }
+}
+
+// Printable keys are sent as Unicode characters, special keys are in the private use area
+fn is_known(synergy: u16) -> bool {
+    match synergy {
+        0x0000 => false,
+        0xD800..=0xDFFF => false,
+        0xE000..=0xE0FF | 0xEE00..=0xEFFF => true,
+        0xE100..=0xF8FF => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyCode, KeymapOverride};
+
+    #[test]
+    fn test_keymap_override() {
+        let keymap: KeymapOverride = serde_yaml::from_str(
+            r#"
+            # Swap Caps Lock and Left Ctrl
+            - synergy: 0xEFE5
+              key: 0xE0
+            - synergy: 0xEFE3
+              key: 0x39
+            # Backslash to Non-US Backslash
+            - synergy: 0x5C
+              key: 0x64
+            # kKeyEject powers the host down
+            - synergy: 0xE001
+              system: 0x81
+            # Both usages, the key wins
+            - synergy: 0xE0B3
+              key: 0x2C
+              consumer: 0xCD
+            # Suppress Scroll Lock
+            - synergy: 0xEF14
+            "#,
+        )
+        .unwrap();
+
+        // Overrides
+        assert_eq!(keymap.get(0xEFE5), KeyCode::Key(0xE0));
+        assert_eq!(keymap.get(0xEFE3), KeyCode::Key(0x39));
+        assert_eq!(keymap.get(0x5C), KeyCode::Key(0x64));
+        assert_eq!(keymap.get(0xE001), KeyCode::System(0x81));
+        assert_eq!(KeymapOverride::new().get(0xE001), KeyCode::Consumer(0xB8));
+        assert_eq!(keymap.get(0xE0B3), KeyCode::Key(0x2C));
+
+        // Fall through to the builtin table
+        assert_eq!(keymap.get('a' as u16), KeyCode::Key(0x04));
+        assert_eq!(keymap.get(0xE0AD), KeyCode::Consumer(0xE2));
+        assert!(!keymap.is_suppressed('a' as u16));
+
+        // Explicitly suppressed, kKeyScrollLock(0xEF14) is known to the builtin table
+        assert_eq!(keymap.get(0xEF14), KeyCode::None);
+        assert!(keymap.is_suppressed(0xEF14));
+        assert_ne!(KeymapOverride::new().get(0xEF14), KeyCode::None);
+    }
+}
@@ -1,31 +1,226 @@
-use log::{debug, warn};
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeSet, VecDeque},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::time::Duration;
+
+#[macro_use]
+mod logging;
+
+mod button_map;
+mod buttons;
+mod compose;
+mod dedup;
+mod descriptor_config;
 mod descriptors;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod hid;
 mod keycodes;
+mod keymap;
+mod macros;
+mod report;
+mod scancodes;
+mod state;
+mod watchdog;
 
+pub use barrier_proto::{clamp_move, KeyMask, LedState};
+pub use button_map::{ButtonMap, ButtonMapEntry, ButtonTarget, Pan};
+use buttons::ServerButtons;
+pub use compose::UnicodeInput;
+use compose::{Composed, Composer};
+pub use dedup::ReportDedup;
+pub use descriptor_config::{DescriptorConfig, HidInterface, ReportInfo};
 pub(crate) use hid::*;
-pub(crate) use keycodes::{synergy_mouse_button, synergy_to_hid, KeyCode};
+pub use keycodes::KeyCode;
+pub(crate) use keycodes::{synergy_mouse_button, synergy_to_hid};
+pub use keymap::{KeymapEntry, KeymapOverride};
+use macros::MacroAction;
+pub use macros::{MacroEntry, MacroStep, MacroTable};
+pub use report::{
+    ConsumerHidReport, GamepadHidReport, HidReport, KeyboardHidReport, MouseHidReport,
+    NkroKeyboardHidReport, RelativeMouseHidReport,
+};
+pub use scancodes::ServerPlatform;
+pub use state::HidState;
+use watchdog::KeyWatchdog;
+pub use watchdog::StuckKeyTimeouts;
 
 pub(crate) use descriptors::{
     ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR, BOOT_KEYBOARD_REPORT_DESCRIPTOR,
-    CONSUMER_CONTROL_REPORT_DESCRIPTOR,
+    CONSUMER_CONTROL_REPORT_DESCRIPTOR, GAMEPAD_REPORT_DESCRIPTOR, NKRO_KEYBOARD_REPORT_DESCRIPTOR,
+    RELATIVE_WHEEL_MOUSE_REPORT_DESCRIPTOR,
 };
 
+/// Size of the buffer to pass to the methods returning a report, the NKRO keyboard report
+/// is the longest
+pub const MAX_REPORT_LEN: usize = NKRO_REPORT_LEN;
+
+const _: () = assert!(BOOT_KEYBOARD_REPORT_LEN <= MAX_REPORT_LEN);
+const _: () = assert!(ABS_MOUSE_REPORT_LEN <= MAX_REPORT_LEN);
+const _: () = assert!(REL_MOUSE_REPORT_LEN <= MAX_REPORT_LEN);
+const _: () = assert!(CONSUMER_REPORT_LEN <= MAX_REPORT_LEN);
+const _: () = assert!(GAMEPAD_REPORT_LEN <= MAX_REPORT_LEN);
+
+/// Largest multiplier of `SynergyHid::with_scroll_speed`
+pub const MAX_SCROLL_SPEED: f32 = 64.0;
+
+// Scroll speed of 1 in fixed point
+const SCROLL_SPEED_ONE: i32 = 256;
+// Wheel delta of a notch sent by the server
+const WHEEL_NOTCH: i32 = 120;
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ReportType {
     Keyboard = 1,
     Mouse = 2,
     Consumer = 3,
+    RelativeMouse = 4,
+    /// Only registered if the gadget forwards the game controllers of the server
+    Gamepad = 5,
+}
+
+impl ReportType {
+    /// The ID of the reports when the descriptor has report IDs, see [`DescriptorConfig`]
+    pub fn report_id(self) -> u8 {
+        self as u8
+    }
+}
+
+/// A report copied out of the buffer it was written to, e.g. to be written later
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    report_type: ReportType,
+    len: usize,
+    data: [u8; MAX_REPORT_LEN],
+}
+
+impl Report {
+    pub fn report_type(&self) -> ReportType {
+        self.report_type
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    /// The type and the bytes as returned by the methods writing to a buffer
+    pub fn as_parts(&self) -> (ReportType, &[u8]) {
+        (self.report_type, self.as_bytes())
+    }
+
+    /// Nothing to write, see [`SynergyHid::key_down`]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl From<(ReportType, &[u8])> for Report {
+    /// Panics if the report is longer than `MAX_REPORT_LEN`, which none returned by
+    /// [`SynergyHid`] is
+    fn from((report_type, bytes): (ReportType, &[u8])) -> Self {
+        let mut data = [0; MAX_REPORT_LEN];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Self {
+            report_type,
+            len: bytes.len(),
+            data,
+        }
+    }
+}
+
+/// What the actuator does next for the macro running, see [`SynergyHid::pending_macro`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MacroEvent<'a> {
+    /// Write the report
+    Report(ReportType, &'a [u8]),
+    /// Wait before the next event
+    Delay(Duration),
+}
+
+/// How the cursor position is reported to the host
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MouseMode {
+    /// Absolute positions, screen coordinates are scaled to 0..=0x7fff
+    #[default]
+    Absolute,
+    /// Boot protocol compatible relative moves, for BIOS/UEFI and KVMs that don't support absolute pointers
+    Relative,
+}
+
+/// How the keys held are reported to the host
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum KeyboardMode {
+    /// Boot protocol keyboard, up to 6 keys besides the modifiers, understood by BIOS/UEFI
+    #[default]
+    Boot,
+    /// A bit per key, any number of keys can be held but only usages up to 0x77
+    Nkro,
 }
 
 #[derive(Debug)]
 pub struct SynergyHid {
-    flip_mouse_wheel: bool,
+    // Whether the horizontal and the vertical wheel are flipped
+    flip_wheel: (bool, bool),
+    // Multipliers of the horizontal and the vertical wheel deltas, in 1/256
+    scroll_speed: (i32, i32),
+    mouse_mode: MouseMode,
+    keyboard_mode: KeyboardMode,
+    width: u16,
+    height: u16,
+    // Cursor position in screen coordinates
     x: u16,
     y: u16,
-    server_buttons: [u16; 512],
+    // Relative motion not sent yet, only used in relative mode
+    pending_x: i32,
+    pending_y: i32,
+    // Wheel deltas not sent yet, in 1/120 of a notch
+    wheel_x: i32,
+    wheel_y: i32,
+    // Remainders of the deltas times the scroll speed, in 1/256 of the above
+    wheel_fraction: (i32, i32),
+    // Key id and usage of the key held on each button, the usage is looked up on the key
+    // down so the key up releases it even if the id is 0
+    server_buttons: ServerButtons,
+    server_platform: ServerPlatform,
+    keymap: KeymapOverride,
+    half_duplex_caps_lock: bool,
+    half_duplex_num_lock: bool,
+    // Half-duplex lock key pressed and not released yet
+    pending_release: Option<u8>,
+    lock_sync: bool,
+    // Lock state of the host from its last LED report and the lock keys pressed since,
+    // `None` until it sends one
+    host_locks: Option<LedState>,
+    macros: MacroTable,
+    // Buttons of the macro triggers held, their key up is suppressed too
+    macro_triggers: Vec<u16>,
+    // Steps of the macro running, `None` once the held keys are restored
+    macro_actions: Option<VecDeque<MacroAction>>,
+    // Keys pressed by the macro, kept apart so the keys held on the server are untouched
+    macro_report: KeyboardReport,
+    // Boot keyboard reports list the keys in ascending order instead of the pressed one
+    canonical_reports: bool,
+    // Keys without a HID usage already logged, the server sends them on every press
+    unmapped: BTreeSet<u16>,
+    button_map: ButtonMap,
+    // Mouse buttons without a HID button already logged
+    unmapped_buttons: BTreeSet<i8>,
+    stuck_key_timeouts: StuckKeyTimeouts,
+    // Last press or repeat of the keys held, for `tick`
+    key_watchdog: KeyWatchdog,
+    unicode_input: UnicodeInput,
+    // Dead key or half of a surrogate pair waiting for the next key down
+    composer: Composer,
+    // Bumped by each clear, see `generation`
+    generation: u64,
 
     // Report 1
     keyboard_report: KeyboardReport,
@@ -33,212 +228,2734 @@ pub struct SynergyHid {
     mouse_report: AbsMouseReport,
     // Report 3
     consumer_report: ConsumerReport,
+    // Report 4
+    rel_mouse_report: RelMouseReport,
+    // Report 5
+    gamepad_report: GamepadReport,
 }
 
 impl SynergyHid {
-    pub fn new(flip_mouse_wheel: bool) -> Self {
-        Self {
+    /// Size of a buffer holding any report, see `report_len` for the size of each
+    pub const MAX_REPORT_LEN: usize = MAX_REPORT_LEN;
+
+    /// Cursor positions are in the coordinates of a `width` x `height` screen, the size
+    /// reported to the server
+    pub fn new(width: u16, height: u16, flip_mouse_wheel: bool) -> Self {
+        Self::with_mouse_mode(width, height, flip_mouse_wheel, MouseMode::Absolute)
+    }
+
+    pub fn with_mouse_mode(
+        width: u16,
+        height: u16,
+        flip_mouse_wheel: bool,
+        mouse_mode: MouseMode,
+    ) -> Self {
+        Self::with_keymap(
+            width,
+            height,
             flip_mouse_wheel,
+            mouse_mode,
+            KeymapOverride::default(),
+        )
+    }
+
+    /// Keys are looked up in `keymap` before the builtin table
+    pub fn with_keymap(
+        width: u16,
+        height: u16,
+        flip_mouse_wheel: bool,
+        mouse_mode: MouseMode,
+        keymap: KeymapOverride,
+    ) -> Self {
+        Self {
+            flip_wheel: (flip_mouse_wheel, flip_mouse_wheel),
+            scroll_speed: (SCROLL_SPEED_ONE, SCROLL_SPEED_ONE),
+            mouse_mode,
+            keyboard_mode: KeyboardMode::Boot,
+            width: width.max(1),
+            height: height.max(1),
             x: 0,
             y: 0,
-            server_buttons: [0; 512],
+            pending_x: 0,
+            pending_y: 0,
+            wheel_x: 0,
+            wheel_y: 0,
+            wheel_fraction: (0, 0),
+            server_buttons: ServerButtons::default(),
+            server_platform: ServerPlatform::Unknown,
+            keymap,
+            half_duplex_caps_lock: false,
+            half_duplex_num_lock: false,
+            pending_release: None,
+            lock_sync: false,
+            host_locks: None,
+            macros: MacroTable::default(),
+            macro_triggers: vec![],
+            macro_actions: None,
+            macro_report: KeyboardReport::default(),
+            canonical_reports: false,
+            unmapped: BTreeSet::new(),
+            button_map: ButtonMap::default(),
+            unmapped_buttons: BTreeSet::new(),
+            stuck_key_timeouts: StuckKeyTimeouts::default(),
+            key_watchdog: KeyWatchdog::default(),
+            unicode_input: UnicodeInput::Skip,
+            composer: Composer::default(),
+            generation: 0,
             keyboard_report: KeyboardReport::default(),
             mouse_report: AbsMouseReport::default(),
             consumer_report: ConsumerReport::default(),
+            rel_mouse_report: RelMouseReport::default(),
+            gamepad_report: GamepadReport::default(),
+        }
+    }
+
+    /// The keyboard report is the boot one unless changed here, the gadget has to be
+    /// registered with the descriptor of the same mode
+    pub fn with_keyboard_mode(mut self, keyboard_mode: KeyboardMode) -> Self {
+        self.keyboard_mode = keyboard_mode;
+        self
+    }
+
+    /// Keys sent with the id 0 are looked up by their button in the scan codes of `platform`,
+    /// they are dropped by default
+    pub fn with_server_platform(mut self, platform: ServerPlatform) -> Self {
+        self.server_platform = platform;
+        self
+    }
+
+    /// Keys matching a trigger of `macros` run its steps instead, see `pending_macro`
+    pub fn with_macros(mut self, macros: MacroTable) -> Self {
+        self.macros = macros;
+        self
+    }
+
+    /// Mouse buttons are looked up in `buttons` before the builtin mapping, e.g. to swap two
+    /// of them or scroll sideways
+    pub fn with_button_map(mut self, buttons: ButtonMap) -> Self {
+        self.button_map = buttons;
+        self
+    }
+
+    /// Keys held for longer than `timeouts` without a repeat are released by `tick`, e.g. when
+    /// the key up was lost. Keys are never released by default.
+    pub fn with_stuck_key_timeouts(mut self, timeouts: StuckKeyTimeouts) -> Self {
+        self.stuck_key_timeouts = timeouts;
+        self
+    }
+
+    /// Characters without a key in the US layout and the dead keys are typed with `input`
+    /// as a macro, see `pending_macro`. They're dropped by default.
+    pub fn with_unicode_input(mut self, input: UnicodeInput) -> Self {
+        self.unicode_input = input;
+        self
+    }
+
+    /// Caps Lock and Num Lock are toggled on the host when they differ from the server, see
+    /// `sync_locks`. Off by default.
+    pub fn with_lock_sync(mut self, sync: bool) -> Self {
+        self.lock_sync = sync;
+        self
+    }
+
+    /// Keys are listed in ascending order in the boot keyboard reports, so a report only
+    /// depends on the keys held, e.g. for golden reports in tests. The NKRO bitmap is always
+    /// in that order.
+    pub fn with_canonical_reports(mut self, canonical: bool) -> Self {
+        self.canonical_reports = canonical;
+        self
+    }
+
+    /// Flips the horizontal and the vertical wheel separately, e.g. for natural scrolling on
+    /// one axis only, instead of both with `flip_mouse_wheel`
+    pub fn with_wheel_flip(mut self, x: bool, y: bool) -> Self {
+        self.flip_wheel = (x, y);
+        self
+    }
+
+    /// Multiplies the horizontal and the vertical wheel deltas, e.g. 3 for a host scrolling a
+    /// line per report. Fractions add up, at 0.5 every other notch scrolls. The speeds are
+    /// clamped to 0-64 in steps of 1/256, 1 by default.
+    pub fn with_scroll_speed(mut self, x: f32, y: f32) -> Self {
+        self.scroll_speed = (scroll_speed(x), scroll_speed(y));
+        self
+    }
+
+    pub fn scroll_speed(&self) -> (f32, f32) {
+        let speed = |s: i32| s as f32 / SCROLL_SPEED_ONE as f32;
+        (speed(self.scroll_speed.0), speed(self.scroll_speed.1))
+    }
+
+    pub fn mouse_mode(&self) -> MouseMode {
+        self.mouse_mode
+    }
+
+    pub fn keyboard_mode(&self) -> KeyboardMode {
+        self.keyboard_mode
+    }
+
+    pub fn screen_size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Cursor position in screen coordinates
+    pub fn cursor_position(&self) -> (u16, u16) {
+        (self.x, self.y)
+    }
+
+    /// Sets where the host has the cursor without sending a report, e.g. the position of a
+    /// `SynergyHid` replaced after a config reload, so the next relative move starts from
+    /// there. It's clamped to the screen.
+    pub fn set_position(&mut self, x: u16, y: u16) {
+        self.x = x.min(self.width - 1);
+        self.y = y.min(self.height - 1);
+    }
+
+    /// Number of clears so far. The reports returned since the last clear are of this
+    /// generation, those of an older one are stale, e.g. for a writer to drop the reports it
+    /// hasn't written yet when a clear comes in.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Starts a new generation and returns it, the reports returned before are stale.
+    /// `clear_all` calls it.
+    pub fn begin_clear(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    /// Continues the generations of a `SynergyHid` replaced after a config reload, so they
+    /// keep increasing
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// The display of the host changed, positions are in the coordinates of the new size from
+    /// now on. The cursor is clamped to it, no report is sent.
+    pub fn set_screen_size(&mut self, width: u16, height: u16) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.set_position(self.x, self.y);
+    }
+
+    /// The server sends a key down when Caps Lock turns on and a key up when it turns off,
+    /// both are sent to the host as a press, followed by the release from `pending_key`
+    pub fn set_half_duplex_caps_lock(&mut self, half_duplex: bool) {
+        self.half_duplex_caps_lock = half_duplex;
+    }
+
+    /// Same as `set_half_duplex_caps_lock` for Num Lock
+    pub fn set_half_duplex_num_lock(&mut self, half_duplex: bool) {
+        self.half_duplex_num_lock = half_duplex;
+    }
+
+    fn is_half_duplex(&self, key: u8) -> bool {
+        (key == keycodes::HID_KEY_CAPS_LOCK && self.half_duplex_caps_lock)
+            || (key == keycodes::HID_KEY_NUM_LOCK && self.half_duplex_num_lock)
+    }
+
+    /// The LEDs of the last output report of the host, see `parse_output_report`. It's the
+    /// lock state `sync_locks` compares with the server.
+    pub fn set_led_state(&mut self, leds: LedState) {
+        self.host_locks = Some(leds);
+    }
+
+    /// The LEDs of the host with the lock keys pressed since its last report, `None` until
+    /// `set_led_state` is called
+    pub fn led_state(&self) -> Option<LedState> {
+        self.host_locks
+    }
+
+    /// Taps Caps Lock and Num Lock through `emit` where the host doesn't have them as in the
+    /// `mask` of a key event, before the event is applied, e.g. after Caps Lock was toggled
+    /// on the host's own keyboard. It does nothing before the host sent its LED state, nor
+    /// for the events of the lock keys themselves: servers disagree on whether their mask
+    /// has the new state already.
+    pub fn sync_locks<F: FnMut((ReportType, &[u8]))>(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        report: &mut [u8],
+        mut emit: F,
+    ) {
+        if !self.lock_sync || self.macro_actions.is_some() {
+            return;
+        }
+        let Some(locks) = self.host_locks else {
+            return;
+        };
+        let hid = match self.server_buttons.get(button) {
+            Some((_, hid)) => hid,
+            None => self.resolve(key, button),
+        };
+        if matches!(hid, KeyCode::Key(key) if is_lock_key(key)) {
+            return;
+        }
+        for (key, server, host) in [
+            (
+                keycodes::HID_KEY_CAPS_LOCK,
+                KeyMask::CAPS_LOCK,
+                locks.caps_lock,
+            ),
+            (
+                keycodes::HID_KEY_NUM_LOCK,
+                KeyMask::NUM_LOCK,
+                locks.num_lock,
+            ),
+        ] {
+            // A lock key held, e.g. by a half-duplex press, is released first
+            if mask.contains(server) == host || self.held_keys().any(|k| k == key) {
+                continue;
+            }
+            debug!("Lock key {:#04x} toggled to match the server", key);
+            self.keyboard_report.press(key);
+            emit(fill(&self.keyboard(), report));
+            self.keyboard_report.release(key);
+            emit(fill(&self.keyboard(), report));
+            self.toggled(key);
+        }
+    }
+
+    // A press of a lock key toggles it on the host, the LED report follows later
+    fn toggled(&mut self, key: u8) {
+        if let Some(locks) = &mut self.host_locks {
+            match key {
+                keycodes::HID_KEY_CAPS_LOCK => locks.caps_lock = !locks.caps_lock,
+                keycodes::HID_KEY_NUM_LOCK => locks.num_lock = !locks.num_lock,
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the release of a half-duplex lock key pressed by the last key down or up
+    pub fn pending_key<'a>(&mut self, report: &'a mut [u8]) -> Option<(ReportType, &'a [u8])> {
+        Some(fill(&self.pending_key_report()?, report))
+    }
+
+    /// [`SynergyHid::pending_key`] returning the report by value
+    pub fn pending_key_report(&mut self) -> Option<HidReport> {
+        let key = self.pending_release.take()?;
+        self.keyboard_report.release(key);
+        Some(self.keyboard())
+    }
+
+    /// HID usages of the non-modifier keys held, more than 6 can't be reported in boot mode
+    pub fn held_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keyboard_report.held_keys()
+    }
+
+    /// Consumer control usages held in the order they were pressed, at most 4
+    pub fn held_consumer_keys(&self) -> impl Iterator<Item = u16> + '_ {
+        self.consumer_report.held()
+    }
+
+    /// Modifier bits of the keys held, in the layout of the first byte of the keyboard report
+    pub fn modifiers(&self) -> u8 {
+        self.keyboard_report.modifiers()
+    }
+
+    /// No key is held on the keyboard or the consumer control, so clearing them would be a no-op
+    pub fn is_keyboard_idle(&self) -> bool {
+        self.keyboard_report.is_idle() && self.consumer_report.is_idle()
+    }
+
+    /// Buttons held on the mouse of the current mode, bit 0 is the left button
+    pub fn mouse_buttons(&self) -> u8 {
+        match self.mouse_mode {
+            MouseMode::Absolute => self.mouse_report.buttons(),
+            MouseMode::Relative => self.rel_mouse_report.buttons(),
+        }
+    }
+
+    /// Everything held and not sent yet, the keys are the ones of the server even while a
+    /// macro runs
+    pub fn state(&self) -> HidState {
+        HidState {
+            cursor: (self.x, self.y),
+            pending_motion: (self.pending_x, self.pending_y),
+            pending_wheel: (self.wheel_x, self.wheel_y),
+            mouse_buttons: self.mouse_buttons(),
+            modifiers: self.keyboard_report.modifiers(),
+            synthetic_modifiers: self.keyboard_report.synthetic_modifiers(),
+            keys: self.keyboard_report.held_keys().collect(),
+            consumer: self.consumer_report.held().collect(),
+            system: self.consumer_report.held_system().collect(),
+            buttons: self.server_buttons.held(),
+            macro_running: self.macro_actions.is_some(),
         }
     }
 
-    pub fn get_report_descriptor(report_type: ReportType) -> (u8, &'static [u8]) {
+    /// `state` as text, for debug logs
+    pub fn dump_state(&self) -> String {
+        self.state().to_string()
+    }
+
+    /// Report length and descriptor, the keyboard ones depend on `keyboard_mode`
+    pub fn get_report_descriptor(
+        report_type: ReportType,
+        keyboard_mode: KeyboardMode,
+    ) -> (u8, &'static [u8]) {
         match report_type {
-            ReportType::Keyboard => (8, BOOT_KEYBOARD_REPORT_DESCRIPTOR),
-            ReportType::Mouse => (7, ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR),
-            ReportType::Consumer => (2, CONSUMER_CONTROL_REPORT_DESCRIPTOR),
+            ReportType::Keyboard => match keyboard_mode {
+                KeyboardMode::Boot => (
+                    BOOT_KEYBOARD_REPORT_LEN as u8,
+                    BOOT_KEYBOARD_REPORT_DESCRIPTOR,
+                ),
+                KeyboardMode::Nkro => (NKRO_REPORT_LEN as u8, NKRO_KEYBOARD_REPORT_DESCRIPTOR),
+            },
+            ReportType::Mouse => (
+                ABS_MOUSE_REPORT_LEN as u8,
+                ABSOLUTE_WHEEL_MOUSE_REPORT_DESCRIPTOR,
+            ),
+            ReportType::Consumer => (
+                CONSUMER_REPORT_LEN as u8,
+                CONSUMER_CONTROL_REPORT_DESCRIPTOR,
+            ),
+            ReportType::RelativeMouse => (
+                REL_MOUSE_REPORT_LEN as u8,
+                RELATIVE_WHEEL_MOUSE_REPORT_DESCRIPTOR,
+            ),
+            ReportType::Gamepad => (GAMEPAD_REPORT_LEN as u8, GAMEPAD_REPORT_DESCRIPTOR),
+        }
+    }
+
+    /// Length of the reports of `report_type` with the keyboard mode of this instance, the
+    /// one registered with the descriptor. The buffer passed to the methods returning a
+    /// report must hold at least this many bytes, they panic otherwise.
+    pub fn report_len(&self, report_type: ReportType) -> usize {
+        Self::get_report_descriptor(report_type, self.keyboard_mode).0 as usize
+    }
+
+    /// Only the keyboard has an output report, a 1 byte LED bitmap in both modes
+    pub fn parse_output_report(report_type: ReportType, report: &[u8]) -> Option<LedState> {
+        match (report_type, report) {
+            (ReportType::Keyboard, [bits, ..]) => Some(LedState::from_bits(*bits)),
+            _ => None,
         }
     }
 
+    /// Presses the key mapped from the Synergy key id, `button` identifies it for the key up.
+    /// Keys with the id 0 are mapped from the button, see `with_server_platform`.
+    ///
+    /// A key without a HID usage changes nothing, the report returned is empty and should
+    /// not be written, so the keys held stay held.
     pub fn key_down<'a>(
         &mut self,
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
         report: &'a mut [u8],
     ) -> (ReportType, &'a [u8]) {
-        debug!("Key down {key} {mask} {button}");
-        self.server_buttons[button as usize] = key;
-        let hid = synergy_to_hid(key);
+        fill_some(self.key_down_report(key, mask, button), report)
+    }
+
+    /// [`SynergyHid::key_down`] returning the report by value, `None` if there is nothing to
+    /// write
+    pub fn key_down_report(&mut self, key: u16, mask: KeyMask, button: u16) -> Option<HidReport> {
+        debug!("Key down {key} {} {button}", mask.bits());
+        if let Some(steps) = self.macros.get(key, mask) {
+            self.macro_triggers.push(button);
+            // Keys typed by a macro are HID usages, a trigger can only come from the server
+            if self.macro_actions.is_some() {
+                warn!(
+                    "Macro on key {:#04x} triggered while another runs, ignored",
+                    key
+                );
+                return None;
+            }
+            debug!("Macro on key {:#04x} triggered", key);
+            self.macro_actions = Some(macros::expand(steps));
+            // The keys held are released for the host while the macro runs
+            self.macro_report = KeyboardReport::default();
+            return Some(self.render_keyboard(&self.macro_report));
+        }
+        let hid = self.resolve(key, button);
+        if self.unicode_input != UnicodeInput::Skip && !self.keymap.is_suppressed(key) {
+            match self.composer.key_down(key, hid) {
+                Composed::Key => {}
+                // Like a macro trigger, the key up and repeats are dropped
+                Composed::Pending => {
+                    self.macro_triggers.push(button);
+                    return None;
+                }
+                Composed::Text(text) => {
+                    self.macro_triggers.push(button);
+                    if self.macro_actions.is_some() {
+                        warn!("{:?} typed while a macro runs, ignored", text.as_str());
+                        return None;
+                    }
+                    debug!("Key {:#04x} types {:?}", key, text.as_str());
+                    let steps = self.unicode_input.text_steps(&text);
+                    self.macro_actions = Some(macros::expand(&steps));
+                    self.macro_report = KeyboardReport::default();
+                    return Some(self.render_keyboard(&self.macro_report));
+                }
+            }
+        }
+        // Nothing is held if neither the id nor the button are known
+        let held = (key != 0 || hid != KeyCode::None).then_some((key, hid));
+        self.server_buttons.set(button, held);
+        if held.is_some() {
+            self.key_watchdog.touch(button);
+        }
         debug!("Key Down {:#04x} -> Keycode: {:?}", key, hid);
         match hid {
+            KeyCode::None if self.keymap.is_suppressed(key) => {
+                debug!("Key {:#04x} suppressed by the keymap", key);
+                Some(self.keyboard())
+            }
             KeyCode::None => {
-                warn!("Keycode not found");
-                report[..8].copy_from_slice(&self.keyboard_report.clear());
-                (ReportType::Keyboard, &report[0..8])
+                self.warn_unmapped(key);
+                None
             }
             KeyCode::Key(key) => {
-                report[..8].copy_from_slice(&self.keyboard_report.press(key));
-                (ReportType::Keyboard, &report[0..8])
-            }
-            KeyCode::Consumer(key) => {
-                report[..2].copy_from_slice(&self.consumer_report.press(key));
-                (ReportType::Consumer, &report[0..2])
+                if self.keyboard_mode == KeyboardMode::Nkro && key > NKRO_MAX_USAGE {
+                    warn!("Key {:#04x} can't be reported by the NKRO keyboard", key);
+                }
+                // e.g. the server sends 'A' with Shift in the mask but no Shift key down
+                self.keyboard_report
+                    .set_synthetic_modifiers(mask.to_hid_modifier_byte());
+                self.keyboard_report.press(key);
+                self.toggled(key);
+                if self.is_half_duplex(key) {
+                    self.pending_release = Some(key);
+                }
+                Some(self.keyboard())
             }
+            KeyCode::Consumer(key) => Some(HidReport::consumer(self.consumer_report.press(key))),
+            KeyCode::System(usage) => Some(HidReport::consumer(
+                self.consumer_report.press_system(usage),
+            )),
         }
     }
 
+    /// Releases the key pressed with `button`, like [`SynergyHid::key_down`] the report is
+    /// empty if there is nothing to release.
+    ///
+    /// A key up with button 0 and no key down on it releases every key of the keyboard.
     pub fn key_up<'a>(
         &mut self,
         key: u16,
-        mask: u16,
+        mask: KeyMask,
         button: u16,
         report: &'a mut [u8],
     ) -> (ReportType, &'a [u8]) {
-        debug!("Key down {key} {mask} {button}");
-        let key = self.server_buttons[button as usize];
-        let hid = if self.server_buttons[button as usize] != 0 {
-            debug!("Key {key} up");
-            self.server_buttons[button as usize] = 0;
-            synergy_to_hid(key)
-        } else if key == 0 {
-            debug!("Key 0 up, clear all key down");
-            KeyCode::None
-        } else {
-            warn!("Key {key} up with no key down");
-            KeyCode::None
+        fill_some(self.key_up_report(key, mask, button), report)
+    }
+
+    /// [`SynergyHid::key_up`] returning the report by value, `None` if there is nothing to
+    /// write
+    pub fn key_up_report(&mut self, key: u16, mask: KeyMask, button: u16) -> Option<HidReport> {
+        debug!("Key up {key} {} {button}", mask.bits());
+        if let Some(index) = self.macro_triggers.iter().position(|&b| b == button) {
+            self.macro_triggers.swap_remove(index);
+            return None;
+        }
+        let (key, hid) = match self.server_buttons.get(button) {
+            None if button == 0 => {
+                debug!("Key up with button 0, clear all key down");
+                self.keyboard_report.clear();
+                return Some(self.keyboard());
+            }
+            None if self.key_watchdog.take_released(button) => {
+                debug!("Key {key} up, already released as stuck");
+                return None;
+            }
+            None => {
+                warn!("Key {key} up with no key down");
+                return None;
+            }
+            Some(pressed) => pressed,
         };
-        debug!("Key Down {:#04x} -> Keycode: {:?}", key, hid);
+        debug!("Key {key} up");
+        self.server_buttons.set(button, None);
+        self.key_watchdog.forget(button);
+        debug!("Key Up {:#04x} -> Keycode: {:?}", key, hid);
         match hid {
+            KeyCode::None if self.keymap.is_suppressed(key) => Some(self.keyboard()),
             KeyCode::None => {
-                warn!("Keycode not found");
-                report[..8].copy_from_slice(&self.keyboard_report.clear());
-                (ReportType::Keyboard, &report[0..8])
+                self.warn_unmapped(key);
+                None
             }
             KeyCode::Key(key) => {
-                report[..8].copy_from_slice(&self.keyboard_report.release(key));
-                (ReportType::Keyboard, &report[0..8])
+                // The mask has the modifiers still held on the server, the keys held may
+                // depend on them
+                self.keyboard_report
+                    .set_synthetic_modifiers(mask.to_hid_modifier_byte());
+                if self.is_half_duplex(key) {
+                    // Toggles the lock off
+                    self.keyboard_report.press(key);
+                    self.toggled(key);
+                    self.pending_release = Some(key);
+                    return Some(self.keyboard());
+                }
+                self.keyboard_report.release(key);
+                Some(self.keyboard())
+            }
+            KeyCode::Consumer(key) => Some(HidReport::consumer(self.consumer_report.release(key))),
+            KeyCode::System(usage) => Some(HidReport::consumer(
+                self.consumer_report.release_system(usage),
+            )),
+        }
+    }
+
+    // Logs the first press of a key without a HID usage, the next ones would flood the log
+    fn warn_unmapped(&mut self, key: u16) {
+        if self.unmapped.insert(key) {
+            warn!("Keycode not found for key {:#04x}", key);
+        } else {
+            debug!("Keycode not found for key {:#04x}", key);
+        }
+    }
+
+    // The keymap first, then the button for the keys the server sends without an id
+    fn resolve(&self, key: u16, button: u16) -> KeyCode {
+        match self.keymap.get(key) {
+            KeyCode::None if key == 0 => {
+                let hid = self.server_platform.button_to_hid(button);
+                debug!("Key id 0, button {:#06x} -> Keycode: {:?}", button, hid);
+                hid
+            }
+            hid => hid,
+        }
+    }
+
+    /// Emits a release/press report pair per repeat through `emit`, the key stays pressed afterwards.
+    /// Modifier keys are not toggled.
+    pub fn key_repeat<F: FnMut((ReportType, &[u8]))>(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+        report: &mut [u8],
+        mut emit: F,
+    ) {
+        debug!("Key repeat {key} {} {button} {count}", mask.bits());
+        if self.macro_triggers.contains(&button) {
+            return;
+        }
+        self.key_watchdog.touch(button);
+        let (key, hid) = match self.server_buttons.get(button) {
+            Some(pressed) => pressed,
+            None => {
+                let hid = self.resolve(key, button);
+                self.server_buttons.set(button, Some((key, hid)));
+                (key, hid)
+            }
+        };
+        debug!("Key Repeat {:#04x} -> Keycode: {:?}", key, hid);
+        if let KeyCode::Key(_) = hid {
+            self.keyboard_report
+                .set_synthetic_modifiers(mask.to_hid_modifier_byte());
+        }
+        for _ in 0..count {
+            match hid {
+                KeyCode::None => {
+                    if !self.keymap.is_suppressed(key) {
+                        self.warn_unmapped(key);
+                    }
+                    return;
+                }
+                // Power and sleep act once, repeating them would wake the host right away
+                KeyCode::System(_) => return,
+                KeyCode::Key(key) => {
+                    // Repeating a half-duplex lock key would toggle it
+                    if self.keyboard_report.is_modifier(key) || self.is_half_duplex(key) {
+                        return;
+                    }
+                    self.keyboard_report.release(key);
+                    emit(fill(&self.keyboard(), report));
+                    self.keyboard_report.press(key);
+                    emit(fill(&self.keyboard(), report));
+                }
+                KeyCode::Consumer(key) => {
+                    let released = HidReport::consumer(self.consumer_report.release(key));
+                    emit(fill(&released, report));
+                    let pressed = HidReport::consumer(self.consumer_report.press(key));
+                    emit(fill(&pressed, report));
+                }
+            }
+        }
+    }
+
+    /// Presses the HID usages in order then releases them in reverse, e.g. a shortcut such as
+    /// Ctrl+Alt+L, through `emit`. Keys already held stay held.
+    pub fn tap_keys<F: FnMut((ReportType, &[u8]))>(
+        &mut self,
+        keys: &[u8],
+        report: &mut [u8],
+        mut emit: F,
+    ) {
+        debug!("Tap keys {:02x?}", keys);
+        let mut pressed = vec![];
+        for &key in keys {
+            let held = (self.modifiers(), self.held_keys().count());
+            self.keyboard_report.press(key);
+            // Nothing changed if the key is already held, it's left as is
+            if (self.modifiers(), self.held_keys().count()) != held {
+                pressed.push(key);
+                emit(fill(&self.keyboard(), report));
+            }
+        }
+        for &key in pressed.iter().rev() {
+            self.keyboard_report.release(key);
+            emit(fill(&self.keyboard(), report));
+        }
+    }
+
+    /// Returns the next event of the macro triggered by the last key down, `None` once it's
+    /// done. The last report restores the keys still held on the server, e.g. the modifiers
+    /// of the trigger. Drain it before handling the next key.
+    pub fn pending_macro<'a>(&mut self, report: &'a mut [u8]) -> Option<MacroEvent<'a>> {
+        let actions = self.macro_actions.as_mut()?;
+        let Some(action) = actions.pop_front() else {
+            self.macro_actions = None;
+            self.macro_report = KeyboardReport::default();
+            let (report_type, report) = fill(&self.keyboard(), report);
+            return Some(MacroEvent::Report(report_type, report));
+        };
+        match action {
+            MacroAction::Press(key) => {
+                self.macro_report.press(key);
+            }
+            MacroAction::Release(key) => {
+                self.macro_report.release(key);
+            }
+            MacroAction::Delay(delay) => return Some(MacroEvent::Delay(delay)),
+        }
+        let (report_type, report) = fill(&self.render_keyboard(&self.macro_report), report);
+        Some(MacroEvent::Report(report_type, report))
+    }
+
+    /// Runs `steps` like a macro triggered from the server, e.g. text typed on request.
+    /// Returns `false` if a macro is already running, the steps are dropped then.
+    pub fn start_macro(&mut self, steps: &[MacroStep]) -> bool {
+        if self.macro_actions.is_some() {
+            warn!("A macro is already running, steps ignored");
+            return false;
+        }
+        self.macro_actions = Some(macros::expand(steps));
+        self.macro_report = KeyboardReport::default();
+        true
+    }
+
+    // The keyboard report of the current mode
+    fn keyboard(&self) -> HidReport {
+        self.render_keyboard(&self.keyboard_report)
+    }
+
+    fn render_keyboard(&self, keyboard_report: &KeyboardReport) -> HidReport {
+        match self.keyboard_mode {
+            KeyboardMode::Boot if self.canonical_reports => {
+                HidReport::keyboard(keyboard_report.canonical())
             }
-            KeyCode::Consumer(_key) => {
-                report[..2].copy_from_slice(&self.consumer_report.release());
-                (ReportType::Consumer, &report[0..2])
+            KeyboardMode::Boot => HidReport::keyboard(keyboard_report.current()),
+            KeyboardMode::Nkro => {
+                HidReport::nkro_keyboard(NkroKeyboardReport::from(keyboard_report).to_bytes())
             }
         }
     }
 
+    /// The position is in screen coordinates, clamped to the screen. In absolute mode it's
+    /// scaled to 0..=0x7fff, in relative mode the screen is assumed to be in the same unit as
+    /// the host's cursor, the move is accumulated from the last position and may need more
+    /// reports, see `pending_motion`.
     pub fn set_cursor_position<'a>(
         &mut self,
         x: u16,
         y: u16,
         report: &'a mut [u8],
     ) -> (ReportType, &'a [u8]) {
+        fill(&self.set_cursor_position_report(x, y), report)
+    }
+
+    /// [`SynergyHid::set_cursor_position`] returning the report by value
+    pub fn set_cursor_position_report(&mut self, x: u16, y: u16) -> HidReport {
+        let x = x.min(self.width - 1);
+        let y = y.min(self.height - 1);
+        if self.mouse_mode == MouseMode::Relative {
+            self.pending_x += x as i32 - self.x as i32;
+            self.pending_y += y as i32 - self.y as i32;
+            (self.x, self.y) = (x, y);
+            return self.relative_step();
+        }
         (self.x, self.y) = (x, y);
-        report[..7].copy_from_slice(&self.mouse_report.move_to(x, y));
-        (ReportType::Mouse, &report[..7])
+        let (abs_x, abs_y) = (scale_to_abs(x, self.width), scale_to_abs(y, self.height));
+        HidReport::mouse(self.mouse_report.move_to(abs_x, abs_y))
     }
 
+    /// Moves by a delta as sent with DMRM, the position is clamped to the screen so moving back
+    /// from an edge doesn't have to make up for the part past it
     pub fn move_cursor<'a>(
         &mut self,
         x: i16,
         y: i16,
         report: &'a mut [u8],
     ) -> (ReportType, &'a [u8]) {
-        self.set_cursor_position(
-            (self.x as i32 + x as i32) as u16,
-            (self.y as i32 + y as i32) as u16,
-            report,
+        fill(&self.move_cursor_report(x, y), report)
+    }
+
+    /// [`SynergyHid::move_cursor`] returning the report by value
+    pub fn move_cursor_report(&mut self, x: i16, y: i16) -> HidReport {
+        if self.mouse_mode == MouseMode::Relative {
+            // The whole move is sent, pushing against the edge keeps the host cursor in sync
+            self.pending_x += x as i32;
+            self.pending_y += y as i32;
+            (self.x, self.y) = self.moved_by(x, y);
+            return self.relative_step();
+        }
+        let (x, y) = self.moved_by(x, y);
+        self.set_cursor_position_report(x, y)
+    }
+
+    fn moved_by(&self, x: i16, y: i16) -> (u16, u16) {
+        (
+            clamp_move(self.x, x, self.width.saturating_sub(1)),
+            clamp_move(self.y, y, self.height.saturating_sub(1)),
         )
     }
 
+    /// Relative moves are limited to i8 range, returns the next report until the cursor has
+    /// arrived at the last position set.
+    pub fn pending_motion<'a>(&mut self, report: &'a mut [u8]) -> Option<(ReportType, &'a [u8])> {
+        Some(fill(&self.pending_motion_report()?, report))
+    }
+
+    /// [`SynergyHid::pending_motion`] returning the report by value
+    pub fn pending_motion_report(&mut self) -> Option<HidReport> {
+        if self.pending_x == 0 && self.pending_y == 0 {
+            return None;
+        }
+        Some(self.relative_step())
+    }
+
+    fn relative_step(&mut self) -> HidReport {
+        let x = self.pending_x.clamp(-127, 127);
+        let y = self.pending_y.clamp(-127, 127);
+        self.pending_x -= x;
+        self.pending_y -= y;
+        HidReport::relative_mouse(self.rel_mouse_report.move_by(x as i8, y as i8))
+    }
+
     pub fn mouse_down<'a>(&mut self, button: i8, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
-        report[..7].copy_from_slice(&self.mouse_report.mouse_down(synergy_mouse_button(button)));
-        (ReportType::Mouse, &report[..7])
+        fill(&self.mouse_down_report(button), report)
+    }
+
+    /// [`SynergyHid::mouse_down`] returning the report by value
+    pub fn mouse_down_report(&mut self, button: i8) -> HidReport {
+        let target = self.button_map.get(button);
+        if let ButtonTarget::Pan(pan) = target {
+            return self.pan_notch(pan);
+        }
+        let button = self.button_bit(button, target);
+        if self.mouse_mode == MouseMode::Relative {
+            return HidReport::relative_mouse(self.rel_mouse_report.mouse_down(button));
+        }
+        HidReport::mouse(self.mouse_report.mouse_down(button))
     }
 
     pub fn mouse_up<'a>(&mut self, button: i8, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
-        report[..7].copy_from_slice(&self.mouse_report.mouse_up(synergy_mouse_button(button)));
-        (ReportType::Mouse, &report[..7])
+        fill(&self.mouse_up_report(button), report)
+    }
+
+    /// [`SynergyHid::mouse_up`] returning the report by value
+    pub fn mouse_up_report(&mut self, button: i8) -> HidReport {
+        let button = self.button_bit(button, self.button_map.get(button));
+        if self.mouse_mode == MouseMode::Relative {
+            return HidReport::relative_mouse(self.rel_mouse_report.mouse_up(button));
+        }
+        HidReport::mouse(self.mouse_report.mouse_up(button))
+    }
+
+    // Bit of the button in the reports, 0 leaves them unchanged. Buttons without a HID
+    // button are logged once unless the button map ignores them on purpose.
+    fn button_bit(&mut self, button: i8, target: ButtonTarget) -> u8 {
+        if target == ButtonTarget::None
+            && !self.button_map.is_ignored(button)
+            && self.unmapped_buttons.insert(button)
+        {
+            warn!("HID button not found for mouse button {}", button);
+        }
+        target.bit()
+    }
+
+    // A notch of the horizontal wheel, the buttons held are sent along
+    fn pan_notch(&mut self, pan: Pan) -> HidReport {
+        let pan = match pan {
+            Pan::Left => -1,
+            Pan::Right => 1,
+        };
+        if self.mouse_mode == MouseMode::Relative {
+            return HidReport::relative_mouse(self.rel_mouse_report.mouse_wheel(0, pan));
+        }
+        HidReport::mouse(self.mouse_report.mouse_wheel(0, pan))
     }
 
+    /// The server sends 120 per notch, deltas are flipped and multiplied by the scroll speed,
+    /// then accumulated until they add up to a notch.
+    /// Bursts that don't fit in one report are continued by `pending_scroll`.
     pub fn mouse_scroll<'a>(
         &mut self,
         x: i16,
         y: i16,
         report: &'a mut [u8],
     ) -> (ReportType, &'a [u8]) {
-        let x = (x as f32  / 120.0) as i16;
-        let y = (y as f32  / 120.0) as i16;
-        let mut x = x as i8;
-        let mut y = y as i8;
-        if self.flip_mouse_wheel {
-            x = -x;
-            y = -y;
+        fill(&self.mouse_scroll_report(x, y), report)
+    }
+
+    /// [`SynergyHid::mouse_scroll`] returning the report by value
+    pub fn mouse_scroll_report(&mut self, x: i16, y: i16) -> HidReport {
+        let flip = |delta: i16, flip: bool| if flip { -(delta as i32) } else { delta as i32 };
+        let x = flip(x, self.flip_wheel.0) * self.scroll_speed.0 + self.wheel_fraction.0;
+        let y = flip(y, self.flip_wheel.1) * self.scroll_speed.1 + self.wheel_fraction.1;
+        // The remainders keep the sign of the deltas, like the partial steps
+        self.wheel_fraction = (x % SCROLL_SPEED_ONE, y % SCROLL_SPEED_ONE);
+        self.wheel_x = self.wheel_x.saturating_add(x / SCROLL_SPEED_ONE);
+        self.wheel_y = self.wheel_y.saturating_add(y / SCROLL_SPEED_ONE);
+        self.wheel_step()
+    }
+
+    /// Returns the next wheel report until the accumulated deltas are less than a step
+    pub fn pending_scroll<'a>(&mut self, report: &'a mut [u8]) -> Option<(ReportType, &'a [u8])> {
+        Some(fill(&self.pending_scroll_report()?, report))
+    }
+
+    /// [`SynergyHid::pending_scroll`] returning the report by value
+    pub fn pending_scroll_report(&mut self) -> Option<HidReport> {
+        if self.wheel_x.abs() < WHEEL_NOTCH && self.wheel_y.abs() < WHEEL_NOTCH {
+            return None;
         }
-        report[..7].copy_from_slice(&self.mouse_report.mouse_wheel(y, x));
-        (ReportType::Mouse, &report[..7])
+        Some(self.wheel_step())
     }
 
-    pub fn clear<'a>(&mut self, report_type: ReportType, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
-        match report_type {
-            ReportType::Keyboard => {
-                report[..8].copy_from_slice(&self.keyboard_report.clear());
-                (ReportType::Keyboard, &report[..8])
-            }
-            ReportType::Mouse => {
-                report[..7].copy_from_slice(&self.mouse_report.clear());
-                (ReportType::Mouse, &report[..7])
-            }
-            ReportType::Consumer => {
-                report[..2].copy_from_slice(&self.consumer_report.clear());
-                (ReportType::Consumer, &report[..2])
-            }
+    fn wheel_step(&mut self) -> HidReport {
+        // The remainder keeps the sign of the delta, partial notches are kept for later
+        let x = (self.wheel_x / WHEEL_NOTCH).clamp(-127, 127);
+        let y = (self.wheel_y / WHEEL_NOTCH).clamp(-127, 127);
+        self.wheel_x -= x * WHEEL_NOTCH;
+        self.wheel_y -= y * WHEEL_NOTCH;
+        if self.mouse_mode == MouseMode::Relative {
+            return HidReport::relative_mouse(self.rel_mouse_report.mouse_wheel(y as i8, x as i8));
         }
+        HidReport::mouse(self.mouse_report.mouse_wheel(y as i8, x as i8))
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::{
-        keycodes::{HID_KEY_A, HID_KEY_B},
-        ReportType,
-    };
+    /// Sets the buttons held on the gamepad, a bit per button
+    pub fn game_buttons<'a>(
+        &mut self,
+        buttons: u16,
+        report: &'a mut [u8],
+    ) -> (ReportType, &'a [u8]) {
+        fill(&self.game_buttons_report(buttons), report)
+    }
 
-    #[test]
-    fn test_key() {
-        let mut hid = super::SynergyHid::new(false);
-        let mut report = [0; 9];
-        assert_eq!(
-            hid.key_down(0x0000, 0x0000, 0x0000, &mut report),
-            (ReportType::Keyboard, [0, 0, 0, 0, 0, 0, 0, 0].as_ref())
-        );
-        assert_eq!(
-            hid.key_down('A' as u16, 0x0000, 0x0000, &mut report),
-            (
-                ReportType::Keyboard,
-                [0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
-            )
-        );
+    /// [`SynergyHid::game_buttons`] returning the report by value
+    pub fn game_buttons_report(&mut self, buttons: u16) -> HidReport {
+        HidReport::gamepad(self.gamepad_report.set_buttons(buttons))
+    }
+
+    /// Moves the sticks of the gamepad, `left` and `right` are their X and Y as sent by the
+    /// server, with Y up
+    pub fn game_sticks<'a>(
+        &mut self,
+        left: (i16, i16),
+        right: (i16, i16),
+        report: &'a mut [u8],
+    ) -> (ReportType, &'a [u8]) {
+        fill(&self.game_sticks_report(left, right), report)
+    }
+
+    /// [`SynergyHid::game_sticks`] returning the report by value
+    pub fn game_sticks_report(&mut self, left: (i16, i16), right: (i16, i16)) -> HidReport {
+        HidReport::gamepad(self.gamepad_report.set_sticks(left, right))
+    }
+
+    /// Sets the triggers of the gamepad, from 0 released to 255
+    pub fn game_triggers<'a>(
+        &mut self,
+        left: u8,
+        right: u8,
+        report: &'a mut [u8],
+    ) -> (ReportType, &'a [u8]) {
+        fill(&self.game_triggers_report(left, right), report)
+    }
+
+    /// [`SynergyHid::game_triggers`] returning the report by value
+    pub fn game_triggers_report(&mut self, left: u8, right: u8) -> HidReport {
+        HidReport::gamepad(self.gamepad_report.set_triggers(left, right))
+    }
+
+    /// Releases everything on the report, the returned type is the mouse of the current mode
+    /// for both `Mouse` and `RelativeMouse`
+    pub fn clear<'a>(
+        &mut self,
+        report_type: ReportType,
+        report: &'a mut [u8],
+    ) -> (ReportType, &'a [u8]) {
+        fill(&self.clear_report(report_type), report)
+    }
+
+    /// [`SynergyHid::clear`] returning the report by value
+    pub fn clear_report(&mut self, report_type: ReportType) -> HidReport {
+        match report_type {
+            ReportType::Keyboard => {
+                self.pending_release = None;
+                self.macro_actions = None;
+                self.macro_report = KeyboardReport::default();
+                self.keyboard_report.clear();
+                self.keyboard()
+            }
+            ReportType::Mouse | ReportType::RelativeMouse => {
+                (self.wheel_x, self.wheel_y) = (0, 0);
+                self.wheel_fraction = (0, 0);
+                if self.mouse_mode == MouseMode::Relative {
+                    (self.pending_x, self.pending_y) = (0, 0);
+                    return HidReport::relative_mouse(self.rel_mouse_report.clear());
+                }
+                HidReport::mouse(self.mouse_report.clear())
+            }
+            ReportType::Consumer => HidReport::consumer(self.consumer_report.clear()),
+            ReportType::Gamepad => HidReport::gamepad(self.gamepad_report.clear()),
+        }
+    }
+
+    /// Clears the keyboard, the mouse and the consumer control, e.g. on leave or disconnect,
+    /// keys still held on the server are forgotten
+    pub fn clear_all(&mut self) -> [Report; 3] {
+        self.begin_clear();
+        self.server_buttons.clear();
+        self.key_watchdog.clear();
+        self.macro_triggers.clear();
+        self.composer.clear();
+        [
+            ReportType::Keyboard,
+            ReportType::Mouse,
+            ReportType::Consumer,
+        ]
+        .map(|report_type| Report::from(self.clear_report(report_type)))
+    }
+
+    /// Releases the keys held for longer than the timeouts of `with_stuck_key_timeouts`
+    /// without a repeat and returns the reports to write. `now` is the time since any fixed
+    /// point, call it every second or so, a key is released up to that late.
+    pub fn tick(&mut self, now: Duration) -> Vec<Report> {
+        let mut reports = vec![];
+        if !self.stuck_key_timeouts.is_enabled() {
+            return reports;
+        }
+        let (buttons, keyboard, timeouts) = (
+            &self.server_buttons,
+            &self.keyboard_report,
+            self.stuck_key_timeouts,
+        );
+        let timeout = |button| match buttons.get(button)? {
+            (_, KeyCode::Key(key)) if keyboard.is_modifier(key) => timeouts.modifier,
+            _ => timeouts.key,
+        };
+        let expired = self.key_watchdog.expired(now, timeout);
+        for button in expired {
+            let Some((key, _)) = self.server_buttons.get(button) else {
+                continue;
+            };
+            let Some(report) = self.key_up_report(key, KeyMask::empty(), button) else {
+                continue;
+            };
+            warn!(
+                "Key {:#04x} held without a repeat for too long, released",
+                key
+            );
+            reports.push(Report::from(report));
+            reports.extend(self.pending_key_report().map(Report::from));
+        }
+        reports
+    }
+}
+
+// Maps 0..=size-1 to 0..=0x7fff, rounded to the nearest
+fn is_lock_key(key: u8) -> bool {
+    key == keycodes::HID_KEY_CAPS_LOCK || key == keycodes::HID_KEY_NUM_LOCK
+}
+
+fn scale_to_abs(pos: u16, size: u16) -> u16 {
+    if size <= 1 {
+        return 0;
+    }
+    let max = size as u32 - 1;
+    ((pos as u32 * 0x7fff + max / 2) / max) as u16
+}
+
+// Scroll speed in fixed point, NaN is 0
+fn scroll_speed(speed: f32) -> i32 {
+    (speed.clamp(0.0, MAX_SCROLL_SPEED) * SCROLL_SPEED_ONE as f32) as i32
+}
+
+// Copies `hid_report` to the buffer of the caller
+fn fill<'a>(hid_report: &HidReport, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
+    let (report_type, bytes) = hid_report.as_parts();
+    assert!(
+        report.len() >= bytes.len(),
+        "{:?} report of {} bytes doesn't fit in a buffer of {}, see SynergyHid::report_len",
+        report_type,
+        bytes.len(),
+        report.len()
+    );
+    report[..bytes.len()].copy_from_slice(bytes);
+    (report_type, &report[..bytes.len()])
+}
+
+// An empty keyboard report if there is nothing to write
+fn fill_some(hid_report: Option<HidReport>, report: &mut [u8]) -> (ReportType, &[u8]) {
+    match hid_report {
+        Some(hid_report) => fill(&hid_report, report),
+        None => (ReportType::Keyboard, &report[..0]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        keycodes::{
+            HID_KEY_9, HID_KEY_A, HID_KEY_ARROW_LEFT, HID_KEY_ARROW_UP, HID_KEY_B, HID_KEY_C,
+            HID_KEY_CAPS_LOCK, HID_KEY_D, HID_KEY_E, HID_KEY_F, HID_KEY_KEYPAD_ADD, HID_KEY_M,
+            HID_KEY_NUM_LOCK, HID_KEY_Q, HID_KEY_SPACE, HID_KEY_U,
+        },
+        ButtonTarget, HidState, KeyCode, KeyMask, KeyboardMode, LedState, MacroEvent, MacroStep,
+        MacroTable, MouseMode, Pan, Report, ReportType, ServerPlatform, StuckKeyTimeouts,
+        SynergyHid, UnicodeInput, CONSUMER_REPORT_LEN, GAMEPAD_REPORT_LEN, MAX_REPORT_LEN,
+        NKRO_REPORT_LEN,
+    };
+
+    #[test]
+    fn test_key() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        // Unknown key, nothing to write
+        assert_eq!(
+            hid.key_down(0x0000, KeyMask::empty(), 0x0000, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        assert_eq!(
+            hid.key_down('A' as u16, KeyMask::empty(), 0x0000, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
 
         assert_eq!(
-            hid.key_down('B' as u16, 0x0000, 0x0000, &mut report),
+            hid.key_down('B' as u16, KeyMask::empty(), 0x0000, &mut report),
             (
                 ReportType::Keyboard,
                 [0, 0, HID_KEY_A, HID_KEY_B, 0, 0, 0, 0].as_ref()
             )
         );
         assert_eq!(
-            hid.key_up('B' as u16, 0x0000, 0x0000, &mut report),
+            hid.key_up('B' as u16, KeyMask::empty(), 0x0000, &mut report),
             (
                 ReportType::Keyboard,
                 [0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
             )
         );
-        // Wrong key up, report is cleared
+        // Key up with button 0 and nothing held on it, report is cleared
         assert_eq!(
-            hid.key_up('C' as u16, 0x0000, 0x0000, &mut report),
+            hid.key_up('C' as u16, KeyMask::empty(), 0x0000, &mut report),
             (ReportType::Keyboard, [0, 0, 0, 0, 0, 0, 0, 0].as_ref())
         );
 
-        // kKeyAudioMute(0xE0AD) -> HID_USAGE_CONSUMER_MUTE(0x00E2)
+        // kKeyAudioMute(0xE0AD) -> HID_USAGE_CONSUMER_MUTE(0x00E2), reports are little-endian
+        assert_eq!(
+            hid.key_down(0xE0AD, KeyMask::empty(), 1, &mut report),
+            (
+                ReportType::Consumer,
+                [0xE2, 0x00, 0, 0, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+    }
+
+    // Synergy media keys, the usages of the HID Consumer Page or Generic Desktop System Control
+    const MEDIA_KEYS: [(u16, KeyCode); 24] = [
+        (0xE001, KeyCode::Consumer(0x00B8)), // kKeyEject -> Eject
+        (0xE05F, KeyCode::System(0x82)),     // kKeySleep -> System Sleep
+        (0xE0A6, KeyCode::Consumer(0x0224)), // kKeyWWWBack -> AC Back
+        (0xE0A7, KeyCode::Consumer(0x0225)), // kKeyWWWForward -> AC Forward
+        (0xE0A8, KeyCode::Consumer(0x0227)), // kKeyWWWRefresh -> AC Refresh
+        (0xE0A9, KeyCode::Consumer(0x0226)), // kKeyWWWStop -> AC Stop
+        (0xE0AA, KeyCode::Consumer(0x0221)), // kKeyWWWSearch -> AC Search
+        (0xE0AB, KeyCode::Consumer(0x022A)), // kKeyWWWFavorites -> AC Bookmarks
+        (0xE0AC, KeyCode::Consumer(0x0223)), // kKeyWWWHome -> AC Home
+        (0xE0AD, KeyCode::Consumer(0x00E2)), // kKeyAudioMute -> Mute
+        (0xE0AE, KeyCode::Consumer(0x00EA)), // kKeyAudioDown -> Volume Decrement
+        (0xE0AF, KeyCode::Consumer(0x00E9)), // kKeyAudioUp -> Volume Increment
+        (0xE0B0, KeyCode::Consumer(0x00B5)), // kKeyAudioNext -> Scan Next Track
+        (0xE0B1, KeyCode::Consumer(0x00B6)), // kKeyAudioPrev -> Scan Previous Track
+        (0xE0B2, KeyCode::Consumer(0x00B7)), // kKeyAudioStop -> Stop
+        (0xE0B3, KeyCode::Consumer(0x00CD)), // kKeyAudioPlay -> Play/Pause
+        (0xE0B4, KeyCode::Consumer(0x018A)), // kKeyAppMail -> AL Email Reader
+        (0xE0B5, KeyCode::Consumer(0x0183)), // kKeyAppMedia -> AL Consumer Control Configuration
+        (0xE0B6, KeyCode::Consumer(0x0194)), // kKeyAppUser1 -> AL Local Machine Browser
+        (0xE0B7, KeyCode::Consumer(0x0192)), // kKeyAppUser2 -> AL Calculator
+        (0xE0B8, KeyCode::Consumer(0x0070)), // kKeyBrightnessDown -> Display Brightness Decrement
+        (0xE0B9, KeyCode::Consumer(0x006F)), // kKeyBrightnessUp -> Display Brightness Increment
+        (0xE0C0, KeyCode::Consumer(0x029F)), // kKeyMissionControl -> AC Desktop Show All Windows
+        (0xE0C1, KeyCode::Consumer(0x02A2)), // kKeyLaunchpad -> AC Desktop Show All Applications
+    ];
+
+    #[test]
+    fn test_media_keys() {
+        for (key, code) in MEDIA_KEYS {
+            assert_eq!(super::synergy_to_hid(key), code, "key {key:#06x}");
+            let mut hid = SynergyHid::new(1920, 1080, false);
+            let mut report = [0; MAX_REPORT_LEN];
+            let (report_type, bytes) = hid.key_down(key, KeyMask::empty(), 1, &mut report);
+            assert_eq!(report_type, ReportType::Consumer, "key {key:#06x}");
+            let mut expected = [0; CONSUMER_REPORT_LEN];
+            match code {
+                KeyCode::Consumer(usage) => expected[..2].copy_from_slice(&usage.to_le_bytes()),
+                KeyCode::System(usage) => expected[8] = 1 << (usage - 0x81),
+                _ => unreachable!(),
+            }
+            assert_eq!(bytes, expected, "key {key:#06x}");
+            assert_eq!(
+                hid.key_up(key, KeyMask::empty(), 1, &mut report),
+                (ReportType::Consumer, [0; CONSUMER_REPORT_LEN].as_ref())
+            );
+        }
+        // Every other key of the range is unmapped
+        for key in 0xE000..=0xE0FF {
+            if !MEDIA_KEYS.iter().any(|(mapped, _)| *mapped == key) {
+                assert_eq!(super::synergy_to_hid(key), KeyCode::None, "key {key:#06x}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_unmapped_media_key() {
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut report = [0; MAX_REPORT_LEN];
+        hid.key_down('a' as u16, KeyMask::empty(), 0x1E, &mut report);
+        for _ in 0..3 {
+            // kKeyAppUser2 + 1, nothing is written and the keys held stay held
+            assert!(hid
+                .key_down(0xE0BA, KeyMask::empty(), 0x60, &mut report)
+                .1
+                .is_empty());
+            hid.key_repeat(0xE0BA, KeyMask::empty(), 0x60, 2, &mut report, |_| {
+                panic!("reported")
+            });
+            assert!(hid
+                .key_up(0xE0BA, KeyMask::empty(), 0x60, &mut report)
+                .1
+                .is_empty());
+        }
+        assert_eq!(hid.held_keys().collect::<Vec<_>>(), [HID_KEY_A]);
+        // Logged once
+        assert_eq!(hid.unmapped.iter().collect::<Vec<_>>(), [&0xE0BA]);
+    }
+
+    #[test]
+    fn test_system_control() {
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut report = [0; MAX_REPORT_LEN];
+        hid.key_down(0xE0AD, KeyMask::empty(), 1, &mut report);
+        // kKeySleep sets the System Sleep bit after the consumer slots
+        assert_eq!(
+            hid.key_down(0xE05F, KeyMask::empty(), 2, &mut report),
+            (
+                ReportType::Consumer,
+                [0xE2, 0x00, 0, 0, 0, 0, 0, 0, 0x02].as_ref()
+            )
+        );
+        assert_eq!(hid.state().system, [0x82]);
+        // Not repeated
+        hid.key_repeat(0xE05F, KeyMask::empty(), 2, 3, &mut report, |_| {
+            panic!("repeated")
+        });
+        assert!(!hid.is_keyboard_idle());
+        assert_eq!(
+            hid.key_up(0xE05F, KeyMask::empty(), 2, &mut report),
+            (
+                ReportType::Consumer,
+                [0xE2, 0x00, 0, 0, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // Power Down and Wake Up come from the keymap
+        let keymap = [
+            (0xE001, KeyCode::System(0x81)),
+            (0xE0B3, KeyCode::System(0x83)),
+        ];
+        let mut hid = SynergyHid::with_keymap(
+            1920,
+            1080,
+            false,
+            MouseMode::Absolute,
+            keymap.into_iter().collect(),
+        );
+        hid.key_down(0xE001, KeyMask::empty(), 1, &mut report);
+        assert_eq!(
+            hid.key_down(0xE0B3, KeyMask::empty(), 2, &mut report).1[8],
+            0x05
+        );
+        assert_eq!(hid.state().system, [0x81, 0x83]);
+        hid.clear_all();
+        assert!(hid.is_keyboard_idle());
+    }
+
+    #[test]
+    fn test_consumer_chords() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        // kKeyAudioPlay(0xE0B3) -> 0xCD, kKeyAudioUp(0xE0AF) -> 0xE9, kKeyAudioDown(0xE0AE) -> 0xEA
+        hid.key_down(0xE0B3, KeyMask::empty(), 1, &mut report);
+        assert_eq!(
+            hid.key_down(0xE0AF, KeyMask::empty(), 2, &mut report),
+            (
+                ReportType::Consumer,
+                [0xCD, 0x00, 0xE9, 0x00, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        hid.key_down(0xE0AE, KeyMask::empty(), 3, &mut report);
+        assert_eq!(
+            hid.held_consumer_keys().collect::<Vec<_>>(),
+            [0xCD, 0xE9, 0xEA]
+        );
+
+        // Out of order release, the others stay pressed
+        assert_eq!(
+            hid.key_up(0xE0AF, KeyMask::empty(), 2, &mut report),
+            (
+                ReportType::Consumer,
+                [0xCD, 0x00, 0xEA, 0x00, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up(0xE0B3, KeyMask::empty(), 1, &mut report),
+            (
+                ReportType::Consumer,
+                [0xEA, 0x00, 0, 0, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert!(!hid.is_keyboard_idle());
+        assert_eq!(
+            hid.key_up(0xE0AE, KeyMask::empty(), 3, &mut report),
+            (ReportType::Consumer, [0; 9].as_ref())
+        );
+        assert!(hid.is_keyboard_idle());
+
+        // Only 4 usages fit, kKeyAudioMute(0xE0AD) -> 0xE2, kKeyAudioNext(0xE0B0) -> 0xB5
+        for (button, key) in [0xE0B3, 0xE0AF, 0xE0AE, 0xE0AD, 0xE0B0]
+            .into_iter()
+            .enumerate()
+        {
+            hid.key_down(key, KeyMask::empty(), button as u16 + 1, &mut report);
+        }
+        assert_eq!(
+            hid.held_consumer_keys().collect::<Vec<_>>(),
+            [0xCD, 0xE9, 0xEA, 0xE2]
+        );
+    }
+
+    #[test]
+    fn test_key_repeat() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        // Shift held while 'a' repeats, kKeyShift_L(0xEFE1)
+        hid.key_down(0xEFE1, KeyMask::SHIFT, 1, &mut report);
+        hid.key_down('a' as u16, KeyMask::SHIFT, 2, &mut report);
+
+        let mut reports = vec![];
+        hid.key_repeat('a' as u16, KeyMask::SHIFT, 2, 2, &mut report, |(t, r)| {
+            reports.push((t, r.to_vec()))
+        });
+        let released = vec![0x02, 0, 0, 0, 0, 0, 0, 0];
+        let pressed = vec![0x02, 0, HID_KEY_A, 0, 0, 0, 0, 0];
+        assert_eq!(
+            reports,
+            vec![
+                (ReportType::Keyboard, released.clone()),
+                (ReportType::Keyboard, pressed.clone()),
+                (ReportType::Keyboard, released),
+                (ReportType::Keyboard, pressed),
+            ]
+        );
+
+        // Repeating a modifier doesn't toggle it
+        let mut reports = vec![];
+        hid.key_repeat(0xEFE1, KeyMask::SHIFT, 1, 3, &mut report, |(t, r)| {
+            reports.push((t, r.to_vec()))
+        });
+        assert!(reports.is_empty());
+
+        // Consumer keys are pressed again
+        hid.key_down(0xE0AD, KeyMask::empty(), 3, &mut report);
+        let mut reports = vec![];
+        hid.key_repeat(0xE0AD, KeyMask::empty(), 3, 1, &mut report, |(t, r)| {
+            reports.push((t, r.to_vec()))
+        });
+        assert_eq!(
+            reports,
+            vec![
+                (ReportType::Consumer, vec![0; 9]),
+                (ReportType::Consumer, vec![0xE2, 0x00, 0, 0, 0, 0, 0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mouse_scroll() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        let wheel = |(_, report): (ReportType, &[u8])| (report[5] as i8, report[6] as i8);
+
+        // One notch each way
+        assert_eq!(wheel(hid.mouse_scroll(0, 120, &mut report)), (1, 0));
+        assert_eq!(wheel(hid.mouse_scroll(-120, -120, &mut report)), (-1, -1));
+        assert_eq!(hid.pending_scroll(&mut report), None);
+
+        // Partial notches add up
+        for _ in 0..3 {
+            assert_eq!(wheel(hid.mouse_scroll(0, 30, &mut report)), (0, 0));
+        }
+        assert_eq!(wheel(hid.mouse_scroll(0, 30, &mut report)), (1, 0));
+        for _ in 0..3 {
+            assert_eq!(wheel(hid.mouse_scroll(0, -30, &mut report)), (0, 0));
+        }
+        assert_eq!(wheel(hid.mouse_scroll(0, -30, &mut report)), (-1, 0));
+
+        // 1000 is 8 notches, the rest is kept
+        assert_eq!(wheel(hid.mouse_scroll(0, 1000, &mut report)), (8, 0));
+        assert_eq!(hid.pending_scroll(&mut report), None);
+        assert_eq!(wheel(hid.mouse_scroll(0, 80, &mut report)), (1, 0));
+
+        // A burst too large for one report is split into multiple reports
+        assert_eq!(wheel(hid.mouse_scroll(0, i16::MAX, &mut report)), (127, 0));
+        let mut steps = vec![];
+        while let Some(report) = hid.pending_scroll(&mut report) {
+            steps.push(wheel(report).0);
+        }
+        assert_eq!(steps, [127, 19]);
+
+        // Flipped before accumulating
+        let mut hid = super::SynergyHid::with_mouse_mode(1920, 1080, true, MouseMode::Relative);
+        hid.mouse_scroll(0, 60, &mut report);
+        assert_eq!(
+            hid.mouse_scroll(60, 60, &mut report),
+            (
+                ReportType::RelativeMouse,
+                [0, 0, 0, (-1i8) as u8, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.mouse_scroll(-60, 0, &mut report),
+            (ReportType::RelativeMouse, [0, 0, 0, 0, 0].as_ref())
+        );
+        // Cleared with the mouse
+        hid.mouse_scroll(0, 60, &mut report);
+        hid.clear(ReportType::Mouse, &mut report);
+        assert_eq!(
+            hid.mouse_scroll(0, 60, &mut report),
+            (ReportType::RelativeMouse, [0, 0, 0, 0, 0].as_ref())
+        );
+    }
+
+    #[test]
+    fn test_scroll_speed() {
+        let mut report = [0; 9];
+        let wheel = |(_, report): (ReportType, &[u8])| (report[5] as i8, report[6] as i8);
+
+        // Half speed scrolls every other notch, the fractions of a delta add up too
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_scroll_speed(0.5, 0.5);
+        let notches: Vec<_> = (0..4)
+            .map(|_| wheel(hid.mouse_scroll(120, 120, &mut report)))
+            .collect();
+        assert_eq!(notches, [(0, 0), (1, 1), (0, 0), (1, 1)]);
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_scroll_speed(1.0, 0.25);
+        let y: i32 = (0..479)
+            .map(|_| wheel(hid.mouse_scroll(0, 1, &mut report)).0 as i32)
+            .sum();
+        assert_eq!(y, 0);
+        assert_eq!(hid.state().pending_wheel, (0, 119));
+        assert_eq!(wheel(hid.mouse_scroll(0, 1, &mut report)), (1, 0));
+
+        // Per axis, the vertical wheel comes first in the report
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_scroll_speed(3.0, 0.25);
+        assert_eq!(wheel(hid.mouse_scroll(120, 120, &mut report)), (0, 3));
+        assert_eq!(hid.scroll_speed(), (3.0, 0.25));
+
+        // Saturated at the bounds of a report, the rest is continued
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_scroll_speed(64.0, 100.0);
+        assert_eq!(hid.scroll_speed(), (64.0, 64.0));
+        assert_eq!(wheel(hid.mouse_scroll(0, -240, &mut report)), (-127, 0));
+        assert_eq!(hid.pending_scroll(&mut report).map(wheel), Some((-1, 0)));
+        assert_eq!(hid.pending_scroll(&mut report), None);
+        assert_eq!(
+            wheel(hid.mouse_scroll(i16::MAX, i16::MIN, &mut report)),
+            (-127, 127)
+        );
+
+        // Off, and cleared with the mouse
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_scroll_speed(0.0, f32::NAN);
+        assert_eq!(wheel(hid.mouse_scroll(1200, 1200, &mut report)), (0, 0));
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_scroll_speed(0.5, 0.5);
+        hid.mouse_scroll(1, 1, &mut report);
+        hid.clear(ReportType::Mouse, &mut report);
+        assert_eq!(hid.wheel_fraction, (0, 0));
+    }
+
+    #[test]
+    fn test_wheel_flip() {
+        let mut report = [0; 9];
+        let wheel = |(_, report): (ReportType, &[u8])| (report[5] as i8, report[6] as i8);
+        let scroll = |hid: SynergyHid| {
+            let mut hid = hid.with_scroll_speed(2.0, 2.0);
+            wheel(hid.mouse_scroll(120, 120, &mut [0; 9]))
+        };
+        let hid = || super::SynergyHid::new(1920, 1080, false);
+
+        assert_eq!(scroll(hid()), (2, 2));
+        assert_eq!(scroll(hid().with_wheel_flip(true, false)), (2, -2));
+        assert_eq!(scroll(hid().with_wheel_flip(false, true)), (-2, 2));
+        assert_eq!(scroll(hid().with_wheel_flip(true, true)), (-2, -2));
+        assert_eq!(scroll(super::SynergyHid::new(1920, 1080, true)), (-2, -2));
+        // Overrides the flip of the constructor
+        assert_eq!(
+            scroll(super::SynergyHid::new(1920, 1080, true).with_wheel_flip(false, true)),
+            (-2, 2)
+        );
+        // The remainders keep the direction
+        let mut hid = hid()
+            .with_wheel_flip(false, true)
+            .with_scroll_speed(0.5, 0.5);
+        assert_eq!(wheel(hid.mouse_scroll(0, 121, &mut report)), (0, 0));
+        assert_eq!(hid.state().pending_wheel, (0, -60));
+        assert_eq!(hid.wheel_fraction, (0, -128));
+        assert_eq!(wheel(hid.mouse_scroll(0, 119, &mut report)), (-1, 0));
+        assert_eq!(hid.wheel_fraction, (0, 0));
+    }
+
+    #[test]
+    fn test_absolute_mouse() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        let pos = |(_, report): (ReportType, &[u8])| {
+            (
+                u16::from_le_bytes([report[1], report[2]]),
+                u16::from_le_bytes([report[3], report[4]]),
+            )
+        };
+        assert_eq!(pos(hid.set_cursor_position(0, 0, &mut report)), (0, 0));
+        assert_eq!(
+            pos(hid.set_cursor_position(1919, 1079, &mut report)),
+            (0x7fff, 0x7fff)
+        );
+        assert_eq!(
+            pos(hid.set_cursor_position(960, 540, &mut report)),
+            (16392, 16399)
+        );
+        // Off the screen is clamped to the edge instead of wrapping around
+        assert_eq!(
+            pos(hid.set_cursor_position(1927, 2000, &mut report)),
+            (0x7fff, 0x7fff)
+        );
+        assert_eq!(hid.cursor_position(), (1919, 1079));
+        assert_eq!(pos(hid.move_cursor(-1919, -2000, &mut report)), (0, 0));
+        assert_eq!(hid.cursor_position(), (0, 0));
+
+        // No drift after going past the edge and back
+        hid.set_cursor_position(1900, 10, &mut report);
+        hid.move_cursor(100, -50, &mut report);
+        assert_eq!(hid.cursor_position(), (1919, 0));
+        hid.move_cursor(-19, 10, &mut report);
+        assert_eq!(hid.cursor_position(), (1900, 10));
+
+        // Flicked left and up right after a reconnect, the cursor stays in the corner
+        hid.set_cursor_position(0, 0, &mut report);
+        assert_eq!(pos(hid.move_cursor(-5, -1, &mut report)), (0, 0));
+        assert_eq!(
+            pos(hid.move_cursor(i16::MIN, i16::MIN, &mut report)),
+            (0, 0)
+        );
+        // Large moves added up stop at the far edge
+        for _ in 0..3 {
+            hid.move_cursor(i16::MAX, i16::MAX, &mut report);
+        }
+        assert_eq!(hid.cursor_position(), (1919, 1079));
+        assert_eq!(pos(hid.move_cursor(1, 1, &mut report)), (0x7fff, 0x7fff));
+
+        // Restored without a report, the next move goes on from there
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        hid.set_position(960, 540);
+        assert_eq!(hid.cursor_position(), (960, 540));
+        assert_eq!(pos(hid.move_cursor(0, 0, &mut report)), (16392, 16399));
+        hid.set_position(4000, 4000);
+        assert_eq!(hid.cursor_position(), (1919, 1079));
+
+        // A smaller display plugged in, its middle is scaled to about the middle
+        hid.set_screen_size(1280, 720);
+        assert_eq!(hid.screen_size(), (1280, 720));
+        assert_eq!(hid.cursor_position(), (1279, 719));
+        assert_eq!(
+            pos(hid.set_cursor_position(640, 360, &mut report)),
+            (16396, 16406)
+        );
+    }
+
+    #[test]
+    fn test_relative_mouse() {
+        let mut hid = super::SynergyHid::with_mouse_mode(1920, 1080, false, MouseMode::Relative);
+        let mut report = [0; 9];
+        assert_eq!(
+            hid.set_cursor_position(100, 50, &mut report),
+            (ReportType::RelativeMouse, [0, 100, 50, 0, 0].as_ref())
+        );
+        assert_eq!(hid.pending_motion(&mut report), None);
+
+        // Large jumps are split into multiple reports
+        assert_eq!(
+            hid.set_cursor_position(400, 0, &mut report),
+            (
+                ReportType::RelativeMouse,
+                [0, 127, (-50i8) as u8, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.pending_motion(&mut report),
+            Some((ReportType::RelativeMouse, [0, 127, 0, 0, 0].as_ref()))
+        );
+        assert_eq!(
+            hid.pending_motion(&mut report),
+            Some((ReportType::RelativeMouse, [0, 46, 0, 0, 0].as_ref()))
+        );
+        assert_eq!(hid.pending_motion(&mut report), None);
+
+        assert_eq!(
+            hid.move_cursor(-10, 5, &mut report),
+            (
+                ReportType::RelativeMouse,
+                [0, (-10i8) as u8, 5, 0, 0].as_ref()
+            )
+        );
+        // Past the edge the move is still sent, the tracked position stops at the edge
+        assert_eq!(
+            hid.move_cursor(0, -20, &mut report),
+            (
+                ReportType::RelativeMouse,
+                [0, 0, (-20i8) as u8, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(hid.cursor_position(), (390, 0));
+        hid.move_cursor(0, 10, &mut report);
+        assert_eq!(hid.cursor_position(), (390, 10));
+        assert_eq!(
+            hid.mouse_down(1, &mut report),
+            (ReportType::RelativeMouse, [1, 0, 0, 0, 0].as_ref())
+        );
+        assert_eq!(
+            hid.clear(ReportType::Mouse, &mut report),
+            (ReportType::RelativeMouse, [0, 0, 0, 0, 0].as_ref())
+        );
+    }
+
+    #[test]
+    fn test_mouse_buttons() {
+        for mode in [MouseMode::Absolute, MouseMode::Relative] {
+            let mut hid = super::SynergyHid::with_mouse_mode(1920, 1080, false, mode);
+            // Left, middle and right, then the back, forward and extra buttons
+            for (button, bit) in [1, 2, 3, 4, 5, 6, 7, 8]
+                .into_iter()
+                .zip([1, 4, 2, 8, 16, 32, 64, 128])
+            {
+                assert_eq!(
+                    hid.mouse_down_report(button).as_bytes()[0],
+                    bit,
+                    "{mode:?} {button}"
+                );
+                assert_eq!(hid.mouse_buttons(), bit);
+                assert_eq!(hid.mouse_up_report(button).as_bytes()[0], 0);
+            }
+            hid.mouse_down_report(1);
+            hid.mouse_down_report(8);
+            assert_eq!(hid.mouse_buttons(), 0x81);
+
+            // Ids the server never sends leave the buttons as they are
+            for button in [0, -1, -128, 9, 127] {
+                assert_eq!(hid.mouse_down_report(button).as_bytes()[0], 0x81);
+                assert_eq!(hid.mouse_up_report(button).as_bytes()[0], 0x81);
+            }
+            assert_eq!(hid.unmapped_buttons.len(), 5);
+        }
+    }
+
+    #[test]
+    fn test_button_map() {
+        let buttons = [
+            (2, ButtonTarget::Button(4)),
+            (4, ButtonTarget::Button(3)),
+            (6, ButtonTarget::Pan(Pan::Left)),
+            (7, ButtonTarget::Pan(Pan::Right)),
+            (8, ButtonTarget::None),
+        ]
+        .into_iter()
+        .collect();
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_button_map(buttons);
+        let mut report = [0; 9];
+
+        // Middle and back swapped
+        assert_eq!(hid.mouse_down(2, &mut report).1[0], 0x08);
+        assert_eq!(hid.mouse_down(4, &mut report).1[0], 0x0C);
+        assert_eq!(hid.mouse_up(2, &mut report).1[0], 0x04);
+        assert_eq!(hid.mouse_up(4, &mut report).1[0], 0);
+        // Not in the map
+        assert_eq!(hid.mouse_down(5, &mut report).1[0], 0x10);
+
+        // A notch sideways on each press with the buttons held, nothing on the release
+        assert_eq!(
+            hid.mouse_down(6, &mut report),
+            (
+                ReportType::Mouse,
+                [0x10, 0, 0, 0, 0, 0, (-1i8) as u8].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.mouse_up(6, &mut report),
+            (ReportType::Mouse, [0x10, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+        assert_eq!(
+            hid.mouse_down(7, &mut report),
+            (ReportType::Mouse, [0x10, 0, 0, 0, 0, 0, 1].as_ref())
+        );
+        assert_eq!(hid.mouse_buttons(), 0x10);
+
+        // Ignored on purpose, not logged
+        assert_eq!(hid.mouse_down(8, &mut report).1[0], 0x10);
+        assert!(hid.unmapped_buttons.is_empty());
+
+        let mut hid = super::SynergyHid::with_mouse_mode(1920, 1080, false, MouseMode::Relative)
+            .with_button_map([(1, ButtonTarget::Pan(Pan::Right))].into_iter().collect());
+        assert_eq!(
+            hid.mouse_down(1, &mut report),
+            (ReportType::RelativeMouse, [0, 0, 0, 0, 1].as_ref())
+        );
+        assert_eq!(hid.mouse_buttons(), 0);
+    }
+
+    #[test]
+    fn test_modifier_mask() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        // Shifted letter without Shift key down
         assert_eq!(
-            hid.key_down(0xE0AD, 0x0000, 1, &mut report),
-            (ReportType::Consumer, [0x00, 0xE2].as_ref())
+            hid.key_down('A' as u16, KeyMask::SHIFT, 1, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x02, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // Until the server releases Shift
+        assert_eq!(
+            hid.key_up('A' as u16, KeyMask::SHIFT, 1, &mut report),
+            (ReportType::Keyboard, [0x02, 0, 0, 0, 0, 0, 0, 0].as_ref())
         );
+
+        // AltGr+Q, only the AltGr bit is set in the mask
+        assert_eq!(
+            hid.key_down('q' as u16, KeyMask::ALT_GR, 2, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x40, 0, HID_KEY_Q, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // AltGr stays applied while the server holds it
+        assert_eq!(
+            hid.key_up('q' as u16, KeyMask::ALT_GR, 2, &mut report),
+            (ReportType::Keyboard, [0x40, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+
+        // Releasing a key keeps the synthetic Shift of another one held
+        hid.key_down('a' as u16, KeyMask::SHIFT, 5, &mut report);
+        hid.key_down('b' as u16, KeyMask::SHIFT, 6, &mut report);
+        assert_eq!(
+            hid.key_up('a' as u16, KeyMask::SHIFT, 5, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x02, 0, HID_KEY_B, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up('b' as u16, KeyMask::empty(), 6, &mut report),
+            (ReportType::Keyboard, [0, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+
+        // Pressed modifiers are not applied again, kKeyShift_L(0xEFE1)
+        hid.key_down(0xEFE1, KeyMask::empty(), 3, &mut report);
+        assert_eq!(
+            hid.key_down(
+                'A' as u16,
+                KeyMask::SHIFT | KeyMask::CONTROL,
+                4,
+                &mut report
+            ),
+            (
+                ReportType::Keyboard,
+                [0x03, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // The synthetic Ctrl goes once the server releases it, the pressed Shift stays
+        assert_eq!(
+            hid.key_up('A' as u16, KeyMask::SHIFT, 4, &mut report),
+            (ReportType::Keyboard, [0x02, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+        assert_eq!(
+            hid.key_up(0xEFE1, KeyMask::SHIFT, 3, &mut report),
+            (ReportType::Keyboard, [0, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+    }
+
+    #[test]
+    fn test_rollover() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        for (button, key) in ('a'..='f').enumerate() {
+            hid.key_down(key as u16, KeyMask::empty(), button as u16, &mut report);
+        }
+        assert_eq!(hid.held_keys().count(), 6);
+        assert_eq!(
+            hid.key_down('g' as u16, KeyMask::empty(), 6, &mut report),
+            (ReportType::Keyboard, [0, 0, 1, 1, 1, 1, 1, 1].as_ref())
+        );
+        assert_eq!(
+            hid.key_down('h' as u16, KeyMask::empty(), 7, &mut report),
+            (ReportType::Keyboard, [0, 0, 1, 1, 1, 1, 1, 1].as_ref())
+        );
+        assert_eq!(hid.held_keys().count(), 8);
+
+        // Still 7 keys held
+        assert_eq!(
+            hid.key_up('b' as u16, KeyMask::empty(), 1, &mut report),
+            (ReportType::Keyboard, [0, 0, 1, 1, 1, 1, 1, 1].as_ref())
+        );
+        // 'a', 'c', 'd', 'e', 'f', 'g'
+        assert_eq!(
+            hid.key_up('h' as u16, KeyMask::empty(), 7, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, 0x04, 0x06, 0x07, 0x08, 0x09, 0x0A].as_ref()
+            )
+        );
+        assert_eq!(hid.held_keys().count(), 6);
+    }
+
+    #[test]
+    fn test_nkro() {
+        let mut hid = SynergyHid::new(1920, 1080, false).with_keyboard_mode(KeyboardMode::Nkro);
+        let mut report = [0; MAX_REPORT_LEN];
+        for (button, key) in ('a'..='i').enumerate() {
+            hid.key_down(key as u16, KeyMask::empty(), button as u16, &mut report);
+        }
+        // 'a' to 'j' are 0x04 to 0x0D, Shift is added from the mask
+        let mut expected = [0; NKRO_REPORT_LEN];
+        expected[..3].copy_from_slice(&[0x02, 0xF0, 0x3F]);
+        assert_eq!(
+            hid.key_down('j' as u16, KeyMask::SHIFT, 9, &mut report),
+            (ReportType::Keyboard, expected.as_ref())
+        );
+        assert_eq!(hid.held_keys().count(), 10);
+
+        assert_eq!(
+            hid.key_up('j' as u16, KeyMask::empty(), 9, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0xF0, 0x1F, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        hid.key_up('b' as u16, KeyMask::empty(), 1, &mut report);
+        assert_eq!(
+            hid.key_up('c' as u16, KeyMask::empty(), 2, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0x90, 0x1F, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+
+        // International1 is above the bitmap, nothing changes
+        let mut reports = vec![];
+        hid.tap_keys(&[0x87], &mut report, |(_, r)| reports.push(r.to_vec()));
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r[..3] == [0, 0x90, 0x1F]));
+
+        assert_eq!(
+            hid.clear(ReportType::Keyboard, &mut report),
+            (ReportType::Keyboard, [0; NKRO_REPORT_LEN].as_ref())
+        );
+    }
+
+    #[test]
+    fn test_report_descriptor() {
+        for (mode, len) in [(KeyboardMode::Boot, 8), (KeyboardMode::Nkro, 16)] {
+            assert_eq!(
+                SynergyHid::get_report_descriptor(ReportType::Keyboard, mode).0,
+                len
+            );
+            // The mouse doesn't depend on the keyboard mode
+            assert_eq!(
+                SynergyHid::get_report_descriptor(ReportType::Mouse, mode).0,
+                7
+            );
+        }
+    }
+
+    #[test]
+    fn test_report_len() {
+        let report = &mut [0; SynergyHid::MAX_REPORT_LEN];
+        for mode in [KeyboardMode::Boot, KeyboardMode::Nkro] {
+            let mut hid = SynergyHid::new(1920, 1080, false).with_keyboard_mode(mode);
+            for report_type in [
+                ReportType::Keyboard,
+                ReportType::Mouse,
+                ReportType::Consumer,
+            ] {
+                assert_eq!(
+                    hid.clear(report_type, report).1.len(),
+                    hid.report_len(report_type)
+                );
+            }
+        }
+        let mut hid = SynergyHid::with_mouse_mode(1920, 1080, false, MouseMode::Relative);
+        assert_eq!(
+            hid.mouse_down(1, report).1.len(),
+            hid.report_len(ReportType::RelativeMouse)
+        );
+        let saved = Report::from(hid.mouse_up(1, report));
+        assert_eq!(
+            saved.as_parts(),
+            (ReportType::RelativeMouse, [0; 5].as_ref())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit in a buffer of 8")]
+    fn test_report_buffer_too_small() {
+        let mut hid = SynergyHid::new(1920, 1080, false).with_keyboard_mode(KeyboardMode::Nkro);
+        hid.key_down('a' as u16, KeyMask::empty(), 1, &mut [0; 8]);
+    }
+
+    #[test]
+    fn test_scan_code_fallback() {
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        // Dropped without a platform
+        assert_eq!(
+            hid.key_down(0, KeyMask::empty(), 0x1E, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+
+        let mut hid = hid.with_server_platform(ServerPlatform::Windows);
+        // Windows scan codes of A and Q, Up is E0 48
+        assert_eq!(
+            hid.key_down(0, KeyMask::SHIFT, 0x1E, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x02, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        hid.key_down(0, KeyMask::empty(), 0x10, &mut report);
+        assert_eq!(
+            hid.key_down(0, KeyMask::empty(), 0x148, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_A, HID_KEY_Q, HID_KEY_ARROW_UP, 0, 0, 0].as_ref()
+            )
+        );
+        // Released by button, the id of the key up doesn't matter
+        assert_eq!(
+            hid.key_up(0, KeyMask::empty(), 0x1E, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_Q, HID_KEY_ARROW_UP, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up('x' as u16, KeyMask::empty(), 0x148, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_Q, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+
+        // Left arrow repeated, then released
+        let mut reports = vec![];
+        hid.key_repeat(0, KeyMask::empty(), 0x14B, 1, &mut report, |(_, r)| {
+            reports.push(r.to_vec())
+        });
+        assert_eq!(
+            reports[1],
+            [0, 0, HID_KEY_Q, HID_KEY_ARROW_LEFT, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            hid.key_up(0, KeyMask::empty(), 0x14B, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_Q, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+
+        // The key id wins when there is one
+        hid.key_up(0, KeyMask::empty(), 0x10, &mut report);
+        assert_eq!(
+            hid.key_down('b' as u16, KeyMask::empty(), 0x1E, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_B, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        assert!(hid.is_keyboard_idle());
+        // kKeyShift_L(0xEFE1)
+        hid.key_down(0xEFE1, KeyMask::empty(), 1, &mut report);
+        hid.key_down('a' as u16, KeyMask::SHIFT, 2, &mut report);
+        hid.key_down('b' as u16, KeyMask::SHIFT, 3, &mut report);
+        hid.mouse_down(1, &mut report);
+        hid.mouse_down(3, &mut report);
+        assert!(!hid.is_keyboard_idle());
+        assert_eq!(hid.held_keys().collect::<Vec<_>>(), [HID_KEY_A, HID_KEY_B]);
+        assert_eq!(hid.modifiers(), 0x02);
+        assert_eq!(hid.mouse_buttons(), 0x03);
+
+        assert_eq!(
+            hid.clear(ReportType::Keyboard, &mut report),
+            (ReportType::Keyboard, [0, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+        assert!(hid.is_keyboard_idle());
+        assert_eq!(hid.held_keys().count(), 0);
+        assert_eq!(hid.modifiers(), 0);
+        assert_eq!(hid.mouse_buttons(), 0x03);
+
+        // Consumer keys count as keys too, kKeyAudioMute(0xE0AD)
+        hid.key_down(0xE0AD, KeyMask::empty(), 4, &mut report);
+        hid.key_down('a' as u16, KeyMask::empty(), 2, &mut report);
+        assert!(!hid.is_keyboard_idle());
+        assert_eq!(hid.generation(), 0);
+        assert_eq!(
+            hid.clear_all()
+                .map(|r| (r.report_type(), r.as_bytes().to_vec())),
+            [
+                (ReportType::Keyboard, vec![0, 0, 0, 0, 0, 0, 0, 0]),
+                (ReportType::Mouse, vec![0, 0, 0, 0, 0, 0, 0]),
+                (ReportType::Consumer, vec![0; 9]),
+            ]
+        );
+        assert!(hid.is_keyboard_idle());
+        assert_eq!(hid.mouse_buttons(), 0);
+        assert_eq!(hid.generation(), 1);
+        assert_eq!(hid.begin_clear(), 2);
+
+        // The relative mouse is cleared in relative mode
+        let mut hid = super::SynergyHid::with_mouse_mode(1920, 1080, false, MouseMode::Relative);
+        hid.mouse_down(1, &mut report);
+        assert_eq!(hid.mouse_buttons(), 0x01);
+        assert_eq!(
+            hid.clear_all()[1].as_parts(),
+            (ReportType::RelativeMouse, [0, 0, 0, 0, 0].as_ref())
+        );
+        assert_eq!(hid.mouse_buttons(), 0);
+    }
+
+    #[test]
+    fn test_keymap() {
+        // Caps Lock to Left Ctrl, Scroll Lock suppressed
+        let keymap = [(0xEFE5, KeyCode::Key(0xE0)), (0xEF14, KeyCode::None)]
+            .into_iter()
+            .collect();
+        let mut hid = SynergyHid::with_keymap(1920, 1080, false, MouseMode::Absolute, keymap);
+        let mut report = [0; 9];
+        assert_eq!(
+            hid.key_down(0xEFE5, KeyMask::empty(), 1, &mut report),
+            (ReportType::Keyboard, [0x01, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+        assert_eq!(
+            hid.key_down('a' as u16, KeyMask::empty(), 2, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x01, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // The suppressed key doesn't release the keys held
+        assert_eq!(
+            hid.key_down(0xEF14, KeyMask::empty(), 3, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x01, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up(0xEF14, KeyMask::empty(), 3, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x01, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up(0xEFE5, KeyMask::empty(), 1, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        hid.key_down('a' as u16, KeyMask::empty(), 1, &mut report);
+        // The unknown key doesn't release the keys held
+        assert_eq!(
+            hid.key_down(0xE0FF, KeyMask::empty(), 2, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        assert_eq!(
+            hid.key_up(0xE0FF, KeyMask::empty(), 2, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        assert_eq!(hid.held_keys().collect::<Vec<_>>(), [HID_KEY_A]);
+        // Neither does a key up with no key down
+        assert_eq!(
+            hid.key_up('b' as u16, KeyMask::empty(), 3, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        assert_eq!(
+            hid.key_down('b' as u16, KeyMask::empty(), 3, &mut report),
+            (
+                ReportType::Keyboard,
+                [0, 0, HID_KEY_A, HID_KEY_B, 0, 0, 0, 0].as_ref()
+            )
+        );
+
+        // Key up with button 0 releases everything
+        assert_eq!(
+            hid.key_up(0x0000, KeyMask::empty(), 0, &mut report),
+            (ReportType::Keyboard, [0, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+        assert!(hid.is_keyboard_idle());
+    }
+
+    #[test]
+    fn test_half_duplex() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        hid.set_half_duplex_caps_lock(true);
+        hid.key_down('a' as u16, KeyMask::empty(), 1, &mut report);
+
+        // Caps Lock turned on and off, each is a press and release
+        for event in [SynergyHid::key_down, SynergyHid::key_up] {
+            assert_eq!(
+                event(&mut hid, 0xEFE5, KeyMask::empty(), 2, &mut report),
+                (
+                    ReportType::Keyboard,
+                    [0, 0, HID_KEY_A, HID_KEY_CAPS_LOCK, 0, 0, 0, 0].as_ref()
+                )
+            );
+            assert_eq!(
+                hid.pending_key(&mut report),
+                Some((
+                    ReportType::Keyboard,
+                    [0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+                ))
+            );
+            assert_eq!(hid.pending_key(&mut report), None);
+        }
+
+        // Num Lock is held as usual
+        hid.key_down(0xEF7F, KeyMask::empty(), 3, &mut report);
+        assert_eq!(hid.pending_key(&mut report), None);
+        assert_eq!(
+            hid.held_keys().collect::<Vec<_>>(),
+            [HID_KEY_A, HID_KEY_NUM_LOCK]
+        );
+    }
+
+    #[test]
+    fn test_lock_sync() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_lock_sync(true);
+        let mut report = [0; 9];
+        let sync = |hid: &mut SynergyHid, key: u16, mask: KeyMask, button: u16| {
+            let mut reports = vec![];
+            hid.sync_locks(key, mask, button, &mut [0; 9], |(_, r)| {
+                reports.push(r.to_vec())
+            });
+            reports
+        };
+        let caps = vec![vec![0, 0, HID_KEY_CAPS_LOCK, 0, 0, 0, 0, 0], vec![0; 8]];
+        let num = vec![vec![0, 0, HID_KEY_NUM_LOCK, 0, 0, 0, 0, 0], vec![0; 8]];
+
+        // Unknown until the host sends its LEDs
+        assert!(sync(&mut hid, 'a' as u16, KeyMask::CAPS_LOCK, 1).is_empty());
+        hid.set_led_state(LedState::default());
+        // On for the server, off on the host, toggled once
+        assert_eq!(sync(&mut hid, 'A' as u16, KeyMask::CAPS_LOCK, 1), caps);
+        assert!(sync(&mut hid, 'A' as u16, KeyMask::CAPS_LOCK, 1).is_empty());
+        // The LED report of the toggle agrees
+        hid.set_led_state(LedState {
+            caps_lock: true,
+            ..Default::default()
+        });
+        assert!(sync(&mut hid, 'A' as u16, KeyMask::CAPS_LOCK, 1).is_empty());
+        // Turned off on the host's own keyboard, on for the server
+        hid.set_led_state(LedState::default());
+        assert_eq!(sync(&mut hid, 'b' as u16, KeyMask::CAPS_LOCK, 2), caps);
+        // Off for the server, on on the host
+        hid.set_led_state(LedState {
+            caps_lock: true,
+            num_lock: true,
+            ..Default::default()
+        });
+        let both: Vec<_> = caps.iter().chain(&num).cloned().collect();
+        assert_eq!(sync(&mut hid, 'c' as u16, KeyMask::empty(), 3), both);
+        assert!(sync(&mut hid, 'c' as u16, KeyMask::empty(), 3).is_empty());
+
+        // The lock keys are left alone, their press toggles the host
+        assert!(sync(&mut hid, 0xEFE5, KeyMask::CAPS_LOCK, 4).is_empty());
+        hid.key_down(0xEFE5, KeyMask::empty(), 4, &mut report);
+        assert!(sync(&mut hid, 0xEFE5, KeyMask::CAPS_LOCK, 4).is_empty());
+        hid.key_up(0xEFE5, KeyMask::CAPS_LOCK, 4, &mut report);
+        assert!(sync(&mut hid, 'd' as u16, KeyMask::CAPS_LOCK, 5).is_empty());
+
+        // Half-duplex, the key down turns it on and the key up off, each a press
+        hid.set_half_duplex_caps_lock(true);
+        hid.set_led_state(LedState::default());
+        hid.key_down(0xEFE5, KeyMask::CAPS_LOCK, 6, &mut report);
+        assert!(sync(&mut hid, 'e' as u16, KeyMask::CAPS_LOCK, 7).is_empty());
+        hid.pending_key(&mut report);
+        hid.key_up(0xEFE5, KeyMask::CAPS_LOCK, 6, &mut report);
+        // Still held until the pending release
+        assert!(sync(&mut hid, 'e' as u16, KeyMask::CAPS_LOCK, 7).is_empty());
+        hid.pending_key(&mut report);
+        assert!(sync(&mut hid, 'e' as u16, KeyMask::empty(), 7).is_empty());
+        assert_eq!(sync(&mut hid, 'e' as u16, KeyMask::CAPS_LOCK, 7), caps);
+
+        // Off by default
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        hid.set_led_state(LedState::default());
+        assert!(sync(&mut hid, 'a' as u16, KeyMask::CAPS_LOCK, 1).is_empty());
+    }
+
+    #[test]
+    fn test_tap_keys() {
+        let mut hid = super::SynergyHid::new(1920, 1080, false);
+        let mut report = [0; 9];
+        hid.key_down('a' as u16, KeyMask::empty(), 1, &mut report);
+
+        // Ctrl+Alt+A, A is held already and stays held
+        let mut reports = vec![];
+        hid.tap_keys(&[0xE0, 0xE2, HID_KEY_A], &mut report, |(t, r)| {
+            reports.push((t, r.to_vec()))
+        });
+        assert_eq!(
+            reports,
+            [
+                [0x01, 0, HID_KEY_A, 0, 0, 0, 0, 0],
+                [0x05, 0, HID_KEY_A, 0, 0, 0, 0, 0],
+                [0x01, 0, HID_KEY_A, 0, 0, 0, 0, 0],
+                [0x00, 0, HID_KEY_A, 0, 0, 0, 0, 0],
+            ]
+            .map(|r| (ReportType::Keyboard, r.to_vec()))
+        );
+        assert_eq!(hid.held_keys().collect::<Vec<_>>(), [HID_KEY_A]);
+        assert_eq!(hid.modifiers(), 0);
+    }
+
+    #[test]
+    fn test_macro() {
+        let mut macros = MacroTable::new();
+        // Ctrl+Alt+m types "B" after a delay
+        macros.insert(
+            0x6D,
+            KeyMask::CONTROL | KeyMask::ALT,
+            vec![MacroStep::Delay(50), MacroStep::Text("B".to_string())],
+        );
+        let mut hid = super::SynergyHid::new(1920, 1080, false).with_macros(macros);
+        let mut report = [0; 9];
+        hid.key_down(0xEFE3, KeyMask::empty(), 0x25, &mut report);
+        hid.key_down(0xEFE9, KeyMask::CONTROL, 0x40, &mut report);
+
+        // The trigger isn't pressed, the modifiers held are released while the macro runs
+        assert_eq!(
+            hid.key_down(
+                0x6D,
+                KeyMask::CONTROL | KeyMask::ALT | KeyMask::CAPS_LOCK,
+                0x3A,
+                &mut report
+            ),
+            (ReportType::Keyboard, [0; 8].as_ref())
+        );
+        // Triggers can't nest
+        assert_eq!(
+            hid.key_down(0x6D, KeyMask::CONTROL | KeyMask::ALT, 0x3B, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        let mut events = vec![];
+        while let Some(event) = hid.pending_macro(&mut report) {
+            events.push(match event {
+                MacroEvent::Report(t, r) => Ok((t, r.to_vec())),
+                MacroEvent::Delay(delay) => Err(delay),
+            });
+        }
+        let keyboard = |r: [u8; 8]| Ok((ReportType::Keyboard, r.to_vec()));
+        assert_eq!(
+            events,
+            [
+                Err(Duration::from_millis(50)),
+                keyboard([0x02, 0, 0, 0, 0, 0, 0, 0]),
+                keyboard([0x02, 0, HID_KEY_B, 0, 0, 0, 0, 0]),
+                keyboard([0x02, 0, 0, 0, 0, 0, 0, 0]),
+                keyboard([0; 8]),
+                // Ctrl+Alt are still held on the server
+                keyboard([0x05, 0, 0, 0, 0, 0, 0, 0]),
+            ]
+        );
+        assert_eq!(hid.modifiers(), 0x05);
+
+        // The key ups of the triggers are suppressed, the key repeats too
+        let mut repeats = 0;
+        hid.key_repeat(
+            0x6D,
+            KeyMask::CONTROL | KeyMask::ALT,
+            0x3A,
+            2,
+            &mut report,
+            |_| repeats += 1,
+        );
+        assert_eq!(repeats, 0);
+        assert_eq!(
+            hid.key_up(0x6D, KeyMask::CONTROL | KeyMask::ALT, 0x3A, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        assert_eq!(
+            hid.key_up(0x6D, KeyMask::CONTROL | KeyMask::ALT, 0x3B, &mut report),
+            (ReportType::Keyboard, [].as_ref())
+        );
+        assert!(hid.pending_macro(&mut report).is_none());
+
+        // Without Alt it's a plain 'm'
+        assert_eq!(
+            hid.key_down(0x6D, KeyMask::CONTROL, 0x3A, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x05, 0, HID_KEY_M, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+
+        // Started without a trigger, the keys held come back once it's done
+        assert!(hid.start_macro(&[MacroStep::Text("b".to_string())]));
+        assert!(!hid.start_macro(&[MacroStep::Text("b".to_string())]));
+        let mut reports = vec![];
+        while let Some(MacroEvent::Report(_, r)) = hid.pending_macro(&mut report) {
+            reports.push(r.to_vec());
+        }
+        assert_eq!(
+            reports,
+            [
+                vec![0, 0, HID_KEY_B, 0, 0, 0, 0, 0],
+                vec![0; 8],
+                vec![0x05, 0, HID_KEY_M, 0, 0, 0, 0, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unicode_input() {
+        let drain = |hid: &mut SynergyHid| {
+            let mut report = [0; 8];
+            let mut reports = vec![];
+            while let Some(MacroEvent::Report(_, r)) = hid.pending_macro(&mut report) {
+                reports.push(r.to_vec());
+            }
+            reports
+        };
+        let keyboard = |modifiers: u8, key: u8| vec![modifiers, 0, key, 0, 0, 0, 0, 0];
+        let mut report = [0; 8];
+        let empty = (ReportType::Keyboard, [].as_ref());
+
+        // Dropped by default
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        assert_eq!(
+            hid.key_down(0x301, KeyMask::empty(), 0x28, &mut report),
+            empty
+        );
+        assert_eq!(
+            hid.key_down(0xE9, KeyMask::empty(), 0x1F, &mut report),
+            empty
+        );
+        assert!(drain(&mut hid).is_empty());
+
+        // The dead key is held back, é is typed once the e is pressed
+        let mut hid = SynergyHid::new(1920, 1080, false).with_unicode_input(UnicodeInput::Linux);
+        assert_eq!(
+            hid.key_down(0x301, KeyMask::empty(), 0x28, &mut report),
+            empty
+        );
+        assert_eq!(
+            hid.key_up(0x301, KeyMask::empty(), 0x28, &mut report),
+            empty
+        );
+        assert_eq!(
+            hid.key_down('e' as u16, KeyMask::empty(), 0x12, &mut report),
+            (ReportType::Keyboard, [0; 8].as_ref())
+        );
+        let mut repeats = 0;
+        hid.key_repeat('e' as u16, KeyMask::empty(), 0x12, 2, &mut report, |_| {
+            repeats += 1
+        });
+        assert_eq!(repeats, 0);
+        assert_eq!(
+            drain(&mut hid),
+            [
+                keyboard(0x01, 0),
+                keyboard(0x03, 0),
+                keyboard(0x03, HID_KEY_U),
+                keyboard(0x03, 0),
+                keyboard(0x01, 0),
+                keyboard(0, 0),
+                keyboard(0, HID_KEY_E),
+                keyboard(0, 0),
+                keyboard(0, HID_KEY_9),
+                keyboard(0, 0),
+                keyboard(0, HID_KEY_SPACE),
+                keyboard(0, 0),
+                keyboard(0, 0),
+            ]
+        );
+        assert_eq!(
+            hid.key_up('e' as u16, KeyMask::empty(), 0x12, &mut report),
+            empty
+        );
+        // Without a dead key the e is pressed
+        assert_eq!(
+            hid.key_down('e' as u16, KeyMask::empty(), 0x12, &mut report),
+            (ReportType::Keyboard, keyboard(0, HID_KEY_E).as_ref())
+        );
+        assert_eq!(
+            hid.key_up('e' as u16, KeyMask::empty(), 0x12, &mut report),
+            (ReportType::Keyboard, [0; 8].as_ref())
+        );
+        // ü sent directly
+        hid.key_down(0xFC, KeyMask::empty(), 0x1A, &mut report);
+        assert_eq!(
+            drain(&mut hid)[6..12],
+            [
+                keyboard(0, HID_KEY_F),
+                keyboard(0, 0),
+                keyboard(0, HID_KEY_C),
+                keyboard(0, 0),
+                keyboard(0, HID_KEY_SPACE),
+                keyboard(0, 0),
+            ]
+        );
+
+        // U+1F600 comes as a surrogate pair, typed as two UTF-16 units on Windows
+        let mut hid = SynergyHid::new(1920, 1080, false).with_unicode_input(UnicodeInput::Windows);
+        assert_eq!(
+            hid.key_down(0xD83D, KeyMask::empty(), 0x10, &mut report),
+            empty
+        );
+        assert!(drain(&mut hid).is_empty());
+        hid.key_down(0xDE00, KeyMask::empty(), 0x10, &mut report);
+        let reports = drain(&mut hid);
+        assert_eq!(reports.len(), 25);
+        for unit in [&reports[..12], &reports[12..24]] {
+            assert_eq!(
+                unit[..4],
+                [
+                    keyboard(0x04, 0),
+                    keyboard(0x04, HID_KEY_KEYPAD_ADD),
+                    keyboard(0x04, 0),
+                    keyboard(0x04, HID_KEY_D),
+                ]
+            );
+            assert_eq!(unit[11], keyboard(0, 0));
+        }
+    }
+
+    #[test]
+    fn test_output_report() {
+        assert_eq!(
+            SynergyHid::parse_output_report(ReportType::Keyboard, &[0x03]),
+            Some(LedState {
+                num_lock: true,
+                caps_lock: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            SynergyHid::parse_output_report(ReportType::Keyboard, &[0x1C]),
+            Some(LedState {
+                scroll_lock: true,
+                compose: true,
+                kana: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            SynergyHid::parse_output_report(ReportType::Keyboard, &[]),
+            None
+        );
+        assert_eq!(
+            SynergyHid::parse_output_report(ReportType::Mouse, &[0x01]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stuck_keys() {
+        let secs = Duration::from_secs;
+        let keyboard = |bytes: [u8; 8]| Report::from((ReportType::Keyboard, bytes.as_ref()));
+        let timeouts = StuckKeyTimeouts {
+            modifier: Some(secs(30)),
+            key: Some(secs(60)),
+        };
+        let mut hid = SynergyHid::new(1920, 1080, false).with_stuck_key_timeouts(timeouts);
+        let mut report = [0; MAX_REPORT_LEN];
+        hid.key_down(0xEFE1, KeyMask::empty(), 0x32, &mut report);
+        hid.key_down('a' as u16, KeyMask::SHIFT, 0x1E, &mut report);
+        assert_eq!(hid.tick(secs(100)), []);
+        hid.key_repeat('a' as u16, KeyMask::SHIFT, 0x1E, 1, &mut report, |_| {});
+        assert_eq!(hid.tick(secs(120)), []);
+        assert_eq!(
+            hid.tick(secs(130)),
+            [keyboard([0, 0, HID_KEY_A, 0, 0, 0, 0, 0])]
+        );
+        // The repeat started over
+        assert_eq!(hid.tick(secs(179)), []);
+        assert_eq!(hid.tick(secs(180)), [keyboard([0; 8])]);
+        assert!(hid.is_keyboard_idle());
+
+        // The key ups arriving late change nothing
+        assert_eq!(hid.key_up_report(0xEFE1, KeyMask::SHIFT, 0x32), None);
+        assert_eq!(hid.key_up_report('a' as u16, KeyMask::empty(), 0x1E), None);
+        assert_eq!(hid.tick(secs(1000)), []);
+
+        // Released by the server in time
+        hid.key_down('a' as u16, KeyMask::empty(), 0x1E, &mut report);
+        assert_eq!(hid.tick(secs(1000)), []);
+        hid.key_up('a' as u16, KeyMask::empty(), 0x1E, &mut report);
+        assert_eq!(hid.tick(secs(2000)), []);
+
+        // Keys are never released by default
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        hid.key_down(0xEFE1, KeyMask::empty(), 0x32, &mut report);
+        assert_eq!(hid.tick(Duration::ZERO), []);
+        assert_eq!(hid.tick(secs(1000)), []);
+        assert_eq!(hid.modifiers(), 0x02);
+    }
+
+    #[test]
+    fn test_gamepad() {
+        for (value, y, axis) in [
+            (0, false, 0),
+            (0, true, 0),
+            (1000, false, 1000),
+            (1000, true, -1000),
+            (-1000, true, 1000),
+            (i16::MAX, false, 32767),
+            (i16::MAX, true, -32767),
+            // Out of the logical range, which is centered on 0
+            (i16::MIN, false, -32767),
+            (i16::MIN, true, 32767),
+        ] {
+            assert_eq!(crate::stick_axis(value, y), axis, "{value} {y}");
+        }
+
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut report = [0; MAX_REPORT_LEN];
+        assert_eq!(hid.report_len(ReportType::Gamepad), GAMEPAD_REPORT_LEN);
+        // A and D-pad up
+        assert_eq!(
+            hid.game_buttons(0x1001, &mut report),
+            (
+                ReportType::Gamepad,
+                [0x01, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // Left stick up left, right stick down
+        assert_eq!(
+            hid.game_sticks((i16::MIN, i16::MAX), (100, i16::MIN), &mut report),
+            (
+                ReportType::Gamepad,
+                [0x01, 0x10, 0x01, 0x80, 0x01, 0x80, 100, 0, 0xFF, 0x7F, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.game_triggers_report(255, 3).as_bytes(),
+            [0x01, 0x10, 0x01, 0x80, 0x01, 0x80, 100, 0, 0xFF, 0x7F, 255, 3]
+        );
+        assert_eq!(
+            hid.clear(ReportType::Gamepad, &mut report),
+            (ReportType::Gamepad, [0; GAMEPAD_REPORT_LEN].as_ref())
+        );
+    }
+
+    #[test]
+    fn test_canonical_reports() {
+        let mut hid = SynergyHid::new(1920, 1080, false).with_canonical_reports(true);
+        let mut report = [0; MAX_REPORT_LEN];
+        hid.key_down('q' as u16, KeyMask::empty(), 0x10, &mut report);
+        hid.key_down('b' as u16, KeyMask::empty(), 0x30, &mut report);
+        assert_eq!(
+            hid.key_down('a' as u16, KeyMask::SHIFT, 0x1E, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x02, 0, HID_KEY_A, HID_KEY_B, HID_KEY_Q, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up('b' as u16, KeyMask::SHIFT, 0x30, &mut report),
+            (
+                ReportType::Keyboard,
+                [0x02, 0, HID_KEY_A, HID_KEY_Q, 0, 0, 0, 0].as_ref()
+            )
+        );
+        // The state keeps the pressed order
+        assert_eq!(hid.state().keys, [HID_KEY_Q, HID_KEY_A]);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut report = [0; MAX_REPORT_LEN];
+        hid.set_cursor_position(100, 200, &mut report);
+        hid.mouse_down(1, &mut report);
+        hid.key_down('a' as u16, KeyMask::SHIFT, 0x1E, &mut report);
+        hid.key_down(0xE0AD, KeyMask::empty(), 0x60, &mut report);
+        let state = hid.state();
+        assert_eq!(
+            state,
+            HidState {
+                cursor: (100, 200),
+                mouse_buttons: 0x01,
+                synthetic_modifiers: 0x02,
+                keys: vec![HID_KEY_A],
+                consumer: vec![0xE2],
+                buttons: vec![(0x1E, 'a' as u16), (0x60, 0xE0AD)],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            hid.dump_state(),
+            "cursor: 100,200\n\
+             pending: motion 0,0 wheel 0,0\n\
+             mouse buttons: 0x01\n\
+             modifiers: 0x00 synthetic 0x02\n\
+             keys: [04]\n\
+             consumer: [00e2]\n\
+             system: []\n\
+             buttons: 30=0x0061 96=0xe0ad\n\
+             macro running: false"
+        );
+        hid.clear_all();
+        assert_eq!(
+            hid.state(),
+            HidState {
+                cursor: (100, 200),
+                ..Default::default()
+            }
+        );
+    }
+
+    // xorshift64, the sequences are the same on every run so a failure can be replayed
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    // The report emitted agrees with the state after it
+    fn check_report(state: &HidState, report_type: ReportType, report: &[u8]) {
+        match report_type {
+            _ if report.is_empty() => {}
+            ReportType::Keyboard => {
+                assert_eq!(report[0], state.modifiers | state.synthetic_modifiers);
+                let slots = &report[2..8];
+                if state.keys.len() > 6 {
+                    assert_eq!(slots, [0x01; 6]);
+                    return;
+                }
+                let mut keys = state.keys.clone();
+                keys.sort_unstable();
+                keys.dedup();
+                assert_eq!(keys.len(), state.keys.len(), "duplicate keys held");
+                // Ascending without duplicates and no modifier usage, then the free slots
+                assert_eq!(&slots[..keys.len()], keys);
+                assert!(slots[keys.len()..].iter().all(|key| *key == 0));
+                assert!(keys.iter().all(|key| !(0xE0..=0xE7).contains(key)));
+            }
+            ReportType::Mouse => assert_eq!(report[0], state.mouse_buttons),
+            ReportType::Consumer => {
+                let held: Vec<u16> = report
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|usage| u16::from_le_bytes(*usage))
+                    .take_while(|usage| *usage != 0)
+                    .collect();
+                assert_eq!(held, state.consumer);
+                let system = state
+                    .system
+                    .iter()
+                    .fold(0, |bits, usage| bits | 1 << (usage - 0x81));
+                assert_eq!(report[8], system);
+            }
+            ReportType::RelativeMouse | ReportType::Gamepad => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_random_sequences() {
+        // Letters, digits, modifiers and media keys, each on a button of its own
+        let keys: Vec<u16> = (0x61..=0x6A)
+            .chain(0x30..=0x33)
+            .chain([0xEFE1, 0xEFE3, 0xEFE9, 0xE0AD, 0xE0AE, 0xE0B5, 0xE05F])
+            .collect();
+        for seed in 1..=64 {
+            let mut rng = Rng(0x9E37_79B9_7F4A_7C15_u64.wrapping_mul(seed));
+            let mut hid = SynergyHid::new(1920, 1080, false).with_canonical_reports(true);
+            let mut report = [0; MAX_REPORT_LEN];
+            for step in 0..200 {
+                let index = rng.below(keys.len());
+                let (key, button) = (keys[index], index as u16 + 1);
+                let mask = if rng.below(4) == 0 {
+                    KeyMask::SHIFT
+                } else {
+                    KeyMask::empty()
+                };
+                let mouse_button = rng.below(5) as i8 + 1;
+                let (report_type, bytes) = match rng.below(6) {
+                    0..=2 => hid.key_down(key, mask, button, &mut report),
+                    3 => hid.key_up(key, mask, button, &mut report),
+                    4 => hid.mouse_down(mouse_button, &mut report),
+                    _ => hid.mouse_up(mouse_button, &mut report),
+                };
+                let bytes = bytes.to_vec();
+                let state = hid.state();
+                let result = std::panic::catch_unwind(|| check_report(&state, report_type, &bytes));
+                assert!(
+                    result.is_ok(),
+                    "seed {seed} step {step}: {report_type:?} {bytes:02x?} with\n{state}"
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,25 @@
+//! `debug!` and `warn!` for the rest of the crate, logged with `log` or `defmt` depending on
+//! the feature enabled, `defmt` wins if both are. With neither, the arguments are still
+//! type-checked but nothing is logged.
+
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        ::log::debug!($($arg)+);
+        #[cfg(feature = "defmt")]
+        ::defmt::debug!("{}", ::defmt::Display2Format(&format_args!($($arg)+)));
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        ::log::warn!($($arg)+);
+        #[cfg(feature = "defmt")]
+        ::defmt::warn!("{}", ::defmt::Display2Format(&format_args!($($arg)+)));
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        let _ = format_args!($($arg)+);
+    }};
+}
@@ -0,0 +1,225 @@
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{keycodes::ASCII_2_HID, KeyMask};
+
+/// One step of a macro, a map with one of `press`, `release`, `delay` or `text` in a file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "StepFields")]
+pub enum MacroStep {
+    /// Presses a HID keyboard usage, modifiers included, e.g. 0xE0 for Left Ctrl
+    Press(u8),
+    /// Releases a HID keyboard usage
+    Release(u8),
+    /// Waits this many milliseconds before the next step
+    Delay(u64),
+    /// Types ASCII text with the US layout, other characters are skipped
+    Text(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepFields {
+    press: Option<u8>,
+    release: Option<u8>,
+    delay: Option<u64>,
+    text: Option<String>,
+}
+
+impl TryFrom<StepFields> for MacroStep {
+    type Error = String;
+
+    fn try_from(fields: StepFields) -> Result<Self, Self::Error> {
+        match fields {
+            StepFields {
+                press: Some(key),
+                release: None,
+                delay: None,
+                text: None,
+            } => Ok(MacroStep::Press(key)),
+            StepFields {
+                press: None,
+                release: Some(key),
+                delay: None,
+                text: None,
+            } => Ok(MacroStep::Release(key)),
+            StepFields {
+                press: None,
+                release: None,
+                delay: Some(ms),
+                text: None,
+            } => Ok(MacroStep::Delay(ms)),
+            StepFields {
+                press: None,
+                release: None,
+                delay: None,
+                text: Some(text),
+            } => Ok(MacroStep::Text(text)),
+            _ => Err("a macro step needs exactly one of press, release, delay or text".into()),
+        }
+    }
+}
+
+/// One entry of a macro file, the steps run when `key` goes down with exactly the modifiers
+/// of `mask` held
+#[derive(Clone, Debug, Deserialize)]
+pub struct MacroEntry {
+    /// Synergy key id of the trigger, e.g. 0x6D for 'm'
+    pub key: u16,
+    /// Synergy modifier mask of the trigger, e.g. 0x0006 for Ctrl+Alt, lock states are ignored
+    #[serde(default)]
+    pub mask: KeyMask,
+    pub steps: Vec<MacroStep>,
+}
+
+/// Macros triggered by Synergy key combos
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "Vec<MacroEntry>")]
+pub struct MacroTable {
+    macros: BTreeMap<(u16, u16), Vec<MacroStep>>,
+}
+
+impl MacroTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `steps` when `key` goes down with the modifiers of `mask`, replacing the macro
+    /// already on that trigger
+    pub fn insert(&mut self, key: u16, mask: KeyMask, steps: Vec<MacroStep>) {
+        let mask = (mask & KeyMask::MODIFIERS).bits();
+        if self.macros.insert((key, mask), steps).is_some() {
+            warn!("Macro on key {:#06x} mask {:#06x} defined twice", key, mask);
+        }
+    }
+
+    pub fn get(&self, key: u16, mask: KeyMask) -> Option<&[MacroStep]> {
+        self.macros
+            .get(&(key, (mask & KeyMask::MODIFIERS).bits()))
+            .map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.macros.is_empty()
+    }
+}
+
+impl From<Vec<MacroEntry>> for MacroTable {
+    fn from(entries: Vec<MacroEntry>) -> Self {
+        let mut table = Self::new();
+        for entry in entries {
+            table.insert(entry.key, entry.mask, entry.steps);
+        }
+        table
+    }
+}
+
+/// What the keyboard report goes through while a macro runs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum MacroAction {
+    Press(u8),
+    Release(u8),
+    Delay(Duration),
+}
+
+// Text is typed as a press and a release per character, with Shift around it if needed
+pub(crate) fn expand(steps: &[MacroStep]) -> VecDeque<MacroAction> {
+    let mut actions = VecDeque::new();
+    for step in steps {
+        match step {
+            MacroStep::Press(key) => actions.push_back(MacroAction::Press(*key)),
+            MacroStep::Release(key) => actions.push_back(MacroAction::Release(*key)),
+            MacroStep::Delay(ms) => {
+                actions.push_back(MacroAction::Delay(Duration::from_millis(*ms)))
+            }
+            MacroStep::Text(text) => {
+                for c in text.chars() {
+                    let [key, modifier] = match ASCII_2_HID.get(c as usize) {
+                        Some(&[key, modifier]) if key != 0 => [key, modifier],
+                        _ => {
+                            warn!("Character {:?} can't be typed by a macro", c);
+                            continue;
+                        }
+                    };
+                    if modifier != 0 {
+                        actions.push_back(MacroAction::Press(modifier));
+                    }
+                    actions.push_back(MacroAction::Press(key));
+                    actions.push_back(MacroAction::Release(key));
+                    if modifier != 0 {
+                        actions.push_back(MacroAction::Release(modifier));
+                    }
+                }
+            }
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{expand, MacroAction, MacroStep, MacroTable};
+    use crate::{
+        keycodes::{HID_KEY_A, HID_KEY_B, HID_KEY_SHIFT_LEFT},
+        KeyMask,
+    };
+
+    #[test]
+    fn test_macro_table() {
+        let table: MacroTable = serde_yaml::from_str(
+            r#"
+            # Ctrl+Alt+m
+            - key: 0x6D
+              mask: 0x0006
+              steps:
+                - press: 0xE0
+                - release: 0xE0
+                - delay: 100
+                - text: "aB"
+            # F1 alone
+            - key: 0xEFBE
+              steps:
+                - text: "hi"
+            "#,
+        )
+        .unwrap();
+
+        // Caps Lock doesn't matter
+        let steps = table
+            .get(0x6D, KeyMask::CONTROL | KeyMask::ALT | KeyMask::CAPS_LOCK)
+            .unwrap();
+        assert_eq!(steps[2], MacroStep::Delay(100));
+        assert!(table.get(0x6D, KeyMask::CONTROL).is_none());
+        assert!(table.get(0xEFBE, KeyMask::empty()).is_some());
+        assert!(table.get(0xEFBE, KeyMask::SHIFT).is_none());
+
+        assert_eq!(
+            expand(steps),
+            [
+                MacroAction::Press(0xE0),
+                MacroAction::Release(0xE0),
+                MacroAction::Delay(Duration::from_millis(100)),
+                MacroAction::Press(HID_KEY_A),
+                MacroAction::Release(HID_KEY_A),
+                MacroAction::Press(HID_KEY_SHIFT_LEFT),
+                MacroAction::Press(HID_KEY_B),
+                MacroAction::Release(HID_KEY_B),
+                MacroAction::Release(HID_KEY_SHIFT_LEFT),
+            ]
+        );
+        assert!(
+            serde_yaml::from_str::<MacroTable>("[{key: 0x6D, steps: [{press: 1, delay: 2}]}]")
+                .is_err()
+        );
+        // Not in the US layout
+        assert!(expand(&[MacroStep::Text("é\u{1}".to_string())]).is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{
+    Report, ReportType, ABS_MOUSE_REPORT_LEN, BOOT_KEYBOARD_REPORT_LEN, CONSUMER_REPORT_LEN,
+    GAMEPAD_REPORT_LEN, NKRO_REPORT_LEN, REL_MOUSE_REPORT_LEN,
+};
+
+macro_rules! hid_report {
+    ($(#[$doc:meta])* $name:ident, $len:expr) => {
+        $(#[$doc])*
+        #[repr(transparent)]
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub struct $name([u8; $len]);
+
+        impl $name {
+            pub fn as_bytes(&self) -> &[u8; $len] {
+                &self.0
+            }
+        }
+    };
+}
+
+hid_report!(
+    /// Boot keyboard report, the modifiers, a reserved byte and 6 keys
+    KeyboardHidReport,
+    BOOT_KEYBOARD_REPORT_LEN
+);
+hid_report!(
+    /// NKRO keyboard report, the modifiers and a bit per usage
+    NkroKeyboardHidReport,
+    NKRO_REPORT_LEN
+);
+hid_report!(
+    /// Absolute mouse report, the buttons, x, y, wheel and pan
+    MouseHidReport,
+    ABS_MOUSE_REPORT_LEN
+);
+hid_report!(
+    /// Boot relative mouse report, the buttons, x, y, wheel and pan
+    RelativeMouseHidReport,
+    REL_MOUSE_REPORT_LEN
+);
+hid_report!(
+    /// Consumer control report, the usages held and the System Control bits
+    ConsumerHidReport,
+    CONSUMER_REPORT_LEN
+);
+hid_report!(
+    /// Gamepad report, the buttons, both sticks and the triggers
+    GamepadHidReport,
+    GAMEPAD_REPORT_LEN
+);
+
+/// A report returned by value by the `*_report` methods of [`SynergyHid`](crate::SynergyHid),
+/// sized for its device so it can be written without copying it to a buffer first
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HidReport {
+    Keyboard(KeyboardHidReport),
+    NkroKeyboard(NkroKeyboardHidReport),
+    Mouse(MouseHidReport),
+    RelativeMouse(RelativeMouseHidReport),
+    Consumer(ConsumerHidReport),
+    Gamepad(GamepadHidReport),
+}
+
+impl HidReport {
+    pub(crate) fn keyboard(bytes: [u8; BOOT_KEYBOARD_REPORT_LEN]) -> Self {
+        Self::Keyboard(KeyboardHidReport(bytes))
+    }
+
+    pub(crate) fn nkro_keyboard(bytes: [u8; NKRO_REPORT_LEN]) -> Self {
+        Self::NkroKeyboard(NkroKeyboardHidReport(bytes))
+    }
+
+    pub(crate) fn mouse(bytes: [u8; ABS_MOUSE_REPORT_LEN]) -> Self {
+        Self::Mouse(MouseHidReport(bytes))
+    }
+
+    pub(crate) fn relative_mouse(bytes: [u8; REL_MOUSE_REPORT_LEN]) -> Self {
+        Self::RelativeMouse(RelativeMouseHidReport(bytes))
+    }
+
+    pub(crate) fn consumer(bytes: [u8; CONSUMER_REPORT_LEN]) -> Self {
+        Self::Consumer(ConsumerHidReport(bytes))
+    }
+
+    pub(crate) fn gamepad(bytes: [u8; GAMEPAD_REPORT_LEN]) -> Self {
+        Self::Gamepad(GamepadHidReport(bytes))
+    }
+
+    /// Both keyboards are `ReportType::Keyboard`, the one enabled is the only one registered
+    pub fn report_type(&self) -> ReportType {
+        match self {
+            HidReport::Keyboard(_) | HidReport::NkroKeyboard(_) => ReportType::Keyboard,
+            HidReport::Mouse(_) => ReportType::Mouse,
+            HidReport::RelativeMouse(_) => ReportType::RelativeMouse,
+            HidReport::Consumer(_) => ReportType::Consumer,
+            HidReport::Gamepad(_) => ReportType::Gamepad,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            HidReport::Keyboard(report) => report.as_bytes(),
+            HidReport::NkroKeyboard(report) => report.as_bytes(),
+            HidReport::Mouse(report) => report.as_bytes(),
+            HidReport::RelativeMouse(report) => report.as_bytes(),
+            HidReport::Consumer(report) => report.as_bytes(),
+            HidReport::Gamepad(report) => report.as_bytes(),
+        }
+    }
+
+    /// The type and the bytes as returned by the methods writing to a buffer
+    pub fn as_parts(&self) -> (ReportType, &[u8]) {
+        (self.report_type(), self.as_bytes())
+    }
+}
+
+impl From<HidReport> for Report {
+    fn from(report: HidReport) -> Self {
+        Report::from(report.as_parts())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HidReport;
+    use crate::{KeyMask, KeyboardMode, MouseMode, Report, ReportType, SynergyHid};
+
+    // The same events through the buffer and the by-value methods
+    fn script(hid: &mut SynergyHid, by_value: bool) -> Vec<(ReportType, Vec<u8>)> {
+        let mut reports = vec![];
+        let buf = &mut [0; SynergyHid::MAX_REPORT_LEN];
+        let mut push = |report: (ReportType, &[u8])| reports.push((report.0, report.1.to_vec()));
+        let none = |report: Option<HidReport>| match report {
+            Some(report) => Report::from(report),
+            None => Report::from((ReportType::Keyboard, &[][..])),
+        };
+        macro_rules! step {
+            ($slice:expr, $value:expr) => {
+                if by_value {
+                    push(Report::from($value).as_parts());
+                } else {
+                    push($slice);
+                }
+            };
+        }
+        step!(
+            hid.set_cursor_position(100, 200, buf),
+            hid.set_cursor_position_report(100, 200)
+        );
+        step!(
+            hid.move_cursor(-300, 5, buf),
+            hid.move_cursor_report(-300, 5)
+        );
+        while let Some(report) = if by_value {
+            hid.pending_motion_report().map(Report::from)
+        } else {
+            hid.pending_motion(buf).map(Report::from)
+        } {
+            push(report.as_parts());
+        }
+        step!(hid.mouse_down(1, buf), hid.mouse_down_report(1));
+        step!(hid.mouse_up(1, buf), hid.mouse_up_report(1));
+        step!(
+            hid.mouse_scroll(0, 360, buf),
+            hid.mouse_scroll_report(0, 360)
+        );
+        while let Some(report) = if by_value {
+            hid.pending_scroll_report().map(Report::from)
+        } else {
+            hid.pending_scroll(buf).map(Report::from)
+        } {
+            push(report.as_parts());
+        }
+        // Shift+A, a media key and a key without a usage
+        for (key, button) in [(0xEFE1, 50), (0x41, 38), (0xE0B5, 60), (0xFFFF, 70)] {
+            step!(
+                hid.key_down(key, KeyMask::SHIFT, button, buf),
+                none(hid.key_down_report(key, KeyMask::SHIFT, button))
+            );
+        }
+        for (key, button) in [(0x41, 38), (0xE0B5, 60), (0xFFFF, 70), (0, 99)] {
+            step!(
+                hid.key_up(key, KeyMask::SHIFT, button, buf),
+                none(hid.key_up_report(key, KeyMask::SHIFT, button))
+            );
+        }
+        for report_type in [
+            ReportType::Keyboard,
+            ReportType::Mouse,
+            ReportType::Consumer,
+        ] {
+            step!(hid.clear(report_type, buf), hid.clear_report(report_type));
+        }
+        reports
+    }
+
+    #[test]
+    fn test_same_reports() {
+        for mouse_mode in [MouseMode::Absolute, MouseMode::Relative] {
+            for keyboard_mode in [KeyboardMode::Boot, KeyboardMode::Nkro] {
+                let hid = || {
+                    SynergyHid::with_mouse_mode(1920, 1080, false, mouse_mode)
+                        .with_keyboard_mode(keyboard_mode)
+                };
+                let by_value = script(&mut hid(), true);
+                assert_eq!(by_value, script(&mut hid(), false));
+                assert!(by_value.iter().any(|(_, bytes)| bytes.is_empty()));
+                assert!(by_value.iter().all(|(report_type, bytes)| bytes.is_empty()
+                    || bytes.len() == hid().report_len(*report_type)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hid_report() {
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let report = hid.key_down_report(0x61, KeyMask::empty(), 38).unwrap();
+        assert_eq!(report.report_type(), ReportType::Keyboard);
+        assert_eq!(report.as_bytes(), [0, 0, 0x04, 0, 0, 0, 0, 0]);
+        let HidReport::Keyboard(keyboard) = report else {
+            panic!("{report:?}");
+        };
+        assert_eq!(keyboard.as_bytes(), &[0, 0, 0x04, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            hid.mouse_down_report(1).as_parts(),
+            (ReportType::Mouse, &[1, 0, 0, 0, 0, 0, 0][..])
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Fallback from the `button` of a key event, the scan code on the server, to a HID usage.
+//!
+//! Some servers send keys they can't translate with the key id 0, the button is then the only
+//! way to tell which key it is. Its meaning depends on the platform of the server.
+
+use crate::KeyCode;
+
+/// Platform of the server, selects how the button of a key with id 0 is mapped
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ServerPlatform {
+    /// Keys with id 0 are dropped
+    #[default]
+    Unknown,
+    /// PC scan code set 1, 0x100 is added for the keys with the E0 prefix
+    Windows,
+    /// X keycodes, the Linux evdev code plus 8
+    X11,
+    /// Virtual keycodes plus 1, as the button 0 is reserved
+    MacOs,
+}
+
+impl ServerPlatform {
+    /// HID usage of the key pressed with `button`, `KeyCode::None` if unknown
+    pub fn button_to_hid(self, button: u16) -> KeyCode {
+        let hid = match self {
+            ServerPlatform::Unknown => None,
+            ServerPlatform::Windows => set1_to_hid(button),
+            ServerPlatform::X11 => button.checked_sub(8).and_then(evdev_to_hid),
+            ServerPlatform::MacOs => button
+                .checked_sub(1)
+                .and_then(|code| MAC_TABLE.get(code as usize).copied()),
+        };
+        match hid {
+            Some(0) | None => KeyCode::None,
+            Some(hid) => KeyCode::Key(hid),
+        }
+    }
+}
+
+// Scan code set 1 without prefix, the evdev codes are the same up to F12
+#[rustfmt::skip]
+const SET1_TABLE: [u8; 0x59] = [
+    0x00, 0x29, 0x1E, 0x1F, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x2D, 0x2E, 0x2A, 0x2B,
+    0x14, 0x1A, 0x08, 0x15, 0x17, 0x1C, 0x18, 0x0C, 0x12, 0x13, 0x2F, 0x30, 0x28, 0xE0, 0x04, 0x16,
+    0x07, 0x09, 0x0A, 0x0B, 0x0D, 0x0E, 0x0F, 0x33, 0x34, 0x35, 0xE1, 0x31, 0x1D, 0x1B, 0x06, 0x19,
+    0x05, 0x11, 0x10, 0x36, 0x37, 0x38, 0xE5, 0x55, 0xE2, 0x2C, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+    0x3F, 0x40, 0x41, 0x42, 0x43, 0x53, 0x47, 0x5F, 0x60, 0x61, 0x56, 0x5C, 0x5D, 0x5E, 0x57, 0x59,
+    0x5A, 0x5B, 0x62, 0x63, 0x00, 0x00, 0x64, 0x44, 0x45,
+];
+
+// macOS virtual keycodes, kVK_ANSI_A is 0
+#[rustfmt::skip]
+const MAC_TABLE: [u8; 0x80] = [
+    0x04, 0x16, 0x07, 0x09, 0x0B, 0x0A, 0x1D, 0x1B, 0x06, 0x19, 0x64, 0x05, 0x14, 0x1A, 0x08, 0x15,
+    0x1C, 0x17, 0x1E, 0x1F, 0x20, 0x21, 0x23, 0x22, 0x2E, 0x26, 0x24, 0x2D, 0x25, 0x27, 0x30, 0x12,
+    0x18, 0x2F, 0x0C, 0x13, 0x28, 0x0F, 0x0D, 0x34, 0x0E, 0x33, 0x31, 0x36, 0x38, 0x11, 0x10, 0x37,
+    0x2B, 0x2C, 0x35, 0x2A, 0x00, 0x29, 0xE7, 0xE3, 0xE1, 0x39, 0xE2, 0xE0, 0xE5, 0xE6, 0xE4, 0x00,
+    0x6C, 0x63, 0x00, 0x55, 0x00, 0x57, 0x00, 0x53, 0x00, 0x00, 0x00, 0x54, 0x58, 0x00, 0x56, 0x6D,
+    0x6E, 0x67, 0x62, 0x59, 0x5A, 0x5B, 0x5C, 0x5D, 0x5E, 0x5F, 0x00, 0x60, 0x61, 0x00, 0x00, 0x00,
+    0x3E, 0x3F, 0x40, 0x3C, 0x41, 0x42, 0x00, 0x44, 0x00, 0x68, 0x6B, 0x69, 0x00, 0x43, 0x65, 0x45,
+    0x00, 0x6A, 0x49, 0x4A, 0x4B, 0x4C, 0x3D, 0x4D, 0x3B, 0x4E, 0x3A, 0x50, 0x4F, 0x51, 0x52, 0x00,
+];
+
+fn set1_to_hid(code: u16) -> Option<u8> {
+    if let Some(hid) = SET1_TABLE.get(code as usize) {
+        return Some(*hid);
+    }
+    // E0 prefixed
+    let hid = match code {
+        0x11C => 0x58, // Keypad Enter
+        0x11D => 0xE4, // Right Control
+        0x135 => 0x54, // Keypad Divide
+        0x137 => 0x46, // Print Screen
+        0x138 => 0xE6, // Right Alt
+        0x147 => 0x4A, // Home
+        0x148 => 0x52, // Up
+        0x149 => 0x4B, // Page Up
+        0x14B => 0x50, // Left
+        0x14D => 0x4F, // Right
+        0x14F => 0x4D, // End
+        0x150 => 0x51, // Down
+        0x151 => 0x4E, // Page Down
+        0x152 => 0x49, // Insert
+        0x153 => 0x4C, // Delete
+        0x15B => 0xE3, // Left GUI
+        0x15C => 0xE7, // Right GUI
+        0x15D => 0x65, // Application
+        _ => return None,
+    };
+    Some(hid)
+}
+
+fn evdev_to_hid(code: u16) -> Option<u8> {
+    // Same keys as the E0 prefixed scan codes
+    let code = match code {
+        0..=0x58 => code,
+        96 => 0x11C,
+        97 => 0x11D,
+        98 => 0x135,
+        99 => 0x137,
+        100 => 0x138,
+        102 => 0x147,
+        103 => 0x148,
+        104 => 0x149,
+        105 => 0x14B,
+        106 => 0x14D,
+        107 => 0x14F,
+        108 => 0x150,
+        109 => 0x151,
+        110 => 0x152,
+        111 => 0x153,
+        // Pause has no scan code of its own
+        119 => return Some(0x48),
+        125 => 0x15B,
+        126 => 0x15C,
+        127 => 0x15D,
+        _ => return None,
+    };
+    set1_to_hid(code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::ServerPlatform;
+    use crate::{
+        keycodes::{
+            HID_KEY_A, HID_KEY_ARROW_DOWN, HID_KEY_ARROW_LEFT, HID_KEY_ARROW_RIGHT,
+            HID_KEY_ARROW_UP, HID_KEY_ESCAPE, HID_KEY_Q, HID_KEY_Z,
+        },
+        KeyCode,
+    };
+
+    #[test]
+    fn test_platforms() {
+        for (windows, x11, mac, hid) in [
+            (0x1E, 38, 0x01, HID_KEY_A),
+            (0x10, 24, 0x0D, HID_KEY_Q),
+            (0x2C, 52, 0x07, HID_KEY_Z),
+            (0x01, 9, 0x36, HID_KEY_ESCAPE),
+            (0x148, 111, 0x7F, HID_KEY_ARROW_UP),
+            (0x14B, 113, 0x7C, HID_KEY_ARROW_LEFT),
+            (0x14D, 114, 0x7D, HID_KEY_ARROW_RIGHT),
+            (0x150, 116, 0x7E, HID_KEY_ARROW_DOWN),
+        ] {
+            assert_eq!(
+                ServerPlatform::Windows.button_to_hid(windows),
+                KeyCode::Key(hid)
+            );
+            assert_eq!(ServerPlatform::X11.button_to_hid(x11), KeyCode::Key(hid));
+            assert_eq!(ServerPlatform::MacOs.button_to_hid(mac), KeyCode::Key(hid));
+        }
+        assert_eq!(ServerPlatform::Unknown.button_to_hid(0x1E), KeyCode::None);
+        assert_eq!(ServerPlatform::Windows.button_to_hid(0x1FF), KeyCode::None);
+        assert_eq!(ServerPlatform::X11.button_to_hid(3), KeyCode::None);
+        assert_eq!(ServerPlatform::MacOs.button_to_hid(0), KeyCode::None);
+    }
+}
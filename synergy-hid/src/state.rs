@@ -0,0 +1,60 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Snapshot of what a [`SynergyHid`](crate::SynergyHid) holds, see `SynergyHid::state`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HidState {
+    /// Cursor position in screen coordinates
+    pub cursor: (u16, u16),
+    /// Relative motion not sent yet, only in relative mode
+    pub pending_motion: (i32, i32),
+    /// Wheel deltas not sent yet, in 1/120 of a notch
+    pub pending_wheel: (i32, i32),
+    /// Buttons held on the mouse of the current mode, bit 0 is the left button
+    pub mouse_buttons: u8,
+    /// Modifier bits of the keys held
+    pub modifiers: u8,
+    /// Modifier bits applied from the server's mask without their key held
+    pub synthetic_modifiers: u8,
+    /// HID usages of the non-modifier keys held, in the order they were pressed
+    pub keys: Vec<u8>,
+    /// Consumer control usages held, in the order they were pressed
+    pub consumer: Vec<u16>,
+    /// System Control usages held, in ascending order
+    pub system: Vec<u8>,
+    /// Server buttons with a key down, and the Synergy key id of each
+    pub buttons: Vec<(u16, u16)>,
+    /// A macro is running, the keys above are restored for the host once it's done
+    pub macro_running: bool,
+}
+
+impl fmt::Display for HidState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "cursor: {},{}", self.cursor.0, self.cursor.1)?;
+        writeln!(
+            f,
+            "pending: motion {},{} wheel {},{}",
+            self.pending_motion.0,
+            self.pending_motion.1,
+            self.pending_wheel.0,
+            self.pending_wheel.1
+        )?;
+        writeln!(f, "mouse buttons: {:#04x}", self.mouse_buttons)?;
+        writeln!(
+            f,
+            "modifiers: {:#04x} synthetic {:#04x}",
+            self.modifiers, self.synthetic_modifiers
+        )?;
+        writeln!(f, "keys: {:02x?}", self.keys)?;
+        writeln!(f, "consumer: {:04x?}", self.consumer)?;
+        writeln!(f, "system: {:02x?}", self.system)?;
+        write!(f, "buttons:")?;
+        for (button, key) in &self.buttons {
+            write!(f, " {button}={key:#06x}")?;
+        }
+        writeln!(f)?;
+        write!(f, "macro running: {}", self.macro_running)
+    }
+}
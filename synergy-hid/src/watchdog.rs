@@ -0,0 +1,114 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// How long a key can be held without a repeat before it's released, see
+/// [`SynergyHid::with_stuck_key_timeouts`](crate::SynergyHid::with_stuck_key_timeouts)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StuckKeyTimeouts {
+    /// For the modifier keys, `None` never releases them
+    pub modifier: Option<Duration>,
+    /// For the other keys, `None` never releases them
+    pub key: Option<Duration>,
+}
+
+impl StuckKeyTimeouts {
+    pub fn is_enabled(&self) -> bool {
+        self.modifier.is_some() || self.key.is_some()
+    }
+}
+
+/// Time of the last press or repeat of each server button held. The key events carry no
+/// time, a key is stamped on the first tick after it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KeyWatchdog {
+    // `None` until the next tick
+    held: Vec<(u16, Option<Duration>)>,
+    // Buttons released by the watchdog, their key up is expected later
+    released: Vec<u16>,
+}
+
+impl KeyWatchdog {
+    /// The key held on `button` was pressed or repeated
+    pub fn touch(&mut self, button: u16) {
+        self.released.retain(|&b| b != button);
+        match self.held.iter_mut().find(|(b, _)| *b == button) {
+            Some((_, since)) => *since = None,
+            None => self.held.push((button, None)),
+        }
+    }
+
+    /// The key held on `button` was released
+    pub fn forget(&mut self, button: u16) {
+        self.held.retain(|&(b, _)| b != button);
+    }
+
+    /// Returns `true` once if the watchdog released the key of `button`
+    pub fn take_released(&mut self, button: u16) -> bool {
+        let released = self.released.len();
+        self.released.retain(|&b| b != button);
+        self.released.len() != released
+    }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.released.clear();
+    }
+
+    /// Stamps the keys touched since the last tick with `now` and returns the buttons held
+    /// for longer than `timeout` of their button, they're forgotten
+    pub fn expired(
+        &mut self,
+        now: Duration,
+        timeout: impl Fn(u16) -> Option<Duration>,
+    ) -> Vec<u16> {
+        let mut expired = Vec::new();
+        self.held.retain_mut(|(button, since)| {
+            let since = *since.get_or_insert(now);
+            match timeout(*button) {
+                Some(timeout) if now.saturating_sub(since) >= timeout => {
+                    expired.push(*button);
+                    false
+                }
+                _ => true,
+            }
+        });
+        self.released.extend_from_slice(&expired);
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+
+    use super::KeyWatchdog;
+
+    #[test]
+    fn test_key_watchdog() {
+        let secs = Duration::from_secs;
+        let mut watchdog = KeyWatchdog::default();
+        watchdog.touch(1);
+        watchdog.touch(2);
+        // Stamped on the first tick
+        assert_eq!(watchdog.expired(secs(100), |_| Some(secs(10))), []);
+        assert_eq!(watchdog.expired(secs(109), |_| Some(secs(10))), []);
+        // A repeat starts over
+        watchdog.touch(2);
+        assert_eq!(watchdog.expired(secs(112), |_| Some(secs(10))), [1]);
+        assert_eq!(watchdog.expired(secs(121), |_| Some(secs(10))), []);
+        // Button 2 has no timeout
+        assert_eq!(
+            watchdog.expired(secs(200), |b| (b != 2).then_some(secs(10))),
+            []
+        );
+        assert_eq!(watchdog.expired(secs(200), |_| Some(secs(10))), [2]);
+
+        assert!(watchdog.take_released(1));
+        assert!(!watchdog.take_released(1));
+        // Pressed again, the key up isn't expected any more
+        watchdog.touch(2);
+        assert!(!watchdog.take_released(2));
+        watchdog.forget(2);
+        assert_eq!(watchdog.expired(secs(300), |_| Some(secs(10))), []);
+    }
+}
@@ -0,0 +1,162 @@
+#![cfg(feature = "ffi")]
+
+use synergy_hid::ffi::*;
+use synergy_hid::{KeyMask, KeyboardMode, ReportType, SynergyHid, MAX_REPORT_LEN};
+
+enum Call {
+    KeyDown(u16, u16, u16),
+    KeyUp(u16, u16, u16),
+    MouseDown(i8),
+    MouseUp(i8),
+    MouseScroll(i16, i16),
+    SetCursorPosition(u16, u16),
+    MoveCursor(i16, i16),
+    Clear(ReportType),
+}
+
+fn native(hid: &mut SynergyHid, call: &Call) -> (u8, Vec<u8>) {
+    let report = &mut [0; MAX_REPORT_LEN];
+    let (report_type, report) = match *call {
+        Call::KeyDown(key, mask, button) => {
+            hid.key_down(key, KeyMask::from_bits_retain(mask), button, report)
+        }
+        Call::KeyUp(key, mask, button) => {
+            hid.key_up(key, KeyMask::from_bits_retain(mask), button, report)
+        }
+        Call::MouseDown(button) => hid.mouse_down(button, report),
+        Call::MouseUp(button) => hid.mouse_up(button, report),
+        Call::MouseScroll(x, y) => hid.mouse_scroll(x, y, report),
+        Call::SetCursorPosition(x, y) => hid.set_cursor_position(x, y, report),
+        Call::MoveCursor(x, y) => hid.move_cursor(x, y, report),
+        Call::Clear(report_type) => hid.clear(report_type, report),
+    };
+    (report_type as u8, report.to_vec())
+}
+
+unsafe fn ffi(hid: *mut SynergyHid, call: &Call) -> (u8, Vec<u8>) {
+    let mut report = [0; SYNERGY_HID_MAX_REPORT_LEN];
+    let (ptr, cap) = (report.as_mut_ptr(), report.len());
+    let (mut report_type, mut len) = (0u8, 0usize);
+    let (t, l) = (&mut report_type as *mut u8, &mut len as *mut usize);
+    let status = match *call {
+        Call::KeyDown(key, mask, button) => {
+            synergy_hid_key_down(hid, key, mask, button, ptr, cap, t, l)
+        }
+        Call::KeyUp(key, mask, button) => {
+            synergy_hid_key_up(hid, key, mask, button, ptr, cap, t, l)
+        }
+        Call::MouseDown(button) => synergy_hid_mouse_down(hid, button, ptr, cap, t, l),
+        Call::MouseUp(button) => synergy_hid_mouse_up(hid, button, ptr, cap, t, l),
+        Call::MouseScroll(x, y) => synergy_hid_mouse_scroll(hid, x, y, ptr, cap, t, l),
+        Call::SetCursorPosition(x, y) => synergy_hid_set_cursor_position(hid, x, y, ptr, cap, t, l),
+        Call::MoveCursor(x, y) => synergy_hid_move_cursor(hid, x, y, ptr, cap, t, l),
+        Call::Clear(report_type) => synergy_hid_clear(hid, report_type as u8, ptr, cap, t, l),
+    };
+    assert_eq!(status, SynergyHidStatus::Ok);
+    (report_type, report[..len].to_vec())
+}
+
+#[test]
+fn test_parity() {
+    let calls = [
+        Call::SetCursorPosition(960, 540),
+        Call::MoveCursor(-2000, 30),
+        Call::MouseDown(1),
+        Call::MouseScroll(0, 240),
+        Call::MouseUp(1),
+        // Shift + 'a'
+        Call::KeyDown(0xefe1, 0, 0x32),
+        Call::KeyDown(0x41, 1, 0x26),
+        Call::KeyUp(0x41, 1, 0x26),
+        Call::KeyUp(0xefe1, 0, 0x32),
+        // Volume up, on the consumer report
+        Call::KeyDown(0xe0ad, 0, 0),
+        Call::KeyUp(0xe0ad, 0, 0),
+        // No usage, nothing to write
+        Call::KeyDown(0xffff, 0, 0x99),
+        Call::KeyDown(0x62, 0, 0x38),
+        Call::Clear(ReportType::Keyboard),
+        Call::Clear(ReportType::Mouse),
+    ];
+    let mut hid = SynergyHid::new(1920, 1080, true);
+    let ffi_hid = synergy_hid_new(1920, 1080, true);
+    assert!(!ffi_hid.is_null());
+    for call in &calls {
+        assert_eq!(unsafe { ffi(ffi_hid, call) }, native(&mut hid, call));
+    }
+    unsafe { synergy_hid_free(ffi_hid) };
+}
+
+#[test]
+fn test_report_descriptor() {
+    for report_type in [
+        ReportType::Keyboard,
+        ReportType::Mouse,
+        ReportType::Consumer,
+        ReportType::RelativeMouse,
+    ] {
+        let mut len = 0;
+        let descriptor = unsafe { synergy_hid_report_descriptor(report_type as u8, &mut len) };
+        let (_, native) = SynergyHid::get_report_descriptor(report_type, KeyboardMode::Boot);
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(descriptor, len) },
+            native
+        );
+    }
+    let mut len = 0;
+    assert!(unsafe { synergy_hid_report_descriptor(0, &mut len) }.is_null());
+}
+
+#[test]
+fn test_errors() {
+    assert!(synergy_hid_new(0, 1080, false).is_null());
+
+    let hid = synergy_hid_new(1920, 1080, false);
+    let mut report = [0; SYNERGY_HID_MAX_REPORT_LEN];
+    let (mut report_type, mut len) = (0, 0);
+    unsafe {
+        assert_eq!(
+            synergy_hid_mouse_down(hid, 1, report.as_mut_ptr(), 4, &mut report_type, &mut len),
+            SynergyHidStatus::BufferTooSmall
+        );
+        assert_eq!(
+            synergy_hid_mouse_down(
+                std::ptr::null_mut(),
+                1,
+                report.as_mut_ptr(),
+                report.len(),
+                &mut report_type,
+                &mut len
+            ),
+            SynergyHidStatus::NullPointer
+        );
+        assert_eq!(
+            synergy_hid_clear(
+                hid,
+                9,
+                report.as_mut_ptr(),
+                report.len(),
+                &mut report_type,
+                &mut len
+            ),
+            SynergyHidStatus::InvalidReportType
+        );
+        // Any button id can hold a key
+        assert_eq!(
+            synergy_hid_key_down(
+                hid,
+                0x61,
+                0,
+                u16::MAX,
+                report.as_mut_ptr(),
+                report.len(),
+                &mut report_type,
+                &mut len
+            ),
+            SynergyHidStatus::Ok
+        );
+        assert_eq!((report_type, len), (1, 8));
+        synergy_hid_free(hid);
+        synergy_hid_free(std::ptr::null_mut());
+    }
+}
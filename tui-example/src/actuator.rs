@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+
+use barrier_client::{Actuator, ActuatorError, ClipboardData, KeyMask, ServerOptions};
+use log::info;
+use synergy_hid::{HidState, SynergyHid};
+use tokio::sync::Notify;
+
+use crate::model::Model;
+
+/// Applies the events to a [`SynergyHid`] like a device would, the reports are dropped and
+/// the state after each event goes to the model
+pub struct TuiActuator {
+    hid: SynergyHid,
+    model: Arc<Mutex<Model>>,
+    changed: Arc<Notify>,
+}
+
+impl TuiActuator {
+    pub fn new(hid: SynergyHid, model: Arc<Mutex<Model>>, changed: Arc<Notify>) -> Self {
+        Self {
+            hid,
+            model,
+            changed,
+        }
+    }
+
+    // Passes the state of the HID after an event to `update`, then redraws
+    fn update<F: FnOnce(&mut Model, HidState)>(&mut self, update: F) -> Result<(), ActuatorError> {
+        let state = self.hid.state();
+        update(&mut self.model.lock().unwrap(), state);
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    fn applied(&mut self) -> Result<(), ActuatorError> {
+        self.update(Model::applied)
+    }
+}
+
+impl Actuator for TuiActuator {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        info!("Connected");
+        Ok(())
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        info!("Disconnected");
+        self.hid.clear_all();
+        self.update(|model, state| model.hid = state)
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        self.hid.screen_size()
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        self.hid.cursor_position()
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.hid.set_cursor_position_report(x, y);
+        self.applied()
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.hid.move_cursor_report(x, y);
+        self.applied()
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.hid.mouse_down_report(button);
+        self.applied()
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.hid.mouse_up_report(button);
+        self.applied()
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.hid.mouse_scroll_report(x, y);
+        self.applied()
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.hid.key_down_report(key, mask, button);
+        self.applied()
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        let report = &mut [0; 9];
+        self.hid
+            .key_repeat(key, mask, button, count, report, |_| {});
+        self.applied()
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.hid.key_up_report(key, mask, button);
+        self.applied()
+    }
+
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        info!("Set options {:?}", opts);
+        Ok(())
+    }
+
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        info!("Reset options");
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        self.update(Model::entered)
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        self.hid.clear_all();
+        self.update(Model::left)
+    }
+
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        let text = data.text();
+        self.model.lock().unwrap().clipboard = text;
+        self.changed.notify_one();
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::{Arc, Mutex};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use tokio::sync::Notify;
+
+use crate::model::Model;
+
+/// Writes the log to the scrollback of the model, the terminal is taken by the UI. The
+/// client is logged down to the trace level to show the packets, the rest from `level`.
+pub struct ScrollbackLogger {
+    model: Arc<Mutex<Model>>,
+    changed: Arc<Notify>,
+    level: Level,
+}
+
+impl ScrollbackLogger {
+    pub fn init(model: Arc<Mutex<Model>>, changed: Arc<Notify>, level: Level) {
+        let logger = Self {
+            model,
+            changed,
+            level,
+        };
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(LevelFilter::Trace);
+        }
+    }
+}
+
+impl Log for ScrollbackLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || metadata.target().starts_with("barrier_client")
+    }
+
+    // Never called with the model locked, nothing logs while it's held
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{:<5} {}", record.level(), record.args());
+        self.model.lock().unwrap().log(line);
+        self.changed.notify_one();
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,143 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use barrier_client::{Client, ConnectionState};
+use clap::Parser;
+use log::{info, Level};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    DefaultTerminal,
+};
+use synergy_hid::SynergyHid;
+use tokio::{
+    sync::{watch, Notify},
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+
+mod actuator;
+mod logger;
+mod model;
+mod ui;
+
+use actuator::TuiActuator;
+use logger::ScrollbackLogger;
+use model::Model;
+
+/// Redrawn at least this often, e.g. after the terminal is resized
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connects to the Barrier server as a screen without any hardware, and draws the cursor,
+/// the keys held and the packets received in the terminal
+#[derive(Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Barrier server address in "server:port" format
+    #[arg(short = 's', long, env = "BARRIER_SERVER")]
+    server: String,
+    /// Screen name, must be accepted by the Barrier server
+    #[arg(short = 'n', long, env = "SCREEN_NAME")]
+    screen_name: String,
+    /// Screen width reported to the server
+    #[arg(short = 'w', long, default_value = "1920", env = "SCREEN_WIDTH")]
+    screen_width: u16,
+    /// Screen height reported to the server
+    #[arg(short = 'e', long, default_value = "1080", env = "SCREEN_HEIGHT")]
+    screen_height: u16,
+    /// Level of the log shown under the screen, the packets are always shown
+    #[arg(long, default_value = "info", env = "LOG_LEVEL")]
+    log_level: Level,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let model = Arc::new(Mutex::new(Model::new((
+        args.screen_width,
+        args.screen_height,
+    ))));
+    let changed = Arc::new(Notify::new());
+    ScrollbackLogger::init(model.clone(), changed.clone(), args.log_level);
+
+    let client = Client::builder()
+        .server(&args.server)
+        .screen_name(&args.screen_name)
+        .reconnect(true)
+        .build()?;
+    let mut actuator = TuiActuator::new(
+        SynergyHid::new(args.screen_width, args.screen_height, false),
+        model.clone(),
+        changed.clone(),
+    );
+
+    let token = CancellationToken::new();
+    read_keys(token.clone());
+    let cloned_token = token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Recieve SIGINT, shutting down...");
+            cloned_token.cancel();
+        }
+    });
+
+    let mut terminal = ratatui::try_init()?;
+    let result = tokio::select! {
+        _ = token.cancelled() => Ok(()),
+        result = client.run(&mut actuator) => result.map_err(anyhow::Error::from),
+        result = render(&mut terminal, &model, &changed, client.state()) => result,
+    };
+    ratatui::restore();
+    result
+}
+
+// Redraws whenever the model or the connection state changes
+async fn render(
+    terminal: &mut DefaultTerminal,
+    model: &Mutex<Model>,
+    changed: &Notify,
+    mut state: watch::Receiver<ConnectionState>,
+) -> anyhow::Result<()> {
+    loop {
+        terminal.draw(|frame| ui::draw(frame, &model.lock().unwrap()))?;
+        tokio::select! {
+            _ = changed.notified() => {}
+            Ok(()) = state.changed() => {
+                let connection = state.borrow_and_update().clone();
+                model.lock().unwrap().set_connection(connection);
+            }
+            _ = sleep(REDRAW_INTERVAL) => {}
+        }
+    }
+}
+
+// The terminal is in raw mode, Ctrl-C comes as a key and not as SIGINT. Crossterm reads
+// the keys with blocking calls, on a thread of its own.
+fn read_keys(token: CancellationToken) {
+    thread::spawn(move || {
+        while !token.is_cancelled() {
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+            if let Ok(Event::Key(key)) = event::read() {
+                if is_quit(&key) {
+                    token.cancel();
+                }
+            }
+        }
+    });
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press
+        && match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+use barrier_client::ConnectionState;
+use ratatui::layout::{Position, Rect};
+use synergy_hid::HidState;
+
+/// Lines kept in the scrollback of the packets
+pub const SCROLLBACK: usize = 500;
+
+// Modifier bits of the keyboard report, from bit 0
+const MODIFIERS: [&str; 8] = [
+    "LCtrl", "LShift", "LAlt", "LGui", "RCtrl", "RShift", "RAlt", "RGui",
+];
+
+// Mouse buttons, from bit 0
+const BUTTONS: [&str; 5] = ["left", "right", "middle", "back", "forward"];
+
+/// What the UI shows, updated by the actuator, the connection state and the log, and drawn
+/// as it is by [`crate::ui::draw`]
+#[derive(Debug, Default)]
+pub struct Model {
+    /// Size the actuator reports to the server
+    pub screen_size: (u16, u16),
+    /// What the `SynergyHid` holds after the last event
+    pub hid: HidState,
+    /// The cursor is on this screen
+    pub entered: bool,
+    pub connection: ConnectionState,
+    /// Text of the last clipboard received, `None` if it had none
+    pub clipboard: Option<String>,
+    /// Events applied since the start
+    pub events: u64,
+    /// Log lines, the packets at the trace level, the last one is the most recent
+    pub scrollback: VecDeque<String>,
+}
+
+impl Model {
+    pub fn new(screen_size: (u16, u16)) -> Self {
+        Self {
+            screen_size,
+            ..Default::default()
+        }
+    }
+
+    /// An event has been applied, `hid` is the state after it
+    pub fn applied(&mut self, hid: HidState) {
+        self.hid = hid;
+        self.events += 1;
+    }
+
+    pub fn entered(&mut self, hid: HidState) {
+        self.entered = true;
+        self.applied(hid);
+    }
+
+    /// The keys are released as the cursor leaves
+    pub fn left(&mut self, hid: HidState) {
+        self.entered = false;
+        self.applied(hid);
+    }
+
+    pub fn set_connection(&mut self, connection: ConnectionState) {
+        // Nothing is held on a screen the server isn't connected to
+        if let ConnectionState::Disconnected { .. } = connection {
+            self.entered = false;
+        }
+        self.connection = connection;
+    }
+
+    pub fn log(&mut self, line: String) {
+        if self.scrollback.len() == SCROLLBACK {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(line);
+    }
+
+    /// Names of the modifiers held, then the HID usages of the other keys
+    pub fn held_keys(&self) -> Vec<String> {
+        let modifiers = MODIFIERS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.hid.modifiers & (1 << bit) != 0)
+            .map(|(_, name)| name.to_string());
+        let keys = self.hid.keys.iter().map(|key| format!("{key:#04x}"));
+        modifiers.chain(keys).collect()
+    }
+
+    pub fn held_buttons(&self) -> Vec<&'static str> {
+        BUTTONS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.hid.mouse_buttons & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// Line of the status bar
+    pub fn status(&self) -> String {
+        let connection = match &self.connection {
+            ConnectionState::Disconnected {
+                reason: Some(reason),
+            } => format!("Disconnected: {reason}"),
+            state => format!("{state:?}"),
+        };
+        format!(
+            "{connection} | {}x{} | {} events | q to quit",
+            self.screen_size.0, self.screen_size.1, self.events
+        )
+    }
+}
+
+/// Cell standing for the cursor at `cursor` on a screen of `screen_size` drawn in `area`,
+/// `None` if the area is empty
+pub fn cursor_cell(cursor: (u16, u16), screen_size: (u16, u16), area: Rect) -> Option<Position> {
+    if area.is_empty() {
+        return None;
+    }
+    let scale = |at: u16, size: u16, cells: u16| {
+        let cell = at as u32 * cells as u32 / size.max(1) as u32;
+        cell.min(cells as u32 - 1) as u16
+    };
+    Some(Position::new(
+        area.x + scale(cursor.0, screen_size.0, area.width),
+        area.y + scale(cursor.1, screen_size.1, area.height),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use barrier_client::ConnectionState;
+    use ratatui::layout::{Position, Rect};
+    use synergy_hid::HidState;
+
+    use super::{cursor_cell, Model, SCROLLBACK};
+
+    #[test]
+    fn test_cursor_cell() {
+        let area = Rect::new(1, 1, 80, 20);
+        let cell = |cursor| cursor_cell(cursor, (1920, 1080), area);
+        assert_eq!(cell((0, 0)), Some(Position::new(1, 1)));
+        assert_eq!(cell((960, 540)), Some(Position::new(41, 11)));
+        assert_eq!(cell((1919, 1079)), Some(Position::new(80, 20)));
+        // Past the screen, e.g. the size changed, still in the box
+        assert_eq!(cell((4000, 4000)), Some(Position::new(80, 20)));
+        assert_eq!(cursor_cell((0, 0), (0, 0), area), Some(Position::new(1, 1)));
+        assert_eq!(
+            cursor_cell((0, 0), (1920, 1080), Rect::new(1, 1, 0, 5)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_held() {
+        let mut model = Model::new((1920, 1080));
+        model.entered(HidState {
+            modifiers: 0x22,
+            keys: vec![0x04, 0x1e],
+            mouse_buttons: 0x05,
+            ..Default::default()
+        });
+        assert!(model.entered);
+        assert_eq!(model.held_keys(), ["LShift", "RShift", "0x04", "0x1e"]);
+        assert_eq!(model.held_buttons(), ["left", "middle"]);
+
+        model.left(HidState::default());
+        assert!(!model.entered);
+        assert!(model.held_keys().is_empty());
+        assert!(model.held_buttons().is_empty());
+        assert_eq!(model.events, 2);
+    }
+
+    #[test]
+    fn test_status() {
+        let mut model = Model::new((1920, 1080));
+        assert_eq!(
+            model.status(),
+            "Disconnected { reason: None } | 1920x1080 | 0 events | q to quit"
+        );
+        model.set_connection(ConnectionState::Entered);
+        model.entered(HidState::default());
+        assert_eq!(model.status(), "Entered | 1920x1080 | 1 events | q to quit");
+
+        model.set_connection(ConnectionState::Disconnected {
+            reason: Some("Disconnected".to_string()),
+        });
+        assert!(!model.entered);
+        assert_eq!(
+            model.status(),
+            "Disconnected: Disconnected | 1920x1080 | 1 events | q to quit"
+        );
+    }
+
+    #[test]
+    fn test_scrollback() {
+        let mut model = Model::default();
+        for n in 0..SCROLLBACK + 2 {
+            model.log(n.to_string());
+        }
+        assert_eq!(model.scrollback.len(), SCROLLBACK);
+        assert_eq!(model.scrollback.front().unwrap(), "2");
+        assert_eq!(
+            model.scrollback.back().unwrap(),
+            &(SCROLLBACK + 1).to_string()
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::model::{cursor_cell, Model};
+
+const CURSOR: &str = "+";
+
+/// Draws the screen with the cursor, what is held, the packets and the status bar
+pub fn draw(frame: &mut Frame, model: &Model) {
+    let [main, status] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [left, side] =
+        Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(main);
+    let [screen, packets] =
+        Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+
+    let (width, height) = model.screen_size;
+    let block = Block::bordered().title(format!(" Screen {width}x{height} "));
+    let inside = block.inner(screen);
+    frame.render_widget(block, screen);
+    if model.entered {
+        if let Some(cell) = cursor_cell(model.hid.cursor, model.screen_size, inside)
+            .and_then(|at| frame.buffer_mut().cell_mut(at))
+        {
+            cell.set_symbol(CURSOR)
+                .set_style(Style::new().yellow().bold());
+        }
+    }
+
+    let (x, y) = model.hid.cursor;
+    let lines = vec![
+        Line::from(format!("Cursor: {x}, {y}")),
+        Line::from(format!("Keys: {}", model.held_keys().join(" "))),
+        Line::from(format!("Buttons: {}", model.held_buttons().join(" "))),
+        Line::from(""),
+        Line::from("Clipboard:".bold()),
+        Line::from(model.clipboard.as_deref().unwrap_or("<none>").to_string()),
+    ];
+    let held = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::bordered().title(" Held "));
+    frame.render_widget(held, side);
+
+    // The most recent lines that fit
+    let block = Block::bordered().title(" Packets ");
+    let rows = block.inner(packets).height as usize;
+    let skip = model.scrollback.len().saturating_sub(rows);
+    let lines: Vec<Line> = model
+        .scrollback
+        .iter()
+        .skip(skip)
+        .map(|line| Line::from(line.as_str()))
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(block), packets);
+
+    frame.render_widget(Paragraph::new(model.status()).reversed(), status);
+}
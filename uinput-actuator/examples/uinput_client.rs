@@ -0,0 +1,22 @@
+use barrier_client::start;
+use env_logger::Env;
+use synergy_hid::SynergyHid;
+use uinput_actuator::UinputActuator;
+
+/// Usage: uinput_client [server:port] [screen name] [width] [height]
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+    let mut args = std::env::args().skip(1);
+    let server = args.next().unwrap_or(String::from("192.168.2.59:24800"));
+    let screen_name = args.next().unwrap_or(String::from("BARPI"));
+    let width = args.next().map(|w| w.parse().unwrap()).unwrap_or(1920);
+    let height = args.next().map(|h| h.parse().unwrap()).unwrap_or(1080);
+
+    let mut actuator = UinputActuator::new(
+        SynergyHid::new(width, height, false),
+        "Barrier virtual input",
+    )
+    .unwrap();
+    start(server, screen_name, &mut actuator).await.unwrap();
+}
@@ -0,0 +1,175 @@
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io,
+    mem::size_of,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use crate::keycodes::MOUSE_BUTTONS;
+
+// Event types and codes of linux/input-event-codes.h
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const SYN_REPORT: u16 = 0x00;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+const BUS_VIRTUAL: u16 = 0x06;
+
+// Requests of linux/uinput.h, the generic encoding of the ioctl numbers
+const fn ioc(write: bool, nr: u32, size: usize) -> u64 {
+    ((write as u64) << 30) | ((size as u64) << 16) | ((b'U' as u64) << 8) | nr as u64
+}
+const UI_DEV_CREATE: u64 = ioc(false, 1, 0);
+const UI_DEV_DESTROY: u64 = ioc(false, 2, 0);
+const UI_DEV_SETUP: u64 = ioc(true, 3, size_of::<libc::uinput_setup>());
+const UI_ABS_SETUP: u64 = ioc(true, 4, size_of::<libc::uinput_abs_setup>());
+const UI_SET_EVBIT: u64 = ioc(true, 100, size_of::<libc::c_int>());
+const UI_SET_KEYBIT: u64 = ioc(true, 101, size_of::<libc::c_int>());
+const UI_SET_RELBIT: u64 = ioc(true, 102, size_of::<libc::c_int>());
+const UI_SET_ABSBIT: u64 = ioc(true, 103, size_of::<libc::c_int>());
+
+/// One `struct input_event`, the kernel sets the time of the events written to uinput
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl InputEvent {
+    pub fn new(type_: u16, code: u16, value: i32) -> Self {
+        Self { type_, code, value }
+    }
+
+    pub fn sync() -> Self {
+        Self::new(EV_SYN, SYN_REPORT, 0)
+    }
+
+    /// The bytes written to the device
+    pub fn to_bytes(self) -> Vec<u8> {
+        // SAFETY: input_event is plain data, all zeroes is a valid value
+        let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+        event.type_ = self.type_;
+        event.code = self.code;
+        event.value = self.value;
+        // SAFETY: the struct is initialized and has no padding besides the zeroed time
+        unsafe {
+            std::slice::from_raw_parts(
+                &event as *const _ as *const u8,
+                size_of::<libc::input_event>(),
+            )
+        }
+        .to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != size_of::<libc::input_event>() {
+            return None;
+        }
+        // SAFETY: the length is checked, the read doesn't need to be aligned
+        let event: libc::input_event =
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const _) };
+        Some(Self::new(event.type_, event.code, event.value))
+    }
+}
+
+// The ioctls of this file take an int or a pointer
+fn ioctl<T>(file: &File, request: u64, arg: T) -> io::Result<()> {
+    // SAFETY: the requests are the uinput ones with the argument type they expect
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A virtual keyboard and absolute pointer, destroyed when dropped
+pub struct UinputDevice {
+    file: File,
+}
+
+impl UinputDevice {
+    /// Creates a device with the keys of `keys`, the mouse buttons, the wheels and a pointer
+    /// spanning a `width` x `height` screen
+    pub fn create(name: &CStr, keys: &[u16], width: u16, height: u16) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")?;
+
+        ioctl(&file, UI_SET_EVBIT, EV_KEY as libc::c_int)?;
+        for key in keys.iter().chain(&MOUSE_BUTTONS) {
+            ioctl(&file, UI_SET_KEYBIT, *key as libc::c_int)?;
+        }
+        ioctl(&file, UI_SET_EVBIT, EV_REL as libc::c_int)?;
+        for rel in [REL_WHEEL, REL_HWHEEL] {
+            ioctl(&file, UI_SET_RELBIT, rel as libc::c_int)?;
+        }
+        ioctl(&file, UI_SET_EVBIT, EV_ABS as libc::c_int)?;
+        for (abs, size) in [(ABS_X, width), (ABS_Y, height)] {
+            ioctl(&file, UI_SET_ABSBIT, abs as libc::c_int)?;
+            // SAFETY: plain data
+            let mut setup: libc::uinput_abs_setup = unsafe { std::mem::zeroed() };
+            setup.code = abs;
+            setup.absinfo.maximum = size.max(1) as i32 - 1;
+            ioctl(&file, UI_ABS_SETUP, &setup as *const libc::uinput_abs_setup)?;
+        }
+
+        // SAFETY: plain data
+        let mut setup: libc::uinput_setup = unsafe { std::mem::zeroed() };
+        setup.id.bustype = BUS_VIRTUAL;
+        for (dst, src) in setup
+            .name
+            .iter_mut()
+            .zip(name.to_bytes().iter().take(libc::UINPUT_MAX_NAME_SIZE - 1))
+        {
+            *dst = *src as libc::c_char;
+        }
+        ioctl(&file, UI_DEV_SETUP, &setup as *const libc::uinput_setup)?;
+        ioctl(&file, UI_DEV_CREATE, 0 as libc::c_int)?;
+        Ok(Self { file })
+    }
+}
+
+impl io::Write for UinputDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut self.file, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        if let Err(e) = ioctl(&self.file, UI_DEV_DESTROY, 0 as libc::c_int) {
+            log::warn!("Cannot destroy the uinput device: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{InputEvent, EV_KEY, UI_DEV_CREATE, UI_SET_EVBIT};
+
+    #[test]
+    fn test_event_bytes() {
+        let event = InputEvent::new(EV_KEY, 30, 1);
+        let bytes = event.to_bytes();
+        assert_eq!(bytes.len(), std::mem::size_of::<libc::input_event>());
+        assert_eq!(InputEvent::from_bytes(&bytes), Some(event));
+        assert_eq!(InputEvent::from_bytes(&bytes[1..]), None);
+    }
+
+    #[test]
+    fn test_ioctl_numbers() {
+        // From linux/uinput.h on x86_64 and arm
+        assert_eq!(UI_DEV_CREATE, 0x5501);
+        assert_eq!(UI_SET_EVBIT, 0x4004_5564);
+    }
+}
@@ -0,0 +1,168 @@
+// Mouse buttons of linux/input-event-codes.h
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+pub const BTN_SIDE: u16 = 0x113;
+pub const BTN_EXTRA: u16 = 0x114;
+pub const BTN_FORWARD: u16 = 0x115;
+pub const BTN_BACK: u16 = 0x116;
+pub const BTN_TASK: u16 = 0x117;
+
+/// evdev button of each bit of the HID mouse buttons, left, right, middle, backward, forward
+/// and the three extra ones, as mapped by the kernel's hid-input
+pub const MOUSE_BUTTONS: [u16; 8] = [
+    BTN_LEFT,
+    BTN_RIGHT,
+    BTN_MIDDLE,
+    BTN_SIDE,
+    BTN_EXTRA,
+    BTN_FORWARD,
+    BTN_BACK,
+    BTN_TASK,
+];
+
+/// evdev KEY_* codes of the HID keyboard usages, 0 if there is none. Same table as the
+/// kernel's hid-input, so the keys come out as if the gadget was plugged in.
+#[rustfmt::skip]
+const KEYBOARD: [u8; 256] = [
+      0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+     50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+      4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+     27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+     65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+    105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+     72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+    191, 192, 193, 194, 134, 138, 130, 132, 128, 129, 131, 137, 133, 135, 136, 113,
+    115, 114,   0,   0,   0, 121,   0,  89,  93, 124,  92,  94,  95,   0,   0,   0,
+    122, 123,  90,  91,  85,   0,   0,   0,   0,   0,   0,   0, 111,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0, 179, 180,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0, 111,   0,   0,   0,   0,   0,   0,   0,
+     29,  42,  56, 125,  97,  54, 100, 126,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+/// evdev KEY_* code of a HID keyboard usage, modifiers included
+pub fn hid_to_evdev(usage: u8) -> Option<u16> {
+    match KEYBOARD[usage as usize] {
+        0 => None,
+        key => Some(key as u16),
+    }
+}
+
+/// evdev KEY_* code of the consumer control usages synergy-hid sends
+pub fn consumer_to_evdev(usage: u16) -> Option<u16> {
+    match usage {
+        0x0032 => Some(142), // Sleep
+        0x006F => Some(225), // Brightness Up
+        0x0070 => Some(224), // Brightness Down
+        0x00B5 => Some(163), // Next Track
+        0x00B6 => Some(165), // Previous Track
+        0x00B7 => Some(166), // Stop
+        0x00B8 => Some(161), // Eject
+        0x00CD => Some(164), // Play/Pause
+        0x00E2 => Some(113), // Mute
+        0x00E9 => Some(115), // Volume Up
+        0x00EA => Some(114), // Volume Down
+        0x0183 => Some(171), // AL Consumer Control Configuration
+        0x018A => Some(155), // Mail
+        0x0192 => Some(140), // AL Calculator
+        0x0194 => Some(144), // AL Local Machine Browser
+        0x0221 => Some(217), // AC Search
+        0x0223 => Some(172), // AC Home
+        0x0224 => Some(158), // AC Back
+        0x0225 => Some(159), // AC Forward
+        0x0226 => Some(128), // AC Stop
+        0x0227 => Some(173), // AC Refresh
+        0x022A => Some(156), // AC Bookmarks
+        0x029F => Some(120), // AC Desktop Show All Windows
+        0x02A2 => Some(204), // AC Desktop Show All Applications
+        _ => None,
+    }
+}
+
+/// evdev KEY_* code of the Generic Desktop System Control usages
+pub fn system_to_evdev(usage: u8) -> Option<u16> {
+    match usage {
+        0x81 => Some(116), // System Power Down
+        0x82 => Some(142), // System Sleep
+        0x83 => Some(143), // System Wake Up
+        _ => None,
+    }
+}
+
+/// Every key the device may send, registered when it's created
+pub fn all_keys() -> Vec<u16> {
+    let mut keys: Vec<u16> = (0..=u8::MAX)
+        .filter_map(hid_to_evdev)
+        .chain((0..=0x0FFF).filter_map(consumer_to_evdev))
+        .chain((0..=u8::MAX).filter_map(system_to_evdev))
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys
+}
+
+#[cfg(test)]
+mod test {
+    use synergy_hid::{KeyMask, SynergyHid};
+
+    use super::{all_keys, consumer_to_evdev, hid_to_evdev, system_to_evdev};
+
+    #[test]
+    fn test_keyboard_table() {
+        // KEY_A, KEY_1, KEY_ENTER, KEY_F1, KEY_F12, KEY_UP, KEY_KP0, KEY_102ND, KEY_F24
+        for (usage, key) in [
+            (0x04, 30),
+            (0x1E, 2),
+            (0x28, 28),
+            (0x3A, 59),
+            (0x45, 88),
+            (0x52, 103),
+            (0x62, 82),
+            (0x64, 86),
+            (0x73, 194),
+        ] {
+            assert_eq!(hid_to_evdev(usage), Some(key), "usage {usage:#04x}");
+        }
+        // KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_LEFTALT, KEY_LEFTMETA, then the right ones
+        assert_eq!(
+            (0xE0..=0xE7).map(hid_to_evdev).collect::<Vec<_>>(),
+            [29, 42, 56, 125, 97, 54, 100, 126].map(Some)
+        );
+        // Reserved and error usages
+        assert_eq!(hid_to_evdev(0x00), None);
+        assert_eq!(hid_to_evdev(0x01), None);
+        assert_eq!(hid_to_evdev(0xE8), None);
+    }
+
+    #[test]
+    fn test_consumer_table() {
+        assert_eq!(consumer_to_evdev(0xE9), Some(115));
+        assert_eq!(consumer_to_evdev(0xCD), Some(164));
+        assert_eq!(consumer_to_evdev(0x1234), None);
+        assert!(all_keys().windows(2).all(|keys| keys[0] < keys[1]));
+    }
+
+    #[test]
+    fn test_synergy_keys() {
+        // Every printable character and media key synergy-hid maps has an evdev key
+        let mut hid = SynergyHid::new(1920, 1080, false);
+        let mut report = [0; SynergyHid::MAX_REPORT_LEN];
+        for key in (0x20..0x7F).chain(0xE000..=0xE0FF) {
+            hid.key_down(key, KeyMask::empty(), 1, &mut report);
+            let state = hid.state();
+            for usage in &state.keys {
+                assert!(hid_to_evdev(*usage).is_some(), "key {key:#06x}");
+            }
+            for usage in &state.consumer {
+                assert!(consumer_to_evdev(*usage).is_some(), "key {key:#06x}");
+            }
+            for usage in &state.system {
+                assert!(system_to_evdev(*usage).is_some(), "key {key:#06x}");
+            }
+            hid.key_up(key, KeyMask::empty(), 1, &mut report);
+        }
+    }
+}
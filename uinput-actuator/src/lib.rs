@@ -0,0 +1,404 @@
+//! An [`Actuator`] injecting the events into the local Linux input stack through uinput,
+//! to use barrier-client as a software Barrier client without a USB gadget.
+//!
+//! The keys go through the same Synergy to HID mapping as barpi, the HID usages are then
+//! translated to evdev codes.
+
+use std::io::Write;
+
+use barrier_client::{Actuator, ActuatorError, ClipboardData, KeyMask, ServerOptions};
+use log::{debug, info};
+use synergy_hid::{HidState, ReportType, SynergyHid};
+
+mod device;
+mod keycodes;
+
+pub use device::{InputEvent, UinputDevice};
+pub use keycodes::{consumer_to_evdev, hid_to_evdev, system_to_evdev};
+
+use device::{ABS_X, ABS_Y, EV_ABS, EV_KEY, EV_REL, REL_HWHEEL, REL_WHEEL};
+use keycodes::MOUSE_BUTTONS;
+
+pub struct UinputActuator {
+    // Owns the screen size, the cursor position and the keys held
+    hid: SynergyHid,
+    // What was last written to the device
+    state: HidState,
+    device: Box<dyn Write + Send>,
+}
+
+impl UinputActuator {
+    /// Creates a virtual keyboard and pointer named `name`, the pointer spans the screen
+    /// of `hid`. Needs write access to `/dev/uinput`.
+    pub fn new(hid: SynergyHid, name: &str) -> std::io::Result<Self> {
+        let (width, height) = hid.screen_size();
+        let name = std::ffi::CString::new(name)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let device = UinputDevice::create(&name, &keycodes::all_keys(), width, height)?;
+        info!("Created uinput device {:?}", name);
+        Ok(Self::with_writer(hid, Box::new(device)))
+    }
+
+    /// Writes the `input_event`s to any writer instead of a uinput device
+    pub fn with_writer(hid: SynergyHid, device: Box<dyn Write + Send>) -> Self {
+        let state = hid.state();
+        Self { hid, state, device }
+    }
+
+    /// Writes the events turning the last state written into the current one of `hid`
+    fn sync(&mut self) -> Result<(), ActuatorError> {
+        let state = self.hid.state();
+        let events = diff(&self.state, &state);
+        self.state = state;
+        self.write(&events)
+    }
+
+    fn write(&mut self, events: &[InputEvent]) -> Result<(), ActuatorError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = events
+            .iter()
+            .chain(&[InputEvent::sync()])
+            .flat_map(|event| event.to_bytes())
+            .collect();
+        self.device
+            .write_all(&bytes)
+            .map_err(|e| ActuatorError::io("uinput write", e))
+    }
+
+    // Half-duplex lock keys are released right after they are pressed
+    fn sync_keys(&mut self) -> Result<(), ActuatorError> {
+        self.sync()?;
+        let report = &mut [0; SynergyHid::MAX_REPORT_LEN];
+        while self.hid.pending_key(report).is_some() {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // The wheel is in the bytes 5 and 6 of the absolute mouse report, the buttons and
+    // position in it are already synced
+    fn write_wheel(&mut self, report_type: ReportType, report: &[u8]) -> Result<(), ActuatorError> {
+        if report_type != ReportType::Mouse {
+            return Ok(());
+        }
+        let events: Vec<InputEvent> = [(REL_WHEEL, report[5]), (REL_HWHEEL, report[6])]
+            .into_iter()
+            .filter(|(_, value)| *value != 0)
+            .map(|(code, value)| InputEvent::new(EV_REL, code, value as i8 as i32))
+            .collect();
+        self.write(&events)
+    }
+}
+
+fn key(code: Option<u16>, pressed: bool, events: &mut Vec<InputEvent>) {
+    if let Some(code) = code {
+        events.push(InputEvent::new(EV_KEY, code, pressed as i32));
+    }
+}
+
+/// Events from `old` to `new`, the modifiers are pressed before the keys and released after
+/// them so a key is never seen without the modifiers it was pressed with
+fn diff(old: &HidState, new: &HidState) -> Vec<InputEvent> {
+    let mut events = vec![];
+    let old_modifiers = old.modifiers | old.synthetic_modifiers;
+    let new_modifiers = new.modifiers | new.synthetic_modifiers;
+    let modifier = |bit: u8| keycodes::hid_to_evdev(0xE0 + bit);
+    for bit in (0..8).filter(|bit| new_modifiers & !old_modifiers & (1 << bit) != 0) {
+        key(modifier(bit), true, &mut events);
+    }
+    for usage in old.keys.iter().filter(|usage| !new.keys.contains(usage)) {
+        key(keycodes::hid_to_evdev(*usage), false, &mut events);
+    }
+    for usage in new.keys.iter().filter(|usage| !old.keys.contains(usage)) {
+        key(keycodes::hid_to_evdev(*usage), true, &mut events);
+    }
+    for bit in (0..8).filter(|bit| old_modifiers & !new_modifiers & (1 << bit) != 0) {
+        key(modifier(bit), false, &mut events);
+    }
+    for usage in old
+        .consumer
+        .iter()
+        .filter(|usage| !new.consumer.contains(usage))
+    {
+        key(keycodes::consumer_to_evdev(*usage), false, &mut events);
+    }
+    for usage in new
+        .consumer
+        .iter()
+        .filter(|usage| !old.consumer.contains(usage))
+    {
+        key(keycodes::consumer_to_evdev(*usage), true, &mut events);
+    }
+    for usage in old
+        .system
+        .iter()
+        .filter(|usage| !new.system.contains(usage))
+    {
+        key(keycodes::system_to_evdev(*usage), false, &mut events);
+    }
+    for usage in new
+        .system
+        .iter()
+        .filter(|usage| !old.system.contains(usage))
+    {
+        key(keycodes::system_to_evdev(*usage), true, &mut events);
+    }
+    if new.cursor.0 != old.cursor.0 {
+        events.push(InputEvent::new(EV_ABS, ABS_X, new.cursor.0 as i32));
+    }
+    if new.cursor.1 != old.cursor.1 {
+        events.push(InputEvent::new(EV_ABS, ABS_Y, new.cursor.1 as i32));
+    }
+    for (bit, button) in MOUSE_BUTTONS.into_iter().enumerate() {
+        let (was, is) = (old.mouse_buttons >> bit & 1, new.mouse_buttons >> bit & 1);
+        if was != is {
+            key(Some(button), is != 0, &mut events);
+        }
+    }
+    events
+}
+
+impl Actuator for UinputActuator {
+    fn connected(&mut self) -> Result<(), ActuatorError> {
+        info!("Connected");
+        Ok(())
+    }
+
+    fn disconnected(&mut self) -> Result<(), ActuatorError> {
+        info!("Disconnected");
+        self.hid.clear_all();
+        self.sync()
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        self.hid.screen_size()
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        self.hid.cursor_position()
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), ActuatorError> {
+        self.hid
+            .set_cursor_position(x, y, &mut [0; SynergyHid::MAX_REPORT_LEN]);
+        self.sync()
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        self.hid
+            .move_cursor(x, y, &mut [0; SynergyHid::MAX_REPORT_LEN]);
+        self.sync()
+    }
+
+    fn mouse_down(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.hid
+            .mouse_down(button, &mut [0; SynergyHid::MAX_REPORT_LEN]);
+        self.sync()
+    }
+
+    fn mouse_up(&mut self, button: i8) -> Result<(), ActuatorError> {
+        self.hid
+            .mouse_up(button, &mut [0; SynergyHid::MAX_REPORT_LEN]);
+        self.sync()
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) -> Result<(), ActuatorError> {
+        let report = &mut [0; SynergyHid::MAX_REPORT_LEN];
+        let (report_type, wheel) = self.hid.mouse_scroll(x, y, report);
+        let wheel = wheel.to_vec();
+        self.write_wheel(report_type, &wheel)?;
+        while let Some((report_type, wheel)) = self.hid.pending_scroll(report) {
+            let wheel = wheel.to_vec();
+            self.write_wheel(report_type, &wheel)?;
+        }
+        Ok(())
+    }
+
+    fn key_down(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.hid
+            .key_down(key, mask, button, &mut [0; SynergyHid::MAX_REPORT_LEN]);
+        self.sync_keys()
+    }
+
+    fn key_repeat(
+        &mut self,
+        key: u16,
+        mask: KeyMask,
+        button: u16,
+        count: u16,
+    ) -> Result<(), ActuatorError> {
+        // The kernel repeats the keys held by itself
+        debug!("Key repeat {key} {mask} {button} {count}");
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: u16, mask: KeyMask, button: u16) -> Result<(), ActuatorError> {
+        self.hid
+            .key_up(key, mask, button, &mut [0; SynergyHid::MAX_REPORT_LEN]);
+        self.sync_keys()
+    }
+
+    fn set_options(&mut self, opts: ServerOptions) -> Result<(), ActuatorError> {
+        debug!("Set options {:#?}", opts);
+        if let Some(half_duplex) = opts.half_duplex_caps_lock {
+            self.hid.set_half_duplex_caps_lock(half_duplex);
+        }
+        if let Some(half_duplex) = opts.half_duplex_num_lock {
+            self.hid.set_half_duplex_num_lock(half_duplex);
+        }
+        Ok(())
+    }
+
+    fn reset_options(&mut self) -> Result<(), ActuatorError> {
+        debug!("Reset options");
+        self.hid.set_half_duplex_caps_lock(false);
+        self.hid.set_half_duplex_num_lock(false);
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), ActuatorError> {
+        info!("Enter");
+        Ok(())
+    }
+
+    fn leave(&mut self) -> Result<(), ActuatorError> {
+        info!("Leave");
+        // Nothing stays held on this screen while the cursor is on another
+        self.hid.clear_all();
+        self.sync()
+    }
+
+    fn set_clipboard(&mut self, data: ClipboardData) -> Result<(), ActuatorError> {
+        // There is no clipboard provider for uinput, it's a display server thing
+        debug!("Clipboard with text {} ignored", data.text().is_some());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use barrier_client::{Actuator, KeyMask};
+    use synergy_hid::SynergyHid;
+
+    use super::{device::*, keycodes::*, InputEvent, UinputActuator};
+
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Events {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Events {
+        // The events written so far as (type, code, value)
+        fn take(&self) -> Vec<(u16, u16, i32)> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+                .chunks(std::mem::size_of::<libc::input_event>())
+                .map(|bytes| {
+                    let event = InputEvent::from_bytes(bytes).unwrap();
+                    (event.type_, event.code, event.value)
+                })
+                .collect()
+        }
+    }
+
+    const SYN: (u16, u16, i32) = (EV_SYN, SYN_REPORT, 0);
+    const KEY_A: u16 = 30;
+    const KEY_LEFTSHIFT: u16 = 42;
+
+    fn actuator() -> (UinputActuator, Events) {
+        let events = Events::default();
+        let actuator = UinputActuator::with_writer(
+            SynergyHid::new(1920, 1080, false),
+            Box::new(events.clone()),
+        );
+        (actuator, events)
+    }
+
+    #[test]
+    fn test_keys() {
+        let (mut actuator, events) = actuator();
+        // 'A' sent with Shift in the mask and no Shift key down
+        actuator.key_down('A' as u16, KeyMask::SHIFT, 0x26).unwrap();
+        assert_eq!(
+            events.take(),
+            [(EV_KEY, KEY_LEFTSHIFT, 1), (EV_KEY, KEY_A, 1), SYN]
+        );
+        // Shift stays applied while the server holds it
+        actuator.key_up('A' as u16, KeyMask::SHIFT, 0x26).unwrap();
+        assert_eq!(events.take(), [(EV_KEY, KEY_A, 0), SYN]);
+        actuator
+            .key_down('A' as u16, KeyMask::empty(), 0x26)
+            .unwrap();
+        actuator.key_up('A' as u16, KeyMask::empty(), 0x26).unwrap();
+        assert_eq!(
+            events.take(),
+            [
+                (EV_KEY, KEY_A, 1),
+                (EV_KEY, KEY_LEFTSHIFT, 0),
+                SYN,
+                (EV_KEY, KEY_A, 0),
+                SYN
+            ]
+        );
+        // Volume up on the consumer control
+        actuator.key_down(0xE0AF, KeyMask::empty(), 0x30).unwrap();
+        actuator.key_up(0xE0AF, KeyMask::empty(), 0x30).unwrap();
+        assert_eq!(
+            events.take(),
+            [(EV_KEY, 115, 1), SYN, (EV_KEY, 115, 0), SYN]
+        );
+        // Nothing mapped, nothing written
+        actuator.key_down(0, KeyMask::empty(), 0x99).unwrap();
+        assert_eq!(events.take(), []);
+    }
+
+    #[test]
+    fn test_mouse() {
+        let (mut actuator, events) = actuator();
+        actuator.set_cursor_position(100, 200).unwrap();
+        actuator.move_cursor(10, 0).unwrap();
+        actuator.mouse_down(3).unwrap();
+        assert_eq!(
+            events.take(),
+            [
+                (EV_ABS, ABS_X, 100),
+                (EV_ABS, ABS_Y, 200),
+                SYN,
+                (EV_ABS, ABS_X, 110),
+                SYN,
+                (EV_KEY, BTN_RIGHT, 1),
+                SYN
+            ]
+        );
+        // Two notches down and a partial one left, kept for later
+        actuator.mouse_wheel(60, -240).unwrap();
+        assert_eq!(events.take(), [(EV_REL, REL_WHEEL, -2), SYN]);
+        actuator.mouse_wheel(60, 0).unwrap();
+        assert_eq!(events.take(), [(EV_REL, REL_HWHEEL, 1), SYN]);
+        // The last of the 8 buttons
+        actuator.mouse_down(8).unwrap();
+        assert_eq!(events.take(), [(EV_KEY, BTN_TASK, 1), SYN]);
+        actuator.mouse_up(8).unwrap();
+        assert_eq!(events.take(), [(EV_KEY, BTN_TASK, 0), SYN]);
+
+        // Released on leave
+        actuator.leave().unwrap();
+        assert_eq!(events.take(), [(EV_KEY, BTN_RIGHT, 0), SYN]);
+        assert_eq!(actuator.get_cursor_position(), (110, 200));
+    }
+}